@@ -0,0 +1,214 @@
+//! `fw adopt`: finds checkouts that were moved by hand by their origin URL and points the projects' `override_path` at
+//! them again.
+use crate::config::path::expand_path;
+use crate::config::{self, project::Project, Config};
+use crate::errors::AppError;
+use crate::git_url::repository_key;
+use crate::output::{Cell, Table, Tone};
+use crate::setup::migrate::checkout_dirs;
+use git2::Repository;
+use slog::{debug, info, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Deep enough for `{workspace}/{forge}/{org}/{name}` layouts with a subgroup.
+const MAX_DEPTH: usize = 5;
+
+/// What `fw adopt` found for a project.
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+  /// The checkout moved to this path.
+  Moved(PathBuf),
+  /// More than one checkout has the project's URL.
+  Ambiguous(Vec<PathBuf>),
+  /// The project's path is a checkout of another repository.
+  OtherOrigin(String),
+}
+
+fn origin_of(path: &Path) -> Option<String> {
+  let repo = Repository::open(path).ok()?;
+  let remote = repo.find_remote("origin").ok()?;
+  remote.url().map(str::to_owned)
+}
+
+/// The workspaces of the settings, the tags and the profiles, plus `extra_dirs`.
+fn search_dirs(config: &Config, extra_dirs: &[PathBuf]) -> BTreeSet<PathBuf> {
+  let tag_workspaces = config.settings.tags.iter().flatten().filter_map(|(_, tag)| tag.workspace.clone());
+  let profile_workspaces = config.settings.profiles.iter().flatten().filter_map(|(_, profile)| profile.workspace.clone());
+  std::iter::once(config.settings.workspace.clone())
+    .chain(tag_workspaces)
+    .chain(profile_workspaces)
+    .map(|dir| expand_path(PathBuf::from(dir)))
+    .chain(extra_dirs.iter().cloned())
+    .collect()
+}
+
+/// The outcome for every project whose checkout is not where the config expects it, by project name.
+fn outcomes(config: &Config, checkouts: &[(PathBuf, String)], logger: &Logger) -> BTreeMap<String, Outcome> {
+  let expected: BTreeMap<&str, PathBuf> = config
+    .projects
+    .values()
+    .map(|project| (project.name.as_str(), config.checkout_path(project, logger)))
+    .collect();
+  let claimed: BTreeSet<&PathBuf> = expected.values().filter(|path| path.exists()).collect();
+  let mut by_key: BTreeMap<String, Vec<&PathBuf>> = BTreeMap::new();
+  for (path, origin) in checkouts {
+    if !claimed.contains(path) {
+      by_key.entry(repository_key(origin)).or_default().push(path);
+    }
+  }
+
+  let mut outcomes = BTreeMap::new();
+  // archived projects have no checkout on purpose
+  let adoptable = config
+    .projects
+    .values()
+    .filter(|project| !project.is_directory() && !project.archived.unwrap_or_default());
+  for project in adoptable {
+    let path = &expected[project.name.as_str()];
+    let key = repository_key(&project.git);
+    if path.exists() {
+      match origin_of(path) {
+        Some(origin) if repository_key(&origin) != key => {
+          outcomes.insert(project.name.clone(), Outcome::OtherOrigin(origin));
+        }
+        _ => (),
+      }
+      continue;
+    }
+    match by_key.get(&key).map(Vec::as_slice) {
+      Some([moved]) => {
+        outcomes.insert(project.name.clone(), Outcome::Moved(moved.to_path_buf()));
+      }
+      Some(candidates) if candidates.len() > 1 => {
+        outcomes.insert(project.name.clone(), Outcome::Ambiguous(candidates.iter().map(|p| p.to_path_buf()).collect()));
+      }
+      _ => debug!(logger, "Not checked out anywhere"; "project" => &project.name),
+    }
+  }
+  outcomes
+}
+
+/// `path` as `override_path`: relative to the project's workspace when it is inside, to stay portable.
+fn override_path(config: &Config, project: &Project, path: &Path, logger: &Logger) -> String {
+  let workspace = expand_path(PathBuf::from(config.resolve_workspace(logger, project)));
+  match path.strip_prefix(&workspace) {
+    Ok(relative) => format!("./{}", relative.to_string_lossy()),
+    Err(_) => path.to_string_lossy().to_string(),
+  }
+}
+
+/// Scans the workspaces and `extra_dirs` for checkouts of projects that are not where the config expects them and sets
+/// their `override_path`, unless `dry_run`. Ambiguous matches and projects whose path holds another repository are
+/// only reported.
+pub fn adopt(maybe_config: Result<Config, AppError>, extra_dirs: &[PathBuf], dry_run: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut checkouts = Vec::new();
+  for dir in search_dirs(&config, extra_dirs) {
+    for path in checkout_dirs(&dir, MAX_DEPTH) {
+      if let Some(origin) = origin_of(&path) {
+        checkouts.push((path, origin));
+      }
+    }
+  }
+  debug!(logger, "Found checkouts"; "count" => checkouts.len());
+
+  let mut table = Table::new();
+  let mut flagged = 0;
+  for (name, outcome) in outcomes(&config, &checkouts, logger) {
+    let project = &config.projects[&name];
+    let (path, status) = match outcome {
+      Outcome::Moved(path) => {
+        let new_override = override_path(&config, project, &path, logger);
+        if !dry_run {
+          config::write_project(
+            &Project {
+              override_path: Some(new_override.clone()),
+              ..project.clone()
+            },
+            logger,
+          )?;
+          info!(logger, "Adopted"; "project" => &name, "override_path" => &new_override);
+        }
+        (
+          path.to_string_lossy().to_string(),
+          Cell::new(if dry_run { "would adopt" } else { "adopted" }, Tone::Good),
+        )
+      }
+      Outcome::Ambiguous(paths) => {
+        flagged += 1;
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        (paths.join(", "), Cell::new("several checkouts, set override_path by hand", Tone::Warning))
+      }
+      Outcome::OtherOrigin(origin) => {
+        flagged += 1;
+        let path = config.checkout_path(project, logger);
+        (path.to_string_lossy().to_string(), Cell::new(format!("origin is {}", origin), Tone::Bad))
+      }
+    };
+    table.add_row(vec![Cell::plain(name), Cell::new(path, Tone::Muted), status]);
+  }
+  table.print();
+  if flagged > 0 {
+    Err(AppError::UserError(format!("{} project(s) need to be fixed by hand", flagged)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::settings::Settings;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+
+  fn a_project(name: &str, git: &str) -> Project {
+    Project {
+      name: name.to_owned(),
+      git: git.to_owned(),
+      tags: None,
+      override_path: None,
+      ..Project::example()
+    }
+  }
+
+  #[test]
+  fn test_outcomes() {
+    let temp = temp_dir();
+    let workspace = temp.path();
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let web = workspace.join("web");
+    Repository::init(&web).unwrap().remote("origin", "git@github.com:brocode/other.git").unwrap();
+    let projects = vec![
+      a_project("fw", "git@github.com:brocode/fw.git"),
+      a_project("api", "git@github.com:brocode/api.git"),
+      a_project("web", "git@github.com:brocode/web.git"),
+      a_project("other", "git@github.com:brocode/other.git"),
+      a_project("lib", "git@github.com:brocode/lib.git"),
+      Project {
+        archived: Some(true),
+        ..a_project("old", "git@github.com:brocode/old.git")
+      },
+    ];
+    let config = Config {
+      projects: projects.into_iter().map(|project| (project.name.clone(), project)).collect(),
+      settings: serde_json::from_value::<Settings>(serde_json::json!({ "workspace": workspace })).unwrap(),
+      outside_profile: BTreeMap::new(),
+    };
+    let checkouts = vec![
+      (workspace.join("moved/fw"), "https://github.com/brocode/fw".to_owned()),
+      (workspace.join("a/api"), "git@github.com:brocode/api.git".to_owned()),
+      (workspace.join("b/api"), "git@github.com:brocode/api.git".to_owned()),
+      // where web is expected, so it is not a moved checkout of other
+      (web.clone(), "git@github.com:brocode/other.git".to_owned()),
+      (workspace.join("archive/old"), "git@github.com:brocode/old.git".to_owned()),
+    ];
+
+    let mut expected = BTreeMap::new();
+    expected.insert("fw".to_owned(), Outcome::Moved(workspace.join("moved/fw")));
+    expected.insert("api".to_owned(), Outcome::Ambiguous(vec![workspace.join("a/api"), workspace.join("b/api")]));
+    expected.insert("web".to_owned(), Outcome::OtherOrigin("git@github.com:brocode/other.git".to_owned()));
+    assert_that(&outcomes(&config, &checkouts, &logger)).is_equal_to(expected);
+  }
+}
@@ -9,6 +9,72 @@ fn validate_number(input: &str, max: i32) -> std::result::Result<(), String> {
   }
 }
 
+// What `add` and the importers do with projects whose name is already in the config.
+fn conflict_args<'a>() -> [Arg<'a, 'a>; 3] {
+  [
+    Arg::with_name("force")
+      .long("force")
+      .short("f")
+      .help("Replace projects with the same name")
+      .conflicts_with_all(&["merge", "interactive"]),
+    Arg::with_name("merge")
+      .long("merge")
+      .help("Give projects with the same name the new git URL, keeping their settings and adding the new tags")
+      .conflicts_with("interactive"),
+    Arg::with_name("interactive")
+      .long("interactive")
+      .short("i")
+      .help("Ask what to do with projects with the same name and another git URL"),
+  ]
+}
+
+fn naming_arg<'a>() -> Arg<'a, 'a> {
+  Arg::with_name("naming")
+    .long("naming")
+    .value_name("naming")
+    .help("Name projects like the repository, prefixed with the organization or group, or suffixed with the forge (default: import_naming in the settings, else name)")
+    .takes_value(true)
+    .possible_values(&["name", "org-prefix", "forge-suffix"])
+}
+
+fn validate_regex(input: &str) -> std::result::Result<(), String> {
+  regex::Regex::new(input).map(|_| ()).map_err(|e| format!("Invalid regex: {}", e))
+}
+
+// Which repositories the importers take, by repository name.
+fn filter_args<'a>() -> [Arg<'a, 'a>; 2] {
+  [
+    Arg::with_name("include")
+      .long("include")
+      .value_name("regex")
+      .help("Only import repositories whose name matches, e.g. '^service-'")
+      .validator(|input| validate_regex(&input))
+      .takes_value(true),
+    Arg::with_name("exclude")
+      .long("exclude")
+      .value_name("regex")
+      .help("Skip repositories whose name matches, e.g. '-deprecated$'")
+      .validator(|input| validate_regex(&input))
+      .takes_value(true),
+  ]
+}
+
+fn timeout_arg<'a>(help: &'a str) -> Arg<'a, 'a> {
+  Arg::with_name("timeout")
+    .long("timeout")
+    .value_name("seconds")
+    .help(help)
+    .validator(|input| validate_number(&input, i32::MAX))
+    .takes_value(true)
+}
+
+fn import_dry_run_arg<'a>() -> Arg<'a, 'a> {
+  Arg::with_name("dry-run")
+    .long("dry-run")
+    .short("n")
+    .help("Only show which projects would be added, skipped or are in conflict, without changing the config")
+}
+
 pub fn app<'a>() -> App<'a, 'a> {
   App::new("fw")
     .version(crate_version!())
@@ -19,8 +85,58 @@ For further information please have a look at our README https://github.com/broc
     )
     .global_setting(AppSettings::ColoredHelp)
     .setting(AppSettings::SubcommandRequired)
-    .arg(Arg::with_name("v").short("v").multiple(true).help("Sets the level of verbosity"))
+    // unknown commands run fw-<command> plugins from the PATH
+    .setting(AppSettings::AllowExternalSubcommands)
+    .arg(
+      Arg::with_name("v")
+        .short("v")
+        .multiple(true)
+        .help("Sets the level of verbosity (-v info, -vv debug, -vvv trace)"),
+    )
     .arg(Arg::with_name("q").short("q").help("Make fw quiet"))
+    .arg(
+      Arg::with_name("frozen")
+        .long("frozen")
+        .help("Refuse to run commands that modify the fw configuration (same as readonly = true in the settings)"),
+    )
+    .arg(
+      Arg::with_name("offline")
+        .long("offline")
+        .help("Do not clone, fetch or contact forge APIs, sync skips what needs the network (same as FW_OFFLINE=1)"),
+    )
+    .arg(
+      Arg::with_name("non-interactive")
+        .long("non-interactive")
+        .help("Never ask, fail on ambiguous project names and workspaces, log JSON without colours and progress bars, e.g. in CI (same as FW_NON_INTERACTIVE=1)"),
+    )
+    .arg(
+      Arg::with_name("config")
+        .long("config")
+        .help("Use this settings file, with the projects and tags next to it, or a single .fw.json file (same as FW_CONFIG)")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("profile")
+        .long("profile")
+        .help("Use a profile from the settings with its own workspace, default tags and projects (same as FW_PROFILE)")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("color")
+        .long("color")
+        .help("Colour output: auto colours terminals unless NO_COLOR is set")
+        .takes_value(true)
+        .possible_values(&["auto", "always", "never"])
+        .default_value("auto"),
+    )
+    .arg(
+      Arg::with_name("log-format")
+        .long("log-format")
+        .help("Format of log records written to stderr and the log file")
+        .takes_value(true)
+        .possible_values(&["plain", "json"])
+        .default_value("plain"),
+    )
     .subcommand(
       SubCommand::with_name("sync")
         .about("Sync workspace. Clones projects or updates remotes for existing projects.")
@@ -62,6 +178,32 @@ For further information please have a look at our README https://github.com/broc
             .validator(|input| validate_number(&input, 10))
             .help("Sets the count of worker")
             .takes_value(true),
+        )
+        .arg(timeout_arg("Give up on a single project after this many seconds"))
+        .arg(
+          Arg::with_name("wait")
+            .long("wait")
+            .help("Wait for projects locked by another fw process")
+            .conflicts_with("skip-locked")
+            .takes_value(false),
+        )
+        .arg(
+          Arg::with_name("skip-locked")
+            .long("skip-locked")
+            .help("Skip projects locked by another fw process")
+            .takes_value(false),
+        )
+        .arg(
+          Arg::with_name("notify")
+            .long("notify")
+            .help("Send a desktop notification with a summary when done")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("no-hooks")
+            .long("no-hooks")
+            .help("Clone without running after_clone hooks")
+            .takes_value(false),
         ),
     )
     .subcommand(
@@ -113,8 +255,65 @@ For further information please have a look at our README https://github.com/broc
     .subcommand(
       SubCommand::with_name("import")
         .about("Import existing git folder to fw")
-        .arg(Arg::with_name("PROJECT_DIR").value_name("PROJECT_DIR").index(1).required(true)),
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(Arg::with_name("PROJECT_DIR").value_name("PROJECT_DIR").index(1).required(true))
+        .arg(import_dry_run_arg())
+        .subcommand(
+          SubCommand::with_name("github-stars")
+            .about(
+              "Import the repositories starred on GitHub into fw, tagged oss. Without --user the stars of the owner of the token in the settings file or FW_GITHUB_TOKEN",
+            )
+            .arg(
+              Arg::with_name("user")
+                .long("user")
+                .value_name("name")
+                .help("Import the stars of this GitHub user, works without a token")
+                .takes_value(true),
+            )
+            .arg(
+              Arg::with_name("language")
+                .long("language")
+                .value_name("language")
+                .help("Only import repositories in this language")
+                .takes_value(true),
+            )
+            .arg(
+              Arg::with_name("topic")
+                .long("topic")
+                .value_name("topic")
+                .help("Only import repositories with this topic")
+                .takes_value(true),
+            )
+            .arg(timeout_arg("Give up on the GitHub API after this many seconds"))
+            .arg(naming_arg())
+            .args(&filter_args())
+            .arg(import_dry_run_arg())
+            .args(&conflict_args()),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("import-from")
+        .about("Import the repositories managed by another multi-repo tool into fw. They keep their checkouts through override_path")
+        .arg(
+          Arg::with_name("TOOL")
+            .value_name("TOOL")
+            .help("mr reads ~/.mrconfig, ghq the checkouts in ghq.root, vcsh its repo.d, gita its repos.csv")
+            .possible_values(&["mr", "ghq", "vcsh", "gita"])
+            .index(1)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("PATH")
+            .value_name("PATH")
+            .help("Config file or directory of the tool, if it is not in the default location")
+            .index(2),
+        )
+        .args(&filter_args())
+        .arg(import_dry_run_arg()),
     )
+    .subcommand(SubCommand::with_name("migrate-paths").about(
+      "Move the config, state and cache of earlier fw versions to the XDG directories and convert a ~/.fw.json",
+    ))
     .subcommand(
       SubCommand::with_name("org-import")
         .about(
@@ -128,21 +327,108 @@ For further information please have a look at our README https://github.com/broc
             .takes_value(false)
             .required(false),
         )
-        .arg(Arg::with_name("ORG_NAME").value_name("ORG_NAME").index(1).required(true)),
+        .arg(Arg::with_name("ORG_NAME").value_name("ORG_NAME").index(1).required(true))
+        .arg(
+          Arg::with_name("team")
+            .long("team")
+            .value_name("slug")
+            .help("Only import the repositories this team of the org has access to")
+            .takes_value(true),
+        )
+        .arg(timeout_arg("Give up on the GitHub API after this many seconds"))
+        .arg(naming_arg())
+        .args(&filter_args())
+        .arg(import_dry_run_arg())
+        .args(&conflict_args()),
     )
     .subcommand(
       SubCommand::with_name("gitlab-import")
         .about("Import all owned repositories / your organizations repositories from gitlab into fw")
         .arg(
-          Arg::with_name("include")
+          Arg::with_name("state")
             .help("Filter projects to import by state")
-            .long("include")
+            .long("state")
             .short("a")
             .takes_value(true)
             .value_name("state")
             .possible_values(&["active", "archived", "both"])
             .default_value("active"),
-        ),
+        )
+        .arg(timeout_arg("Give up on the Gitlab API after this many seconds"))
+        .arg(naming_arg())
+        .args(&filter_args())
+        .arg(import_dry_run_arg())
+        .args(&conflict_args()),
+    )
+    .subcommand(
+      SubCommand::with_name("gitea-import")
+        .about(
+          "Import all repositories of an organization or user from a Gitea or Forgejo instance like codeberg.org into fw. Token for private repositories can be set in the settings file or provided via the environment variable FW_GITEA_TOKEN",
+        )
+        .arg(
+          Arg::with_name("include-archived")
+            .value_name("include-archived")
+            .long("include-archived")
+            .short("a")
+            .takes_value(false)
+            .required(false),
+        )
+        .arg(Arg::with_name("BASE_URL").value_name("BASE_URL").index(1).required(true))
+        .arg(Arg::with_name("OWNER").value_name("OWNER").index(2).required(true))
+        .arg(timeout_arg("Give up on the Gitea API after this many seconds"))
+        .arg(naming_arg())
+        .args(&filter_args())
+        .arg(import_dry_run_arg())
+        .args(&conflict_args()),
+    )
+    .subcommand(
+      SubCommand::with_name("azure-import")
+        .about(
+          "Import all git repositories of an Azure DevOps project into fw, tagged with the project name. The personal access token can be set in the settings file or provided via the environment variable FW_AZURE_TOKEN",
+        )
+        .arg(
+          Arg::with_name("PROJECT")
+            .value_name("ORGANIZATION/PROJECT")
+            .index(1)
+            .required(true),
+        )
+        .arg(timeout_arg("Give up on the Azure DevOps API after this many seconds"))
+        .arg(naming_arg())
+        .args(&filter_args())
+        .arg(import_dry_run_arg())
+        .args(&conflict_args()),
+    )
+    .subcommand(
+      SubCommand::with_name("codecommit-import")
+        .about("Import all AWS CodeCommit repositories of a region into fw. Uses the aws CLI and its credentials")
+        .arg(
+          Arg::with_name("region")
+            .long("region")
+            .value_name("region")
+            .help("AWS region, the default region of the aws CLI otherwise")
+            .takes_value(true),
+        )
+        .arg(timeout_arg("Give up on the aws CLI after this many seconds"))
+        .arg(naming_arg())
+        .args(&filter_args())
+        .arg(import_dry_run_arg())
+        .args(&conflict_args()),
+    )
+    .subcommand(
+      SubCommand::with_name("cloud-source-import")
+        .about("Import all Google Cloud Source Repositories of a project into fw. Uses the gcloud CLI and its credentials")
+        .arg(
+          Arg::with_name("project")
+            .long("project")
+            .value_name("project")
+            .help("Google Cloud project, the default project of gcloud otherwise")
+            .takes_value(true),
+        )
+        .arg(timeout_arg("Give up on the gcloud CLI after this many seconds"))
+        .arg(naming_arg())
+        .args(&filter_args())
+        .arg(import_dry_run_arg())
+        .args(&conflict_args()),
     )
     .subcommand(
       SubCommand::with_name("add-remote")
@@ -161,7 +447,13 @@ For further information please have a look at our README https://github.com/broc
       SubCommand::with_name("add")
         .about("Add project to config")
         .arg(Arg::with_name("NAME").value_name("NAME").index(2).required(false))
-        .arg(Arg::with_name("URL").value_name("URL").index(1).required(true))
+        .arg(
+          Arg::with_name("URL")
+            .value_name("URL")
+            .help("Git URL of the project, or . for the checkout you are in")
+            .index(1)
+            .required(true),
+        )
         .arg(
           Arg::with_name("override-path")
             .value_name("override-path")
@@ -182,7 +474,8 @@ For further information please have a look at our README https://github.com/broc
             .long("after-clone")
             .takes_value(true)
             .required(false),
-        ),
+        )
+        .args(&conflict_args()),
     )
     .subcommand(
       SubCommand::with_name("remove")
@@ -197,6 +490,47 @@ For further information please have a look at our README https://github.com/broc
             .takes_value(false),
         ),
     )
+    .subcommand(
+      SubCommand::with_name("move-to-workspace")
+        .about("Move the checkout of a project with an override_path into the workspace and drop the override_path")
+        .arg(Arg::with_name("NAME").value_name("NAME").index(1).required(true)),
+    )
+    .subcommand(
+      SubCommand::with_name("archive")
+        .about("Archive a project: remove its checkout and leave it out of sync, ls and the other bulk commands")
+        .arg(Arg::with_name("NAME").value_name("NAME").index(1).required(true))
+        .arg(
+          Arg::with_name("bundle")
+            .long("bundle")
+            .help("Keep all branches and tags of the checkout in a git bundle")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("bundle-dir")
+            .long("bundle-dir")
+            .value_name("DIR")
+            .help("Where the bundles are, by default the archive directory in the fw state directory")
+            .takes_value(true),
+        )
+        .arg(
+          Arg::with_name("force")
+            .long("force")
+            .help("Remove the checkout even if it has uncommitted or unpushed work")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("unarchive")
+        .about("Bring an archived project back, from its bundle if there is one")
+        .arg(Arg::with_name("NAME").value_name("NAME").index(1).required(true))
+        .arg(
+          Arg::with_name("bundle-dir")
+            .long("bundle-dir")
+            .value_name("DIR")
+            .help("Where the bundles are, by default the archive directory in the fw state directory")
+            .takes_value(true),
+        ),
+    )
     .subcommand(
       SubCommand::with_name("foreach")
         .about("Run script on each project")
@@ -209,6 +543,18 @@ For further information please have a look at our README https://github.com/broc
             .validator(|input| validate_number(&input, 20))
             .takes_value(true),
         )
+        .arg(
+          Arg::with_name("ordered")
+            .long("ordered")
+            .help("Run projects only after the projects they depend on (depends_on)")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("notify")
+            .long("notify")
+            .help("Send a desktop notification with a summary when done")
+            .required(false),
+        )
         .arg(
           Arg::with_name("tag")
             .long("tag")
@@ -220,136 +566,1276 @@ For further information please have a look at our README https://github.com/broc
         ),
     )
     .subcommand(
-      SubCommand::with_name("print-path")
-        .about("Print project path on stdout")
-        .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").index(1).required(true)),
-    )
-    .subcommand(SubCommand::with_name("projectile").about("Write projectile bookmarks"))
-    .subcommand(
-      SubCommand::with_name("ls").about("List projects").arg(
-        Arg::with_name("tag")
-          .long("tag")
-          .short("t")
-          .help("Filter projects by tag. More than 1 is allowed.")
-          .required(false)
-          .takes_value(true)
-          .multiple(true),
-      ),
-    )
-    .subcommand(
-      SubCommand::with_name("gen-workon")
-        .about("Generate sourceable shell code to work on project")
-        .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").index(1).required(true))
+      SubCommand::with_name("run")
+        .about("Run a command from the commands of the settings, tags or projects in each project that has it")
+        .arg(Arg::with_name("NAME").value_name("NAME").required(true))
         .arg(
-          Arg::with_name("quick")
+          Arg::with_name("parallel")
+            .short("p")
+            .help("Parallelism to use (default is set by rayon but probably equal to the number of cores)")
             .required(false)
-            .short("x")
-            .help("Don't generate post_workon shell code, only cd into the folder"),
-        ),
-    )
-    .subcommand(SubCommand::with_name("gen-reworkon").about("Generate sourceable shell code to re-work on project"))
-    .subcommand(
-      SubCommand::with_name("inspect")
-        .about("Inspect project")
-        .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").index(1).required(true))
+            .validator(|input| validate_number(&input, 20))
+            .takes_value(true),
+        )
         .arg(
-          Arg::with_name("json")
-            .help("output json instead of cool text")
-            .short("j")
-            .long("json")
+          Arg::with_name("ordered")
+            .long("ordered")
+            .help("Run projects only after the projects they depend on (depends_on)")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("notify")
+            .long("notify")
+            .help("Send a desktop notification with a summary when done")
             .required(false),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
         ),
     )
     .subcommand(
-      SubCommand::with_name("update")
-        .about("Modifies project settings.")
-        .arg(Arg::with_name("NAME").value_name("NAME").required(true))
-        .arg(Arg::with_name("git").value_name("URL").long("git-url").takes_value(true).required(false))
+      SubCommand::with_name("path")
+        .alias("print-path")
+        .about("Print the path of a project, resolved like workon does")
+        .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").index(1).required(true)),
+    )
+    .subcommand(SubCommand::with_name("projectile").about("Write projectile bookmarks"))
+    .subcommand(
+      SubCommand::with_name("ls")
+        .about("List projects")
         .arg(
-          Arg::with_name("override-path")
-            .value_name("override-path")
-            .long("override-path")
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
             .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("long")
+            .long("long")
+            .short("l")
+            .help("Show path and tags in aligned columns")
             .required(false),
         )
         .arg(
-          Arg::with_name("after-workon")
-            .value_name("after-workon")
-            .long("after-workon")
-            .takes_value(true)
+          Arg::with_name("url")
+            .long("url")
+            .short("u")
+            .help("Print the git URLs, in the form prefer in the settings asks for, instead of the names (as another column with --long)")
             .required(false),
         )
         .arg(
-          Arg::with_name("after-clone")
-            .value_name("after-clone")
-            .long("after-clone")
-            .takes_value(true)
+          Arg::with_name("recent")
+            .long("recent")
+            .short("r")
+            .help("List the most recently worked on projects first")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("archived")
+            .long("archived")
+            .short("a")
+            .help("Also list archived projects")
             .required(false),
         ),
     )
+    .subcommand(SubCommand::with_name("doctor").about("Check the configuration for problems, e.g. tags with contradictory workspaces or risky hooks"))
     .subcommand(
-      SubCommand::with_name("tag")
-        .alias("tags")
-        .about("Allows working with tags.")
-        .subcommand(
-          SubCommand::with_name("ls")
-            .alias("list")
-            .about("Lists tags")
-            .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").required(false)),
-        )
-        .subcommand(
-          SubCommand::with_name("tag-project")
-            .about("Add tag to project")
-            .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").required(true))
-            .arg(Arg::with_name("tag-name").value_name("tag").required(true)),
-        )
-        .subcommand(
-          SubCommand::with_name("untag-project")
-            .about("Removes tag from project")
-            .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").required(true))
-            .arg(Arg::with_name("tag-name").value_name("tag").required(true)),
+      SubCommand::with_name("recent")
+        .about("List the projects worked on most recently")
+        .arg(
+          Arg::with_name("limit")
+            .long("limit")
+            .short("n")
+            .help("Show at most this many projects")
+            .takes_value(true)
+            .validator(|input| input.parse::<usize>().map(|_| ()).map_err(|_| format!("Expected a number. Was '{}'.", input)))
+            .required(false),
         )
+        .arg(
+          Arg::with_name("visits")
+            .long("visits")
+            .help("List every workon, newest first, instead of each project once")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("time")
+        .about("Report the time spent on projects, tracked by workon with time_tracking in the settings")
+        .setting(AppSettings::SubcommandRequired)
         .subcommand(
-          SubCommand::with_name("autotag")
-            .about("tags projects when CMD returns exit code 0")
-            .arg(Arg::with_name("tag-name").value_name("tag").required(true))
-            .arg(Arg::with_name("CMD").value_name("CMD").required(true))
+          SubCommand::with_name("report")
+            .about("Sum up the hours per project since a date or age")
             .arg(
-              Arg::with_name("parallel")
-                .short("p")
-                .help("Parallelism to use (default is set by rayon but probably equal to the number of cores)")
-                .required(false)
-                .validator(|input| validate_number(&input, 20))
-                .takes_value(true),
+              Arg::with_name("since")
+                .long("since")
+                .value_name("AGE_OR_DATE")
+                .help("Sessions of the last 1w, 3d, 12h, ... or since a date like 2021-03-01")
+                .takes_value(true)
+                .default_value("1w")
+                .validator(|input| fw_core::changes::parse_since(&input, 0).map(|_| ())),
+            )
+            .arg(
+              Arg::with_name("by-tag")
+                .long("by-tag")
+                .help("Sum up the hours per tag instead, projects with several tags count for each")
+                .required(false),
             ),
         )
         .subcommand(
-          SubCommand::with_name("inspect")
-            .about("Inspect a tag")
-            .arg(Arg::with_name("tag-name").value_name("tag name").required(true)),
-        )
-        .subcommand(
-          SubCommand::with_name("rm")
-            .about("Deletes a tag. Will not untag projects.")
-            .arg(Arg::with_name("tag-name").value_name("tag name").required(true)),
-        )
-        .subcommand(
-          SubCommand::with_name("add")
-            .alias("update")
-            .alias("create")
-            .about("Creates a new tag. Replaces existing.")
-            .arg(Arg::with_name("tag-name").value_name("tag name").required(true))
+          SubCommand::with_name("invoice")
+            .about("Summarize hours, commits and the amount per project of a client for an invoice")
             .arg(
-              Arg::with_name("after-workon")
-                .value_name("after-workon")
-                .long("after-workon")
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("The tag of the client's projects. More than 1 is allowed.")
+                .required(true)
                 .takes_value(true)
-                .required(false),
+                .multiple(true),
             )
             .arg(
-              Arg::with_name("priority")
-                .value_name("priority")
-                .long("priority")
+              Arg::with_name("rate")
+                .long("rate")
+                .value_name("RATE")
+                .help("Price of an hour")
+                .takes_value(true)
+                .required(true)
+                .validator(|input| input.parse::<f64>().map(|_| ()).map_err(|_| format!("Expected a number. Was '{}'.", input))),
+            )
+            .arg(
+              Arg::with_name("since")
+                .long("since")
+                .value_name("AGE_OR_DATE")
+                .help("Sessions and commits of the last 4w, 3d, ... or since a date like 2021-03-01")
+                .takes_value(true)
+                .default_value("4w")
+                .validator(|input| fw_core::changes::parse_since(&input, 0).map(|_| ())),
+            )
+            .arg(
+              Arg::with_name("until")
+                .long("until")
+                .value_name("AGE_OR_DATE")
+                .help("End of the period, like --since, by default now. A date is the start of that day")
+                .takes_value(true)
+                .validator(|input| fw_core::changes::parse_since(&input, 0).map(|_| ())),
+            )
+            .arg(
+              Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .default_value("markdown")
+                .possible_values(&["markdown", "csv"]),
+            ),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("status")
+        .about("Show branch, ahead/behind and last fetch of projects")
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("json")
+            .help("output json instead of cool text")
+            .short("j")
+            .long("json")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("no-cache")
+            .long("no-cache")
+            .help("Query every repository instead of reusing cached results for unchanged ones")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("check-remotes")
+            .long("check-remotes")
+            .help("Ask GitHub and GitLab whether repositories were renamed or transferred")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("fix-remotes")
+        .about("Point projects that were renamed or transferred on GitHub or GitLab to their new URL, in the config and the checkouts")
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("dry-run")
+            .long("dry-run")
+            .short("n")
+            .help("Only show which projects moved")
+            .required(false),
+        )
+        .arg(timeout_arg("Give up on the GitHub and GitLab APIs after this many seconds")),
+    )
+    .subcommand(
+      SubCommand::with_name("grep")
+        .about("Search the files of all projects, skipping what git ignores")
+        .arg(Arg::with_name("PATTERN").help("A regular expression").required(true))
+        .arg(
+          Arg::with_name("ignore-case")
+            .long("ignore-case")
+            .short("i")
+            .help("Match case insensitively")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("fixed-strings")
+            .long("fixed-strings")
+            .short("F")
+            .help("Treat the pattern as a literal string")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("query")
+        .about("Select projects and print their fields, e.g. 'projects[effective_tags contains \"rust\"].path'")
+        .arg(
+          Arg::with_name("QUERY")
+            .value_name("QUERY")
+            .help("projects[FILTER]... or settings, optionally followed by .FIELD")
+            .index(1)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("json")
+            .help("Print a JSON array instead of a line per value")
+            .short("j")
+            .long("json")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("edit")
+        .about("Open the settings, a project or a tag in $EDITOR and keep the change only if the config is still valid")
+        .arg(
+          Arg::with_name("PROJECT_NAME")
+            .value_name("PROJECT_NAME")
+            .help("The project to edit instead of the settings")
+            .index(1)
+            .conflicts_with("tag"),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .value_name("TAG")
+            .help("The tag to edit instead of the settings")
+            .takes_value(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("fmt")
+        .about("Rewrite the config files in the order and formatting fw writes them, without values that are the default")
+        .arg(
+          Arg::with_name("check")
+            .long("check")
+            .help("Only list the files that are not formatted and exit non-zero if there are any, e.g. in a pre-commit hook"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("adopt")
+        .about("Find checkouts that were moved by hand by their origin URL and set the override_path of their projects")
+        .arg(
+          Arg::with_name("DIR")
+            .value_name("DIR")
+            .help("Also search these directories, the workspaces of the settings, tags and profiles are always searched")
+            .index(1)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("dry-run")
+            .long("dry-run")
+            .short("n")
+            .help("Only show what would be adopted")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("dedupe")
+        .about("Merge projects with the same repository, e.g. added by SSH and HTTPS URL, keeping the tags of all")
+        .arg(
+          Arg::with_name("yes")
+            .long("yes")
+            .short("y")
+            .help("Merge without asking")
+            .conflicts_with("dry-run"),
+        )
+        .arg(
+          Arg::with_name("dry-run")
+            .long("dry-run")
+            .short("n")
+            .help("Only show what would be merged")
+            .required(false),
+        ),
+    )
+    .subcommand(SubCommand::with_name("plugins").about("List the fw-<command> executables on the PATH that fw runs as plugins"))
+    .subcommand(
+      SubCommand::with_name("which")
+        .about("List the projects that contain a file")
+        .arg(
+          Arg::with_name("PATTERN")
+            .help("A file name or glob like Dockerfile or **/flake.nix, patterns with / match the path in the project")
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("json")
+            .help("output json instead of cool text")
+            .short("j")
+            .long("json")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("du").about("Show the disk usage of projects, largest first").arg(
+        Arg::with_name("tag")
+          .long("tag")
+          .short("t")
+          .help("Filter projects by tag. More than 1 is allowed.")
+          .required(false)
+          .takes_value(true)
+          .multiple(true),
+      ),
+    )
+    .subcommand(
+      SubCommand::with_name("serve-webhook")
+        .about("Add, repoint and remove projects when repositories are created, renamed or deleted on the forge, until interrupted")
+        .arg(
+          Arg::with_name("listen")
+            .long("listen")
+            .value_name("address")
+            .help("Where the forge sends the webhooks to, the secret is taken from FW_WEBHOOK_SECRET")
+            .default_value("127.0.0.1:9921")
+            .validator(|input| {
+              input
+                .parse::<std::net::SocketAddr>()
+                .map(|_| ())
+                .map_err(|_| format!("Expected an address like 127.0.0.1:9921. Was '{}'.", input))
+            })
+            .takes_value(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("daemon")
+        .about("Fetch projects periodically and keep the status cache fresh, until interrupted")
+        .arg(
+          Arg::with_name("interval")
+            .long("interval")
+            .short("i")
+            .value_name("interval")
+            .help("Time between two fetches, like 90s, 30m or 1h")
+            .default_value("30m")
+            .validator(|input| fw_core::daemon::parse_interval(&input).map(|_| ()))
+            .takes_value(true),
+        )
+        .arg(
+          Arg::with_name("metrics-listen")
+            .long("metrics-listen")
+            .value_name("address")
+            .help("Serve Prometheus metrics about the projects at http://<address>/metrics, e.g. 127.0.0.1:9920")
+            .validator(|input| {
+              input
+                .parse::<std::net::SocketAddr>()
+                .map(|_| ())
+                .map_err(|_| format!("Expected an address like 127.0.0.1:9920. Was '{}'.", input))
+            })
+            .takes_value(true),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("export")
+        .about("Export the fw configuration for other tools")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(
+          SubCommand::with_name("direnv")
+            .about("Write an .envrc for direnv into the project that sets it up like workon")
+            .arg(
+              Arg::with_name("PROJECT_NAME")
+                .value_name("PROJECT_NAME")
+                .help("The project to export")
+                .required(true)
+                .index(1),
+            )
+            .arg(Arg::with_name("force").long("force").short("f").help("Replace an .envrc that was not written by fw")),
+        )
+        .subcommand(
+          SubCommand::with_name("all-direnv")
+            .about("Write an .envrc for direnv into every project that is checked out")
+            .arg(
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("Filter projects by tag. More than 1 is allowed.")
+                .required(false)
+                .takes_value(true)
+                .multiple(true),
+            )
+            .arg(Arg::with_name("force").long("force").short("f").help("Replace .envrc files that were not written by fw")),
+        )
+        .subcommand(
+          SubCommand::with_name("nix")
+            .about("Print the settings, projects and tags as a Nix expression. Tokens are left out")
+            .arg(
+              Arg::with_name("home-manager")
+                .long("home-manager")
+                .help("Print a home-manager module that writes the configuration to ~/.config/fw instead"),
+            ),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("branch")
+        .about("Create, delete and list a branch across projects")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(
+          SubCommand::with_name("create")
+            .about("Create the branch at HEAD in every project")
+            .arg(
+              Arg::with_name("BRANCH")
+                .value_name("BRANCH")
+                .help("The name of the branch")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("Filter projects by tag. More than 1 is allowed.")
+                .required(false)
+                .takes_value(true)
+                .multiple(true),
+            )
+            .arg(
+              Arg::with_name("checkout")
+                .long("checkout")
+                .short("c")
+                .help("Switch to the branch as well")
+                .required(false),
+            ),
+        )
+        .subcommand(
+          SubCommand::with_name("delete")
+            .about("Delete the local branch in every project")
+            .arg(
+              Arg::with_name("BRANCH")
+                .value_name("BRANCH")
+                .help("The name of the branch")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("Filter projects by tag. More than 1 is allowed.")
+                .required(false)
+                .takes_value(true)
+                .multiple(true),
+            )
+            .arg(
+              Arg::with_name("force")
+                .long("force")
+                .help("Delete the branch even if it is not merged into HEAD or its upstream")
+                .required(false),
+            ),
+        )
+        .subcommand(
+          SubCommand::with_name("ls")
+            .about("Show which projects have the branch")
+            .arg(
+              Arg::with_name("BRANCH")
+                .value_name("BRANCH")
+                .help("The name of the branch")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("Filter projects by tag. More than 1 is allowed.")
+                .required(false)
+                .takes_value(true)
+                .multiple(true),
+            ),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("clean-branches")
+        .about("Delete branches merged into the default branch across projects")
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("prune")
+            .long("prune")
+            .help("Also remove remote-tracking branches whose branch is gone from origin")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("dry-run")
+            .long("dry-run")
+            .short("n")
+            .help("Only show what would be removed")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("compare")
+        .about("Show how far the checked out branch of every project is ahead of and behind a base branch")
+        .arg(
+          Arg::with_name("base")
+            .long("base")
+            .value_name("BRANCH")
+            .help("The branch to compare with, by default the default branch of origin")
+            .takes_value(true),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("changes")
+        .about("Summarize the commits of all projects since a date or tag")
+        .arg(
+          Arg::with_name("since")
+            .long("since")
+            .value_name("AGE_OR_DATE")
+            .help("Commits of the last 2w, 3d, 12h, ... or since a date like 2021-03-01")
+            .takes_value(true)
+            .required_unless("since-tag")
+            .validator(|input| fw_core::changes::parse_since(&input, 0).map(|_| ())),
+        )
+        .arg(
+          Arg::with_name("since-tag")
+            .long("since-tag")
+            .value_name("TAG")
+            .help("Commits that are not in this tag, projects without the tag are skipped")
+            .takes_value(true)
+            .conflicts_with("since"),
+        )
+        .arg(
+          Arg::with_name("by-author")
+            .long("by-author")
+            .help("Group the commits by author instead of by project")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("markdown")
+            .long("markdown")
+            .help("Print markdown, e.g. for release notes")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("stats")
+        .about("Count the commits of all projects by author")
+        .arg(
+          Arg::with_name("since")
+            .long("since")
+            .value_name("AGE_OR_DATE")
+            .help("Commits of the last 2w, 3d, 12h, ... or since a date like 2021-03-01")
+            .takes_value(true)
+            .default_value("4w")
+            .validator(|input| fw_core::changes::parse_since(&input, 0).map(|_| ())),
+        )
+        .arg(
+          Arg::with_name("json")
+            .help("output json instead of cool text")
+            .short("j")
+            .long("json")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("retag-from-forge")
+        .about("Refresh the tags of the projects that come from the topics and the language on GitHub or GitLab (forge_tags in the settings)")
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(timeout_arg("Give up on the GitHub and GitLab APIs after this many seconds")),
+    )
+    .subcommand(
+      SubCommand::with_name("issues")
+        .about("List open issues and pull requests assigned to you in the projects")
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(timeout_arg("Give up on the GitHub and GitLab APIs after this many seconds")),
+    )
+    .subcommand(
+      SubCommand::with_name("release")
+        .about("Tag a release across projects")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(
+          SubCommand::with_name("tag")
+            .about("Create an annotated tag at HEAD in every project and record the tagged commits in a manifest")
+            .arg(
+              Arg::with_name("VERSION")
+                .value_name("VERSION")
+                .help("The name of the tag, e.g. v1.2.3")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("Filter projects by tag. More than 1 is allowed.")
+                .required(false)
+                .takes_value(true)
+                .multiple(true),
+            )
+            .arg(
+              Arg::with_name("message")
+                .long("message")
+                .short("m")
+                .value_name("MESSAGE")
+                .help("The tag message, by default \"Release VERSION\"")
+                .takes_value(true),
+            )
+            .arg(
+              Arg::with_name("push")
+                .long("push")
+                .help("Push the tag to origin")
+                .required(false),
+            )
+            .arg(
+              Arg::with_name("manifest")
+                .long("manifest")
+                .value_name("FILE")
+                .help("Where to write the tagged commits (JSON), by default release-VERSION.json")
+                .takes_value(true),
+            ),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("pr")
+        .about("Open pull requests (GitHub) or merge requests (GitLab) across projects")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(
+          SubCommand::with_name("create")
+            .about("Push the branch and open a pull request in every project where it has commits that are not in the base branch")
+            .arg(
+              Arg::with_name("title")
+                .long("title")
+                .value_name("TITLE")
+                .help("The title of every pull request")
+                .required(true),
+            )
+            .arg(
+              Arg::with_name("body-file")
+                .long("body-file")
+                .value_name("FILE")
+                .help("A file with the description of every pull request")
+                .takes_value(true),
+            )
+            .arg(
+              Arg::with_name("branch")
+                .long("branch")
+                .value_name("BRANCH")
+                .help("The branch to propose, by default the one checked out in each project")
+                .takes_value(true),
+            )
+            .arg(
+              Arg::with_name("base")
+                .long("base")
+                .value_name("BRANCH")
+                .help("The branch to merge into, by default the default branch of origin")
+                .takes_value(true),
+            )
+            .arg(
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("Filter projects by tag. More than 1 is allowed.")
+                .required(false)
+                .takes_value(true)
+                .multiple(true),
+            )
+            .arg(
+              Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only show what would be pushed and proposed")
+                .required(false),
+            )
+            .arg(timeout_arg("Give up on a single API call after this many seconds")),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("propagate")
+        .about("Commit a file to a new branch in many projects, optionally opening pull requests")
+        .arg(
+          Arg::with_name("SOURCE")
+            .value_name("SOURCE")
+            .index(1)
+            .help("The file to copy. {{project.name}}, {{project.git}} and {{tags}} are replaced for every project.")
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("dest")
+            .long("dest")
+            .value_name("PATH")
+            .help("The path of the file in every project, e.g. .github/workflows/ci.yml")
+            .takes_value(true)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("to-tag")
+            .alias("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("branch")
+            .long("branch")
+            .value_name("BRANCH")
+            .help("The branch to commit to, created from the default branch of origin")
+            .default_value("fw-propagate")
+            .takes_value(true),
+        )
+        .arg(
+          Arg::with_name("message")
+            .long("message")
+            .short("m")
+            .value_name("MESSAGE")
+            .help("The commit message and title of the pull requests, by default 'Update PATH'")
+            .takes_value(true),
+        )
+        .arg(Arg::with_name("push").long("push").help("Push the branch").required(false))
+        .arg(
+          Arg::with_name("pr")
+            .long("pr")
+            .help("Push the branch and open a pull request (GitHub) or merge request (GitLab)")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("dry-run")
+            .long("dry-run")
+            .short("n")
+            .help("Only show where the file would be committed")
+            .required(false),
+        )
+        .arg(timeout_arg("Give up on a single API call after this many seconds")),
+    )
+    .subcommand(
+      SubCommand::with_name("apply-patch")
+        .about("Apply a patch or a substitution to many projects and commit it")
+        .arg(
+          Arg::with_name("PATCH")
+            .value_name("PATCH")
+            .index(1)
+            .help("A patch as printed by git diff, - reads it from stdin")
+            .required_unless("sed"),
+        )
+        .arg(
+          Arg::with_name("sed")
+            .long("sed")
+            .value_name("EXPRESSION")
+            .help("A substitution like s/old/new/g in every line of the tracked files instead of a patch")
+            .takes_value(true)
+            .conflicts_with("PATCH")
+            .validator(|input| fw_core::patch::parse_substitution(&input).map(|_| ())),
+        )
+        .arg(
+          Arg::with_name("files")
+            .long("files")
+            .value_name("GLOB")
+            .help("Only substitute in the files matching this pattern, e.g. '**/Cargo.toml'")
+            .takes_value(true)
+            .requires("sed"),
+        )
+        .arg(
+          Arg::with_name("message")
+            .long("message")
+            .short("m")
+            .value_name("MESSAGE")
+            .help("The commit message in every project")
+            .takes_value(true)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("dry-run")
+            .long("dry-run")
+            .short("n")
+            .help("Only show which projects would change")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("pull")
+        .about("Fetch projects and bring their checked out branches up to date")
+        .arg(
+          Arg::with_name("rebase")
+            .long("rebase")
+            .help("Rebase branches that diverged from their upstream, by default only fast-forwards are done")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("autostash")
+            .long("autostash")
+            .help("Stash uncommitted changes before and pop them after, by default projects with changes fail")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("wait")
+            .long("wait")
+            .help("Wait for projects locked by another fw process")
+            .conflicts_with("skip-locked")
+            .takes_value(false),
+        )
+        .arg(
+          Arg::with_name("skip-locked")
+            .long("skip-locked")
+            .help("Skip projects locked by another fw process")
+            .takes_value(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("stash-all")
+        .about("Stash uncommitted changes in every project, fw unstash-all brings them back")
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("include-untracked")
+            .long("include-untracked")
+            .short("u")
+            .help("Stash untracked files as well")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("unstash-all").about("Pop the stashes made by fw stash-all").arg(
+        Arg::with_name("tag")
+          .long("tag")
+          .short("t")
+          .help("Filter projects by tag. More than 1 is allowed.")
+          .required(false)
+          .takes_value(true)
+          .multiple(true),
+      ),
+    )
+    .subcommand(
+      SubCommand::with_name("snapshot")
+        .about("Write branch, HEAD and dirty state of every checked out project to a file")
+        .arg(
+          Arg::with_name("FILE")
+            .value_name("FILE")
+            .help("The snapshot file to write (JSON)")
+            .required(true)
+            .index(1),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("restore")
+        .about("Check out the branches and commits recorded by fw snapshot")
+        .arg(
+          Arg::with_name("FILE")
+            .value_name("FILE")
+            .help("A snapshot file written by fw snapshot")
+            .required(true)
+            .index(1),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        )
+        .arg(
+          Arg::with_name("force")
+            .long("force")
+            .help("Discard uncommitted changes and reset branches that moved since the snapshot")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("manifest")
+        .about("Write and check out the HEAD commits of all projects")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(
+          SubCommand::with_name("write")
+            .about("Write the HEAD commit of every checked out project to a file")
+            .arg(
+              Arg::with_name("FILE")
+                .value_name("FILE")
+                .help("The manifest file to write (JSON)")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("Filter projects by tag. More than 1 is allowed.")
+                .required(false)
+                .takes_value(true)
+                .multiple(true),
+            ),
+        )
+        .subcommand(
+          SubCommand::with_name("checkout")
+            .about("Detach HEAD at the commits of a manifest")
+            .arg(
+              Arg::with_name("FILE")
+                .value_name("FILE")
+                .help("A manifest written by fw manifest write or fw release tag")
+                .required(true)
+                .index(1),
+            )
+            .arg(
+              Arg::with_name("tag")
+                .long("tag")
+                .short("t")
+                .help("Filter projects by tag. More than 1 is allowed.")
+                .required(false)
+                .takes_value(true)
+                .multiple(true),
+            )
+            .arg(
+              Arg::with_name("force")
+                .long("force")
+                .help("Discard uncommitted changes")
+                .required(false),
+            ),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("backup")
+        .about("Back up all projects as git bundles, with only the new commits after the first backup")
+        .arg(
+          Arg::with_name("dest")
+            .long("dest")
+            .value_name("DIR")
+            .help("The backup directory, with a directory per project")
+            .takes_value(true)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("restore-from-bundles")
+        .about("Rebuild the projects that are not checked out from the bundles of fw backup, offline")
+        .arg(
+          Arg::with_name("from")
+            .long("from")
+            .value_name("DIR")
+            .help("The directory fw backup wrote to")
+            .takes_value(true)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .help("Filter projects by tag. More than 1 is allowed.")
+            .required(false)
+            .takes_value(true)
+            .multiple(true),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("gen-workon")
+        .about("Generate sourceable shell code to work on project")
+        .arg(
+          Arg::with_name("PROJECT_NAME")
+            .value_name("PROJECT_NAME")
+            .help("Project name or git URL, - for the project worked on before the last one")
+            .index(1)
+            .required_unless("last"),
+        )
+        .arg(
+          Arg::with_name("last")
+            .long("last")
+            .help("Work on the project worked on last again")
+            .conflicts_with_all(&["PROJECT_NAME", "create"]),
+        )
+        .arg(
+          Arg::with_name("quick")
+            .required(false)
+            .short("x")
+            .help("Don't generate post_workon shell code, only cd into the folder"),
+        )
+        .arg(
+          Arg::with_name("create")
+            .long("create")
+            .short("c")
+            .help("Add PROJECT_NAME to the config if it is a git URL and clone it if it is missing. Implied for git URLs."),
+        ),
+    )
+    .subcommand(SubCommand::with_name("gen-reworkon").about("Generate sourceable shell code to re-work on project"))
+    .subcommand(
+      SubCommand::with_name("inspect")
+        .about("Inspect project")
+        .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").index(1).required(true))
+        .arg(
+          Arg::with_name("json")
+            .help("output json instead of cool text")
+            .short("j")
+            .long("json")
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("explain")
+            .help("Show for each resolved field which layer sets it: the project, a tag or the settings")
+            .short("e")
+            .long("explain")
+            .conflicts_with("json")
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("trust")
+        .about("Approve the hooks of a project from a shared config or a .fw.json you did not write")
+        .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").index(1).required(true)),
+    )
+    .subcommand(
+      SubCommand::with_name("url")
+        .about("Print the git URL of a project")
+        .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").index(1).required(true)),
+    )
+    .subcommand(
+      SubCommand::with_name("main-branch")
+        .about("Print the default branch of a cloned project's origin")
+        .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").index(1).required(true)),
+    )
+    .subcommand(
+      SubCommand::with_name("update")
+        .about("Modifies project settings.")
+        .arg(Arg::with_name("NAME").value_name("NAME").required(true))
+        .arg(Arg::with_name("git").value_name("URL").long("git-url").takes_value(true).required(false))
+        .arg(
+          Arg::with_name("override-path")
+            .value_name("override-path")
+            .long("override-path")
+            .takes_value(true)
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("after-workon")
+            .value_name("after-workon")
+            .long("after-workon")
+            .takes_value(true)
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("after-clone")
+            .value_name("after-clone")
+            .long("after-clone")
+            .takes_value(true)
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("tag")
+        .alias("tags")
+        .about("Allows working with tags.")
+        .subcommand(
+          SubCommand::with_name("ls")
+            .alias("list")
+            .about("Lists tags")
+            .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").required(false)),
+        )
+        .subcommand(
+          SubCommand::with_name("tag-project")
+            .about("Add tag to project")
+            .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").required(true))
+            .arg(Arg::with_name("tag-name").value_name("tag").required(true)),
+        )
+        .subcommand(
+          SubCommand::with_name("untag-project")
+            .about("Removes tag from project")
+            .arg(Arg::with_name("PROJECT_NAME").value_name("PROJECT_NAME").required(true))
+            .arg(Arg::with_name("tag-name").value_name("tag").required(true)),
+        )
+        .subcommand(
+          SubCommand::with_name("autotag")
+            .about("tags projects when CMD returns exit code 0, or by the autotag rules in the settings with --apply")
+            .arg(Arg::with_name("tag-name").value_name("tag").required_unless("apply"))
+            .arg(Arg::with_name("CMD").value_name("CMD").required_unless("apply"))
+            .arg(
+              Arg::with_name("apply")
+                .long("apply")
+                .help("Tag all projects matching the autotag rules in the settings")
+                .conflicts_with_all(&["tag-name", "CMD"]),
+            )
+            .arg(
+              Arg::with_name("parallel")
+                .short("p")
+                .help("Parallelism to use (default is set by rayon but probably equal to the number of cores)")
+                .required(false)
+                .validator(|input| validate_number(&input, 20))
+                .takes_value(true),
+            ),
+        )
+        .subcommand(
+          SubCommand::with_name("inspect")
+            .about("Inspect a tag")
+            .arg(Arg::with_name("tag-name").value_name("tag name").required(true)),
+        )
+        .subcommand(
+          SubCommand::with_name("rm")
+            .about("Deletes a tag. Will not untag projects.")
+            .arg(Arg::with_name("tag-name").value_name("tag name").required(true)),
+        )
+        .subcommand(
+          SubCommand::with_name("add")
+            .alias("update")
+            .alias("create")
+            .about("Creates a new tag. Replaces existing.")
+            .arg(Arg::with_name("tag-name").value_name("tag name").required(true))
+            .arg(
+              Arg::with_name("after-workon")
+                .value_name("after-workon")
+                .long("after-workon")
+                .takes_value(true)
+                .required(false),
+            )
+            .arg(
+              Arg::with_name("priority")
+                .value_name("priority")
+                .long("priority")
                 .takes_value(true)
                 .required(false),
             )
@@ -369,4 +1855,66 @@ For further information please have a look at our README https://github.com/broc
             ),
         ),
     )
+    .subcommand(
+      SubCommand::with_name("diff-config")
+        .about("Show how the projects differ from a shared config and optionally take them over")
+        .arg(
+          Arg::with_name("SOURCE")
+            .value_name("SOURCE")
+            .help("Directory laid out like the fw config, or git URL of a repository that is")
+            .index(1)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("ref")
+            .long("ref")
+            .value_name("REF")
+            .help("Branch, tag or commit of the shared config to compare against")
+            .takes_value(true)
+            .required(false),
+        )
+        .arg(
+          Arg::with_name("apply")
+            .long("apply")
+            .value_name("PROJECT_NAME")
+            .help("Write the shared definitions to the local config. Without names all added and changed projects, removals only when named.")
+            .takes_value(true)
+            .min_values(0)
+            .multiple(true)
+            .required(false),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("config")
+        .about("Work with the fw configuration itself")
+        .setting(AppSettings::SubcommandRequired)
+        .subcommand(
+          SubCommand::with_name("schema").about("Print a JSON Schema of the configuration").arg(
+            Arg::with_name("KIND")
+              .value_name("KIND")
+              .help("config for the whole configuration, or the schema of a single settings, project or tag file")
+              .possible_values(&["config", "settings", "project", "tag"])
+              .default_value("config")
+              .index(1),
+          ),
+        )
+        .subcommand(
+          SubCommand::with_name("encrypt")
+            .about("Encrypt the settings file so tokens are not stored in plain text. fw decrypts it on every read and keeps it encrypted on writes.")
+            .arg(
+              Arg::with_name("with")
+                .long("with")
+                .value_name("ENCRYPTION")
+                .help("age uses the identity in FW_AGE_IDENTITY (recipient FW_AGE_RECIPIENT), gpg your keyring or agent (recipient FW_GPG_RECIPIENT)")
+                .possible_values(&["age", "gpg"])
+                .default_value("gpg"),
+            )
+            .arg(Arg::with_name("all").long("all").help("Encrypt the project and tag files as well")),
+        )
+        .subcommand(
+          SubCommand::with_name("decrypt")
+            .about("Store an encrypted config in plain text again")
+            .arg(Arg::with_name("all").long("all").help("Decrypt the project and tag files as well")),
+        ),
+    )
 }
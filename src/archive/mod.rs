@@ -0,0 +1,340 @@
+use crate::config::{self, project::Project, Config};
+use crate::errors::AppError;
+use crate::git::{clone_project, run_git, update_lfs};
+use git2::{BranchType, Oid, Repository, StatusOptions};
+use slog::{debug, info, o, Logger};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A clone of a bundle only has `refs/stash` as a ref but none of the older entries, so every stash gets a ref of its own
+// under this prefix while the bundle is written, the newest one is 0.
+const STASH_REFS: &str = "refs/fw-stashes/";
+
+/// How `fw archive` and `fw unarchive` handle the bundle of the project.
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveOptions {
+  /// Keeps every branch and tag of the checkout in a `git bundle` before it is removed.
+  pub bundle: bool,
+  /// Where the bundles are, by default `archive` in the state directory.
+  pub bundle_dir: Option<PathBuf>,
+  /// Removes the checkout even with uncommitted, untracked or unpushed work.
+  pub force: bool,
+}
+
+impl ArchiveOptions {
+  fn bundle_path(&self, name: &str) -> Result<PathBuf, AppError> {
+    let dir = match &self.bundle_dir {
+      Some(dir) => dir.clone(),
+      None => config::path::fw_path()?.state.join("archive"),
+    };
+    Ok(dir.join(format!("{}.bundle", name)))
+  }
+}
+
+/// Local branches with commits that are in no remote-tracking branch.
+fn unpushed_branches(repo: &Repository) -> Result<Vec<String>, AppError> {
+  let mut remote_tips: Vec<Oid> = Vec::new();
+  for remote_branch in repo.branches(Some(BranchType::Remote))? {
+    if let Some(tip) = remote_branch?.0.get().target() {
+      remote_tips.push(tip);
+    }
+  }
+  let mut unpushed = Vec::new();
+  for branch in repo.branches(Some(BranchType::Local))? {
+    let (branch, _) = branch?;
+    let tip = match branch.get().target() {
+      Some(tip) => tip,
+      None => continue,
+    };
+    let mut pushed = false;
+    for remote_tip in &remote_tips {
+      if *remote_tip == tip || repo.graph_descendant_of(*remote_tip, tip)? {
+        pushed = true;
+        break;
+      }
+    }
+    if !pushed {
+      unpushed.push(branch.name()?.unwrap_or_default().to_owned());
+    }
+  }
+  Ok(unpushed)
+}
+
+// Unlike `git::is_dirty` untracked files count, a bundle does not keep them either. Ignored files do not.
+fn has_local_changes(repo: &Repository) -> Result<bool, AppError> {
+  let mut options = StatusOptions::new();
+  options.include_untracked(true).include_ignored(false);
+  Ok(!repo.statuses(Some(&mut options))?.is_empty())
+}
+
+// Without a bundle only work that is on a remote survives the removal of the checkout.
+fn check_removable(repo: &mut Repository, options: &ArchiveOptions) -> Result<(), AppError> {
+  if options.force {
+    return Ok(());
+  }
+  if has_local_changes(repo)? {
+    return Err(AppError::UserError(
+      "The checkout has uncommitted changes or untracked files, commit them or use --force to lose them".to_owned(),
+    ));
+  }
+  if options.bundle {
+    return Ok(());
+  }
+  let unpushed = unpushed_branches(repo)?;
+  if !unpushed.is_empty() {
+    return Err(AppError::UserError(format!(
+      "{} not pushed, use --bundle to keep them or --force to lose them",
+      unpushed.join(", ")
+    )));
+  }
+  let mut stashes = 0;
+  repo.stash_foreach(|_, _, _| {
+    stashes += 1;
+    true
+  })?;
+  if stashes > 0 {
+    return Err(AppError::UserError(format!(
+      "{} stash(es), use --bundle to keep them or --force to lose them",
+      stashes
+    )));
+  }
+  Ok(())
+}
+
+fn stash_refs(repo: &mut Repository) -> Result<Vec<String>, AppError> {
+  let mut stashes = Vec::new();
+  repo.stash_foreach(|index, _, oid| {
+    stashes.push((index, *oid));
+    true
+  })?;
+  let mut refs = Vec::new();
+  for (index, oid) in stashes {
+    let name = format!("{}{}", STASH_REFS, index);
+    repo.reference(&name, oid, true, "fw archive")?;
+    refs.push(name);
+  }
+  Ok(refs)
+}
+
+fn write_bundle(path: &Path, bundle_path: &Path) -> Result<(), AppError> {
+  let mut repo = Repository::open(path)?;
+  let refs = stash_refs(&mut repo)?;
+  let written = run_git(path, &["bundle", "create", &bundle_path.to_string_lossy(), "--all"]);
+  for name in refs {
+    repo.find_reference(&name)?.delete()?;
+  }
+  written
+}
+
+// Puts the stashes of the bundle back on the stash of the clone, the oldest first so their order stays the same.
+fn restore_stashes(repo: &Repository, bundle_path: &Path) -> Result<(), AppError> {
+  let path = repo.workdir().ok_or(AppError::InternalError("bundle clone has no working directory"))?;
+  let refspec = format!("{}*:{}*", STASH_REFS, STASH_REFS);
+  run_git(path, &["fetch", &bundle_path.to_string_lossy(), &refspec])?;
+  let mut stashes = Vec::new();
+  for reference in repo.references_glob(&format!("{}*", STASH_REFS))? {
+    let reference = reference?;
+    let name = reference.name().unwrap_or_default().to_owned();
+    if let (Ok(index), Some(oid)) = (name[STASH_REFS.len()..].parse::<usize>(), reference.target()) {
+      stashes.push((index, oid));
+    }
+  }
+  stashes.sort_by_key(|(index, _)| std::cmp::Reverse(*index));
+  for (index, oid) in stashes {
+    let commit = repo.find_commit(oid)?;
+    let message = commit.summary().unwrap_or("fw unarchive").to_owned();
+    run_git(path, &["stash", "store", "-m", &message, &oid.to_string()])?;
+    repo.find_reference(&format!("{}{}", STASH_REFS, index))?.delete()?;
+  }
+  Ok(())
+}
+
+// A clone of a bundle has the bundled branches as `origin/*`. They become local branches and the remote-tracking ones are
+// dropped, the next fetch brings back those that really are on origin.
+fn adopt_bundle_branches(repo: &Repository) -> Result<(), AppError> {
+  for remote_branch in repo.branches(Some(BranchType::Remote))? {
+    let (mut remote_branch, _) = remote_branch?;
+    let name = remote_branch.name()?.unwrap_or_default().to_owned();
+    if let Some(local) = name.strip_prefix("origin/").filter(|local| *local != "HEAD") {
+      if repo.find_branch(local, BranchType::Local).is_err() {
+        repo.branch(local, &remote_branch.get().peel_to_commit()?, false)?;
+      }
+    }
+    remote_branch.delete()?;
+  }
+  Ok(())
+}
+
+fn project_named(config: &Config, name: &str) -> Result<Project, AppError> {
+  config
+    .projects
+    .get(name)
+    .cloned()
+    .ok_or_else(|| AppError::UserError(format!("project key {} not found", name)))
+}
+
+/// Marks the project archived, optionally keeps its checkout in a bundle and removes the checkout, unless other
+/// projects share it through `subdir`. Archived projects are left out of `fw sync`, `fw ls` and the other commands that
+/// work on many projects.
+pub fn archive(maybe_config: Result<Config, AppError>, name: &str, options: &ArchiveOptions, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut project = project_named(&config, name)?;
+  if project.archived.unwrap_or_default() {
+    return Err(AppError::UserError(format!("{} is already archived", name)));
+  }
+  let logger = logger.new(o!("project" => project.name.clone()));
+  let path = config.checkout_path(&project, &logger);
+  // the clone of a project with a subdir belongs to the other projects in it as well
+  let shared = project.subdir.is_some();
+  let mut bundle = None;
+  if shared && options.bundle {
+    return Err(AppError::UserError(format!(
+      "{} shares its clone through subdir, there is nothing of its own to bundle",
+      name
+    )));
+  } else if path.exists() && !shared {
+    let mut repo = Repository::open(&path)?;
+    check_removable(&mut repo, options)?;
+    if options.bundle {
+      let bundle_path = options.bundle_path(name)?;
+      if let Some(dir) = bundle_path.parent() {
+        fs::create_dir_all(dir)?;
+      }
+      write_bundle(&path, &bundle_path)?;
+      info!(logger, "Bundle written"; "path" => bundle_path.to_string_lossy().to_string());
+      bundle = Some(bundle_path);
+    }
+  } else if options.bundle {
+    return Err(AppError::UserError(format!("{} is not checked out, there is nothing to bundle", name)));
+  }
+
+  project.archived = Some(true);
+  project.archive_bundle = bundle.as_ref().map(|bundle| bundle.to_string_lossy().to_string());
+  config::write_project(&project, &logger)?;
+  if path.exists() && !shared {
+    fs::remove_dir_all(&path)?;
+    debug!(logger, "Checkout removed"; "path" => path.to_string_lossy().to_string());
+  }
+  match bundle {
+    Some(bundle) => println!("Archived {}, the bundle is {}", name, bundle.to_string_lossy()),
+    None => println!("Archived {}", name),
+  }
+  Ok(())
+}
+
+/// Brings an archived project back: the checkout is restored from its bundle if there is one, cloned otherwise.
+pub fn unarchive(maybe_config: Result<Config, AppError>, name: &str, options: &ArchiveOptions, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut project = project_named(&config, name)?;
+  if !project.archived.unwrap_or_default() {
+    return Err(AppError::UserError(format!("{} is not archived", name)));
+  }
+  let logger = logger.new(o!("project" => project.name.clone()));
+  let path = config.checkout_path(&project, &logger);
+  // the bundle is where `fw archive` wrote it unless --bundle-dir says otherwise
+  let bundle = match (&options.bundle_dir, &project.archive_bundle) {
+    (None, Some(bundle)) => PathBuf::from(bundle),
+    _ => options.bundle_path(name)?,
+  };
+  let kept = path.exists();
+  let message = if kept {
+    format!("Unarchived {}, kept the checkout at {}", name, path.to_string_lossy())
+  } else if bundle.exists() {
+    let parent = path.parent().ok_or(AppError::InternalError("project path has no parent"))?;
+    fs::create_dir_all(parent)?;
+    run_git(parent, &["clone", &bundle.to_string_lossy(), &path.to_string_lossy()])?;
+    let repo = Repository::open(&path)?;
+    adopt_bundle_branches(&repo)?;
+    restore_stashes(&repo, &bundle)?;
+    repo.remote_set_url("origin", &project.git)?;
+    format!("Unarchived {} from {}, fw sync adds its other remotes", name, bundle.to_string_lossy())
+  } else {
+    clone_project(&config, &project, &path, &logger)?;
+    format!("Unarchived {}, cloned to {}", name, path.to_string_lossy())
+  };
+  // bundles do not carry LFS objects, they come from origin either way
+  if !kept && config.resolve_lfs(&project, &logger) {
+    update_lfs(&path, true, project.bare.unwrap_or_default(), &logger)?;
+  }
+  project.archived = None;
+  project.archive_bundle = None;
+  config::write_project(&project, &logger)?;
+  println!("{}", message);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{commit_file, signature, temp_dir, test_repo};
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_unpushed_branches() {
+    let (_dir, repo) = test_repo();
+    let first = repo.head().unwrap().peel_to_commit().unwrap();
+    let head = repo.head().unwrap().shorthand().unwrap().to_owned();
+    assert_that(&unpushed_branches(&repo).unwrap()).is_equal_to(vec![head.clone()]);
+
+    repo.reference("refs/remotes/origin/main", first.id(), false, "pushed").unwrap();
+    assert_that(&unpushed_branches(&repo).unwrap()).is_equal_to(vec![]);
+
+    let second = repo
+      .commit(None, &signature(), &signature(), "work", &first.tree().unwrap(), &[&first])
+      .unwrap();
+    repo.branch("feature", &repo.find_commit(second).unwrap(), false).unwrap();
+    assert_that(&unpushed_branches(&repo).unwrap()).is_equal_to(vec!["feature".to_owned()]);
+  }
+
+  #[test]
+  fn test_untracked_files_block_the_archive() {
+    let (temp, mut repo) = test_repo();
+    let dir = temp.path();
+    let options = ArchiveOptions {
+      bundle: true,
+      ..ArchiveOptions::default()
+    };
+    fs::write(dir.join(".git/info/exclude"), "target\n").unwrap();
+    fs::write(dir.join("target"), "build output").unwrap();
+    let ignored = check_removable(&mut repo, &options);
+    fs::write(dir.join("notes.txt"), "work in progress").unwrap();
+
+    let refused = check_removable(&mut repo, &options);
+    let forced = check_removable(&mut repo, &ArchiveOptions { force: true, ..options });
+
+    assert_that(&ignored).is_ok();
+    assert_that(&refused).is_err();
+    assert_that(&forced).is_ok();
+  }
+
+  #[test]
+  fn test_stashes_survive_the_bundle() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let checkout = dir.join("checkout");
+    let mut repo = Repository::init(&checkout).unwrap();
+    commit_file(&repo, "file", "0");
+    for work in &["older", "newer"] {
+      fs::write(checkout.join("file"), work).unwrap();
+      repo.stash_save(&signature(), work, None).unwrap();
+    }
+
+    let bundle = dir.join("checkout.bundle");
+    write_bundle(&checkout, &bundle).unwrap();
+    assert_that(&repo.references_glob("refs/fw-stashes/*").unwrap().count()).is_equal_to(0);
+
+    let clone = dir.join("clone");
+    run_git(dir, &["clone", &bundle.to_string_lossy(), &clone.to_string_lossy()]).unwrap();
+    let mut restored = Repository::open(&clone).unwrap();
+    restore_stashes(&restored, &bundle).unwrap();
+    assert_that(&restored.references_glob("refs/fw-stashes/*").unwrap().count()).is_equal_to(0);
+    let mut messages = Vec::new();
+    restored
+      .stash_foreach(|_, message, _| {
+        messages.push(message.rsplit(": ").next().unwrap_or_default().to_owned());
+        true
+      })
+      .unwrap();
+    assert_that(&messages).is_equal_to(vec!["newer".to_owned(), "older".to_owned()]);
+  }
+}
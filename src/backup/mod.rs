@@ -0,0 +1,261 @@
+use crate::config::{self, project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git::run_git;
+use crate::output::{Cell, Table, Tone};
+use git2::build::CheckoutBuilder;
+use git2::{BranchType, Oid, ReferenceType, Repository};
+use serde::{Deserialize, Serialize};
+use slog::{debug, o, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MANIFEST: &str = "backup.json";
+
+/// One `fw backup` of a project: every ref at that time and the bundle with what is new since the earlier backups.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupEntry {
+  /// Seconds since the epoch.
+  pub created: u64,
+  /// The bundle file next to the manifest, `None` if only refs moved to commits of earlier bundles.
+  pub bundle: Option<String>,
+  /// What HEAD points to, a ref like `refs/heads/main` or a commit.
+  pub head: String,
+  pub refs: BTreeMap<String, String>,
+}
+
+/// The backups of a project, `backup.json` in its directory of the backup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectBackup {
+  pub git: String,
+  pub backups: Vec<BackupEntry>,
+}
+
+fn read_backup(dir: &Path) -> Result<Option<ProjectBackup>, AppError> {
+  let manifest = dir.join(MANIFEST);
+  if !manifest.exists() {
+    return Ok(None);
+  }
+  let content = fs::read_to_string(&manifest)?;
+  serde_json::from_str(&content)
+    .map(Some)
+    .map_err(|e| AppError::UserError(format!("Invalid backup manifest {}: {}", manifest.to_string_lossy(), e)))
+}
+
+// Symbolic refs like `refs/remotes/origin/HEAD` are left out, they follow the refs they point to.
+fn current_refs(repo: &Repository) -> Result<BTreeMap<String, String>, AppError> {
+  let mut refs = BTreeMap::new();
+  for reference in repo.references()? {
+    let reference = reference?;
+    if reference.kind() != Some(ReferenceType::Direct) {
+      continue;
+    }
+    if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+      refs.insert(name.to_owned(), target.to_string());
+    }
+  }
+  Ok(refs)
+}
+
+fn head_of(repo: &Repository) -> Result<String, AppError> {
+  let head = repo.find_reference("HEAD")?;
+  match head.symbolic_target() {
+    Some(target) => Ok(target.to_owned()),
+    None => head
+      .target()
+      .map(|oid| oid.to_string())
+      .ok_or(AppError::InternalError("HEAD is neither symbolic nor direct")),
+  }
+}
+
+// Only what is not in an earlier bundle goes into the new one, git refuses to write a bundle without new objects.
+fn backup_repo(repo: &Repository, path: &Path, dir: &Path, git: &str, created: u64) -> Result<(String, Tone), AppError> {
+  let mut backup = read_backup(dir)?.unwrap_or_else(|| ProjectBackup {
+    git: git.to_owned(),
+    backups: Vec::new(),
+  });
+  let refs = current_refs(repo)?;
+  let head = head_of(repo)?;
+  if refs.is_empty() {
+    return Ok(("nothing to back up".to_owned(), Tone::Muted));
+  }
+  if backup.backups.last().map(|last| last.refs == refs && last.head == head).unwrap_or(false) {
+    return Ok(("up to date".to_owned(), Tone::Muted));
+  }
+
+  let known: BTreeSet<&String> = backup.backups.iter().flat_map(|entry| entry.refs.values()).collect();
+  let known: Vec<&String> = known
+    .into_iter()
+    .filter(|oid| Oid::from_str(oid).map(|oid| repo.find_object(oid, None).is_ok()).unwrap_or(false))
+    .collect();
+  let file = format!("{:04}.bundle", backup.backups.len() + 1);
+  let bundle_path = dir.join(&file).to_string_lossy().to_string();
+  let mut args = vec!["bundle", "create", bundle_path.as_str()];
+  args.extend(refs.keys().map(String::as_str));
+  if !known.is_empty() {
+    args.push("--not");
+    args.extend(known.iter().map(|oid| oid.as_str()));
+  }
+  fs::create_dir_all(dir)?;
+  let bundle = match run_git(path, &args) {
+    Ok(()) => Some(file),
+    Err(AppError::UserError(message)) if message.contains("empty bundle") => None,
+    Err(e) => return Err(e),
+  };
+  let message = match &bundle {
+    Some(file) => (format!("{} refs, new commits in {}", refs.len(), file), Tone::Good),
+    None => (format!("{} refs, no new commits", refs.len()), Tone::Good),
+  };
+  backup.backups.push(BackupEntry { created, bundle, head, refs });
+  config::write_atomically(&dir.join(MANIFEST), &format!("{}\n", serde_json::to_string_pretty(&backup)?))?;
+  Ok(message)
+}
+
+// Fetches every bundle in order and then sets the refs and HEAD to those of the last backup.
+fn restore_repo(path: &Path, backup: &ProjectBackup, dir: &Path, bare: bool) -> Result<usize, AppError> {
+  let last = backup.backups.last().ok_or(AppError::InternalError("restoring an empty backup"))?;
+  let repo = if bare { Repository::init_bare(path)? } else { Repository::init(path)? };
+  let bundles: Vec<&String> = backup.backups.iter().filter_map(|entry| entry.bundle.as_ref()).collect();
+  for bundle in &bundles {
+    let bundle = dir.join(bundle).to_string_lossy().to_string();
+    run_git(path, &["fetch", "--quiet", "--update-head-ok", &bundle, "+refs/*:refs/*"])?;
+  }
+
+  let stale: Vec<String> = current_refs(&repo)?.into_keys().filter(|name| !last.refs.contains_key(name)).collect();
+  for name in stale {
+    repo.find_reference(&name)?.delete()?;
+  }
+  for (name, target) in &last.refs {
+    repo.reference(name, Oid::from_str(target)?, true, "fw restore-from-bundles")?;
+  }
+  if last.head.starts_with("refs/") {
+    repo.set_head(&last.head)?;
+  } else {
+    repo.set_head_detached(Oid::from_str(&last.head)?)?;
+  }
+  if !bare && repo.head().is_ok() {
+    repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+  }
+
+  repo.remote("origin", &backup.git)?;
+  for branch in repo.branches(Some(BranchType::Local))? {
+    let (mut branch, _) = branch?;
+    let upstream = format!("origin/{}", branch.name()?.unwrap_or_default());
+    if repo.find_branch(&upstream, BranchType::Remote).is_ok() {
+      branch.set_upstream(Some(&upstream))?;
+    }
+  }
+  Ok(bundles.len())
+}
+
+fn restore_project(config: &Config, project: &Project, dir: &Path, logger: &Logger) -> Result<(String, Tone), AppError> {
+  let path = config.checkout_path(project, logger);
+  if path.exists() {
+    return Ok(("already checked out".to_owned(), Tone::Muted));
+  }
+  let backup = match read_backup(dir)? {
+    Some(backup) if !backup.backups.is_empty() => backup,
+    _ => return Ok(("no backup".to_owned(), Tone::Muted)),
+  };
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  match restore_repo(&path, &backup, dir, project.bare.unwrap_or_default()) {
+    Ok(bundles) => Ok((format!("restored from {} bundle(s)", bundles), Tone::Good)),
+    Err(e) => {
+      debug!(logger, "Removing partial restore");
+      let _ = fs::remove_dir_all(&path);
+      Err(e)
+    }
+  }
+}
+
+fn print_results(table: Table, failed: usize) -> Result<(), AppError> {
+  table.print();
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+fn add_result(table: &mut Table, failed: &mut usize, project: &Project, result: Result<(String, Tone), AppError>) {
+  let (message, tone) = match result {
+    Ok(result) => result,
+    Err(AppError::UserError(message)) => {
+      *failed += 1;
+      (message, Tone::Bad)
+    }
+    Err(e) => {
+      *failed += 1;
+      (format!("{}", e), Tone::Bad)
+    }
+  };
+  table.add_row(vec![Cell::plain(project.name.clone()), Cell::new(message, tone)]);
+}
+
+/// Writes a bundle of every selected project that is checked out to `dest/<project>/`, with only the commits that are
+/// not in the earlier bundles there, and records the refs in `dest/<project>/backup.json`.
+pub fn backup(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, dest: &Path, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let created = SystemTime::now().duration_since(UNIX_EPOCH).map_err(AppError::ClockError)?.as_secs();
+  let mut table = Table::new();
+  let mut failed = 0;
+  for project in selector.select_repositories(&config) {
+    let path = config.checkout_path(project, logger);
+    if !path.exists() {
+      debug!(logger, "Skipping, not checked out"; "project" => &project.name);
+      continue;
+    }
+    let result = Repository::open(&path)
+      .map_err(AppError::from)
+      .and_then(|repo| backup_repo(&repo, &path, &dest.join(&project.name), &project.git, created));
+    add_result(&mut table, &mut failed, project, result);
+  }
+  print_results(table, failed)
+}
+
+/// Rebuilds the selected projects that are not checked out from the bundles in `from`, without network access. The
+/// checkouts are at the state of the last backup and no hooks run.
+pub fn restore_from_bundles(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, from: &Path, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut table = Table::new();
+  let mut failed = 0;
+  for project in selector.select_repositories(&config) {
+    let project_logger = logger.new(o!("project" => project.name.clone()));
+    let result = restore_project(&config, project, &from.join(&project.name), &project_logger);
+    add_result(&mut table, &mut failed, project, result);
+  }
+  print_results(table, failed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{commit, temp_dir};
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_incremental_backup_and_restore() {
+    let root = temp_dir();
+    let (path, dir, restored) = (root.path().join("work"), root.path().join("backup"), root.path().join("restored"));
+    let repo = Repository::init(&path).unwrap();
+    let first = commit(&repo, "init");
+    let git = "git@github.com:brocode/fw.git";
+
+    assert_that(&backup_repo(&repo, &path, &dir, git, 1).unwrap().0).ends_with("new commits in 0001.bundle");
+    assert_that(&backup_repo(&repo, &path, &dir, git, 2).unwrap().0).is_equal_to("up to date".to_owned());
+    let second = commit(&repo, "work");
+    assert_that(&backup_repo(&repo, &path, &dir, git, 3).unwrap().0).ends_with("new commits in 0002.bundle");
+    repo.branch("old", &repo.find_commit(first).unwrap(), false).unwrap();
+    assert_that(&backup_repo(&repo, &path, &dir, git, 4).unwrap().0).ends_with("no new commits");
+
+    let backup = read_backup(&dir).unwrap().unwrap();
+    assert_that(&restore_repo(&restored, &backup, &dir, false).unwrap()).is_equal_to(2);
+    let restored = Repository::open(&restored).unwrap();
+    assert_that(&current_refs(&restored).unwrap()).is_equal_to(current_refs(&repo).unwrap());
+    assert_that(&restored.head().unwrap().target()).is_equal_to(Some(second));
+    assert_that(&restored.find_remote("origin").unwrap().url()).is_equal_to(Some(git));
+  }
+}
@@ -0,0 +1,349 @@
+use crate::config::{project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git::{default_branch, gone_remote_branches};
+use crate::output::{Cell, Table, Tone};
+use git2::build::CheckoutBuilder;
+use git2::{BranchType, Oid, Repository};
+use slog::{debug, Logger};
+
+fn short(oid: git2::Oid) -> String {
+  oid.to_string()[..8].to_owned()
+}
+
+fn current_branch(repo: &Repository) -> Option<String> {
+  repo
+    .head()
+    .ok()
+    .filter(|head| head.is_branch())
+    .and_then(|head| head.shorthand().map(ToOwned::to_owned))
+}
+
+// Runs `action` in every selected project that is checked out and prints one row per project. Fails if any project failed.
+fn for_each_repo<F>(config: &Config, selector: &ProjectSelector, logger: &Logger, mut action: F) -> Result<(), AppError>
+where
+  F: FnMut(&Project, &Repository) -> Result<(String, Tone), AppError>,
+{
+  for_each_repo_row(config, selector, logger, |project, repo| {
+    action(project, repo).map(|(message, tone)| vec![Cell::new(message, tone)])
+  })
+}
+
+// Like `for_each_repo`, with any number of cells after the project name.
+fn for_each_repo_row<F>(config: &Config, selector: &ProjectSelector, logger: &Logger, mut action: F) -> Result<(), AppError>
+where
+  F: FnMut(&Project, &Repository) -> Result<Vec<Cell>, AppError>,
+{
+  let mut table = Table::new();
+  let mut failed = 0;
+  for project in selector.select_repositories(config) {
+    let path = config.checkout_path(project, logger);
+    if !path.exists() {
+      debug!(logger, "Skipping, not checked out"; "project" => &project.name);
+      continue;
+    }
+    let cells = match Repository::open(&path).map_err(AppError::from).and_then(|repo| action(project, &repo)) {
+      Ok(cells) => cells,
+      Err(e) => {
+        failed += 1;
+        match e {
+          AppError::UserError(message) => vec![Cell::new(message, Tone::Bad)],
+          other => vec![Cell::new(format!("{}", other), Tone::Bad)],
+        }
+      }
+    };
+    let mut row = vec![Cell::plain(project.name.clone())];
+    row.extend(cells);
+    table.add_row(row);
+  }
+  table.print();
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+fn create_in(repo: &Repository, name: &str, checkout: bool) -> Result<(String, Tone), AppError> {
+  let head = repo.head()?.peel_to_commit()?;
+  let (branch, message, tone) = match repo.find_branch(name, BranchType::Local) {
+    Ok(existing) => (existing, "exists".to_owned(), Tone::Muted),
+    Err(_) => (repo.branch(name, &head, false)?, format!("created at {}", short(head.id())), Tone::Good),
+  };
+  if !checkout || current_branch(repo).as_deref() == Some(name) {
+    return Ok((message, tone));
+  }
+  let target = branch.get().peel_to_commit()?;
+  repo
+    .checkout_tree(target.as_object(), Some(CheckoutBuilder::new().safe()))
+    .map_err(|e| AppError::UserError(format!("{}, checkout failed: {}", message, e.message())))?;
+  repo.set_head(&format!("refs/heads/{}", name))?;
+  Ok((format!("{}, checked out", message), tone))
+}
+
+/// Creates the branch `name` at HEAD in every selected project, optionally switching to it. The branch naming policy of
+/// a project's tags adds a prefix and refuses names that do not match its pattern.
+pub fn create(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, name: &str, checkout: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  for_each_repo_row(&config, selector, logger, |project, repo| {
+    let branch = config.resolve_branch_policy(project, logger).apply(name)?;
+    let (message, tone) = create_in(repo, &branch, checkout)?;
+    Ok(vec![Cell::plain(branch), Cell::new(message, tone)])
+  })
+}
+
+fn contained_in(repo: &Repository, targets: &[Oid], tip: Oid) -> Result<bool, AppError> {
+  for target in targets {
+    if *target == tip || repo.graph_descendant_of(*target, tip)? {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+// A branch is merged when HEAD or the branch's upstream contains its tip.
+fn is_merged(repo: &Repository, branch: &git2::Branch<'_>) -> Result<bool, AppError> {
+  let tip = branch.get().peel_to_commit()?.id();
+  let mut targets = vec![repo.head()?.peel_to_commit()?.id()];
+  if let Some(upstream) = branch.upstream().ok().and_then(|u| u.get().target()) {
+    targets.push(upstream);
+  }
+  contained_in(repo, &targets, tip)
+}
+
+fn delete_in(repo: &Repository, name: &str, force: bool) -> Result<(String, Tone), AppError> {
+  let mut branch = match repo.find_branch(name, BranchType::Local) {
+    Ok(branch) => branch,
+    Err(_) => return Ok(("not there".to_owned(), Tone::Muted)),
+  };
+  if branch.is_head() {
+    return Err(AppError::UserError("checked out, switch to another branch first".to_owned()));
+  }
+  if !force && !is_merged(repo, &branch)? {
+    return Err(AppError::UserError("not merged, use --force to delete it anyway".to_owned()));
+  }
+  let tip = branch.get().peel_to_commit()?.id();
+  branch.delete()?;
+  Ok((format!("deleted (was {})", short(tip)), Tone::Good))
+}
+
+/// Deletes the local branch `name` in every selected project. Unmerged branches are kept unless `force` is set.
+pub fn delete(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, name: &str, force: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  for_each_repo(&config, selector, logger, |_, repo| delete_in(repo, name, force))
+}
+
+// Merged into the default branch means merged into it locally or on origin, whichever is further.
+fn merged_branches(repo: &Repository) -> Result<Vec<String>, AppError> {
+  let default = default_branch(repo).ok_or_else(|| AppError::UserError("origin has no default branch".to_owned()))?;
+  let targets: Vec<Oid> = vec![
+    repo.find_branch(&default, BranchType::Local),
+    repo.find_branch(&format!("origin/{}", default), BranchType::Remote),
+  ]
+  .into_iter()
+  .filter_map(|branch| branch.ok().and_then(|b| b.get().target()))
+  .collect();
+  let mut merged = Vec::new();
+  for maybe_branch in repo.branches(Some(BranchType::Local))? {
+    let (branch, _) = maybe_branch?;
+    let name = match branch.name()? {
+      Some(name) if name != default && !branch.is_head() => name.to_owned(),
+      _ => continue,
+    };
+    if contained_in(repo, &targets, branch.get().peel_to_commit()?.id())? {
+      merged.push(name);
+    }
+  }
+  Ok(merged)
+}
+
+fn clean_in(repo: &Repository, gone: Vec<String>, dry_run: bool) -> Result<(String, Tone), AppError> {
+  let merged = merged_branches(repo)?;
+  if merged.is_empty() && gone.is_empty() {
+    return Ok(("nothing to clean".to_owned(), Tone::Muted));
+  }
+  if !dry_run {
+    for name in &merged {
+      repo.find_branch(name, BranchType::Local)?.delete()?;
+    }
+    for name in &gone {
+      repo.find_branch(name, BranchType::Remote)?.delete()?;
+    }
+  }
+  let mut removed = Vec::new();
+  if !merged.is_empty() {
+    removed.push(format!("{} {}", if dry_run { "would delete" } else { "deleted" }, merged.join(", ")));
+  }
+  if !gone.is_empty() {
+    removed.push(format!("{} {}", if dry_run { "would prune" } else { "pruned" }, gone.join(", ")));
+  }
+  Ok((removed.join("; "), if dry_run { Tone::Warning } else { Tone::Good }))
+}
+
+/// Deletes the local branches merged into the default branch in every selected project, except the one checked out.
+/// With `prune` also the remote-tracking branches whose branch is gone from origin, with `dry_run` nothing is deleted.
+pub fn clean(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, prune: bool, dry_run: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  for_each_repo(&config, selector, logger, |project, repo| {
+    let gone = if prune { gone_remote_branches(project, repo)? } else { vec![] };
+    clean_in(repo, gone, dry_run)
+  })
+}
+
+fn describe_in(repo: &Repository, name: &str) -> Result<(String, Tone), AppError> {
+  let branch = match repo.find_branch(name, BranchType::Local) {
+    Ok(branch) => branch,
+    Err(_) => return Ok(("-".to_owned(), Tone::Muted)),
+  };
+  let tip = branch.get().peel_to_commit()?.id();
+  let mut message = short(tip);
+  if let Some(upstream) = branch.upstream().ok().and_then(|u| u.get().target()) {
+    let (ahead, behind) = repo.graph_ahead_behind(tip, upstream)?;
+    message.push_str(&format!(" +{} -{}", ahead, behind));
+  }
+  if branch.is_head() {
+    message.push_str(" (checked out)");
+  }
+  Ok((message, Tone::Good))
+}
+
+/// Shows which of the selected projects have a local branch `name`.
+pub fn list(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, name: &str, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  for_each_repo(&config, selector, logger, |_, repo| describe_in(repo, name))
+}
+
+// `origin/<base>` if it was fetched, the local branch otherwise.
+fn base_tip(repo: &Repository, base: &str) -> Option<(String, Oid)> {
+  let remote = format!("origin/{}", base);
+  repo
+    .find_branch(&remote, BranchType::Remote)
+    .ok()
+    .and_then(|branch| branch.get().target())
+    .map(|tip| (remote, tip))
+    .or_else(|| {
+      repo
+        .find_branch(base, BranchType::Local)
+        .ok()
+        .and_then(|branch| branch.get().target())
+        .map(|tip| (base.to_owned(), tip))
+    })
+}
+
+fn compare_in(repo: &Repository, base: Option<&str>) -> Result<Vec<Cell>, AppError> {
+  let base = match base.map(ToOwned::to_owned).or_else(|| default_branch(repo)) {
+    Some(base) => base,
+    None => return Err(AppError::UserError("no default branch on origin, use --base".to_owned())),
+  };
+  let branch = match current_branch(repo) {
+    Some(branch) => Cell::plain(branch),
+    None => Cell::new("(detached)", Tone::Warning),
+  };
+  let (base_name, base_tip) = match base_tip(repo, &base) {
+    Some(found) => found,
+    None => return Ok(vec![branch, Cell::new(format!("no branch {}", base), Tone::Muted)]),
+  };
+  let (ahead, behind) = repo.graph_ahead_behind(repo.head()?.peel_to_commit()?.id(), base_tip)?;
+  let count_tone = |count: usize, tone: Tone| if count > 0 { tone } else { Tone::Muted };
+  Ok(vec![
+    branch,
+    Cell::new(format!("+{}", ahead), count_tone(ahead, Tone::Good)),
+    Cell::new(format!("-{}", behind), count_tone(behind, Tone::Warning)),
+    Cell::new(format!("vs {}", base_name), Tone::Muted),
+  ])
+}
+
+/// Shows how many commits the checked out branch of every selected project is ahead of and behind `base`, by default the
+/// default branch of `origin`.
+pub fn compare(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, base: Option<&str>, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  for_each_repo_row(&config, selector, logger, |_, repo| compare_in(repo, base))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{commit, signature, test_repo};
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_create_list_delete() {
+    let (_dir, repo) = test_repo();
+    assert_that(&describe_in(&repo, "feature").unwrap().0).is_equal_to("-".to_owned());
+
+    assert_that(&create_in(&repo, "feature", true).unwrap().0).ends_with(", checked out");
+    assert_that(&current_branch(&repo)).is_equal_to(Some("feature".to_owned()));
+    assert_that(&describe_in(&repo, "feature").unwrap().0).ends_with("(checked out)");
+    assert_that(&create_in(&repo, "feature", false).unwrap().0).is_equal_to("exists".to_owned());
+    assert_that(&delete_in(&repo, "feature", false).is_err()).is_true();
+
+    let other = repo.head().unwrap().peel_to_commit().unwrap();
+    repo.branch("other", &other, false).unwrap();
+    repo.set_head("refs/heads/other").unwrap();
+    assert_that(&delete_in(&repo, "feature", false).unwrap().0).starts_with("deleted");
+    assert_that(&delete_in(&repo, "feature", false).unwrap().0).is_equal_to("not there".to_owned());
+  }
+
+  #[test]
+  fn test_keeps_unmerged_branches() {
+    let (_dir, repo) = test_repo();
+    create_in(&repo, "feature", true).unwrap();
+    let parent = repo.head().unwrap().peel_to_commit().unwrap();
+    commit(&repo, "work");
+    repo.set_head_detached(parent.id()).unwrap();
+
+    assert_that(&delete_in(&repo, "feature", false).is_err()).is_true();
+    assert_that(&delete_in(&repo, "feature", true).unwrap().0).starts_with("deleted");
+  }
+
+  #[test]
+  fn test_clean_merged_branches() {
+    let (_dir, repo) = test_repo();
+    let initial = current_branch(&repo).unwrap();
+    let init = repo.head().unwrap().peel_to_commit().unwrap();
+    repo.reference("refs/remotes/origin/trunk", init.id(), false, "test").unwrap();
+    repo
+      .reference_symbolic("refs/remotes/origin/HEAD", "refs/remotes/origin/trunk", false, "test")
+      .unwrap();
+    repo.branch("trunk", &init, false).unwrap();
+    repo.branch("done", &init, false).unwrap();
+    create_in(&repo, "wip", true).unwrap();
+    commit(&repo, "work");
+    repo.branch("review", &repo.head().unwrap().peel_to_commit().unwrap(), false).unwrap();
+
+    // wip is checked out and review is not merged, trunk is the default branch itself
+    let mut merged = vec!["done".to_owned(), initial];
+    merged.sort();
+    assert_that(&clean_in(&repo, vec![], true).unwrap().0).is_equal_to(format!("would delete {}", merged.join(", ")));
+    assert_that(&merged_branches(&repo).unwrap()).is_equal_to(&merged);
+    assert_that(&clean_in(&repo, vec![], false).unwrap().1).is_equal_to(Tone::Good);
+    assert_that(&clean_in(&repo, vec![], false).unwrap().0).is_equal_to("nothing to clean".to_owned());
+    assert_that(&repo.find_branch("review", BranchType::Local).is_ok()).is_true();
+  }
+
+  #[test]
+  fn test_compare_with_base() {
+    let (_dir, repo) = test_repo();
+    let init = repo.head().unwrap().peel_to_commit().unwrap();
+    repo.branch("main", &init, false).unwrap();
+    create_in(&repo, "feature", true).unwrap();
+    commit(&repo, "work");
+    let signature = signature();
+    let tree = init.tree().unwrap();
+    let row = |ahead: &str, behind: &str, base: &str| {
+      vec![
+        Cell::plain("feature"),
+        Cell::new(ahead, Tone::Good),
+        Cell::new(behind, if behind == "-0" { Tone::Muted } else { Tone::Warning }),
+        Cell::new(base, Tone::Muted),
+      ]
+    };
+
+    assert_that(&compare_in(&repo, Some("main")).unwrap()).is_equal_to(row("+1", "-0", "vs main"));
+
+    let released = repo.commit(None, &signature, &signature, "release", &tree, &[&init]).unwrap();
+    repo.reference("refs/remotes/origin/main", released, false, "test").unwrap();
+    assert_that(&compare_in(&repo, Some("main")).unwrap()).is_equal_to(row("+1", "-1", "vs origin/main"));
+    assert_that(&compare_in(&repo, Some("develop")).unwrap()).is_equal_to(vec![Cell::plain("feature"), Cell::new("no branch develop", Tone::Muted)]);
+    assert_that(&compare_in(&repo, None).unwrap()).is_equal_to(row("+1", "-1", "vs origin/main"));
+  }
+}
@@ -0,0 +1,367 @@
+use crate::config::{selector::ProjectSelector, Config};
+use crate::daemon::parse_interval;
+use crate::errors::AppError;
+use crate::output::{print_warning, Cell, Table, Tone};
+use git2::{Repository, Sort};
+use rayon::prelude::*;
+use serde::Serialize;
+use slog::{debug, Logger};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the changes of every project start.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Since {
+  /// Commits after this time, in seconds since the epoch.
+  Time(i64),
+  /// Commits that are not reachable from this tag.
+  Tag(String),
+}
+
+/// How the changes are grouped in the summary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grouping {
+  Project,
+  Author,
+}
+
+/// A commit subject in one of the projects.
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+  project: String,
+  commit: String,
+  author: String,
+  subject: String,
+}
+
+// Days since the epoch of a date in the proleptic Gregorian calendar, see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = if year >= 0 { year } else { year - 399 } / 400;
+  let year_of_era = year - era * 400;
+  let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146_097 + day_of_era - 719_468
+}
+
+/// Parses `--since`, a date like `2021-03-01` (UTC) or an age like `2w`, `3d` or `12h` before `now`.
+pub fn parse_since(input: &str, now: i64) -> Result<i64, String> {
+  let input = input.trim();
+  let date: Vec<&str> = input.split('-').collect();
+  if let [year, month, day] = date.as_slice() {
+    return match (year.parse::<i64>(), month.parse::<i64>(), day.parse::<i64>()) {
+      (Ok(year), Ok(month @ 1..=12), Ok(day @ 1..=31)) => Ok(days_from_civil(year, month, day) * 24 * 60 * 60),
+      _ => Err(format!("Expected a date like 2021-03-01. Was '{}'.", input)),
+    };
+  }
+  let seconds = match input.strip_suffix('w') {
+    Some(weeks) => weeks.parse::<i64>().map(|weeks| weeks * 7 * 24 * 60 * 60).ok(),
+    None => parse_interval(input).ok().map(|age| age.as_secs() as i64),
+  };
+  seconds
+    .map(|seconds| now - seconds)
+    .ok_or_else(|| format!("Expected an age like 2w, 3d or 12h or a date like 2021-03-01. Was '{}'.", input))
+}
+
+fn changes_in(project_name: &str, repo: &Repository, since: &Since) -> Result<Option<Vec<Change>>, AppError> {
+  let mut walk = repo.revwalk()?;
+  walk.set_sorting(Sort::TIME)?;
+  walk.push_head()?;
+  if let Since::Tag(tag) = since {
+    match repo.revparse_single(&format!("refs/tags/{}", tag)) {
+      Ok(object) => walk.hide(object.peel_to_commit()?.id())?,
+      Err(_) => return Ok(None),
+    }
+  }
+  let mut changes = Vec::new();
+  for maybe_oid in walk {
+    let commit = repo.find_commit(maybe_oid?)?;
+    if let Since::Time(time) = since {
+      if commit.time().seconds() < *time {
+        break;
+      }
+    }
+    if commit.parent_count() > 1 {
+      continue;
+    }
+    changes.push(Change {
+      project: project_name.to_owned(),
+      commit: commit.id().to_string()[..8].to_owned(),
+      author: commit.author().name().unwrap_or("unknown").to_owned(),
+      subject: commit.summary().unwrap_or_default().to_owned(),
+    });
+  }
+  Ok(Some(changes))
+}
+
+fn render(changes: &[Change], grouping: Grouping, markdown: bool) -> String {
+  let mut groups: BTreeMap<&str, Vec<&Change>> = BTreeMap::new();
+  for change in changes {
+    let key = match grouping {
+      Grouping::Project => &change.project,
+      Grouping::Author => &change.author,
+    };
+    groups.entry(key).or_default().push(change);
+  }
+  let mut out = String::new();
+  for (group, group_changes) in groups {
+    if markdown {
+      out.push_str(&format!("## {}\n\n", group));
+    } else {
+      out.push_str(&format!("{} ({})\n", Tone::Heading.paint(group), group_changes.len()));
+    }
+    for change in group_changes {
+      let origin = match grouping {
+        Grouping::Project => &change.author,
+        Grouping::Author => &change.project,
+      };
+      if markdown {
+        out.push_str(&format!("- {} ({}, {})\n", change.subject, origin, change.commit));
+      } else {
+        out.push_str(&format!(
+          "  {} {} {}\n",
+          Tone::Muted.paint(&change.commit),
+          change.subject,
+          Tone::Muted.paint(&format!("({})", origin))
+        ));
+      }
+    }
+    out.push('\n');
+  }
+  out
+}
+
+/// Prints the commit subjects of the selected projects since a time or tag, grouped by project or author. Merge commits
+/// are left out.
+pub fn changes(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  since: &Since,
+  grouping: Grouping,
+  markdown: bool,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut changes = Vec::new();
+  let mut failed = 0;
+  for project in selector.select_repositories(&config) {
+    let path = config.checkout_path(project, logger);
+    if !path.exists() {
+      debug!(logger, "Skipping, not checked out"; "project" => &project.name);
+      continue;
+    }
+    match Repository::open(&path)
+      .map_err(AppError::from)
+      .and_then(|repo| changes_in(&project.name, &repo, since))
+    {
+      Ok(Some(project_changes)) => changes.extend(project_changes),
+      Ok(None) => debug!(logger, "Skipping, tag not found"; "project" => &project.name),
+      Err(e) => {
+        failed += 1;
+        print_warning(&format!("Could not collect the changes of {}: {}", project.name, e));
+      }
+    }
+  }
+  print!("{}", render(&changes, grouping, markdown));
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+/// The number of non-merge commits on HEAD from `since` up to `until`, in seconds since the epoch, whose author is the
+/// `user.email` of the repository.
+pub(crate) fn count_since(repo: &Repository, since: i64, until: i64) -> Result<usize, AppError> {
+  let email = repo
+    .config()?
+    .get_string("user.email")
+    .map_err(|_| AppError::UserError("user.email is not set, so the own commits are unknown".to_owned()))?;
+  let mut walk = repo.revwalk()?;
+  walk.set_sorting(Sort::TIME)?;
+  walk.push_head()?;
+  let mut count = 0;
+  for maybe_oid in walk {
+    let commit = repo.find_commit(maybe_oid?)?;
+    let time = commit.time().seconds();
+    if time < since {
+      break;
+    }
+    let own = commit.author().email().is_some_and(|author| author.eq_ignore_ascii_case(&email));
+    if time < until && own && commit.parent_count() <= 1 {
+      count += 1;
+    }
+  }
+  Ok(count)
+}
+
+/// Commit counts by author, per project and over all selected projects.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Stats {
+  pub projects: BTreeMap<String, BTreeMap<String, usize>>,
+  pub total: BTreeMap<String, usize>,
+}
+
+fn count(changes: &[Change]) -> Stats {
+  let mut stats = Stats::default();
+  for change in changes {
+    *stats
+      .projects
+      .entry(change.project.clone())
+      .or_default()
+      .entry(change.author.clone())
+      .or_default() += 1;
+    *stats.total.entry(change.author.clone()).or_default() += 1;
+  }
+  stats
+}
+
+// One row per author, most commits first, with the group name in the first row only.
+fn add_rows(table: &mut Table, group: Cell, counts: &BTreeMap<String, usize>) {
+  let mut authors: Vec<(&String, &usize)> = counts.iter().collect();
+  authors.sort_by_key(|(_, count)| Reverse(**count));
+  let mut group = Some(group);
+  for (author, count) in authors {
+    table.add_row(vec![
+      group.take().unwrap_or_else(|| Cell::plain("")),
+      Cell::plain(author.clone()),
+      Cell::new(count.to_string(), Tone::Good),
+    ]);
+  }
+}
+
+/// Counts the non-merge commits on HEAD of the selected projects since `since` by author. The projects are walked in
+/// parallel.
+pub fn stats(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, since: i64, json: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let since = Since::Time(since);
+  let walked: Vec<(String, Result<Vec<Change>, AppError>)> = selector
+    .select_repositories(&config)
+    .par_iter()
+    .filter_map(|project| {
+      let path = config.checkout_path(project, logger);
+      if path.exists() {
+        let result = Repository::open(&path)
+          .map_err(AppError::from)
+          .and_then(|repo| changes_in(&project.name, &repo, &since))
+          .map(Option::unwrap_or_default);
+        Some((project.name.clone(), result))
+      } else {
+        debug!(logger, "Skipping, not checked out"; "project" => &project.name);
+        None
+      }
+    })
+    .collect();
+  let mut changes = Vec::new();
+  let mut failed = 0;
+  for (name, result) in walked {
+    match result {
+      Ok(project_changes) => changes.extend(project_changes),
+      Err(e) => {
+        failed += 1;
+        print_warning(&format!("Could not count the commits of {}: {}", name, e));
+      }
+    }
+  }
+  let stats = count(&changes);
+
+  if json {
+    println!("{}", serde_json::to_string(&stats)?);
+  } else {
+    let mut table = Table::new();
+    for (project, counts) in &stats.projects {
+      add_rows(&mut table, Cell::plain(project.clone()), counts);
+    }
+    add_rows(&mut table, Cell::new("total", Tone::Heading), &stats.total);
+    table.print();
+  }
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+/// Now in seconds since the epoch, the reference for `--since` ages.
+pub fn now() -> Result<i64, AppError> {
+  Ok(SystemTime::now().duration_since(UNIX_EPOCH).map_err(AppError::ClockError)?.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use maplit::btreemap;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_parse_since() {
+    assert_that(&parse_since("2021-03-01", 0)).is_equal_to(Ok(1_614_556_800));
+    assert_that(&parse_since("1970-01-01", 0)).is_equal_to(Ok(0));
+    assert_that(&parse_since("2w", 2_000_000)).is_equal_to(Ok(2_000_000 - 14 * 24 * 60 * 60));
+    assert_that(&parse_since("3d", 1_000_000)).is_equal_to(Ok(1_000_000 - 3 * 24 * 60 * 60));
+    assert_that(&parse_since("2021-13-01", 0).is_err()).is_true();
+    assert_that(&parse_since("w", 0).is_err()).is_true();
+  }
+
+  #[test]
+  fn test_render_markdown() {
+    let change = |project: &str, author: &str, subject: &str| Change {
+      project: project.to_owned(),
+      commit: "0123abcd".to_owned(),
+      author: author.to_owned(),
+      subject: subject.to_owned(),
+    };
+    let changes = vec![
+      change("web", "Alice", "Add login"),
+      change("api", "Bob", "Fix token refresh"),
+      change("api", "Alice", "Add login endpoint"),
+    ];
+    assert_that(&render(&changes, Grouping::Project, true)).is_equal_to(
+      "## api\n\n- Fix token refresh (Bob, 0123abcd)\n- Add login endpoint (Alice, 0123abcd)\n\n## web\n\n- Add login (Alice, 0123abcd)\n\n".to_owned(),
+    );
+    assert_that(&render(&changes, Grouping::Author, true))
+      .starts_with("## Alice\n\n- Add login (web, 0123abcd)\n- Add login endpoint (api, 0123abcd)\n\n## Bob");
+  }
+
+  #[test]
+  fn test_count_since_counts_own_commits_in_the_period() {
+    let dir = temp_dir();
+    let repo = Repository::init(dir.path()).unwrap();
+    repo.config().unwrap().set_str("user.email", "me@example.com").unwrap();
+    let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+    let mut parent = None;
+    for (email, time) in [
+      ("me@example.com", 100),
+      ("me@example.com", 200),
+      ("Me@Example.com", 250),
+      ("other@example.com", 300),
+      ("me@example.com", 400),
+    ] {
+      let signature = git2::Signature::new("someone", email, &git2::Time::new(time, 0)).unwrap();
+      let parents: Vec<git2::Commit> = parent.iter().map(|oid| repo.find_commit(*oid).unwrap()).collect();
+      parent = Some(
+        repo
+          .commit(Some("HEAD"), &signature, &signature, "work", &tree, &parents.iter().collect::<Vec<_>>())
+          .unwrap(),
+      );
+    }
+    assert_that(&count_since(&repo, 150, 400).unwrap()).is_equal_to(2);
+    assert_that(&count_since(&repo, 0, 1000).unwrap()).is_equal_to(4);
+  }
+
+  #[test]
+  fn test_count_by_author() {
+    let change = |project: &str, author: &str| Change {
+      project: project.to_owned(),
+      commit: "0123abcd".to_owned(),
+      author: author.to_owned(),
+      subject: "work".to_owned(),
+    };
+    let stats = count(&[change("api", "Alice"), change("api", "Alice"), change("api", "Bob"), change("web", "Alice")]);
+    assert_that(&stats.projects["api"]).is_equal_to(btreemap!["Alice".to_owned() => 2, "Bob".to_owned() => 1]);
+    assert_that(&stats.projects["web"]).is_equal_to(btreemap!["Alice".to_owned() => 1]);
+    assert_that(&stats.total).is_equal_to(btreemap!["Alice".to_owned() => 3, "Bob".to_owned() => 1]);
+  }
+}
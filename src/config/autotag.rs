@@ -0,0 +1,169 @@
+use crate::errors::AppError;
+use crate::git_url::ParsedUrl;
+use maplit::btreemap;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Tags projects by their git URL when they are added or imported. All conditions that are set have to match.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct AutotagRule {
+  /// The tag to apply.
+  pub tag: String,
+  /// A substring of the git URL, e.g. `github.com/acme/`.
+  pub url_contains: Option<String>,
+  /// A regular expression the git URL has to match.
+  pub url_pattern: Option<String>,
+  /// The owner of the repository: the GitHub org or user, or the top level GitLab group.
+  pub org: Option<String>,
+}
+
+impl AutotagRule {
+  pub fn example() -> AutotagRule {
+    AutotagRule {
+      tag: "work".to_owned(),
+      url_contains: None,
+      url_pattern: None,
+      org: Some("acme".to_owned()),
+    }
+  }
+
+  pub fn check(&self) -> Result<(), AppError> {
+    if self.url_contains.is_none() && self.url_pattern.is_none() && self.org.is_none() {
+      return Err(AppError::UserError(format!(
+        "Autotag rule for tag {} needs url_contains, url_pattern or org",
+        self.tag
+      )));
+    }
+    if let Some(pattern) = &self.url_pattern {
+      Regex::new(pattern).map_err(|e| AppError::UserError(format!("Invalid url_pattern in autotag rule for tag {}: {}", self.tag, e)))?;
+    }
+    Ok(())
+  }
+
+  pub fn matches(&self, url: &str) -> bool {
+    self.url_contains.as_ref().map(|part| url.contains(part.as_str())).unwrap_or(true)
+      && self
+        .url_pattern
+        .as_ref()
+        .map(|pattern| Regex::new(pattern).map(|r| r.is_match(url)).unwrap_or(false))
+        .unwrap_or(true)
+      && self
+        .org
+        .as_ref()
+        .map(|org| org_of(url).map(|o| o.eq_ignore_ascii_case(org)).unwrap_or(false))
+        .unwrap_or(true)
+  }
+}
+
+/// Tags imported projects with the topics and the primary language their forge reports, also for `fw retag-from-forge`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct ForgeTags {
+  /// Put in front of the tags from topics, e.g. `topic-`.
+  pub topic_prefix: Option<String>,
+  /// Put in front of the tag from the language, e.g. `lang-`.
+  pub language_prefix: Option<String>,
+  /// The tag for a topic or language instead of its prefixed name, e.g. `machine-learning = "ml"`. An empty tag drops it.
+  pub mapping: Option<BTreeMap<String, String>>,
+}
+
+impl ForgeTags {
+  pub fn example() -> ForgeTags {
+    ForgeTags {
+      topic_prefix: None,
+      language_prefix: Some("lang-".to_owned()),
+      mapping: Some(btreemap! {"machine-learning".to_owned() => "ml".to_owned()}),
+    }
+  }
+
+  fn tag(&self, prefix: &Option<String>, name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    match self.mapping.as_ref().and_then(|mapping| mapping.get(&name)) {
+      Some(tag) if tag.is_empty() => None,
+      Some(tag) => Some(tag.clone()),
+      None => Some(format!("{}{}", prefix.as_deref().unwrap_or_default(), name.replace(' ', "-"))),
+    }
+  }
+
+  /// The tags for a repository with `language` and `topics`, in lowercase.
+  pub fn tags_for(&self, language: Option<&str>, topics: &[String]) -> BTreeSet<String> {
+    language
+      .and_then(|language| self.tag(&self.language_prefix, language))
+      .into_iter()
+      .chain(topics.iter().filter_map(|topic| self.tag(&self.topic_prefix, topic)))
+      .collect()
+  }
+
+  /// Whether `tag` looks like it came from the forge: it starts with a prefix or is a tag of the mapping. Tags from topics
+  /// and languages without a prefix can't be told apart from the user's own tags.
+  pub fn is_forge_tag(&self, tag: &str) -> bool {
+    [&self.topic_prefix, &self.language_prefix]
+      .iter()
+      .filter_map(|prefix| prefix.as_deref())
+      .any(|prefix| !prefix.is_empty() && tag.starts_with(prefix))
+      || self.mapping.iter().flat_map(|mapping| mapping.values()).any(|t| t == tag)
+  }
+}
+
+/// The first path segment of a git URL like `git@github.com:acme/fw.git` or `https://gitlab.com/acme/sub/fw`.
+pub fn org_of(url: &str) -> Option<&str> {
+  ParsedUrl::parse(url)?.owner()?.split('/').next()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_org_of() {
+    assert_that(&org_of("git@github.com:acme/fw.git")).is_equal_to(Some("acme"));
+    assert_that(&org_of("https://gitlab.com/acme/sub/fw")).is_equal_to(Some("acme"));
+    assert_that(&org_of("ssh://git@host:22/acme/fw.git")).is_equal_to(Some("acme"));
+    assert_that(&org_of("/tmp/fw")).is_equal_to(None);
+  }
+
+  #[test]
+  fn test_rule_matches_all_conditions() {
+    let rule = AutotagRule {
+      tag: "work".to_owned(),
+      url_contains: Some("github.com".to_owned()),
+      url_pattern: None,
+      org: Some("ACME".to_owned()),
+    };
+    assert_that(&rule.matches("git@github.com:acme/fw.git")).is_true();
+    assert_that(&rule.matches("git@gitlab.com:acme/fw.git")).is_false();
+    assert_that(&rule.matches("git@github.com:brocode/fw.git")).is_false();
+
+    let pattern = AutotagRule {
+      url_pattern: Some("^https://".to_owned()),
+      ..AutotagRule::example()
+    };
+    assert_that(&pattern.matches("https://gitlab.com/acme/fw")).is_true();
+    assert_that(&pattern.matches("git@gitlab.com:acme/fw")).is_false();
+  }
+
+  #[test]
+  fn test_forge_tags() {
+    let forge_tags = ForgeTags {
+      topic_prefix: Some("topic-".to_owned()),
+      language_prefix: None,
+      mapping: Some(btreemap! {
+        "machine-learning".to_owned() => "ml".to_owned(),
+        "hacktoberfest".to_owned() => "".to_owned(),
+      }),
+    };
+    let topics = vec!["CLI".to_owned(), "machine-learning".to_owned(), "hacktoberfest".to_owned()];
+    assert_that(&forge_tags.tags_for(Some("Jupyter Notebook"), &topics)).is_equal_to(
+      vec!["jupyter-notebook", "ml", "topic-cli"]
+        .into_iter()
+        .map(str::to_owned)
+        .collect::<BTreeSet<String>>(),
+    );
+    assert_that(&forge_tags.is_forge_tag("topic-cli")).is_true();
+    assert_that(&forge_tags.is_forge_tag("ml")).is_true();
+    assert_that(&forge_tags.is_forge_tag("work")).is_false();
+    assert_that(&ForgeTags::default().is_forge_tag("rust")).is_false();
+  }
+}
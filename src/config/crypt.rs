@@ -0,0 +1,376 @@
+use super::path::{expand_path, fw_path};
+use super::{is_hidden, write_atomically};
+use crate::errors::AppError;
+use slog::{debug, info, Logger};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use walkdir::WalkDir;
+
+const AGE_ARMOR: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+const AGE_BINARY: &str = "age-encryption.org/v1";
+const GPG_ARMOR: &str = "-----BEGIN PGP MESSAGE-----";
+
+/// How a config file is encrypted at rest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Envelope {
+  /// https://age-encryption.org, decrypted with the identity in `FW_AGE_IDENTITY`.
+  Age,
+  /// GnuPG, decrypted with the keys in the keyring or gpg-agent.
+  Gpg,
+}
+
+impl FromStr for Envelope {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "age" => Ok(Envelope::Age),
+      "gpg" => Ok(Envelope::Gpg),
+      other => Err(AppError::UserError(format!("Unknown encryption {}, expected age or gpg", other))),
+    }
+  }
+}
+
+impl Envelope {
+  /// Recognizes encrypted files by their header. Everything else is read as plain text.
+  pub fn detect(raw: &[u8]) -> Option<Envelope> {
+    let start = String::from_utf8_lossy(&raw[..raw.len().min(64)]).trim_start().to_owned();
+    if start.starts_with(AGE_ARMOR) || start.starts_with(AGE_BINARY) {
+      Some(Envelope::Age)
+    } else if start.starts_with(GPG_ARMOR) {
+      Some(Envelope::Gpg)
+    } else {
+      None
+    }
+  }
+
+  fn tool(self) -> &'static str {
+    match self {
+      Envelope::Age => "age",
+      Envelope::Gpg => "gpg",
+    }
+  }
+
+  fn decrypt_args(self) -> Result<Vec<String>, AppError> {
+    match self {
+      Envelope::Age => Ok(vec![
+        "--decrypt".to_owned(),
+        "--identity".to_owned(),
+        age_identity()?.to_string_lossy().to_string(),
+      ]),
+      Envelope::Gpg => Ok(vec!["--decrypt".to_owned(), "--batch".to_owned(), "--quiet".to_owned()]),
+    }
+  }
+
+  // `FW_AGE_RECIPIENTS_FILE`, `FW_AGE_RECIPIENT` or the public key of the identity, with the number of recipients.
+  fn age_recipient_args() -> Result<(Vec<String>, usize), AppError> {
+    if let Ok(file) = env::var("FW_AGE_RECIPIENTS_FILE") {
+      let file = expand_path(PathBuf::from(file));
+      let count = fs::read_to_string(&file)
+        .map_err(|e| AppError::UserError(format!("Could not read FW_AGE_RECIPIENTS_FILE {}: {}", file.to_string_lossy(), e)))?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .count();
+      return Ok((vec!["--recipients-file".to_owned(), file.to_string_lossy().to_string()], count));
+    }
+    let recipient = match env::var("FW_AGE_RECIPIENT") {
+      Ok(recipient) => recipient,
+      Err(_) => age_recipient_of_identity()?,
+    };
+    Ok((vec!["--recipient".to_owned(), recipient], 1))
+  }
+
+  fn encrypt_args(self) -> Result<Vec<String>, AppError> {
+    match self {
+      Envelope::Age => Ok(
+        vec!["--encrypt".to_owned(), "--armor".to_owned()]
+          .into_iter()
+          .chain(Envelope::age_recipient_args()?.0)
+          .collect(),
+      ),
+      Envelope::Gpg => {
+        let mut args = vec!["--encrypt".to_owned(), "--armor".to_owned(), "--batch".to_owned(), "--quiet".to_owned()];
+        match env::var("FW_GPG_RECIPIENT") {
+          Ok(recipient) => args.extend(vec!["--recipient".to_owned(), recipient]),
+          Err(_) => args.push("--default-recipient-self".to_owned()),
+        }
+        Ok(args)
+      }
+    }
+  }
+
+  // The recipients of `existing` for gpg. age files don't name theirs, they are encrypted for the configured recipients
+  // unless the file has more of them.
+  fn reencrypt_args(self, existing: &[u8], gnupg_home: Option<&Path>) -> Result<Vec<String>, AppError> {
+    match self {
+      Envelope::Age => {
+        let stanzas = age_recipient_stanzas(existing)?;
+        let (recipients, count) = Envelope::age_recipient_args()?;
+        if stanzas > count {
+          return Err(AppError::UserError(format!(
+            "the file is encrypted for {} age recipients, put all of them into FW_AGE_RECIPIENTS_FILE to write it",
+            stanzas
+          )));
+        }
+        Ok(vec!["--encrypt".to_owned(), "--armor".to_owned()].into_iter().chain(recipients).collect())
+      }
+      Envelope::Gpg => {
+        let packets = run(
+          "gpg",
+          &["--batch".to_owned(), "--list-packets".to_owned(), "--list-only".to_owned()],
+          existing,
+          gnupg_home,
+        )?;
+        let key_ids = gpg_recipients(&String::from_utf8_lossy(&packets))?;
+        let mut args = vec!["--encrypt".to_owned(), "--armor".to_owned(), "--batch".to_owned(), "--quiet".to_owned()];
+        for key_id in key_ids {
+          args.extend(vec!["--recipient".to_owned(), key_id]);
+        }
+        Ok(args)
+      }
+    }
+  }
+}
+
+// `:pubkey enc packet: version 3, algo 1, keyid CE0D2A998D8350A2` in the output of `gpg --list-packets`.
+fn gpg_recipients(packets: &str) -> Result<Vec<String>, AppError> {
+  let key_ids: Vec<String> = packets
+    .lines()
+    .filter(|line| line.starts_with(":pubkey enc packet:"))
+    .filter_map(|line| line.split("keyid ").nth(1))
+    .map(|key_id| key_id.trim().to_owned())
+    .collect();
+  if key_ids.is_empty() {
+    Err(AppError::UserError("the file is not encrypted for any public key".to_owned()))
+  } else if key_ids.iter().any(|key_id| key_id.chars().all(|c| c == '0')) {
+    Err(AppError::UserError(
+      "the file has hidden recipients, fw cannot encrypt it for them again".to_owned(),
+    ))
+  } else {
+    Ok(key_ids)
+  }
+}
+
+// The `-> X25519 ...` lines of the age header, without the grease that some implementations add.
+fn age_recipient_stanzas(raw: &[u8]) -> Result<usize, AppError> {
+  let text = String::from_utf8_lossy(raw);
+  let binary = if text.trim_start().starts_with(AGE_ARMOR) {
+    let encoded: String = text.lines().map(str::trim).filter(|line| !line.starts_with("-----")).collect();
+    base64::decode(encoded).map_err(|_| AppError::UserError("the age armor is not valid base64".to_owned()))?
+  } else {
+    raw.to_vec()
+  };
+  let header = String::from_utf8_lossy(&binary);
+  Ok(
+    header
+      .lines()
+      .take_while(|line| !line.starts_with("---"))
+      .filter(|line| line.starts_with("-> ") && !line.contains("-grease"))
+      .count(),
+  )
+}
+
+fn age_identity() -> Result<PathBuf, AppError> {
+  env::var("FW_AGE_IDENTITY")
+    .map(|identity| expand_path(PathBuf::from(identity)))
+    .map_err(|_| AppError::UserError("The config is age encrypted, set FW_AGE_IDENTITY to the identity file to decrypt it".to_owned()))
+}
+
+fn age_recipient_of_identity() -> Result<String, AppError> {
+  let identity = age_identity()?;
+  let public_key = run("age-keygen", &["-y".to_owned(), identity.to_string_lossy().to_string()], &[], None)?;
+  Ok(String::from_utf8_lossy(&public_key).trim().to_owned())
+}
+
+// Feeds `input` from a separate thread so a large output cannot block the child while we are still writing. gpg uses
+// `gnupg_home` instead of its default keyring if it is given.
+fn run(tool: &str, args: &[String], input: &[u8], gnupg_home: Option<&Path>) -> Result<Vec<u8>, AppError> {
+  let mut command = Command::new(tool);
+  if let Some(home) = gnupg_home {
+    command.env("GNUPGHOME", home);
+  }
+  let mut child = command
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .map_err(|e| AppError::UserError(format!("Could not run {}: {}", tool, e)))?;
+  let mut stdin = child.stdin.take().ok_or(AppError::InternalError("stdin of child process is piped"))?;
+  let input = input.to_vec();
+  let writer = thread::spawn(move || stdin.write_all(&input));
+  let output = child.wait_with_output()?;
+  writer.join().map_err(|_| AppError::InternalError("stdin writer panicked"))??;
+  if output.status.success() {
+    Ok(output.stdout)
+  } else {
+    Err(AppError::UserError(format!(
+      "{} failed: {}",
+      tool,
+      String::from_utf8_lossy(&output.stderr).trim()
+    )))
+  }
+}
+
+pub fn decrypt(envelope: Envelope, raw: &[u8]) -> Result<String, AppError> {
+  decrypt_in(envelope, raw, None)
+}
+
+fn decrypt_in(envelope: Envelope, raw: &[u8], gnupg_home: Option<&Path>) -> Result<String, AppError> {
+  let plain = run(envelope.tool(), &envelope.decrypt_args()?, raw, gnupg_home)?;
+  String::from_utf8(plain).map_err(|_| AppError::UserError("Decrypted config is not valid UTF-8".to_owned()))
+}
+
+/// Encrypts `plain` into an ASCII armored envelope.
+pub fn encrypt(envelope: Envelope, plain: &str) -> Result<String, AppError> {
+  let armored = run(envelope.tool(), &envelope.encrypt_args()?, plain.as_bytes(), None)?;
+  String::from_utf8(armored).map_err(|_| AppError::InternalError("armored output is ASCII"))
+}
+
+/// Encrypts `plain` to replace `existing`, for the same recipients. Refuses if some of them would lose access.
+pub fn reencrypt(envelope: Envelope, existing: &[u8], plain: &str) -> Result<String, AppError> {
+  reencrypt_in(envelope, existing, plain, None)
+}
+
+fn reencrypt_in(envelope: Envelope, existing: &[u8], plain: &str, gnupg_home: Option<&Path>) -> Result<String, AppError> {
+  let armored = run(envelope.tool(), &envelope.reencrypt_args(existing, gnupg_home)?, plain.as_bytes(), gnupg_home)?;
+  String::from_utf8(armored).map_err(|_| AppError::InternalError("armored output is ASCII"))
+}
+
+/// The envelope of the file at `path`, `None` if it is plain text or does not exist.
+pub fn envelope_of(path: &Path) -> Result<Option<Envelope>, AppError> {
+  match fs::read(path) {
+    Ok(raw) => Ok(Envelope::detect(&raw)),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+    Err(e) => Err(AppError::IO(e)),
+  }
+}
+
+/// Reads a config file, decrypting it first if it is encrypted.
+pub fn read_config_file(path: &Path) -> Result<String, AppError> {
+  let raw = fs::read(path)?;
+  match Envelope::detect(&raw) {
+    Some(envelope) => decrypt(envelope, &raw).map_err(|e| match e {
+      AppError::UserError(message) => AppError::UserError(format!("Could not decrypt {}: {}", path.to_string_lossy(), message)),
+      other => other,
+    }),
+    None => String::from_utf8(raw).map_err(|_| AppError::UserError(format!("Config file {} is not valid UTF-8", path.to_string_lossy()))),
+  }
+}
+
+fn config_files(all: bool) -> Result<Vec<PathBuf>, AppError> {
+  let paths = fw_path()?;
+  let mut files = vec![paths.settings];
+  if all {
+    for dir in &[paths.projects, paths.tags] {
+      if dir.exists() {
+        for entry in WalkDir::new(dir).follow_links(true) {
+          let entry = entry?;
+          if entry.metadata()?.is_file() && !is_hidden(entry.file_name()) {
+            files.push(entry.into_path());
+          }
+        }
+      }
+    }
+  }
+  Ok(files)
+}
+
+/// Encrypts the settings file, or with `all` every config file, with `envelope`. `None` decrypts them to plain text again.
+pub fn set_envelope(envelope: Option<Envelope>, all: bool, logger: &Logger) -> Result<(), AppError> {
+  for path in config_files(all)? {
+    let file = path.to_string_lossy().to_string();
+    if envelope_of(&path)? == envelope {
+      debug!(logger, "Config file unchanged"; "path" => file);
+      continue;
+    }
+    let plain = read_config_file(&path)?;
+    match envelope {
+      Some(envelope) => write_atomically(&path, &encrypt(envelope, &plain)?)?,
+      None => write_atomically(&path, &plain)?,
+    }
+    info!(logger, "Config file written"; "path" => file, "encryption" => envelope.map(Envelope::tool).unwrap_or("none"));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_detects_envelopes() {
+    assert_that(&Envelope::detect(b"-----BEGIN AGE ENCRYPTED FILE-----\nYWdl\n")).is_equal_to(Some(Envelope::Age));
+    assert_that(&Envelope::detect(b"age-encryption.org/v1\n-> X25519 abc\n\x00\x01")).is_equal_to(Some(Envelope::Age));
+    assert_that(&Envelope::detect(b"\n-----BEGIN PGP MESSAGE-----\n\nhQEM")).is_equal_to(Some(Envelope::Gpg));
+    assert_that(&Envelope::detect(b"workspace = '~/workspace'\n")).is_equal_to(None);
+    assert_that(&Envelope::detect(b"")).is_equal_to(None);
+  }
+
+  #[test]
+  fn test_gpg_recipients() {
+    let packets = ":pubkey enc packet: version 3, algo 1, keyid CE0D2A998D8350A2\n\tdata: [3071 bits]\n\
+                   :pubkey enc packet: version 3, algo 1, keyid AA509F8FB4D50DCD\n:encrypted data packet:\n";
+    assert_that(&gpg_recipients(packets).unwrap()).is_equal_to(vec!["CE0D2A998D8350A2".to_owned(), "AA509F8FB4D50DCD".to_owned()]);
+    assert_that(&gpg_recipients(":pubkey enc packet: version 3, algo 18, keyid 0000000000000000\n")).is_err();
+    assert_that(&gpg_recipients(":symkey enc packet: version 4, cipher 9\n")).is_err();
+  }
+
+  #[test]
+  fn test_age_recipient_stanzas() {
+    let header = "age-encryption.org/v1\n-> X25519 abc\nYWJj\n-> X25519 def\nZGVm\n-> 1-grease x\n\n--- mac\n-> X25519 not-a-stanza";
+    assert_that(&age_recipient_stanzas(header.as_bytes()).unwrap()).is_equal_to(2);
+    let armored = format!("{}\n{}\n-----END AGE ENCRYPTED FILE-----\n", AGE_ARMOR, base64::encode(header));
+    assert_that(&age_recipient_stanzas(armored.as_bytes()).unwrap()).is_equal_to(2);
+  }
+
+  // Needs gpg, it is skipped without. The keys live in a temporary GnuPG home that only the spawned gpg sees.
+  #[test]
+  fn test_reencrypt_keeps_the_envelope() {
+    if Command::new("gpg").arg("--version").output().is_err() {
+      return;
+    }
+    let home = temp_dir();
+    let gnupg = home.path().join("gnupg");
+    fs::create_dir_all(&gnupg).unwrap();
+    #[cfg(unix)]
+    fs::set_permissions(&gnupg, std::os::unix::fs::PermissionsExt::from_mode(0o700)).unwrap();
+    let gpg = |args: &[&str], input: &[u8]| run("gpg", &args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>(), input, Some(&gnupg)).unwrap();
+    for user in &["me <me@example.com>", "teammate <teammate@example.com>"] {
+      gpg(&["--batch", "--passphrase", "", "--quick-gen-key", user, "default", "default", "never"], b"");
+    }
+    let recipients = |raw: &[u8]| gpg_recipients(&String::from_utf8_lossy(&gpg(&["--batch", "--list-packets", "--list-only"], raw))).unwrap();
+    let shared = gpg(
+      &[
+        "--batch",
+        "--armor",
+        "--encrypt",
+        "--recipient",
+        "me@example.com",
+        "--recipient",
+        "teammate@example.com",
+      ],
+      b"workspace = '~/old'\n",
+    );
+    let before = recipients(&shared);
+
+    let written = reencrypt_in(Envelope::Gpg, &shared, "workspace = '~/new'\n", Some(&gnupg)).unwrap();
+    let written = written.as_bytes();
+    let after = recipients(written);
+    let plain = decrypt_in(Envelope::Gpg, written, Some(&gnupg)).unwrap();
+    let _ = Command::new("gpgconf").args(["--kill", "gpg-agent"]).env("GNUPGHOME", &gnupg).status();
+
+    assert_that(&Envelope::detect(written)).is_equal_to(Some(Envelope::Gpg));
+    assert_that(&plain).is_equal_to("workspace = '~/new'\n".to_owned());
+    assert_that(&after.len()).is_equal_to(2);
+    assert_that(&after).is_equal_to(before);
+  }
+}
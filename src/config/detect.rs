@@ -0,0 +1,49 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+// A project type and the files in the repository root that identify it.
+const PROJECT_TYPES: [(&str, &[&str]); 10] = [
+  ("rust", &["Cargo.toml"]),
+  ("node", &["package.json"]),
+  ("go", &["go.mod"]),
+  ("python", &["pyproject.toml", "setup.py", "requirements.txt"]),
+  ("java", &["pom.xml", "build.gradle", "build.gradle.kts"]),
+  ("ruby", &["Gemfile"]),
+  ("elixir", &["mix.exs"]),
+  ("php", &["composer.json"]),
+  ("haskell", &["stack.yaml", "cabal.project"]),
+  ("c", &["CMakeLists.txt", "Makefile", "meson.build"]),
+];
+
+/// The types of the project checked out at `path`, detected from well known files in its root.
+pub fn project_types(path: &Path) -> BTreeSet<String> {
+  PROJECT_TYPES
+    .iter()
+    .filter(|(_, markers)| markers.iter().any(|marker| path.join(marker).exists()))
+    .map(|(project_type, _)| (*project_type).to_owned())
+    .collect()
+}
+
+/// Every project type fw can detect.
+pub fn known_project_types() -> impl Iterator<Item = &'static str> {
+  PROJECT_TYPES.iter().map(|(project_type, _)| *project_type)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use maplit::btreeset;
+  use spectral::prelude::*;
+  use std::fs;
+
+  #[test]
+  fn test_detects_project_types() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    assert_that(&project_types(dir).is_empty()).is_true();
+    fs::write(dir.join("Cargo.toml"), "").unwrap();
+    fs::write(dir.join("package.json"), "{}").unwrap();
+    assert_that(&project_types(dir)).is_equal_to(btreeset!["node".to_owned(), "rust".to_owned()]);
+  }
+}
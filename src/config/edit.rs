@@ -0,0 +1,193 @@
+//! `fw edit`: a config file in the editor, only written if the whole configuration still reads with the edit.
+use super::format::{entry_file, ConfigFormat};
+use super::path::fw_path;
+use super::{crypt, read_config_replacing, write_config_file, Config};
+use crate::errors::AppError;
+use crate::output::{self, print_error};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use slog::{info, Logger};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The config file `fw edit` opens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditTarget {
+  Settings,
+  Project(String),
+  Tag(String),
+}
+
+fn target_path(maybe_config: Result<Config, AppError>, target: &EditTarget, logger: &Logger) -> Result<PathBuf, AppError> {
+  let paths = fw_path()?;
+  match target {
+    // the settings can be edited when they do not read, fixing them is what fw edit is for
+    EditTarget::Settings => Ok(paths.settings),
+    EditTarget::Project(name) => {
+      let config = maybe_config?;
+      let project = config.find_project(name, logger)?;
+      Ok(entry_file(&paths.projects.join(&project.project_config_path), &project.name).0)
+    }
+    EditTarget::Tag(name) => {
+      let config = maybe_config?;
+      let tag = config
+        .settings
+        .tags
+        .as_ref()
+        .and_then(|tags| tags.get(name))
+        .ok_or_else(|| AppError::UserError(format!("tag {} not found", name)))?;
+      Ok(entry_file(&paths.tags.join(&tag.tag_config_path), name).0)
+    }
+  }
+}
+
+/// The name of the copy the editor works on. TOML files get the extension for syntax highlighting.
+fn scratch_name(path: &Path) -> String {
+  let name = path
+    .file_name()
+    .map(|name| name.to_string_lossy().to_string())
+    .unwrap_or_else(|| "config".to_owned());
+  if ConfigFormat::from_path(path) == ConfigFormat::Toml && path.extension().map(|extension| extension != "toml").unwrap_or(true) {
+    format!("{}.toml", name)
+  } else {
+    name
+  }
+}
+
+// A new directory only readable by the user, the copy of an encrypted config is plain text. The name is random and an
+// existing directory is an error, one that someone else prepared must not get the copy.
+fn scratch_dir() -> Result<PathBuf, AppError> {
+  let suffix: String = thread_rng().sample_iter(&Alphanumeric).take(16).collect();
+  let dir = env::temp_dir().join(format!("fw-edit-{}", suffix));
+  let mut builder = fs::DirBuilder::new();
+  #[cfg(unix)]
+  std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+  builder
+    .create(&dir)
+    .map_err(|e| AppError::RuntimeError(format!("Could not create {}: {}", dir.to_string_lossy(), e)))?;
+  Ok(dir)
+}
+
+fn editor() -> String {
+  env::var("VISUAL")
+    .or_else(|_| env::var("EDITOR"))
+    .ok()
+    .filter(|editor| !editor.trim().is_empty())
+    .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_owned())
+}
+
+// through the shell, editors like `code --wait` come with arguments
+fn run_editor(path: &Path) -> Result<(), AppError> {
+  let editor = editor();
+  let status = if cfg!(windows) {
+    Command::new("cmd")
+      .arg("/C")
+      .arg(format!("{} \"{}\"", editor, path.to_string_lossy()))
+      .status()?
+  } else {
+    Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(path).status()?
+  };
+  if status.success() {
+    Ok(())
+  } else {
+    Err(AppError::UserError(format!("{} failed, the config is left as it was", editor)))
+  }
+}
+
+fn edit_again() -> Result<bool, AppError> {
+  if !output::can_prompt() {
+    return Ok(false);
+  }
+  loop {
+    eprint!("[e]dit again or [k]eep the previous version? ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    match answer.trim() {
+      "e" | "edit" | "" => return Ok(true),
+      "k" | "keep" => return Ok(false),
+      _ => continue,
+    }
+  }
+}
+
+// Reads the whole configuration with the edit in place of the file, so that the usual parsing and sanity checks decide
+// before anything is written. Returns whether something changed.
+fn edit_until_valid(path: &Path, scratch: &Path, original: &str, logger: &Logger) -> Result<bool, AppError> {
+  loop {
+    run_editor(scratch)?;
+    let edited = fs::read_to_string(scratch)?;
+    if edited == original {
+      return Ok(false);
+    }
+    match read_config_replacing(path, &edited, logger) {
+      Ok(_) => {
+        write_config_file(path, &edited)?;
+        return Ok(true);
+      }
+      Err(e) => {
+        print_error(&e);
+        if !edit_again()? {
+          return Err(AppError::UserError(format!(
+            "{} would be invalid, kept the previous version",
+            path.to_string_lossy()
+          )));
+        }
+      }
+    }
+  }
+}
+
+/// Opens the config file of `target` in `$VISUAL` or `$EDITOR` and keeps the change if the configuration is still
+/// valid. Otherwise it offers to edit again or keeps the previous version.
+pub fn edit(maybe_config: Result<Config, AppError>, target: &EditTarget, logger: &Logger) -> Result<(), AppError> {
+  let path = target_path(maybe_config, target, logger)?;
+  let original = crypt::read_config_file(&path).map_err(|e| match e {
+    AppError::IO(e) => AppError::UserError(format!("Could not read {}: {}", path.to_string_lossy(), e)),
+    other => other,
+  })?;
+  let dir = scratch_dir()?;
+  let scratch = dir.join(scratch_name(&path));
+  let result = fs::write(&scratch, &original)
+    .map_err(AppError::from)
+    .and_then(|_| edit_until_valid(&path, &scratch, &original, logger));
+  let _ = fs::remove_dir_all(&dir);
+  // the config file itself is only written once the edit is valid
+  if result? {
+    info!(logger, "Config written"; "path" => path.to_string_lossy().to_string());
+  } else {
+    println!("No changes");
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_scratch_name() {
+    assert_that(&scratch_name(Path::new("/c/projects/default/fw"))).is_equal_to("fw.toml".to_owned());
+    assert_that(&scratch_name(Path::new("/c/projects/default/fw.yaml"))).is_equal_to("fw.yaml".to_owned());
+    assert_that(&scratch_name(Path::new("/c/settings.toml"))).is_equal_to("settings.toml".to_owned());
+    assert_that(&scratch_name(Path::new("/c/projects/default/socket.io"))).is_equal_to("socket.io.toml".to_owned());
+  }
+
+  #[test]
+  fn test_scratch_dir_is_new_each_time() {
+    let first = scratch_dir().unwrap();
+    let second = scratch_dir().unwrap();
+    assert_that(&first).is_not_equal_to(&second);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      assert_that(&(fs::metadata(&first).unwrap().permissions().mode() & 0o777)).is_equal_to(0o700);
+    }
+    fs::remove_dir_all(&first).unwrap();
+    fs::remove_dir_all(&second).unwrap();
+  }
+}
@@ -0,0 +1,186 @@
+//! `fw fmt`: every config file rewritten the way fw writes it, without the values that are the same as leaving them out.
+use super::format::ConfigFormat;
+use super::path::{self, fw_path};
+use super::project::Project;
+use super::schema::{self, SchemaKind};
+use super::settings::{ImportNaming, PersistedSettings, ProjectDefaults, Tag};
+use super::{crypt, is_hidden, parse_config_file, project_text, settings_text, tag_text, write_config_file};
+use crate::errors::AppError;
+use crate::git::RetryPolicy;
+use crate::timetrack::DEFAULT_SESSION_TIMEOUT;
+use slog::{info, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+fn unless<T: PartialEq>(value: Option<T>, default: T) -> Option<T> {
+  value.filter(|value| *value != default)
+}
+
+fn non_empty_set<T>(set: Option<BTreeSet<T>>) -> Option<BTreeSet<T>> {
+  set.filter(|set| !set.is_empty())
+}
+
+fn non_empty_map<K, V>(map: Option<BTreeMap<K, V>>) -> Option<BTreeMap<K, V>> {
+  map.filter(|map| !map.is_empty())
+}
+
+fn non_empty_vec<T>(vec: Option<Vec<T>>) -> Option<Vec<T>> {
+  vec.filter(|vec| !vec.is_empty())
+}
+
+fn canonical_settings(settings: PersistedSettings) -> PersistedSettings {
+  let retry = RetryPolicy::default();
+  PersistedSettings {
+    shell: unless(settings.shell, vec!["sh".to_owned(), "-c".to_owned()]),
+    readonly: unless(settings.readonly, false),
+    discover_config: unless(settings.discover_config, false),
+    notify: unless(settings.notify, false),
+    retries: unless(settings.retries, retry.retries),
+    retry_backoff: unless(settings.retry_backoff, retry.backoff.as_secs()),
+    no_proxy: non_empty_vec(settings.no_proxy),
+    import_naming: unless(settings.import_naming, ImportNaming::Name),
+    time_tracking: unless(settings.time_tracking, false),
+    session_timeout: unless(settings.session_timeout, DEFAULT_SESSION_TIMEOUT),
+    commands: non_empty_map(settings.commands),
+    project_defaults: settings.project_defaults.filter(|defaults| *defaults != ProjectDefaults::default()),
+    autotag: non_empty_vec(settings.autotag),
+    profiles: non_empty_map(settings.profiles),
+    ..settings
+  }
+}
+
+fn canonical_project(project: Project) -> Project {
+  Project {
+    bare: unless(project.bare, false),
+    tags: non_empty_set(project.tags),
+    archived: unless(project.archived, false),
+    depends_on: non_empty_vec(project.depends_on),
+    commands: non_empty_map(project.commands),
+    env: non_empty_map(project.env),
+    additional_remotes: non_empty_vec(project.additional_remotes),
+    ..project
+  }
+}
+
+fn canonical_tag(tag: Tag) -> Tag {
+  Tag {
+    default: unless(tag.default, false),
+    commands: non_empty_map(tag.commands),
+    env: non_empty_map(tag.env),
+    when: tag.when.filter(|when| when.file_exists.is_some() || when.project_type.is_some()),
+    ..tag
+  }
+}
+
+/// The canonical text of the config file at `path` of the given kind.
+fn canonical(path: &Path, raw: &str, kind: SchemaKind, logger: &Logger) -> Result<String, AppError> {
+  let format = ConfigFormat::from_path(path);
+  let schema = schema::schema(kind)?;
+  match kind {
+    SchemaKind::Settings => settings_text(&canonical_settings(parse_config_file(path, raw, &schema, logger)?), format),
+    SchemaKind::Project => project_text(&canonical_project(parse_config_file(path, raw, &schema, logger)?), format),
+    SchemaKind::Tag => tag_text(&canonical_tag(parse_config_file(path, raw, &schema, logger)?), format),
+    _ => Err(AppError::InternalError("only files of settings, projects and tags are formatted")),
+  }
+}
+
+fn files_below(dir: &Path) -> Result<Vec<PathBuf>, AppError> {
+  let mut files = Vec::new();
+  if dir.exists() {
+    for entry in WalkDir::new(dir).follow_links(true).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+      let entry = entry?;
+      if entry.metadata()?.is_file() && !is_hidden(entry.file_name()) {
+        files.push(entry.into_path());
+      }
+    }
+  }
+  Ok(files)
+}
+
+/// Rewrites the config files that are not canonical, or with `check` only lists them and fails if there are any.
+pub fn fmt(check: bool, logger: &Logger) -> Result<(), AppError> {
+  if path::config_override().filter(|path| path::is_single_file(path)).is_some() {
+    return Err(AppError::UserError("fw fmt only formats config directories, not a single file".to_owned()));
+  }
+  let paths = fw_path()?;
+  let mut files = Vec::new();
+  if paths.settings.exists() {
+    files.push((paths.settings.clone(), SchemaKind::Settings));
+  }
+  files.extend(files_below(&paths.projects)?.into_iter().map(|file| (file, SchemaKind::Project)));
+  files.extend(files_below(&paths.tags)?.into_iter().map(|file| (file, SchemaKind::Tag)));
+
+  let mut unformatted = Vec::new();
+  for (file, kind) in files {
+    let raw = crypt::read_config_file(&file)?;
+    let canonical = canonical(&file, &raw, kind, logger)?;
+    if raw == canonical {
+      continue;
+    }
+    let relative = file.strip_prefix(&paths.base).unwrap_or(&file).to_string_lossy().to_string();
+    if check {
+      println!("{}", relative);
+    } else {
+      write_config_file(&file, &canonical)?;
+      info!(logger, "Formatted"; "path" => &relative);
+    }
+    unformatted.push(relative);
+  }
+  if check && !unformatted.is_empty() {
+    Err(AppError::UserError(format!(
+      "{} config file(s) are not formatted, run fw fmt",
+      unformatted.len()
+    )))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use maplit::btreeset;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_canonical_project() {
+    let project = Project {
+      bare: Some(false),
+      archived: Some(true),
+      tags: Some(btreeset![]),
+      depends_on: Some(vec![]),
+      commands: None,
+      additional_remotes: Some(vec![]),
+      ..Project::example()
+    };
+    let canonical = canonical_project(project);
+    assert_that(&canonical.bare).is_none();
+    assert_that(&canonical.archived).is_equal_to(Some(true));
+    assert_that(&canonical.tags).is_none();
+    assert_that(&canonical.depends_on).is_none();
+    assert_that(&canonical.additional_remotes).is_none();
+
+    let text = project_text(&canonical_project(Project::example()), ConfigFormat::Toml).unwrap();
+    let reparsed: Project = ConfigFormat::Toml.parse(&text).unwrap();
+    assert_that(&project_text(&canonical_project(reparsed), ConfigFormat::Toml).unwrap()).is_equal_to(text);
+  }
+
+  #[test]
+  fn test_canonical_settings() {
+    let settings = PersistedSettings {
+      shell: Some(vec!["sh".to_owned(), "-c".to_owned()]),
+      retries: Some(2),
+      retry_backoff: Some(5),
+      session_timeout: Some(DEFAULT_SESSION_TIMEOUT),
+      import_naming: Some(ImportNaming::OrgPrefix),
+      ..PersistedSettings::example()
+    };
+    let canonical = canonical_settings(settings);
+    assert_that(&canonical.shell).is_none();
+    assert_that(&canonical.retries).is_none();
+    assert_that(&canonical.retry_backoff).is_equal_to(Some(5));
+    assert_that(&canonical.session_timeout).is_none();
+    assert_that(&canonical.import_naming).is_equal_to(Some(ImportNaming::OrgPrefix));
+  }
+}
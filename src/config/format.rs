@@ -0,0 +1,141 @@
+use crate::errors::AppError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Serialization format of a config file, chosen by its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+  Toml,
+  Yaml,
+}
+
+const YAML_EXTENSIONS: [&str; 2] = ["yaml", "yml"];
+
+impl ConfigFormat {
+  pub fn from_path(path: &Path) -> ConfigFormat {
+    match path.extension().and_then(|e| e.to_str()) {
+      Some(extension) if YAML_EXTENSIONS.contains(&extension) => ConfigFormat::Yaml,
+      _ => ConfigFormat::Toml,
+    }
+  }
+
+  pub fn parse<T: DeserializeOwned>(self, raw: &str) -> Result<T, AppError> {
+    match self {
+      ConfigFormat::Toml => Ok(toml::from_str(raw)?),
+      ConfigFormat::Yaml => Ok(serde_yaml::from_str(raw)?),
+    }
+  }
+
+  /// Like [`ConfigFormat::parse`], but the error names the key that could not be deserialized.
+  pub fn parse_located<T: DeserializeOwned>(self, raw: &str) -> Result<T, String> {
+    match self {
+      ConfigFormat::Toml => serde_path_to_error::deserialize(&mut toml::Deserializer::new(raw)).map_err(|e| format!("{}: {}", e.path(), e.inner())),
+      ConfigFormat::Yaml => serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(raw)).map_err(|e| format!("{}: {}", e.path(), e.inner())),
+    }
+  }
+
+  /// Parses without a target type, e.g. for validating against a schema.
+  pub fn parse_value(self, raw: &str) -> Result<serde_json::Value, AppError> {
+    match self {
+      ConfigFormat::Toml => Ok(serde_json::to_value(toml::from_str::<toml::Value>(raw)?)?),
+      ConfigFormat::Yaml => Ok(serde_json::to_value(serde_yaml::from_str::<serde_yaml::Value>(raw)?)?),
+    }
+  }
+
+  pub fn serialize<T: Serialize>(self, value: &T) -> Result<String, AppError> {
+    match self {
+      ConfigFormat::Toml => Ok(toml::to_string_pretty(value)?),
+      ConfigFormat::Yaml => {
+        let mut yaml = serde_yaml::to_value(value)?;
+        without_nulls(&mut yaml);
+        // drop the document marker so the file reads like a hand written one
+        Ok(format!("{}\n", serde_yaml::to_string(&yaml)?.trim_start_matches("---\n").trim_end()))
+      }
+    }
+  }
+
+  pub fn mode_header(self) -> &'static str {
+    match self {
+      ConfigFormat::Toml => "# -*- mode: Conf; -*-\n",
+      ConfigFormat::Yaml => "# -*- mode: yaml; -*-\n",
+    }
+  }
+}
+
+// TOML has no null and simply leaves unset options out, YAML would write `key: ~` for each of them
+fn without_nulls(value: &mut serde_yaml::Value) {
+  match value {
+    serde_yaml::Value::Mapping(mapping) => {
+      let null_keys: Vec<serde_yaml::Value> = mapping.iter().filter(|(_, v)| v.is_null()).map(|(k, _)| k.clone()).collect();
+      for key in null_keys {
+        mapping.remove(&key);
+      }
+      for (_, v) in mapping.iter_mut() {
+        without_nulls(v);
+      }
+    }
+    serde_yaml::Value::Sequence(sequence) => sequence.iter_mut().for_each(without_nulls),
+    _ => {}
+  }
+}
+
+/// Name of the project or tag defined by a config file: the file name without a YAML extension.
+pub fn entry_name(path: &Path) -> Option<String> {
+  match ConfigFormat::from_path(path) {
+    ConfigFormat::Yaml => path.file_stem(),
+    ConfigFormat::Toml => path.file_name(),
+  }
+  .and_then(|n| n.to_str())
+  .map(ToOwned::to_owned)
+}
+
+/// The file that holds the entry `name` in `dir`: an existing YAML file or the extension-less TOML file.
+pub fn entry_file(dir: &Path, name: &str) -> (PathBuf, ConfigFormat) {
+  YAML_EXTENSIONS
+    .iter()
+    .map(|extension| dir.join(format!("{}.{}", name, extension)))
+    .find(|path| path.exists())
+    .map(|path| (path, ConfigFormat::Yaml))
+    .unwrap_or_else(|| (dir.join(name), ConfigFormat::Toml))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::project::Project;
+  use crate::config::settings::PersistedSettings;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_format_from_extension() {
+    assert_that(&ConfigFormat::from_path(Path::new("projects/default/fw"))).is_equal_to(ConfigFormat::Toml);
+    assert_that(&ConfigFormat::from_path(Path::new("projects/default/fw.yaml"))).is_equal_to(ConfigFormat::Yaml);
+    assert_that(&ConfigFormat::from_path(Path::new("settings.yml"))).is_equal_to(ConfigFormat::Yaml);
+    assert_that(&entry_name(Path::new("projects/default/fw.yml"))).is_equal_to(Some("fw".to_owned()));
+    assert_that(&entry_name(Path::new("projects/default/fw.rs"))).is_equal_to(Some("fw.rs".to_owned()));
+  }
+
+  #[test]
+  fn test_toml_roundtrip() {
+    let toml = ConfigFormat::Toml.serialize(&Project::example()).unwrap();
+    let project: Project = ConfigFormat::Toml.parse(&toml).unwrap();
+    assert_that(&project.depends_on).is_equal_to(Project::example().depends_on);
+  }
+
+  #[test]
+  fn test_toml_settings_roundtrip() {
+    let toml = ConfigFormat::Toml.serialize(&PersistedSettings::example()).unwrap();
+    let settings: PersistedSettings = ConfigFormat::Toml.parse(&toml).unwrap();
+    assert_that(&settings.autotag).is_equal_to(PersistedSettings::example().autotag);
+  }
+
+  #[test]
+  fn test_yaml_roundtrip() {
+    let yaml = ConfigFormat::Yaml.serialize(&Project::example()).unwrap();
+    assert_that(&yaml.starts_with("---")).is_false();
+    let project: Project = ConfigFormat::Yaml.parse(&yaml).unwrap();
+    assert_that(&project.git).is_equal_to(Project::example().git);
+    assert_that(&ConfigFormat::Yaml.serialize(&project).unwrap()).is_equal_to(yaml);
+  }
+}
@@ -0,0 +1,80 @@
+//! `layout` in the settings: the directory tree projects are checked out in, like `{workspace}/{forge}/{org}/{name}`.
+use crate::errors::AppError;
+use crate::git_url::ParsedUrl;
+
+/// What a layout can refer to. `forge` is the host of the git URL and `org` the path before the repository, including
+/// subgroups like `acme/backend`.
+const VARIABLES: [&str; 4] = ["workspace", "forge", "org", "name"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part<'a> {
+  Text(&'a str),
+  Variable(&'a str),
+}
+
+fn parts(layout: &str) -> Result<Vec<Part<'_>>, AppError> {
+  let invalid = |reason: String| AppError::UserError(format!("Invalid layout {}: {}", layout, reason));
+  let mut parts = Vec::new();
+  let mut rest = layout;
+  while let Some(start) = rest.find('{') {
+    if start > 0 {
+      parts.push(Part::Text(&rest[..start]));
+    }
+    let end = rest[start..].find('}').ok_or_else(|| invalid("{ is not closed".to_owned()))? + start;
+    let variable = &rest[start + 1..end];
+    if !VARIABLES.contains(&variable) {
+      return Err(invalid(format!("unknown variable {{{}}}, expected one of {}", variable, VARIABLES.join(", "))));
+    }
+    parts.push(Part::Variable(variable));
+    rest = &rest[end + 1..];
+  }
+  if rest.contains('}') {
+    return Err(invalid("} without {".to_owned()));
+  }
+  if !rest.is_empty() {
+    parts.push(Part::Text(rest));
+  }
+  Ok(parts)
+}
+
+/// Fails for layouts with unknown variables or unbalanced braces.
+pub fn check(layout: &str) -> Result<(), AppError> {
+  parts(layout).map(|_| ())
+}
+
+/// `layout` filled in for the project `name` cloned from `git`. `None` if the layout is invalid or uses `forge` or `org`
+/// and the URL has none, e.g. a local path.
+pub fn expand(layout: &str, workspace: &str, name: &str, git: &str) -> Option<String> {
+  let parsed = ParsedUrl::parse(git);
+  let mut expanded = String::new();
+  for part in parts(layout).ok()? {
+    match part {
+      Part::Text(text) => expanded.push_str(text),
+      Part::Variable("workspace") => expanded.push_str(workspace),
+      Part::Variable("name") => expanded.push_str(name),
+      Part::Variable("forge") => expanded.push_str(parsed?.host?),
+      Part::Variable(_) => expanded.push_str(parsed?.owner()?),
+    }
+  }
+  Some(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_expand() {
+    let layout = "{workspace}/{forge}/{org}/{name}";
+    assert_that(&expand(layout, "~/src", "fw", "git@github.com:brocode/fw.git")).is_equal_to(Some("~/src/github.com/brocode/fw".to_owned()));
+    assert_that(&expand(layout, "/ws", "api", "https://gitlab.com/acme/backend/api")).is_equal_to(Some("/ws/gitlab.com/acme/backend/api".to_owned()));
+    assert_that(&expand(layout, "/ws", "local", "/tmp/repos/local")).is_none();
+    assert_that(&expand("{workspace}/{name}-src", "/ws", "local", "/tmp/repos/local")).is_equal_to(Some("/ws/local-src".to_owned()));
+
+    assert_that(&check(layout)).is_ok();
+    assert_that(&check("{workspace}/{owner}/{name}")).is_err();
+    assert_that(&check("{workspace}/{name")).is_err();
+    assert_that(&check("{workspace}/name}")).is_err();
+  }
+}
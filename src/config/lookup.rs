@@ -0,0 +1,100 @@
+//! Finds a project by a name the user typed, which may be off in case or only part of the name.
+
+/// How many suggestions a failed lookup offers.
+const SUGGESTIONS: usize = 3;
+
+/// Why a name matched no single project.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LookupError {
+  /// The closest names by edit distance, possibly none.
+  NotFound(Vec<String>),
+  /// All names the query matches equally well.
+  Ambiguous(Vec<String>),
+}
+
+impl LookupError {
+  pub fn message(&self, query: &str) -> String {
+    match self {
+      LookupError::NotFound(suggestions) if suggestions.is_empty() => format!("project {} not found", query),
+      LookupError::NotFound(suggestions) => format!("project {} not found, did you mean {}?", query, suggestions.join(", ")),
+      LookupError::Ambiguous(names) => format!("project {} is ambiguous, it matches {}", query, names.join(", ")),
+    }
+  }
+}
+
+/// Levenshtein distance in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut previous: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut current = vec![i + 1];
+    for (j, cb) in b.iter().enumerate() {
+      let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+      current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+    }
+    previous = current;
+  }
+  previous[b.len()]
+}
+
+fn suggestions(names: &[&str], query: &str) -> Vec<String> {
+  let query = query.to_lowercase();
+  // more than a third of the query wrong is not a typo any more
+  let limit = (query.chars().count() / 3).max(2);
+  let mut close: Vec<(usize, &str)> = names
+    .iter()
+    .map(|name| (edit_distance(&name.to_lowercase(), &query), *name))
+    .filter(|(distance, _)| *distance <= limit)
+    .collect();
+  close.sort();
+  close.into_iter().take(SUGGESTIONS).map(|(_, name)| name.to_owned()).collect()
+}
+
+/// The name among `names` that `query` means: the exact name, else the only one that matches ignoring case, else the
+/// only one it is a prefix of, else the only one containing it, both ignoring case. Several matches at a step are
+/// ambiguous.
+pub fn resolve<'a>(names: impl IntoIterator<Item = &'a str>, query: &str) -> Result<&'a str, LookupError> {
+  let names: Vec<&str> = names.into_iter().collect();
+  if let Some(name) = names.iter().find(|name| **name == query) {
+    return Ok(name);
+  }
+  let lowercase = query.to_lowercase();
+  let steps: [&dyn Fn(&str) -> bool; 3] = [&|name: &str| name == lowercase, &|name: &str| name.starts_with(&lowercase), &|name: &str| {
+    name.contains(&lowercase)
+  }];
+  for matches in steps.iter() {
+    let found: Vec<&str> = names.iter().copied().filter(|name| matches(&name.to_lowercase())).collect();
+    match found.len() {
+      0 => continue,
+      1 => return Ok(found[0]),
+      _ => return Err(LookupError::Ambiguous(found.into_iter().map(str::to_owned).collect())),
+    }
+  }
+  Err(LookupError::NotFound(suggestions(&names, query)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_resolve() {
+    let names = vec!["fw", "api-gateway", "api-server", "Dotfiles", "web", "Web-legacy", "infra-tools"];
+    assert_that(&resolve(names.clone(), "fw")).is_equal_to(Ok("fw"));
+    assert_that(&resolve(names.clone(), "dotfiles")).is_equal_to(Ok("Dotfiles"));
+    assert_that(&resolve(names.clone(), "WEB")).is_equal_to(Ok("web"));
+    assert_that(&resolve(names.clone(), "api-g")).is_equal_to(Ok("api-gateway"));
+    assert_that(&resolve(names.clone(), "tools")).is_equal_to(Ok("infra-tools"));
+    assert_that(&resolve(names.clone(), "api")).is_equal_to(Err(LookupError::Ambiguous(vec!["api-gateway".to_owned(), "api-server".to_owned()])));
+    assert_that(&resolve(names.clone(), "api-sever")).is_equal_to(Err(LookupError::NotFound(vec!["api-server".to_owned()])));
+    assert_that(&resolve(names, "kubernetes")).is_equal_to(Err(LookupError::NotFound(vec![])));
+  }
+
+  #[test]
+  fn test_edit_distance() {
+    assert_that(&edit_distance("kitten", "sitting")).is_equal_to(3);
+    assert_that(&edit_distance("", "fw")).is_equal_to(2);
+    assert_that(&edit_distance("fw", "fw")).is_equal_to(0);
+  }
+}
@@ -1,79 +1,205 @@
 use crate::errors::AppError;
+use crate::output;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use slog::{debug, o, trace, warn, Logger};
+use slog::{debug, info, o, trace, warn, Logger};
 use std::collections::{BTreeMap, BTreeSet};
-use std::fs::{self, read_to_string, File};
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
 use walkdir::WalkDir;
 
-static CONF_MODE_HEADER: &str = "# -*- mode: Conf; -*-\n";
-
-mod path;
+pub mod autotag;
+pub mod crypt;
+pub mod detect;
+pub mod edit;
+pub mod fmt;
+pub mod format;
+pub mod layout;
+pub mod lookup;
+pub mod order;
+pub mod path;
+pub mod profile;
 pub mod project;
+pub mod resolve;
+pub mod schema;
+pub mod selector;
 pub mod settings;
+pub mod template;
+use format::{entry_file, entry_name, ConfigFormat};
 use path::{expand_path, fw_path};
+use schema::SchemaKind;
 
 use project::Project;
-use settings::{PersistedSettings, Settings, Tag};
+use resolve::{Explanation, Layer, Resolver};
+use settings::{PersistedSettings, Settings, Tag, TagCondition};
+
+/// A tag of a project that sets the project's workspace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkspaceCandidate {
+  pub tag: String,
+  pub workspace: String,
+  pub priority: u8,
+}
+
+/// How new branches of a project are named, from its tags.
+#[derive(Debug, Clone, Default)]
+pub struct BranchPolicy {
+  pub prefix: Option<String>,
+  /// Compiled once, doctor checks every local branch against it.
+  pub pattern: Option<Regex>,
+}
+
+impl BranchPolicy {
+  /// Whether the tags set neither a prefix nor a pattern.
+  pub fn is_empty(&self) -> bool {
+    self.prefix.is_none() && self.pattern.is_none()
+  }
+
+  /// `name` as is if it already matches the pattern, else with the prefix if it does not have it yet. Fails if the
+  /// result does not match the pattern.
+  pub fn apply(&self, name: &str) -> Result<String, AppError> {
+    let name = match &self.prefix {
+      Some(prefix) if !name.starts_with(prefix.as_str()) && !self.matches(name) => format!("{}{}", prefix, name),
+      _ => name.to_owned(),
+    };
+    if self.allows(&name) {
+      Ok(name)
+    } else {
+      Err(AppError::UserError(format!(
+        "{} does not match the branch_pattern {}",
+        name,
+        self.pattern.as_ref().map(Regex::as_str).unwrap_or_default()
+      )))
+    }
+  }
+
+  fn matches(&self, name: &str) -> bool {
+    self.pattern.as_ref().map(|regex| regex.is_match(name)).unwrap_or(false)
+  }
+
+  /// Whether an existing branch `name` follows the policy: it matches the pattern, or has the prefix without one.
+  pub fn allows(&self, name: &str) -> bool {
+    match (&self.pattern, &self.prefix) {
+      (Some(regex), _) => regex.is_match(name),
+      (None, Some(prefix)) => name.starts_with(prefix.as_str()),
+      (None, None) => true,
+    }
+  }
+}
+
+/// Tags of one project that define different workspaces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkspaceConflict {
+  /// The workspace that is used.
+  pub chosen: WorkspaceCandidate,
+  /// All tags defining a workspace, lowest priority first.
+  pub candidates: Vec<WorkspaceCandidate>,
+  /// Tags with the highest priority disagree, so only the tag names decide.
+  pub tie: bool,
+}
+
+impl WorkspaceConflict {
+  pub fn tied_tags(&self) -> Vec<String> {
+    self
+      .candidates
+      .iter()
+      .filter(|c| c.priority == self.chosen.priority)
+      .map(|c| c.tag.clone())
+      .collect()
+  }
+}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The complete fw configuration: all projects and the settings including tags.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Config {
   pub projects: BTreeMap<String, Project>,
   pub settings: Settings,
+  /// The projects the active profile leaves out. Nothing selects them, but new projects must not take their names.
+  #[serde(skip)]
+  #[schemars(skip)]
+  pub outside_profile: BTreeMap<String, Project>,
 }
 
+/// Reads the configuration from `FW_CONFIG_DIR` or the default location (`~/.config/fw`).
 pub fn read_config(logger: &Logger) -> Result<Config, AppError> {
+  read_config_with(None, logger)
+}
+
+/// Reads the configuration as if the config file at `path` had `content`, without writing it.
+pub fn read_config_replacing(path: &Path, content: &str, logger: &Logger) -> Result<Config, AppError> {
+  read_config_with(Some((path, content)), logger)
+}
+
+/// A config file and the content to read instead of what is on disk.
+type Pending<'a> = Option<(&'a Path, &'a str)>;
+
+fn read_config_with(pending: Pending, logger: &Logger) -> Result<Config, AppError> {
+  let mut config = read_config_files(pending, logger)?;
+  if let Some(name) = profile::active() {
+    config.apply_profile(&name, logger)?;
+  }
+  Ok(config)
+}
+
+fn read_file(path: &Path, pending: Pending) -> Result<String, AppError> {
+  match pending {
+    Some((pending_path, content)) if pending_path == path => Ok(content.to_owned()),
+    _ => crypt::read_config_file(path),
+  }
+}
+
+// fw cannot write back to a single JSON file, so it is read-only. Its hooks are someone else's and need approval.
+fn read_single_file(path: &Path, logger: &Logger) -> Result<Config, AppError> {
+  debug!(logger, "Reading single file configuration"; "path" => path.to_string_lossy().to_string());
+  let mut config = read_legacy_json(path, logger)?;
+  config.settings.readonly = Some(true);
+  config.settings.untrusted_source = Some(path.to_string_lossy().to_string());
+  Ok(config)
+}
+
+fn read_config_files(pending: Pending, logger: &Logger) -> Result<Config, AppError> {
+  let config_override = path::config_override();
+  if let Some(single_file) = config_override.as_ref().filter(|path| path::is_single_file(path)) {
+    return read_single_file(single_file, logger);
+  }
   let paths = fw_path()?;
+  if !paths.settings.exists() && config_override.is_none() {
+    if let Some(legacy) = path::legacy_json() {
+      warn!(logger, "Reading the configuration of fw 1.x, run fw migrate-paths to move it to the current format"; "path" => legacy.to_string_lossy().to_string());
+      return read_legacy_json(&legacy, logger);
+    }
+  }
 
-  let settings_raw = read_to_string(&paths.settings)
-    .map_err(|e| AppError::RuntimeError(format!("Could not read settings file ({}): {}", paths.settings.to_string_lossy(), e)))?;
+  let settings_raw = read_file(&paths.settings, pending).map_err(|e| match e {
+    AppError::IO(e) => AppError::RuntimeError(format!("Could not read settings file ({}): {}", paths.settings.to_string_lossy(), e)),
+    other => other,
+  })?;
 
-  let settings: PersistedSettings = toml::from_str(&settings_raw)?;
+  let settings: PersistedSettings = parse_config_file(&paths.settings, &settings_raw, &schema::schema(SchemaKind::Settings)?, logger)?;
 
   debug!(logger, "read new settings ok");
 
-  let mut projects: BTreeMap<String, Project> = BTreeMap::new();
-  if paths.projects.exists() {
-    for maybe_project_file in WalkDir::new(&paths.projects).follow_links(true) {
-      let project_file = maybe_project_file?;
-      if project_file.metadata()?.is_file() {
-        let raw_project = read_to_string(project_file.path())?;
-        let mut project: Project = toml::from_str(&raw_project)?;
-        project.name = project_file
-          .file_name()
-          .to_str()
-          .map(ToOwned::to_owned)
-          .ok_or(AppError::InternalError("Failed to get project name"))?;
-        project.project_config_path = PathBuf::from(project_file.path().parent().ok_or(AppError::InternalError("Expected file to have a parent"))?)
-          .strip_prefix(paths.projects.as_path())
-          .map_err(|e| AppError::RuntimeError(format!("Failed to strip prefix: {}", e)))?
-          .to_string_lossy()
-          .to_string();
-        if projects.contains_key(&project.name) {
-          warn!(
-            logger,
-            "Inconsistency found: project {} defined more than once. Will use the project that is found last. Results might be inconsistent.", project.name
-          );
-        }
-        projects.insert(project.name.clone(), project);
-      }
+  if settings.discover_config.unwrap_or_default() {
+    if let Some(local) = path::discover_local_config(&std::env::current_dir()?) {
+      return read_single_file(&local, logger);
     }
-    debug!(logger, "read projects ok");
   }
 
+  let projects = read_project_files(&paths.projects, pending, logger)?;
+
   let mut tags: BTreeMap<String, Tag> = BTreeMap::new();
   if paths.tags.exists() {
+    let tag_schema = schema::schema(SchemaKind::Tag)?;
     for maybe_tag_file in WalkDir::new(&paths.tags).follow_links(true) {
       let tag_file = maybe_tag_file?;
-      if tag_file.metadata()?.is_file() {
-        let raw_tag = read_to_string(tag_file.path())?;
-        let mut tag: Tag = toml::from_str(&raw_tag)?;
-        let tag_name: String = tag_file
-          .file_name()
-          .to_str()
-          .map(ToOwned::to_owned)
-          .ok_or(AppError::InternalError("Failed to get tag name"))?;
+      if tag_file.metadata()?.is_file() && !is_hidden(tag_file.file_name()) {
+        let raw_tag = read_file(tag_file.path(), pending)?;
+        let mut tag: Tag = parse_config_file(tag_file.path(), &raw_tag, &tag_schema, logger)?;
+        let tag_name: String = entry_name(tag_file.path()).ok_or(AppError::InternalError("Failed to get tag name"))?;
         tag.tag_config_path = PathBuf::from(tag_file.path().parent().ok_or(AppError::InternalError("Expected file to have a parent"))?)
           .strip_prefix(paths.tags.as_path())
           .map_err(|e| AppError::RuntimeError(format!("Failed to strip prefix: {}", e)))?
@@ -91,42 +217,221 @@ pub fn read_config(logger: &Logger) -> Result<Config, AppError> {
     debug!(logger, "read tags ok");
   }
 
+  assemble(settings, projects, tags, logger)
+}
+
+/// The single file of fw 1.x with the projects and the tags in it.
+#[derive(Deserialize, Debug)]
+struct LegacyConfig {
+  projects: BTreeMap<String, Project>,
+  settings: LegacySettings,
+}
+
+#[derive(Deserialize, Debug)]
+struct LegacySettings {
+  #[serde(flatten)]
+  settings: PersistedSettings,
+  tags: Option<BTreeMap<String, Tag>>,
+  // tags have `default = true` now
+  default_tags: Option<BTreeSet<String>>,
+}
+
+/// The settings, projects and tags in the `~/.fw.json` of fw 1.x. Projects and tags belong to the `default` subdirectory.
+pub struct LegacyParts {
+  pub settings: PersistedSettings,
+  pub projects: BTreeMap<String, Project>,
+  pub tags: BTreeMap<String, Tag>,
+}
+
+pub fn legacy_json_parts(path: &Path) -> Result<LegacyParts, AppError> {
+  let raw = fs::read_to_string(path)?;
+  let legacy: LegacyConfig = serde_json::from_str(&raw)?;
+  let projects = legacy
+    .projects
+    .into_iter()
+    .map(|(name, project)| {
+      let project = Project {
+        name: name.clone(),
+        project_config_path: "default".to_owned(),
+        ..project
+      };
+      (name, project)
+    })
+    .collect();
+  let default_tags = legacy.settings.default_tags.unwrap_or_default();
+  let tags = legacy
+    .settings
+    .tags
+    .unwrap_or_default()
+    .into_iter()
+    .map(|(name, tag)| {
+      let tag = Tag {
+        default: if default_tags.contains(&name) { Some(true) } else { tag.default },
+        tag_config_path: "default".to_owned(),
+        ..tag
+      };
+      (name, tag)
+    })
+    .collect();
+  Ok(LegacyParts {
+    settings: legacy.settings.settings,
+    projects,
+    tags,
+  })
+}
+
+pub fn read_legacy_json(path: &Path, logger: &Logger) -> Result<Config, AppError> {
+  let parts = legacy_json_parts(path)?;
+  assemble(parts.settings, parts.projects, parts.tags, logger)
+}
+
+fn assemble(settings: PersistedSettings, projects: BTreeMap<String, Project>, tags: BTreeMap<String, Tag>, logger: &Logger) -> Result<Config, AppError> {
+  let defaults = settings.project_defaults.unwrap_or_default();
   let default_tags: BTreeSet<String> = tags
     .iter()
     .filter(|(_, value)| value.default.unwrap_or_default())
     .map(|(key, _)| key.to_string())
+    .chain(defaults.tags.into_iter().flatten())
     .collect();
+  let mut commands = settings.commands;
+  if let Some(default_commands) = defaults.commands {
+    commands.get_or_insert_with(BTreeMap::new).extend(default_commands);
+  }
 
-  Ok(Config {
+  let config = Config {
     projects,
+    outside_profile: BTreeMap::new(),
     settings: Settings {
       tags: Some(tags),
       workspace: settings.workspace,
-      shell: settings.shell,
+      shell: defaults.shell.or(settings.shell),
+      hook_timeout: defaults.hook_timeout.or(settings.hook_timeout),
+      ssh_key: defaults.ssh_key.or(settings.ssh_key),
+      lfs: defaults.lfs.or(settings.lfs),
       default_after_workon: settings.default_after_workon,
       default_after_clone: settings.default_after_clone,
+      after_clone: defaults.after_clone,
+      after_workon: defaults.after_workon,
       default_tags: Some(default_tags),
       github_token: settings.github_token,
+      gitea_token: settings.gitea_token,
+      azure_token: settings.azure_token,
       gitlab: settings.gitlab,
+      log_file: settings.log_file,
+      readonly: settings.readonly,
+      discover_config: settings.discover_config,
+      notify: settings.notify,
+      notify_command: settings.notify_command,
+      retries: settings.retries,
+      retry_backoff: settings.retry_backoff,
+      http_proxy: settings.http_proxy,
+      https_proxy: settings.https_proxy,
+      no_proxy: settings.no_proxy,
+      ca_bundle: settings.ca_bundle,
+      import_naming: settings.import_naming,
+      time_tracking: settings.time_tracking,
+      session_timeout: settings.session_timeout,
+      layout: defaults.override_path.or(settings.layout),
+      prefer: settings.prefer,
+      reference_repo: settings.reference_repo,
+      max_network_operations: settings.max_network_operations,
+      bandwidth_limit: settings.bandwidth_limit,
+      hook_nice: settings.hook_nice,
+      clone_depth: defaults.clone_depth,
+      env: defaults.env,
+      commands,
+      autotag: settings.autotag,
+      forge_tags: settings.forge_tags,
+      tag_groups: settings.tag_groups,
+      profiles: settings.profiles,
+      shared_config: settings.shared_config,
+      untrusted_source: None,
     },
-  })
+  };
+  // checked as a whole, projects outside of the profile can still be dependencies
+  config.check_sanity(logger)?;
+  Ok(config)
+}
+
+/// Reads all project files below `projects_dir`, which is laid out like the `projects` directory of the fw config.
+pub fn read_projects(projects_dir: &Path, logger: &Logger) -> Result<BTreeMap<String, Project>, AppError> {
+  read_project_files(projects_dir, None, logger)
+}
+
+fn read_project_files(projects_dir: &Path, pending: Pending, logger: &Logger) -> Result<BTreeMap<String, Project>, AppError> {
+  let mut projects: BTreeMap<String, Project> = BTreeMap::new();
+  if projects_dir.exists() {
+    let project_schema = schema::schema(SchemaKind::Project)?;
+    for maybe_project_file in WalkDir::new(projects_dir).follow_links(true) {
+      let project_file = maybe_project_file?;
+      if project_file.metadata()?.is_file() && !is_hidden(project_file.file_name()) {
+        let raw_project = read_file(project_file.path(), pending)?;
+        let mut project: Project = parse_config_file(project_file.path(), &raw_project, &project_schema, logger)?;
+        project.name = entry_name(project_file.path()).ok_or(AppError::InternalError("Failed to get project name"))?;
+        project.project_config_path = PathBuf::from(project_file.path().parent().ok_or(AppError::InternalError("Expected file to have a parent"))?)
+          .strip_prefix(projects_dir)
+          .map_err(|e| AppError::RuntimeError(format!("Failed to strip prefix: {}", e)))?
+          .to_string_lossy()
+          .to_string();
+        if projects.contains_key(&project.name) {
+          warn!(
+            logger,
+            "Inconsistency found: project {} defined more than once. Will use the project that is found last. Results might be inconsistent.", project.name
+          );
+        }
+        projects.insert(project.name.clone(), project);
+      }
+    }
+    debug!(logger, "read projects ok");
+  }
+  Ok(projects)
+}
+
+// Validates against the schema first so that errors name the file, line and key instead of only what serde expected.
+fn parse_config_file<T: DeserializeOwned>(path: &Path, raw: &str, schema: &serde_json::Value, logger: &Logger) -> Result<T, AppError> {
+  let file = path.to_string_lossy();
+  let format = ConfigFormat::from_path(path);
+  let value = format
+    .parse_value(raw)
+    .map_err(|e| AppError::UserError(format!("Invalid config file {}: {}", file, e)))?;
+  for issue in schema::validate(schema, &value) {
+    let location = match schema::line_of(raw, &issue.path) {
+      Some(line) => format!("{}:{}", file, line),
+      None => file.to_string(),
+    };
+    if issue.unknown_key {
+      warn!(logger, "Ignoring unknown key in config"; "file" => location, "key" => issue.key_path());
+    } else {
+      return Err(AppError::UserError(format!(
+        "Invalid config {}: {}: {}",
+        location,
+        issue.key_path(),
+        issue.message
+      )));
+    }
+  }
+  format
+    .parse_located(raw)
+    .map_err(|e| AppError::UserError(format!("Invalid config file {}: {}", file, e)))
+}
+
+fn is_hidden(file_name: &std::ffi::OsStr) -> bool {
+  file_name.to_str().map(|n| n.starts_with('.')).unwrap_or(false)
 }
 
 pub fn write_settings(settings: &PersistedSettings, logger: &Logger) -> Result<(), AppError> {
   let paths = fw_path()?;
   paths.ensure_base_exists()?;
 
-  let mut buffer = File::create(&paths.settings)?;
-  let serialized = toml::to_string_pretty(settings)?;
-  write!(buffer, "{}", serialized)?;
-  write_example(&mut buffer, PersistedSettings::example())?;
+  let buffer = settings_text(settings, ConfigFormat::from_path(&paths.settings))?;
+  write_config_file(&paths.settings, &buffer)?;
 
-  debug!(logger, "Wrote settings file to {:?}", paths.settings);
+  info!(logger, "Config written"; "path" => paths.settings.to_string_lossy().to_string());
 
   Ok(())
 }
 
-pub fn write_tag(tag_name: &str, tag: &Tag) -> Result<(), AppError> {
+pub fn write_tag(tag_name: &str, tag: &Tag, logger: &Logger) -> Result<(), AppError> {
   let paths = fw_path()?;
   paths.ensure_base_exists()?;
 
@@ -135,57 +440,111 @@ pub fn write_tag(tag_name: &str, tag: &Tag) -> Result<(), AppError> {
   std::fs::create_dir_all(&tag_path)
     .map_err(|e| AppError::RuntimeError(format!("Failed to create tag config path '{}'. {}", tag_path.to_string_lossy(), e)))?;
 
-  let mut tag_file_path = tag_path;
-  tag_file_path.push(&tag_name);
+  let (tag_file_path, format) = entry_file(&tag_path, tag_name);
 
-  let mut buffer = File::create(&tag_file_path)
-    .map_err(|e| AppError::RuntimeError(format!("Failed to create project config file '{}'. {}", tag_file_path.to_string_lossy(), e)))?;
-  let serialized = toml::to_string_pretty(&tag)?;
-  write!(buffer, "{}", CONF_MODE_HEADER)?;
-  write!(buffer, "{}", serialized)?;
-  write_example(&mut buffer, Tag::example())?;
+  write_config_file(&tag_file_path, &tag_text(tag, format)?)
+    .map_err(|e| AppError::RuntimeError(format!("Failed to write tag config file '{}'. {}", tag_file_path.to_string_lossy(), e)))?;
+  info!(logger, "Config written"; "tag" => tag_name, "path" => tag_file_path.to_string_lossy().to_string());
   Ok(())
 }
 
-pub fn delete_tag_config(tag_name: &str, tag: &Tag) -> Result<(), AppError> {
+pub fn delete_tag_config(tag_name: &str, tag: &Tag, logger: &Logger) -> Result<(), AppError> {
   let paths = fw_path()?;
   paths.ensure_base_exists()?;
 
-  let mut tag_file_path = paths.tags;
-  tag_file_path.push(PathBuf::from(&tag.tag_config_path));
-  tag_file_path.push(tag_name);
+  let (tag_file_path, _) = entry_file(&paths.tags.join(&tag.tag_config_path), tag_name);
 
   fs::remove_file(&tag_file_path).map_err(|e| AppError::RuntimeError(format!("Failed to delete tag config from '{:?}': {}", tag_file_path, e)))?;
+  info!(logger, "Config deleted"; "tag" => tag_name, "path" => tag_file_path.to_string_lossy().to_string());
   Ok(())
 }
 
-pub fn delete_project_config(project: &Project) -> Result<(), AppError> {
+pub fn delete_project_config(project: &Project, logger: &Logger) -> Result<(), AppError> {
   let paths = fw_path()?;
   paths.ensure_base_exists()?;
 
-  let mut project_file_path = paths.projects;
-  project_file_path.push(PathBuf::from(&project.project_config_path));
-  project_file_path.push(&project.name);
+  let (project_file_path, _) = entry_file(&paths.projects.join(&project.project_config_path), &project.name);
 
-  fs::remove_file(project_file_path).map_err(|e| AppError::RuntimeError(format!("Failed to delete project config: {}", e)))?;
+  fs::remove_file(&project_file_path).map_err(|e| AppError::RuntimeError(format!("Failed to delete project config: {}", e)))?;
+  info!(logger, "Config deleted"; "project" => &project.name, "path" => project_file_path.to_string_lossy().to_string());
   Ok(())
 }
 
-fn write_example<T>(buffer: &mut File, example: T) -> Result<(), AppError>
+fn write_example<T>(buffer: &mut String, format: ConfigFormat, example: T) -> Result<(), AppError>
 where
   T: serde::Serialize,
 {
-  let example_toml = toml::to_string_pretty(&example)?;
-  writeln!(buffer, "\n# Example:")?;
-  for line in example_toml.split('\n') {
+  let example = format.serialize(&example)?;
+  buffer.push_str("\n# Example:\n");
+  for line in example.split('\n') {
     if line.trim() != "" {
-      writeln!(buffer, "# {}", line)?;
+      buffer.push_str(&format!("# {}\n", line));
     }
   }
   Ok(())
 }
 
-pub fn write_project(project: &Project) -> Result<(), AppError> {
+/// The settings file as fw writes it.
+fn settings_text(settings: &PersistedSettings, format: ConfigFormat) -> Result<String, AppError> {
+  let mut buffer = format.serialize(settings)?;
+  write_example(&mut buffer, format, PersistedSettings::example())?;
+  Ok(buffer)
+}
+
+/// A tag file as fw writes it.
+fn tag_text(tag: &Tag, format: ConfigFormat) -> Result<String, AppError> {
+  let mut buffer = String::from(format.mode_header());
+  buffer.push_str(&format.serialize(tag)?);
+  write_example(&mut buffer, format, Tag::example())?;
+  Ok(buffer)
+}
+
+/// A project file as fw writes it.
+fn project_text(project: &Project, format: ConfigFormat) -> Result<String, AppError> {
+  let mut buffer = String::from(format.mode_header());
+  buffer.push_str(&format.serialize(project)?);
+  write_example(&mut buffer, format, Project::example())?;
+  Ok(buffer)
+}
+
+// Writes to a hidden temp file next to `path` and renames it into place so an interrupted write never leaves a truncated config behind.
+pub(crate) fn write_atomically(path: &Path, content: &str) -> Result<(), AppError> {
+  let file_name = path
+    .file_name()
+    .and_then(|n| n.to_str())
+    .ok_or(AppError::InternalError("Expected config path to have a file name"))?;
+  let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+  let result = File::create(&tmp_path)
+    .and_then(|mut file| {
+      file.write_all(content.as_bytes())?;
+      file.sync_all()
+    })
+    .and_then(|_| fs::rename(&tmp_path, path));
+  if result.is_err() {
+    let _ = fs::remove_file(&tmp_path);
+  }
+  result.map_err(AppError::from)
+}
+
+// An encrypted config file stays encrypted. Failing to encrypt fails the write, it never falls back to plain text.
+// An encrypted file stays encrypted for the recipients it has.
+fn write_config_file(path: &Path, content: &str) -> Result<(), AppError> {
+  let existing = match fs::read(path) {
+    Ok(raw) => raw,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+    Err(e) => return Err(AppError::IO(e)),
+  };
+  match crypt::Envelope::detect(&existing) {
+    Some(envelope) => {
+      let encrypted = crypt::reencrypt(envelope, &existing, content)
+        .map_err(|e| AppError::UserError(format!("Could not encrypt {} again: {}", path.to_string_lossy(), e)))?;
+      write_atomically(path, &encrypted)
+    }
+    None => write_atomically(path, content),
+  }
+}
+
+pub fn write_project(project: &Project, logger: &Logger) -> Result<(), AppError> {
   let paths = fw_path()?;
   paths.ensure_base_exists()?;
 
@@ -194,55 +553,495 @@ pub fn write_project(project: &Project) -> Result<(), AppError> {
   std::fs::create_dir_all(&project_path)
     .map_err(|e| AppError::RuntimeError(format!("Failed to create project config path '{}'. {}", project_path.to_string_lossy(), e)))?;
 
-  let mut project_file_path = project_path;
-  project_file_path.push(&project.name);
+  let (project_file_path, format) = entry_file(&project_path, &project.name);
 
-  let mut buffer: File = File::create(&project_file_path)
-    .map_err(|e| AppError::RuntimeError(format!("Failed to create project config file '{}'. {}", project_file_path.to_string_lossy(), e)))?;
-  let serialized = toml::to_string_pretty(&project)?;
-
-  write!(buffer, "{}", CONF_MODE_HEADER)?;
-  write!(buffer, "{}", serialized)?;
-  write_example(&mut buffer, Project::example())?;
+  write_config_file(&project_file_path, &project_text(project, format)?)
+    .map_err(|e| AppError::RuntimeError(format!("Failed to write project config file '{}'. {}", project_file_path.to_string_lossy(), e)))?;
+  info!(logger, "Config written"; "project" => &project.name, "path" => project_file_path.to_string_lossy().to_string());
   Ok(())
 }
 
 impl Config {
+  /// Validates relations between projects that a single project file cannot express, e.g. `depends_on` cycles.
+  pub fn check_sanity(&self, logger: &Logger) -> Result<(), AppError> {
+    for project in self.projects.values() {
+      if project.git.is_empty() && !project.is_directory() {
+        return Err(AppError::UserError(format!(
+          "project {} has no git URL, set kind = \"directory\" if it is not a repository",
+          project.name
+        )));
+      }
+      if let Some(subdir) = &project.subdir {
+        let inside = Path::new(subdir).components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !inside || project.is_directory() {
+          return Err(AppError::UserError(format!(
+            "subdir {} of project {} has to be a relative path inside its git repository",
+            subdir, project.name
+          )));
+        }
+      }
+      if project.has_cwd_relative_override() {
+        debug!(logger, "override_path is relative to the current directory, fw doctor explains"; "project" => &project.name);
+      }
+      // several workspaces or environments would apply and which one wins is up to tag priorities
+      // fw doctor reports it as an error, fw tag untag-project has to stay usable to fix it
+      for (group, tags) in self.settings.tag_group_conflicts(&project.tags.clone().unwrap_or_default()) {
+        warn!(logger, "Project has more than one tag of a tag group"; "project" => &project.name, "group" => group, "tags" => tags.join(", "));
+      }
+      for dependency in project.dependencies() {
+        if !self.projects.contains_key(dependency) {
+          warn!(logger, "Project depends on unknown project"; "project" => &project.name, "depends_on" => dependency);
+        }
+      }
+    }
+    if let Some(layout) = &self.settings.layout {
+      layout::check(layout)?;
+    }
+    for rule in self.settings.autotag.iter().flatten() {
+      rule.check()?;
+      if !self.settings.tags.iter().flatten().any(|(tag_name, _)| tag_name == &rule.tag) {
+        warn!(logger, "Autotag rule uses unknown tag"; "tag" => &rule.tag);
+      }
+    }
+    for (group, members) in self.settings.tag_groups.iter().flatten() {
+      for member in members {
+        if !self.settings.tags.iter().flatten().any(|(tag_name, _)| tag_name == member) {
+          warn!(logger, "Tag group uses unknown tag"; "group" => group, "tag" => member);
+        }
+      }
+    }
+    for (tag_name, tag) in self.settings.tags.iter().flatten() {
+      if let Some(pattern) = &tag.branch_pattern {
+        Regex::new(pattern).map_err(|e| AppError::UserError(format!("Invalid branch_pattern of tag {}: {}", tag_name, e)))?;
+      }
+      if let Some(project_type) = tag.when.as_ref().and_then(|w| w.project_type.as_ref()) {
+        if !detect::known_project_types().any(|known| known == project_type) {
+          warn!(logger, "Tag condition uses unknown project type"; "tag" => tag_name, "project_type" => project_type);
+        }
+      }
+    }
+    let projects: Vec<&Project> = self.projects.values().collect();
+    order::in_dependency_order(&projects)?;
+    Ok(())
+  }
+
+  /// The tags of the project plus every tag whose `when` condition matches the project's checkout.
+  pub fn effective_tags(&self, project: &Project, logger: &Logger) -> BTreeSet<String> {
+    let mut tags = project.tags.clone().unwrap_or_default();
+    let conditional: Vec<(&String, &TagCondition)> = self
+      .settings
+      .tags
+      .iter()
+      .flatten()
+      .filter_map(|(name, tag)| tag.when.as_ref().map(|when| (name, when)))
+      .filter(|(name, _)| !tags.contains(*name))
+      .collect();
+    if conditional.is_empty() {
+      return tags;
+    }
+    let path = self.actual_path_to_project(project, logger);
+    for (name, when) in conditional {
+      if when.matches(&path) {
+        trace!(logger, "Conditional tag applies"; "project" => &project.name, "tag" => name);
+        tags.insert(name.clone());
+      }
+    }
+    tags
+  }
+
+  pub fn log_file(&self) -> Option<PathBuf> {
+    self.settings.log_file.clone().map(PathBuf::from).map(expand_path)
+  }
+
+  /// Narrows the configuration to the profile `name`: its workspace, its default tags and only its projects. The other
+  /// projects move to `outside_profile`.
+  pub fn apply_profile(&mut self, name: &str, logger: &Logger) -> Result<(), AppError> {
+    let profiles = self.settings.profiles.clone().unwrap_or_default();
+    let profile = profiles.get(name).ok_or_else(|| {
+      let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+      AppError::UserError(format!("Unknown profile {}, the settings define: {}", name, known.join(", ")))
+    })?;
+    if let Some(workspace) = &profile.workspace {
+      self.settings.workspace = workspace.clone();
+    }
+    if profile.default_tags.is_some() {
+      self.settings.default_tags = profile.default_tags.clone();
+    }
+    let outside: Vec<String> = self
+      .projects
+      .values()
+      .filter(|p| !profile.includes(&p.name, &self.effective_tags(p, logger)))
+      .map(|p| p.name.clone())
+      .collect();
+    for project_name in outside {
+      if let Some(project) = self.projects.remove(&project_name) {
+        self.outside_profile.insert(project_name, project);
+      }
+    }
+    debug!(logger, "Applied profile"; "profile" => name, "projects" => self.projects.len());
+    Ok(())
+  }
+
+  /// Every project, also those outside the active profile. Adding a project checks its name against these.
+  pub fn all_projects(&self) -> BTreeMap<String, Project> {
+    let mut projects = self.outside_profile.clone();
+    projects.extend(self.projects.clone());
+    projects
+  }
+
+  /// The project `name` refers to, see [`lookup::resolve`] for how inexact names are matched.
+  pub fn find_project(&self, name: &str, logger: &Logger) -> Result<&Project, AppError> {
+    let found = lookup::resolve(self.projects.keys().map(String::as_str), name).map_err(|e| AppError::UserError(e.message(name)))?;
+    if found != name && output::non_interactive() {
+      return Err(AppError::UserError(format!(
+        "project {} not found, only the exact name counts with --non-interactive (did you mean {}?)",
+        name, found
+      )));
+    }
+    if found != name {
+      debug!(logger, "Resolved project name"; "query" => name, "project" => found);
+    }
+    let project = &self.projects[found];
+    self.check_workspace_choice(project, logger)?;
+    Ok(project)
+  }
+
+  /// Where the project is checked out: its `override_path` or the `layout` of the settings (`<workspace>/<name>` without
+  /// one) with the workspace resolved through tags. An `override_path` starting with `./` or `../` is relative to that
+  /// workspace. For projects with a `subdir` this is the directory inside the checkout.
   pub fn actual_path_to_project(&self, project: &Project, logger: &Logger) -> PathBuf {
-    let path = project
-      .override_path
-      .clone()
-      .map(PathBuf::from)
-      .unwrap_or_else(|| Path::new(self.resolve_workspace(logger, project).as_str()).join(project.name.as_str()));
+    let checkout = self.checkout_path(project, logger);
+    match &project.subdir {
+      Some(subdir) => checkout.join(subdir),
+      None => checkout,
+    }
+  }
+
+  /// The project with its git URL in the form `prefer` asks for, to clone or show it. The config keeps the URL as it
+  /// was entered.
+  pub fn preferred(&self, project: &Project) -> Project {
+    Project {
+      git: self.settings.git_url(&project.git),
+      ..project.clone()
+    }
+  }
+
+  /// Where the repository of the project is cloned, the same directory for all projects with a `subdir` in it.
+  pub fn checkout_path(&self, project: &Project, logger: &Logger) -> PathBuf {
+    let workspace = || self.resolve_workspace(logger, project);
+    let path = match (project.workspace_override(), &project.override_path) {
+      (Some(relative), _) => PathBuf::from(workspace()).join(relative),
+      (None, Some(path)) => PathBuf::from(path),
+      (None, None) => self.laid_out_path(&workspace(), project, logger),
+    };
     expand_path(path)
   }
 
-  fn resolve_workspace(&self, logger: &Logger, project: &Project) -> String {
-    let mut x = self.resolve_from_tags(|tag| tag.workspace.clone(), project.tags.clone(), logger);
-    let workspace = x.pop().unwrap_or_else(|| self.settings.workspace.clone());
+  fn laid_out_path(&self, workspace: &str, project: &Project, logger: &Logger) -> PathBuf {
+    let name = project.checkout_name();
+    let default = || Path::new(workspace).join(name);
+    match &self.settings.layout {
+      Some(layout) => layout::expand(layout, workspace, name, &project.git).map(PathBuf::from).unwrap_or_else(|| {
+        trace!(logger, "Layout does not apply, the git URL has no forge or org"; "project" => &project.name);
+        default()
+      }),
+      None => default(),
+    }
+  }
+
+  // Only the project's own tags count here: conditional tags are checked against the checkout, which lives in the workspace.
+  fn workspace_resolver(&self, project: &Project, logger: &Logger) -> Resolver<String> {
+    let known_tags: BTreeSet<String> = project
+      .tags
+      .iter()
+      .flatten()
+      .filter(|name| self.settings.tags.iter().flatten().any(|(tag_name, _)| tag_name == *name))
+      .cloned()
+      .collect();
+    self.layers(&known_tags, Some(self.settings.workspace.clone()), |t| t.workspace.clone(), None, logger)
+  }
+
+  fn workspace_candidates(&self, project: &Project, logger: &Logger) -> Vec<WorkspaceCandidate> {
+    self
+      .workspace_resolver(project, logger)
+      .contributions()
+      .iter()
+      .filter_map(|c| match &c.layer {
+        Layer::Tag { name, priority } => Some(WorkspaceCandidate {
+          tag: name.clone(),
+          workspace: c.value.clone(),
+          priority: *priority,
+        }),
+        _ => None,
+      })
+      .collect()
+  }
+
+  /// Tags of the project that define different workspaces. `None` if there is at most one workspace to choose from.
+  pub fn workspace_conflict(&self, project: &Project, logger: &Logger) -> Option<WorkspaceConflict> {
+    let candidates = self.workspace_candidates(project, logger);
+    let chosen = candidates.last()?.clone();
+    if candidates.iter().all(|c| c.workspace == chosen.workspace) {
+      return None;
+    }
+    let tie = candidates.iter().any(|c| c.priority == chosen.priority && c.workspace != chosen.workspace);
+    Some(WorkspaceConflict { chosen, candidates, tie })
+  }
+
+  /// Warns once if tags of the same priority define different workspaces for the project, with `--non-interactive` it
+  /// is an error instead: fw would pick one by tag name and nobody is there to notice. Commands call it once for each
+  /// project they work on, resolving the path stays quiet.
+  pub fn check_workspace_choice(&self, project: &Project, logger: &Logger) -> Result<(), AppError> {
+    let uses_workspace = project.override_path.is_none() || project.workspace_override().is_some();
+    let conflict = match self.workspace_conflict(project, logger).filter(|c| c.tie && uses_workspace) {
+      Some(conflict) => conflict,
+      None => return Ok(()),
+    };
+    if output::non_interactive() {
+      return Err(AppError::UserError(format!(
+        "tags of project {} define contradictory workspaces with equal priority, fw doctor shows them",
+        project.name
+      )));
+    }
+    warn!(logger, "Tags with the same priority define different workspaces, set a priority to choose one";
+      "project" => &project.name, "tags" => conflict.tied_tags().join(", "), "using" => &conflict.chosen.workspace);
+    Ok(())
+  }
+
+  // The tag with the highest priority wins. Between tags of the same priority the one that comes last by name wins.
+  pub(crate) fn resolve_workspace(&self, logger: &Logger, project: &Project) -> String {
+    let workspace = self
+      .workspace_resolver(project, logger)
+      .first()
+      .unwrap_or_else(|| self.settings.workspace.clone());
     trace!(logger, "resolved"; "workspace" => &workspace);
     workspace
   }
+
+  // Only the hooks of `project_defaults` are a layer here, `default_after_clone` and `default_after_workon` are copied
+  // into new projects.
+  fn after_clone_resolver(&self, project: &Project, logger: &Logger) -> Resolver<String> {
+    self.resolver(
+      project,
+      self.settings.after_clone.clone(),
+      |t| t.after_clone.clone(),
+      project.after_clone.clone(),
+      logger,
+    )
+  }
+
+  fn after_workon_resolver(&self, project: &Project, logger: &Logger) -> Resolver<String> {
+    self.resolver(
+      project,
+      self.settings.after_workon.clone(),
+      |t| t.after_workon.clone(),
+      project.after_workon.clone(),
+      logger,
+    )
+  }
+
+  /// The after_clone hooks of the project defaults, of the project's tags in priority order and the project's own hook.
   pub fn resolve_after_clone(&self, logger: &Logger, project: &Project) -> Vec<String> {
-    let mut commands: Vec<String> = vec![];
-    commands.extend_from_slice(&self.resolve_after_clone_from_tags(project.tags.clone(), logger));
-    let commands_from_project: Vec<String> = project.after_clone.clone().into_iter().collect();
-    commands.extend_from_slice(&commands_from_project);
-    commands
+    self
+      .after_clone_resolver(project, logger)
+      .join()
+      .iter()
+      .map(|c| self.expand_hook(c, project, logger))
+      .collect()
   }
+  /// The after_workon hooks of the project defaults, of the project's tags in priority order and the project's own hook.
   pub fn resolve_after_workon(&self, logger: &Logger, project: &Project) -> Vec<String> {
-    let mut commands: Vec<String> = vec![];
-    commands.extend_from_slice(&self.resolve_workon_from_tags(project.tags.clone(), logger));
-    let commands_from_project: Vec<String> = project.after_workon.clone().into_iter().collect();
-    commands.extend_from_slice(&commands_from_project);
-    commands
+    self
+      .after_workon_resolver(project, logger)
+      .join()
+      .iter()
+      .map(|c| self.expand_hook(c, project, logger))
+      .collect()
+  }
+
+  // Tag hooks are shared between projects, the placeholders make them about the project at hand. The values are quoted
+  // for the shell, a path with spaces or quotes stays one word and can't run commands.
+  fn expand_hook(&self, hook: &str, project: &Project, logger: &Logger) -> String {
+    template::expand_placeholders(hook, |name| match name {
+      "project.name" => Some(template::shell_word(&project.name)),
+      "project.path" => Some(template::shell_word(&self.actual_path_to_project(project, logger).to_string_lossy())),
+      "project.git" => Some(template::shell_word(&project.git)),
+      "tags" => Some(
+        self
+          .effective_tags(project, logger)
+          .iter()
+          .map(|tag| template::shell_word(tag))
+          .collect::<Vec<String>>()
+          .join(" "),
+      ),
+      unknown => {
+        warn!(logger, "Unknown placeholder in hook, leaving it as it is"; "placeholder" => unknown, "project" => &project.name);
+        None
+      }
+    })
+  }
+
+  fn commands_resolver(&self, project: &Project, logger: &Logger) -> Resolver<BTreeMap<String, String>> {
+    self.resolver(
+      project,
+      self.settings.commands.clone(),
+      |t| t.commands.clone(),
+      project.commands.clone(),
+      logger,
+    )
+  }
+
+  /// The command `name` for `fw run` in a project: the project's own, else the one of its tag with the highest priority,
+  /// else the one in the settings.
+  pub fn resolve_command(&self, name: &str, project: &Project, logger: &Logger) -> Option<String> {
+    self.resolve_commands(project, logger).remove(name)
+  }
+
+  /// All commands for `fw run` in a project by name, resolved like `resolve_command`.
+  pub fn resolve_commands(&self, project: &Project, logger: &Logger) -> BTreeMap<String, String> {
+    self.commands_resolver(project, logger).merge()
   }
 
-  fn resolve_workon_from_tags(&self, maybe_tags: Option<BTreeSet<String>>, logger: &Logger) -> Vec<String> {
-    self.resolve_from_tags(|t| t.clone().after_workon, maybe_tags, logger)
+  fn shell_resolver(&self, project: &Project, logger: &Logger) -> Resolver<Vec<String>> {
+    self.resolver(project, self.settings.shell.clone(), |t| t.shell.clone(), project.shell.clone(), logger)
   }
-  fn resolve_after_clone_from_tags(&self, maybe_tags: Option<BTreeSet<String>>, logger: &Logger) -> Vec<String> {
-    self.resolve_from_tags(|t| t.clone().after_clone, maybe_tags, logger)
+
+  /// The shell for the project's hooks and commands: the project's own, else the one of its tag with the highest priority,
+  /// else the one in the settings.
+  pub fn resolve_shell(&self, project: &Project, logger: &Logger) -> Vec<String> {
+    self
+      .shell_resolver(project, logger)
+      .first()
+      .unwrap_or_else(|| self.settings.get_shell_or_default())
+  }
+
+  fn hook_timeout_resolver(&self, project: &Project, logger: &Logger) -> Resolver<u64> {
+    self.resolver(project, self.settings.hook_timeout, |t| t.hook_timeout, project.hook_timeout, logger)
+  }
+
+  /// The timeout for the project's hooks: the project's own, else the one of its tag with the highest priority,
+  /// else the one in the settings.
+  pub fn resolve_hook_timeout(&self, project: &Project, logger: &Logger) -> Option<Duration> {
+    self.hook_timeout_resolver(project, logger).first().map(Duration::from_secs)
+  }
+
+  fn ssh_key_resolver(&self, project: &Project, logger: &Logger) -> Resolver<String> {
+    self.resolver(project, self.settings.ssh_key.clone(), |t| t.ssh_key.clone(), project.ssh_key.clone(), logger)
+  }
+
+  /// The private key for the project's remotes: the project's own, else the one of its tag with the highest priority,
+  /// else the one in the settings. Without one the ssh agent is asked.
+  pub fn resolve_ssh_key(&self, project: &Project, logger: &Logger) -> Option<PathBuf> {
+    self.ssh_key_resolver(project, logger).first().map(|key| expand_path(PathBuf::from(key)))
+  }
+
+  /// The `reference_repo` of the settings if it exists.
+  pub fn reference_repo(&self) -> Option<PathBuf> {
+    let path = expand_path(PathBuf::from(self.settings.reference_repo.as_ref()?));
+    if path.exists() {
+      Some(path)
+    } else {
+      None
+    }
+  }
+
+  fn lfs_resolver(&self, project: &Project, logger: &Logger) -> Resolver<bool> {
+    self.resolver(project, self.settings.lfs, |t| t.lfs, project.lfs, logger)
+  }
+
+  /// Whether the project's git LFS objects are fetched: the project's own choice, else the one of its tag with the
+  /// highest priority, else the one in the settings. Off without any.
+  pub fn resolve_lfs(&self, project: &Project, logger: &Logger) -> bool {
+    self.lfs_resolver(project, logger).first().unwrap_or_default()
+  }
+
+  fn clone_depth_resolver(&self, project: &Project, logger: &Logger) -> Resolver<u32> {
+    self.resolver(project, self.settings.clone_depth, |t| t.clone_depth, project.clone_depth, logger)
+  }
+
+  /// How many commits a clone of the project gets: the project's own depth, else the one of its tag with the highest
+  /// priority, else the one in the settings. The full history without any.
+  pub fn resolve_clone_depth(&self, project: &Project, logger: &Logger) -> Option<u32> {
+    self.clone_depth_resolver(project, logger).first()
+  }
+
+  fn env_resolver(&self, project: &Project, logger: &Logger) -> Resolver<BTreeMap<String, String>> {
+    self.resolver(project, self.settings.env.clone(), |t| t.env.clone(), project.env.clone(), logger)
+  }
+
+  /// The environment variables for the project's hooks and commands: those of the settings, of its tags by priority
+  /// and its own, later ones overriding earlier ones. A config fw does not trust gets none, they could run code through
+  /// `BASH_ENV` and the like.
+  pub fn resolve_env(&self, project: &Project, logger: &Logger) -> BTreeMap<String, String> {
+    if self.settings.untrusted_source.is_some() {
+      debug!(logger, "Ignoring environment variables of an untrusted config"; "project" => &project.name);
+      return BTreeMap::new();
+    }
+    self.env_resolver(project, logger).merge()
+  }
+
+  fn branch_prefix_resolver(&self, project: &Project, logger: &Logger) -> Resolver<String> {
+    self.resolver(project, None, |t| t.default_branch_prefix.clone(), None, logger)
+  }
+
+  fn branch_pattern_resolver(&self, project: &Project, logger: &Logger) -> Resolver<String> {
+    self.resolver(project, None, |t| t.branch_pattern.clone(), None, logger)
+  }
+
+  /// The branch naming policy of the project: prefix and pattern each from its tag with the highest priority.
+  pub fn resolve_branch_policy(&self, project: &Project, logger: &Logger) -> BranchPolicy {
+    BranchPolicy {
+      prefix: self.branch_prefix_resolver(project, logger).first(),
+      // invalid patterns are rejected when the config is loaded
+      pattern: self
+        .branch_pattern_resolver(project, logger)
+        .first()
+        .and_then(|pattern| Regex::new(&pattern).ok()),
+    }
+  }
+
+  /// Every field the layers resolve for the project, with the value it ends up with and the layers that set it.
+  pub fn explain(&self, project: &Project, logger: &Logger) -> Vec<Explanation> {
+    let env = Explanation::merge("env", self.env_resolver(project, logger));
+    let env = match &self.settings.untrusted_source {
+      Some(source) => env.ignored(format!("ignored, {} is not trusted", source)),
+      None => env,
+    };
+    vec![
+      Explanation::first("workspace", self.workspace_resolver(project, logger), None),
+      Explanation::first(
+        "shell",
+        self.shell_resolver(project, logger).map(|shell| shell.join(" ")),
+        Some(self.settings.get_shell_or_default().join(" ")),
+      ),
+      Explanation::first("hook_timeout", self.hook_timeout_resolver(project, logger).map(|t| t.to_string()), None),
+      Explanation::first("ssh_key", self.ssh_key_resolver(project, logger), None),
+      Explanation::first("lfs", self.lfs_resolver(project, logger).map(|lfs| lfs.to_string()), Some(false.to_string())),
+      Explanation::first("clone_depth", self.clone_depth_resolver(project, logger).map(|d| d.to_string()), None),
+      Explanation::all(
+        "after_clone",
+        self.after_clone_resolver(project, logger),
+        self.resolve_after_clone(logger, project).join(" && "),
+      ),
+      Explanation::all(
+        "after_workon",
+        self.after_workon_resolver(project, logger),
+        self.resolve_after_workon(logger, project).join(" && "),
+      ),
+      Explanation::merge("commands", self.commands_resolver(project, logger)),
+      env,
+      Explanation::first("default_branch_prefix", self.branch_prefix_resolver(project, logger), None),
+      Explanation::first("branch_pattern", self.branch_pattern_resolver(project, logger), None),
+    ]
+  }
+
+  /// One field of the project through all layers: `settings`, `from_tag` of every tag of the project including the
+  /// conditional ones, and `from_project`.
+  pub fn resolver<T, F>(&self, project: &Project, settings: Option<T>, from_tag: F, from_project: Option<T>, logger: &Logger) -> Resolver<T>
+  where
+    F: Fn(&Tag) -> Option<T>,
+  {
+    self.layers(&self.effective_tags(project, logger), settings, from_tag, from_project, logger)
   }
 
   fn tag_priority_or_fallback(&self, name: &str, tag: &Tag, logger: &Logger) -> u8 {
@@ -259,37 +1058,42 @@ conscious choice and set the value."#;
     }
   }
 
-  fn resolve_from_tags<F>(&self, resolver: F, maybe_tags: Option<BTreeSet<String>>, logger: &Logger) -> Vec<String>
+  fn layers<T, F>(&self, tags: &BTreeSet<String>, settings: Option<T>, from_tag: F, from_project: Option<T>, logger: &Logger) -> Resolver<T>
   where
-    F: Fn(&Tag) -> Option<String>,
+    F: Fn(&Tag) -> Option<T>,
   {
-    let tag_logger = logger.new(o!("tags" => format!("{:?}", maybe_tags)));
+    let tag_logger = logger.new(o!("tags" => format!("{:?}", tags)));
     trace!(tag_logger, "Resolving");
-    if let (Some(tags), Some(settings_tags)) = (maybe_tags, self.clone().settings.tags) {
-      let mut resolved_with_priority: Vec<(String, u8)> = tags
+    let mut by_priority: Vec<(&String, &Tag, u8)> = match &self.settings.tags {
+      Some(settings_tags) => tags
         .iter()
-        .flat_map(|t| match settings_tags.get(t) {
+        .filter_map(|t| match settings_tags.get(t) {
           None => {
             warn!(tag_logger, "Ignoring tag since it was not found in the config"; "missing_tag" => t.clone());
             None
           }
-          Some(actual_tag) => resolver(actual_tag).map(|val| (val, self.tag_priority_or_fallback(t, actual_tag, logger))),
+          Some(actual_tag) => Some((t, actual_tag, self.tag_priority_or_fallback(t, actual_tag, logger))),
         })
-        .collect();
-      trace!(logger, "before sort"; "tags" => format!("{:?}", resolved_with_priority));
-      resolved_with_priority.sort_by_key(|resolved_and_priority| resolved_and_priority.1);
-      trace!(logger, "after sort"; "tags" => format!("{:?}", resolved_with_priority));
-      resolved_with_priority.into_iter().map(|r| r.0).collect()
-    } else {
-      vec![]
-    }
+        .collect(),
+      None => vec![],
+    };
+    // stable, so tags of the same priority stay in the order of their names
+    by_priority.sort_by_key(|(_, _, priority)| *priority);
+    by_priority
+      .into_iter()
+      .fold(Resolver::new(settings), |resolver, (name, tag, priority)| {
+        resolver.tag(name, priority, from_tag(tag))
+      })
+      .project(from_project)
   }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
-  use maplit::btreeset;
+  use crate::config::autotag::AutotagRule;
+  use crate::testing::temp_dir;
+  use maplit::{btreemap, btreeset};
   use spectral::prelude::*;
 
   #[test]
@@ -363,92 +1167,502 @@ mod tests {
     assert_that(&resolved).is_equal_to(vec!["clone1".to_string(), "clone override in project".to_owned()]);
   }
 
+  #[test]
+  fn test_hook_placeholders() {
+    let mut config = a_config();
+    let logger = a_logger();
+    let tags = config.settings.tags.as_mut().unwrap();
+    tags.get_mut("tag1").unwrap().after_workon = Some("echo {{project.name}} in {{project.path}}".to_owned());
+
+    let resolved = config.resolve_after_workon(&logger, config.projects.get("test1").unwrap());
+    assert_that(&resolved).is_equal_to(vec!["echo test1 in /test/test1".to_owned(), "workon2".to_owned()]);
+
+    config.projects.get_mut("test1").unwrap().override_path = Some("/test/it's $(here)".to_owned());
+    let resolved = config.resolve_after_workon(&logger, config.projects.get("test1").unwrap());
+    assert_that(&resolved[0]).is_equal_to("echo test1 in '/test/it'\\''s $(here)'".to_owned());
+  }
+
+  #[test]
+  fn test_read_legacy_json() {
+    let temp = temp_dir();
+    let path = temp.path().join("fw.json");
+    fs::write(
+      &path,
+      r#"{"projects": {"fw": {"git": "git@github.com:brocode/fw.git", "tags": ["rust"]}},
+          "settings": {"workspace": "~/workspace", "default_tags": ["rust"], "tags": {"rust": {"after_workon": "cargo check", "priority": 10}}}}"#,
+    )
+    .unwrap();
+    let config = read_legacy_json(&path, &a_logger()).unwrap();
+
+    let fw = config.projects.get("fw").unwrap();
+    assert_that(&fw.name).is_equal_to("fw".to_owned());
+    assert_that(&fw.project_config_path).is_equal_to("default".to_owned());
+    assert_that(&config.settings.workspace).is_equal_to("~/workspace".to_owned());
+    assert_that(&config.resolve_after_workon(&a_logger(), fw)).is_equal_to(vec!["cargo check".to_owned()]);
+    assert_that(&config.settings.default_tags).is_equal_to(Some(btreeset!["rust".to_owned()]));
+  }
+
+  #[test]
+  fn test_apply_profile() {
+    let mut config = a_config();
+    let logger = a_logger();
+    config.settings.profiles = Some(btreemap![
+      "work".to_owned() => profile::Profile {
+        workspace: Some("/work".to_owned()),
+        default_tags: Some(btreeset!["tag3".to_owned()]),
+        tags: Some(btreeset!["tag3".to_owned()]),
+        projects: Some(btreeset!["test1".to_owned()]),
+      },
+    ]);
+    assert_that(&config.clone().apply_profile("oss", &logger).is_err()).is_true();
+
+    config.apply_profile("work", &logger).unwrap();
+    assert_that(&config.projects.keys().cloned().collect::<Vec<String>>()).is_equal_to(vec!["test1".to_owned(), "test5".to_owned()]);
+    assert_that(&config.all_projects().len()).is_equal_to(5);
+    assert_that(&config.all_projects().contains_key("test2")).is_true();
+    assert_that(&config.settings.workspace).is_equal_to("/work".to_owned());
+    assert_that(&config.settings.tags_for_new_project("git@github.com:acme/api.git")).is_equal_to(Some(btreeset!["tag3".to_owned()]));
+  }
+
+  #[test]
+  fn test_resolve_command() {
+    let mut config = a_config();
+    let logger = a_logger();
+    let tags = config.settings.tags.as_mut().unwrap();
+    tags.get_mut("tag3").unwrap().commands = Some(btreemap!["test".to_owned() => "test from tag3".to_owned()]);
+    tags.get_mut("tag4").unwrap().commands = Some(btreemap!["test".to_owned() => "test from tag4".to_owned()]);
+    config.settings.commands = Some(btreemap![
+      "test".to_owned() => "test from settings".to_owned(),
+      "lint".to_owned() => "lint from settings".to_owned(),
+    ]);
+    config.projects.get_mut("test1").unwrap().commands = Some(btreemap!["test".to_owned() => "test from project".to_owned()]);
+
+    let test5 = config.projects.get("test5").unwrap();
+    assert_that(&config.resolve_command("test", test5, &logger)).is_equal_to(Some("test from tag3".to_owned()));
+    assert_that(&config.resolve_command("lint", test5, &logger)).is_equal_to(Some("lint from settings".to_owned()));
+    assert_that(&config.resolve_command("build", test5, &logger)).is_none();
+    let test1 = config.projects.get("test1").unwrap();
+    assert_that(&config.resolve_command("test", test1, &logger)).is_equal_to(Some("test from project".to_owned()));
+  }
+
+  #[test]
+  fn test_resolve_shell() {
+    let mut config = a_config();
+    let logger = a_logger();
+    let tags = config.settings.tags.as_mut().unwrap();
+    tags.get_mut("tag3").unwrap().shell = Some(vec!["pwsh".to_owned(), "-Command".to_owned()]);
+    tags.get_mut("tag4").unwrap().shell = Some(vec!["bash".to_owned(), "-c".to_owned()]);
+    config.settings.shell = Some(vec!["zsh".to_owned(), "-c".to_owned()]);
+    config.projects.get_mut("test1").unwrap().shell = Some(vec!["fish".to_owned(), "-c".to_owned()]);
+
+    assert_that(&config.resolve_shell(config.projects.get("test5").unwrap(), &logger)).is_equal_to(vec!["pwsh".to_owned(), "-Command".to_owned()]);
+    assert_that(&config.resolve_shell(config.projects.get("test1").unwrap(), &logger)).is_equal_to(vec!["fish".to_owned(), "-c".to_owned()]);
+    assert_that(&config.resolve_shell(config.projects.get("test2").unwrap(), &logger)).is_equal_to(vec!["zsh".to_owned(), "-c".to_owned()]);
+  }
+
+  #[test]
+  fn test_resolve_ssh_key() {
+    let mut config = a_config();
+    let logger = a_logger();
+    assert_that(&config.resolve_ssh_key(config.projects.get("test1").unwrap(), &logger)).is_none();
+
+    config.settings.tags.as_mut().unwrap().get_mut("tag3").unwrap().ssh_key = Some("/keys/id_work".to_owned());
+    config.settings.ssh_key = Some("/keys/id_personal".to_owned());
+    config.projects.get_mut("test1").unwrap().ssh_key = Some("/keys/id_test1".to_owned());
+
+    assert_that(&config.resolve_ssh_key(config.projects.get("test5").unwrap(), &logger)).is_equal_to(Some(PathBuf::from("/keys/id_work")));
+    assert_that(&config.resolve_ssh_key(config.projects.get("test1").unwrap(), &logger)).is_equal_to(Some(PathBuf::from("/keys/id_test1")));
+    assert_that(&config.resolve_ssh_key(config.projects.get("test2").unwrap(), &logger)).is_equal_to(Some(PathBuf::from("/keys/id_personal")));
+  }
+
+  #[test]
+  fn test_resolve_lfs() {
+    let mut config = a_config();
+    let logger = a_logger();
+    assert_that(&config.resolve_lfs(config.projects.get("test1").unwrap(), &logger)).is_false();
+
+    config.settings.tags.as_mut().unwrap().get_mut("tag3").unwrap().lfs = Some(true);
+    config.settings.lfs = Some(false);
+    config.projects.get_mut("test1").unwrap().lfs = Some(true);
+
+    assert_that(&config.resolve_lfs(config.projects.get("test5").unwrap(), &logger)).is_true();
+    assert_that(&config.resolve_lfs(config.projects.get("test1").unwrap(), &logger)).is_true();
+    assert_that(&config.resolve_lfs(config.projects.get("test2").unwrap(), &logger)).is_false();
+  }
+
+  #[test]
+  fn test_workspace_relative_override_path() {
+    let mut config = a_config();
+    let logger = a_logger();
+    config.settings.tags.as_mut().unwrap().get_mut("tag1").unwrap().workspace = Some("/one".to_owned());
+    let project = config.projects.get_mut("test1").unwrap();
+    project.tags = Some(btreeset!["tag1".to_owned()]);
+    project.override_path = Some("./forks/test1".to_owned());
+    assert_that(&config.actual_path_to_project(&config.projects["test1"], &logger)).is_equal_to(PathBuf::from("/one/forks/test1"));
+    assert_that(&config.check_sanity(&logger)).is_ok();
+
+    config.projects.get_mut("test1").unwrap().override_path = Some("../elsewhere".to_owned());
+    assert_that(&config.actual_path_to_project(&config.projects["test1"], &logger)).is_equal_to(PathBuf::from("/one/../elsewhere"));
+
+    config.projects.get_mut("test1").unwrap().override_path = Some("/abs/test1".to_owned());
+    assert_that(&config.actual_path_to_project(&config.projects["test1"], &logger)).is_equal_to(PathBuf::from("/abs/test1"));
+
+    // still loads, fw doctor warns about it
+    config.projects.get_mut("test1").unwrap().override_path = Some("forks/test1".to_owned());
+    assert_that(&config.check_sanity(&logger)).is_ok();
+    assert_that(&config.projects["test1"].has_cwd_relative_override()).is_true();
+  }
+
+  #[test]
+  fn test_tag_groups_are_exclusive() {
+    let mut config = a_config();
+    let logger = a_logger();
+    config.settings.tag_groups = Some(btreemap!["context".to_owned() => btreeset!["tag1".to_owned(), "tag2".to_owned()]]);
+    config.projects.get_mut("test1").unwrap().tags = Some(btreeset!["tag1".to_owned(), "other".to_owned()]);
+    assert_that(&config.check_sanity(&logger)).is_ok();
+
+    config.projects.get_mut("test1").unwrap().tags = Some(btreeset!["tag1".to_owned(), "tag2".to_owned()]);
+    assert_that(&config.check_sanity(&logger)).is_ok();
+    assert_that(&config.settings.tag_group_conflicts(&btreeset!["tag1".to_owned(), "tag2".to_owned()]).len()).is_equal_to(1);
+  }
+
+  #[test]
+  fn test_added_tags_respect_tag_groups() {
+    let mut config = a_config();
+    config.settings.tag_groups = Some(btreemap!["context".to_owned() => btreeset!["tag1".to_owned(), "tag2".to_owned()]]);
+    let own = btreeset!["tag1".to_owned()];
+    let tags = config.settings.with_tags(&own, vec!["tag2".to_owned(), "other".to_owned()]);
+    assert_that(&tags).is_equal_to(btreeset!["tag1".to_owned(), "other".to_owned()]);
+
+    config.settings.default_tags = Some(btreeset!["tag2".to_owned()]);
+    config.settings.autotag = Some(vec![AutotagRule {
+      tag: "tag1".to_owned(),
+      ..AutotagRule::example()
+    }]);
+    assert_that(&config.settings.tags_for_new_project("git@github.com:acme/api.git")).is_equal_to(Some(btreeset!["tag2".to_owned()]));
+  }
+
+  #[test]
+  fn test_branch_policy_from_tags() {
+    let mut config = a_config();
+    let logger = a_logger();
+    let tag1 = config.settings.tags.as_mut().unwrap().get_mut("tag1").unwrap();
+    tag1.default_branch_prefix = Some("feature/".to_owned());
+    tag1.branch_pattern = Some("^feature/[a-z-]+$".to_owned());
+    config.projects.get_mut("test1").unwrap().tags = Some(btreeset!["tag1".to_owned()]);
+    let policy = config.resolve_branch_policy(&config.projects["test1"], &logger);
+    assert_that(&policy.apply("login-form").ok()).is_equal_to(Some("feature/login-form".to_owned()));
+    assert_that(&policy.apply("feature/login-form").ok()).is_equal_to(Some("feature/login-form".to_owned()));
+    assert_that(&policy.apply("Login_Form")).is_err();
+    assert_that(&policy.allows("hotfix")).is_false();
+
+    // the example tag allows fix/ branches next to its feature/ prefix
+    config.settings.tags.as_mut().unwrap().insert("tag1".to_owned(), Tag::example());
+    let policy = config.resolve_branch_policy(&config.projects["test1"], &logger);
+    assert_that(&policy.apply("fix/crash").ok()).is_equal_to(Some("fix/crash".to_owned()));
+    assert_that(&policy.apply("login-form").ok()).is_equal_to(Some("feature/login-form".to_owned()));
+    assert_that(&policy.allows("fix/crash")).is_true();
+
+    config.settings.tags.as_mut().unwrap().get_mut("tag1").unwrap().branch_pattern = Some("(".to_owned());
+    assert_that(&config.check_sanity(&logger)).is_err();
+  }
+
+  #[test]
+  fn test_env_and_clone_depth_from_all_layers() {
+    let mut config = a_config();
+    let logger = a_logger();
+    config.settings.clone_depth = Some(50);
+    config.settings.env = Some(btreemap!["A".to_owned() => "settings".to_owned(), "B".to_owned() => "settings".to_owned()]);
+    let tag1 = config.settings.tags.as_mut().unwrap().get_mut("tag1").unwrap();
+    tag1.clone_depth = Some(10);
+    tag1.env = Some(btreemap!["B".to_owned() => "tag".to_owned(), "C".to_owned() => "tag".to_owned()]);
+    let project = config.projects.get_mut("test1").unwrap();
+    project.tags = Some(btreeset!["tag1".to_owned()]);
+    project.env = Some(btreemap!["C".to_owned() => "project".to_owned()]);
+    let project = &config.projects["test1"];
+    assert_that(&config.resolve_clone_depth(project, &logger)).is_equal_to(Some(10));
+    assert_that(&config.resolve_env(project, &logger)).is_equal_to(btreemap![
+      "A".to_owned() => "settings".to_owned(),
+      "B".to_owned() => "tag".to_owned(),
+      "C".to_owned() => "project".to_owned(),
+    ]);
+
+    config.settings.untrusted_source = Some("/tmp/.fw.json".to_owned());
+    assert_that(&config.resolve_env(&config.projects["test1"], &logger).is_empty()).is_true();
+  }
+
+  #[test]
+  fn test_project_defaults_are_the_bottom_layer() {
+    let logger = a_logger();
+    let settings: PersistedSettings = toml::from_str(
+      r#"
+      workspace = "/workspace"
+      default_after_clone = "for new projects"
+
+      [project_defaults]
+      after_clone = "direnv allow"
+      after_workon = "nvm use"
+      override_path = "{workspace}/{forge}/{name}"
+      "#,
+    )
+    .unwrap();
+    let project = Project {
+      after_clone: Some("make".to_owned()),
+      after_workon: None,
+      tags: None,
+      override_path: None,
+      ..Project::example()
+    };
+    let config = assemble(settings, btreemap!["fw".to_owned() => project], BTreeMap::new(), &logger).unwrap();
+    let project = &config.projects["fw"];
+    assert_that(&config.resolve_after_clone(&logger, project)).is_equal_to(vec!["direnv allow".to_owned(), "make".to_owned()]);
+    assert_that(&config.resolve_after_workon(&logger, project)).is_equal_to(vec!["nvm use".to_owned()]);
+    assert_that(&config.settings.default_after_clone).is_equal_to(Some("for new projects".to_owned()));
+    assert_that(&config.checkout_path(project, &logger)).is_equal_to(PathBuf::from("/workspace/github.com/fw"));
+  }
+
+  #[test]
+  fn test_directory_project() {
+    let mut config = a_config();
+    let logger = a_logger();
+    let project: Project = toml::from_str("kind = \"directory\"\ntags = [\"tag1\"]").unwrap();
+    assert_that(&project.is_directory()).is_true();
+    assert_that(&toml::to_string(&project).unwrap().contains("git")).is_false();
+    config.projects.insert(
+      "notes".to_owned(),
+      Project {
+        name: "notes".to_owned(),
+        ..project
+      },
+    );
+    assert_that(&config.check_sanity(&logger)).is_ok();
+
+    config.projects.get_mut("notes").unwrap().kind = None;
+    assert_that(&config.check_sanity(&logger)).is_err();
+  }
+
+  #[test]
+  fn test_subdir_projects_share_a_clone() {
+    let mut config = a_config();
+    let logger = a_logger();
+    config.settings.workspace = "/ws".to_owned();
+    for (name, subdir) in &[("api", "services/api"), ("web", "web")] {
+      let project = Project {
+        name: (*name).to_owned(),
+        git: "git@github.com:acme/monorepo.git".to_owned(),
+        subdir: Some((*subdir).to_owned()),
+        tags: None,
+        ..config.projects["test1"].clone()
+      };
+      config.projects.insert((*name).to_owned(), project);
+    }
+    let api = &config.projects["api"];
+    assert_that(&config.checkout_path(api, &logger)).is_equal_to(PathBuf::from("/ws/monorepo"));
+    assert_that(&config.actual_path_to_project(api, &logger)).is_equal_to(PathBuf::from("/ws/monorepo/services/api"));
+    let selected: Vec<&str> = selector::ProjectSelector::all()
+      .select_repositories(&config)
+      .iter()
+      .map(|p| p.name.as_str())
+      .collect();
+    assert_that(&selected).contains("api");
+    assert_that(&selected).does_not_contain("web");
+    assert_that(&config.check_sanity(&logger)).is_ok();
+
+    config.projects.get_mut("web").unwrap().subdir = Some("../elsewhere".to_owned());
+    assert_that(&config.check_sanity(&logger)).is_err();
+  }
+
   fn a_config() -> Config {
     let project = Project {
       name: "test1".to_owned(),
       git: "irrelevant".to_owned(),
+      kind: None,
       tags: Some(btreeset!["tag1".to_owned(), "tag2".to_owned()]),
       after_clone: None,
       after_workon: None,
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       override_path: None,
+      subdir: None,
       additional_remotes: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
       bare: None,
       project_config_path: "".to_string(),
     };
     let project2 = Project {
       name: "test2".to_owned(),
       git: "irrelevant".to_owned(),
+      kind: None,
       tags: Some(btreeset!["tag1".to_owned(), "tag-does-not-exist".to_owned(),]),
       after_clone: None,
       after_workon: None,
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       override_path: None,
+      subdir: None,
       additional_remotes: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
       bare: None,
       project_config_path: "".to_string(),
     };
     let project3 = Project {
       name: "test3".to_owned(),
       git: "irrelevant".to_owned(),
+      kind: None,
       tags: Some(btreeset!["tag1".to_owned()]),
       after_clone: Some("clone override in project".to_owned()),
       after_workon: Some("workon override in project".to_owned()),
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       override_path: None,
+      subdir: None,
       additional_remotes: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
       bare: None,
       project_config_path: "".to_string(),
     };
     let project4 = Project {
       name: "test4".to_owned(),
       git: "irrelevant".to_owned(),
+      kind: None,
       tags: Some(btreeset!["tag-does-not-exist".to_owned()]),
       after_clone: None,
       after_workon: None,
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       override_path: None,
+      subdir: None,
       additional_remotes: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
       bare: None,
       project_config_path: "".to_string(),
     };
     let project5 = Project {
       name: "test5".to_owned(),
       git: "irrelevant".to_owned(),
+      kind: None,
       tags: Some(btreeset!["tag3".to_owned(), "tag4".to_owned()]),
       after_clone: None,
       after_workon: None,
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       override_path: None,
+      subdir: None,
       additional_remotes: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
       bare: None,
       project_config_path: "".to_string(),
     };
     let tag1 = Tag {
       after_clone: Some("clone1".to_owned()),
       after_workon: Some("workon1".to_owned()),
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       priority: None,
       workspace: None,
       default: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
+      when: None,
+      default_branch_prefix: None,
+      branch_pattern: None,
       tag_config_path: "".to_string(),
     };
     let tag2 = Tag {
       after_clone: Some("clone2".to_owned()),
       after_workon: Some("workon2".to_owned()),
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       priority: None,
       workspace: None,
       default: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
+      when: None,
+      default_branch_prefix: None,
+      branch_pattern: None,
       tag_config_path: "".to_string(),
     };
     let tag3 = Tag {
       after_clone: Some("clone3".to_owned()),
       after_workon: Some("workon3".to_owned()),
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       priority: Some(100),
       workspace: None,
       default: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
+      when: None,
+      default_branch_prefix: None,
+      branch_pattern: None,
       tag_config_path: "".to_string(),
     };
     let tag4 = Tag {
       after_clone: Some("clone4".to_owned()),
       after_workon: Some("workon4".to_owned()),
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       priority: Some(0),
       workspace: None,
       default: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
+      when: None,
+      default_branch_prefix: None,
+      branch_pattern: None,
       tag_config_path: "".to_string(),
     };
     let mut projects: BTreeMap<String, Project> = BTreeMap::new();
@@ -466,13 +1680,143 @@ mod tests {
       workspace: "/test".to_owned(),
       default_after_workon: None,
       default_after_clone: None,
+      after_clone: None,
+      after_workon: None,
       default_tags: None,
       shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       tags: Some(tags),
       github_token: None,
+      gitea_token: None,
+      azure_token: None,
       gitlab: None,
+      log_file: None,
+      readonly: None,
+      discover_config: None,
+      notify: None,
+      notify_command: None,
+      retries: None,
+      retry_backoff: None,
+      http_proxy: None,
+      https_proxy: None,
+      no_proxy: None,
+      ca_bundle: None,
+      import_naming: None,
+      time_tracking: None,
+      session_timeout: None,
+      layout: None,
+      prefer: None,
+      reference_repo: None,
+      max_network_operations: None,
+      bandwidth_limit: None,
+      hook_nice: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
+      autotag: None,
+      forge_tags: None,
+      tag_groups: None,
+      profiles: None,
+      shared_config: None,
+      untrusted_source: None,
+    };
+    Config {
+      projects,
+      settings,
+      outside_profile: BTreeMap::new(),
+    }
+  }
+
+  #[test]
+  fn test_workspace_conflicts() {
+    let mut config = a_config();
+    let logger = a_logger();
+    let tags = config.settings.tags.as_mut().unwrap();
+    tags.get_mut("tag1").unwrap().workspace = Some("/one".to_owned());
+    tags.get_mut("tag2").unwrap().workspace = Some("/two".to_owned());
+    tags.get_mut("tag3").unwrap().workspace = Some("/three".to_owned());
+    tags.get_mut("tag4").unwrap().workspace = Some("/four".to_owned());
+
+    let tied = config.projects.get("test1").unwrap();
+    let conflict = config.workspace_conflict(tied, &logger).unwrap();
+    assert_that(&conflict.tie).is_true();
+    assert_that(&conflict.tied_tags()).is_equal_to(vec!["tag1".to_owned(), "tag2".to_owned()]);
+    assert_that(&config.actual_path_to_project(tied, &logger)).is_equal_to(PathBuf::from("/two/test1"));
+
+    let prioritized = config.projects.get("test5").unwrap();
+    let conflict = config.workspace_conflict(prioritized, &logger).unwrap();
+    assert_that(&conflict.tie).is_false();
+    assert_that(&conflict.chosen.tag).is_equal_to("tag3".to_owned());
+    assert_that(&config.actual_path_to_project(prioritized, &logger)).is_equal_to(PathBuf::from("/three/test5"));
+
+    assert_that(&config.workspace_conflict(config.projects.get("test3").unwrap(), &logger)).is_none();
+  }
+
+  #[test]
+  fn test_non_interactive_fails_only_for_the_tied_project() {
+    let mut config = a_config();
+    let logger = a_logger();
+    let tags = config.settings.tags.as_mut().unwrap();
+    tags.get_mut("tag1").unwrap().workspace = Some("/one".to_owned());
+    tags.get_mut("tag2").unwrap().workspace = Some("/two".to_owned());
+
+    output::with_non_interactive(true, || {
+      assert_that(&config.check_sanity(&logger)).is_ok();
+      assert_that(&config.check_workspace_choice(&config.projects["test1"], &logger)).is_err();
+      assert_that(&config.check_workspace_choice(&config.projects["test3"], &logger)).is_ok();
+      assert_that(&config.find_project("test1", &logger)).is_err();
+      assert_that(&config.find_project("test3", &logger).ok().map(|p| p.name.clone())).is_equal_to(Some("test3".to_owned()));
+    });
+    output::with_non_interactive(false, || {
+      assert_that(&config.find_project("test1", &logger)).is_ok();
+    });
+  }
+
+  #[test]
+  fn test_non_interactive_find_project_is_exact() {
+    let config = a_config();
+    let logger = a_logger();
+    output::with_non_interactive(false, || {
+      assert_that(&config.find_project("TEST2", &logger).ok().map(|p| p.name.clone())).is_equal_to(Some("test2".to_owned()));
+    });
+    output::with_non_interactive(true, || {
+      assert_that(&config.find_project("test2", &logger).ok().map(|p| p.name.clone())).is_equal_to(Some("test2".to_owned()));
+      match config.find_project("TEST2", &logger) {
+        Err(AppError::UserError(message)) => assert_that(&message).contains("did you mean test2?"),
+        other => panic!("expected an error, got {:?}", other.map(|p| p.name.clone())),
+      }
+    });
+  }
+
+  #[test]
+  fn test_conditional_tags() {
+    let temp = temp_dir();
+    let workspace = temp.path();
+    fs::create_dir_all(workspace.join("test1")).unwrap();
+    fs::write(workspace.join("test1").join("Cargo.toml"), "").unwrap();
+    let mut config = a_config();
+    config.settings.workspace = workspace.to_string_lossy().to_string();
+    let rust = Tag {
+      after_workon: Some("cargo check".to_owned()),
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      commands: None,
+      when: Some(TagCondition {
+        file_exists: None,
+        project_type: Some("rust".to_owned()),
+      }),
+      ..config.settings.tags.as_ref().unwrap()["tag1"].clone()
     };
-    Config { projects, settings }
+    config.settings.tags.as_mut().unwrap().insert("rust".to_owned(), rust);
+    let logger = a_logger();
+
+    let test1 = config.projects.get("test1").unwrap();
+    assert_that(&config.effective_tags(test1, &logger).contains("rust")).is_true();
+    assert_that(&config.resolve_after_workon(&logger, test1)).is_equal_to(vec!["cargo check".to_string(), "workon1".to_string(), "workon2".to_string()]);
+    assert_that(&config.effective_tags(config.projects.get("test2").unwrap(), &logger).contains("rust")).is_false();
   }
 
   fn a_logger() -> Logger {
@@ -0,0 +1,115 @@
+use super::project::Project;
+use crate::errors::AppError;
+use std::collections::BTreeSet;
+
+/// Groups `projects` into levels so that every project comes after the projects it depends on.
+/// Projects within one level are independent of each other. Dependencies outside of `projects` are ignored.
+pub fn in_dependency_order<'a>(projects: &[&'a Project]) -> Result<Vec<Vec<&'a Project>>, AppError> {
+  let groups = projects.iter().map(|p| vec![*p]).collect();
+  Ok(
+    groups_in_dependency_order(groups)?
+      .into_iter()
+      .map(|level| level.into_iter().flatten().collect())
+      .collect(),
+  )
+}
+
+/// Like [`in_dependency_order`] for groups of projects that are handled together, e.g. those sharing a clone through
+/// `subdir`. A group comes after the groups of everything its projects depend on.
+pub fn groups_in_dependency_order<'a>(groups: Vec<Vec<&'a Project>>) -> Result<Vec<Vec<Vec<&'a Project>>>, AppError> {
+  let selected: BTreeSet<&str> = groups.iter().flatten().map(|p| p.name.as_str()).collect();
+  let mut done: BTreeSet<&str> = BTreeSet::new();
+  let mut levels: Vec<Vec<Vec<&'a Project>>> = Vec::new();
+  let mut remaining = groups;
+
+  while !remaining.is_empty() {
+    let (ready, blocked): (Vec<Vec<&'a Project>>, Vec<Vec<&'a Project>>) = remaining.into_iter().partition(|group| {
+      group
+        .iter()
+        .flat_map(|p| p.dependencies())
+        .all(|d| done.contains(d) || !selected.contains(d) || group.iter().any(|p| p.name == d))
+    });
+    if ready.is_empty() {
+      let names: Vec<&str> = blocked.iter().flatten().map(|p| p.name.as_str()).collect();
+      return Err(AppError::UserError(format!("Dependency cycle between projects: {}", names.join(", "))));
+    }
+    done.extend(ready.iter().flatten().map(|p| p.name.as_str()));
+    levels.push(ready);
+    remaining = blocked;
+  }
+  Ok(levels)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  fn a_project(name: &str, depends_on: &[&str]) -> Project {
+    Project {
+      name: name.to_owned(),
+      git: "irrelevant".to_owned(),
+      kind: None,
+      after_clone: None,
+      after_workon: None,
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
+      override_path: None,
+      subdir: None,
+      bare: None,
+      tags: None,
+      additional_remotes: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: Some(depends_on.iter().map(|d| d.to_string()).collect()),
+      clone_depth: None,
+      env: None,
+      commands: None,
+      project_config_path: "".to_owned(),
+    }
+  }
+
+  fn names(levels: Vec<Vec<&Project>>) -> Vec<Vec<String>> {
+    levels.into_iter().map(|l| l.into_iter().map(|p| p.name.clone()).collect()).collect()
+  }
+
+  #[test]
+  fn test_orders_dependencies_first() {
+    let service = a_project("service", &["lib", "unknown"]);
+    let lib = a_project("lib", &["base"]);
+    let base = a_project("base", &[]);
+    let other = a_project("other", &[]);
+    let levels = in_dependency_order(&[&service, &lib, &base, &other]).unwrap();
+    assert_that(&names(levels)).is_equal_to(vec![
+      vec!["base".to_owned(), "other".to_owned()],
+      vec!["lib".to_owned()],
+      vec!["service".to_owned()],
+    ]);
+  }
+
+  #[test]
+  fn test_orders_groups_after_the_dependencies_of_all_members() {
+    let api = a_project("api", &["proto"]);
+    let web = a_project("web", &[]);
+    let proto = a_project("proto", &[]);
+    let client = a_project("client", &["web"]);
+    let levels = groups_in_dependency_order(vec![vec![&api, &web], vec![&client], vec![&proto]]).unwrap();
+    let levels: Vec<Vec<Vec<String>>> = levels.into_iter().map(names).collect();
+    assert_that(&levels).is_equal_to(vec![
+      vec![vec!["proto".to_owned()]],
+      vec![vec!["api".to_owned(), "web".to_owned()]],
+      vec![vec!["client".to_owned()]],
+    ]);
+  }
+
+  #[test]
+  fn test_detects_cycles() {
+    let a = a_project("a", &["b"]);
+    let b = a_project("b", &["a"]);
+    let c = a_project("c", &[]);
+    assert_that(&in_dependency_order(&[&a, &b, &c]).is_err()).is_true();
+  }
+}
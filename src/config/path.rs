@@ -1,13 +1,16 @@
 use crate::errors::AppError;
-use dirs::config_dir;
+use dirs::{cache_dir, config_dir, data_local_dir};
 use std::env;
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 
 pub struct FwPaths {
   pub settings: PathBuf,
   pub base: PathBuf,
   pub projects: PathBuf,
   pub tags: PathBuf,
+  pub state: PathBuf,
+  pub cache: PathBuf,
 }
 
 impl FwPaths {
@@ -15,6 +18,12 @@ impl FwPaths {
     std::fs::create_dir_all(&self.base).map_err(|e| AppError::RuntimeError(format!("Failed to create fw config base directory. {}", e)))?;
     Ok(())
   }
+
+  pub fn ensure_state_dir_exists(&self, dir: &str) -> Result<PathBuf, AppError> {
+    let path = self.state.join(dir);
+    std::fs::create_dir_all(&path).map_err(|e| AppError::RuntimeError(format!("Failed to create fw state directory '{}'. {}", path.to_string_lossy(), e)))?;
+    Ok(path)
+  }
 }
 
 fn do_expand(path: PathBuf, home_dir: Option<PathBuf>) -> PathBuf {
@@ -33,18 +42,112 @@ pub fn expand_path(path: PathBuf) -> PathBuf {
   }
 }
 
-pub fn fw_path() -> Result<FwPaths, AppError> {
-  let base = env::var("FW_CONFIG_DIR")
+/// `$<var>/fw` or `~/<fallback>/fw` as the XDG base directory specification has it. Windows keeps its known folders.
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+  if cfg!(windows) {
+    return None;
+  }
+  env::var_os(var)
+    .map(PathBuf::from)
+    .filter(|dir| dir.is_absolute())
+    .or_else(|| dirs::home_dir().map(|home| home.join(fallback)))
+    .map(|dir| dir.join("fw"))
+}
+
+/// A directory of an earlier fw version (e.g. `~/Library/Application Support/fw` on macOS or `~/.local/share/fw` for the state)
+/// and the XDG directory that replaces it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+  pub legacy: PathBuf,
+  pub xdg: PathBuf,
+}
+
+impl Relocation {
+  fn new(legacy: Option<PathBuf>, xdg: Option<PathBuf>) -> Option<Relocation> {
+    match (legacy, xdg) {
+      (Some(legacy), Some(xdg)) => Some(Relocation { legacy, xdg }),
+      _ => None,
+    }
+  }
+
+  /// The legacy directory still holds files that belong in the XDG directory.
+  pub fn pending(&self) -> bool {
+    self.legacy != self.xdg && self.legacy.exists() && !self.xdg.exists()
+  }
+
+  /// Until `fw migrate-paths` moved the files, the legacy directory stays in use.
+  fn resolve(&self) -> PathBuf {
+    if self.pending() {
+      self.legacy.clone()
+    } else {
+      self.xdg.clone()
+    }
+  }
+}
+
+/// The config, state and cache directories that moved to their XDG locations, unless overridden by the environment.
+pub fn relocations() -> Vec<Relocation> {
+  let mut relocations = vec![];
+  if env::var_os("FW_CONFIG_DIR").is_none() && config_override().is_none() {
+    relocations.extend(Relocation::new(config_dir().map(|c| c.join("fw")), xdg_dir("XDG_CONFIG_HOME", ".config")));
+  }
+  if env::var_os("FW_STATE_DIR").is_none() {
+    relocations.extend(Relocation::new(
+      data_local_dir().map(|d| d.join("fw")),
+      xdg_dir("XDG_STATE_HOME", ".local/state"),
+    ));
+  }
+  if env::var_os("FW_CACHE_DIR").is_none() {
+    relocations.extend(Relocation::new(cache_dir().map(|c| c.join("fw")), xdg_dir("XDG_CACHE_HOME", ".cache")));
+  }
+  relocations
+}
+
+fn resolve_dir(override_var: &str, legacy: Option<PathBuf>, xdg: Option<PathBuf>) -> Option<PathBuf> {
+  env::var(override_var)
     .map(PathBuf::from)
     .ok()
     .map(expand_path)
-    .or_else(|| {
-      config_dir().map(|mut c| {
-        c.push("fw");
-        c
-      })
-    })
-    .ok_or(AppError::InternalError("Cannot resolve fw config dir"))?;
+    .or_else(|| Relocation::new(legacy.clone(), xdg.clone()).map(|r| r.resolve()))
+    .or(xdg)
+    .or(legacy)
+}
+
+/// The single file configuration of fw 1.x, read as long as there is no settings file.
+pub fn legacy_json() -> Option<PathBuf> {
+  dirs::home_dir().map(|home| home.join(".fw.json")).filter(|path| path.exists())
+}
+
+const LOCAL_CONFIG: &str = ".fw.json";
+
+/// The config given by `FW_CONFIG` (which `fw --config` sets): a settings file with the projects and tags next to it,
+/// or a single JSON file.
+pub fn config_override() -> Option<PathBuf> {
+  env::var_os("FW_CONFIG").filter(|path| !path.is_empty()).map(PathBuf::from).map(expand_path)
+}
+
+/// A single JSON file holds settings, projects and tags like the `~/.fw.json` of fw 1.x.
+pub fn is_single_file(path: &Path) -> bool {
+  path.extension() == Some(OsStr::new("json"))
+}
+
+/// The nearest `.fw.json` in `dir` or above. The one in the home directory is the legacy configuration, not a local one.
+pub fn discover_local_config(dir: &Path) -> Option<PathBuf> {
+  let home_config = dirs::home_dir().map(|home| home.join(LOCAL_CONFIG));
+  dir
+    .ancestors()
+    .map(|ancestor| ancestor.join(LOCAL_CONFIG))
+    .filter(|path| Some(path) != home_config.as_ref())
+    .find(|path| path.is_file())
+}
+
+pub fn fw_path() -> Result<FwPaths, AppError> {
+  let settings_override = config_override().filter(|path| !is_single_file(path));
+  let base = match &settings_override {
+    Some(settings) => env::current_dir()?.join(settings.parent().unwrap_or_else(|| Path::new(""))),
+    None => resolve_dir("FW_CONFIG_DIR", config_dir().map(|c| c.join("fw")), xdg_dir("XDG_CONFIG_HOME", ".config"))
+      .ok_or(AppError::InternalError("Cannot resolve fw config dir"))?,
+  };
 
   let mut settings = base.clone();
 
@@ -55,6 +158,14 @@ pub fn fw_path() -> Result<FwPaths, AppError> {
     .replace("/", "");
 
   settings.push(format!("{}settings.toml", env));
+  if let Some(settings_override) = settings_override {
+    settings = base.join(settings_override.file_name().ok_or(AppError::InternalError("FW_CONFIG has no file name"))?);
+  } else if !settings.exists() {
+    // a YAML settings file takes over if there is no TOML one
+    if let Some(yaml_settings) = ["yaml", "yml"].iter().map(|e| settings.with_extension(e)).find(|p| p.exists()) {
+      settings = yaml_settings;
+    }
+  }
 
   let mut projects = base.clone();
   projects.push("projects");
@@ -62,17 +173,30 @@ pub fn fw_path() -> Result<FwPaths, AppError> {
   let mut tags = base.clone();
   tags.push("tags");
 
+  let state = resolve_dir(
+    "FW_STATE_DIR",
+    data_local_dir().map(|d| d.join("fw")),
+    xdg_dir("XDG_STATE_HOME", ".local/state"),
+  )
+  .ok_or(AppError::InternalError("Cannot resolve fw state dir"))?;
+
+  let cache = resolve_dir("FW_CACHE_DIR", cache_dir().map(|c| c.join("fw")), xdg_dir("XDG_CACHE_HOME", ".cache"))
+    .ok_or(AppError::InternalError("Cannot resolve fw cache dir"))?;
+
   Ok(FwPaths {
     settings,
     base,
     projects,
     tags,
+    state,
+    cache,
   })
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::testing::temp_dir;
   use spectral::prelude::*;
 
   #[test]
@@ -80,6 +204,32 @@ mod tests {
     let path = PathBuf::from("/foo/bar");
     assert_that(&expand_path(path.clone())).is_equal_to(&path);
   }
+  #[test]
+  fn test_relocation_keeps_legacy_dir_until_moved() {
+    let temp = temp_dir();
+    let root = temp.path();
+    let relocation = Relocation {
+      legacy: root.join("share").join("fw"),
+      xdg: root.join("state").join("fw"),
+    };
+    assert_that(&relocation.resolve()).is_equal_to(&relocation.xdg);
+    std::fs::create_dir_all(&relocation.legacy).unwrap();
+    assert_that(&relocation.resolve()).is_equal_to(&relocation.legacy);
+    std::fs::create_dir_all(&relocation.xdg).unwrap();
+    assert_that(&relocation.resolve()).is_equal_to(&relocation.xdg);
+  }
+
+  #[test]
+  fn test_discover_local_config() {
+    let temp = temp_dir();
+    let root = temp.path();
+    let nested = root.join("team").join("src").join("deep");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(root.join("team").join(LOCAL_CONFIG), "{}").unwrap();
+    assert_that(&discover_local_config(&nested)).is_equal_to(Some(root.join("team").join(LOCAL_CONFIG)));
+    assert_that(&discover_local_config(&root.join("team"))).is_equal_to(Some(root.join("team").join(LOCAL_CONFIG)));
+  }
+
   #[test]
   fn test_do_expand_path() {
     let path = PathBuf::from("~/foo/bar");
@@ -0,0 +1,64 @@
+use maplit::btreeset;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::env;
+
+/// A named view on the configuration, e.g. `work` or `oss`, chosen with `fw --profile` or `FW_PROFILE`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Profile {
+  /// The workspace of the profile, instead of the one in the settings.
+  pub workspace: Option<String>,
+  /// Tags of projects added while the profile is active, instead of the tags with `default = true`.
+  pub default_tags: Option<BTreeSet<String>>,
+  /// Only projects with one of these tags belong to the profile.
+  pub tags: Option<BTreeSet<String>>,
+  /// Projects that belong to the profile regardless of their tags.
+  pub projects: Option<BTreeSet<String>>,
+}
+
+impl Profile {
+  pub fn example() -> Profile {
+    Profile {
+      workspace: Some("~/work".to_owned()),
+      default_tags: Some(btreeset!["work".to_owned()]),
+      tags: Some(btreeset!["work".to_owned()]),
+      projects: Some(btreeset!["dotfiles".to_owned()]),
+    }
+  }
+
+  /// Without `tags` and `projects` every project belongs to the profile.
+  pub fn includes(&self, project_name: &str, project_tags: &BTreeSet<String>) -> bool {
+    if self.tags.is_none() && self.projects.is_none() {
+      return true;
+    }
+    self.projects.iter().flatten().any(|name| name == project_name) || self.tags.iter().flatten().any(|tag| project_tags.contains(tag))
+  }
+}
+
+/// The profile chosen with `FW_PROFILE`, which `fw --profile` sets.
+pub fn active() -> Option<String> {
+  env::var("FW_PROFILE").ok().filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_includes() {
+    let profile = Profile::example();
+    assert_that(&profile.includes("api", &btreeset!["work".to_owned(), "rust".to_owned()])).is_true();
+    assert_that(&profile.includes("dotfiles", &BTreeSet::new())).is_true();
+    assert_that(&profile.includes("fw", &btreeset!["rust".to_owned()])).is_false();
+
+    let everything = Profile {
+      workspace: Some("~/oss".to_owned()),
+      default_tags: None,
+      tags: None,
+      projects: None,
+    };
+    assert_that(&everything.includes("fw", &BTreeSet::new())).is_true();
+  }
+}
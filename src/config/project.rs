@@ -1,24 +1,70 @@
-use maplit::btreeset;
+use crate::git_url::ParsedUrl;
+use maplit::{btreemap, btreeset};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Remote {
   pub name: String,
   pub git: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// What a project is checked out as.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectKind {
+  /// A git repository, the default.
+  Git,
+  /// A plain directory.
+  Directory,
+}
+
+/// A single repository managed by fw. The name is the file name of the project config.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Project {
   #[serde(skip)]
   pub name: String,
 
+  /// Empty for directory projects.
+  #[serde(default, skip_serializing_if = "String::is_empty")]
   pub git: String,
+  /// `directory` for a plain directory without git, like a notes folder or a mounted share. It has no `git` URL and
+  /// sync and the other git commands skip it.
+  pub kind: Option<ProjectKind>,
   pub after_clone: Option<String>,
   pub after_workon: Option<String>,
+  /// Program and arguments that run the hooks and commands of this project, overriding those of the tags and the settings.
+  pub shell: Option<Vec<String>>,
+  /// Seconds after which the after_clone and after_workon hooks are killed, overriding the timeout of the tags and the settings.
+  pub hook_timeout: Option<u64>,
+  /// Private key for cloning and fetching over SSH, overriding the key of the tags and the settings.
+  pub ssh_key: Option<String>,
+  /// Fetch the git LFS objects of the project, overriding the tags and the settings.
+  pub lfs: Option<bool>,
+  /// Where the project is checked out instead of `<workspace>/<name>`. Paths starting with `./` or `../` are relative to the workspace.
   pub override_path: Option<String>,
+  /// Directory inside the repository that `workon` and the commands of this project run in, like `services/api`.
+  /// Projects with a `subdir` share one clone, named after the repository, and `override_path` moves that clone.
+  pub subdir: Option<String>,
   pub bare: Option<bool>,
   pub tags: Option<BTreeSet<String>>,
+  /// Branch, tag or commit that is checked out after cloning instead of the default branch.
+  pub pin: Option<String>,
+  /// Archived projects are not synced, listed or selected by other bulk commands, see `fw archive`.
+  pub archived: Option<bool>,
+  /// The bundle `fw archive --bundle` kept the checkout in, `fw unarchive` restores it from there.
+  pub archive_bundle: Option<String>,
+  /// Projects that have to be synchronized before this one.
+  pub depends_on: Option<Vec<String>>,
+  /// Clone with only that many commits of history, overriding the tags and the settings.
+  pub clone_depth: Option<u32>,
+  /// Commands for `fw run`, overriding those of the tags and the settings.
+  pub commands: Option<BTreeMap<String, String>>,
+  /// Environment variables for the hooks and commands of this project, overriding those of the tags and the settings.
+  pub env: Option<BTreeMap<String, String>>,
+  // TOML writes arrays of tables last, keep it after all plain values
   pub additional_remotes: Option<Vec<Remote>>,
 
   #[serde(skip)]
@@ -33,13 +79,60 @@ impl Project {
       tags: Some(btreeset!["rust".to_owned(), "brocode".to_owned()]),
       after_clone: Some("echo BROCODE!!".to_string()),
       after_workon: Some("echo workon fw".to_string()),
+      shell: Some(vec!["bash".to_owned(), "-c".to_owned()]),
+      hook_timeout: Some(600),
+      ssh_key: Some("~/.ssh/id_work".to_owned()),
+      lfs: None,
       override_path: Some("/some/fancy/path/to/fw".to_string()),
+      subdir: None,
       additional_remotes: Some(vec![Remote {
         name: "upstream".to_string(),
         git: "git@...".to_string(),
       }]),
       bare: Some(false),
+      pin: Some("v2.6.0".to_string()),
+      archived: Some(false),
+      archive_bundle: None,
+      depends_on: Some(vec!["brocode-commons".to_string()]),
+      clone_depth: None,
+      commands: Some(btreemap!["test".to_owned() => "cargo test --all-features".to_owned()]),
+      env: Some(btreemap!["RUST_BACKTRACE".to_owned() => "1".to_owned()]),
+      kind: None,
       project_config_path: "".to_string(), // ignored
     }
   }
+
+  pub fn is_directory(&self) -> bool {
+    self.kind == Some(ProjectKind::Directory)
+  }
+
+  pub fn dependencies(&self) -> impl Iterator<Item = &str> {
+    self.depends_on.iter().flatten().map(String::as_str)
+  }
+
+  /// The name of the directory the project is cloned into: its own name, or that of the repository for projects with
+  /// a `subdir`.
+  pub fn checkout_name(&self) -> &str {
+    match self.subdir {
+      Some(_) => ParsedUrl::parse(&self.git).map(|url| url.repo()).unwrap_or(&self.name),
+      None => &self.name,
+    }
+  }
+
+  /// `override_path` below the workspace, for paths that start with `./` or `../`. The leading `./` is dropped.
+  /// An `override_path` like `forks/fw`, which is relative to the directory fw runs in.
+  pub fn has_cwd_relative_override(&self) -> bool {
+    match &self.override_path {
+      Some(path) => self.workspace_override().is_none() && !path.starts_with('~') && Path::new(path).is_relative(),
+      None => false,
+    }
+  }
+
+  pub fn workspace_override(&self) -> Option<PathBuf> {
+    let path = Path::new(self.override_path.as_ref()?);
+    match path.components().next() {
+      Some(Component::CurDir) | Some(Component::ParentDir) => Some(path.components().filter(|c| *c != Component::CurDir).collect()),
+      _ => None,
+    }
+  }
 }
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Where a value of a project setting comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layer {
+  Settings,
+  Tag { name: String, priority: u8 },
+  Project,
+}
+
+/// A value that one layer sets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contribution<T> {
+  pub layer: Layer,
+  pub value: T,
+}
+
+/// The values the layers set for one field of a project: the settings at the bottom, the tags of the project by
+/// priority above them and the project itself on top. How they combine is up to the field: `first` takes the topmost
+/// one, `join` all of them and `merge` joins maps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolver<T> {
+  contributions: Vec<Contribution<T>>,
+}
+
+impl<T> Resolver<T> {
+  pub fn new(settings: Option<T>) -> Resolver<T> {
+    Resolver { contributions: Vec::new() }.with(Layer::Settings, settings)
+  }
+
+  /// Adds the value of a tag, tags have to come in ascending priority.
+  pub fn tag(self, name: &str, priority: u8, value: Option<T>) -> Resolver<T> {
+    self.with(
+      Layer::Tag {
+        name: name.to_owned(),
+        priority,
+      },
+      value,
+    )
+  }
+
+  pub fn project(self, value: Option<T>) -> Resolver<T> {
+    self.with(Layer::Project, value)
+  }
+
+  fn with(mut self, layer: Layer, value: Option<T>) -> Resolver<T> {
+    if let Some(value) = value {
+      self.contributions.push(Contribution { layer, value });
+    }
+    self
+  }
+
+  /// The layers that set a value, from the bottom up.
+  pub fn contributions(&self) -> &[Contribution<T>] {
+    &self.contributions
+  }
+
+  pub fn map<U, F>(self, f: F) -> Resolver<U>
+  where
+    F: Fn(T) -> U,
+  {
+    Resolver {
+      contributions: self
+        .contributions
+        .into_iter()
+        .map(|c| Contribution {
+          layer: c.layer,
+          value: f(c.value),
+        })
+        .collect(),
+    }
+  }
+
+  /// The value of the topmost layer that sets one.
+  pub fn first(mut self) -> Option<T> {
+    self.contributions.pop().map(|c| c.value)
+  }
+
+  /// The values of all layers, from the bottom up.
+  pub fn join(self) -> Vec<T> {
+    self.contributions.into_iter().map(|c| c.value).collect()
+  }
+}
+
+impl<K: Ord, V> Resolver<BTreeMap<K, V>> {
+  /// The maps of all layers in one, upper layers win for keys that several set.
+  pub fn merge(self) -> BTreeMap<K, V> {
+    let mut merged = BTreeMap::new();
+    for map in self.join() {
+      merged.extend(map);
+    }
+    merged
+  }
+}
+
+impl fmt::Display for Layer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Layer::Settings => write!(f, "settings"),
+      Layer::Tag { name, priority } => write!(f, "tag {} (priority {})", name, priority),
+      Layer::Project => write!(f, "project"),
+    }
+  }
+}
+
+/// How one field of a project was resolved, for `fw inspect --explain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+  pub field: &'static str,
+  /// What the project ends up with, `None` without a value from any layer or a default.
+  pub value: Option<String>,
+  /// The layers that set the field from the bottom up, each with whether its value is used.
+  pub contributions: Vec<(Contribution<String>, bool)>,
+  /// Why none of the values apply, e.g. for the environment of an untrusted config.
+  pub note: Option<String>,
+}
+
+impl Explanation {
+  /// For fields the topmost layer decides, `default` applies without any.
+  pub fn first(field: &'static str, resolver: Resolver<String>, default: Option<String>) -> Explanation {
+    let topmost = resolver.contributions().len();
+    let contributions = resolver
+      .contributions()
+      .iter()
+      .cloned()
+      .enumerate()
+      .map(|(i, c)| (c, i + 1 == topmost))
+      .collect();
+    Explanation {
+      field,
+      value: resolver.first().or(default),
+      contributions,
+      note: None,
+    }
+  }
+
+  /// For fields every layer adds to, `value` is what they add up to.
+  pub fn all(field: &'static str, resolver: Resolver<String>, value: String) -> Explanation {
+    Explanation {
+      field,
+      value: Some(value).filter(|v| !v.is_empty()),
+      contributions: resolver.contributions().iter().cloned().map(|c| (c, true)).collect(),
+      note: None,
+    }
+  }
+
+  /// For maps every layer adds to, with a `key=value` contribution per key. Only the topmost layer that sets a key is
+  /// used for it.
+  pub fn merge<K: Ord + fmt::Display, V: fmt::Display>(field: &'static str, resolver: Resolver<BTreeMap<K, V>>) -> Explanation {
+    let layers = resolver.contributions();
+    let mut contributions = Vec::new();
+    for (i, layer) in layers.iter().enumerate() {
+      for (key, value) in &layer.value {
+        let overridden = layers[i + 1..].iter().any(|upper| upper.value.contains_key(key));
+        let contribution = Contribution {
+          layer: layer.layer.clone(),
+          value: format!("{}={}", key, value),
+        };
+        contributions.push((contribution, !overridden));
+      }
+    }
+    let merged: Vec<String> = resolver.merge().iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    Explanation {
+      field,
+      value: Some(merged.join(" ")).filter(|v| !v.is_empty()),
+      contributions,
+      note: None,
+    }
+  }
+
+  /// None of the layers apply, for the reason in `note`.
+  pub fn ignored(self, note: String) -> Explanation {
+    Explanation {
+      value: None,
+      contributions: self.contributions.into_iter().map(|(c, _)| (c, false)).collect(),
+      note: Some(note),
+      ..self
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  /// Settings, tags with their priority and project, `None` stands for a layer without a value.
+  type Layers = (Option<u8>, Vec<(u8, Option<u8>)>, Option<u8>);
+
+  // Empty layers, tags without values, several tags of the same priority and equal values in different layers.
+  fn cases() -> Vec<Layers> {
+    vec![
+      (None, vec![], None),
+      (Some(1), vec![], None),
+      (None, vec![], Some(2)),
+      (Some(1), vec![(0, Some(2)), (1, None)], None),
+      (Some(1), vec![(0, Some(2)), (2, Some(3))], Some(0)),
+      (None, vec![(1, None), (1, Some(3)), (1, Some(2))], None),
+      (Some(3), vec![(0, None), (4, Some(3))], Some(3)),
+      (None, vec![(0, None), (1, None)], None),
+    ]
+  }
+
+  fn resolver(settings: Option<u8>, tags: &[(u8, Option<u8>)], project: Option<u8>) -> Resolver<u8> {
+    tags
+      .iter()
+      .enumerate()
+      .fold(Resolver::new(settings), |resolver, (i, (priority, value))| {
+        resolver.tag(&format!("tag{}", i), *priority, *value)
+      })
+      .project(project)
+  }
+
+  #[test]
+  fn test_explanation_marks_the_used_layer() {
+    let resolver = Resolver::new(Some("sh -c".to_owned()))
+      .tag("rust", 10, Some("bash -c".to_owned()))
+      .project(None);
+    let explanation = Explanation::first("shell", resolver, None);
+    assert_that(&explanation.value).is_equal_to(Some("bash -c".to_owned()));
+    let layers: Vec<(String, bool)> = explanation.contributions.iter().map(|(c, used)| (c.layer.to_string(), *used)).collect();
+    assert_that(&layers).is_equal_to(vec![("settings".to_owned(), false), ("tag rust (priority 10)".to_owned(), true)]);
+  }
+
+  #[test]
+  fn test_merge_explanation_marks_each_key() {
+    let map = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<BTreeMap<_, _>>();
+    let resolver = Resolver::new(Some(map(&[("build", "make"), ("test", "make test")])))
+      .tag("rust", 10, Some(map(&[("build", "cargo build")])))
+      .project(None);
+    let explanation = Explanation::merge("commands", resolver);
+    assert_that(&explanation.value).is_equal_to(Some("build=cargo build test=make test".to_owned()));
+    let rows: Vec<(String, String, bool)> = explanation
+      .contributions
+      .iter()
+      .map(|(c, used)| (c.layer.to_string(), c.value.clone(), *used))
+      .collect();
+    assert_that(&rows).is_equal_to(vec![
+      ("settings".to_owned(), "build=make".to_owned(), false),
+      ("settings".to_owned(), "test=make test".to_owned(), true),
+      ("tag rust (priority 10)".to_owned(), "build=cargo build".to_owned(), true),
+    ]);
+
+    let ignored = explanation.ignored("untrusted".to_owned());
+    assert_that(&ignored.value).is_none();
+    assert_that(&ignored.contributions.iter().any(|(_, used)| *used)).is_false();
+  }
+
+  #[test]
+  fn test_first_is_the_topmost_value() {
+    for (settings, tags, project) in cases() {
+      let expected = project.or_else(|| tags.iter().rev().find_map(|(_, v)| *v)).or(settings);
+      assert_that(&resolver(settings, &tags, project).first()).is_equal_to(expected);
+    }
+  }
+
+  #[test]
+  fn test_join_keeps_every_value_bottom_up() {
+    for (settings, tags, project) in cases() {
+      let expected: Vec<u8> = settings.into_iter().chain(tags.iter().filter_map(|(_, v)| *v)).chain(project).collect();
+      let resolved = resolver(settings, &tags, project);
+      assert_that(&resolved.contributions().len()).is_equal_to(expected.len());
+      assert_that(&resolved.clone().first()).is_equal_to(expected.last().cloned());
+      assert_that(&resolved.join()).is_equal_to(expected);
+    }
+  }
+
+  #[test]
+  fn test_merge_lets_upper_layers_win() {
+    for (settings, tags, project) in cases() {
+      // every layer maps its own value and one shared key
+      let resolved = resolver(settings, &tags, project).map(|v| {
+        let mut map = BTreeMap::new();
+        map.insert(format!("own{}", v), v);
+        map.insert("shared".to_owned(), v);
+        map
+      });
+      let values: Vec<u8> = resolved.contributions().iter().map(|c| c.value["shared"]).collect();
+      let merged = resolved.merge();
+      assert_that(&merged.get("shared").cloned()).is_equal_to(values.last().cloned());
+      for v in values {
+        assert_that(&merged.contains_key(&format!("own{}", v))).is_true();
+      }
+    }
+  }
+}
@@ -0,0 +1,244 @@
+use super::project::Project;
+use super::settings::{PersistedSettings, Tag};
+use super::Config;
+use crate::errors::AppError;
+use schemars::schema_for;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// The config structures a JSON Schema can be exported for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemaKind {
+  /// Projects and settings together, as fw sees them after reading all files.
+  Config,
+  /// `settings.toml`
+  Settings,
+  /// A file below `projects/`
+  Project,
+  /// A file below `tags/`
+  Tag,
+}
+
+impl FromStr for SchemaKind {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<SchemaKind, AppError> {
+    match s {
+      "config" => Ok(SchemaKind::Config),
+      "settings" => Ok(SchemaKind::Settings),
+      "project" => Ok(SchemaKind::Project),
+      "tag" => Ok(SchemaKind::Tag),
+      _ => Err(AppError::UserError(format!("Unknown schema {}, expected config, settings, project or tag", s))),
+    }
+  }
+}
+
+pub fn schema(kind: SchemaKind) -> Result<Value, AppError> {
+  let schema = match kind {
+    SchemaKind::Config => schema_for!(Config),
+    SchemaKind::Settings => schema_for!(PersistedSettings),
+    SchemaKind::Project => schema_for!(Project),
+    SchemaKind::Tag => schema_for!(Tag),
+  };
+  Ok(serde_json::to_value(schema)?)
+}
+
+pub fn print_schema(kind: SchemaKind) -> Result<(), AppError> {
+  println!("{}", serde_json::to_string_pretty(&schema(kind)?)?);
+  Ok(())
+}
+
+/// Something in a config file that does not match the schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+  /// Keys and array indices leading to the offending value.
+  pub path: Vec<String>,
+  pub message: String,
+  /// Unknown keys are ignored when reading the config, everything else makes it unreadable.
+  pub unknown_key: bool,
+}
+
+impl Issue {
+  pub fn key_path(&self) -> String {
+    if self.path.is_empty() {
+      "(root)".to_owned()
+    } else {
+      self.path.join(".")
+    }
+  }
+}
+
+/// Checks `value` against `schema` as returned by [`schema`].
+pub fn validate(schema: &Value, value: &Value) -> Vec<Issue> {
+  let mut issues = Vec::new();
+  check(schema, &schema["definitions"], value, &mut Vec::new(), &mut issues);
+  issues
+}
+
+fn type_name(value: &Value) -> &'static str {
+  match value {
+    Value::Null => "null",
+    Value::Bool(_) => "boolean",
+    Value::Number(n) if n.is_f64() => "number",
+    Value::Number(_) => "integer",
+    Value::String(_) => "string",
+    Value::Array(_) => "array",
+    Value::Object(_) => "object",
+  }
+}
+
+fn type_matches(expected: &str, value: &Value) -> bool {
+  let actual = type_name(value);
+  expected == actual || (expected == "number" && actual == "integer")
+}
+
+fn check(schema: &Value, definitions: &Value, value: &Value, path: &mut Vec<String>, issues: &mut Vec<Issue>) {
+  if let Some(reference) = schema["$ref"].as_str() {
+    let name = reference.trim_start_matches("#/definitions/");
+    check(&definitions[name], definitions, value, path, issues);
+    return;
+  }
+  if let Some(all_of) = schema["allOf"].as_array() {
+    for sub_schema in all_of {
+      check(sub_schema, definitions, value, path, issues);
+    }
+  }
+  if let Some(any_of) = schema["anyOf"].as_array() {
+    let mut candidates: Vec<Vec<Issue>> = any_of
+      .iter()
+      .map(|sub_schema| {
+        let mut sub_issues = Vec::new();
+        check(sub_schema, definitions, value, path, &mut sub_issues);
+        sub_issues
+      })
+      .collect();
+    let best = candidates.iter().position(|sub_issues| sub_issues.iter().all(|i| i.unknown_key)).unwrap_or(0);
+    if !candidates.is_empty() {
+      issues.append(&mut candidates.swap_remove(best));
+    }
+  }
+
+  let expected: Vec<&str> = match &schema["type"] {
+    Value::String(t) => vec![t.as_str()],
+    Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+    _ => vec![],
+  };
+  if !expected.is_empty() && !expected.iter().any(|t| type_matches(t, value)) {
+    issues.push(Issue {
+      path: path.clone(),
+      message: format!("expected {}, found {}", expected.join(" or "), type_name(value)),
+      unknown_key: false,
+    });
+    return;
+  }
+
+  match value {
+    Value::Object(map) => {
+      let properties = schema["properties"].as_object();
+      if let Some(required) = schema["required"].as_array() {
+        for key in required.iter().filter_map(Value::as_str) {
+          if !map.contains_key(key) {
+            issues.push(Issue {
+              path: path.clone(),
+              message: format!("missing required key {}", key),
+              unknown_key: false,
+            });
+          }
+        }
+      }
+      for (key, child) in map {
+        path.push(key.clone());
+        match properties.and_then(|p| p.get(key)) {
+          Some(child_schema) => check(child_schema, definitions, child, path, issues),
+          None if schema["additionalProperties"].is_object() => check(&schema["additionalProperties"], definitions, child, path, issues),
+          None if properties.is_some() => issues.push(Issue {
+            path: path.clone(),
+            message: "unknown key".to_owned(),
+            unknown_key: true,
+          }),
+          None => {}
+        }
+        path.pop();
+      }
+    }
+    Value::Array(items) if schema["items"].is_object() => {
+      for (index, item) in items.iter().enumerate() {
+        path.push(index.to_string());
+        check(&schema["items"], definitions, item, path, issues);
+        path.pop();
+      }
+    }
+    _ => {}
+  }
+}
+
+/// Best effort guess of the 1-based line that defines the key at `path` in a TOML or YAML document.
+pub fn line_of(raw: &str, path: &[String]) -> Option<usize> {
+  let keys: Vec<&String> = path.iter().filter(|segment| segment.parse::<usize>().is_err()).collect();
+  let mut start = 0;
+  let mut found = None;
+  for key in keys {
+    let position = raw
+      .lines()
+      .enumerate()
+      .skip(start)
+      .find(|(_, line)| defines_key(line, key))
+      .map(|(index, _)| index)?;
+    start = position + 1;
+    found = Some(position + 1);
+  }
+  found
+}
+
+fn defines_key(line: &str, key: &str) -> bool {
+  let line = line.trim_start().trim_start_matches("- ").trim_start_matches('[').trim_start_matches('[');
+  let rest = line
+    .strip_prefix(key)
+    .or_else(|| line.strip_prefix(&format!("\"{}\"", key)))
+    .or_else(|| line.strip_prefix(&format!("'{}'", key)));
+  match rest {
+    Some(rest) => {
+      let rest = rest.trim_start();
+      rest.starts_with('=') || rest.starts_with(':') || rest.starts_with(']')
+    }
+    None => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  fn project_issues(raw: &str) -> Vec<Issue> {
+    let value: toml::Value = toml::from_str(raw).unwrap();
+    validate(&schema(SchemaKind::Project).unwrap(), &serde_json::to_value(value).unwrap())
+  }
+
+  #[test]
+  fn test_valid_project() {
+    let raw = "git = 'git@github.com:brocode/fw.git'\ntags = ['rust']\n[[additional_remotes]]\nname = 'upstream'\ngit = 'git@...'\n";
+    assert_that(&project_issues(raw)).is_empty();
+  }
+
+  #[test]
+  fn test_reports_wrong_types_and_unknown_keys() {
+    let raw = "git = 'git@github.com:brocode/fw.git'\ntags = 'rust'\nafter_clon = 'make'\n";
+    let issues = project_issues(raw);
+    assert_that(&issues).has_length(2);
+    assert_that(&issues[0].key_path()).is_equal_to("after_clon".to_owned());
+    assert_that(&issues[0].unknown_key).is_true();
+    assert_that(&issues[1].key_path()).is_equal_to("tags".to_owned());
+    assert_that(&issues[1].message).is_equal_to("expected array or null, found string".to_owned());
+    assert_that(&line_of(raw, &issues[1].path)).is_equal_to(Some(2));
+  }
+
+  #[test]
+  fn test_reports_nested_issues() {
+    let raw = "git = 'x'\n[[additional_remotes]]\nname = 'upstream'\ngit = 3\n";
+    let issues = project_issues(raw);
+    assert_that(&issues).has_length(1);
+    assert_that(&issues[0].key_path()).is_equal_to("additional_remotes.0.git".to_owned());
+    assert_that(&line_of(raw, &issues[0].path)).is_equal_to(Some(4));
+  }
+}
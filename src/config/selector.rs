@@ -0,0 +1,92 @@
+use crate::config::{project::Project, Config};
+use slog::{o, Discard, Logger};
+use std::collections::BTreeSet;
+
+/// Selects the projects a bulk operation (sync, foreach, ls, ...) works on.
+///
+/// An empty selector matches every project that is not archived. With tags given, a project matches if it carries at
+/// least one of them, including tags that apply through their `when` condition.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectSelector {
+  pub tags: BTreeSet<String>,
+  pub include_archived: bool,
+}
+
+impl ProjectSelector {
+  pub fn all() -> ProjectSelector {
+    ProjectSelector::default()
+  }
+
+  pub fn with_tags(tags: BTreeSet<String>) -> ProjectSelector {
+    ProjectSelector { tags, include_archived: false }
+  }
+
+  /// Also selects archived projects.
+  pub fn including_archived(self) -> ProjectSelector {
+    ProjectSelector {
+      include_archived: true,
+      ..self
+    }
+  }
+
+  /// Whether the project's own tags match, without looking at conditional tags.
+  pub fn matches(&self, project: &Project) -> bool {
+    self.matches_tags(&project.tags.clone().unwrap_or_default())
+  }
+
+  fn matches_tags(&self, tags: &BTreeSet<String>) -> bool {
+    self.tags.is_empty() || tags.intersection(&self.tags).count() > 0
+  }
+
+  /// The matching projects of `config` in name order.
+  pub fn select<'a>(&self, config: &'a Config) -> Vec<&'a Project> {
+    let candidates = config.projects.values().filter(|p| self.include_archived || !p.archived.unwrap_or_default());
+    if self.tags.is_empty() {
+      return candidates.collect();
+    }
+    let logger = Logger::root(Discard, o!());
+    candidates.filter(|p| self.matches_tags(&config.effective_tags(p, &logger))).collect()
+  }
+
+  /// The matching projects that are git repositories, for operations that fetch, commit or inspect history. Of the
+  /// projects sharing a clone through `subdir` only the first one is selected.
+  pub fn select_repositories<'a>(&self, config: &'a Config) -> Vec<&'a Project> {
+    let logger = Logger::root(Discard, o!());
+    let mut checkouts = BTreeSet::new();
+    self
+      .select_git_projects(config)
+      .into_iter()
+      .filter(|p| checkouts.insert(config.checkout_path(p, &logger)))
+      .collect()
+  }
+
+  /// The matching projects that are git repositories, all of those sharing a clone through `subdir` as well. For
+  /// operations with steps of their own for every project, like the hooks of `fw sync`.
+  pub fn select_git_projects<'a>(&self, config: &'a Config) -> Vec<&'a Project> {
+    self.select(config).into_iter().filter(|p| !p.is_directory()).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use maplit::btreeset;
+  use spectral::prelude::*;
+
+  fn a_project(tags: Option<BTreeSet<String>>) -> Project {
+    Project { tags, ..Project::example() }
+  }
+
+  #[test]
+  fn test_empty_selector_matches_everything() {
+    assert_that(&ProjectSelector::all().matches(&a_project(None))).is_true();
+  }
+
+  #[test]
+  fn test_selector_matches_any_tag() {
+    let selector = ProjectSelector::with_tags(btreeset!["rust".to_owned(), "go".to_owned()]);
+    assert_that(&selector.matches(&a_project(Some(btreeset!["go".to_owned()])))).is_true();
+    assert_that(&selector.matches(&a_project(Some(btreeset!["python".to_owned()])))).is_false();
+    assert_that(&selector.matches(&a_project(None))).is_false();
+  }
+}
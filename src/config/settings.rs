@@ -1,13 +1,64 @@
+use super::autotag::{AutotagRule, ForgeTags};
+use super::detect::project_types;
+use super::profile::Profile;
+use crate::errors::AppError;
+use crate::git_url::{self, Protocol};
+use crate::output::print_warning;
+use maplit::{btreemap, btreeset};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::str::FromStr;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Checks against a project's checkout. All conditions that are set have to hold.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct TagCondition {
+  /// A file or directory relative to the project root.
+  pub file_exists: Option<String>,
+  /// A detected project type like rust, node, go or python.
+  pub project_type: Option<String>,
+}
+
+impl TagCondition {
+  pub fn matches(&self, project_path: &Path) -> bool {
+    self.file_exists.as_ref().map(|file| project_path.join(file).exists()).unwrap_or(true)
+      && self
+        .project_type
+        .as_ref()
+        .map(|project_type| project_types(project_path).contains(project_type))
+        .unwrap_or(true)
+  }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Tag {
   pub after_clone: Option<String>,
   pub after_workon: Option<String>,
+  /// Program and arguments that run hooks and commands of the tagged projects, overriding the one in the settings.
+  pub shell: Option<Vec<String>>,
+  /// Seconds after which hooks of the tagged projects are killed, overriding the timeout in the settings.
+  pub hook_timeout: Option<u64>,
+  /// Private key for cloning and fetching the tagged projects over SSH, overriding the key in the settings.
+  pub ssh_key: Option<String>,
+  /// Fetch the git LFS objects of the tagged projects, overriding the settings.
+  pub lfs: Option<bool>,
   pub priority: Option<u8>,
   pub workspace: Option<String>,
   pub default: Option<bool>,
+  /// Commands for `fw run`, overriding those of the settings.
+  pub commands: Option<BTreeMap<String, String>>,
+  /// Applies the tag to every project whose checkout matches, without listing it in the project.
+  pub when: Option<TagCondition>,
+  /// Put in front of branch names given to `fw branch create` for the tagged projects, e.g. `feature/`.
+  pub default_branch_prefix: Option<String>,
+  /// A regex that names of new branches in the tagged projects have to match, prefix included.
+  pub branch_pattern: Option<String>,
+  /// Clone the tagged projects with only that many commits of history, overriding the settings.
+  pub clone_depth: Option<u32>,
+  /// Environment variables for hooks and commands of the tagged projects, added to those of the settings.
+  pub env: Option<BTreeMap<String, String>>,
 
   #[serde(skip)]
   pub tag_config_path: String,
@@ -18,46 +69,328 @@ impl Tag {
     Tag {
       after_clone: Some("echo after clone from tag".to_owned()),
       after_workon: Some("echo after workon from tag".to_owned()),
+      shell: Some(vec!["pwsh".to_owned(), "-Command".to_owned()]),
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       priority: Some(0),
       workspace: Some("/home/other".to_string()),
       default: Some(false),
+      commands: Some(btreemap!["test".to_owned() => "cargo test".to_owned()]),
+      when: Some(TagCondition {
+        file_exists: Some("Cargo.toml".to_string()),
+        project_type: None,
+      }),
+      default_branch_prefix: Some("feature/".to_owned()),
+      branch_pattern: Some("^(feature|fix)/[a-z0-9-]+$".to_owned()),
+      clone_depth: None,
+      env: Some(btreemap!["RUST_LOG".to_owned() => "debug".to_owned()]),
       tag_config_path: "".to_string(), // ignored
     }
   }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct GitlabSettings {
   pub token: String,
   pub host: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// The keys a shared config for `fw diff-config` has to be signed with.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct SharedConfigSettings {
+  /// minisign public keys, the second line of a `minisign.pub`.
+  pub minisign_keys: Option<Vec<String>>,
+  /// Fingerprints of GPG keys, which have to be in your keyring.
+  pub gpg_keys: Option<Vec<String>>,
+}
+
+/// How the importers name the projects they add, the same repository always gets the same name.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportNaming {
+  /// The name of the repository, `api`.
+  Name,
+  /// The organization, group or owner and the name, `acme-api`.
+  OrgPrefix,
+  /// The name and the forge, `api-github`.
+  ForgeSuffix,
+}
+
+impl FromStr for ImportNaming {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "name" => Ok(ImportNaming::Name),
+      "org-prefix" => Ok(ImportNaming::OrgPrefix),
+      "forge-suffix" => Ok(ImportNaming::ForgeSuffix),
+      other => Err(AppError::UserError(format!(
+        "Unknown import naming {}, expected name, org-prefix or forge-suffix",
+        other
+      ))),
+    }
+  }
+}
+
+impl ImportNaming {
+  /// The project name for repository `name` of `org` on `forge`. Nested groups like `acme/backend` become `acme-backend`.
+  pub fn apply(self, name: &str, org: Option<&str>, forge: &str) -> String {
+    match (self, org) {
+      (ImportNaming::OrgPrefix, Some(org)) if !org.is_empty() => format!("{}-{}", org.replace('/', "-"), name),
+      (ImportNaming::ForgeSuffix, _) => format!("{}-{}", name, forge),
+      _ => name.to_owned(),
+    }
+  }
+}
+
+/// Values for every project that neither sets them itself nor gets them from one of its tags.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct ProjectDefaults {
+  /// Runs before the after_clone hooks of the tags and the project, unlike `default_after_clone` for every project and
+  /// not only for new ones.
+  pub after_clone: Option<String>,
+  pub after_workon: Option<String>,
+  /// Checkout path of projects without their own `override_path`, with the variables of `layout`, e.g.
+  /// `{workspace}/{forge}/{name}`. Takes precedence over `layout`.
+  pub override_path: Option<String>,
+  pub shell: Option<Vec<String>>,
+  pub hook_timeout: Option<u64>,
+  pub ssh_key: Option<String>,
+  pub lfs: Option<bool>,
+  /// Clone with only that many commits of history, through `git clone --depth`.
+  pub clone_depth: Option<u32>,
+  /// Tags for projects that are added or imported.
+  pub tags: Option<BTreeSet<String>>,
+  // TOML writes tables last, keep them after all plain values
+  /// Environment variables for hooks and commands.
+  pub env: Option<BTreeMap<String, String>>,
+  pub commands: Option<BTreeMap<String, String>>,
+}
+
+impl ProjectDefaults {
+  pub fn example() -> ProjectDefaults {
+    ProjectDefaults {
+      clone_depth: Some(50),
+      env: Some(btreemap!["GOFLAGS".to_owned() => "-mod=mod".to_owned()]),
+      ..ProjectDefaults::default()
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
 pub struct Settings {
   pub workspace: String,
   pub shell: Option<Vec<String>>,
+  pub hook_timeout: Option<u64>,
+  pub ssh_key: Option<String>,
+  pub lfs: Option<bool>,
   pub default_after_workon: Option<String>,
   pub default_after_clone: Option<String>,
+  /// The hooks of `project_defaults`, the layer beneath those of tags and projects.
+  pub after_clone: Option<String>,
+  pub after_workon: Option<String>,
   pub default_tags: Option<BTreeSet<String>>,
   pub tags: Option<BTreeMap<String, Tag>>,
   pub github_token: Option<String>,
+  pub gitea_token: Option<String>,
+  pub azure_token: Option<String>,
   pub gitlab: Option<GitlabSettings>,
+  pub log_file: Option<String>,
+  pub readonly: Option<bool>,
+  pub discover_config: Option<bool>,
+  pub notify: Option<bool>,
+  pub notify_command: Option<String>,
+  pub retries: Option<u32>,
+  pub retry_backoff: Option<u64>,
+  pub http_proxy: Option<String>,
+  pub https_proxy: Option<String>,
+  pub no_proxy: Option<Vec<String>>,
+  pub ca_bundle: Option<String>,
+  pub import_naming: Option<ImportNaming>,
+  pub time_tracking: Option<bool>,
+  pub session_timeout: Option<u64>,
+  pub layout: Option<String>,
+  pub prefer: Option<Protocol>,
+  pub reference_repo: Option<String>,
+  pub max_network_operations: Option<u32>,
+  pub bandwidth_limit: Option<u64>,
+  pub hook_nice: Option<i32>,
+  pub clone_depth: Option<u32>,
+  pub env: Option<BTreeMap<String, String>>,
+  pub commands: Option<BTreeMap<String, String>>,
+  pub autotag: Option<Vec<AutotagRule>>,
+  pub forge_tags: Option<ForgeTags>,
+  pub tag_groups: Option<BTreeMap<String, BTreeSet<String>>>,
+  pub profiles: Option<BTreeMap<String, Profile>>,
+  pub shared_config: Option<SharedConfigSettings>,
+  /// The file of a config the user did not write themselves, e.g. a discovered `.fw.json`. Its hooks need approval.
+  #[serde(skip)]
+  pub untrusted_source: Option<String>,
 }
 
 impl Settings {
   pub fn get_shell_or_default(self: &Settings) -> Vec<String> {
-    self.shell.clone().unwrap_or_else(|| vec!["sh".to_owned(), "-c".to_owned()])
+    self.shell.clone().unwrap_or_else(Settings::default_shell)
+  }
+
+  /// `sh -c`, the shell without one in the settings.
+  pub fn default_shell() -> Vec<String> {
+    vec!["sh".to_owned(), "-c".to_owned()]
+  }
+
+  /// The tags of matching autotag rules for a project with git URL `url`.
+  pub fn autotags_for(&self, url: &str) -> BTreeSet<String> {
+    self
+      .autotag
+      .iter()
+      .flatten()
+      .filter(|rule| rule.matches(url))
+      .map(|rule| rule.tag.clone())
+      .collect()
+  }
+
+  /// Tags for a project that is added or imported: the default tags plus the tags of matching autotag rules.
+  pub fn tags_for_new_project(&self, url: &str) -> Option<BTreeSet<String>> {
+    let tags = self.with_tags(&self.default_tags.clone().unwrap_or_default(), self.autotags_for(url));
+    if tags.is_empty() {
+      self.default_tags.clone()
+    } else {
+      Some(tags)
+    }
+  }
+
+  /// `own` plus each of `added` that does not share a tag group with a tag already there. The skipped tags are
+  /// reported as a warning.
+  pub fn with_tags(&self, own: &BTreeSet<String>, added: impl IntoIterator<Item = String>) -> BTreeSet<String> {
+    let mut tags = own.clone();
+    for tag in added {
+      let mut candidate = tags.clone();
+      candidate.insert(tag.clone());
+      match self.tag_group_conflicts(&candidate).into_iter().find(|(_, present)| present.contains(&tag)) {
+        Some((group, present)) if !tags.contains(&tag) => print_warning(&format!(
+          "Not adding tag {}, only one of the tags {} of tag group {} is allowed",
+          tag,
+          present.join(", "),
+          group
+        )),
+        _ => {
+          tags.insert(tag);
+        }
+      }
+    }
+    tags
+  }
+
+  /// Every tag group of which `tags` has more than one tag, with those tags.
+  pub fn tag_group_conflicts(&self, tags: &BTreeSet<String>) -> Vec<(String, Vec<String>)> {
+    self
+      .tag_groups
+      .iter()
+      .flatten()
+      .map(|(group, members)| (group.clone(), members.intersection(tags).cloned().collect::<Vec<_>>()))
+      .filter(|(_, present)| present.len() > 1)
+      .collect()
+  }
+
+  /// `shell` run through `nice` with the `hook_nice` of the settings, as it is without one or on Windows.
+  pub fn niced(&self, shell: Vec<String>) -> Vec<String> {
+    match self.hook_nice {
+      Some(niceness) if cfg!(unix) => vec!["nice".to_owned(), "-n".to_owned(), niceness.to_string()]
+        .into_iter()
+        .chain(shell)
+        .collect(),
+      _ => shell,
+    }
+  }
+
+  /// `url` in the form `prefer` asks for if it is on a known forge or the GitLab host of the settings.
+  pub fn git_url(&self, url: &str) -> String {
+    match self.prefer {
+      Some(protocol) => {
+        // the host may be given as a URL
+        let gitlab_host = self.gitlab.as_ref().map(|gitlab| {
+          let host = gitlab.host.split("://").last().unwrap_or_default();
+          host.split('/').next().unwrap_or_default()
+        });
+        git_url::normalize(url, protocol, &gitlab_host.into_iter().collect::<Vec<_>>())
+      }
+      None => url.to_owned(),
+    }
   }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
 pub struct PersistedSettings {
   pub workspace: String,
   pub shell: Option<Vec<String>>,
+  /// Seconds after which after_clone and after_workon hooks are killed. Tags and projects can override it.
+  pub hook_timeout: Option<u64>,
+  /// Private key for cloning and fetching over SSH instead of the keys of the ssh agent. Tags and projects can override it.
+  pub ssh_key: Option<String>,
+  /// Run `git lfs install` and `git lfs pull` after cloning and fetch the LFS objects on sync. Tags and projects can
+  /// override it.
+  pub lfs: Option<bool>,
   pub default_after_workon: Option<String>,
   pub default_after_clone: Option<String>,
   pub github_token: Option<String>,
+  /// Token for `fw gitea-import`, only needed for private repositories. `FW_GITEA_TOKEN` takes precedence.
+  pub gitea_token: Option<String>,
+  /// Personal access token for `fw azure-import` with read access to code. `FW_AZURE_TOKEN` takes precedence.
+  pub azure_token: Option<String>,
+  pub log_file: Option<String>,
+  pub readonly: Option<bool>,
+  /// Use the nearest `.fw.json` in the current directory or above instead of this configuration, e.g. one a team ships in its repository.
+  pub discover_config: Option<bool>,
+  pub notify: Option<bool>,
+  pub notify_command: Option<String>,
+  /// How often a failed clone or fetch is retried when the error looks temporary. Defaults to 2.
+  pub retries: Option<u32>,
+  /// Seconds to wait before the first retry, doubled for every further one. Defaults to 2.
+  pub retry_backoff: Option<u64>,
+  /// Proxy for plain HTTP, e.g. `http://proxy.corp:3128`. Overrides `HTTP_PROXY` from the environment.
+  pub http_proxy: Option<String>,
+  /// Proxy for HTTPS clones and the GitHub and GitLab APIs. Overrides `HTTPS_PROXY` from the environment.
+  pub https_proxy: Option<String>,
+  /// Hosts reached without the proxy, `corp.com` also covers its subdomains.
+  pub no_proxy: Option<Vec<String>>,
+  /// PEM file with additional certificate authorities to trust, e.g. the one of a TLS inspecting proxy.
+  pub ca_bundle: Option<String>,
+  /// How the importers name projects: `name` (the default), `org-prefix` or `forge-suffix`.
+  pub import_naming: Option<ImportNaming>,
+  /// Record a session per workon for `fw time report`.
+  pub time_tracking: Option<bool>,
+  /// Seconds a session lasts at most when no later workon ends it. Defaults to 7200.
+  pub session_timeout: Option<u64>,
+  /// Where projects without `override_path` are checked out, e.g. `{workspace}/{forge}/{org}/{name}`. Defaults to
+  /// `{workspace}/{name}`.
+  pub layout: Option<String>,
+  /// Clone and show the git URLs of forges as `ssh` or `https`, however they were entered. As entered without it.
+  pub prefer: Option<Protocol>,
+  /// A local repository git borrows objects from when cloning, e.g. an earlier clone of a monorepo. The clones keep
+  /// using its objects, so it has to stay around.
+  pub reference_repo: Option<String>,
+  /// At most this many clones and fetches at the same time, also for `fw daemon`. Lowers `fw sync --parallelism`.
+  pub max_network_operations: Option<u32>,
+  /// KiB per second a single clone or fetch may download. Clones with `reference_repo` are not limited.
+  pub bandwidth_limit: Option<u64>,
+  /// Niceness the hooks run with, e.g. 10 so that the after_clone hooks of a background sync yield to interactive work.
+  pub hook_nice: Option<i32>,
+  // TOML writes tables last, keep them after all plain values
+  /// Named commands for `fw run`, e.g. `test = "make test"`. Tags and projects can override them.
+  pub commands: Option<BTreeMap<String, String>>,
   pub gitlab: Option<GitlabSettings>,
+  /// Defaults for all project settings, beneath those of the tags and the projects. They take precedence over the
+  /// older `shell`, `commands` and the like.
+  pub project_defaults: Option<ProjectDefaults>,
+  pub autotag: Option<Vec<AutotagRule>>,
+  pub forge_tags: Option<ForgeTags>,
+  /// Named groups of tags a project may only have one of, e.g. `context = ["work", "personal"]`.
+  pub tag_groups: Option<BTreeMap<String, BTreeSet<String>>>,
+  /// Named profiles with their own workspace, default tags and projects, chosen with `fw --profile` or `FW_PROFILE`.
+  pub profiles: Option<BTreeMap<String, Profile>>,
+  /// Shared configs are only taken over with a valid signature by one of these keys.
+  pub shared_config: Option<SharedConfigSettings>,
 }
 
 impl PersistedSettings {
@@ -67,11 +400,82 @@ impl PersistedSettings {
       default_after_workon: Some("echo default after workon".to_string()),
       default_after_clone: Some("echo default after clone".to_string()),
       shell: Some(vec!["/usr/bin/zsh".to_string(), "-c".to_string()]),
+      hook_timeout: Some(300),
+      ssh_key: None,
+      lfs: Some(false),
       github_token: Some("githubtokensecret".to_string()),
+      gitea_token: None,
+      azure_token: None,
       gitlab: Some(GitlabSettings {
         host: "localhost".to_string(),
         token: "token".to_string(),
       }),
+      log_file: Some("~/.local/state/fw/fw.log".to_string()),
+      readonly: Some(false),
+      discover_config: Some(false),
+      notify: Some(false),
+      notify_command: Some("curl -s -d \"$FW_NOTIFY_BODY\" ntfy.sh/my-fw".to_string()),
+      retries: Some(2),
+      retry_backoff: Some(2),
+      http_proxy: None,
+      https_proxy: Some("http://proxy.corp:3128".to_string()),
+      no_proxy: Some(vec!["localhost".to_string(), "corp.com".to_string()]),
+      ca_bundle: Some("~/.config/fw/corp-ca.pem".to_string()),
+      import_naming: Some(ImportNaming::Name),
+      time_tracking: Some(false),
+      session_timeout: Some(7200),
+      layout: Some("{workspace}/{forge}/{org}/{name}".to_owned()),
+      prefer: Some(Protocol::Ssh),
+      reference_repo: Some("~/.cache/fw/reference.git".to_owned()),
+      max_network_operations: Some(4),
+      bandwidth_limit: Some(2048),
+      hook_nice: Some(10),
+      commands: Some(btreemap!["test".to_owned() => "make test".to_owned()]),
+      project_defaults: Some(ProjectDefaults::example()),
+      autotag: Some(vec![AutotagRule::example()]),
+      forge_tags: Some(ForgeTags::example()),
+      tag_groups: Some(btreemap!["context".to_owned() => btreeset!["work".to_owned(), "personal".to_owned()]]),
+      profiles: Some(btreemap!["work".to_owned() => Profile::example()]),
+      shared_config: Some(SharedConfigSettings {
+        minisign_keys: Some(vec!["RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3".to_string()]),
+        gpg_keys: None,
+      }),
     }
   }
 }
+
+/// Removes the settings that end in `_token` and the GitLab settings, which can't do without theirs, from `settings`
+/// serialized as JSON.
+pub fn without_tokens(settings: &mut Value) {
+  if let Value::Object(attributes) = settings {
+    let secrets: Vec<String> = attributes
+      .keys()
+      .filter(|name| *name == "gitlab" || name.ends_with("_token"))
+      .cloned()
+      .collect();
+    for name in secrets {
+      attributes.remove(&name);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_without_tokens() {
+    let mut settings = serde_json::json!({"workspace": "~/ws", "github_token": "secret", "gitlab": {"host": "gitlab.corp", "token": "secret"}});
+    without_tokens(&mut settings);
+    assert_that(&settings).is_equal_to(serde_json::json!({"workspace": "~/ws"}));
+  }
+
+  #[test]
+  fn test_import_naming() {
+    assert_that(&ImportNaming::Name.apply("api", Some("acme"), "github")).is_equal_to("api".to_owned());
+    assert_that(&ImportNaming::OrgPrefix.apply("api", Some("acme/backend"), "gitlab")).is_equal_to("acme-backend-api".to_owned());
+    assert_that(&ImportNaming::OrgPrefix.apply("api", None, "codecommit")).is_equal_to("api".to_owned());
+    assert_that(&ImportNaming::ForgeSuffix.apply("api", Some("acme"), "gitea")).is_equal_to("api-gitea".to_owned());
+  }
+}
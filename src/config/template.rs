@@ -0,0 +1,106 @@
+/// Replaces the placeholders `{{name}}` in a hook with `value(name)`. Placeholders without a value stay as they are.
+pub fn expand_placeholders<F>(hook: &str, value: F) -> String
+where
+  F: Fn(&str) -> Option<String>,
+{
+  let mut expanded = String::new();
+  let mut rest = hook;
+  while let Some(start) = rest.find("{{") {
+    let (before, placeholder) = rest.split_at(start);
+    expanded.push_str(before);
+    match placeholder[2..].find("}}") {
+      Some(end) => {
+        match value(placeholder[2..2 + end].trim()) {
+          Some(v) => expanded.push_str(&v),
+          None => expanded.push_str(&placeholder[..end + 4]),
+        }
+        rest = &placeholder[end + 4..];
+      }
+      None => {
+        expanded.push_str(placeholder);
+        rest = "";
+      }
+    }
+  }
+  expanded.push_str(rest);
+  expanded
+}
+
+/// `value` as one word for a POSIX shell: as it is if it has nothing the shell would interpret, single quoted otherwise.
+pub fn shell_word(value: &str) -> String {
+  let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:@%+=,~".contains(c);
+  if !value.is_empty() && value.chars().all(plain) && !value.starts_with('~') {
+    value.to_owned()
+  } else {
+    format!("'{}'", value.replace('\'', "'\\''"))
+  }
+}
+
+/// Splits `command` into words like a POSIX shell: whitespace separates them, single and double quotes and backslashes
+/// keep what they quote in one word.
+pub fn split_shell_words(command: &str) -> Vec<String> {
+  let mut words = Vec::new();
+  let mut word: Option<String> = None;
+  let mut chars = command.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      c if c.is_whitespace() => words.extend(word.take()),
+      '\'' => word.get_or_insert_with(String::new).extend(chars.by_ref().take_while(|c| *c != '\'')),
+      '"' => {
+        let word = word.get_or_insert_with(String::new);
+        while let Some(c) = chars.next() {
+          match c {
+            '"' => break,
+            '\\' => word.extend(chars.next()),
+            c => word.push(c),
+          }
+        }
+      }
+      '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+      c => word.get_or_insert_with(String::new).push(c),
+    }
+  }
+  words.extend(word);
+  words
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_expand_placeholders() {
+    let value = |name: &str| match name {
+      "project.name" => Some("fw".to_owned()),
+      "tags" => Some("rust brocode".to_owned()),
+      _ => None,
+    };
+    assert_that(&expand_placeholders("echo {{project.name}} {{ tags }}", value)).is_equal_to("echo fw rust brocode".to_owned());
+    assert_that(&expand_placeholders("echo {{unknown}} ${{x}} {{project.name", value)).is_equal_to("echo {{unknown}} ${{x}} {{project.name".to_owned());
+  }
+
+  #[test]
+  fn test_split_shell_words() {
+    assert_that(&split_shell_words("ssh -i '/keys/my key' -o IdentitiesOnly=yes")).is_equal_to(vec![
+      "ssh".to_owned(),
+      "-i".to_owned(),
+      "/keys/my key".to_owned(),
+      "-o".to_owned(),
+      "IdentitiesOnly=yes".to_owned(),
+    ]);
+    assert_that(&split_shell_words("a\\ b \"c \\\" d\" ''")).is_equal_to(vec!["a b".to_owned(), "c \" d".to_owned(), "".to_owned()]);
+    let path = "/keys/it's \"$HOME\"";
+    assert_that(&split_shell_words(&shell_word(path))).is_equal_to(vec![path.to_owned()]);
+  }
+
+  #[test]
+  fn test_shell_word() {
+    assert_that(&shell_word("/home/me/workspace/fw")).is_equal_to("/home/me/workspace/fw".to_owned());
+    assert_that(&shell_word("git@github.com:brocode/fw.git")).is_equal_to("git@github.com:brocode/fw.git".to_owned());
+    assert_that(&shell_word("/home/me/my projects/fw")).is_equal_to("'/home/me/my projects/fw'".to_owned());
+    assert_that(&shell_word("it's $(rm -rf ~)")).is_equal_to("'it'\\''s $(rm -rf ~)'".to_owned());
+    assert_that(&shell_word("~root")).is_equal_to("'~root'".to_owned());
+    assert_that(&shell_word("")).is_equal_to("''".to_owned());
+  }
+}
@@ -0,0 +1,149 @@
+use crate::errors::AppError;
+use slog::{debug, warn, Logger};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What the last round found out about a checked out project.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectMetrics {
+  pub name: String,
+  /// `None` without an upstream.
+  pub behind: Option<usize>,
+  pub dirty: bool,
+  /// Seconds since the epoch of the last fetch.
+  pub last_fetch: Option<u64>,
+}
+
+/// The metrics of the last round, shared between the daemon and the endpoint.
+#[derive(Debug, Default)]
+pub struct Metrics {
+  pub projects: Vec<ProjectMetrics>,
+  /// Seconds since the epoch when the last round finished.
+  pub last_round: Option<u64>,
+}
+
+fn escape_label(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, samples: impl Iterator<Item = (String, u64)>) {
+  let _ = writeln!(out, "# HELP {} {}", name, help);
+  let _ = writeln!(out, "# TYPE {} gauge", name);
+  for (project, value) in samples {
+    let _ = writeln!(out, "{}{{project=\"{}\"}} {}", name, escape_label(&project), value);
+  }
+}
+
+/// Renders the metrics in the Prometheus text format. The fetch age is computed against `now`, so it keeps growing between rounds.
+pub fn render(metrics: &Metrics, now: u64) -> String {
+  let mut out = String::new();
+  gauge(
+    &mut out,
+    "fw_project_behind_count",
+    "Commits the checked out branch is behind its upstream.",
+    metrics.projects.iter().filter_map(|p| p.behind.map(|b| (p.name.clone(), b as u64))),
+  );
+  gauge(
+    &mut out,
+    "fw_project_dirty",
+    "1 if the project has uncommitted changes.",
+    metrics.projects.iter().map(|p| (p.name.clone(), p.dirty as u64)),
+  );
+  gauge(
+    &mut out,
+    "fw_project_last_fetch_age_seconds",
+    "Seconds since the project was last fetched.",
+    metrics
+      .projects
+      .iter()
+      .filter_map(|p| p.last_fetch.map(|t| (p.name.clone(), now.saturating_sub(t)))),
+  );
+  if let Some(last_round) = metrics.last_round {
+    let _ = writeln!(
+      out,
+      "# HELP fw_daemon_last_round_timestamp_seconds When the last fetch round of fw daemon finished."
+    );
+    let _ = writeln!(out, "# TYPE fw_daemon_last_round_timestamp_seconds gauge");
+    let _ = writeln!(out, "fw_daemon_last_round_timestamp_seconds {}", last_round);
+  }
+  out
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<Metrics>) -> std::io::Result<()> {
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut request_line = String::new();
+  reader.read_line(&mut request_line)?;
+  // the headers are not needed, but are read so the client does not see a reset connection
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 2 {
+    header.clear();
+  }
+  let (status, content_type, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>().as_slice() {
+    ["GET", "/metrics"] => {
+      let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+      let metrics = metrics.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+      ("200 OK", "text/plain; version=0.0.4", render(&metrics, now))
+    }
+    _ => ("404 Not Found", "text/plain", "Metrics are served at /metrics\n".to_owned()),
+  };
+  write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status,
+    content_type,
+    body.len(),
+    body
+  )
+}
+
+/// Binds `address` and answers scrapes from a background thread for as long as the process runs.
+pub fn serve(address: SocketAddr, metrics: Arc<Mutex<Metrics>>, logger: &Logger) -> Result<(), AppError> {
+  let listener = TcpListener::bind(address).map_err(|e| AppError::UserError(format!("Could not listen on {}: {}", address, e)))?;
+  let logger = logger.clone();
+  std::thread::spawn(move || {
+    for stream in listener.incoming() {
+      match stream.and_then(|stream| respond(stream, &metrics)) {
+        Ok(()) => debug!(logger, "Metrics scraped"),
+        Err(e) => warn!(logger, "Could not answer metrics request"; "error" => format!("{}", e)),
+      }
+    }
+  });
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_render() {
+    let metrics = Metrics {
+      projects: vec![
+        ProjectMetrics {
+          name: "api".to_owned(),
+          behind: Some(3),
+          dirty: true,
+          last_fetch: Some(100),
+        },
+        ProjectMetrics {
+          name: "we\"ird".to_owned(),
+          behind: None,
+          dirty: false,
+          last_fetch: None,
+        },
+      ],
+      last_round: Some(150),
+    };
+    let rendered = render(&metrics, 160);
+    assert_that(&rendered).contains("fw_project_behind_count{project=\"api\"} 3\n");
+    assert_that(&rendered).contains("fw_project_dirty{project=\"api\"} 1\n");
+    assert_that(&rendered).contains("fw_project_dirty{project=\"we\\\"ird\"} 0\n");
+    assert_that(&rendered).contains("fw_project_last_fetch_age_seconds{project=\"api\"} 60\n");
+    assert_that(&rendered).contains("fw_daemon_last_round_timestamp_seconds 150\n");
+    assert_that(&rendered.contains("behind_count{project=\"we")).is_false();
+  }
+}
@@ -0,0 +1,205 @@
+mod metrics;
+
+use self::metrics::{Metrics, ProjectMetrics};
+use crate::config::path::fw_path;
+use crate::config::{self, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git::is_dirty;
+use crate::lock::LockMode;
+use crate::output::Tone;
+use crate::signal;
+use crate::status::{self, format_age, ProjectStatus};
+use crate::sync::{self, SyncOptions};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use slog::{info, warn, Logger};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What the last round of `fw daemon` did, read by `fw status`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DaemonState {
+  pub pid: u32,
+  /// Seconds between two rounds.
+  pub interval: u64,
+  /// Seconds since the epoch when the last round finished.
+  pub last_run: u64,
+  /// `None` if every project was fetched.
+  pub error: Option<String>,
+}
+
+fn state_file() -> Result<PathBuf, AppError> {
+  Ok(fw_path()?.state.join("daemon.json"))
+}
+
+fn write_state(path: &Path, state: &DaemonState) -> Result<(), AppError> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  config::write_atomically(path, &serde_json::to_string(state)?)
+}
+
+/// The state the daemon left behind, if it ever ran.
+pub fn last_state() -> Option<DaemonState> {
+  let raw = fs::read_to_string(state_file().ok()?).ok()?;
+  serde_json::from_str(&raw).ok()
+}
+
+/// Parses intervals like `30m`, `1h`, `90s` or `45` (seconds).
+pub fn parse_interval(input: &str) -> Result<Duration, String> {
+  let input = input.trim();
+  let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+    Some(index) => input.split_at(index),
+    None => (input, "s"),
+  };
+  let number: u64 = number.parse().map_err(|_| format!("Expected an interval like 30m. Was '{}'.", input))?;
+  let seconds = match unit {
+    "s" => number,
+    "m" => number * 60,
+    "h" => number * 60 * 60,
+    "d" => number * 60 * 60 * 24,
+    _ => return Err(format!("Unknown unit '{}', use s, m, h or d. Was '{}'.", unit, input)),
+  };
+  if seconds == 0 {
+    Err("The interval must be longer than 0s.".to_owned())
+  } else {
+    Ok(Duration::from_secs(seconds))
+  }
+}
+
+/// One line for `fw status` about the daemon, which turns into a warning when it missed two rounds.
+pub(crate) fn describe(state: &DaemonState, now: u64) -> (String, Tone) {
+  let age = now.saturating_sub(state.last_run);
+  let line = format!("fw daemon (pid {}) fetched {}", state.pid, format_age(age));
+  match &state.error {
+    _ if age > 2 * state.interval => (format!("{}, it does not seem to be running anymore", line), Tone::Warning),
+    Some(error) => (format!("{}: {}", line, error), Tone::Bad),
+    None => (line, Tone::Muted),
+  }
+}
+
+fn now() -> Result<u64, AppError> {
+  Ok(SystemTime::now().duration_since(UNIX_EPOCH).map_err(AppError::ClockError)?.as_secs())
+}
+
+fn project_metrics(config: &Config, statuses: Vec<ProjectStatus>, logger: &Logger) -> Vec<ProjectMetrics> {
+  statuses
+    .into_iter()
+    .filter_map(|project_status| {
+      let status = project_status.status?;
+      let dirty = config
+        .projects
+        .get(&project_status.name)
+        .and_then(|project| Repository::open(config.checkout_path(project, logger)).ok())
+        .and_then(|repo| is_dirty(&repo).ok())
+        .unwrap_or(false);
+      Some(ProjectMetrics {
+        name: project_status.name,
+        behind: status.upstream.as_ref().map(|_| status.behind),
+        dirty,
+        last_fetch: status.last_fetch,
+      })
+    })
+    .collect()
+}
+
+fn fetch_round(mut config: Config, selector: &ProjectSelector, logger: &Logger) -> Result<Vec<ProjectMetrics>, AppError> {
+  // a fetch every few minutes is not worth a notification
+  config.settings.notify = None;
+  config.settings.notify_command = None;
+  let options = SyncOptions {
+    no_progress_bar: true,
+    only_existing: true,
+    ff_merge: false,
+    selector: selector.clone(),
+    lock_mode: LockMode::Skip,
+    ..SyncOptions::default()
+  };
+  sync::synchronize(Ok(config.clone()), &options, logger)?;
+  let statuses = status::refresh(&config, selector, true, logger)?;
+  Ok(project_metrics(&config, statuses, logger))
+}
+
+/// Fetches the selected projects every `interval` until interrupted, without touching working trees or cloning anything.
+/// The status cache is refreshed after every round, so `fw status` answers from fresh data.
+/// With `metrics_listen` the results of the last round are served to Prometheus at `/metrics`.
+pub fn daemon(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  interval: Duration,
+  metrics_listen: Option<SocketAddr>,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let mut first_config = Some(maybe_config?);
+  let path = state_file()?;
+  let metrics = Arc::new(Mutex::new(Metrics::default()));
+  if let Some(address) = metrics_listen {
+    metrics::serve(address, Arc::clone(&metrics), logger)?;
+    info!(logger, "Serving metrics"; "address" => format!("http://{}/metrics", address));
+  }
+  info!(logger, "Daemon started"; "interval" => interval.as_secs(), "pid" => std::process::id());
+  while !signal::interrupted() {
+    // the config is read again every round, so projects added in the meantime are picked up
+    let result = first_config
+      .take()
+      .map(Ok)
+      .unwrap_or_else(|| config::read_config(logger))
+      .and_then(|config| fetch_round(config, selector, logger));
+    if let Err(AppError::Interrupted) = result {
+      break;
+    }
+    let last_run = now()?;
+    // a failed round keeps the metrics of the last good one, their fetch age shows how stale they are
+    match &result {
+      Ok(projects) => {
+        let mut metrics = metrics.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        metrics.projects = projects.clone();
+        metrics.last_round = Some(last_run);
+      }
+      Err(e) => warn!(logger, "Fetch round failed"; "error" => format!("{}", e)),
+    }
+    let state = DaemonState {
+      pid: std::process::id(),
+      interval: interval.as_secs(),
+      last_run,
+      error: result.err().map(|e| format!("{}", e)),
+    };
+    if let Err(e) = write_state(&path, &state) {
+      warn!(logger, "Could not write daemon state"; "path" => path.to_string_lossy().to_string(), "error" => format!("{}", e));
+    }
+    signal::sleep_interruptibly(interval);
+  }
+  info!(logger, "Daemon stopped");
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_parse_interval() {
+    assert_that(&parse_interval("30m")).is_equal_to(Ok(Duration::from_secs(30 * 60)));
+    assert_that(&parse_interval("1h")).is_equal_to(Ok(Duration::from_secs(60 * 60)));
+    assert_that(&parse_interval("45")).is_equal_to(Ok(Duration::from_secs(45)));
+    assert_that(&parse_interval("0m").is_err()).is_true();
+    assert_that(&parse_interval("5w").is_err()).is_true();
+    assert_that(&parse_interval("m").is_err()).is_true();
+  }
+
+  #[test]
+  fn test_describe_stale_daemon() {
+    let state = DaemonState {
+      pid: 42,
+      interval: 60,
+      last_run: 1000,
+      error: None,
+    };
+    assert_that(&describe(&state, 1030)).is_equal_to(("fw daemon (pid 42) fetched just now".to_owned(), Tone::Muted));
+    assert_that(&describe(&state, 1000 + 60 * 3).1).is_equal_to(Tone::Warning);
+  }
+}
@@ -0,0 +1,310 @@
+//! Lint rules for the shell code of hooks. They work on words and operators of a rough shell tokenizer, which is good
+//! enough for the one-liners hooks usually are.
+
+/// Lets a hook opt out of rules, e.g. `# fw-lint: allow cd-without-and, tilde-not-expanded`.
+const SUPPRESSION: &str = "fw-lint: allow";
+
+/// Something a rule found in a hook. Rules are `tilde-not-expanded`, `cd-without-and`, `rm-rf-variable` and
+/// `shell-syntax`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+  pub rule: &'static str,
+  /// `rm -rf` with a variable can delete everything if the variable is empty, the other rules are about surprises.
+  pub dangerous: bool,
+  pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quoting {
+  Unquoted,
+  Single,
+  Double,
+  Escaped,
+}
+
+/// The characters of a word, each with how it was quoted.
+type Word = [(char, Quoting)];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Word(Vec<(char, Quoting)>),
+  /// `;`, `&`, `&&`, `||`, `|` or a newline.
+  Operator(String),
+  Comment(String),
+}
+
+fn text(word: &Word) -> String {
+  word.iter().map(|(c, _)| *c).collect()
+}
+
+fn tokenize(hook: &str) -> Vec<Token> {
+  let mut tokens = Vec::new();
+  let mut word: Vec<(char, Quoting)> = Vec::new();
+  let mut quoting = Quoting::Unquoted;
+  let mut chars = hook.chars().peekable();
+  while let Some(c) = chars.next() {
+    match (quoting, c) {
+      (Quoting::Single, '\'') | (Quoting::Double, '"') => quoting = Quoting::Unquoted,
+      (Quoting::Single, c) => word.push((c, Quoting::Single)),
+      (Quoting::Double, '\\') => match chars.next() {
+        Some(escaped) if "$`\"\\".contains(escaped) => word.push((escaped, Quoting::Escaped)),
+        Some(other) => word.extend([('\\', Quoting::Double), (other, Quoting::Double)]),
+        None => word.push(('\\', Quoting::Double)),
+      },
+      (Quoting::Double, c) => word.push((c, Quoting::Double)),
+      (_, '\'') => quoting = Quoting::Single,
+      (_, '"') => quoting = Quoting::Double,
+      (_, '\\') => {
+        if let Some(escaped) = chars.next().filter(|escaped| *escaped != '\n') {
+          word.push((escaped, Quoting::Escaped));
+        }
+      }
+      (_, '#') if word.is_empty() => {
+        let mut comment = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '\n') {
+          comment.push(c);
+        }
+        tokens.push(Token::Comment(comment));
+      }
+      (_, c) if c == ' ' || c == '\t' || c == '\n' || c == ';' || c == '&' || c == '|' => {
+        if !word.is_empty() {
+          tokens.push(Token::Word(std::mem::take(&mut word)));
+        }
+        if c == '&' || c == '|' {
+          let operator = match chars.next_if_eq(&c) {
+            Some(_) => format!("{}{}", c, c),
+            None => c.to_string(),
+          };
+          tokens.push(Token::Operator(operator));
+        } else if c == ';' || c == '\n' {
+          tokens.push(Token::Operator(c.to_string()));
+        }
+      }
+      (_, c) => word.push((c, Quoting::Unquoted)),
+    }
+  }
+  if !word.is_empty() {
+    tokens.push(Token::Word(word));
+  }
+  tokens
+}
+
+/// The commands of the hook with the operator that follows each of them.
+fn commands(tokens: &[Token]) -> Vec<(Vec<&Word>, Option<&str>)> {
+  let mut commands = Vec::new();
+  let mut command = Vec::new();
+  for token in tokens {
+    match token {
+      Token::Word(word) => command.push(word.as_slice()),
+      Token::Operator(operator) => {
+        if !command.is_empty() {
+          commands.push((std::mem::take(&mut command), Some(operator.as_str())));
+        }
+      }
+      Token::Comment(_) => (),
+    }
+  }
+  if !command.is_empty() {
+    commands.push((command, None));
+  }
+  commands
+}
+
+// The shell expands `~` only unquoted at the start of a word or of the value of an assignment like `DIR=~/src`.
+fn tilde_not_expanded(word: &Word) -> bool {
+  word.iter().enumerate().any(|(i, (c, quoting))| {
+    if *c != '~' || *quoting == Quoting::Escaped {
+      return false;
+    }
+    if *quoting != Quoting::Unquoted {
+      return true;
+    }
+    let before = text(&word[..i]);
+    let assignment = before
+      .strip_suffix('=')
+      .map(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+      .unwrap_or(false);
+    i > 0 && !assignment
+  })
+}
+
+fn rm_rf_variable(command: &[&Word]) -> Option<String> {
+  if command.first().map(|word| text(word)) != Some("rm".to_owned()) {
+    return None;
+  }
+  let (mut recursive, mut force) = (false, false);
+  let mut arguments = Vec::new();
+  let mut options_done = false;
+  for word in &command[1..] {
+    let word = text(word);
+    if options_done || !word.starts_with('-') {
+      arguments.push(word);
+    } else if word == "--" {
+      options_done = true;
+    } else if word == "--recursive" {
+      recursive = true;
+    } else if word == "--force" {
+      force = true;
+    } else if !word.starts_with("--") {
+      recursive = recursive || word.contains('r') || word.contains('R');
+      force = force || word.contains('f');
+    }
+  }
+  // `${DIR:?}` fails the hook instead of expanding to nothing
+  let risky: Vec<String> = arguments
+    .into_iter()
+    .filter(|argument| argument.contains('$') && !argument.contains(":?"))
+    .collect();
+  if recursive && force && !risky.is_empty() {
+    Some(risky.join(" "))
+  } else {
+    None
+  }
+}
+
+/// What the hook uses that `shell` does not understand, `shell` being the program that runs it like `sh` or `fish`.
+fn foreign_syntax(tokens: &[Token], shell: &str) -> Vec<&'static str> {
+  let mut found = Vec::new();
+  let mut first_word = true;
+  for token in tokens {
+    let word = match token {
+      Token::Word(word) => word,
+      Token::Operator(_) => {
+        first_word = true;
+        continue;
+      }
+      Token::Comment(_) => continue,
+    };
+    let unquoted: String = word
+      .iter()
+      .filter(|(_, quoting)| *quoting != Quoting::Single && *quoting != Quoting::Escaped)
+      .map(|(c, _)| *c)
+      .collect();
+    let command = if first_word { text(word) } else { String::new() };
+    let candidates: Vec<(&'static str, bool)> = match shell {
+      "sh" | "dash" | "ash" => vec![
+        ("[[ ]]", command == "[["),
+        ("function", command == "function"),
+        ("source", command == "source"),
+        ("<<<", unquoted.contains("<<<")),
+        (
+          "$'...'",
+          word.windows(2).any(|pair| pair[0] == ('$', Quoting::Unquoted) && pair[1].1 == Quoting::Single),
+        ),
+        ("arrays", unquoted.contains("=(")),
+      ],
+      "fish" => vec![
+        ("[[ ]]", command == "[["),
+        ("`...`", unquoted.contains('`')),
+        ("${...}", unquoted.contains("${")),
+        ("$((...))", unquoted.contains("$((")),
+      ],
+      _ => vec![],
+    };
+    for (syntax, used) in candidates {
+      if used && !found.contains(&syntax) {
+        found.push(syntax);
+      }
+    }
+    first_word = false;
+  }
+  found
+}
+
+fn suppressed(tokens: &[Token]) -> Vec<String> {
+  tokens
+    .iter()
+    .filter_map(|token| match token {
+      Token::Comment(comment) => comment.split_once(SUPPRESSION).map(|(_, rules)| rules),
+      _ => None,
+    })
+    .flat_map(|rules| rules.split(|c: char| c == ',' || c.is_whitespace()))
+    .filter(|rule| !rule.is_empty())
+    .map(str::to_owned)
+    .collect()
+}
+
+/// Lints one hook that runs with `shell`, the program and arguments from the config like `["sh", "-c"]`.
+pub fn lint_hook(hook: &str, shell: &[String]) -> Vec<Lint> {
+  let tokens = tokenize(hook);
+  let commands = commands(&tokens);
+  let mut lints = Vec::new();
+
+  if tokens.iter().any(|token| matches!(token, Token::Word(word) if tilde_not_expanded(word))) {
+    lints.push(Lint {
+      rule: "tilde-not-expanded",
+      dangerous: false,
+      message: "~ in quotes or inside a word is not expanded to the home directory, use $HOME".to_owned(),
+    });
+  }
+  let cd_then_more = commands
+    .iter()
+    .enumerate()
+    .any(|(i, (command, operator))| text(command[0]) == "cd" && matches!(operator, Some(";") | Some("\n")) && i + 1 < commands.len());
+  if cd_then_more {
+    lints.push(Lint {
+      rule: "cd-without-and",
+      dangerous: false,
+      message: "the commands after a failed cd run in the wrong directory, use cd DIR && ...".to_owned(),
+    });
+  }
+  if let Some(arguments) = commands.iter().find_map(|(command, _)| rm_rf_variable(command)) {
+    lints.push(Lint {
+      rule: "rm-rf-variable",
+      dangerous: true,
+      message: format!("rm -rf {} deletes more than intended if a variable is empty, use ${{VAR:?}}", arguments),
+    });
+  }
+  let program = shell.first().map(|program| program.rsplit('/').next().unwrap_or(program)).unwrap_or("sh");
+  let foreign = foreign_syntax(&tokens, program);
+  if !foreign.is_empty() {
+    lints.push(Lint {
+      rule: "shell-syntax",
+      dangerous: false,
+      message: format!("{} does not understand {}", program, foreign.join(", ")),
+    });
+  }
+
+  let suppressed = suppressed(&tokens);
+  lints.retain(|lint| !suppressed.iter().any(|rule| rule == lint.rule));
+  lints
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  fn rules(hook: &str, shell: &str) -> Vec<&'static str> {
+    lint_hook(hook, &[shell.to_owned(), "-c".to_owned()])
+      .into_iter()
+      .map(|lint| lint.rule)
+      .collect()
+  }
+
+  #[test]
+  fn test_lint_hook() {
+    let none: Vec<&str> = vec![];
+    assert_that(&rules("cd ~/src && DIR=~/x make", "sh")).is_equal_to(none.clone());
+    assert_that(&rules("cp config \"~/.config/app\"", "sh")).is_equal_to(vec!["tilde-not-expanded"]);
+    assert_that(&rules("make --prefix=~/bin; echo \\~", "sh")).is_equal_to(vec!["tilde-not-expanded"]);
+    assert_that(&rules("cd build; make", "sh")).is_equal_to(vec!["cd-without-and"]);
+    assert_that(&rules("cd build\nmake", "sh")).is_equal_to(vec!["cd-without-and"]);
+    assert_that(&rules("make; cd build", "sh")).is_equal_to(none.clone());
+    assert_that(&rules("rm -rf \"$BUILD_DIR/out\"", "sh")).is_equal_to(vec!["rm-rf-variable"]);
+    assert_that(&rules("rm -r -f $TMP", "sh")).is_equal_to(vec!["rm-rf-variable"]);
+    assert_that(&rules("rm -rf \"${BUILD_DIR:?}/out\" target", "sh")).is_equal_to(none.clone());
+    assert_that(&rules("rm -f $TMP", "sh")).is_equal_to(none.clone());
+    assert_that(&rules("[[ -f .envrc ]] && source .envrc", "sh")).is_equal_to(vec!["shell-syntax"]);
+    assert_that(&rules("[[ -f .envrc ]] && source .envrc", "/bin/bash")).is_equal_to(none.clone());
+    assert_that(&rules("echo '[[ ${x} ]]'", "fish")).is_equal_to(none.clone());
+    assert_that(&rules("set -x V ${HOME}", "fish")).is_equal_to(vec!["shell-syntax"]);
+    assert_that(&rules("cd build; rm -rf $OUT # fw-lint: allow cd-without-and, rm-rf-variable", "sh")).is_equal_to(none);
+  }
+
+  #[test]
+  fn test_lint_messages() {
+    let lints = lint_hook("[[ -d x ]] && source x.sh <<< $'a'", &["sh".to_owned(), "-c".to_owned()]);
+    assert_that(&lints[0].message).is_equal_to("sh does not understand [[ ]], source, <<<, $'...'".to_owned());
+  }
+}
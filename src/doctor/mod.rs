@@ -0,0 +1,276 @@
+mod lint;
+
+use crate::config::{Config, WorkspaceConflict};
+use crate::errors::AppError;
+use crate::git::{default_branch, uses_lfs};
+use crate::output::{Cell, Table, Tone};
+use crate::project::duplicates;
+use git2::{BranchType, Repository};
+use slog::{debug, Logger};
+use std::collections::BTreeMap;
+
+/// How bad a finding of `fw doctor` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  /// fw copes with it, but possibly not the way the user expects.
+  Warning,
+  /// fw has to guess, fails `fw doctor`.
+  Error,
+}
+
+/// Something `fw doctor` found wrong with a project, a tag or the settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+  pub severity: Severity,
+  /// The project, `tag <name>` or `settings`.
+  pub subject: String,
+  pub message: String,
+}
+
+fn describe_workspace_conflict(conflict: &WorkspaceConflict) -> String {
+  let tags: Vec<String> = conflict
+    .candidates
+    .iter()
+    .rev()
+    .map(|c| format!("{} (priority {}) -> {}", c.tag, c.priority, c.workspace))
+    .collect();
+  if conflict.tie {
+    format!(
+      "tags define contradictory workspaces with equal priority, using {}: {}",
+      conflict.chosen.workspace,
+      tags.join(", ")
+    )
+  } else {
+    format!(
+      "tags define different workspaces, using {} by priority: {}",
+      conflict.chosen.workspace,
+      tags.join(", ")
+    )
+  }
+}
+
+fn workspace_findings(config: &Config, logger: &Logger) -> Vec<Finding> {
+  config
+    .projects
+    .values()
+    .filter(|project| project.override_path.is_none() || project.workspace_override().is_some())
+    .filter_map(|project| {
+      config.workspace_conflict(project, logger).map(|conflict| Finding {
+        severity: if conflict.tie { Severity::Error } else { Severity::Warning },
+        subject: project.name.clone(),
+        message: describe_workspace_conflict(&conflict),
+      })
+    })
+    .collect()
+}
+
+// The checkout moves with the directory fw runs in, `./` makes it relative to the workspace.
+fn override_path_findings(config: &Config) -> Vec<Finding> {
+  config
+    .projects
+    .values()
+    .filter(|project| project.has_cwd_relative_override())
+    .map(|project| Finding {
+      severity: Severity::Warning,
+      subject: project.name.clone(),
+      message: format!(
+        "override_path {} is relative to the current directory, start it with ./ to make it relative to the workspace",
+        project.override_path.clone().unwrap_or_default()
+      ),
+    })
+    .collect()
+}
+
+fn lint_findings(subject: &str, hooks: &[(&str, &Option<String>)], shell: &[String]) -> Vec<Finding> {
+  hooks
+    .iter()
+    .filter_map(|(name, hook)| hook.as_ref().map(|hook| (name, hook)))
+    .flat_map(|(name, hook)| {
+      lint::lint_hook(hook, shell).into_iter().map(move |lint| Finding {
+        severity: if lint.dangerous { Severity::Error } else { Severity::Warning },
+        subject: subject.to_owned(),
+        message: format!("{}: {} [{}]", name, lint.message, lint.rule),
+      })
+    })
+    .collect()
+}
+
+// `API` and `api` are the same directory on case-insensitive file systems like the default ones of macOS and Windows.
+fn case_findings(config: &Config, logger: &Logger) -> Vec<Finding> {
+  let mut by_path: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+  for project in config.projects.values() {
+    let path = config.actual_path_to_project(project, logger).to_string_lossy().to_lowercase();
+    by_path.entry(path).or_default().push(&project.name);
+  }
+  by_path
+    .values()
+    .filter(|names| names.len() > 1)
+    .flat_map(|names| {
+      names.iter().map(move |name| Finding {
+        severity: Severity::Warning,
+        subject: (*name).to_owned(),
+        message: format!(
+          "checkout only differs in case from that of {}, they collide on macOS and Windows",
+          names.iter().filter(|other| *other != name).cloned().collect::<Vec<_>>().join(", ")
+        ),
+      })
+    })
+    .collect()
+}
+
+// Usually the same repository added once by SSH and once by HTTPS URL, synced and checked out twice.
+fn duplicate_findings(config: &Config, logger: &Logger) -> Vec<Finding> {
+  duplicates(config, logger)
+    .iter()
+    .flat_map(|group| {
+      group.iter().map(move |project| Finding {
+        severity: Severity::Warning,
+        subject: project.name.clone(),
+        message: format!(
+          "same repository as {}, fw dedupe merges them",
+          group
+            .iter()
+            .filter(|other| other.name != project.name)
+            .map(|other| other.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+        ),
+      })
+    })
+    .collect()
+}
+
+// Without lfs the checkout only has the pointer files of git LFS, which builds usually trip over.
+fn lfs_findings(config: &Config, logger: &Logger) -> Vec<Finding> {
+  config
+    .projects
+    .values()
+    .filter(|project| !project.is_directory() && !config.resolve_lfs(project, logger))
+    .filter(|project| uses_lfs(&config.checkout_path(project, logger)))
+    .map(|project| Finding {
+      severity: Severity::Warning,
+      subject: project.name.clone(),
+      message: "uses git LFS but lfs is off, the checkout only has pointer files".to_owned(),
+    })
+    .collect()
+}
+
+// Every hook is linted for the shell that runs it.
+fn hook_findings(config: &Config, logger: &Logger) -> Vec<Finding> {
+  let settings = &config.settings;
+  let settings_shell = settings.get_shell_or_default();
+  let mut findings = lint_findings(
+    "settings",
+    &[
+      ("default_after_clone", &settings.default_after_clone),
+      ("default_after_workon", &settings.default_after_workon),
+      ("project_defaults.after_clone", &settings.after_clone),
+      ("project_defaults.after_workon", &settings.after_workon),
+    ],
+    &settings_shell,
+  );
+  for (name, tag) in settings.tags.iter().flatten() {
+    let shell = tag.shell.clone().unwrap_or_else(|| settings_shell.clone());
+    let hooks = [("after_clone", &tag.after_clone), ("after_workon", &tag.after_workon)];
+    findings.extend(lint_findings(&format!("tag {}", name), &hooks, &shell));
+  }
+  for project in config.projects.values() {
+    let hooks = [("after_clone", &project.after_clone), ("after_workon", &project.after_workon)];
+    findings.extend(lint_findings(&project.name, &hooks, &config.resolve_shell(project, logger)));
+  }
+  findings
+}
+
+// Conditional tags are not checked when the config is loaded, they depend on the checkout.
+fn tag_group_findings(config: &Config, logger: &Logger) -> Vec<Finding> {
+  config
+    .projects
+    .values()
+    .flat_map(|project| {
+      config
+        .settings
+        .tag_group_conflicts(&config.effective_tags(project, logger))
+        .into_iter()
+        .map(move |(group, tags)| Finding {
+          severity: Severity::Error,
+          subject: project.name.clone(),
+          message: format!("has the tags {} of tag group {}, only one of them is allowed", tags.join(", "), group),
+        })
+    })
+    .collect()
+}
+
+// Only local branches count, the default branch is exempt.
+fn branch_findings(config: &Config, logger: &Logger) -> Vec<Finding> {
+  let mut findings = Vec::new();
+  for project in config.projects.values().filter(|project| !project.is_directory()) {
+    let policy = config.resolve_branch_policy(project, logger);
+    if policy.is_empty() {
+      continue;
+    }
+    let repo = match Repository::open(config.checkout_path(project, logger)) {
+      Ok(repo) => repo,
+      Err(_) => continue,
+    };
+    let default = default_branch(&repo);
+    let names: Vec<String> = repo
+      .branches(Some(BranchType::Local))
+      .into_iter()
+      .flatten()
+      .flatten()
+      .filter_map(|(branch, _)| branch.name().ok().flatten().map(ToOwned::to_owned))
+      .filter(|name| Some(name) != default.as_ref() && name != "main" && name != "master")
+      .filter(|name| !policy.allows(name))
+      .collect();
+    if !names.is_empty() {
+      findings.push(Finding {
+        severity: Severity::Warning,
+        subject: project.name.clone(),
+        message: format!("branches do not follow the naming policy of its tags: {}", names.join(", ")),
+      });
+    }
+  }
+  findings
+}
+
+/// Runs all checks against the config.
+pub fn findings(config: &Config, logger: &Logger) -> Vec<Finding> {
+  let mut findings = workspace_findings(config, logger);
+  findings.extend(override_path_findings(config));
+  findings.extend(case_findings(config, logger));
+  findings.extend(duplicate_findings(config, logger));
+  findings.extend(lfs_findings(config, logger));
+  findings.extend(hook_findings(config, logger));
+  findings.extend(tag_group_findings(config, logger));
+  findings.extend(branch_findings(config, logger));
+  findings.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.subject.cmp(&b.subject)));
+  findings
+}
+
+/// Lists problems of the config and fails if any of them is an error.
+pub fn doctor(maybe_config: Result<Config, AppError>, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let findings = findings(&config, logger);
+  debug!(logger, "Checks done"; "findings" => findings.len());
+  if findings.is_empty() {
+    println!("{}", Tone::Good.paint("No problems found"));
+    return Ok(());
+  }
+
+  let mut table = Table::new();
+  for finding in &findings {
+    let severity = match finding.severity {
+      Severity::Error => Cell::new("error", Tone::Bad),
+      Severity::Warning => Cell::new("warning", Tone::Warning),
+    };
+    table.add_row(vec![severity, Cell::plain(finding.subject.clone()), Cell::plain(finding.message.clone())]);
+  }
+  table.print();
+
+  let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+  if errors > 0 {
+    Err(AppError::UserError(format!("fw doctor found {} problem(s) that need fixing", errors)))
+  } else {
+    Ok(())
+  }
+}
@@ -0,0 +1,214 @@
+use crate::config::{selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::output::{Cell, Table, Tone};
+use crate::signal;
+use ignore::{WalkBuilder, WalkState};
+use slog::{debug, Logger};
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// git's own defaults for gc.auto and gc.autoPackLimit
+const GC_LOOSE_OBJECTS: u64 = 6700;
+const GC_PACKS: u64 = 50;
+const SHALLOW_MIN_GIT_BYTES: u64 = 500 * 1024 * 1024;
+
+/// Disk usage of one checkout.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+  /// Bytes in `.git`, or in the whole directory of a bare project.
+  pub git: u64,
+  pub worktree: u64,
+  pub loose_objects: u64,
+  pub packs: u64,
+}
+
+impl Usage {
+  pub fn total(&self) -> u64 {
+    self.git + self.worktree
+  }
+
+  /// Why the repository could use some care, if it could.
+  pub fn hint(&self) -> Option<String> {
+    if self.loose_objects > GC_LOOSE_OBJECTS || self.packs > GC_PACKS {
+      Some(format!("git gc ({} loose objects, {} packs)", self.loose_objects, self.packs))
+    } else if self.git > SHALLOW_MIN_GIT_BYTES && self.git > 2 * self.worktree {
+      Some("history dominates, consider a shallow clone".to_owned())
+    } else {
+      None
+    }
+  }
+}
+
+fn format_size(bytes: u64) -> String {
+  const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+  if bytes < 1024 {
+    return format!("{} B", bytes);
+  }
+  let mut size = bytes as f64 / 1024.0;
+  let mut unit = 0;
+  while size >= 1024.0 && unit < UNITS.len() - 1 {
+    size /= 1024.0;
+    unit += 1;
+  }
+  format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[derive(Default)]
+struct Counters {
+  git: AtomicU64,
+  worktree: AtomicU64,
+  loose_objects: AtomicU64,
+  packs: AtomicU64,
+}
+
+impl Counters {
+  fn count(&self, relative: &Path, bytes: u64, bare: bool) {
+    let components: Vec<Component<'_>> = relative.components().collect();
+    let (in_git, git_path) = if bare {
+      (true, &components[..])
+    } else {
+      match components.split_first() {
+        Some((first, rest)) if first.as_os_str() == ".git" => (true, rest),
+        _ => (false, &components[..]),
+      }
+    };
+    if !in_git {
+      self.worktree.fetch_add(bytes, Ordering::Relaxed);
+      return;
+    }
+    self.git.fetch_add(bytes, Ordering::Relaxed);
+    let names: Vec<String> = git_path.iter().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+    match names.as_slice() {
+      [objects, dir, _] if objects == "objects" && dir.len() == 2 && dir.chars().all(|c| c.is_ascii_hexdigit()) => {
+        self.loose_objects.fetch_add(1, Ordering::Relaxed);
+      }
+      [objects, pack, file] if objects == "objects" && pack == "pack" && file.ends_with(".pack") => {
+        self.packs.fetch_add(1, Ordering::Relaxed);
+      }
+      _ => {}
+    }
+  }
+}
+
+/// Adds up the sizes of all files in `root` with a parallel walker. Nothing is skipped, ignored build output counts too.
+pub fn usage(root: &Path, bare: bool, logger: &Logger) -> Usage {
+  let counters = Counters::default();
+  WalkBuilder::new(root).standard_filters(false).build_parallel().run(|| {
+    Box::new(|entry| {
+      if signal::interrupted() {
+        return WalkState::Quit;
+      }
+      match entry {
+        Ok(entry) => {
+          if let Ok(metadata) = entry.metadata() {
+            if metadata.is_file() {
+              counters.count(entry.path().strip_prefix(root).unwrap_or(entry.path()), metadata.len(), bare);
+            }
+          }
+        }
+        Err(e) => debug!(logger, "Skipping unreadable entry"; "error" => format!("{}", e)),
+      }
+      WalkState::Continue
+    })
+  });
+  Usage {
+    git: counters.git.into_inner(),
+    worktree: counters.worktree.into_inner(),
+    loose_objects: counters.loose_objects.into_inner(),
+    packs: counters.packs.into_inner(),
+  }
+}
+
+/// Shows the disk usage of the selected projects, largest first, split into `.git` and working tree,
+/// and points out repositories that need a `git gc` or might be better off as shallow clones.
+pub fn du(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut usages: Vec<(String, Usage)> = Vec::new();
+  for project in selector.select(&config) {
+    let path = config.actual_path_to_project(project, logger);
+    if path.exists() {
+      usages.push((project.name.clone(), usage(&path, project.bare.unwrap_or_default(), logger)));
+    }
+    signal::check_interrupted()?;
+  }
+  usages.sort_by(|a, b| b.1.total().cmp(&a.1.total()).then_with(|| a.0.cmp(&b.0)));
+
+  let mut table = Table::new();
+  table.add_row(vec![
+    Cell::new("project", Tone::Heading),
+    Cell::new("total", Tone::Heading),
+    Cell::new(".git", Tone::Heading),
+    Cell::new("worktree", Tone::Heading),
+  ]);
+  let mut sum = Usage::default();
+  for (name, usage) in &usages {
+    let mut row = vec![
+      Cell::plain(name.clone()),
+      Cell::plain(format_size(usage.total())),
+      Cell::new(format_size(usage.git), Tone::Muted),
+      Cell::new(format_size(usage.worktree), Tone::Muted),
+    ];
+    if let Some(hint) = usage.hint() {
+      row.push(Cell::new(hint, Tone::Warning));
+    }
+    table.add_row(row);
+    sum.git += usage.git;
+    sum.worktree += usage.worktree;
+  }
+  table.add_row(vec![
+    Cell::new(format!("{} projects", usages.len()), Tone::Heading),
+    Cell::new(format_size(sum.total()), Tone::Heading),
+    Cell::new(format_size(sum.git), Tone::Muted),
+    Cell::new(format_size(sum.worktree), Tone::Muted),
+  ]);
+  table.print();
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use slog::o;
+  use spectral::prelude::*;
+  use std::fs;
+
+  #[test]
+  fn test_usage_splits_git_and_worktree() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    fs::create_dir_all(dir.join(".git/objects/ab")).unwrap();
+    fs::create_dir_all(dir.join(".git/objects/pack")).unwrap();
+    fs::create_dir_all(dir.join("target")).unwrap();
+    fs::write(dir.join(".git/objects/ab/cdef"), [0u8; 10]).unwrap();
+    fs::write(dir.join(".git/objects/pack/pack-1.pack"), [0u8; 100]).unwrap();
+    fs::write(dir.join(".git/objects/pack/pack-1.idx"), [0u8; 5]).unwrap();
+    fs::write(dir.join(".gitignore"), "target\n").unwrap();
+    fs::write(dir.join("target/big"), [0u8; 1000]).unwrap();
+
+    let logger = Logger::root(slog::Discard, o!());
+    assert_that(&usage(dir, false, &logger)).is_equal_to(Usage {
+      git: 115,
+      worktree: 1007,
+      loose_objects: 1,
+      packs: 1,
+    });
+  }
+
+  #[test]
+  fn test_format_size_and_hint() {
+    assert_that(&format_size(512)).is_equal_to("512 B".to_owned());
+    assert_that(&format_size(1536)).is_equal_to("1.5 KiB".to_owned());
+    assert_that(&format_size(3 * 1024 * 1024 * 1024)).is_equal_to("3.0 GiB".to_owned());
+
+    let packed = Usage { packs: 60, ..Usage::default() };
+    assert_that(&packed.hint()).is_equal_to(Some("git gc (0 loose objects, 60 packs)".to_owned()));
+    let history = Usage {
+      git: 2 * 1024 * 1024 * 1024,
+      worktree: 100,
+      ..Usage::default()
+    };
+    assert_that(&history.hint().unwrap()).contains("shallow");
+    assert_that(&Usage::default().hint()).is_none();
+  }
+}
@@ -15,8 +15,10 @@ pub enum AppError {
   Regex(regex::Error),
   TomlSerError(toml::ser::Error),
   TomlDeError(toml::de::Error),
+  YamlError(serde_yaml::Error),
   WalkdirError(walkdir::Error),
   ReqwestError(reqwest::Error),
+  Interrupted,
 }
 
 macro_rules! app_error_from {
@@ -52,8 +54,10 @@ impl fmt::Display for AppError {
       AppError::Regex(ref err) => write!(f, "Regex error: {}", err),
       AppError::TomlSerError(ref err) => write!(f, "toml serialization error: {}", err),
       AppError::TomlDeError(ref err) => write!(f, "toml read error: {}", err),
+      AppError::YamlError(ref err) => write!(f, "yaml error: {}", err),
       AppError::WalkdirError(ref err) => write!(f, "walkdir error: {}", err),
       AppError::ReqwestError(ref err) => write!(f, "reqwest error: {}", err),
+      AppError::Interrupted => write!(f, "Interrupted"),
     }
   }
 }
@@ -71,21 +75,24 @@ impl Error for AppError {
       AppError::Regex(ref err) => err.description(),
       AppError::TomlSerError(ref err) => err.description(),
       AppError::TomlDeError(ref err) => err.description(),
+      AppError::YamlError(ref err) => err.description(),
       AppError::WalkdirError(ref err) => err.description(),
       AppError::ReqwestError(ref err) => err.description(),
+      AppError::Interrupted => "Interrupted",
     }
   }
 
   fn cause(&self) -> Option<&dyn Error> {
     match *self {
       AppError::IO(ref err) => Some(err),
-      AppError::UserError(_) | AppError::RuntimeError(_) | AppError::InternalError(_) => None,
+      AppError::UserError(_) | AppError::RuntimeError(_) | AppError::InternalError(_) | AppError::Interrupted => None,
       AppError::BadJson(ref err) => Some(err),
       AppError::ClockError(ref err) => Some(err),
       AppError::GitError(ref err) => Some(err),
       AppError::Regex(ref err) => Some(err),
       AppError::TomlSerError(ref err) => Some(err),
       AppError::TomlDeError(ref err) => Some(err),
+      AppError::YamlError(ref err) => Some(err),
       AppError::WalkdirError(ref err) => Some(err),
       AppError::ReqwestError(ref err) => Some(err),
     }
@@ -94,7 +101,7 @@ impl Error for AppError {
 
 impl From<core::num::ParseIntError> for AppError {
   fn from(err: core::num::ParseIntError) -> AppError {
-    AppError::UserError(format!("Type error: {}", err.to_string()))
+    AppError::UserError(format!("Type error: {}", err))
   }
 }
 
@@ -104,5 +111,6 @@ app_error_from!(serde_json::Error, BadJson);
 app_error_from!(regex::Error, Regex);
 app_error_from!(toml::ser::Error, TomlSerError);
 app_error_from!(toml::de::Error, TomlDeError);
+app_error_from!(serde_yaml::Error, YamlError);
 app_error_from!(walkdir::Error, WalkdirError);
 app_error_from!(reqwest::Error, ReqwestError);
@@ -0,0 +1,111 @@
+pub mod nix;
+
+use crate::config::{self, project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::output::{Cell, Table, Tone};
+use crate::trust;
+use slog::Logger;
+use std::fs;
+use std::path::Path;
+
+const ENVRC_HEADER: &str = "# Generated by fw export direnv, run it again instead of editing this file.";
+
+fn shell_quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// A `.envrc` that sets up the project like `fw workon` does: `FW_PROJECT` and the resolved after_workon hooks,
+/// whose changes to the environment direnv picks up.
+fn envrc(config: &Config, project: &Project, logger: &Logger) -> String {
+  let mut lines = vec![ENVRC_HEADER.to_owned(), format!("export FW_PROJECT={}", shell_quote(&project.name))];
+  lines.extend(trust::approved_hooks(
+    config,
+    project,
+    "after_workon",
+    config.resolve_after_workon(logger, project),
+    logger,
+  ));
+  lines.push(String::new());
+  lines.join("\n")
+}
+
+// An .envrc that fw did not write belongs to someone and is only replaced with `force`.
+fn write_envrc(path: &Path, content: &str, force: bool) -> Result<(String, Tone), AppError> {
+  let envrc_path = path.join(".envrc");
+  match fs::read_to_string(&envrc_path) {
+    Ok(existing) if existing == content => return Ok(("up to date".to_owned(), Tone::Muted)),
+    Ok(existing) if !existing.starts_with(ENVRC_HEADER) && !force => {
+      return Err(AppError::UserError("has an .envrc not written by fw, use --force to replace it".to_owned()))
+    }
+    _ => {}
+  }
+  config::write_atomically(&envrc_path, content)?;
+  Ok(("written, run direnv allow".to_owned(), Tone::Good))
+}
+
+fn export_project(config: &Config, project: &Project, force: bool, logger: &Logger) -> Result<(String, Tone), AppError> {
+  let path = config.actual_path_to_project(project, logger);
+  if !path.exists() {
+    return Err(AppError::UserError(format!("not checked out at {}", path.to_string_lossy())));
+  }
+  write_envrc(&path, &envrc(config, project, logger), force)
+}
+
+/// Writes the `.envrc` for direnv into the checkout of `project_name`.
+pub fn direnv(maybe_config: Result<Config, AppError>, project_name: &str, force: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let project = config
+    .projects
+    .get(project_name)
+    .ok_or_else(|| AppError::UserError(format!("project {} not found", project_name)))?;
+  let (message, _) = export_project(&config, project, force, logger)?;
+  println!("{}: {}", project.name, message);
+  Ok(())
+}
+
+/// Writes the `.envrc` for direnv into every selected project that is checked out.
+pub fn all_direnv(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, force: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut table = Table::new();
+  let mut failed = 0;
+  for project in selector.select(&config) {
+    if !config.actual_path_to_project(project, logger).exists() {
+      continue;
+    }
+    let (message, tone) = export_project(&config, project, force, logger).unwrap_or_else(|e| {
+      failed += 1;
+      match e {
+        AppError::UserError(message) => (message, Tone::Bad),
+        other => (format!("{}", other), Tone::Bad),
+      }
+    });
+    table.add_row(vec![Cell::plain(project.name.clone()), Cell::new(message, tone)]);
+  }
+  table.print();
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_write_envrc_keeps_foreign_files() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let content = format!("{}\nexport FW_PROJECT={}\n", ENVRC_HEADER, shell_quote("it's"));
+    assert_that(&content).contains("export FW_PROJECT='it'\\''s'");
+
+    fs::write(dir.join(".envrc"), "use nix\n").unwrap();
+    assert_that(&write_envrc(dir, &content, false).is_err()).is_true();
+    assert_that(&write_envrc(dir, &content, true).unwrap().1).is_equal_to(Tone::Good);
+    assert_that(&write_envrc(dir, &content, false).unwrap().1).is_equal_to(Tone::Muted);
+    assert_that(&fs::read_to_string(dir.join(".envrc")).unwrap()).is_equal_to(content);
+  }
+}
@@ -0,0 +1,154 @@
+use crate::config::settings::without_tokens;
+use crate::config::Config;
+use crate::errors::AppError;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+const NIX_KEYWORDS: [&str; 10] = ["assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with"];
+
+fn nix_string(value: &str) -> String {
+  let escaped = value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace("${", "\\${")
+    .replace('\n', "\\n")
+    .replace('\r', "\\r")
+    .replace('\t', "\\t");
+  format!("\"{}\"", escaped)
+}
+
+fn nix_attribute_name(name: &str) -> String {
+  let mut chars = name.chars();
+  let identifier = chars.next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false)
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '\'')
+    && !NIX_KEYWORDS.contains(&name);
+  if identifier {
+    name.to_owned()
+  } else {
+    nix_string(name)
+  }
+}
+
+/// Renders JSON as a Nix value. Nulls are unset options, so attributes with null values are left out.
+fn to_nix(value: &Value, indent: usize) -> String {
+  let padding = "  ".repeat(indent + 1);
+  match value {
+    Value::Null => "null".to_owned(),
+    Value::Bool(b) => b.to_string(),
+    Value::Number(n) => n.to_string(),
+    Value::String(s) => nix_string(s),
+    Value::Array(items) if items.is_empty() => "[ ]".to_owned(),
+    Value::Array(items) => {
+      let items: Vec<String> = items.iter().map(|item| format!("{}{}", padding, to_nix(item, indent + 1))).collect();
+      format!("[\n{}\n{}]", items.join("\n"), "  ".repeat(indent))
+    }
+    Value::Object(attributes) => {
+      let attributes: Vec<String> = attributes
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(name, value)| format!("{}{} = {};", padding, nix_attribute_name(name), to_nix(value, indent + 1)))
+        .collect();
+      if attributes.is_empty() {
+        "{ }".to_owned()
+      } else {
+        format!("{{\n{}\n{}}}", attributes.join("\n"), "  ".repeat(indent))
+      }
+    }
+  }
+}
+
+/// The configuration as JSON grouped like the config directory: settings, projects and tags by their subdirectory.
+fn layout(config: &Config) -> Result<Value, AppError> {
+  let mut settings = serde_json::to_value(&config.settings)?;
+  if let Value::Object(attributes) = &mut settings {
+    // tags get their own files, default_tags are derived from them
+    attributes.remove("tags");
+    attributes.remove("default_tags");
+  }
+  // tokens would end up in the world readable Nix store
+  without_tokens(&mut settings);
+
+  let mut projects: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+  for project in config.projects.values() {
+    projects
+      .entry(project.project_config_path.clone())
+      .or_default()
+      .insert(project.name.clone(), serde_json::to_value(project)?);
+  }
+  let mut tags: BTreeMap<String, Map<String, Value>> = BTreeMap::new();
+  for (name, tag) in config.settings.tags.iter().flatten() {
+    tags
+      .entry(tag.tag_config_path.clone())
+      .or_default()
+      .insert(name.clone(), serde_json::to_value(tag)?);
+  }
+  Ok(serde_json::json!({ "settings": settings, "projects": projects, "tags": tags }))
+}
+
+fn file_entries(layout: &Value) -> Vec<String> {
+  let mut entries = vec!["    \"fw/settings.toml\".source = toml.generate \"settings.toml\" fw.settings;".to_owned()];
+  for kind in &["projects", "tags"] {
+    for (directory, files) in layout[*kind].as_object().into_iter().flatten() {
+      for name in files.as_object().into_iter().flatten().map(|(name, _)| name) {
+        entries.push(format!(
+          "    {}.source = toml.generate {} fw.{}.{}.{};",
+          nix_string(&format!("fw/{}/{}/{}", kind, directory, name)),
+          nix_string(name),
+          kind,
+          nix_attribute_name(directory),
+          nix_attribute_name(name)
+        ));
+      }
+    }
+  }
+  entries
+}
+
+/// Prints the configuration as a Nix expression, or as a home-manager module that writes it to `~/.config/fw`.
+/// Tokens are left out, they belong in the environment or an encrypted settings file.
+pub fn nix(maybe_config: Result<Config, AppError>, home_manager: bool) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let layout = layout(&config)?;
+  if !home_manager {
+    println!("# Generated by fw export nix\n{}", to_nix(&layout, 0));
+    return Ok(());
+  }
+  println!(
+    "# Generated by fw export nix --home-manager\n{{ pkgs, ... }}:\nlet\n  toml = pkgs.formats.toml {{ }};\n  fw = {};\nin\n{{\n  xdg.configFile = {{\n{}\n  }};\n}}",
+    to_nix(&layout, 1),
+    file_entries(&layout).join("\n")
+  );
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_to_nix() {
+    let value = serde_json::json!({
+      "git": "git@github.com:brocode/fw.git",
+      "after_workon": "echo \"${HOME}\"",
+      "tags": ["rust"],
+      "pin": null,
+      "with": true,
+      "my.project": { "bare": false },
+    });
+    assert_that(&to_nix(&value, 0)).is_equal_to(
+      r#"{
+  after_workon = "echo \"\${HOME}\"";
+  git = "git@github.com:brocode/fw.git";
+  "my.project" = {
+    bare = false;
+  };
+  tags = [
+    "rust"
+  ];
+  "with" = true;
+}"#
+        .to_owned(),
+    );
+  }
+}
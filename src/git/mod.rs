@@ -1,36 +1,49 @@
-use crate::config::{project::Project, Config};
+use crate::config::{
+  path::expand_path,
+  project::Project,
+  settings::Settings,
+  template::{shell_word, split_shell_words},
+  Config,
+};
 use crate::errors::AppError;
+use crate::git_url::ParsedUrl;
+use crate::network;
+use crate::runtime;
+use crate::signal;
 
-use crate::spawn::spawn_maybe;
+use crate::spawn::{hook_log_path, spawn_hook, wait_until};
+use crate::trust;
 use crate::util::random_colour;
 
-use git2::build::RepoBuilder;
-use git2::{AutotagOption, Branch, Direction, FetchOptions, MergeAnalysis, ProxyOptions, Remote, RemoteCallbacks, Repository};
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{
+  AutotagOption, Branch, BranchType, Direction, ErrorClass, ErrorCode, FetchOptions, MergeAnalysis, Oid, ProxyOptions, PushOptions, Remote, RemoteCallbacks,
+  Repository, StatusOptions,
+};
 
 use regex::Regex;
 use slog::Logger;
-use slog::{debug, warn};
+use slog::{debug, info, warn};
 use std::borrow::ToOwned;
 
 use std::env;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
 
+/// The name of the repository, see [`ParsedUrl`] for the URLs it understands.
 pub fn repo_name_from_url(url: &str) -> Result<&str, AppError> {
-  let last_fragment = url.rsplit('/').next().ok_or_else(|| {
-    AppError::UserError(format!(
-      "Given URL {} does not have path fragments so cannot determine project name. Please give \
+  ParsedUrl::parse(url)
+    .map(|parsed| parsed.repo())
+    .filter(|name| !name.is_empty())
+    .ok_or_else(|| {
+      AppError::UserError(format!(
+        "Given URL {} does not have path fragments so cannot determine project name. Please give \
        one.",
-      url
-    ))
-  })?;
-
-  // trim_right_matches is more efficient but would fuck us up with repos like git@github.com:bauer/test.git.git (which is legal)
-  Ok(if last_fragment.ends_with(".git") {
-    last_fragment.split_at(last_fragment.len() - 4).0
-  } else {
-    last_fragment
-  })
+        url
+      ))
+    })
 }
 
 fn username_from_git_url(url: &str) -> String {
@@ -41,49 +54,123 @@ fn username_from_git_url(url: &str) -> String {
     }
   }
   if let Ok(user) = env::var("USER") {
-    if user != "" {
+    if !user.is_empty() {
       return user;
     }
   }
   "git".to_string()
 }
 
-fn agent_callbacks(git_user: &str) -> git2::RemoteCallbacks<'_> {
+/// The identity file given with `-i` in a `core.sshCommand` like `ssh -i ~/.ssh/id_work -o IdentitiesOnly=yes`.
+fn identity_from_ssh_command(command: &str) -> Option<PathBuf> {
+  let mut words = split_shell_words(command).into_iter();
+  while let Some(word) = words.next() {
+    let identity = match word.as_str() {
+      "-i" => words.next(),
+      _ => word.strip_prefix("-i").filter(|rest| !rest.is_empty()).map(str::to_owned),
+    };
+    if let Some(identity) = identity {
+      return Some(expand_path(PathBuf::from(identity)));
+    }
+  }
+  None
+}
+
+/// The `core.sshCommand` that makes git use `ssh_key` and nothing else.
+fn ssh_key_command(ssh_key: &Path) -> String {
+  format!("ssh -i {} -o IdentitiesOnly=yes", shell_word(&ssh_key.to_string_lossy()))
+}
+
+/// libgit2 does not run `core.sshCommand`, but the key in it is used like git would.
+fn configured_identity(config: Result<git2::Config, git2::Error>) -> Option<PathBuf> {
+  config.ok()?.get_string("core.sshCommand").ok().as_deref().and_then(identity_from_ssh_command)
+}
+
+/// Makes git in the checkout use the same key as fw, unless the checkout has a `core.sshCommand` of its own.
+fn configure_ssh_key(repo: &Repository, ssh_key: &Path, logger: &Logger) -> Result<(), AppError> {
+  let command = ssh_key_command(ssh_key);
+  let mut config = repo.config()?.open_level(git2::ConfigLevel::Local)?;
+  match config.get_string("core.sshCommand").ok() {
+    None => config.set_str("core.sshCommand", &command)?,
+    Some(existing) if existing != command => {
+      warn!(logger, "Keeping the core.sshCommand of the checkout instead of the ssh_key"; "core.sshCommand" => existing)
+    }
+    Some(_) => (),
+  }
+  Ok(())
+}
+
+fn agent_callbacks<'a>(git_user: &'a str, identity: Option<&'a Path>) -> git2::RemoteCallbacks<'a> {
   let mut remote_callbacks = RemoteCallbacks::new();
-  remote_callbacks.credentials(move |_, _, _| git2::Cred::ssh_key_from_agent(git_user));
+  let mut identity_tried = false;
+  remote_callbacks.credentials(move |url, username, allowed| {
+    // HTTPS remotes get what git's credential helpers have, e.g. the ones of the aws and gcloud CLIs
+    if allowed.contains(git2::CredentialType::USER_PASS_PLAINTEXT) {
+      let config = git2::Config::open_default()?;
+      return git2::Cred::credential_helper(&config, url, username);
+    }
+    // libgit2 asks again if the key is rejected, then the agent gets its turn
+    if let (Some(identity), false) = (identity, identity_tried) {
+      identity_tried = true;
+      return git2::Cred::ssh_key(git_user, None, identity, None);
+    }
+    git2::Cred::ssh_key_from_agent(git_user)
+  });
+  // pausing here holds back reading from the connection, so the server has to slow down
+  let started = Instant::now();
+  let limit = network::bandwidth_limit();
+  remote_callbacks.transfer_progress(move |progress| {
+    if let Some(delay) = limit.and_then(|limit| network::throttle_delay(progress.received_bytes(), started.elapsed(), limit)) {
+      std::thread::sleep(delay);
+    }
+    // returning false aborts the transfer
+    !signal::interrupted() && !runtime::deadline_passed()
+  });
   remote_callbacks
 }
 
-fn agent_fetch_options(git_user: &str) -> git2::FetchOptions<'_> {
-  let remote_callbacks = agent_callbacks(git_user);
+/// The proxy libgit2 detects from the git config and `HTTP(S)_PROXY`, unless `NO_PROXY` lists the host of `url`,
+/// which libgit2 does not check itself.
+fn proxy_options(url: &str) -> ProxyOptions<'static> {
   let mut proxy_options = ProxyOptions::new();
-  proxy_options.auto();
+  let host = url
+    .split_once("://")
+    .and_then(|(_, rest)| rest.split('/').next())
+    .map(|authority| authority.rsplit('@').next().unwrap_or(authority))
+    .map(|host| host.rsplit_once(':').map(|(host, _)| host).unwrap_or(host));
+  if !host.map(network::bypasses_proxy).unwrap_or(false) {
+    proxy_options.auto();
+  }
+  proxy_options
+}
+
+fn agent_fetch_options<'a>(git_user: &'a str, identity: Option<&'a Path>, url: &str) -> git2::FetchOptions<'a> {
+  let remote_callbacks = agent_callbacks(git_user, identity);
   let mut fetch_options = FetchOptions::new();
   fetch_options.remote_callbacks(remote_callbacks);
-  fetch_options.proxy_options(proxy_options);
+  fetch_options.proxy_options(proxy_options(url));
 
   fetch_options
 }
 
-fn builder(git_user: &str) -> RepoBuilder<'_> {
-  let options = agent_fetch_options(git_user);
+fn builder<'a>(git_user: &'a str, identity: Option<&'a Path>, url: &str) -> RepoBuilder<'a> {
+  let options = agent_fetch_options(git_user, identity, url);
   let mut repo_builder = RepoBuilder::new();
   repo_builder.fetch_options(options);
   repo_builder
 }
 
-fn update_remote(project: &Project, remote: &mut Remote<'_>, project_logger: &Logger) -> Result<(), AppError> {
+fn update_remote(project: &Project, remote: &mut Remote<'_>, identity: Option<&Path>, project_logger: &Logger) -> Result<(), AppError> {
   let git_user = username_from_git_url(&project.git);
-  let remote_callbacks = agent_callbacks(&git_user);
-  let mut proxy_options = ProxyOptions::new();
-  proxy_options.auto();
+  let remote_callbacks = agent_callbacks(&git_user, identity);
+  let url = remote.url().unwrap_or_default().to_owned();
   remote
-    .connect_auth(Direction::Fetch, Some(remote_callbacks), Some(proxy_options))
+    .connect_auth(Direction::Fetch, Some(remote_callbacks), Some(proxy_options(&url)))
     .map_err(|error| {
       warn!(project_logger, "Error connecting remote"; "error" => format!("{}", error), "project" => &project.name);
       AppError::GitError(error)
     })?;
-  let mut options = agent_fetch_options(&git_user);
+  let mut options = agent_fetch_options(&git_user, identity, &url);
   remote.download::<String>(&[], Some(&mut options)).map_err(|error| {
     warn!(project_logger, "Error downloading for remote"; "error" => format!("{}", error), "project" => &project.name);
     AppError::GitError(error)
@@ -93,19 +180,26 @@ fn update_remote(project: &Project, remote: &mut Remote<'_>, project_logger: &Lo
   Ok(())
 }
 
-pub fn update_project_remotes(project: &Project, path: &PathBuf, project_logger: &Logger, ff_merge: bool) -> Result<(), AppError> {
-  debug!(project_logger, "Update project remotes");
+pub fn update_project_remotes(project: &Project, path: &PathBuf, ssh_key: Option<&Path>, project_logger: &Logger, ff_merge: bool) -> Result<(), AppError> {
+  info!(project_logger, "Fetch started");
   let local: Repository = Repository::open(path).map_err(|error| {
     warn!(project_logger, "Error opening local repo"; "error" => format!("{}", error));
     AppError::GitError(error)
   })?;
-  for desired_remote in project.additional_remotes.clone().unwrap_or_default().into_iter().chain(
-    vec![crate::config::project::Remote {
+  if let Some(ssh_key) = ssh_key {
+    configure_ssh_key(&local, ssh_key, project_logger)?;
+  }
+  let identity = configured_identity(local.config());
+  for desired_remote in project
+    .additional_remotes
+    .clone()
+    .unwrap_or_default()
+    .into_iter()
+    .chain(vec![crate::config::project::Remote {
       name: "origin".to_string(),
       git: project.git.to_owned(),
-    }]
-    .into_iter(),
-  ) {
+    }])
+  {
     let remote = local
       .find_remote(&desired_remote.name)
       .or_else(|_| local.remote(&desired_remote.name, &desired_remote.git))?;
@@ -118,15 +212,16 @@ pub fn update_project_remotes(project: &Project, path: &PathBuf, project_logger:
       }
     };
 
-    update_remote(project, &mut remote, project_logger)?;
+    update_remote(project, &mut remote, identity.as_deref(), project_logger)?;
   }
 
-  if ff_merge {
+  if ff_merge && project.pin.is_none() {
     if let Err(error) = fast_forward_merge(&local, project_logger) {
       debug!(project_logger, "Fast forward failed: {}", error)
     }
   }
 
+  info!(project_logger, "Fetch finished");
   Ok(())
 }
 
@@ -153,35 +248,417 @@ fn fast_forward_merge(local: &Repository, project_logger: &Logger) -> Result<(),
 }
 
 pub fn clone_project(config: &Config, project: &Project, path: &PathBuf, project_logger: &Logger) -> Result<(), AppError> {
-  let shell = config.settings.get_shell_or_default();
+  let project = &config.preferred(project);
+  let ssh_key = config.resolve_ssh_key(project, project_logger);
+  let reference = config.reference_repo();
+  let depth = config.resolve_clone_depth(project, project_logger);
+  clone_repository(project, path, ssh_key.as_deref(), reference.as_deref(), depth, project_logger)
+    .and_then(|_| run_after_clone(config, project, path, project_logger))
+}
+
+/// Runs the after_clone hooks of the project in the new clone at `path`, also for a project whose clone was made for
+/// another one it shares it with through `subdir`.
+pub fn run_after_clone(config: &Config, project: &Project, path: &PathBuf, project_logger: &Logger) -> Result<(), AppError> {
+  let after_clone = config.resolve_after_clone(project_logger, project).join(" && ");
+  if after_clone.is_empty() || !trust::allows_hook(config, project, "after_clone", &after_clone, project_logger) {
+    return Ok(());
+  }
+  debug!(project_logger, "Handling post hooks"; "after_clone" => &after_clone);
+  let shell = config.settings.niced(config.resolve_shell(project, project_logger));
+  let timeout = config.resolve_hook_timeout(project, project_logger);
+  let log = hook_log_path(&project.name)?;
+  let env = config.resolve_env(project, project_logger);
+  spawn_hook(
+    &shell,
+    &after_clone,
+    path,
+    &project.name,
+    &env,
+    random_colour(),
+    timeout,
+    Some(&log),
+    project_logger,
+  )
+  .map_err(|error| match error {
+    AppError::UserError(cause) => AppError::UserError(format!("Post-clone hook failed: {}", cause)),
+    other => other,
+  })
+  .map(|_| info!(project_logger, "Hook executed"; "hook" => "after_clone", "log" => log.to_string_lossy().to_string()))
+}
+
+// Like the transfers of libgit2 the git process stops on Ctrl-C and when the deadline of the thread passes. git reads
+// the proxy settings from the environment itself, but not SSL_CERT_FILE, and it can't be throttled.
+fn run_git_transfer(dir: &Path, args: &[String], logger: &Logger) -> Result<(), AppError> {
+  if network::bandwidth_limit().is_some() {
+    warn!(logger, "The bandwidth_limit does not apply to transfers of the git command line");
+  }
+  let mut command = std::process::Command::new("git");
+  command
+    .args(args)
+    .current_dir(dir)
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped());
+  if let Some(ca_file) = network::ca_file() {
+    command.env("GIT_SSL_CAINFO", ca_file);
+  }
+  // its own process group, so that killing it reaches git-remote-https and ssh as well
+  #[cfg(unix)]
+  std::os::unix::process::CommandExt::process_group(&mut command, 0);
+  let mut child = command.spawn().map_err(|e| AppError::UserError(format!("Could not run git: {}", e)))?;
+  let mut stderr = child.stderr.take().ok_or(AppError::InternalError("git stderr not piped"))?;
+  let reader = std::thread::spawn(move || {
+    let mut output = String::new();
+    let _ = std::io::Read::read_to_string(&mut stderr, &mut output);
+    output
+  });
+  let command_name = args.first().map(String::as_str).unwrap_or_default();
+  match wait_until(&mut child, runtime::deadline())? {
+    None => {
+      signal::check_interrupted()?;
+      Err(AppError::UserError(format!("git {} ran out of time", command_name)))
+    }
+    Some(status) if status.success() => Ok(()),
+    Some(_) => Err(AppError::UserError(format!(
+      "git {} failed: {}",
+      command_name,
+      reader.join().unwrap_or_default().trim()
+    ))),
+  }
+}
+
+// libgit2 can neither borrow objects from another repository nor clone shallow, git does both.
+fn clone_with_git(
+  project: &Project,
+  path: &Path,
+  reference: Option<&Path>,
+  depth: Option<u32>,
+  ssh_key: Option<&Path>,
+  logger: &Logger,
+) -> Result<Repository, AppError> {
+  let parent = path.parent().ok_or(AppError::InternalError("project path has no parent"))?;
+  std::fs::create_dir_all(parent)?;
+  let mut args = vec!["clone".to_owned()];
+  if let Some(reference) = reference {
+    args.push("--reference-if-able".to_owned());
+    args.push(reference.to_string_lossy().to_string());
+  }
+  if let Some(depth) = depth {
+    args.push("--depth".to_owned());
+    args.push(depth.to_string());
+  }
+  if project.bare.unwrap_or_default() {
+    args.push("--bare".to_owned());
+  }
+  if let Some(ssh_key) = ssh_key {
+    args.push("--config".to_owned());
+    args.push(format!("core.sshCommand={}", ssh_key_command(ssh_key)));
+  }
+  args.push(project.git.clone());
+  args.push(path.to_string_lossy().to_string());
+  run_git_transfer(parent, &args, logger)?;
+  Ok(Repository::open(path)?)
+}
+
+/// Clones the project and its additional remotes without running the after_clone hooks.
+/// Without an `ssh_key` the one in the global `core.sshCommand` is used, if there is one. With a `reference` repository
+/// git borrows the objects it already has instead of downloading them. With a `depth` only that many commits are cloned.
+pub fn clone_repository(
+  project: &Project,
+  path: &PathBuf,
+  ssh_key: Option<&Path>,
+  reference: Option<&Path>,
+  depth: Option<u32>,
+  project_logger: &Logger,
+) -> Result<(), AppError> {
+  if project.is_directory() {
+    return Err(AppError::UserError(format!(
+      "{} is a directory project, there is no repository to clone",
+      project.name
+    )));
+  }
   let git_user = username_from_git_url(&project.git);
-  let mut repo_builder = builder(&git_user);
-  debug!(project_logger, "Cloning project");
-  repo_builder
-    .bare(project.bare.unwrap_or_default())
-    .clone(project.git.as_str(), path)
-    .map_err(|error| {
-      warn!(project_logger, "Error cloning repo"; "error" => format!("{}", error));
-      AppError::GitError(error)
+  let identity = ssh_key.map(Path::to_path_buf).or_else(|| configured_identity(git2::Config::open_default()));
+  let mut repo_builder = builder(&git_user, identity.as_deref(), &project.git);
+  info!(project_logger, "Clone started");
+  let existed_before = path.exists();
+  let cloned = match (reference, depth) {
+    (None, None) => repo_builder
+      .bare(project.bare.unwrap_or_default())
+      .clone(project.git.as_str(), path)
+      .map_err(AppError::GitError),
+    _ => {
+      debug!(project_logger, "Cloning with git"; "reference" => format!("{:?}", reference), "depth" => depth);
+      clone_with_git(project, path, reference, depth, ssh_key, project_logger)
+    }
+  };
+  cloned
+    .inspect_err(|error| warn!(project_logger, "Error cloning repo"; "error" => format!("{}", error)))
+    .and_then(|repo| {
+      if let Some(ssh_key) = ssh_key {
+        configure_ssh_key(&repo, ssh_key, project_logger)?;
+      }
+      if let (Some(pin), false) = (&project.pin, project.bare.unwrap_or_default()) {
+        checkout_pin(&repo, pin, project_logger)?;
+      }
+      init_additional_remotes(project, repo, identity.as_deref(), project_logger)
     })
-    .and_then(|repo| init_additional_remotes(project, repo, project_logger))
-    .and_then(|_| {
-      let after_clone = config.resolve_after_clone(project_logger, project);
-      if !after_clone.is_empty() {
-        debug!(project_logger, "Handling post hooks"; "after_clone" => format!("{:?}", after_clone));
-        spawn_maybe(&shell, &after_clone.join(" && "), path, &project.name, random_colour(), project_logger)
-          .map_err(|error| AppError::UserError(format!("Post-clone hook failed (nonzero exit code). Cause: {:?}", error)))
-      } else {
-        Ok(())
+    .inspect_err(|_| {
+      if !existed_before && path.exists() {
+        debug!(project_logger, "Removing partial clone");
+        if let Err(e) = std::fs::remove_dir_all(path) {
+          warn!(project_logger, "Failed to remove partial clone"; "error" => format!("{}", e));
+        }
       }
     })
+    .map(|_| info!(project_logger, "Clone finished"))
+}
+
+/// How often a failed clone or fetch is tried again and how long to wait in between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+  pub retries: u32,
+  /// The wait before the first retry, doubled for every further one up to a minute.
+  pub backoff: Duration,
+  /// How long a single attempt may take.
+  pub timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> RetryPolicy {
+    RetryPolicy {
+      retries: 2,
+      backoff: Duration::from_secs(2),
+      timeout: None,
+    }
+  }
+}
+
+impl RetryPolicy {
+  pub fn from_settings(settings: &Settings) -> RetryPolicy {
+    let default = RetryPolicy::default();
+    RetryPolicy {
+      retries: settings.retries.unwrap_or(default.retries),
+      backoff: settings.retry_backoff.map(Duration::from_secs).unwrap_or(default.backoff),
+      ..default
+    }
+  }
+
+  /// The time every attempt and the waits between them take at most, `None` without a `timeout`.
+  pub fn total_timeout(&self) -> Option<Duration> {
+    self
+      .timeout
+      .map(|timeout| (1..=self.retries).fold(timeout, |total, attempt| total.saturating_add(timeout).saturating_add(self.delay(attempt))))
+  }
+
+  /// The wait after the `attempt`th failed attempt, counting from 1.
+  pub fn delay(&self, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    self.backoff.saturating_mul(factor).min(Duration::from_secs(60))
+  }
 }
 
-fn init_additional_remotes(project: &Project, repository: Repository, project_logger: &Logger) -> Result<(), AppError> {
+/// Network trouble that might be gone on the next attempt. Rejected credentials, missing repositories and local
+/// trouble like a full disk are not.
+pub fn is_transient(error: &AppError) -> bool {
+  match error {
+    AppError::GitError(e) => {
+      matches!(e.class(), ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssh)
+        && !matches!(e.code(), ErrorCode::Auth | ErrorCode::Certificate | ErrorCode::NotFound)
+    }
+    _ => false,
+  }
+}
+
+/// Runs `operation` until it succeeds, fails for good or `policy` gives up. Returns the result and the number of attempts.
+/// Every attempt gets the `timeout` of `policy` as its deadline, one that ran out is tried again. There are no more
+/// attempts once the deadline of the surrounding work passed.
+pub fn with_retries<T, F>(policy: &RetryPolicy, logger: &Logger, mut operation: F) -> (Result<T, AppError>, u32)
+where
+  F: FnMut() -> Result<T, AppError>,
+{
+  let mut attempt = 1;
+  loop {
+    let started = Instant::now();
+    let result = runtime::with_deadline(policy.timeout.map(|timeout| started + timeout), &mut operation);
+    let timed_out = policy.timeout.filter(|timeout| started.elapsed() >= *timeout && !signal::interrupted());
+    let result = result.map_err(|e| match timed_out {
+      Some(timeout) => AppError::RuntimeError(format!("timed out after {}s: {}", timeout.as_secs(), e)),
+      None => e,
+    });
+    match result {
+      Err(e) if attempt <= policy.retries && (timed_out.is_some() || is_transient(&e)) && !signal::interrupted() && !runtime::deadline_passed() => {
+        let delay = policy.delay(attempt);
+        warn!(logger, "Retrying"; "attempt" => attempt, "in_seconds" => delay.as_secs(), "error" => format!("{}", e));
+        let until = Instant::now() + delay;
+        while !signal::interrupted() && !runtime::deadline_passed() && Instant::now() < until {
+          std::thread::sleep(Duration::from_millis(100));
+        }
+        if runtime::deadline_passed() {
+          return (Err(e), attempt);
+        }
+        attempt += 1;
+      }
+      result => return (result, attempt),
+    }
+  }
+}
+
+/// The branch `origin/HEAD` points to, or else `main` or `master` if `origin` has it.
+pub fn default_branch(repo: &Repository) -> Option<String> {
+  let from_head = repo
+    .find_reference("refs/remotes/origin/HEAD")
+    .ok()
+    .and_then(|head| head.symbolic_target().map(ToOwned::to_owned))
+    .and_then(|target| target.strip_prefix("refs/remotes/origin/").map(ToOwned::to_owned));
+  from_head.or_else(|| {
+    ["main", "master"]
+      .iter()
+      .find(|name| repo.find_branch(&format!("origin/{}", name), BranchType::Remote).is_ok())
+      .map(|name| (*name).to_owned())
+  })
+}
+
+/// Tracked files have uncommitted changes. Untracked files do not count.
+pub fn is_dirty(repo: &Repository) -> Result<bool, AppError> {
+  if repo.is_bare() {
+    return Ok(false);
+  }
+  let mut options = StatusOptions::new();
+  options.include_untracked(false).include_ignored(false);
+  Ok(!repo.statuses(Some(&mut options))?.is_empty())
+}
+
+/// Remote-tracking branches of `origin` whose branch no longer exists on the remote, e.g. `origin/feature`.
+pub fn gone_remote_branches(project: &Project, repo: &Repository) -> Result<Vec<String>, AppError> {
+  let git_user = username_from_git_url(&project.git);
+  let identity = configured_identity(repo.config());
+  let mut remote = repo.find_remote("origin")?;
+  let url = remote.url().unwrap_or_default().to_owned();
+  remote.connect_auth(
+    Direction::Fetch,
+    Some(agent_callbacks(&git_user, identity.as_deref())),
+    Some(proxy_options(&url)),
+  )?;
+  // git2 hands out an invalid slice for a remote without refs, a remote with a default branch has at least HEAD.
+  // Without it nothing is known to be gone.
+  let heads: Vec<String> = match remote.default_branch() {
+    Ok(_) => remote
+      .list()?
+      .iter()
+      .filter_map(|head| head.name().strip_prefix("refs/heads/").map(ToOwned::to_owned))
+      .collect(),
+    Err(_) => {
+      remote.disconnect()?;
+      return Ok(vec![]);
+    }
+  };
+  remote.disconnect()?;
+  let mut gone = Vec::new();
+  for maybe_branch in repo.branches(Some(BranchType::Remote))? {
+    let (branch, _) = maybe_branch?;
+    if let Some(name) = branch.name()? {
+      if let Some(short_name) = name.strip_prefix("origin/") {
+        if short_name != "HEAD" && !heads.iter().any(|head| head == short_name) {
+          gone.push(name.to_owned());
+        }
+      }
+    }
+  }
+  Ok(gone)
+}
+
+/// Pushes the local `branch` to `origin` and makes `origin/<branch>` its upstream.
+pub fn push_branch(project: &Project, repo: &Repository, branch: &str, project_logger: &Logger) -> Result<(), AppError> {
+  push_refspec(project, repo, &format!("refs/heads/{0}:refs/heads/{0}", branch))?;
+  repo.find_branch(branch, BranchType::Local)?.set_upstream(Some(&format!("origin/{}", branch)))?;
+  info!(project_logger, "Pushed"; "branch" => branch);
+  Ok(())
+}
+
+/// Pushes the tag `name` to `origin`.
+pub fn push_tag(project: &Project, repo: &Repository, name: &str, project_logger: &Logger) -> Result<(), AppError> {
+  push_refspec(project, repo, &format!("refs/tags/{0}:refs/tags/{0}", name))?;
+  info!(project_logger, "Pushed"; "tag" => name);
+  Ok(())
+}
+
+fn push_refspec(project: &Project, repo: &Repository, refspec: &str) -> Result<(), AppError> {
+  let git_user = username_from_git_url(&project.git);
+  let identity = configured_identity(repo.config());
+  let mut remote_callbacks = agent_callbacks(&git_user, identity.as_deref());
+  // a rejected reference (e.g. not a fast-forward) is only reported through this callback, push itself succeeds
+  remote_callbacks.push_update_reference(|reference, rejection| match rejection {
+    Some(reason) => Err(git2::Error::from_str(&format!("push of {} rejected: {}", reference, reason))),
+    None => Ok(()),
+  });
+  let mut remote = repo.find_remote("origin")?;
+  let mut options = PushOptions::new();
+  options.remote_callbacks(remote_callbacks);
+  options.proxy_options(proxy_options(remote.url().unwrap_or_default()));
+  remote.push(&[refspec], Some(&mut options))?;
+  Ok(())
+}
+
+/// Runs the git command line in `dir`, for what libgit2 cannot do like bundles.
+pub fn run_git(dir: &Path, args: &[&str]) -> Result<(), AppError> {
+  let output = std::process::Command::new("git")
+    .args(args)
+    .current_dir(dir)
+    .output()
+    .map_err(|e| AppError::UserError(format!("Could not run git: {}", e)))?;
+  if output.status.success() {
+    Ok(())
+  } else {
+    Err(AppError::UserError(format!(
+      "git {} failed: {}",
+      args.first().unwrap_or(&""),
+      String::from_utf8_lossy(&output.stderr).trim()
+    )))
+  }
+}
+
+/// Whether the `.gitattributes` of the checkout at `path` hands files to git LFS.
+pub fn uses_lfs(path: &Path) -> bool {
+  std::fs::read_to_string(path.join(".gitattributes"))
+    .map(|attributes| attributes.contains("filter=lfs"))
+    .unwrap_or(false)
+}
+
+/// Fetches the git LFS objects of the checkout at `path` and, unless the repository is bare, replaces the pointer
+/// files with them. A fresh clone gets the LFS hooks first, libgit2 does not run them.
+pub fn update_lfs(path: &Path, cloned: bool, bare: bool, project_logger: &Logger) -> Result<(), AppError> {
+  if cloned {
+    run_git(path, &["lfs", "install", "--local"])?;
+  }
+  run_git(path, &["lfs", if bare { "fetch" } else { "pull" }])?;
+  debug!(project_logger, "LFS objects fetched");
+  Ok(())
+}
+
+/// Resolves a pinned branch, tag or commit. Branches only exist as remote branches right after cloning.
+pub fn resolve_pin(repo: &Repository, pin: &str) -> Result<Oid, AppError> {
+  repo
+    .revparse_single(pin)
+    .or_else(|_| repo.revparse_single(&format!("origin/{}", pin)))
+    .and_then(|object| object.peel_to_commit())
+    .map(|commit| commit.id())
+    .map_err(|e| AppError::UserError(format!("Could not resolve pin {}: {}", pin, e.message())))
+}
+
+fn checkout_pin(repo: &Repository, pin: &str, project_logger: &Logger) -> Result<(), AppError> {
+  let oid = resolve_pin(repo, pin)?;
+  repo.checkout_tree(&repo.find_object(oid, None)?, Some(CheckoutBuilder::new().force()))?;
+  repo.set_head_detached(oid)?;
+  info!(project_logger, "Checked out pin"; "pin" => pin, "commit" => oid.to_string());
+  Ok(())
+}
+
+fn init_additional_remotes(project: &Project, repository: Repository, identity: Option<&Path>, project_logger: &Logger) -> Result<(), AppError> {
   if let Some(additional_remotes) = &project.additional_remotes {
     for remote in additional_remotes {
       let mut git_remote = repository.remote(&remote.name, &remote.git)?;
-      update_remote(project, &mut git_remote, project_logger)?;
+      update_remote(project, &mut git_remote, identity, project_logger)?;
       debug!(project_logger, "Added remote"; "remote" => remote.name.to_string())
     }
   }
@@ -191,38 +668,178 @@ fn init_additional_remotes(project: &Project, repository: Repository, project_lo
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::testing::{commit, temp_dir};
   use spectral::prelude::*;
 
   #[test]
   fn test_username_from_git_url() {
     let user = env::var("USER").unwrap();
-    assert_that(&username_from_git_url(&"git+ssh://git@fkbr.org:sxoe.git")).is_equal_to("git".to_string());
-    assert_that(&username_from_git_url(&"ssh://aur@aur.archlinux.org/fw.git")).is_equal_to("aur".to_string());
-    assert_that(&username_from_git_url(&"aur@github.com:21re/fkbr.git")).is_equal_to("aur".to_string());
-    assert_that(&username_from_git_url(&"aur_fkbr_1@github.com:21re/fkbr.git")).is_equal_to("aur_fkbr_1".to_string());
-    assert_that(&username_from_git_url(&"github.com:21re/fkbr.git")).is_equal_to(user.to_string());
-    assert_that(&username_from_git_url(&"git://fkbr.org/sxoe.git")).is_equal_to(user.to_string());
+    assert_that(&username_from_git_url("git+ssh://git@fkbr.org:sxoe.git")).is_equal_to("git".to_string());
+    assert_that(&username_from_git_url("ssh://aur@aur.archlinux.org/fw.git")).is_equal_to("aur".to_string());
+    assert_that(&username_from_git_url("aur@github.com:21re/fkbr.git")).is_equal_to("aur".to_string());
+    assert_that(&username_from_git_url("aur_fkbr_1@github.com:21re/fkbr.git")).is_equal_to("aur_fkbr_1".to_string());
+    assert_that(&username_from_git_url("github.com:21re/fkbr.git")).is_equal_to(user.to_string());
+    assert_that(&username_from_git_url("git://fkbr.org/sxoe.git")).is_equal_to(user.to_string());
 
-    assert_that(&username_from_git_url(&"https://github.com/brocode/fw.git")).is_equal_to(user.to_string());
-    assert_that(&username_from_git_url(&"https://kuci@github.com/brocode/fw.git")).is_equal_to("kuci".to_string());
+    assert_that(&username_from_git_url("https://github.com/brocode/fw.git")).is_equal_to(user.to_string());
+    assert_that(&username_from_git_url("https://kuci@github.com/brocode/fw.git")).is_equal_to("kuci".to_string());
+  }
+
+  #[test]
+  fn test_identity_from_ssh_command() {
+    assert_that(&identity_from_ssh_command("ssh -i '/keys/id_work' -o IdentitiesOnly=yes")).is_equal_to(Some(PathBuf::from("/keys/id_work")));
+    assert_that(&identity_from_ssh_command("ssh -o IdentitiesOnly=yes -i/keys/id_oss")).is_equal_to(Some(PathBuf::from("/keys/id_oss")));
+    assert_that(&identity_from_ssh_command("ssh -v")).is_none();
+    let key = Path::new("/keys/it's mine");
+    assert_that(&identity_from_ssh_command(&ssh_key_command(key))).is_equal_to(Some(key.to_path_buf()));
+  }
+
+  #[test]
+  fn test_configure_ssh_key_keeps_the_checkout_command() {
+    let dir = temp_dir();
+    let repo = Repository::init(dir.path()).unwrap();
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let command = || {
+      repo
+        .config()
+        .unwrap()
+        .open_level(git2::ConfigLevel::Local)
+        .unwrap()
+        .get_string("core.sshCommand")
+        .ok()
+    };
+
+    configure_ssh_key(&repo, Path::new("/keys/my key"), &logger).unwrap();
+    assert_that(&command()).is_equal_to(Some("ssh -i '/keys/my key' -o IdentitiesOnly=yes".to_owned()));
+    repo.config().unwrap().set_str("core.sshCommand", "ssh -F ~/.ssh/work_config").unwrap();
+    configure_ssh_key(&repo, Path::new("/keys/id_work"), &logger).unwrap();
+    assert_that(&command()).is_equal_to(Some("ssh -F ~/.ssh/work_config".to_owned()));
+  }
+
+  #[test]
+  fn test_clone_with_git_borrows_from_the_reference() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let origin = dir.join("origin");
+    commit(&Repository::init(&origin).unwrap(), "init");
+    let reference = dir.join("reference.git");
+    run_git(dir, &["clone", "--bare", "origin", "reference.git"]).unwrap();
+    let project = Project {
+      name: "origin".to_owned(),
+      git: origin.to_string_lossy().to_string(),
+      bare: None,
+      ..Project::example()
+    };
+    let path = dir.join("clone");
+    let logger = Logger::root(slog::Discard, slog::o!());
+
+    clone_with_git(&project, &path, Some(&reference), None, None, &logger).unwrap();
+    let alternates = std::fs::read_to_string(path.join(".git/objects/info/alternates")).unwrap();
+    assert_that(&PathBuf::from(alternates.trim())).is_equal_to(reference.join("objects"));
+  }
+
+  #[test]
+  fn test_run_git_transfer_stops_at_the_deadline() {
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let started = Instant::now();
+    // an alias keeps git busy without the network
+    let args = ["-c", "alias.wait=!sleep 10", "wait"].iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    let result = runtime::with_deadline(Some(started + Duration::from_millis(200)), || run_git_transfer(Path::new("."), &args, &logger));
+    assert_that(&format!("{}", result.unwrap_err())).contains("ran out of time");
+    assert_that(&(started.elapsed() < Duration::from_secs(5))).is_true();
   }
 
   #[test]
   fn test_repo_name_from_url() {
     let https_url = "https://github.com/mriehl/fw";
-    let name = repo_name_from_url(&https_url).unwrap().to_owned();
+    let name = repo_name_from_url(https_url).unwrap().to_owned();
     assert_that(&name).is_equal_to("fw".to_owned());
   }
+  #[test]
+  fn test_repo_name_from_url_with_port_and_subgroups() {
+    assert_that(&repo_name_from_url("ssh://git@host:2222/group/sub/repo.git").unwrap()).is_equal_to("repo");
+    assert_that(&repo_name_from_url("https://gitlab.com/group/sub/repo/").unwrap()).is_equal_to("repo");
+    assert_that(&repo_name_from_url("file:///srv/git/repo.git").unwrap()).is_equal_to("repo");
+    assert_that(&repo_name_from_url("git@host:repo.git").unwrap()).is_equal_to("repo");
+    assert_that(&repo_name_from_url("https://gitlab.com/")).is_err();
+  }
+
   #[test]
   fn test_repo_name_from_ssh_pragma() {
     let ssh_pragma = "git@github.com:mriehl/fw.git";
-    let name = repo_name_from_url(&ssh_pragma).unwrap().to_owned();
+    let name = repo_name_from_url(ssh_pragma).unwrap().to_owned();
     assert_that(&name).is_equal_to("fw".to_owned());
   }
   #[test]
   fn test_repo_name_from_ssh_pragma_with_multiple_git_endings() {
     let ssh_pragma = "git@github.com:mriehl/fw.git.git";
-    let name = repo_name_from_url(&ssh_pragma).unwrap().to_owned();
+    let name = repo_name_from_url(ssh_pragma).unwrap().to_owned();
     assert_that(&name).is_equal_to("fw.git".to_owned());
   }
+
+  #[test]
+  fn test_with_retries() {
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let policy = RetryPolicy {
+      retries: 2,
+      backoff: Duration::from_secs(0),
+      timeout: None,
+    };
+    let flaky = || AppError::GitError(git2::Error::new(ErrorCode::GenericError, ErrorClass::Net, "connection reset"));
+
+    let mut calls = 0;
+    let (result, attempts) = with_retries(&policy, &logger, || {
+      calls += 1;
+      if calls < 3 {
+        Err(flaky())
+      } else {
+        Ok(calls)
+      }
+    });
+    assert_that(&result.unwrap()).is_equal_to(3);
+    assert_that(&attempts).is_equal_to(3);
+
+    let (result, attempts) = with_retries(&policy, &logger, || Err::<(), _>(flaky()));
+    assert_that(&result.is_err()).is_true();
+    assert_that(&attempts).is_equal_to(3);
+
+    let denied = || AppError::GitError(git2::Error::new(ErrorCode::Auth, ErrorClass::Ssh, "denied"));
+    assert_that(&with_retries(&policy, &logger, || Err::<(), _>(denied())).1).is_equal_to(1);
+    let disk_full = || AppError::GitError(git2::Error::new(ErrorCode::GenericError, ErrorClass::Os, "no space left on device"));
+    assert_that(&with_retries(&policy, &logger, || Err::<(), _>(disk_full())).1).is_equal_to(1);
+  }
+
+  #[test]
+  fn test_with_retries_has_a_deadline_per_attempt() {
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let policy = RetryPolicy {
+      retries: 2,
+      backoff: Duration::from_secs(0),
+      timeout: Some(Duration::from_millis(100)),
+    };
+    // stops like a transfer does when its deadline passed
+    let stalled = || {
+      while !runtime::deadline_passed() {
+        std::thread::sleep(Duration::from_millis(5));
+      }
+      Err::<(), _>(AppError::GitError(git2::Error::new(ErrorCode::User, ErrorClass::Callback, "transfer aborted")))
+    };
+    let (result, attempts) = with_retries(&policy, &logger, stalled);
+    assert_that(&format!("{}", result.unwrap_err())).contains("timed out after");
+    assert_that(&attempts).is_equal_to(3);
+
+    // the surrounding work ran out of time, no further attempts
+    let (result, attempts) = runtime::with_deadline(Some(Instant::now() + Duration::from_millis(150)), || with_retries(&policy, &logger, stalled));
+    assert_that(&result.is_err()).is_true();
+    assert_that(&attempts).is_equal_to(2);
+    assert_that(&policy.total_timeout()).is_equal_to(Some(Duration::from_millis(300)));
+  }
+
+  #[test]
+  fn test_retry_delay() {
+    let policy = RetryPolicy::default();
+    assert_that(&policy.delay(1)).is_equal_to(Duration::from_secs(2));
+    assert_that(&policy.delay(3)).is_equal_to(Duration::from_secs(8));
+    assert_that(&policy.delay(10)).is_equal_to(Duration::from_secs(60));
+  }
 }
@@ -0,0 +1,252 @@
+//! Git URLs of forges in their SSH (`git@github.com:brocode/fw.git`) and HTTPS (`https://github.com/brocode/fw.git`)
+//! form. With `prefer` in the settings every project is cloned and shown in one of them, however its URL was entered.
+
+/// Forges whose SSH and HTTPS URLs differ only in the form, `host:path` and `host/path`. Others like Azure DevOps
+/// use different paths for the two.
+const KNOWN_FORGES: &[&str] = &["github.com", "gitlab.com", "bitbucket.org", "codeberg.org", "gitea.com", "git.sr.ht"];
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// The form of git URLs set by `prefer` in the settings.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+  /// `git@github.com:brocode/fw.git`
+  Ssh,
+  /// `https://github.com/brocode/fw.git`
+  Https,
+}
+
+/// The parts of any git URL: `ssh://git@host:2222/group/sub/repo.git`, SCP-like `git@host:group/repo.git`, `https://`,
+/// `git://`, `file://` URLs and local paths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ParsedUrl<'a> {
+  /// `None` for SCP-like URLs and local paths.
+  pub scheme: Option<&'a str>,
+  pub user: Option<&'a str>,
+  /// `None` for `file://` URLs and local paths.
+  pub host: Option<&'a str>,
+  pub port: Option<u16>,
+  /// Without surrounding slashes and one `.git` suffix, `group/sub/repo`. Local paths keep their leading slash.
+  pub path: &'a str,
+}
+
+fn is_local_path(url: &str) -> bool {
+  let bytes = url.as_bytes();
+  let windows_drive = bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && (bytes[2] == b'\\' || bytes[2] == b'/');
+  url.starts_with('/') || url.starts_with('.') || url.starts_with('~') || windows_drive
+}
+
+fn without_git_suffix(path: &str) -> &str {
+  path.strip_suffix(".git").unwrap_or(path)
+}
+
+impl<'a> ParsedUrl<'a> {
+  pub fn parse(url: &'a str) -> Option<ParsedUrl<'a>> {
+    let url = url.trim();
+    let (scheme, authority, path) = match url.split_once("://") {
+      Some((scheme, rest)) if scheme.eq_ignore_ascii_case("file") => (Some(scheme), None, rest),
+      Some((scheme, rest)) => match rest.split_once('/') {
+        Some((authority, path)) => (Some(scheme), Some(authority), path),
+        None => (Some(scheme), Some(rest), ""),
+      },
+      None if is_local_path(url) => (None, None, url),
+      None => match url.split_once(':') {
+        Some((authority, path)) if !authority.contains('/') => (None, Some(authority), path),
+        _ => (None, None, url),
+      },
+    };
+    let (user, host, port) = match authority {
+      Some(authority) => {
+        let (user, host_and_port) = match authority.rsplit_once('@') {
+          Some((user, host_and_port)) => (Some(user), host_and_port),
+          None => (None, authority),
+        };
+        let (host, port) = match host_and_port.strip_prefix('[') {
+          // `[::1]:2222`
+          Some(bracketed) => {
+            let (host, port) = bracketed.split_once(']')?;
+            (host, port.strip_prefix(':'))
+          }
+          None => match host_and_port.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_and_port, None),
+          },
+        };
+        let port = match port.filter(|port| !port.is_empty()) {
+          Some(port) => Some(port.parse().ok()?),
+          None => None,
+        };
+        if host.is_empty() {
+          return None;
+        }
+        (user, Some(host), port)
+      }
+      None => (None, None, None),
+    };
+    let path = if host.is_some() { path.trim_matches('/') } else { path.trim_end_matches('/') };
+    let path = without_git_suffix(path);
+    if path.is_empty() {
+      return None;
+    }
+    Some(ParsedUrl {
+      scheme,
+      user,
+      host,
+      port,
+      path,
+    })
+  }
+
+  /// The last part of the path, the name of the repository.
+  pub fn repo(&self) -> &'a str {
+    self.path.rsplit(['/', '\\']).next().unwrap_or(self.path)
+  }
+
+  /// The path before the repository on a host: the organization, group or user including subgroups, `group/sub`.
+  pub fn owner(&self) -> Option<&'a str> {
+    self.host?;
+    self.path.rsplit_once('/').map(|(owner, _)| owner)
+  }
+}
+
+/// A git URL that has both forms: a host, a repository path without `.git` and the user of SSH URLs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitUrl {
+  pub host: String,
+  pub path: String,
+  /// `None` for HTTPS URLs, whose user is not an SSH user.
+  pub ssh_user: Option<String>,
+}
+
+impl GitUrl {
+  /// `None` for local paths, `file://` URLs and URLs with a port, which do not translate to the other protocol.
+  pub fn parse(url: &str) -> Option<GitUrl> {
+    let parsed = ParsedUrl::parse(url)?;
+    let (known_scheme, ssh) = match parsed.scheme.map(str::to_lowercase) {
+      Some(scheme) => match scheme.as_str() {
+        "ssh" | "git+ssh" | "ssh+git" => (true, true),
+        "git" | "http" | "https" => (true, false),
+        _ => (false, false),
+      },
+      None => (true, true),
+    };
+    if !known_scheme || parsed.port.is_some() {
+      return None;
+    }
+    Some(GitUrl {
+      host: parsed.host?.to_owned(),
+      path: parsed.path.to_owned(),
+      ssh_user: parsed.user.filter(|_| ssh).map(ToOwned::to_owned),
+    })
+  }
+
+  pub fn to_url(&self, protocol: Protocol) -> String {
+    match protocol {
+      Protocol::Ssh => format!("{}@{}:{}.git", self.ssh_user.as_deref().unwrap_or("git"), self.host, self.path),
+      Protocol::Https => format!("https://{}/{}.git", self.host, self.path),
+    }
+  }
+}
+
+/// `url` in the form of `protocol`, or as it is if it has no such form or is not on one of the known forges or
+/// `forge_hosts`.
+pub fn normalize(url: &str, protocol: Protocol, forge_hosts: &[&str]) -> String {
+  GitUrl::parse(url)
+    .filter(|git_url| KNOWN_FORGES.iter().chain(forge_hosts).any(|host| host.eq_ignore_ascii_case(&git_url.host)))
+    .map(|git_url| git_url.to_url(protocol))
+    .unwrap_or_else(|| url.to_owned())
+}
+
+/// Host and repository path of URLs like `git@github.com:acme/fw.git` or `https://gitlab.com/acme/sub/fw`.
+pub fn host_and_path(url: &str) -> Option<(&str, &str)> {
+  let parsed = ParsedUrl::parse(url)?;
+  Some((parsed.host?, parsed.path))
+}
+
+/// The same for the SSH and HTTPS URL of a repository, `github.com/brocode/fw` for both
+/// `git@github.com:brocode/fw.git` and `https://github.com/brocode/fw`.
+pub fn repository_key(url: &str) -> String {
+  match host_and_path(url) {
+    Some((host, path)) => format!("{}/{}", host, path).to_lowercase(),
+    None => url.trim_end_matches('/').trim_end_matches(".git").to_owned(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_normalize() {
+    for url in &[
+      "git@github.com:brocode/fw.git",
+      "git@github.com:brocode/fw",
+      "https://github.com/brocode/fw",
+      "https://user@github.com/brocode/fw.git/",
+      "ssh://git@github.com/brocode/fw.git",
+    ] {
+      assert_that(&normalize(url, Protocol::Ssh, &[])).is_equal_to("git@github.com:brocode/fw.git".to_owned());
+      assert_that(&normalize(url, Protocol::Https, &[])).is_equal_to("https://github.com/brocode/fw.git".to_owned());
+    }
+    assert_that(&normalize("https://gitlab.com/acme/backend/api", Protocol::Ssh, &[])).is_equal_to("git@gitlab.com:acme/backend/api.git".to_owned());
+    for unchanged in &["ssh://git@host:2222/acme/fw.git", "/tmp/repos/fw", "file:///tmp/repos/fw", "~/repos/fw"] {
+      assert_that(&normalize(unchanged, Protocol::Https, &[])).is_equal_to(unchanged.to_string());
+    }
+  }
+
+  #[test]
+  fn test_normalize_only_known_forges() {
+    for unchanged in &[
+      "git@ssh.dev.azure.com:v3/acme/project/repo",
+      "https://dev.azure.com/acme/project/_git/repo",
+      "ssh://git-codecommit.eu-west-1.amazonaws.com/v1/repos/fw",
+      "https://source.developers.google.com/p/acme/r/fw",
+    ] {
+      assert_that(&normalize(unchanged, Protocol::Ssh, &[])).is_equal_to(unchanged.to_string());
+      assert_that(&normalize(unchanged, Protocol::Https, &[])).is_equal_to(unchanged.to_string());
+    }
+    assert_that(&normalize("https://git.acme.dev/team/fw", Protocol::Ssh, &["git.acme.dev"])).is_equal_to("git@git.acme.dev:team/fw.git".to_owned());
+  }
+
+  #[test]
+  fn test_normalize_keeps_ssh_user() {
+    assert_that(&normalize("ssh://aur@github.com/brocode/fw.git", Protocol::Ssh, &[])).is_equal_to("aur@github.com:brocode/fw.git".to_owned());
+    assert_that(&normalize("kuci@bitbucket.org:brocode/fw.git", Protocol::Https, &[])).is_equal_to("https://bitbucket.org/brocode/fw.git".to_owned());
+    assert_that(&normalize("https://kuci@github.com/brocode/fw", Protocol::Ssh, &[])).is_equal_to("git@github.com:brocode/fw.git".to_owned());
+  }
+
+  #[test]
+  fn test_parse() {
+    let parsed = ParsedUrl::parse("ssh://git@host:2222/group/sub/repo.git").unwrap();
+    assert_that(&parsed).is_equal_to(ParsedUrl {
+      scheme: Some("ssh"),
+      user: Some("git"),
+      host: Some("host"),
+      port: Some(2222),
+      path: "group/sub/repo",
+    });
+    assert_that(&parsed.owner()).is_equal_to(Some("group/sub"));
+    assert_that(&parsed.repo()).is_equal_to("repo");
+
+    let scp = ParsedUrl::parse("git@github.com:brocode/fw.git.git").unwrap();
+    assert_that(&(scp.scheme, scp.host, scp.owner(), scp.repo())).is_equal_to((None, Some("github.com"), Some("brocode"), "fw.git"));
+    let ipv6 = ParsedUrl::parse("ssh://git@[::1]:22/fw").unwrap();
+    assert_that(&(ipv6.host, ipv6.port, ipv6.owner(), ipv6.repo())).is_equal_to((Some("::1"), Some(22), None, "fw"));
+
+    let file = ParsedUrl::parse("file:///srv/git/fw.git/").unwrap();
+    assert_that(&(file.host, file.path, file.owner(), file.repo())).is_equal_to((None, "/srv/git/fw", None, "fw"));
+    assert_that(&ParsedUrl::parse("../repos/fw").map(|p| (p.host, p.repo()))).is_equal_to(Some((None, "fw")));
+    assert_that(&ParsedUrl::parse("C:\\repos\\fw.git").map(|p| (p.host, p.repo()))).is_equal_to(Some((None, "fw")));
+    assert_that(&ParsedUrl::parse("https://github.com/")).is_none();
+    assert_that(&ParsedUrl::parse("ssh://git@host:port/fw")).is_none();
+  }
+
+  #[test]
+  fn test_repository_key() {
+    assert_that(&repository_key("git@github.com:Brocode/fw.git")).is_equal_to(repository_key("https://github.com/brocode/fw"));
+    assert_that(&repository_key("ssh://git@gitlab.com/acme/sub/api.git")).is_equal_to("gitlab.com/acme/sub/api".to_owned());
+    assert_that(&repository_key("/tmp/repos/local.git")).is_equal_to("/tmp/repos/local".to_owned());
+  }
+}
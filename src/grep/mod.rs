@@ -0,0 +1,144 @@
+use crate::config::{selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::output::Tone;
+use crate::signal;
+use grep_regex::RegexMatcherBuilder;
+use grep_searcher::sinks::Lossy;
+use grep_searcher::{BinaryDetection, SearcherBuilder};
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+use slog::{debug, Logger};
+use std::path::{Path, PathBuf};
+
+/// How `fw grep` interprets the pattern.
+#[derive(Debug, Clone, Default)]
+pub struct GrepOptions {
+  pub ignore_case: bool,
+  /// Search for the pattern literally instead of as a regular expression.
+  pub fixed_strings: bool,
+}
+
+/// A matching line, `path` is relative to the project.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+  pub path: PathBuf,
+  pub line: u64,
+  pub text: String,
+}
+
+/// Calls `f` with the absolute and the relative path of every file in `root` like ripgrep walks:
+/// files ignored by git or `.ignore` are skipped, and so are hidden files unless `include_hidden`. `.git` is always skipped.
+pub(crate) fn walk_files<F>(root: &Path, include_hidden: bool, logger: &Logger, mut f: F) -> Result<(), AppError>
+where
+  F: FnMut(&Path, &Path),
+{
+  let walker = WalkBuilder::new(root)
+    .hidden(!include_hidden)
+    .filter_entry(|entry| entry.file_name() != ".git")
+    .build();
+  for entry in walker {
+    signal::check_interrupted()?;
+    let entry = match entry {
+      Ok(entry) => entry,
+      Err(e) => {
+        debug!(logger, "Skipping unreadable entry"; "error" => format!("{}", e));
+        continue;
+      }
+    };
+    if entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+      let path = entry.path();
+      f(path, path.strip_prefix(root).unwrap_or(path));
+    }
+  }
+  Ok(())
+}
+
+// Binary files are not searched.
+fn search_dir(matcher: &grep_regex::RegexMatcher, root: &Path, logger: &Logger) -> Result<Vec<Match>, AppError> {
+  let mut searcher = SearcherBuilder::new()
+    .binary_detection(BinaryDetection::quit(b'\x00'))
+    .line_number(true)
+    .build();
+  let mut matches = Vec::new();
+  walk_files(root, false, logger, |path, relative| {
+    let result = searcher.search_path(
+      matcher,
+      path,
+      Lossy(|line, text| {
+        matches.push(Match {
+          path: relative.to_path_buf(),
+          line,
+          text: text.trim_end_matches(&['\r', '\n'][..]).to_owned(),
+        });
+        Ok(true)
+      }),
+    );
+    if let Err(e) = result {
+      debug!(logger, "Skipping unreadable file"; "path" => path.to_string_lossy().to_string(), "error" => format!("{}", e));
+    }
+  })?;
+  Ok(matches)
+}
+
+/// Searches the checkouts of the selected projects for `pattern` in parallel and prints the matches as `project:path:line:text`,
+/// in the order of the projects.
+pub fn grep(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, pattern: &str, options: &GrepOptions, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let matcher = RegexMatcherBuilder::new()
+    .case_insensitive(options.ignore_case)
+    .fixed_strings(options.fixed_strings)
+    .build(pattern)
+    .map_err(|e| AppError::UserError(format!("Invalid pattern: {}", e)))?;
+
+  let results: Vec<(String, Result<Vec<Match>, AppError>)> = selector
+    .select(&config)
+    .par_iter()
+    .map(|project| (project, config.actual_path_to_project(project, logger)))
+    .filter(|(_, path)| path.exists())
+    .map(|(project, path)| (project.name.clone(), search_dir(&matcher, &path, logger)))
+    .collect();
+
+  for (project, result) in results {
+    for found in result? {
+      println!(
+        "{}{}{}",
+        Tone::Good.paint(&format!("{}:", project)),
+        Tone::Muted.paint(&format!("{}:{}:", found.path.to_string_lossy(), found.line)),
+        found.text
+      );
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use git2::Repository;
+  use slog::o;
+  use spectral::prelude::*;
+  use std::fs;
+
+  #[test]
+  fn test_search_dir_respects_gitignore() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    Repository::init(dir).unwrap();
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::create_dir_all(dir.join("target")).unwrap();
+    fs::write(dir.join(".gitignore"), "target\n").unwrap();
+    fs::write(dir.join("src/main.rs"), "fn main() {\n  println!(\"Needle\");\n}\n").unwrap();
+    fs::write(dir.join("target/out.txt"), "needle\n").unwrap();
+    fs::write(dir.join("blob.bin"), b"needle\x00\x01").unwrap();
+
+    let logger = Logger::root(slog::Discard, o!());
+    let matcher = RegexMatcherBuilder::new().case_insensitive(true).build("needle").unwrap();
+    let matches = search_dir(&matcher, dir, &logger).unwrap();
+    assert_that(&matches).is_equal_to(vec![Match {
+      path: PathBuf::from("src/main.rs"),
+      line: 2,
+      text: "  println!(\"Needle\");".to_owned(),
+    }]);
+  }
+}
@@ -0,0 +1,166 @@
+use crate::config::settings::GitlabSettings;
+use crate::config::{selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::output::{print_warning, Cell, Table, Tone};
+use crate::pr::{forge_of, Forge};
+use crate::runtime;
+use crate::ws::client::ForgeClient;
+use crate::ws::github;
+use serde::Deserialize;
+use slog::{debug, Logger};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// An open issue, pull request or merge request assigned to the user in one of the projects.
+#[derive(Debug, Clone, PartialEq)]
+struct Assignment {
+  project: String,
+  /// `PR #12`, `issue #3` or `MR !7`, the way the forge refers to it.
+  reference: String,
+  number: u64,
+  title: String,
+  url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitlabReferences {
+  full: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitlabItem {
+  iid: u64,
+  title: String,
+  web_url: String,
+  references: GitlabReferences,
+}
+
+/// The project path of a full GitLab reference like `acme/backend/api#12` or `acme/backend/api!7`.
+fn gitlab_path(full_reference: &str) -> Option<&str> {
+  full_reference.rfind(['#', '!']).map(|index| &full_reference[..index])
+}
+
+fn github_assignments(token: &str, by_repository: &BTreeMap<String, String>, timeout: Option<Duration>, logger: &Logger) -> Result<Vec<Assignment>, AppError> {
+  let api = github::github_api(token, logger)?;
+  let issues = runtime::block_on_interruptible(runtime::blocking("GitHub issues", timeout, move || api.assigned_issues()))?;
+  Ok(
+    issues
+      .into_iter()
+      .filter_map(|issue| {
+        let project = by_repository.get(&issue.repository.to_lowercase())?;
+        let kind = if issue.pull_request { "PR" } else { "issue" };
+        Some(Assignment {
+          project: project.clone(),
+          reference: format!("{} #{}", kind, issue.number),
+          number: issue.number,
+          title: issue.title,
+          url: issue.url,
+        })
+      })
+      .collect(),
+  )
+}
+
+fn gitlab_assignments(
+  gitlab: GitlabSettings,
+  by_path: &BTreeMap<String, String>,
+  timeout: Option<Duration>,
+  logger: &Logger,
+) -> Result<Vec<Assignment>, AppError> {
+  let logger = logger.clone();
+  let (issues, merge_requests) = runtime::block_on_interruptible(runtime::blocking("GitLab issues", timeout, move || {
+    let client = ForgeClient::gitlab(&gitlab.host, &gitlab.token, &logger)?;
+    let issues: Vec<GitlabItem> = client.get_all("issues?scope=assigned_to_me&state=opened&per_page=100")?;
+    let merge_requests: Vec<GitlabItem> = client.get_all("merge_requests?scope=assigned_to_me&state=opened&per_page=100")?;
+    Ok((issues, merge_requests))
+  }))?;
+  let labelled = issues
+    .into_iter()
+    .map(|item| (format!("issue #{}", item.iid), item))
+    .chain(merge_requests.into_iter().map(|item| (format!("MR !{}", item.iid), item)));
+  Ok(
+    labelled
+      .filter_map(|(reference, item)| {
+        let project = by_path.get(&gitlab_path(&item.references.full)?.to_lowercase())?;
+        Some(Assignment {
+          project: project.clone(),
+          reference,
+          number: item.iid,
+          title: item.title,
+          url: item.web_url,
+        })
+      })
+      .collect(),
+  )
+}
+
+/// Lists the open issues and pull or merge requests assigned to the owner of the GitHub token or the GitLab token that
+/// belong to the selected projects.
+pub fn issues(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, timeout: Option<Duration>, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let gitlab_host = config.settings.gitlab.as_ref().map(|g| g.host.as_str());
+  // the forge repository of every selected project, lowercase since GitHub and GitLab paths are case insensitive
+  let mut github_repositories = BTreeMap::new();
+  let mut gitlab_paths = BTreeMap::new();
+  for project in selector.select_repositories(&config) {
+    match forge_of(&project.git, gitlab_host) {
+      Some(Forge::GitHub { owner, repo }) => {
+        github_repositories.insert(format!("{}/{}", owner, repo).to_lowercase(), project.name.clone());
+      }
+      Some(Forge::GitLab { path }) => {
+        gitlab_paths.insert(path.to_lowercase(), project.name.clone());
+      }
+      None => debug!(logger, "Skipping, not on GitHub or GitLab"; "project" => &project.name),
+    }
+  }
+
+  let mut assignments = Vec::new();
+  if !github_repositories.is_empty() {
+    match github::token(config.settings.github_token.as_ref()) {
+      Some(token) => assignments.extend(github_assignments(&token, &github_repositories, timeout, logger)?),
+      None => print_warning(&format!(
+        "Skipping {} GitHub project(s), there is no github oauth token (settings.github_token or FW_GITHUB_TOKEN)",
+        github_repositories.len()
+      )),
+    }
+  }
+  if !gitlab_paths.is_empty() {
+    match config.settings.gitlab.clone() {
+      Some(gitlab) => assignments.extend(gitlab_assignments(gitlab, &gitlab_paths, timeout, logger)?),
+      None => print_warning(&format!(
+        "Skipping {} GitLab project(s), there are no gitlab settings (token and host)",
+        gitlab_paths.len()
+      )),
+    }
+  }
+
+  if assignments.is_empty() {
+    println!("{}", Tone::Muted.paint("Nothing assigned to you"));
+    return Ok(());
+  }
+  assignments.sort_by(|a, b| (&a.project, a.number).cmp(&(&b.project, b.number)));
+  let mut table = Table::new();
+  for assignment in assignments {
+    table.add_row(vec![
+      Cell::plain(assignment.project),
+      Cell::plain(assignment.reference),
+      Cell::plain(assignment.title),
+      Cell::new(assignment.url, Tone::Muted),
+    ]);
+  }
+  table.print();
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_gitlab_path() {
+    assert_that(&gitlab_path("acme/backend/api#12")).is_equal_to(Some("acme/backend/api"));
+    assert_that(&gitlab_path("acme/api!7")).is_equal_to(Some("acme/api"));
+    assert_that(&gitlab_path("acme/api")).is_none();
+  }
+}
@@ -0,0 +1,62 @@
+//! The library behind the `fw` binary.
+//!
+//! It contains the config model ([`Config`], [`Project`]), the resolution of tags, hooks and paths,
+//! and the git operations used by `fw sync`. Editor plugins and other tools can depend on it to read
+//! a user's fw configuration and act on it the same way the command line does.
+
+pub mod adopt;
+pub mod archive;
+pub mod backup;
+pub mod branch;
+pub mod changes;
+pub mod config;
+pub mod daemon;
+pub mod doctor;
+pub mod du;
+pub mod errors;
+pub mod export;
+pub mod git;
+pub mod git_url;
+pub mod grep;
+pub mod issues;
+pub mod lock;
+pub mod network;
+pub mod notify;
+pub mod output;
+pub mod patch;
+pub mod plugin;
+pub mod pr;
+pub mod project;
+pub mod projectile;
+pub mod propagate;
+pub mod pull;
+pub mod query;
+pub mod recent;
+pub mod release;
+pub mod remotes;
+pub mod runtime;
+pub mod setup;
+pub mod shared;
+pub mod shell;
+pub mod signal;
+pub mod snapshot;
+pub mod spawn;
+pub mod stash;
+pub mod status;
+pub mod sync;
+pub mod tag;
+#[cfg(test)]
+mod testing;
+pub mod timetrack;
+pub mod trust;
+pub mod util;
+pub mod webhook;
+pub mod which;
+pub mod workon;
+pub mod ws;
+
+pub use crate::config::project::Project;
+pub use crate::config::selector::ProjectSelector;
+pub use crate::config::Config;
+pub use crate::errors::AppError;
+pub use crate::sync::SyncOptions;
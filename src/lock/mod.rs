@@ -0,0 +1,117 @@
+use crate::config::path::fw_path;
+use crate::errors::AppError;
+use crate::signal;
+use fs2::FileExt;
+use slog::{debug, info, Logger};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// What to do when another fw process holds the lock of a project.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LockMode {
+  /// Fail the project.
+  #[default]
+  Fail,
+  /// Block until the lock is released.
+  Wait,
+  /// Leave the project alone and report success.
+  Skip,
+}
+
+/// Exclusive lock on a project, released when dropped.
+#[derive(Debug)]
+pub struct ProjectLock {
+  file: File,
+}
+
+impl Drop for ProjectLock {
+  fn drop(&mut self) {
+    let _ = self.file.unlock();
+  }
+}
+
+/// Takes the lock of `project_name` below the fw state dir. Returns `None` if the project is locked and `mode` is `Skip`.
+pub fn lock_project(project_name: &str, mode: LockMode, logger: &Logger) -> Result<Option<ProjectLock>, AppError> {
+  let lock_dir = fw_path()?.ensure_state_dir_exists("locks")?;
+  lock_in(&lock_dir, project_name, mode, logger)
+}
+
+fn lock_in(lock_dir: &Path, project_name: &str, mode: LockMode, logger: &Logger) -> Result<Option<ProjectLock>, AppError> {
+  let path = lock_dir.join(format!("{}.lock", project_name));
+  let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+  loop {
+    match file.try_lock_exclusive() {
+      Ok(()) => break,
+      Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+        let holder = lock_holder(&mut file);
+        match mode {
+          LockMode::Fail => {
+            return Err(AppError::RuntimeError(format!(
+              "Project {} is locked by another fw process{}. Use --wait or --skip-locked.",
+              project_name, holder
+            )))
+          }
+          LockMode::Skip => {
+            info!(logger, "Skipping locked project"; "project" => project_name, "lock" => path.to_string_lossy().to_string());
+            return Ok(None);
+          }
+          LockMode::Wait => {
+            signal::check_interrupted()?;
+            thread::sleep(Duration::from_millis(100));
+          }
+        }
+      }
+      Err(e) => return Err(AppError::IO(e)),
+    }
+  }
+  file.set_len(0)?;
+  file.seek(SeekFrom::Start(0))?;
+  write!(file, "{}", std::process::id())?;
+  debug!(logger, "Lock acquired"; "project" => project_name, "lock" => path.to_string_lossy().to_string());
+  Ok(Some(ProjectLock { file }))
+}
+
+fn lock_holder(file: &mut File) -> String {
+  let mut pid = String::new();
+  match file.seek(SeekFrom::Start(0)).and_then(|_| file.read_to_string(&mut pid)) {
+    Ok(_) if !pid.trim().is_empty() => format!(" (pid {})", pid.trim()),
+    _ => String::new(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+
+  fn a_logger() -> Logger {
+    use slog::Drain;
+    let plain = slog_term::PlainSyncDecorator::new(std::io::stdout());
+    Logger::root(slog_term::FullFormat::new(plain).build().fuse(), slog::o!())
+  }
+
+  #[test]
+  fn test_lock_is_exclusive() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let logger = a_logger();
+    let first = lock_in(dir, "project", LockMode::Fail, &logger).unwrap();
+    assert_that(&first.is_some()).is_true();
+    assert_that(&lock_in(dir, "project", LockMode::Fail, &logger).is_err()).is_true();
+    assert_that(&lock_in(dir, "project", LockMode::Skip, &logger).unwrap().is_none()).is_true();
+    assert_that(&lock_in(dir, "other", LockMode::Fail, &logger).unwrap().is_some()).is_true();
+  }
+
+  #[test]
+  fn test_lock_is_released_on_drop() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let logger = a_logger();
+    drop(lock_in(dir, "project", LockMode::Fail, &logger).unwrap());
+    assert_that(&lock_in(dir, "project", LockMode::Fail, &logger).unwrap().is_some()).is_true();
+  }
+}
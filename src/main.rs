@@ -1,12 +1,23 @@
-use crate::errors::AppError;
-use crate::util::logger_from_verbosity;
+use fw_core::errors::AppError;
+use fw_core::lock::LockMode;
+use fw_core::util::{logger_from_verbosity, LogFormat};
+use fw_core::{
+  adopt, archive, backup, branch, changes, config, daemon, doctor, du, export, grep, issues, network, output, patch, plugin, pr, project, projectile,
+  propagate, pull, query, recent, release, remotes, setup, shared, signal, snapshot, spawn, stash, status, sync, tag, timetrack, trust, webhook, which, workon,
+};
+use fw_core::{ProjectSelector, SyncOptions};
+use regex::Regex;
 use slog::Logger;
 use slog::{crit, debug, o, warn};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 fn main() {
   openssl_probe::init_ssl_cert_env_vars();
+  if let Err(e) = signal::install_handler() {
+    eprintln!("{}", e);
+  }
   let return_code = _main();
   std::process::exit(return_code)
 }
@@ -14,43 +25,98 @@ fn main() {
 fn _main() -> i32 {
   let matches = crate::app::app().get_matches();
 
-  let logger = logger_from_verbosity(matches.occurrences_of("v"), matches.is_present("q"));
+  let verbosity = matches.occurrences_of("v");
+  let quiet = matches.is_present("q");
+  if matches.is_present("non-interactive") {
+    std::env::set_var("FW_NON_INTERACTIVE", "1");
+  }
+  // without an explicit choice CI gets JSON logs and no colours
+  let non_interactive = output::non_interactive();
+  let log_format: LogFormat = if non_interactive && matches.occurrences_of("log-format") == 0 {
+    LogFormat::Json
+  } else {
+    matches
+      .value_of("log-format")
+      .expect("argument has default value")
+      .parse()
+      .expect("argument values restricted by clap.rs")
+  };
+  output::init(if non_interactive && matches.occurrences_of("color") == 0 {
+    output::ColorChoice::Never
+  } else {
+    matches
+      .value_of("color")
+      .expect("argument has default value")
+      .parse()
+      .expect("argument values restricted by clap.rs")
+  });
+  let mut logger = logger_from_verbosity(verbosity, quiet, log_format, None).expect("stderr logger does not touch the file system");
 
+  // through the environment the config and profile also hold for fw called from hooks and foreach
+  if let Some(config) = matches.value_of("config") {
+    let config = config::path::expand_path(PathBuf::from(config));
+    std::env::set_var("FW_CONFIG", std::env::current_dir().map(|dir| dir.join(&config)).unwrap_or(config));
+  }
+  if let Some(profile) = matches.value_of("profile") {
+    std::env::set_var("FW_PROFILE", profile);
+  }
+  if matches.is_present("offline") {
+    std::env::set_var("FW_OFFLINE", "1");
+  }
   let config = config::read_config(&logger);
-  if config.is_err() {
-    warn!(
+  match config {
+    Err(_) => warn!(
       logger,
       "Could not read v2.0 config: {:?}. If you are running the setup right now this is expected.", config
-    );
+    ),
+    Ok(ref c) => {
+      network::apply(&c.settings, &logger);
+      if let Some(log_file) = c.log_file() {
+        match logger_from_verbosity(verbosity, quiet, log_format, Some(&log_file)) {
+          Ok(file_logger) => logger = file_logger,
+          Err(e) => warn!(logger, "Could not open log file, logging to stderr only"; "log_file" => format!("{:?}", log_file), "error" => format!("{}", e)),
+        }
+      }
+    }
   };
 
   let subcommand_name = matches.subcommand_name().expect("subcommand required by clap.rs").to_owned();
   let subcommand_matches = matches.subcommand_matches(&subcommand_name).expect("subcommand matches enforced by clap.rs");
   let subcommand_logger = logger.new(o!("command" => subcommand_name.clone()));
 
+  let frozen = matches.is_present("frozen") || config.as_ref().map(|c| c.settings.readonly.unwrap_or_default()).unwrap_or(false);
+
   let now = SystemTime::now();
   let result: Result<String, AppError> = match subcommand_name.as_ref() {
+    name if frozen && modifies_config(name, subcommand_matches) => Err(AppError::UserError(format!(
+      "fw is read-only (readonly = true in the settings, --frozen or a single .fw.json file), refusing to run '{}' because it modifies the configuration",
+      name
+    ))),
     "sync" => {
       let worker = subcommand_matches
         .value_of("parallelism")
         .and_then(|i| i.parse::<i32>().ok())
         .expect("enforced by clap.rs");
 
-      sync::synchronize(
-        config,
-        subcommand_matches.is_present("no-progress-bar"),
-        subcommand_matches.is_present("only-new"),
-        !subcommand_matches.is_present("no-fast-forward-merge"),
-        &subcommand_matches.values_of_lossy("tag").unwrap_or_default().into_iter().collect(),
+      let options = SyncOptions {
+        no_progress_bar: subcommand_matches.is_present("no-progress-bar"),
+        only_new: subcommand_matches.is_present("only-new"),
+        only_existing: false,
+        ff_merge: !subcommand_matches.is_present("no-fast-forward-merge"),
         worker,
-        &subcommand_logger,
-      )
+        timeout: timeout_from(subcommand_matches),
+        selector: selector_from(subcommand_matches),
+        lock_mode: lock_mode_from(subcommand_matches),
+        notify: subcommand_matches.is_present("notify"),
+        no_hooks: subcommand_matches.is_present("no-hooks"),
+      };
+      sync::synchronize(config, &options, &subcommand_logger)
     }
     "add-remote" => {
       let name: &str = subcommand_matches.value_of("NAME").expect("argument required by clap.rs");
       let remote_name: &str = subcommand_matches.value_of("REMOTE_NAME").expect("argument required by clap.rs");
       let url: &str = subcommand_matches.value_of("URL").expect("argument required by clap.rs");
-      project::add_remote(config, name, remote_name.to_string(), url.to_string())
+      project::add_remote(config, name, remote_name.to_string(), url.to_string(), &subcommand_logger)
     }
     "remove-remote" => {
       let name: &str = subcommand_matches.value_of("NAME").expect("argument required by clap.rs");
@@ -63,14 +129,49 @@ fn _main() -> i32 {
       let after_workon: Option<String> = subcommand_matches.value_of("after-workon").map(str::to_string);
       let after_clone: Option<String> = subcommand_matches.value_of("after-clone").map(str::to_string);
       let override_path: Option<String> = subcommand_matches.value_of("override-path").map(str::to_string);
-      project::add_entry(config, name, url, after_workon, after_clone, override_path, &subcommand_logger)
+      // asking needs someone to answer
+      let default = if output::can_prompt() {
+        project::OnConflict::Ask
+      } else {
+        project::OnConflict::Fail
+      };
+      let on_conflict = on_conflict_from(subcommand_matches, default);
+      project::add_entry(config, name, url, after_workon, after_clone, override_path, on_conflict, &subcommand_logger)
     }
+    "archive" => archive::archive(
+      config,
+      subcommand_matches.value_of("NAME").expect("argument required by clap.rs"),
+      &archive::ArchiveOptions {
+        bundle: subcommand_matches.is_present("bundle"),
+        bundle_dir: subcommand_matches
+          .value_of("bundle-dir")
+          .map(|dir| config::path::expand_path(PathBuf::from(dir))),
+        force: subcommand_matches.is_present("force"),
+      },
+      &subcommand_logger,
+    ),
+    "unarchive" => archive::unarchive(
+      config,
+      subcommand_matches.value_of("NAME").expect("argument required by clap.rs"),
+      &archive::ArchiveOptions {
+        bundle_dir: subcommand_matches
+          .value_of("bundle-dir")
+          .map(|dir| config::path::expand_path(PathBuf::from(dir))),
+        ..archive::ArchiveOptions::default()
+      },
+      &subcommand_logger,
+    ),
     "remove" => project::remove_project(
       config,
       subcommand_matches.value_of("NAME").expect("argument required by clap.rs"),
       subcommand_matches.is_present("purge-directory"),
       &subcommand_logger,
     ),
+    "move-to-workspace" => project::move_to_workspace(
+      config,
+      subcommand_matches.value_of("NAME").expect("argument required by clap.rs"),
+      &subcommand_logger,
+    ),
     "update" => {
       let name: &str = subcommand_matches.value_of("NAME").expect("argument required by clap.rs");
       let git: Option<String> = subcommand_matches.value_of("git").map(str::to_string);
@@ -83,55 +184,158 @@ fn _main() -> i32 {
       subcommand_matches.value_of("WORKSPACE_DIR").expect("argument required by clap.rs"),
       &subcommand_logger,
     ),
-    "import" => setup::import(
-      config,
-      subcommand_matches.value_of("PROJECT_DIR").expect("argument required by clap.rs"),
-      &subcommand_logger,
-    ),
+    "import" => match subcommand_matches.subcommand_matches("github-stars") {
+      Some(stars_matches) => setup::github_stars_import(
+        config,
+        stars_matches.value_of("user"),
+        stars_matches.value_of("language"),
+        stars_matches.value_of("topic"),
+        timeout_from(stars_matches),
+        &import_options_from(stars_matches),
+        &subcommand_logger,
+      ),
+      None => setup::import(
+        config,
+        subcommand_matches.value_of("PROJECT_DIR").expect("argument required by clap.rs"),
+        subcommand_matches.is_present("dry-run"),
+        &subcommand_logger,
+      ),
+    },
     "org-import" => setup::org_import(
       config,
       subcommand_matches.value_of("ORG_NAME").expect("argument required by clap.rs"),
+      subcommand_matches.value_of("team"),
       subcommand_matches.is_present("include-archived"),
+      timeout_from(subcommand_matches),
+      &import_options_from(subcommand_matches),
       &subcommand_logger,
     ),
     "gitlab-import" => {
-      let state = subcommand_matches
+      // --include took the state before it filtered the names for all importers
+      let legacy_state = subcommand_matches
         .value_of("include")
+        .filter(|include| ["active", "archived", "both"].contains(include));
+      let mut options = import_options_from(subcommand_matches);
+      if legacy_state.is_some() {
+        warn!(subcommand_logger, "--include with a state is deprecated, use --state");
+        options.include = None;
+      }
+      let state = legacy_state
+        .or_else(|| subcommand_matches.value_of("state"))
         .expect("argument required by clap.rs")
         .parse()
         .expect("argument values restricted by clap.rs");
-      setup::gitlab_import(config, state, &subcommand_logger)
+      setup::gitlab_import(config, state, timeout_from(subcommand_matches), &options, &subcommand_logger)
     }
-    "gen-workon" => workon::gen(
-      subcommand_matches.value_of("PROJECT_NAME").expect("argument required by clap.rs"),
+    "migrate-paths" => setup::migrate::migrate_paths(&subcommand_logger),
+    "import-from" => subcommand_matches
+      .value_of("TOOL")
+      .expect("argument required by clap.rs")
+      .parse()
+      .and_then(|tool| {
+        setup::migrate::import_from(
+          config,
+          tool,
+          subcommand_matches.value_of("PATH"),
+          &import_options_from(subcommand_matches),
+          &subcommand_logger,
+        )
+      }),
+    "gitea-import" => setup::gitea_import(
+      config,
+      subcommand_matches.value_of("BASE_URL").expect("argument required by clap.rs"),
+      subcommand_matches.value_of("OWNER").expect("argument required by clap.rs"),
+      subcommand_matches.is_present("include-archived"),
+      timeout_from(subcommand_matches),
+      &import_options_from(subcommand_matches),
+      &subcommand_logger,
+    ),
+    "azure-import" => setup::azure_import(
+      config,
+      subcommand_matches.value_of("PROJECT").expect("argument required by clap.rs"),
+      timeout_from(subcommand_matches),
+      &import_options_from(subcommand_matches),
+      &subcommand_logger,
+    ),
+    "codecommit-import" => setup::codecommit_import(
+      config,
+      subcommand_matches.value_of("region"),
+      timeout_from(subcommand_matches),
+      &import_options_from(subcommand_matches),
+      &subcommand_logger,
+    ),
+    "cloud-source-import" => setup::cloud_source_import(
       config,
-      subcommand_matches.is_present("quick"),
+      subcommand_matches.value_of("project"),
+      timeout_from(subcommand_matches),
+      &import_options_from(subcommand_matches),
       &subcommand_logger,
     ),
+    "gen-workon" => match subcommand_matches.value_of("PROJECT_NAME") {
+      Some("-") => workon::gen_from_history(config, true, subcommand_matches.is_present("quick"), &subcommand_logger),
+      None => workon::gen_from_history(config, false, subcommand_matches.is_present("quick"), &subcommand_logger),
+      Some(name_or_url) => workon::gen(
+        name_or_url,
+        config,
+        subcommand_matches.is_present("quick"),
+        subcommand_matches.is_present("create"),
+        &subcommand_logger,
+      ),
+    },
     "gen-reworkon" => workon::gen_reworkon(config, &subcommand_logger),
     "reworkon" => workon::reworkon(config, &subcommand_logger),
+    "path" => project::print_attribute(
+      config,
+      subcommand_matches.value_of("PROJECT_NAME").expect("argument required by clap.rs"),
+      project::Attribute::Path,
+      &subcommand_logger,
+    ),
+    "url" => project::print_attribute(
+      config,
+      subcommand_matches.value_of("PROJECT_NAME").expect("argument required by clap.rs"),
+      project::Attribute::Url,
+      &subcommand_logger,
+    ),
+    "main-branch" => project::print_attribute(
+      config,
+      subcommand_matches.value_of("PROJECT_NAME").expect("argument required by clap.rs"),
+      project::Attribute::MainBranch,
+      &subcommand_logger,
+    ),
     "inspect" => project::inspect(
       subcommand_matches.value_of("PROJECT_NAME").expect("argument required by clap.rs"),
       config,
       subcommand_matches.is_present("json"),
+      subcommand_matches.is_present("explain"),
       &subcommand_logger,
     ),
-    "projectile" => projectile::projectile(config, &subcommand_logger),
-    "print-path" => project::print_path(
+    "trust" => trust::trust(
       config,
       subcommand_matches.value_of("PROJECT_NAME").expect("argument required by clap.rs"),
       &subcommand_logger,
     ),
+    "projectile" => projectile::projectile(config, &subcommand_logger),
     "foreach" => spawn::foreach(
       config,
       subcommand_matches.value_of("CMD").expect("argument required by clap.rs"),
-      &subcommand_matches.values_of_lossy("tag").unwrap_or_default().into_iter().collect(),
+      &selector_from(subcommand_matches),
+      subcommand_matches.is_present("ordered"),
+      &subcommand_logger,
+      &subcommand_matches.value_of("parallel").map(ToOwned::to_owned),
+      subcommand_matches.is_present("notify"),
+    ),
+    "run" => spawn::run(
+      config,
+      subcommand_matches.value_of("NAME").expect("argument required by clap.rs"),
+      &selector_from(subcommand_matches),
+      subcommand_matches.is_present("ordered"),
       &subcommand_logger,
       &subcommand_matches.value_of("parallel").map(ToOwned::to_owned),
+      subcommand_matches.is_present("notify"),
     ),
-    "print-zsh-setup" => crate::shell::print_zsh_setup(subcommand_matches.is_present("with-fzf"), subcommand_matches.is_present("with-skim")),
-    "print-bash-setup" => crate::shell::print_bash_setup(subcommand_matches.is_present("with-fzf"), subcommand_matches.is_present("with-skim")),
-    "print-fish-setup" => crate::shell::print_fish_setup(subcommand_matches.is_present("with-fzf"), subcommand_matches.is_present("with-skim")),
+    "print-zsh-setup" => fw_core::shell::print_zsh_setup(subcommand_matches.is_present("with-fzf"), subcommand_matches.is_present("with-skim")),
+    "print-bash-setup" => fw_core::shell::print_bash_setup(subcommand_matches.is_present("with-fzf"), subcommand_matches.is_present("with-skim")),
+    "print-fish-setup" => fw_core::shell::print_fish_setup(subcommand_matches.is_present("with-fzf"), subcommand_matches.is_present("with-skim")),
     "tag" => {
       let subsubcommand_name: String = subcommand_matches.subcommand_name().expect("subcommand matches enforced by clap.rs").to_owned();
       let subsubcommand_matches: clap::ArgMatches<'_> = subcommand_matches
@@ -140,8 +344,362 @@ fn _main() -> i32 {
         .to_owned();
       execute_tag_subcommand(config, &subsubcommand_name, &subsubcommand_matches, &subcommand_logger)
     }
-    "ls" => project::ls(config, &subcommand_matches.values_of_lossy("tag").unwrap_or_default().into_iter().collect()),
-    _ => Err(AppError::InternalError("Command not implemented")),
+    "ls" => project::ls(
+      config,
+      &selector_from(subcommand_matches),
+      subcommand_matches.is_present("long"),
+      subcommand_matches.is_present("recent"),
+      subcommand_matches.is_present("url"),
+      &subcommand_logger,
+    ),
+    "doctor" => doctor::doctor(config, &subcommand_logger),
+    "recent" => recent::recent(
+      config,
+      subcommand_matches.value_of("limit").map(|n| n.parse().expect("validated by clap.rs")),
+      subcommand_matches.is_present("visits"),
+      &subcommand_logger,
+    ),
+    "time" => match subcommand_matches.subcommand() {
+      ("report", Some(report_matches)) => changes::now().and_then(|now| {
+        timetrack::report(
+          config,
+          changes::parse_since(report_matches.value_of("since").expect("argument has default value"), now).expect("validated by clap.rs"),
+          now,
+          report_matches.is_present("by-tag"),
+          &subcommand_logger,
+        )
+      }),
+      ("invoice", Some(invoice_matches)) => changes::now().and_then(|now| {
+        timetrack::invoice(
+          config,
+          &selector_from(invoice_matches).including_archived(),
+          invoice_matches
+            .value_of("rate")
+            .expect("argument required by clap.rs")
+            .parse()
+            .expect("validated by clap.rs"),
+          changes::parse_since(invoice_matches.value_of("since").expect("argument has default value"), now).expect("validated by clap.rs"),
+          invoice_matches
+            .value_of("until")
+            .map_or(now, |until| changes::parse_since(until, now).expect("validated by clap.rs")),
+          invoice_matches
+            .value_of("format")
+            .expect("argument has default value")
+            .parse()
+            .expect("argument values restricted by clap.rs"),
+          &subcommand_logger,
+        )
+      }),
+      _ => Err(AppError::InternalError("Command not implemented")),
+    },
+    "config" => match subcommand_matches.subcommand() {
+      ("schema", Some(schema_matches)) => schema_matches
+        .value_of("KIND")
+        .expect("argument has default value")
+        .parse()
+        .and_then(config::schema::print_schema),
+      ("encrypt", Some(encrypt_matches)) => encrypt_matches
+        .value_of("with")
+        .expect("argument has default value")
+        .parse()
+        .and_then(|envelope| config::crypt::set_envelope(Some(envelope), encrypt_matches.is_present("all"), &subcommand_logger)),
+      ("decrypt", Some(decrypt_matches)) => config::crypt::set_envelope(None, decrypt_matches.is_present("all"), &subcommand_logger),
+      _ => Err(AppError::InternalError("Command not implemented")),
+    },
+    "export" => match subcommand_matches.subcommand() {
+      ("direnv", Some(export_matches)) => export::direnv(
+        config,
+        export_matches.value_of("PROJECT_NAME").expect("argument required by clap.rs"),
+        export_matches.is_present("force"),
+        &subcommand_logger,
+      ),
+      ("nix", Some(export_matches)) => export::nix::nix(config, export_matches.is_present("home-manager")),
+      ("all-direnv", Some(export_matches)) => {
+        export::all_direnv(config, &selector_from(export_matches), export_matches.is_present("force"), &subcommand_logger)
+      }
+      _ => Err(AppError::InternalError("Command not implemented")),
+    },
+    "branch" => match subcommand_matches.subcommand() {
+      ("create", Some(branch_matches)) => branch::create(
+        config,
+        &selector_from(branch_matches),
+        branch_matches.value_of("BRANCH").expect("argument required by clap.rs"),
+        branch_matches.is_present("checkout"),
+        &subcommand_logger,
+      ),
+      ("delete", Some(branch_matches)) => branch::delete(
+        config,
+        &selector_from(branch_matches),
+        branch_matches.value_of("BRANCH").expect("argument required by clap.rs"),
+        branch_matches.is_present("force"),
+        &subcommand_logger,
+      ),
+      ("ls", Some(branch_matches)) => branch::list(
+        config,
+        &selector_from(branch_matches),
+        branch_matches.value_of("BRANCH").expect("argument required by clap.rs"),
+        &subcommand_logger,
+      ),
+      _ => Err(AppError::InternalError("Command not implemented")),
+    },
+    "release" => match subcommand_matches.subcommand() {
+      ("tag", Some(release_matches)) => {
+        let version = release_matches.value_of("VERSION").expect("argument required by clap.rs");
+        let options = release::ReleaseOptions {
+          version: version.to_owned(),
+          message: release_matches.value_of("message").map(ToOwned::to_owned),
+          push: release_matches.is_present("push"),
+        };
+        let manifest = release_matches
+          .value_of("manifest")
+          .map(PathBuf::from)
+          .unwrap_or_else(|| PathBuf::from(format!("release-{}.json", version)));
+        release::tag(config, &selector_from(release_matches), &options, &manifest, &subcommand_logger)
+      }
+      _ => Err(AppError::InternalError("Command not implemented")),
+    },
+    "pr" => match subcommand_matches.subcommand() {
+      ("create", Some(pr_matches)) => pr_matches
+        .value_of("body-file")
+        .map(|file| std::fs::read_to_string(file).map_err(|e| AppError::UserError(format!("Could not read body file {}: {}", file, e))))
+        .unwrap_or_else(|| Ok(String::new()))
+        .and_then(|body| {
+          let options = pr::PullRequestOptions {
+            title: pr_matches.value_of("title").expect("argument required by clap.rs").to_owned(),
+            body,
+            branch: pr_matches.value_of("branch").map(ToOwned::to_owned),
+            base: pr_matches.value_of("base").map(ToOwned::to_owned),
+            dry_run: pr_matches.is_present("dry-run"),
+            timeout: timeout_from(pr_matches),
+          };
+          pr::create(config, &selector_from(pr_matches), &options, &subcommand_logger)
+        }),
+      _ => Err(AppError::InternalError("Command not implemented")),
+    },
+    "propagate" => propagate::propagate(
+      config,
+      &selector_from(subcommand_matches),
+      Path::new(subcommand_matches.value_of("SOURCE").expect("argument required by clap.rs")),
+      &propagate::PropagateOptions {
+        dest: subcommand_matches.value_of("dest").expect("argument required by clap.rs").to_owned(),
+        branch: subcommand_matches.value_of("branch").expect("argument has default value").to_owned(),
+        message: subcommand_matches.value_of("message").map(ToOwned::to_owned),
+        push: subcommand_matches.is_present("push"),
+        pr: subcommand_matches.is_present("pr"),
+        dry_run: subcommand_matches.is_present("dry-run"),
+        timeout: timeout_from(subcommand_matches),
+      },
+      &subcommand_logger,
+    ),
+    "apply-patch" => match subcommand_matches.value_of("sed") {
+      Some(expression) => patch::Change::substitute(expression, subcommand_matches.value_of("files")),
+      None => patch::Change::patch_from(subcommand_matches.value_of("PATCH").expect("argument required by clap.rs")),
+    }
+    .and_then(|change| {
+      let options = patch::PatchOptions {
+        message: subcommand_matches.value_of("message").expect("argument required by clap.rs").to_owned(),
+        dry_run: subcommand_matches.is_present("dry-run"),
+      };
+      patch::apply_patch(config, &selector_from(subcommand_matches), &change, &options, &subcommand_logger)
+    }),
+    "pull" => pull::pull(
+      config,
+      &selector_from(subcommand_matches),
+      &pull::PullOptions {
+        rebase: subcommand_matches.is_present("rebase"),
+        autostash: subcommand_matches.is_present("autostash"),
+        lock_mode: lock_mode_from(subcommand_matches),
+      },
+      &subcommand_logger,
+    ),
+    "clean-branches" => branch::clean(
+      config,
+      &selector_from(subcommand_matches),
+      subcommand_matches.is_present("prune"),
+      subcommand_matches.is_present("dry-run"),
+      &subcommand_logger,
+    ),
+    "compare" => branch::compare(
+      config,
+      &selector_from(subcommand_matches),
+      subcommand_matches.value_of("base"),
+      &subcommand_logger,
+    ),
+    "changes" => changes::now().and_then(|now| {
+      let since = match subcommand_matches.value_of("since-tag") {
+        Some(tag) => changes::Since::Tag(tag.to_owned()),
+        None => changes::Since::Time(
+          changes::parse_since(subcommand_matches.value_of("since").expect("argument required by clap.rs"), now).expect("validated by clap.rs"),
+        ),
+      };
+      let grouping = if subcommand_matches.is_present("by-author") {
+        changes::Grouping::Author
+      } else {
+        changes::Grouping::Project
+      };
+      changes::changes(
+        config,
+        &selector_from(subcommand_matches),
+        &since,
+        grouping,
+        subcommand_matches.is_present("markdown"),
+        &subcommand_logger,
+      )
+    }),
+    "stats" => changes::now().and_then(|now| {
+      changes::stats(
+        config,
+        &selector_from(subcommand_matches),
+        changes::parse_since(subcommand_matches.value_of("since").expect("argument has default value"), now).expect("validated by clap.rs"),
+        subcommand_matches.is_present("json"),
+        &subcommand_logger,
+      )
+    }),
+    "retag-from-forge" => tag::retag_from_forge(config, &selector_from(subcommand_matches), timeout_from(subcommand_matches), &subcommand_logger),
+    "issues" => issues::issues(config, &selector_from(subcommand_matches), timeout_from(subcommand_matches), &subcommand_logger),
+    "stash-all" => stash::stash_all(
+      config,
+      &selector_from(subcommand_matches),
+      subcommand_matches.is_present("include-untracked"),
+      &subcommand_logger,
+    ),
+    "unstash-all" => stash::unstash_all(config, &selector_from(subcommand_matches), &subcommand_logger),
+    "snapshot" => snapshot::snapshot(
+      config,
+      &selector_from(subcommand_matches),
+      Path::new(subcommand_matches.value_of("FILE").expect("argument required by clap.rs")),
+      &subcommand_logger,
+    ),
+    "restore" => snapshot::restore(
+      config,
+      &selector_from(subcommand_matches),
+      Path::new(subcommand_matches.value_of("FILE").expect("argument required by clap.rs")),
+      subcommand_matches.is_present("force"),
+      &subcommand_logger,
+    ),
+    "manifest" => match subcommand_matches.subcommand() {
+      ("write", Some(manifest_matches)) => snapshot::write_manifest(
+        config,
+        &selector_from(manifest_matches),
+        Path::new(manifest_matches.value_of("FILE").expect("argument required by clap.rs")),
+        &subcommand_logger,
+      ),
+      ("checkout", Some(manifest_matches)) => snapshot::checkout_manifest(
+        config,
+        &selector_from(manifest_matches),
+        Path::new(manifest_matches.value_of("FILE").expect("argument required by clap.rs")),
+        manifest_matches.is_present("force"),
+        &subcommand_logger,
+      ),
+      _ => Err(AppError::InternalError("Command not implemented")),
+    },
+    "backup" => backup::backup(
+      config,
+      &selector_from(subcommand_matches),
+      &config::path::expand_path(PathBuf::from(subcommand_matches.value_of("dest").expect("argument required by clap.rs"))),
+      &subcommand_logger,
+    ),
+    "restore-from-bundles" => backup::restore_from_bundles(
+      config,
+      &selector_from(subcommand_matches),
+      &config::path::expand_path(PathBuf::from(subcommand_matches.value_of("from").expect("argument required by clap.rs"))),
+      &subcommand_logger,
+    ),
+    "serve-webhook" => webhook::serve_webhook(
+      config,
+      subcommand_matches
+        .value_of("listen")
+        .expect("argument has default value")
+        .parse()
+        .expect("validated by clap.rs"),
+      &subcommand_logger,
+    ),
+    "daemon" => daemon::daemon(
+      config,
+      &selector_from(subcommand_matches),
+      daemon::parse_interval(subcommand_matches.value_of("interval").expect("argument has default value")).expect("validated by clap.rs"),
+      subcommand_matches
+        .value_of("metrics-listen")
+        .map(|address| address.parse().expect("validated by clap.rs")),
+      &subcommand_logger,
+    ),
+    "grep" => grep::grep(
+      config,
+      &selector_from(subcommand_matches),
+      subcommand_matches.value_of("PATTERN").expect("argument required by clap.rs"),
+      &grep::GrepOptions {
+        ignore_case: subcommand_matches.is_present("ignore-case"),
+        fixed_strings: subcommand_matches.is_present("fixed-strings"),
+      },
+      &subcommand_logger,
+    ),
+    "which" => which::which(
+      config,
+      &selector_from(subcommand_matches),
+      subcommand_matches.value_of("PATTERN").expect("argument required by clap.rs"),
+      subcommand_matches.is_present("json"),
+      &subcommand_logger,
+    ),
+    "du" => du::du(config, &selector_from(subcommand_matches), &subcommand_logger),
+    "status" => status::status(
+      config,
+      &selector_from(subcommand_matches),
+      subcommand_matches.is_present("json"),
+      !subcommand_matches.is_present("no-cache"),
+      subcommand_matches.is_present("check-remotes"),
+      &subcommand_logger,
+    ),
+    "fix-remotes" => remotes::fix_remotes(
+      config,
+      &selector_from(subcommand_matches),
+      subcommand_matches.is_present("dry-run"),
+      timeout_from(subcommand_matches),
+      &subcommand_logger,
+    ),
+    "diff-config" => shared::diff_config(
+      config,
+      subcommand_matches.value_of("SOURCE").expect("argument required by clap.rs"),
+      subcommand_matches.value_of("ref"),
+      subcommand_matches.is_present("apply"),
+      &subcommand_matches.values_of_lossy("apply").unwrap_or_default(),
+      &subcommand_logger,
+    ),
+    "plugins" => plugin::list(),
+    "edit" => {
+      let target = match (subcommand_matches.value_of("PROJECT_NAME"), subcommand_matches.value_of("tag")) {
+        (Some(name), _) => config::edit::EditTarget::Project(name.to_owned()),
+        (None, Some(tag)) => config::edit::EditTarget::Tag(tag.to_owned()),
+        (None, None) => config::edit::EditTarget::Settings,
+      };
+      config::edit::edit(config, &target, &subcommand_logger)
+    }
+    "fmt" => config::fmt::fmt(subcommand_matches.is_present("check"), &subcommand_logger),
+    "dedupe" => project::dedupe(
+      config,
+      subcommand_matches.is_present("yes"),
+      subcommand_matches.is_present("dry-run"),
+      &subcommand_logger,
+    ),
+    "adopt" => {
+      let dirs: Vec<PathBuf> = subcommand_matches
+        .values_of("DIR")
+        .map(|dirs| dirs.map(|dir| config::path::expand_path(PathBuf::from(dir))).collect())
+        .unwrap_or_default();
+      adopt::adopt(config, &dirs, subcommand_matches.is_present("dry-run"), &subcommand_logger)
+    }
+    "query" => subcommand_matches
+      .value_of("QUERY")
+      .expect("argument required by clap.rs")
+      .parse()
+      .and_then(|query| query::query(config, &query, subcommand_matches.is_present("json"), &subcommand_logger)),
+    name => {
+      let input = plugin::PluginInput::new(&config, frozen, &subcommand_logger);
+      match plugin::run(name, &subcommand_matches.values_of_lossy("").unwrap_or_default(), &input, &subcommand_logger) {
+        // the plugin reports its own errors, fw only passes on its exit code
+        Ok(code) => return code,
+        Err(e) => Err(e),
+      }
+    }
   }
   .and_then(|_| now.elapsed().map_err(AppError::ClockError))
   .map(|duration| format!("{}sec", duration.as_secs()));
@@ -151,13 +709,94 @@ fn _main() -> i32 {
       debug!(subcommand_logger, "Done"; "time" => time);
       0
     }
+    Err(AppError::Interrupted) => {
+      output::print_error(&AppError::Interrupted);
+      130
+    }
     Err(error) => {
-      crit!(subcommand_logger, "Error running command"; "error" => format!("{:?}", error));
+      // the JSON log is read by tools, everyone else gets a readable message instead of a log record
+      match log_format {
+        LogFormat::Json => crit!(subcommand_logger, "Error running command"; "error" => format!("{:?}", error)),
+        LogFormat::Plain => {
+          debug!(subcommand_logger, "Error running command"; "error" => format!("{:?}", error));
+          output::print_error(&error);
+        }
+      }
       1
     }
   }
 }
 
+fn modifies_config(subcommand_name: &str, subcommand_matches: &clap::ArgMatches<'_>) -> bool {
+  match subcommand_name {
+    "gen-workon" => subcommand_matches.is_present("create") || workon::looks_like_git_url(subcommand_matches.value_of("PROJECT_NAME").unwrap_or_default()),
+    "diff-config" => subcommand_matches.is_present("apply"),
+    "fmt" => !subcommand_matches.is_present("check"),
+    "adopt" | "dedupe" | "fix-remotes" => !subcommand_matches.is_present("dry-run"),
+    "import-from" | "org-import" | "gitlab-import" | "gitea-import" | "azure-import" | "codecommit-import" | "cloud-source-import" => {
+      !subcommand_matches.is_present("dry-run")
+    }
+    "import" => match subcommand_matches.subcommand_matches("github-stars") {
+      Some(stars_matches) => !stars_matches.is_present("dry-run"),
+      None => !subcommand_matches.is_present("dry-run"),
+    },
+    "config" => matches!(subcommand_matches.subcommand_name(), Some("encrypt") | Some("decrypt")),
+    "setup" | "migrate-paths" | "add" | "edit" | "add-remote" | "remove-remote" | "remove" | "move-to-workspace" | "archive" | "unarchive"
+    | "serve-webhook" | "retag-from-forge" | "update" => true,
+    "tag" => matches!(
+      subcommand_matches.subcommand_name(),
+      Some("tag-project") | Some("untag-project") | Some("autotag") | Some("rm") | Some("add")
+    ),
+    _ => false,
+  }
+}
+
+fn selector_from(matches: &clap::ArgMatches<'_>) -> ProjectSelector {
+  let selector = ProjectSelector::with_tags(matches.values_of_lossy("tag").unwrap_or_default().into_iter().collect());
+  if matches.is_present("archived") {
+    selector.including_archived()
+  } else {
+    selector
+  }
+}
+
+fn lock_mode_from(matches: &clap::ArgMatches<'_>) -> LockMode {
+  if matches.is_present("wait") {
+    LockMode::Wait
+  } else if matches.is_present("skip-locked") {
+    LockMode::Skip
+  } else {
+    LockMode::Fail
+  }
+}
+
+fn on_conflict_from(matches: &clap::ArgMatches<'_>, default: project::OnConflict) -> project::OnConflict {
+  project::OnConflict::from_flags(
+    matches.is_present("force"),
+    matches.is_present("merge"),
+    matches.is_present("interactive"),
+    default,
+  )
+}
+
+fn import_options_from(matches: &clap::ArgMatches<'_>) -> setup::ImportOptions {
+  setup::ImportOptions {
+    on_conflict: on_conflict_from(matches, project::OnConflict::Skip),
+    naming: matches
+      .value_of("naming")
+      .map(|naming| naming.parse().expect("argument values restricted by clap.rs")),
+    include: matches.value_of("include").map(|r| Regex::new(r).expect("validated by clap.rs")),
+    exclude: matches.value_of("exclude").map(|r| Regex::new(r).expect("validated by clap.rs")),
+    dry_run: matches.is_present("dry-run"),
+  }
+}
+
+fn timeout_from(matches: &clap::ArgMatches<'_>) -> Option<Duration> {
+  matches
+    .value_of("timeout")
+    .map(|t| Duration::from_secs(t.parse().expect("validated by clap.rs")))
+}
+
 fn execute_tag_subcommand(
   maybe_config: Result<config::Config, AppError>,
   tag_command_name: &str,
@@ -181,7 +820,7 @@ fn execute_tag_subcommand(
     }
     "inspect" => {
       let tag_name: String = tag_matches.value_of("tag-name").map(str::to_string).expect("argument enforced by clap.rs");
-      tag::inspect_tag(maybe_config, &tag_name)
+      tag::inspect_tag(maybe_config, &tag_name, logger)
     }
     "rm" => {
       let tag_name: String = tag_matches.value_of("tag-name").map(str::to_string).expect("argument enforced by clap.rs");
@@ -198,11 +837,12 @@ fn execute_tag_subcommand(
         .map(|p| p.expect("invalid tag priority value, must be an u8"));
       tag::create_tag(maybe_config, tag_name, after_workon, after_clone, priority, tag_workspace, logger)
     }
+    "autotag" if tag_matches.is_present("apply") => tag::apply_autotag_rules(maybe_config, logger),
     "autotag" => tag::autotag(
       maybe_config,
       tag_matches.value_of("CMD").expect("argument required by clap.rs"),
       &tag_matches.value_of("tag-name").map(str::to_string).expect("argument enforced by clap.rs"),
-      &logger,
+      logger,
       &tag_matches.value_of("parallel").map(ToOwned::to_owned),
     ),
     _ => Result::Err(AppError::InternalError("Command not implemented")),
@@ -210,16 +850,3 @@ fn execute_tag_subcommand(
 }
 
 mod app;
-mod config;
-mod errors;
-mod git;
-mod project;
-mod projectile;
-mod setup;
-mod shell;
-mod spawn;
-mod sync;
-mod tag;
-mod util;
-mod workon;
-mod ws;
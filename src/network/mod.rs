@@ -0,0 +1,283 @@
+use crate::config::path::{expand_path, fw_path};
+use crate::config::settings::Settings;
+use crate::errors::AppError;
+use slog::{warn, Logger};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static CA_BUNDLE: OnceLock<PathBuf> = OnceLock::new();
+static CA_FILE: OnceLock<PathBuf> = OnceLock::new();
+static BANDWIDTH_LIMIT: OnceLock<u64> = OnceLock::new();
+
+// Where `SSL_CERT_FILE` pointed before fw replaced it, so an fw started by a hook doesn't add the `ca_bundle` twice.
+const SYSTEM_CERT_FILE: &str = "FW_SYSTEM_SSL_CERT_FILE";
+
+fn append_pem(pem: &mut Vec<u8>, extra: &[u8]) {
+  if !pem.is_empty() && !pem.ends_with(b"\n") {
+    pem.push(b'\n');
+  }
+  pem.extend_from_slice(extra);
+}
+
+// OpenSSL reads the certificate authorities from one file, so the system ones and the `ca_bundle` go into a file of
+// their own in the state directory.
+fn combined_ca_bundle(ca_bundle: &Path) -> Result<PathBuf, AppError> {
+  let system = env::var_os(SYSTEM_CERT_FILE).map(PathBuf::from).or_else(|| openssl_probe::probe().cert_file);
+  let mut pem = match &system {
+    Some(system) => fs::read(system).map_err(|e| AppError::UserError(format!("Could not read the system certificates {}: {}", system.to_string_lossy(), e)))?,
+    None => Vec::new(),
+  };
+  let extra = fs::read(ca_bundle).map_err(|e| AppError::UserError(format!("Could not read ca_bundle {}: {}", ca_bundle.to_string_lossy(), e)))?;
+  append_pem(&mut pem, &extra);
+  let dir = fw_path()?.ensure_state_dir_exists("tls")?;
+  let path = dir.join("ca-bundle.pem");
+  let tmp_path = dir.join(format!("ca-bundle.pem.{}", std::process::id()));
+  fs::write(&tmp_path, &pem).and_then(|_| fs::rename(&tmp_path, &path))?;
+  if let Some(system) = system {
+    env::set_var(SYSTEM_CERT_FILE, system);
+  }
+  Ok(path)
+}
+
+/// Exports the proxy and CA bundle settings as `HTTP_PROXY`, `HTTPS_PROXY`, `NO_PROXY` and `SSL_CERT_FILE`,
+/// which libgit2, OpenSSL and the HTTP clients of the importers all read. Settings win over the environment, the
+/// `ca_bundle` is trusted together with the system certificates. Keeps the `bandwidth_limit` for the transfers of
+/// libgit2. Has to run before the first network access.
+pub fn apply(settings: &Settings, logger: &Logger) {
+  let exports = [
+    ("HTTP_PROXY", settings.http_proxy.clone()),
+    ("HTTPS_PROXY", settings.https_proxy.clone()),
+    ("NO_PROXY", settings.no_proxy.as_ref().map(|hosts| hosts.join(","))),
+  ];
+  for (name, value) in exports.iter() {
+    if let Some(value) = value {
+      env::set_var(name, value);
+      env::set_var(name.to_lowercase(), value);
+    }
+  }
+  if let Some(ca_bundle) = &settings.ca_bundle {
+    let path = expand_path(PathBuf::from(ca_bundle));
+    match combined_ca_bundle(&path) {
+      Ok(combined) => {
+        env::set_var("SSL_CERT_FILE", &combined);
+        let _ = CA_FILE.set(combined);
+      }
+      Err(e) => warn!(logger, "The ca_bundle is only used for the forge APIs"; "error" => format!("{}", e)),
+    }
+    let _ = CA_BUNDLE.set(path);
+  }
+  if let Some(kib) = settings.bandwidth_limit.filter(|kib| *kib > 0) {
+    let _ = BANDWIDTH_LIMIT.set(kib * 1024);
+  }
+}
+
+/// The system certificates together with the `ca_bundle` of the settings, if there is one.
+pub fn ca_file() -> Option<&'static Path> {
+  CA_FILE.get().map(PathBuf::as_path)
+}
+
+/// The `bandwidth_limit` of the settings in bytes per second.
+pub fn bandwidth_limit() -> Option<u64> {
+  BANDWIDTH_LIMIT.get().copied()
+}
+
+/// How long a transfer that received `received` bytes after `elapsed` has to pause to stay below `limit` bytes per
+/// second.
+pub fn throttle_delay(received: usize, elapsed: Duration, limit: u64) -> Option<Duration> {
+  let due = Duration::from_secs_f64(received as f64 / limit as f64);
+  due.checked_sub(elapsed).filter(|delay| !delay.is_zero())
+}
+
+fn no_proxy_matches(no_proxy: &str, host: &str) -> bool {
+  let host = host.trim_start_matches('[').trim_end_matches(']').to_lowercase();
+  no_proxy.split(',').map(str::trim).filter(|entry| !entry.is_empty()).any(|entry| {
+    let entry = entry.to_lowercase();
+    let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+    entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+  })
+}
+
+/// `NO_PROXY` lists `host`: exactly, as a subdomain of an entry like `corp.com` or `.corp.com`, or with `*`.
+pub fn bypasses_proxy(host: &str) -> bool {
+  env::var("NO_PROXY")
+    .or_else(|_| env::var("no_proxy"))
+    .map(|no_proxy| no_proxy_matches(&no_proxy, host))
+    .unwrap_or(false)
+}
+
+/// `fw --offline` or `FW_OFFLINE=1`: nothing is cloned or fetched and the forge APIs are not contacted.
+pub fn offline() -> bool {
+  env::var("FW_OFFLINE").map(|value| !value.is_empty() && value != "0").unwrap_or(false)
+}
+
+/// Host and port `url` connects to: `ssh://`, `git://`, `http(s)://` and scp-like `git@host:path` URLs. `None` for local
+/// paths and `file://` URLs, which need no network.
+pub fn remote_host(url: &str) -> Option<(String, u16)> {
+  let (scheme, rest) = match url.split_once("://") {
+    Some((scheme, rest)) => (scheme.to_lowercase(), rest),
+    None if url.starts_with('/') || url.starts_with('.') || url.starts_with('~') => return None,
+    None => ("ssh".to_owned(), url.split_once(':')?.0),
+  };
+  let default_port = match scheme.as_str() {
+    "ssh" | "git+ssh" | "ssh+git" => 22,
+    "git" => 9418,
+    "http" => 80,
+    "https" => 443,
+    _ => return None,
+  };
+  let authority = rest.split('/').next()?.rsplit('@').next()?;
+  let (host, port) = match authority.strip_prefix('[') {
+    // `[::1]:8080`
+    Some(bracketed) => {
+      let (host, port) = bracketed.split_once(']')?;
+      (host, port.strip_prefix(':'))
+    }
+    None => match authority.split_once(':') {
+      Some((host, port)) => (host, Some(port)),
+      None => (authority, None),
+    },
+  };
+  let port = match port {
+    Some(port) => port.parse().ok()?,
+    None => default_port,
+  };
+  if host.is_empty() {
+    None
+  } else {
+    Some((host.to_owned(), port))
+  }
+}
+
+// HTTP goes through the proxy, so that is what has to answer.
+fn endpoint(url: &str) -> Option<(String, u16)> {
+  let (host, port) = remote_host(url)?;
+  let proxy = match url.split_once("://").map(|(scheme, _)| scheme.to_lowercase()) {
+    Some(scheme) if scheme == "https" => env::var("HTTPS_PROXY").or_else(|_| env::var("https_proxy")).ok(),
+    Some(scheme) if scheme == "http" => env::var("HTTP_PROXY").or_else(|_| env::var("http_proxy")).ok(),
+    _ => None,
+  };
+  match proxy.filter(|proxy| !proxy.is_empty() && !bypasses_proxy(&host)) {
+    Some(proxy) if proxy.contains("://") => remote_host(&proxy),
+    Some(proxy) => remote_host(&format!("http://{}", proxy)),
+    None => Some((host, port)),
+  }
+}
+
+fn reachable(host: &str, port: u16, timeout: Duration) -> bool {
+  match (host, port).to_socket_addrs() {
+    Ok(addresses) => addresses.into_iter().any(|address| TcpStream::connect_timeout(&address, timeout).is_ok()),
+    Err(_) => false,
+  }
+}
+
+/// Whether none of the hosts of `urls` accepts a connection within `timeout`, tried in parallel. A machine without
+/// network fails fast here instead of waiting for the timeouts of git. With only local URLs there is nothing to detect.
+pub fn detect_offline<'a>(urls: impl IntoIterator<Item = &'a str>, timeout: Duration) -> bool {
+  let endpoints: BTreeSet<(String, u16)> = urls.into_iter().filter_map(endpoint).collect();
+  if endpoints.is_empty() {
+    return false;
+  }
+  // name lookups take no timeout, so the checks are not waited for beyond it and one that hangs is left behind
+  let deadline = Instant::now() + timeout;
+  let (sender, receiver) = mpsc::channel();
+  for (host, port) in endpoints {
+    let sender = sender.clone();
+    thread::spawn(move || sender.send(reachable(&host, port, timeout)));
+  }
+  drop(sender);
+  loop {
+    match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+      Ok(true) => return false,
+      Ok(false) => continue,
+      // every check failed or the time is up
+      Err(_) => return true,
+    }
+  }
+}
+
+/// A builder for blocking HTTP clients to the forge APIs. They use the proxy from the environment and trust the `ca_bundle`
+/// of the settings in addition to the system certificates.
+pub fn http_client_builder() -> Result<reqwest::blocking::ClientBuilder, AppError> {
+  if offline() {
+    return Err(AppError::UserError(
+      "fw is offline (--offline or FW_OFFLINE), not contacting the forge API".to_owned(),
+    ));
+  }
+  let mut builder = reqwest::blocking::Client::builder();
+  if let Some(path) = CA_BUNDLE.get() {
+    let pem = std::fs::read(path).map_err(|e| AppError::UserError(format!("Could not read ca_bundle {}: {}", path.to_string_lossy(), e)))?;
+    let certificate = reqwest::Certificate::from_pem(&pem).map_err(|e| AppError::UserError(format!("Invalid ca_bundle {}: {}", path.to_string_lossy(), e)))?;
+    builder = builder.add_root_certificate(certificate);
+  }
+  Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+  use std::net::TcpListener;
+
+  #[test]
+  fn test_append_pem() {
+    let mut pem = b"system".to_vec();
+    append_pem(&mut pem, b"corp\n");
+    assert_that(&pem).is_equal_to(b"system\ncorp\n".to_vec());
+    let mut pem = b"system\n".to_vec();
+    append_pem(&mut pem, b"corp\n");
+    assert_that(&pem).is_equal_to(b"system\ncorp\n".to_vec());
+    let mut pem = Vec::new();
+    append_pem(&mut pem, b"corp\n");
+    assert_that(&pem).is_equal_to(b"corp\n".to_vec());
+  }
+
+  #[test]
+  fn test_detect_offline() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open = format!("ssh://git@127.0.0.1:{}/fw.git", listener.local_addr().unwrap().port());
+    let closed_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let closed = format!("ssh://git@127.0.0.1:{}/fw.git", closed_port);
+    let timeout = Duration::from_secs(2);
+    assert_that(&detect_offline(vec![closed.as_str(), open.as_str()], timeout)).is_false();
+    assert_that(&detect_offline(vec![closed.as_str()], timeout)).is_true();
+    assert_that(&detect_offline(vec!["/srv/git/fw.git"], timeout)).is_false();
+  }
+
+  #[test]
+  fn test_throttle_delay() {
+    assert_that(&throttle_delay(4096, Duration::from_secs(1), 1024)).is_equal_to(Some(Duration::from_secs(3)));
+    assert_that(&throttle_delay(1024, Duration::from_secs(2), 1024)).is_none();
+    assert_that(&throttle_delay(0, Duration::ZERO, 1024)).is_none();
+  }
+
+  #[test]
+  fn test_no_proxy_matches() {
+    let no_proxy = "localhost, .corp.com,git.internal,*.svc";
+    assert_that(&no_proxy_matches(no_proxy, "localhost")).is_true();
+    assert_that(&no_proxy_matches(no_proxy, "gitlab.corp.com")).is_true();
+    assert_that(&no_proxy_matches(no_proxy, "corp.com")).is_true();
+    assert_that(&no_proxy_matches(no_proxy, "GIT.internal")).is_true();
+    assert_that(&no_proxy_matches(no_proxy, "api.svc")).is_true();
+    assert_that(&no_proxy_matches(no_proxy, "github.com")).is_false();
+    assert_that(&no_proxy_matches(no_proxy, "notcorp.com")).is_false();
+    assert_that(&no_proxy_matches("*", "github.com")).is_true();
+  }
+
+  #[test]
+  fn test_remote_host() {
+    assert_that(&remote_host("git@github.com:brocode/fw.git")).is_equal_to(Some(("github.com".to_owned(), 22)));
+    assert_that(&remote_host("ssh://git@gitlab.corp.com:2222/acme/api.git")).is_equal_to(Some(("gitlab.corp.com".to_owned(), 2222)));
+    assert_that(&remote_host("https://github.com/brocode/fw")).is_equal_to(Some(("github.com".to_owned(), 443)));
+    assert_that(&remote_host("http://[::1]:8080/fw.git")).is_equal_to(Some(("::1".to_owned(), 8080)));
+    assert_that(&remote_host("git://git.kernel.org/pub/scm/git/git.git")).is_equal_to(Some(("git.kernel.org".to_owned(), 9418)));
+    assert_that(&remote_host("/srv/git/fw.git")).is_none();
+    assert_that(&remote_host("file:///srv/git/fw.git")).is_none();
+    assert_that(&remote_host("../fw.git")).is_none();
+  }
+}
@@ -0,0 +1,116 @@
+use crate::config::settings::Settings;
+use crate::errors::AppError;
+use crate::signal::BatchSummary;
+use crate::trust;
+use slog::{debug, warn, Logger};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// A finished batch like `fw sync` or `fw foreach`.
+pub struct Notification {
+  pub title: String,
+  pub body: String,
+  pub success: bool,
+}
+
+impl Notification {
+  pub fn for_batch(operation: &str, summary: &BatchSummary, elapsed: Duration) -> Notification {
+    let success = summary.succeeded();
+    Notification {
+      title: format!("fw {} {}", operation, if success { "finished" } else { "failed" }),
+      body: format!("{} in {}s", summary.message(), elapsed.as_secs()),
+      success,
+    }
+  }
+}
+
+#[cfg(target_os = "macos")]
+fn desktop_command(notification: &Notification) -> Command {
+  let script = format!("display notification {:?} with title {:?}", notification.body, notification.title);
+  let mut command = Command::new("osascript");
+  command.arg("-e").arg(script);
+  command
+}
+
+#[cfg(not(target_os = "macos"))]
+fn desktop_command(notification: &Notification) -> Command {
+  let mut command = Command::new("notify-send");
+  command
+    .arg("--urgency")
+    .arg(if notification.success { "normal" } else { "critical" })
+    .arg(&notification.title)
+    .arg(&notification.body);
+  command
+}
+
+fn run(mut command: Command) -> Result<(), AppError> {
+  let program = command.get_program().to_string_lossy().to_string();
+  let status = command
+    .stdin(Stdio::null())
+    .stdout(Stdio::null())
+    .status()
+    .map_err(|e| AppError::RuntimeError(format!("Could not run {}: {}", program, e)))?;
+  if status.success() {
+    Ok(())
+  } else {
+    Err(AppError::RuntimeError(format!("exited with {}", status)))
+  }
+}
+
+fn user_command(settings: &Settings, notify_command: &str, notification: &Notification) -> Command {
+  let shell = settings.get_shell_or_default();
+  let mut command = Command::new(&shell[0]);
+  command
+    .args(&shell[1..])
+    .arg(notify_command)
+    .env("FW_NOTIFY_TITLE", &notification.title)
+    .env("FW_NOTIFY_BODY", &notification.body)
+    .env("FW_NOTIFY_STATUS", if notification.success { "success" } else { "failure" });
+  command
+}
+
+/// Sends a desktop notification if `requested` or `notify = true`, and runs `notify_command` if it is set.
+/// A notification that cannot be delivered is only logged, it never fails the batch.
+pub fn batch_finished(settings: &Settings, requested: bool, notification: &Notification, logger: &Logger) {
+  if requested || settings.notify.unwrap_or_default() {
+    match run(desktop_command(notification)) {
+      Ok(()) => debug!(logger, "Desktop notification sent"),
+      Err(e) => warn!(logger, "Could not send desktop notification"; "error" => format!("{}", e)),
+    }
+  }
+  if let Some(notify_command) = settings.notify_command.as_ref().filter(|_| trust::allows_notify_command(settings, logger)) {
+    match run(user_command(settings, notify_command, notification)) {
+      Ok(()) => debug!(logger, "notify_command done"),
+      Err(e) => warn!(logger, "notify_command failed"; "command" => notify_command, "error" => format!("{}", e)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_batch_notification() {
+    let mut summary = BatchSummary::default();
+    summary.record("a", &Ok(()));
+    summary.record("b", &Err(AppError::UserError("broken".to_owned())));
+    summary.record("c", &Ok(()));
+    let notification = Notification::for_batch("sync", &summary, Duration::from_secs(42));
+    assert_that(&notification.success).is_false();
+    assert_that(&notification.title).is_equal_to("fw sync failed".to_owned());
+    assert_that(&notification.body).is_equal_to("2 completed, 1 failed (b) in 42s".to_owned());
+  }
+
+  #[test]
+  fn test_batch_notification_with_skipped_projects() {
+    let mut summary = BatchSummary::default();
+    summary.record("a", &Ok(()));
+    summary.record_skipped("b", "offline");
+    summary.record_skipped("c", "offline");
+    let notification = Notification::for_batch("sync", &summary, Duration::from_secs(3));
+    assert_that(&notification.success).is_true();
+    assert_that(&notification.body).is_equal_to("1 completed, 2 skipped: offline (b, c) in 3s".to_owned());
+  }
+}
@@ -0,0 +1,275 @@
+use crate::errors::AppError;
+use ansi_term::{Colour, Style};
+use std::env;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// When to colour output, as given by `--color`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorChoice {
+  /// Colour terminals unless `NO_COLOR` is set.
+  #[default]
+  Auto,
+  Always,
+  Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(Self::Auto),
+      "always" => Ok(Self::Always),
+      "never" => Ok(Self::Never),
+      _ => Err(AppError::UserError(format!("Unknown color choice {}, expected auto, always or never", s))),
+    }
+  }
+}
+
+static COLOR_CHOICE: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the colour choice for everything fw prints afterwards. Without a call it is [`ColorChoice::Auto`].
+pub fn init(choice: ColorChoice) {
+  COLOR_CHOICE.store(choice as u8, Ordering::SeqCst);
+}
+
+pub fn color_choice() -> ColorChoice {
+  match COLOR_CHOICE.load(Ordering::SeqCst) {
+    1 => ColorChoice::Always,
+    2 => ColorChoice::Never,
+    _ => ColorChoice::Auto,
+  }
+}
+
+// https://no-color.org: only a set and non-empty NO_COLOR counts
+fn no_color_requested() -> bool {
+  env::var_os("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false)
+}
+
+/// `fw --non-interactive` or `FW_NON_INTERACTIVE=1`, e.g. in CI: fw never asks, fails where it would have to guess and
+/// prints for machines instead of people.
+pub fn non_interactive() -> bool {
+  #[cfg(test)]
+  {
+    if let Some(forced) = FORCED_NON_INTERACTIVE.with(std::cell::Cell::get) {
+      return forced;
+    }
+  }
+  env::var("FW_NON_INTERACTIVE").map(|value| !value.is_empty() && value != "0").unwrap_or(false)
+}
+
+#[cfg(test)]
+thread_local! {
+  static FORCED_NON_INTERACTIVE: std::cell::Cell<Option<bool>> = const { std::cell::Cell::new(None) };
+}
+
+/// Runs `f` with `non_interactive` returning `on` on this thread, without touching the environment of other tests.
+#[cfg(test)]
+pub fn with_non_interactive<T>(on: bool, f: impl FnOnce() -> T) -> T {
+  let previous = FORCED_NON_INTERACTIVE.with(|forced| forced.replace(Some(on)));
+  let result = f();
+  FORCED_NON_INTERACTIVE.with(|forced| forced.set(previous));
+  result
+}
+
+/// Whether there is someone to ask on the terminal.
+pub fn can_prompt() -> bool {
+  !non_interactive() && atty::is(atty::Stream::Stdin)
+}
+
+/// Whether output to `stream` gets colours.
+pub fn colors_enabled(stream: atty::Stream) -> bool {
+  match color_choice() {
+    ColorChoice::Always => true,
+    ColorChoice::Never => false,
+    ColorChoice::Auto => !no_color_requested() && atty::is(stream),
+  }
+}
+
+fn paint(stream: atty::Stream, style: Style, text: &str) -> String {
+  if colors_enabled(stream) {
+    style.paint(text).to_string()
+  } else {
+    text.to_owned()
+  }
+}
+
+/// Styles for the meaning of a piece of output, so that commands colour the same things the same way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tone {
+  Plain,
+  Heading,
+  Good,
+  Warning,
+  Bad,
+  Muted,
+}
+
+impl Tone {
+  fn style(self) -> Style {
+    match self {
+      Tone::Plain => Style::new(),
+      Tone::Heading => Style::new().underline().bold(),
+      Tone::Good => Colour::Green.normal(),
+      Tone::Warning => Colour::Yellow.normal(),
+      Tone::Bad => Colour::Red.bold(),
+      Tone::Muted => Style::new().dimmed(),
+    }
+  }
+
+  /// `text` in this tone, for stdout.
+  pub fn paint(self, text: &str) -> String {
+    paint(atty::Stream::Stdout, self.style(), text)
+  }
+
+  /// `text` in this tone, for stderr.
+  pub fn paint_err(self, text: &str) -> String {
+    paint(atty::Stream::Stderr, self.style(), text)
+  }
+}
+
+/// A cell of a [`Table`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+  text: String,
+  tone: Tone,
+}
+
+impl Cell {
+  pub fn new<S: Into<String>>(text: S, tone: Tone) -> Cell {
+    Cell { text: text.into(), tone }
+  }
+
+  pub fn plain<S: Into<String>>(text: S) -> Cell {
+    Cell::new(text, Tone::Plain)
+  }
+}
+
+/// Rows printed with aligned columns. Widths are measured before colouring so escape codes do not shift columns.
+#[derive(Debug, Default)]
+pub struct Table {
+  rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+  pub fn new() -> Table {
+    Table::default()
+  }
+
+  pub fn add_row(&mut self, row: Vec<Cell>) {
+    self.rows.push(row);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.rows.is_empty()
+  }
+
+  fn widths(&self) -> Vec<usize> {
+    let mut widths: Vec<usize> = Vec::new();
+    for row in &self.rows {
+      for (index, cell) in row.iter().enumerate() {
+        let width = cell.text.chars().count();
+        match widths.get_mut(index) {
+          Some(w) => *w = (*w).max(width),
+          None => widths.push(width),
+        }
+      }
+    }
+    widths
+  }
+
+  fn render(&self, colored: bool) -> Vec<String> {
+    let widths = self.widths();
+    self
+      .rows
+      .iter()
+      .map(|row| {
+        let cells: Vec<String> = row
+          .iter()
+          .enumerate()
+          .map(|(index, cell)| {
+            let padding = " ".repeat(widths[index] - cell.text.chars().count());
+            let text = if colored {
+              cell.tone.style().paint(&cell.text).to_string()
+            } else {
+              cell.text.clone()
+            };
+            format!("{}{}", text, padding)
+          })
+          .collect();
+        // rows can be shorter than others or end in empty cells, neither should leave trailing whitespace
+        cells.join("  ").trim_end().to_owned()
+      })
+      .collect()
+  }
+
+  pub fn print(&self) {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for line in self.render(colors_enabled(atty::Stream::Stdout)) {
+      // stdout going away (e.g. `| head`) is not worth a panic
+      if writeln!(out, "{}", line).is_err() {
+        break;
+      }
+    }
+  }
+}
+
+/// Prints `error` to stderr the way a user should see it: a coloured severity followed by the message.
+pub fn print_error(error: &AppError) {
+  match error {
+    AppError::Interrupted => eprintln!("{}", Tone::Warning.paint_err("interrupted")),
+    AppError::UserError(message) => eprintln!("{} {}", Tone::Bad.paint_err("error:"), message),
+    AppError::InternalError(message) => eprintln!("{} {} (this is a bug in fw)", Tone::Bad.paint_err("internal error:"), message),
+    other => eprintln!("{} {}", Tone::Bad.paint_err("error:"), other),
+  }
+}
+
+/// Prints a warning that is meant for the user rather than for the log.
+pub fn print_warning(message: &str) {
+  eprintln!("{} {}", Tone::Warning.paint_err("warning:"), message);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_table_alignment() {
+    let mut table = Table::new();
+    table.add_row(vec![Cell::plain("fw"), Cell::new("master", Tone::Good), Cell::plain("+1")]);
+    table.add_row(vec![Cell::plain("workspace"), Cell::plain("main"), Cell::plain("+12")]);
+    assert_that(&table.render(false)).is_equal_to(vec!["fw         master  +1".to_owned(), "workspace  main    +12".to_owned()]);
+  }
+
+  #[test]
+  fn test_colored_table_keeps_alignment() {
+    let mut table = Table::new();
+    table.add_row(vec![Cell::new("fw", Tone::Bad), Cell::plain("x")]);
+    table.add_row(vec![Cell::plain("longer"), Cell::plain("y")]);
+    let lines = table.render(true);
+    assert_that(&lines[0]).is_equal_to(format!("{}      x", Colour::Red.bold().paint("fw")));
+    assert_that(&lines[1]).is_equal_to("longer  y".to_owned());
+  }
+
+  #[test]
+  fn test_color_choice_from_str() {
+    assert_that(&"never".parse::<ColorChoice>().unwrap()).is_equal_to(ColorChoice::Never);
+    assert_that(&"sometimes".parse::<ColorChoice>().is_err()).is_true();
+  }
+
+  #[test]
+  fn test_non_interactive_never_prompts() {
+    with_non_interactive(true, || {
+      assert_that(&non_interactive()).is_true();
+      assert_that(&can_prompt()).is_false();
+    });
+    // the tests don't run on a terminal either
+    with_non_interactive(false, || {
+      assert_that(&non_interactive()).is_false();
+      assert_that(&can_prompt()).is_equal_to(atty::is(atty::Stream::Stdin));
+    });
+  }
+}
@@ -0,0 +1,379 @@
+use crate::config::{selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git::is_dirty;
+use crate::output::{Cell, Table, Tone};
+use git2::{ApplyLocation, Delta, Diff, Oid, Patch, Repository};
+use globset::{Glob, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use slog::{debug, o, Logger};
+use std::io::Read;
+use std::path::Path;
+
+/// A sed-like substitution, `s/PATTERN/REPLACEMENT/FLAGS`, applied to every line.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+  regex: Regex,
+  /// In the syntax of the regex crate, `$1` instead of `\1`.
+  replacement: String,
+  global: bool,
+}
+
+/// What `fw apply-patch` changes in every project.
+#[derive(Debug, Clone)]
+pub enum Change {
+  /// A unified diff as printed by `git diff`, relative to the root of the projects.
+  Patch(Vec<u8>),
+  /// A substitution in the tracked files, in those matching `files` if given.
+  Substitute { substitution: Substitution, files: Option<GlobMatcher> },
+}
+
+/// How `fw apply-patch` commits the change in every project.
+#[derive(Debug, Clone)]
+pub struct PatchOptions {
+  pub message: String,
+  pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+  Compliant,
+  /// The number of changed files and the commit, `None` for a dry run.
+  Changed(usize, Option<Oid>),
+}
+
+// Splits `s/a\/b/c/g` at the unescaped delimiters, an escaped delimiter becomes the delimiter itself.
+fn split_substitution(expression: &str) -> Option<(String, String, String)> {
+  let mut chars = expression.strip_prefix('s')?.chars();
+  let delimiter = chars.next().filter(|d| !d.is_alphanumeric() && *d != '\\' && *d != '\n')?;
+  let mut parts = vec![String::new()];
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => match chars.next() {
+        Some(escaped) if escaped == delimiter => parts.last_mut()?.push(escaped),
+        Some(escaped) => {
+          parts.last_mut()?.push('\\');
+          parts.last_mut()?.push(escaped);
+        }
+        None => return None,
+      },
+      c if c == delimiter && parts.len() < 3 => parts.push(String::new()),
+      c => parts.last_mut()?.push(c),
+    }
+  }
+  match parts.as_slice() {
+    [pattern, replacement, flags] => Some((pattern.clone(), replacement.clone(), flags.clone())),
+    _ => None,
+  }
+}
+
+// sed's `\1` and `&` in the syntax of the regex crate.
+fn translate_replacement(replacement: &str) -> String {
+  let mut translated = String::new();
+  let mut chars = replacement.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => match chars.next() {
+        Some(digit @ '0'..='9') => translated.push_str(&format!("${{{}}}", digit)),
+        Some('n') => translated.push('\n'),
+        Some('t') => translated.push('\t'),
+        Some(other) => translated.push(other),
+        None => translated.push('\\'),
+      },
+      '&' => translated.push_str("${0}"),
+      '$' => translated.push_str("$$"),
+      c => translated.push(c),
+    }
+  }
+  translated
+}
+
+/// Parses a sed-like substitution, `s/PATTERN/REPLACEMENT/` with the flags `g` (every match in a line) and `i` (ignore
+/// case). The pattern is a regular expression, `\1` and `&` in the replacement refer to its groups.
+pub fn parse_substitution(expression: &str) -> Result<Substitution, String> {
+  let (pattern, replacement, flags) =
+    split_substitution(expression).ok_or_else(|| format!("Expected a substitution like s/old/new/g. Was '{}'.", expression))?;
+  if let Some(unknown) = flags.chars().find(|flag| *flag != 'g' && *flag != 'i') {
+    return Err(format!("Unknown flag '{}' in '{}', only g and i are supported.", unknown, expression));
+  }
+  let regex = RegexBuilder::new(&pattern)
+    .case_insensitive(flags.contains('i'))
+    .build()
+    .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))?;
+  Ok(Substitution {
+    regex,
+    replacement: translate_replacement(&replacement),
+    global: flags.contains('g'),
+  })
+}
+
+impl Change {
+  /// The patch in `file`, `-` reads it from stdin.
+  pub fn patch_from(file: &str) -> Result<Change, AppError> {
+    let mut patch = Vec::new();
+    let read = if file == "-" {
+      std::io::stdin().read_to_end(&mut patch).map(|_| ())
+    } else {
+      std::fs::read(file).map(|content| patch = content)
+    };
+    read.map_err(|e| AppError::UserError(format!("Could not read patch {}: {}", file, e)))?;
+    Ok(Change::Patch(patch))
+  }
+
+  /// The substitution `expression` in the tracked files matching the glob `files`, or in all of them.
+  pub fn substitute(expression: &str, files: Option<&str>) -> Result<Change, AppError> {
+    let files = match files {
+      Some(pattern) => Some(
+        Glob::new(pattern)
+          .map_err(|e| AppError::UserError(format!("Invalid pattern: {}", e)))?
+          .compile_matcher(),
+      ),
+      None => None,
+    };
+    Ok(Change::Substitute {
+      substitution: parse_substitution(expression).map_err(AppError::UserError)?,
+      files,
+    })
+  }
+}
+
+impl Substitution {
+  fn apply(&self, text: &str) -> String {
+    text
+      .split_inclusive('\n')
+      .map(|line| {
+        let (content, newline) = match line.strip_suffix('\n') {
+          Some(content) => (content, "\n"),
+          None => (line, ""),
+        };
+        let replaced = if self.global {
+          self.regex.replace_all(content, self.replacement.as_str())
+        } else {
+          self.regex.replace(content, self.replacement.as_str())
+        };
+        format!("{}{}", replaced, newline)
+      })
+      .collect()
+  }
+}
+
+// A patch that does not apply counts as applied if the file of every deletion is gone and the new side of every hunk is
+// in its file.
+fn already_applied(root: &Path, diff: &Diff<'_>) -> Result<bool, AppError> {
+  for (index, delta) in diff.deltas().enumerate() {
+    let path = match delta.new_file().path().or_else(|| delta.old_file().path()) {
+      Some(path) => root.join(path),
+      None => return Ok(false),
+    };
+    if delta.status() == Delta::Deleted {
+      if path.exists() {
+        return Ok(false);
+      }
+      continue;
+    }
+    let content = match std::fs::read(&path) {
+      Ok(content) => content,
+      Err(_) => return Ok(false),
+    };
+    let patch = match Patch::from_diff(diff, index)? {
+      Some(patch) => patch,
+      None => continue,
+    };
+    for hunk in 0..patch.num_hunks() {
+      let mut new_side = Vec::new();
+      for line in 0..patch.num_lines_in_hunk(hunk)? {
+        let line = patch.line_in_hunk(hunk, line)?;
+        if line.origin() == ' ' || line.origin() == '+' {
+          new_side.extend_from_slice(line.content());
+        }
+      }
+      if !new_side.is_empty() && !content.windows(new_side.len()).any(|window| window == new_side.as_slice()) {
+        return Ok(false);
+      }
+    }
+  }
+  Ok(true)
+}
+
+fn apply_diff(repo: &Repository, root: &Path, patch: &[u8], dry_run: bool) -> Result<Outcome, AppError> {
+  let diff = Diff::from_buffer(patch).map_err(|e| AppError::UserError(format!("Invalid patch: {}", e.message())))?;
+  let mut check = git2::ApplyOptions::new();
+  check.check(true);
+  if repo.apply(&diff, ApplyLocation::Both, Some(&mut check)).is_err() {
+    return if already_applied(root, &diff)? {
+      Ok(Outcome::Compliant)
+    } else {
+      Err(AppError::UserError("patch does not apply".to_owned()))
+    };
+  }
+  if !dry_run {
+    repo.apply(&diff, ApplyLocation::Both, None)?;
+  }
+  Ok(Outcome::Changed(diff.deltas().len(), None))
+}
+
+// Only tracked files are changed, binary files are left alone.
+fn substitute(repo: &Repository, root: &Path, substitution: &Substitution, files: Option<&GlobMatcher>, dry_run: bool) -> Result<Outcome, AppError> {
+  let mut index = repo.index()?;
+  let tracked: Vec<String> = index.iter().map(|entry| String::from_utf8_lossy(&entry.path).to_string()).collect();
+  let mut changed = 0;
+  for relative in tracked {
+    if !files.map(|glob| glob.is_match(&relative)).unwrap_or(true) {
+      continue;
+    }
+    let path = root.join(&relative);
+    let text = match std::fs::read(&path).ok().and_then(|content| String::from_utf8(content).ok()) {
+      Some(text) => text,
+      None => continue,
+    };
+    let replaced = substitution.apply(&text);
+    if replaced != text {
+      changed += 1;
+      if !dry_run {
+        std::fs::write(&path, replaced)?;
+        index.add_path(Path::new(&relative))?;
+      }
+    }
+  }
+  if !dry_run {
+    index.write()?;
+  }
+  Ok(if changed == 0 { Outcome::Compliant } else { Outcome::Changed(changed, None) })
+}
+
+fn commit(repo: &Repository, message: &str) -> Result<Oid, AppError> {
+  let tree = repo.find_tree(repo.index()?.write_tree()?)?;
+  let head = repo.head()?.peel_to_commit()?;
+  let author = repo
+    .signature()
+    .map_err(|e| AppError::UserError(format!("No author, set user.name and user.email in git: {}", e.message())))?;
+  Ok(repo.commit(Some("HEAD"), &author, &author, message, &tree, &[&head])?)
+}
+
+fn apply_in(repo: &Repository, root: &Path, change: &Change, options: &PatchOptions) -> Result<Outcome, AppError> {
+  if is_dirty(repo)? {
+    return Err(AppError::UserError("has uncommitted changes".to_owned()));
+  }
+  let outcome = match change {
+    Change::Patch(patch) => apply_diff(repo, root, patch, options.dry_run)?,
+    Change::Substitute { substitution, files } => substitute(repo, root, substitution, files.as_ref(), options.dry_run)?,
+  };
+  match outcome {
+    Outcome::Changed(files, None) if !options.dry_run => Ok(Outcome::Changed(files, Some(commit(repo, &options.message)?))),
+    other => Ok(other),
+  }
+}
+
+/// Applies `change` to every selected project that is checked out and commits it on the checked out branch. Projects
+/// that already have the change are reported as compliant, projects with uncommitted changes fail.
+pub fn apply_patch(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  change: &Change,
+  options: &PatchOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut table = Table::new();
+  let (mut changed, mut compliant, mut failed) = (0, 0, 0);
+  for project in selector.select_repositories(&config) {
+    let path = config.checkout_path(project, logger);
+    if !path.exists() {
+      debug!(logger, "Skipping, not checked out"; "project" => &project.name);
+      continue;
+    }
+    let project_logger = logger.new(o!("project" => project.name.clone()));
+    let result = Repository::open(&path)
+      .map_err(AppError::from)
+      .and_then(|repo| apply_in(&repo, &path, change, options));
+    let (message, tone) = match result {
+      Ok(Outcome::Compliant) => {
+        compliant += 1;
+        ("already compliant".to_owned(), Tone::Muted)
+      }
+      Ok(Outcome::Changed(files, commit)) => {
+        changed += 1;
+        debug!(project_logger, "Changed"; "files" => files);
+        match commit {
+          Some(commit) => (format!("changed {} file(s), committed {}", files, &commit.to_string()[..8]), Tone::Good),
+          None => (format!("would change {} file(s)", files), Tone::Plain),
+        }
+      }
+      Err(AppError::UserError(message)) => {
+        failed += 1;
+        (message, Tone::Bad)
+      }
+      Err(e) => {
+        failed += 1;
+        (format!("{}", e), Tone::Bad)
+      }
+    };
+    table.add_row(vec![Cell::plain(project.name.clone()), Cell::new(message, tone)]);
+  }
+  table.print();
+  println!(
+    "{}",
+    Tone::Muted.paint(&format!("{} changed, {} already compliant, {} failed", changed, compliant, failed))
+  );
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{commit_file, temp_dir};
+  use spectral::prelude::*;
+  use std::fs;
+
+  #[test]
+  fn test_parse_substitution() {
+    let substitution = parse_substitution("s/edition = \"(\\d+)\"/edition = \"2021\" # was \\1/").unwrap();
+    assert_that(&substitution.apply("edition = \"2018\"\nname = \"fw\"\n")).is_equal_to("edition = \"2021\" # was 2018\nname = \"fw\"\n".to_owned());
+    assert_that(&parse_substitution("s|a/b|<&>|g").unwrap().apply("a/b a/b")).is_equal_to("<a/b> <a/b>".to_owned());
+    assert_that(&parse_substitution("s/a\\/b/$x/").unwrap().apply("a/b a/b")).is_equal_to("$x a/b".to_owned());
+    assert_that(&parse_substitution("s/A/b/i").unwrap().apply("aA")).is_equal_to("bA".to_owned());
+    assert_that(&parse_substitution("s/a/b").is_err()).is_true();
+    assert_that(&parse_substitution("s/a/b/x").is_err()).is_true();
+    assert_that(&parse_substitution("s/(/b/").is_err()).is_true();
+  }
+
+  #[test]
+  fn test_apply_in() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let repo = Repository::init(dir).unwrap();
+    repo.config().unwrap().set_str("user.name", "fw").unwrap();
+    repo.config().unwrap().set_str("user.email", "fw@example.com").unwrap();
+    commit_file(&repo, "README", "fw\nby brocode\n");
+
+    let patch = Change::Patch(b"diff --git a/README b/README\n--- a/README\n+++ b/README\n@@ -1,2 +1,2 @@\n fw\n-by brocode\n+by the fw authors\n".to_vec());
+    let options = PatchOptions {
+      message: "Update the authors".to_owned(),
+      dry_run: false,
+    };
+    let dry_run = PatchOptions {
+      dry_run: true,
+      ..options.clone()
+    };
+    assert_that(&apply_in(&repo, dir, &patch, &dry_run).unwrap()).is_equal_to(Outcome::Changed(1, None));
+    let commit = match apply_in(&repo, dir, &patch, &options).unwrap() {
+      Outcome::Changed(1, Some(commit)) => repo.find_commit(commit).unwrap(),
+      other => panic!("not committed: {:?}", other),
+    };
+    assert_that(&commit.message()).is_equal_to(Some("Update the authors"));
+    assert_that(&fs::read_to_string(dir.join("README")).unwrap()).is_equal_to("fw\nby the fw authors\n".to_owned());
+    assert_that(&is_dirty(&repo).unwrap()).is_false();
+    assert_that(&apply_in(&repo, dir, &patch, &options).unwrap()).is_equal_to(Outcome::Compliant);
+
+    let substitute = Change::Substitute {
+      substitution: parse_substitution("s/fw authors/fw contributors/").unwrap(),
+      files: None,
+    };
+    assert_that(&apply_in(&repo, dir, &substitute, &options).unwrap()).matches(|outcome| matches!(outcome, Outcome::Changed(1, Some(_))));
+    assert_that(&apply_in(&repo, dir, &substitute, &options).unwrap()).is_equal_to(Outcome::Compliant);
+    assert_that(&apply_in(&repo, dir, &patch, &options).is_err()).is_true();
+  }
+}
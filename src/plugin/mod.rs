@@ -0,0 +1,197 @@
+//! Plugins are executables named `fw-<name>` on the `PATH`. `fw <name> ARGS...` runs them with ARGS when fw has no such
+//! command itself and writes a `PluginInput` as JSON to their stdin.
+use crate::config::{project::Project, settings::without_tokens, Config};
+use crate::errors::AppError;
+use crate::output::{Cell, Table, Tone};
+use serde::Serialize;
+use serde_json::Value;
+use slog::{debug, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Goes up when fields of `PluginInput` are removed or change their meaning, new fields do not change it.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const PREFIX: &str = "fw-";
+
+/// What a plugin reads from stdin.
+#[derive(Debug, Serialize)]
+pub struct PluginInput<'a> {
+  pub version: u32,
+  /// fw is read-only (`readonly` in the settings or `--frozen`), the plugin should not change the configuration either.
+  pub readonly: bool,
+  /// Why the configuration could not be read. `settings` is null and `projects` empty then.
+  pub config_error: Option<String>,
+  /// The settings without tokens, every `fw-*` on the PATH gets them.
+  pub settings: Option<Value>,
+  pub projects: Vec<PluginProject<'a>>,
+}
+
+/// A project as in its config file plus what fw resolves for it.
+#[derive(Debug, Serialize)]
+pub struct PluginProject<'a> {
+  pub name: &'a str,
+  /// Where the project is checked out, resolved like `fw path` does.
+  pub path: String,
+  pub cloned: bool,
+  /// The tags of the project and those that apply through their `when` condition.
+  pub effective_tags: BTreeSet<String>,
+  #[serde(flatten)]
+  pub project: &'a Project,
+}
+
+impl<'a> PluginProject<'a> {
+  pub fn new(config: &Config, project: &'a Project, logger: &Logger) -> PluginProject<'a> {
+    let path = config.actual_path_to_project(project, logger);
+    PluginProject {
+      name: &project.name,
+      cloned: path.exists(),
+      path: path.to_string_lossy().to_string(),
+      effective_tags: config.effective_tags(project, logger),
+      project,
+    }
+  }
+}
+
+impl<'a> PluginInput<'a> {
+  pub fn new(config: &'a Result<Config, AppError>, readonly: bool, logger: &Logger) -> PluginInput<'a> {
+    match config {
+      Ok(config) => PluginInput {
+        version: PROTOCOL_VERSION,
+        readonly,
+        config_error: None,
+        settings: serde_json::to_value(&config.settings).ok().map(|mut settings| {
+          without_tokens(&mut settings);
+          settings
+        }),
+        projects: config.projects.values().map(|project| PluginProject::new(config, project, logger)).collect(),
+      },
+      Err(e) => PluginInput {
+        version: PROTOCOL_VERSION,
+        readonly,
+        config_error: Some(format!("{}", e)),
+        settings: None,
+        projects: vec![],
+      },
+    }
+  }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+  use std::os::unix::fs::PermissionsExt;
+  fs::metadata(path).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+  path.is_file()
+}
+
+/// The plugin name of an executable's file name, `fw-foo` and on Windows `fw-foo.exe` are `foo`.
+fn plugin_name(file_name: &str) -> Option<&str> {
+  let name = file_name.strip_prefix(PREFIX)?;
+  let name = if cfg!(windows) { name.strip_suffix(".exe").unwrap_or(name) } else { name };
+  if name.is_empty() {
+    None
+  } else {
+    Some(name)
+  }
+}
+
+/// All plugins in `dirs` by name, the first of a name wins like for the `PATH`.
+fn plugins_in(dirs: impl Iterator<Item = PathBuf>) -> BTreeMap<String, PathBuf> {
+  let mut plugins = BTreeMap::new();
+  for dir in dirs {
+    let entries = match fs::read_dir(&dir) {
+      Ok(entries) => entries,
+      Err(_) => continue,
+    };
+    for entry in entries.flatten() {
+      let file_name = entry.file_name().to_string_lossy().to_string();
+      if let Some(name) = plugin_name(&file_name) {
+        if !plugins.contains_key(name) && is_executable(&entry.path()) {
+          plugins.insert(name.to_owned(), entry.path());
+        }
+      }
+    }
+  }
+  plugins
+}
+
+fn path_dirs() -> Vec<PathBuf> {
+  env::var_os("PATH").map(|path| env::split_paths(&path).collect()).unwrap_or_default()
+}
+
+/// Lists the plugins on the `PATH`.
+pub fn list() -> Result<(), AppError> {
+  let mut table = Table::new();
+  for (name, path) in plugins_in(path_dirs().into_iter()) {
+    table.add_row(vec![Cell::plain(name), Cell::new(path.to_string_lossy(), Tone::Muted)]);
+  }
+  table.print();
+  Ok(())
+}
+
+/// Runs the plugin for the unknown command `name` and returns its exit code.
+pub fn run(name: &str, arguments: &[String], input: &PluginInput<'_>, logger: &Logger) -> Result<i32, AppError> {
+  let path = plugins_in(path_dirs().into_iter()).remove(name).ok_or_else(|| {
+    AppError::UserError(format!(
+      "{} is not a fw command and there is no {}{} plugin on the PATH, fw help lists the commands",
+      name, PREFIX, name
+    ))
+  })?;
+  debug!(logger, "Running plugin"; "path" => path.to_string_lossy().to_string());
+  let json = serde_json::to_string(input)?;
+  let mut child = Command::new(&path)
+    .args(arguments)
+    .env("FW_PLUGIN_PROTOCOL", PROTOCOL_VERSION.to_string())
+    .env("FW_EXECUTABLE", env::current_exe()?)
+    .stdin(Stdio::piped())
+    .spawn()?;
+  if let Some(mut stdin) = child.stdin.take() {
+    match writeln!(stdin, "{}", json) {
+      // plugins that do not need the configuration may exit without reading it
+      Err(e) if e.kind() == io::ErrorKind::BrokenPipe => (),
+      other => other?,
+    }
+  }
+  let status = child.wait()?;
+  Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+  use std::os::unix::fs::PermissionsExt;
+
+  #[test]
+  fn test_plugins_in() {
+    let temp = temp_dir();
+    let root = temp.path();
+    let (first, second) = (root.join("first"), root.join("second"));
+    fs::create_dir_all(&first).unwrap();
+    fs::create_dir_all(&second).unwrap();
+    for (dir, file, mode) in [
+      (&first, "fw-hello", 0o755),
+      (&first, "fw-not-executable", 0o644),
+      (&first, "fw-", 0o755),
+      (&second, "fw-hello", 0o755),
+      (&second, "fw-bye", 0o755),
+      (&second, "git", 0o755),
+    ] {
+      let path = dir.join(file);
+      fs::write(&path, "#!/bin/sh\n").unwrap();
+      fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+    let plugins = plugins_in(vec![first.clone(), root.join("missing"), second.clone()].into_iter());
+    assert_that(&plugins.keys().cloned().collect::<Vec<String>>()).is_equal_to(vec!["bye".to_owned(), "hello".to_owned()]);
+    assert_that(&plugins["hello"]).is_equal_to(first.join("fw-hello"));
+  }
+}
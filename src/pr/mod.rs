@@ -0,0 +1,235 @@
+use crate::config::settings::GitlabSettings;
+use crate::config::{project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git::{default_branch, push_branch};
+use crate::git_url::host_and_path;
+use crate::output::{Cell, Table, Tone};
+use crate::runtime;
+use crate::ws::client::ForgeClient;
+use crate::ws::github;
+use git2::{BranchType, Repository};
+use serde::Deserialize;
+use slog::{debug, o, Logger};
+use std::time::Duration;
+
+/// Where a repository is hosted, parsed from its git URL.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Forge {
+  GitHub {
+    owner: String,
+    repo: String,
+  },
+  /// `path` is the full project path including subgroups, e.g. `acme/backend/api`.
+  GitLab {
+    path: String,
+  },
+}
+
+/// github.com is GitHub, gitlab.com and the host of the gitlab settings are GitLab.
+pub fn forge_of(url: &str, gitlab_host: Option<&str>) -> Option<Forge> {
+  let (host, path) = host_and_path(url)?;
+  if host.eq_ignore_ascii_case("github.com") {
+    let (owner, repo) = path.split_once('/')?;
+    Some(Forge::GitHub {
+      owner: owner.to_owned(),
+      repo: repo.to_owned(),
+    })
+  } else if host.eq_ignore_ascii_case("gitlab.com") || gitlab_host.map(|h| h.eq_ignore_ascii_case(host)).unwrap_or(false) {
+    Some(Forge::GitLab { path: path.to_owned() })
+  } else {
+    None
+  }
+}
+
+/// What `fw pr create` opens in every project.
+#[derive(Debug, Clone)]
+pub struct PullRequestOptions {
+  pub title: String,
+  pub body: String,
+  /// The branch to propose, by default the one checked out in each project.
+  pub branch: Option<String>,
+  /// The branch to merge into, by default the default branch of `origin`.
+  pub base: Option<String>,
+  pub dry_run: bool,
+  pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+struct Proposal {
+  branch: String,
+  base: String,
+  ahead: usize,
+  needs_push: bool,
+}
+
+enum Plan {
+  Propose(Proposal),
+  /// Nothing to propose, for the given reason.
+  Skip(String),
+}
+
+fn plan(repo: &Repository, options: &PullRequestOptions) -> Result<Plan, AppError> {
+  let branch = match &options.branch {
+    Some(branch) => branch.clone(),
+    None => {
+      let head = repo.head()?;
+      match head.shorthand().filter(|_| head.is_branch()) {
+        Some(branch) => branch.to_owned(),
+        None => return Ok(Plan::Skip("HEAD is detached".to_owned())),
+      }
+    }
+  };
+  let base = match options.base.clone().or_else(|| default_branch(repo)) {
+    Some(base) => base,
+    None => return Err(AppError::UserError("no default branch on origin, use --base".to_owned())),
+  };
+  if branch == base {
+    return Ok(Plan::Skip(format!("on {}", base)));
+  }
+  let tip = match repo.find_branch(&branch, BranchType::Local) {
+    Ok(local) => local.get().peel_to_commit()?.id(),
+    Err(_) => return Ok(Plan::Skip(format!("no branch {}", branch))),
+  };
+  let base_tip = repo
+    .find_branch(&format!("origin/{}", base), BranchType::Remote)
+    .map_err(|_| AppError::UserError(format!("origin has no branch {}", base)))?
+    .get()
+    .peel_to_commit()?
+    .id();
+  let (ahead, _) = repo.graph_ahead_behind(tip, base_tip)?;
+  if ahead == 0 {
+    return Ok(Plan::Skip(format!("{} has no commits that are not in {}", branch, base)));
+  }
+  let pushed = repo
+    .find_branch(&format!("origin/{}", branch), BranchType::Remote)
+    .ok()
+    .and_then(|remote| remote.get().target());
+  Ok(Plan::Propose(Proposal {
+    branch,
+    base,
+    ahead,
+    needs_push: pushed != Some(tip),
+  }))
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+  web_url: String,
+}
+
+fn create_merge_request(gitlab: GitlabSettings, path: String, proposal: &Proposal, options: &PullRequestOptions, logger: &Logger) -> Result<String, AppError> {
+  use gitlab::api::Query;
+
+  let client = ForgeClient::gitlab(&gitlab.host, &gitlab.token, logger)?;
+  let endpoint = gitlab::api::projects::merge_requests::CreateMergeRequest::builder()
+    .project(path)
+    .source_branch(proposal.branch.clone())
+    .target_branch(proposal.base.clone())
+    .title(options.title.clone())
+    .description(options.body.clone())
+    .build()
+    .map_err(|e| AppError::RuntimeError(format!("Invalid merge request: {}", e)))?;
+  let merge_request: MergeRequest = endpoint
+    .query(&client)
+    .map_err(|e| AppError::UserError(format!("GitLab refused the merge request: {}", e)))?;
+  Ok(merge_request.web_url)
+}
+
+fn open(config: &Config, project: &Project, forge: Forge, proposal: &Proposal, options: &PullRequestOptions, logger: &Logger) -> Result<String, AppError> {
+  match forge {
+    Forge::GitHub { owner, repo } => {
+      let token = github::token(config.settings.github_token.as_ref())
+        .ok_or_else(|| AppError::UserError("no github oauth token (settings.github_token or FW_GITHUB_TOKEN) to open pull requests".to_owned()))?;
+      let api = github::github_api(&token, logger)?;
+      let (branch, base, title, body) = (proposal.branch.clone(), proposal.base.clone(), options.title.clone(), options.body.clone());
+      runtime::block_on(runtime::blocking(&format!("Pull request for {}", project.name), options.timeout, move || {
+        api.create_pull_request(&owner, &repo, &branch, &base, &title, &body)
+      }))
+    }
+    Forge::GitLab { path } => {
+      let gitlab = config
+        .settings
+        .gitlab
+        .clone()
+        .ok_or_else(|| AppError::UserError("no gitlab settings (token and host) to open merge requests".to_owned()))?;
+      let (proposal, options, logger) = (proposal.clone(), options.clone(), logger.clone());
+      runtime::block_on(runtime::blocking(&format!("Merge request for {}", project.name), options.timeout, move || {
+        create_merge_request(gitlab, path, &proposal, &options, &logger)
+      }))
+    }
+  }
+}
+
+pub(crate) fn create_for(config: &Config, project: &Project, options: &PullRequestOptions, logger: &Logger) -> Result<(String, Tone), AppError> {
+  let path = config.checkout_path(project, logger);
+  let repo = Repository::open(&path)?;
+  let proposal = match plan(&repo, options)? {
+    Plan::Propose(proposal) => proposal,
+    Plan::Skip(reason) => return Ok((format!("skipped, {}", reason), Tone::Muted)),
+  };
+  let gitlab_host = config.settings.gitlab.as_ref().map(|g| g.host.as_str());
+  let forge = forge_of(&project.git, gitlab_host).ok_or_else(|| AppError::UserError(format!("{} is not hosted on GitHub or GitLab", project.git)))?;
+  debug!(logger, "Proposing"; "branch" => &proposal.branch, "base" => &proposal.base, "ahead" => proposal.ahead, "push" => proposal.needs_push);
+  if options.dry_run {
+    let push = if proposal.needs_push { "push and " } else { "" };
+    return Ok((
+      format!("would {}propose {} ({} commits) for {}", push, proposal.branch, proposal.ahead, proposal.base),
+      Tone::Plain,
+    ));
+  }
+  if proposal.needs_push {
+    push_branch(project, &repo, &proposal.branch, logger)?;
+  }
+  open(config, project, forge, &proposal, options, logger).map(|url| (url, Tone::Good))
+}
+
+/// Pushes the branch and opens a pull or merge request in every selected project where it has commits that are not in the base branch.
+pub fn create(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, options: &PullRequestOptions, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut table = Table::new();
+  let mut failed = 0;
+  for project in selector.select_repositories(&config) {
+    if !config.checkout_path(project, logger).exists() {
+      continue;
+    }
+    let project_logger = logger.new(o!("project" => project.name.clone()));
+    let (message, tone) = match create_for(&config, project, options, &project_logger) {
+      Ok(result) => result,
+      Err(AppError::Interrupted) => return Err(AppError::Interrupted),
+      Err(e) => {
+        failed += 1;
+        match e {
+          AppError::UserError(message) => (message, Tone::Bad),
+          other => (format!("{}", other), Tone::Bad),
+        }
+      }
+    };
+    table.add_row(vec![Cell::plain(project.name.clone()), Cell::new(message, tone)]);
+  }
+  table.print();
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_forge_of() {
+    assert_that(&forge_of("git@github.com:acme/fw.git", None)).is_equal_to(Some(Forge::GitHub {
+      owner: "acme".to_owned(),
+      repo: "fw".to_owned(),
+    }));
+    assert_that(&forge_of("https://gitlab.com/acme/backend/api.git", None)).is_equal_to(Some(Forge::GitLab {
+      path: "acme/backend/api".to_owned(),
+    }));
+    assert_that(&forge_of("ssh://git@git.acme.com:2222/team/api", Some("git.acme.com"))).is_equal_to(Some(Forge::GitLab { path: "team/api".to_owned() }));
+    assert_that(&forge_of("git@git.acme.com:team/api", None)).is_equal_to(None);
+    assert_that(&forge_of("/tmp/fw", None)).is_equal_to(None);
+  }
+}
@@ -0,0 +1,170 @@
+use crate::config::{self, project::Project};
+use crate::errors::AppError;
+use crate::output;
+use slog::{info, Logger};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+/// What `fw add` and the importers do with a new project whose name is already in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+  /// Refuse to add it, `fw add` without a terminal.
+  Fail,
+  /// Keep the existing project, the importers by default.
+  Skip,
+  /// Ask whether to merge, replace, add under another name or skip.
+  Ask,
+  /// Replace the existing project.
+  Force,
+  /// Keep the existing project with the new git URL and the tags of both.
+  Merge,
+}
+
+impl OnConflict {
+  /// `--force`, `--merge` and `--interactive`, which exclude each other, or `default` without them.
+  pub fn from_flags(force: bool, merge: bool, interactive: bool, default: OnConflict) -> OnConflict {
+    if force {
+      OnConflict::Force
+    } else if merge {
+      OnConflict::Merge
+    } else if interactive {
+      OnConflict::Ask
+    } else {
+      default
+    }
+  }
+}
+
+// The settings of the existing project win, the new one only adds what is missing.
+pub(super) fn merge(existing: &Project, new: Project) -> Project {
+  let mut tags = existing.tags.clone().unwrap_or_default();
+  tags.extend(new.tags.unwrap_or_default());
+  Project {
+    git: new.git,
+    tags: if tags.is_empty() { None } else { Some(tags) },
+    after_clone: existing.after_clone.clone().or(new.after_clone),
+    after_workon: existing.after_workon.clone().or(new.after_workon),
+    override_path: existing.override_path.clone().or(new.override_path),
+    ..existing.clone()
+  }
+}
+
+/// `name-2`, `name-3`, ... whichever is free first.
+fn free_name(projects: &BTreeMap<String, Project>, name: &str) -> String {
+  (2..)
+    .map(|n| format!("{}-{}", name, n))
+    .find(|candidate| !projects.contains_key(candidate))
+    .expect("there is always a free name")
+}
+
+fn read_answer(question: &str) -> Result<String, AppError> {
+  eprint!("{} ", question);
+  io::stderr().flush()?;
+  let mut answer = String::new();
+  io::stdin().lock().read_line(&mut answer)?;
+  Ok(answer.trim().to_owned())
+}
+
+fn ask(projects: &BTreeMap<String, Project>, existing: &Project, new: Project) -> Result<Option<Project>, AppError> {
+  if !output::can_prompt() {
+    return Err(AppError::UserError(format!(
+      "Project key {} already exists, asking what to do needs a terminal (or use --force or --merge)",
+      existing.name
+    )));
+  }
+  eprintln!("Project {} already exists", existing.name);
+  eprintln!("  in the config: {}", existing.git);
+  eprintln!("  new:           {}", new.git);
+  loop {
+    match read_answer("[m]erge (take the new URL), [r]eplace, [a]dd under another name or [s]kip?")?.as_str() {
+      "m" | "merge" => return Ok(Some(merge(existing, new))),
+      "r" | "replace" => {
+        return Ok(Some(Project {
+          project_config_path: existing.project_config_path.clone(),
+          ..new
+        }))
+      }
+      "a" | "add" => {
+        let suggestion = free_name(projects, &existing.name);
+        let name = read_answer(&format!("Name [{}]:", suggestion))?;
+        let name = if name.is_empty() { suggestion } else { name };
+        if projects.contains_key(&name) {
+          eprintln!("Project key {} exists too", name);
+          continue;
+        }
+        return Ok(Some(Project { name, ..new }));
+      }
+      "s" | "skip" | "" => return Ok(None),
+      _ => continue,
+    }
+  }
+}
+
+/// Writes `project` to the config unless its name is taken by one of `projects`, then `on_conflict` decides. An
+/// existing project with the same git URL is only replaced with `OnConflict::Force`. Returns what was written.
+pub(crate) fn add_project(
+  projects: &BTreeMap<String, Project>,
+  project: Project,
+  on_conflict: OnConflict,
+  logger: &Logger,
+) -> Result<Option<Project>, AppError> {
+  let resolved = match projects.get(&project.name) {
+    None => Some(project),
+    Some(existing) => match on_conflict {
+      OnConflict::Force => Some(Project {
+        project_config_path: existing.project_config_path.clone(),
+        ..project
+      }),
+      OnConflict::Fail => {
+        return Err(AppError::UserError(format!(
+          "Project key {} already exists, not gonna overwrite it for you (use --force, --merge or --interactive)",
+          project.name
+        )))
+      }
+      _ if existing.git == project.git => {
+        info!(logger, "Project already exists with the same git URL"; "project_name" => &project.name);
+        None
+      }
+      OnConflict::Skip => {
+        info!(logger, "Skipping project because its name already exists in the config"; "project_name" => &project.name, "git" => &existing.git);
+        None
+      }
+      OnConflict::Merge => Some(merge(existing, project)),
+      OnConflict::Ask => ask(projects, existing, project)?,
+    },
+  };
+  if let Some(project) = &resolved {
+    info!(logger, "Saving project"; "project_name" => &project.name);
+    config::write_project(project, logger)?;
+  }
+  Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use maplit::btreeset;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_merge_keeps_existing_settings() {
+    let existing = Project::example();
+    let new = Project {
+      git: "git@github.com:fork/fw.git".to_owned(),
+      tags: Some(btreeset!["fork".to_owned()]),
+      after_clone: Some("make".to_owned()),
+      override_path: None,
+      ..Project::example()
+    };
+    let merged = merge(&existing, new);
+    assert_that(&merged.git).is_equal_to("git@github.com:fork/fw.git".to_owned());
+    assert_that(&merged.tags).is_equal_to(Some(btreeset!["brocode".to_owned(), "fork".to_owned(), "rust".to_owned()]));
+    assert_that(&merged.after_clone).is_equal_to(existing.after_clone.clone());
+    assert_that(&merged.override_path).is_equal_to(existing.override_path.clone());
+
+    let mut projects = BTreeMap::new();
+    projects.insert("fw".to_owned(), existing.clone());
+    projects.insert("fw-2".to_owned(), existing);
+    assert_that(&free_name(&projects, "fw")).is_equal_to("fw-3".to_owned());
+  }
+}
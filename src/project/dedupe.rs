@@ -0,0 +1,222 @@
+use super::conflict::merge;
+use crate::config::{self, project::Project, Config};
+use crate::errors::AppError;
+use crate::git_url::repository_key;
+use crate::output::{self, print_warning};
+use slog::{debug, info, Logger};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+/// Groups of projects with the same repository and `subdir`, see [`repository_key`]. Directory projects have none. The project to keep comes first: the one that
+/// is checked out, then the one with the shortest name.
+pub fn duplicates<'a>(config: &'a Config, logger: &Logger) -> Vec<Vec<&'a Project>> {
+  let mut by_key: BTreeMap<(String, Option<&str>), Vec<&Project>> = BTreeMap::new();
+  for project in config.projects.values().filter(|project| !project.is_directory()) {
+    by_key
+      .entry((repository_key(&project.git), project.subdir.as_deref()))
+      .or_default()
+      .push(project);
+  }
+  by_key
+    .into_values()
+    .filter(|group| group.len() > 1)
+    .map(|mut group| {
+      group.sort_by_key(|project| {
+        (
+          !config.actual_path_to_project(project, logger).exists(),
+          project.name.len(),
+          project.name.clone(),
+        )
+      });
+      group
+    })
+    .collect()
+}
+
+fn confirm(question: &str) -> Result<bool, AppError> {
+  if !output::can_prompt() {
+    return Err(AppError::UserError("Asking before merging needs a terminal (or use --yes)".to_owned()));
+  }
+  eprint!("{} [y/N] ", question);
+  io::stderr().flush()?;
+  let mut answer = String::new();
+  io::stdin().lock().read_line(&mut answer)?;
+  Ok(matches!(answer.trim(), "y" | "yes"))
+}
+
+/// The projects that change when each of `groups` is merged into its first project, and the names that are merged
+/// away with the name they are merged into.
+fn merge_groups<'a>(config: &'a Config, groups: &[&Vec<&'a Project>], logger: &Logger) -> (BTreeMap<&'a str, Project>, BTreeMap<&'a str, &'a str>) {
+  let mut merged_into: BTreeMap<&str, &str> = BTreeMap::new();
+  let mut updated: BTreeMap<&str, Project> = BTreeMap::new();
+  for group in groups {
+    let (keep, others) = group.split_first().expect("groups have more than one project");
+    let merged = others.iter().fold((*keep).clone(), |merged, other| {
+      merge(
+        &merged,
+        Project {
+          git: keep.git.clone(),
+          ..(*other).clone()
+        },
+      )
+    });
+    updated.insert(&keep.name, merged);
+    for other in others {
+      merged_into.insert(&other.name, &keep.name);
+    }
+  }
+
+  for project in config.projects.values().filter(|project| !merged_into.contains_key(project.name.as_str())) {
+    let current = updated.get(project.name.as_str()).cloned().unwrap_or_else(|| project.clone());
+    if current.dependencies().any(|dependency| merged_into.contains_key(dependency)) {
+      let mut depends_on: Vec<String> = Vec::new();
+      for dependency in current.dependencies() {
+        let dependency = merged_into.get(dependency).copied().unwrap_or(dependency).to_owned();
+        if !depends_on.contains(&dependency) && dependency != current.name {
+          depends_on.push(dependency);
+        }
+      }
+      debug!(logger, "Renaming dependencies"; "project" => &current.name);
+      updated.insert(
+        &project.name,
+        Project {
+          depends_on: Some(depends_on),
+          ..current
+        },
+      );
+    }
+  }
+  (updated, merged_into)
+}
+
+/// Merges every group of projects with the same repository into the first of the group, which keeps its settings and
+/// gets the tags of all. `depends_on` of other projects is changed to the kept name. Asks for each group unless `yes`.
+pub fn dedupe(maybe_config: Result<Config, AppError>, yes: bool, dry_run: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let groups = duplicates(&config, logger);
+  if groups.is_empty() {
+    println!("No duplicate projects");
+    return Ok(());
+  }
+  let mut accepted = Vec::new();
+  for group in &groups {
+    let (keep, others) = group.split_first().expect("groups have more than one project");
+    let names: Vec<&str> = others.iter().map(|project| project.name.as_str()).collect();
+    let question = format!("Merge {} into {} ({})?", names.join(", "), keep.name, keep.git);
+    if dry_run {
+      println!("{}", question.trim_end_matches('?'));
+      continue;
+    }
+    if yes || confirm(&question)? {
+      accepted.push(group);
+    }
+  }
+  let (updated, merged_into) = merge_groups(&config, &accepted, logger);
+
+  for project in updated.values() {
+    config::write_project(project, logger)?;
+  }
+  for (name, kept) in merged_into {
+    let project = &config.projects[name];
+    config::delete_project_config(project, logger)?;
+    info!(logger, "Merged duplicate"; "project" => name, "into" => kept);
+    let checkout = config.actual_path_to_project(project, logger);
+    if checkout.exists() {
+      print_warning(&format!("The checkout of {} at {} is left as it is", name, checkout.to_string_lossy()));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::settings::Settings;
+  use crate::testing::temp_dir;
+  use maplit::btreeset;
+  use spectral::prelude::*;
+  use std::fs;
+
+  fn a_project(name: &str, git: &str) -> Project {
+    Project {
+      name: name.to_owned(),
+      git: git.to_owned(),
+      tags: None,
+      after_clone: None,
+      override_path: None,
+      depends_on: None,
+      ..Project::example()
+    }
+  }
+
+  fn a_config(workspace: &std::path::Path, projects: Vec<Project>) -> Config {
+    Config {
+      projects: projects.into_iter().map(|project| (project.name.clone(), project)).collect(),
+      settings: serde_json::from_value::<Settings>(serde_json::json!({ "workspace": workspace })).unwrap(),
+      outside_profile: BTreeMap::new(),
+    }
+  }
+
+  fn names<'a>(groups: &[Vec<&'a Project>]) -> Vec<Vec<&'a str>> {
+    groups.iter().map(|group| group.iter().map(|project| project.name.as_str()).collect()).collect()
+  }
+
+  #[test]
+  fn test_duplicates_groups_and_orders() {
+    let temp = temp_dir();
+    let workspace = temp.path();
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let config = a_config(
+      workspace,
+      vec![
+        a_project("fw", "git@github.com:brocode/fw.git"),
+        a_project("fw-https", "https://github.com/brocode/fw"),
+        Project {
+          subdir: Some("docs".to_owned()),
+          ..a_project("fw-docs", "git@github.com:brocode/fw.git")
+        },
+        a_project("other", "git@github.com:brocode/other.git"),
+      ],
+    );
+    assert_that(&names(&duplicates(&config, &logger))).is_equal_to(vec![vec!["fw", "fw-https"]]);
+
+    fs::create_dir_all(workspace.join("fw-https")).unwrap();
+    assert_that(&names(&duplicates(&config, &logger))).is_equal_to(vec![vec!["fw-https", "fw"]]);
+  }
+
+  #[test]
+  fn test_merge_groups() {
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let config = a_config(
+      std::path::Path::new("/nonexistent"),
+      vec![
+        Project {
+          tags: Some(btreeset!["rust".to_owned()]),
+          depends_on: Some(vec!["fw-fork".to_owned(), "lib".to_owned()]),
+          ..a_project("fw", "git@github.com:brocode/fw.git")
+        },
+        Project {
+          tags: Some(btreeset!["fork".to_owned()]),
+          after_clone: Some("make".to_owned()),
+          ..a_project("fw-fork", "https://github.com/brocode/fw")
+        },
+        Project {
+          depends_on: Some(vec!["fw-fork".to_owned(), "fw".to_owned(), "lib".to_owned()]),
+          ..a_project("app", "git@github.com:brocode/app.git")
+        },
+        a_project("lib", "git@github.com:brocode/lib.git"),
+      ],
+    );
+    let groups = duplicates(&config, &logger);
+    let (updated, merged_into) = merge_groups(&config, &groups.iter().collect::<Vec<_>>(), &logger);
+
+    assert_that(&merged_into).is_equal_to(BTreeMap::from([("fw-fork", "fw")]));
+    assert_that(&updated.keys().copied().collect::<Vec<_>>()).is_equal_to(vec!["app", "fw"]);
+    let fw = &updated["fw"];
+    assert_that(&fw.git.as_str()).is_equal_to("git@github.com:brocode/fw.git");
+    assert_that(&fw.tags).is_equal_to(Some(btreeset!["fork".to_owned(), "rust".to_owned()]));
+    assert_that(&fw.after_clone).is_equal_to(Some("make".to_owned()));
+    assert_that(&fw.depends_on).is_equal_to(Some(vec!["lib".to_owned()]));
+    assert_that(&updated["app"].depends_on).is_equal_to(Some(vec!["fw".to_owned(), "lib".to_owned()]));
+  }
+}
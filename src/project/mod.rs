@@ -1,14 +1,29 @@
 use crate::config;
+use crate::config::detect;
 use crate::config::Config;
-use crate::config::{project::Project, project::Remote};
+use crate::config::{project::Project, project::Remote, selector::ProjectSelector};
 use crate::errors::AppError;
-use crate::git::repo_name_from_url;
-use ansi_term::Style;
+use crate::git::{default_branch, repo_name_from_url};
+use crate::output::{print_warning, Cell, Table, Tone};
+use crate::recent;
+use git2::Repository;
 use slog::Logger;
 use slog::{debug, info};
-use std::collections::BTreeSet;
+use std::env;
 use std::fs;
+use std::io;
+use std::path::Path;
+use walkdir::WalkDir;
 
+mod conflict;
+mod dedupe;
+
+pub(crate) use self::conflict::add_project;
+pub use self::conflict::OnConflict;
+pub use self::dedupe::{dedupe, duplicates};
+
+/// Adds a project to the config, `on_conflict` decides what happens if its name is taken.
+#[allow(clippy::too_many_arguments)]
 pub fn add_entry(
   maybe_config: Result<Config, AppError>,
   maybe_name: Option<&str>,
@@ -16,35 +31,125 @@ pub fn add_entry(
   after_workon: Option<String>,
   after_clone: Option<String>,
   override_path: Option<String>,
+  on_conflict: OnConflict,
   logger: &Logger,
 ) -> Result<(), AppError> {
+  if url == "." {
+    return add_current_checkout(maybe_config?, maybe_name, after_workon, after_clone, override_path, on_conflict, logger);
+  }
   let name = maybe_name
     .ok_or_else(|| AppError::UserError(format!("No project name specified for {}", url)))
     .or_else(|_| repo_name_from_url(url))?;
   let config: Config = maybe_config?;
   info!(logger, "Prepare new project entry"; "name" => name, "url" => url);
-  if config.projects.contains_key(name) {
-    Err(AppError::UserError(format!(
-      "Project key {} already exists, not gonna overwrite it for you",
-      name
-    )))
-  } else {
-    let default_after_clone = config.settings.default_after_clone.clone();
-    let default_after_workon = config.settings.default_after_clone.clone();
+  let project = new_project(&config, name, url, after_workon, after_clone, override_path);
+  save_new_project(&config, project, url, on_conflict, logger)
+}
 
-    config::write_project(&Project {
-      git: url.to_owned(),
-      name: name.to_owned(),
-      after_clone: after_clone.or(default_after_clone),
-      after_workon: after_workon.or(default_after_workon),
-      override_path,
-      tags: config.settings.default_tags,
-      bare: None,
-      additional_remotes: None,
-      project_config_path: "default".to_string(),
-    })?;
-    Ok(())
+fn new_project(config: &Config, name: &str, url: &str, after_workon: Option<String>, after_clone: Option<String>, override_path: Option<String>) -> Project {
+  let default_after_clone = config.settings.default_after_clone.clone();
+  let default_after_workon = config.settings.default_after_workon.clone();
+
+  Project {
+    git: config.settings.git_url(url),
+    kind: None,
+    name: name.to_owned(),
+    after_clone: after_clone.or(default_after_clone),
+    after_workon: after_workon.or(default_after_workon),
+    shell: None,
+    hook_timeout: None,
+    ssh_key: None,
+    lfs: None,
+    override_path,
+    subdir: None,
+    tags: config.settings.tags_for_new_project(url),
+    bare: None,
+    additional_remotes: None,
+    pin: None,
+    archived: None,
+    archive_bundle: None,
+    depends_on: None,
+    commands: None,
+    clone_depth: None,
+    env: None,
+    project_config_path: "default".to_string(),
+  }
+}
+
+fn save_new_project(config: &Config, project: Project, url: &str, on_conflict: OnConflict, logger: &Logger) -> Result<(), AppError> {
+  let name = project.name.clone();
+  match add_project(&config.all_projects(), project, on_conflict, logger)? {
+    Some(added) if added.name != name => println!("Added {} as {}", url, added.name),
+    Some(_) => (),
+    None => println!("Kept the existing project {}", name),
+  }
+  Ok(())
+}
+
+/// The remote a checkout is added with: `origin`, or else its only remote. The others become additional remotes.
+fn main_remote(mut remotes: Vec<Remote>) -> Result<(Remote, Vec<Remote>), AppError> {
+  let main = match remotes.iter().position(|r| r.name == "origin") {
+    Some(index) => remotes.remove(index),
+    None if remotes.len() == 1 => remotes.remove(0),
+    None if remotes.is_empty() => {
+      return Err(AppError::UserError(
+        "The checkout has no remote, add one or add it as a directory project (kind = \"directory\")".to_owned(),
+      ))
+    }
+    None => {
+      let names: Vec<&str> = remotes.iter().map(|r| r.name.as_str()).collect();
+      return Err(AppError::UserError(format!(
+        "The checkout has several remotes and none is called origin ({}), use fw add <URL>",
+        names.join(", ")
+      )));
+    }
+  };
+  Ok((main, remotes))
+}
+
+/// `fw add .`: adds the git checkout the current directory is in, named like its directory unless `maybe_name` is given.
+/// It keeps its location through `override_path` wherever fw would look for the project somewhere else.
+fn add_current_checkout(
+  config: Config,
+  maybe_name: Option<&str>,
+  after_workon: Option<String>,
+  after_clone: Option<String>,
+  override_path: Option<String>,
+  on_conflict: OnConflict,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let repo = Repository::discover(env::current_dir()?).map_err(|_| AppError::UserError("The current directory is not in a git checkout".to_owned()))?;
+  let checkout = repo
+    .workdir()
+    .ok_or_else(|| AppError::UserError("fw add . needs a checkout, not a bare repository".to_owned()))
+    .and_then(|workdir| Ok(fs::canonicalize(workdir)?))?;
+  let mut remotes = Vec::new();
+  for remote_name in repo.remotes()?.iter().flatten() {
+    if let Some(git) = repo.find_remote(remote_name)?.url() {
+      remotes.push(Remote {
+        name: remote_name.to_owned(),
+        git: git.to_owned(),
+      });
+    }
+  }
+  let (main, others) = main_remote(remotes)?;
+  let name = match maybe_name {
+    Some(name) => name.to_owned(),
+    None => AppError::require(checkout.file_name(), AppError::InternalError("checkout without directory name"))?
+      .to_string_lossy()
+      .into_owned(),
+  };
+  info!(logger, "Prepare new project entry from checkout"; "name" => &name, "url" => &main.git, "path" => checkout.to_string_lossy().to_string());
+  let mut project = new_project(&config, &name, &main.git, after_workon, after_clone, override_path);
+  if !others.is_empty() {
+    project.additional_remotes = Some(others);
   }
+  let expected = config.actual_path_to_project(&project, logger);
+  if project.override_path.is_none() && fs::canonicalize(&expected).ok().as_ref() != Some(&checkout) {
+    debug!(logger, "Checkout is not where fw would look for it"; "expected" => expected.to_string_lossy().to_string());
+    project.override_path = Some(checkout.to_string_lossy().into_owned());
+  }
+  save_new_project(&config, project, &main.git, on_conflict, logger)
 }
 
 pub fn remove_project(maybe_config: Result<Config, AppError>, project_name: &str, purge_directory: bool, logger: &Logger) -> Result<(), AppError> {
@@ -54,23 +159,157 @@ pub fn remove_project(maybe_config: Result<Config, AppError>, project_name: &str
 
   if !config.projects.contains_key(project_name) {
     Err(AppError::UserError(format!("Project key {} does not exist in config", project_name)))
-  } else if let Some(project) = config.projects.get(&project_name.to_owned()).cloned() {
+  } else if let Some(project) = config.projects.get(project_name).cloned() {
     info!(logger, "Updated config"; "config" => format!("{:?}", config));
 
-    if purge_directory {
+    if purge_directory && project.subdir.is_some() {
+      print_warning(&format!("{} shares its clone through subdir, the checkout is left as it is", project_name));
+    } else if purge_directory {
       let path = config.actual_path_to_project(&project, logger);
 
       if path.exists() {
         fs::remove_dir_all(&path)?;
       }
     }
-    config::delete_project_config(&project)
+    config::delete_project_config(&project, logger)
   } else {
     Err(AppError::UserError(format!("Unknown project {}", project_name)))
   }
 }
 
-pub fn add_remote(maybe_config: Result<Config, AppError>, name: &str, remote_name: String, git: String) -> Result<(), AppError> {
+/// HEAD and the number of changed files, which must be the same before and after a checkout is moved.
+fn integrity_of(path: &Path) -> Result<(Option<git2::Oid>, usize), AppError> {
+  let repo = Repository::open(path)?;
+  if repo.is_worktree() || repo.path().join("worktrees").read_dir().is_ok_and(|mut d| d.next().is_some()) {
+    return Err(AppError::UserError(format!(
+      "{} is or has a linked worktree, they point to each other by path, move it yourself",
+      path.to_string_lossy()
+    )));
+  }
+  let head = repo.head().ok().and_then(|head| head.target());
+  let changed = repo.statuses(None)?.len();
+  Ok((head, changed))
+}
+
+// Symlinks are copied as symlinks, files keep their permissions.
+fn copy_tree(from: &Path, to: &Path) -> Result<(), AppError> {
+  for entry in WalkDir::new(from) {
+    let entry = entry.map_err(|e| AppError::UserError(format!("Could not read {}: {}", from.to_string_lossy(), e)))?;
+    let target = to.join(entry.path().strip_prefix(from).expect("walkdir stays below its root"));
+    let file_type = entry.file_type();
+    if file_type.is_dir() {
+      fs::create_dir(&target)?;
+      fs::set_permissions(&target, entry.metadata().map_err(io::Error::from)?.permissions())?;
+    } else if file_type.is_symlink() {
+      let link = fs::read_link(entry.path())?;
+      #[cfg(unix)]
+      std::os::unix::fs::symlink(&link, &target)?;
+      #[cfg(not(unix))]
+      fs::copy(entry.path().parent().unwrap_or(from).join(link), &target)?;
+    } else {
+      fs::copy(entry.path(), &target)?;
+    }
+  }
+  Ok(())
+}
+
+// A rename can't cross filesystems, then the checkout is copied and only removed once the copy looks the same.
+fn copy_checkout(from: &Path, to: &Path, before: &(Option<git2::Oid>, usize)) -> Result<(), AppError> {
+  let copied = copy_tree(from, to).and_then(|_| integrity_of(to));
+  match copied {
+    Ok(after) if after == *before => {
+      fs::remove_dir_all(from)?;
+      Ok(())
+    }
+    copied => {
+      let _ = fs::remove_dir_all(to);
+      Err(match copied {
+        Err(e) => AppError::UserError(format!("Could not copy {} to {}: {}", from.to_string_lossy(), to.to_string_lossy(), e)),
+        Ok(_) => AppError::RuntimeError(format!(
+          "The copy of {} at {} did not look the same and was removed again",
+          from.to_string_lossy(),
+          to.to_string_lossy()
+        )),
+      })
+    }
+  }
+}
+
+/// Moves the checkout at `from` to `to` and checks that HEAD and the changed files are the same afterwards.
+fn move_checkout(from: &Path, to: &Path) -> Result<(), AppError> {
+  let before = integrity_of(from)?;
+  if let Some(parent) = to.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  match fs::rename(from, to) {
+    Ok(()) => (),
+    Err(e) if e.kind() == io::ErrorKind::CrossesDevices => return copy_checkout(from, to, &before),
+    Err(e) => {
+      return Err(AppError::UserError(format!(
+        "Could not move {} to {} ({}), move it yourself",
+        from.to_string_lossy(),
+        to.to_string_lossy(),
+        e
+      )))
+    }
+  }
+  let after = integrity_of(to);
+  if after.as_ref().ok() != Some(&before) {
+    fs::rename(to, from)?;
+    return Err(AppError::RuntimeError(format!(
+      "{} did not look the same after the move and was moved back",
+      from.to_string_lossy()
+    )));
+  }
+  Ok(())
+}
+
+/// Moves the checkout of a project with an `override_path` to where the workspace layout puts it and drops the
+/// `override_path`, the reverse of `fw adopt`.
+pub fn move_to_workspace(maybe_config: Result<Config, AppError>, name: &str, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let project = config.find_project(name, logger)?.clone();
+  if project.override_path.is_none() {
+    return Err(AppError::UserError(format!(
+      "{} has no override_path, it already lives in the workspace",
+      project.name
+    )));
+  }
+  if project.subdir.is_some() {
+    return Err(AppError::UserError(format!(
+      "{} shares its clone through subdir, move the project that owns the clone",
+      project.name
+    )));
+  }
+  let moved_project = Project {
+    override_path: None,
+    ..project.clone()
+  };
+  let from = config.checkout_path(&project, logger);
+  let to = config.checkout_path(&moved_project, logger);
+
+  if !from.exists() {
+    info!(logger, "Nothing to move, the project is not checked out"; "path" => from.to_string_lossy().to_string());
+  } else if fs::canonicalize(&from).ok() != fs::canonicalize(&to).ok() {
+    if to.exists() {
+      return Err(AppError::UserError(format!(
+        "{} already exists, can not move {} there",
+        to.to_string_lossy(),
+        project.name
+      )));
+    }
+    move_checkout(&from, &to)?;
+    println!(
+      "{} {} {}",
+      project.name,
+      Tone::Muted.paint(&from.to_string_lossy()),
+      Tone::Good.paint(&to.to_string_lossy())
+    );
+  }
+  config::write_project(&moved_project, logger)
+}
+
+pub fn add_remote(maybe_config: Result<Config, AppError>, name: &str, remote_name: String, git: String, logger: &Logger) -> Result<(), AppError> {
   let config: Config = maybe_config?;
   if !config.projects.contains_key(name) {
     return Err(AppError::UserError(format!("Project key {} does not exists. Can not update.", name)));
@@ -86,7 +325,7 @@ pub fn add_remote(maybe_config: Result<Config, AppError>, name: &str, remote_nam
   additional_remotes.push(Remote { name: remote_name, git });
   project_config.additional_remotes = Some(additional_remotes);
 
-  config::write_project(&project_config)?;
+  config::write_project(&project_config, logger)?;
   Ok(())
 }
 
@@ -101,7 +340,7 @@ pub fn remove_remote(maybe_config: Result<Config, AppError>, name: &str, remote_
   project_config.additional_remotes = Some(additional_remotes);
 
   debug!(logger, "Updated config"; "config" => format!("{:?}", config));
-  config::write_project(&project_config)?;
+  config::write_project(&project_config, logger)?;
   Ok(())
 }
 
@@ -124,52 +363,92 @@ pub fn update_entry(
   } else if !config.projects.contains_key(name) {
     Err(AppError::UserError(format!("Project key {} does not exists. Can not update.", name)))
   } else {
-    let old_project_config: Project = config.projects.get(name).expect("Already checked in the if above").clone();
-    config::write_project(&Project {
-      git: git.unwrap_or(old_project_config.git),
-      name: old_project_config.name,
-      after_clone: after_clone.or(old_project_config.after_clone),
-      after_workon: after_workon.or(old_project_config.after_workon),
-      override_path: override_path.or(old_project_config.override_path),
-      tags: old_project_config.tags,
-      bare: old_project_config.bare,
-      additional_remotes: old_project_config.additional_remotes,
-      project_config_path: old_project_config.project_config_path,
-    })?;
+    let old_project_config = config.projects.get(name).expect("Already checked in the if above");
+    config::write_project(&updated_project(old_project_config, git, after_workon, after_clone, override_path), logger)?;
     Ok(())
   }
 }
 
-pub fn ls(maybe_config: Result<Config, AppError>, tags: &BTreeSet<String>) -> Result<(), AppError> {
+// Only the given values change, everything else the project has stays as it is.
+fn updated_project(
+  old_project_config: &Project,
+  git: Option<String>,
+  after_workon: Option<String>,
+  after_clone: Option<String>,
+  override_path: Option<String>,
+) -> Project {
+  let old_project_config = old_project_config.clone();
+  Project {
+    git: git.unwrap_or(old_project_config.git),
+    after_clone: after_clone.or(old_project_config.after_clone),
+    after_workon: after_workon.or(old_project_config.after_workon),
+    override_path: override_path.or(old_project_config.override_path),
+    ..old_project_config
+  }
+}
+
+/// Prints the names of the selected projects, one per line. `long` adds aligned path and tag columns,
+/// `recent` lists the most recently worked on projects first.
+pub fn ls(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, long: bool, recent: bool, url: bool, logger: &Logger) -> Result<(), AppError> {
   let config = maybe_config?;
-  for (name, project) in config.projects {
-    if tags.is_empty() || project.tags.unwrap_or_default().intersection(tags).count() > 0 {
-      println!("{}", name)
+  let mut projects = selector.select(&config);
+  if recent {
+    recent::read_history(logger)?.sort(&mut projects);
+  }
+  if !long {
+    for project in projects {
+      println!("{}", if url { config.settings.git_url(&project.git) } else { project.name.clone() })
+    }
+    return Ok(());
+  }
+  let mut table = Table::new();
+  for project in projects {
+    let path = if project.archived.unwrap_or_default() {
+      Cell::new("archived", Tone::Muted)
+    } else {
+      Cell::plain(config.actual_path_to_project(project, logger).to_string_lossy())
+    };
+    let tags: Vec<String> = project.tags.clone().unwrap_or_default().into_iter().collect();
+    let mut row = vec![Cell::new(project.name.clone(), Tone::Heading), path];
+    if url {
+      row.push(Cell::plain(config.settings.git_url(&project.git)));
     }
+    row.push(Cell::new(tags.join(", "), Tone::Muted));
+    table.add_row(row);
   }
+  table.print();
   Ok(())
 }
 
-pub fn print_path(maybe_config: Result<Config, AppError>, name: &str, logger: &Logger) -> Result<(), AppError> {
+/// A single resolved attribute of a project for `fw path`, `fw url` and `fw main-branch`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Attribute {
+  Path,
+  Url,
+  MainBranch,
+}
+
+/// Prints one attribute of the project, resolved like `fw workon` does, without decoration for scripts.
+pub fn print_attribute(maybe_config: Result<Config, AppError>, name: &str, attribute: Attribute, logger: &Logger) -> Result<(), AppError> {
   let config = maybe_config?;
-  let project = config
-    .projects
-    .get(name)
-    .ok_or_else(|| AppError::UserError(format!("project {} not found", name)))?;
-  let canonical_project_path = config.actual_path_to_project(project, logger);
-  let path = canonical_project_path
-    .to_str()
-    .ok_or(AppError::InternalError("project path is not valid unicode"))?;
-  println!("{}", path);
+  let project = config.find_project(name, logger)?;
+  let path = config.actual_path_to_project(project, logger);
+  let value = match attribute {
+    Attribute::Path => path.to_string_lossy().to_string(),
+    Attribute::Url => config.settings.git_url(&project.git),
+    Attribute::MainBranch => {
+      let path = config.checkout_path(project, logger);
+      let repo = Repository::open(&path).map_err(|_| AppError::UserError(format!("project {} is not cloned at {}", name, path.to_string_lossy())))?;
+      default_branch(&repo).ok_or_else(|| AppError::UserError(format!("origin of project {} has no default branch", name)))?
+    }
+  };
+  println!("{}", value);
   Ok(())
 }
 
-pub fn inspect(name: &str, maybe_config: Result<Config, AppError>, json: bool, logger: &Logger) -> Result<(), AppError> {
+pub fn inspect(name: &str, maybe_config: Result<Config, AppError>, json: bool, explain: bool, logger: &Logger) -> Result<(), AppError> {
   let config = maybe_config?;
-  let project = config
-    .projects
-    .get(name)
-    .ok_or_else(|| AppError::UserError(format!("project {} not found", name)))?;
+  let project = config.find_project(name, logger)?;
   if json {
     println!("{}", serde_json::to_string(project)?);
     return Ok(());
@@ -178,7 +457,7 @@ pub fn inspect(name: &str, maybe_config: Result<Config, AppError>, json: bool, l
   let path = canonical_project_path
     .to_str()
     .ok_or(AppError::InternalError("project path is not valid unicode"))?;
-  println!("{}", Style::new().underline().bold().paint(project.name.clone()));
+  println!("{}", Tone::Heading.paint(&project.name));
   println!("{:<20}: {}", "Path", path);
   println!("{:<20}: {}", "config path", project.project_config_path);
   let tags = project
@@ -190,6 +469,22 @@ pub fn inspect(name: &str, maybe_config: Result<Config, AppError>, json: bool, l
     })
     .unwrap_or_else(|| "None".to_owned());
   println!("{:<20}: {}", "Tags", tags);
+  let conditional_tags: Vec<String> = config
+    .effective_tags(project, logger)
+    .into_iter()
+    .filter(|t| !project.tags.iter().flatten().any(|own| own == t))
+    .collect();
+  if !conditional_tags.is_empty() {
+    println!("{:<20}: {}", "Conditional tags", conditional_tags.join(", "));
+  }
+  let project_types: Vec<String> = detect::project_types(&canonical_project_path).into_iter().collect();
+  if !project_types.is_empty() {
+    println!("{:<20}: {}", "Project types", project_types.join(", "));
+  }
+  println!("{:<20}: {}", "Shell", config.resolve_shell(project, logger).join(" "));
+  if let Some(ssh_key) = config.resolve_ssh_key(project, logger) {
+    println!("{:<20}: {}", "SSH key", ssh_key.to_string_lossy());
+  }
   let additional_remotes = project
     .additional_remotes
     .clone()
@@ -199,7 +494,169 @@ pub fn inspect(name: &str, maybe_config: Result<Config, AppError>, json: bool, l
     })
     .unwrap_or_else(|| "None".to_owned());
   println!("{:<20}: {}", "Additional remotes", additional_remotes);
-  let git = project.git.clone();
-  println!("{:<20}: {}", "Git", git);
+  println!("{:<20}: {}", "Git", config.settings.git_url(&project.git));
+  if explain {
+    print_explanation(&config, project, logger);
+  }
   Ok(())
 }
+
+// Every layer that sets a field gets a row, the ones whose value is overridden are muted.
+fn print_explanation(config: &Config, project: &Project, logger: &Logger) {
+  println!();
+  let mut table = Table::new();
+  for explanation in config.explain(project, logger) {
+    let value = explanation.value.clone().unwrap_or_else(|| "None".to_owned());
+    if explanation.contributions.is_empty() {
+      table.add_row(vec![
+        Cell::new(explanation.field, Tone::Heading),
+        Cell::new(value, Tone::Muted),
+        Cell::new("default", Tone::Muted),
+      ]);
+      continue;
+    }
+    let note = match &explanation.note {
+      Some(note) => Cell::new(note.clone(), Tone::Warning),
+      None => Cell::plain(""),
+    };
+    table.add_row(vec![Cell::new(explanation.field, Tone::Heading), Cell::plain(value), note]);
+    for (contribution, used) in explanation.contributions.iter().rev() {
+      let tone = if *used { Tone::Good } else { Tone::Muted };
+      table.add_row(vec![
+        Cell::plain(""),
+        Cell::new(contribution.value.clone(), tone),
+        Cell::new(contribution.layer.to_string(), tone),
+      ]);
+    }
+  }
+  table.print();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::format::ConfigFormat;
+  use crate::config::project::ProjectKind;
+  use crate::testing::{commit_file, temp_dir};
+  use spectral::prelude::*;
+
+  fn a_remote(name: &str) -> Remote {
+    Remote {
+      name: name.to_owned(),
+      git: format!("git@github.com:{}/fw.git", name),
+    }
+  }
+
+  #[test]
+  fn test_main_remote_prefers_origin() {
+    let (main, others) = main_remote(vec![a_remote("upstream"), a_remote("origin")]).unwrap();
+    assert_that(&main.name.as_str()).is_equal_to("origin");
+    assert_that(&others.iter().map(|r| r.name.as_str()).collect::<Vec<_>>()).is_equal_to(vec!["upstream"]);
+
+    let (main, others) = main_remote(vec![a_remote("fork")]).unwrap();
+    assert_that(&main.name.as_str()).is_equal_to("fork");
+    assert_that(&others).is_empty();
+
+    assert_that(&main_remote(vec![a_remote("fork"), a_remote("upstream")]).is_err()).is_true();
+    assert_that(&main_remote(vec![]).is_err()).is_true();
+  }
+
+  fn a_checkout(dir: &Path) -> (Option<git2::Oid>, usize) {
+    let repo = Repository::init(dir).unwrap();
+    commit_file(&repo, "tracked", "committed");
+    fs::write(dir.join("tracked"), "changed").unwrap();
+    fs::write(dir.join("untracked"), "new").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("tracked", dir.join("link")).unwrap();
+    integrity_of(dir).unwrap()
+  }
+
+  #[test]
+  fn test_move_checkout() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let (from, to) = (dir.join("elsewhere/fw"), dir.join("workspace/brocode/fw"));
+    let before = a_checkout(&from);
+
+    move_checkout(&from, &to).unwrap();
+    assert_that(&from.exists()).is_false();
+    assert_that(&integrity_of(&to).unwrap()).is_equal_to(before);
+  }
+
+  #[test]
+  fn test_copy_checkout_across_filesystems() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let (from, to) = (dir.join("elsewhere/fw"), dir.join("fw"));
+    let before = a_checkout(&from);
+
+    copy_checkout(&from, &to, &before).unwrap();
+    assert_that(&from.exists()).is_false();
+    assert_that(&integrity_of(&to).unwrap()).is_equal_to(before);
+    assert_that(&fs::read_to_string(to.join("untracked")).unwrap()).is_equal_to("new".to_owned());
+    #[cfg(unix)]
+    assert_that(&fs::read_link(to.join("link")).unwrap()).is_equal_to(Path::new("tracked").to_path_buf());
+
+    // a copy that doesn't match is removed and the original stays
+    let (from, to) = (to, dir.join("copy"));
+    copy_checkout(&from, &to, &(None, 0)).unwrap_err();
+    assert_that(&to.exists()).is_false();
+    assert_that(&from.exists()).is_true();
+  }
+
+  #[test]
+  fn test_new_project_gets_both_default_hooks() {
+    let config = Config {
+      projects: std::collections::BTreeMap::new(),
+      settings: serde_json::from_value::<config::settings::Settings>(serde_json::json!({
+        "workspace": "/workspace",
+        "default_after_clone": "make setup",
+        "default_after_workon": "nvm use",
+      }))
+      .unwrap(),
+      outside_profile: std::collections::BTreeMap::new(),
+    };
+    let project = new_project(&config, "fw", "git@github.com:brocode/fw.git", None, None, None);
+    assert_that(&project.after_clone).is_equal_to(Some("make setup".to_owned()));
+    assert_that(&project.after_workon).is_equal_to(Some("nvm use".to_owned()));
+
+    let project = new_project(&config, "fw", "git@github.com:brocode/fw.git", Some("code .".to_owned()), None, None);
+    assert_that(&project.after_clone).is_equal_to(Some("make setup".to_owned()));
+    assert_that(&project.after_workon).is_equal_to(Some("code .".to_owned()));
+  }
+
+  #[test]
+  fn test_update_keeps_directory_projects() {
+    let notes = Project {
+      name: "notes".to_owned(),
+      git: String::new(),
+      kind: Some(ProjectKind::Directory),
+      ..Project::example()
+    };
+    let updated = updated_project(&notes, None, Some("x".to_owned()), None, None);
+    let written = ConfigFormat::Toml.serialize(&updated).unwrap();
+    let read: Project = ConfigFormat::Toml.parse(&written).unwrap();
+    assert_that(&read.is_directory()).is_true();
+    assert_that(&read.git.as_str()).is_equal_to("");
+    assert_that(&read.after_workon).is_equal_to(Some("x".to_owned()));
+    assert_that(&read.after_clone).is_equal_to(notes.after_clone);
+  }
+
+  #[test]
+  fn test_update_keeps_subdir_and_per_project_settings() {
+    let api = Project {
+      subdir: Some("services/api".to_owned()),
+      hook_timeout: Some(30),
+      ssh_key: Some("~/.ssh/work".to_owned()),
+      lfs: Some(true),
+      ..Project::example()
+    };
+    let updated = updated_project(&api, Some("git@github.com:brocode/mono.git".to_owned()), None, None, None);
+    assert_that(&updated.git.as_str()).is_equal_to("git@github.com:brocode/mono.git");
+    assert_that(&updated.subdir).is_equal_to(api.subdir);
+    assert_that(&updated.shell).is_equal_to(api.shell);
+    assert_that(&updated.hook_timeout).is_equal_to(Some(30));
+    assert_that(&updated.ssh_key).is_equal_to(api.ssh_key);
+    assert_that(&updated.lfs).is_equal_to(Some(true));
+  }
+}
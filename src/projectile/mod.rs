@@ -7,15 +7,15 @@ use std::borrow::ToOwned;
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub fn projectile(maybe_config: Result<Config, AppError>, logger: &Logger) -> Result<(), AppError> {
   let config: Config = maybe_config?;
   let projects_paths: Vec<PathBuf> = config
     .clone()
     .projects
-    .into_iter()
-    .map(|(_, p)| config.actual_path_to_project(&p, logger))
+    .into_values()
+    .map(|p| config.actual_path_to_project(&p, logger))
     .collect();
   let home_dir: PathBuf = dirs::home_dir().ok_or_else(|| AppError::UserError("$HOME not set".to_owned()))?;
   let mut projectile_bookmarks: PathBuf = home_dir.clone();
@@ -25,7 +25,7 @@ pub fn projectile(maybe_config: Result<Config, AppError>, logger: &Logger) -> Re
   persist(logger, &home_dir, writer, projects_paths)
 }
 
-fn persist<W>(logger: &Logger, home_dir: &PathBuf, writer: W, paths: Vec<PathBuf>) -> Result<(), AppError>
+fn persist<W>(logger: &Logger, home_dir: &Path, writer: W, paths: Vec<PathBuf>) -> Result<(), AppError>
 where
   W: io::Write,
 {
@@ -33,7 +33,7 @@ where
   let mut buffer = io::BufWriter::new(writer);
   buffer.write_all(b"(")?;
   for path in paths {
-    let path = replace_path_with_tilde(&path, home_dir.clone()).unwrap_or(path);
+    let path = replace_path_with_tilde(&path, home_dir.to_path_buf()).unwrap_or(path);
     debug!(logger, "Writing projectile entry"; "entry" => &path);
     buffer.write_all(format!("\"{}/\"", path).as_bytes())?;
     buffer.write_all(b" ")?;
@@ -55,7 +55,6 @@ mod tests {
   use super::*;
   use slog::o;
   use spectral::prelude::*;
-  use std::path::Path;
 
   #[test]
   fn test_persists_projectile_config() {
@@ -0,0 +1,253 @@
+use crate::config::{project::Project, selector::ProjectSelector, template, Config};
+use crate::errors::AppError;
+use crate::git::{default_branch, push_branch};
+use crate::output::{Cell, Table, Tone};
+use crate::pr::{self, PullRequestOptions};
+use git2::{BranchType, Commit, IndexEntry, IndexTime, ObjectType, Oid, Repository};
+use slog::{debug, o, Logger};
+use std::path::{Component, Path};
+use std::time::Duration;
+
+/// How `fw propagate` commits the file in every project.
+#[derive(Debug, Clone)]
+pub struct PropagateOptions {
+  /// The path of the file in every project, relative to its root.
+  pub dest: String,
+  pub branch: String,
+  /// The commit message, also the title of the pull requests. By default `Update <dest>`.
+  pub message: Option<String>,
+  pub push: bool,
+  /// Opens a pull or merge request for the branch, which pushes it.
+  pub pr: bool,
+  pub dry_run: bool,
+  pub timeout: Option<Duration>,
+}
+
+impl PropagateOptions {
+  fn message(&self) -> String {
+    self.message.clone().unwrap_or_else(|| format!("Update {}", self.dest))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+  /// The base branch already has the file as it is.
+  UpToDate,
+  /// The branch already exists with the file as it is, e.g. from an earlier run that could not push.
+  AlreadyOnBranch(Oid),
+  Committed(Oid),
+  WouldCommit,
+}
+
+// Only plain relative paths, the file must end up inside every project.
+fn check_dest(dest: &str) -> Result<(), AppError> {
+  let path = Path::new(dest);
+  if dest.is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+    Err(AppError::UserError(format!(
+      "The destination must be a path inside the projects, was '{}'",
+      dest
+    )))
+  } else {
+    Ok(())
+  }
+}
+
+/// The file for `project`: the placeholders `{{project.name}}`, `{{project.git}}` and `{{tags}}` are replaced in text
+/// files, anything else like `${{ secrets.TOKEN }}` in a workflow stays as it is.
+fn render(config: &Config, project: &Project, source: &[u8], logger: &Logger) -> Vec<u8> {
+  match std::str::from_utf8(source) {
+    Ok(text) => template::expand_placeholders(text, |name| match name {
+      "project.name" => Some(project.name.clone()),
+      "project.git" => Some(project.git.clone()),
+      "tags" => Some(config.effective_tags(project, logger).into_iter().collect::<Vec<String>>().join(" ")),
+      _ => None,
+    })
+    .into_bytes(),
+    Err(_) => source.to_vec(),
+  }
+}
+
+// origin's default branch if there is one, else HEAD.
+fn base_of(repo: &Repository) -> Result<(Option<String>, Commit<'_>), AppError> {
+  if let Some(base) = default_branch(repo) {
+    if let Ok(remote) = repo.find_branch(&format!("origin/{}", base), BranchType::Remote) {
+      return Ok((Some(base), remote.get().peel_to_commit()?));
+    }
+  }
+  Ok((None, repo.head()?.peel_to_commit()?))
+}
+
+fn has_file(commit: &Commit<'_>, dest: &str, blob: Oid) -> Result<bool, AppError> {
+  Ok(commit.tree()?.get_path(Path::new(dest)).map(|entry| entry.id() == blob).unwrap_or(false))
+}
+
+// Commits `content` as `dest` on top of `base` to the new branch `options.branch`, without touching the working tree.
+fn propagate_in(repo: &Repository, base: &Commit<'_>, content: &[u8], options: &PropagateOptions) -> Result<Outcome, AppError> {
+  let blob = Oid::hash_object(ObjectType::Blob, content)?;
+  if let Ok(existing) = repo.find_branch(&options.branch, BranchType::Local) {
+    let tip = existing.get().peel_to_commit()?;
+    return if has_file(&tip, &options.dest, blob)? {
+      Ok(Outcome::AlreadyOnBranch(tip.id()))
+    } else {
+      Err(AppError::UserError(format!(
+        "branch {} already exists, delete it or use another --branch",
+        options.branch
+      )))
+    };
+  }
+  if has_file(base, &options.dest, blob)? {
+    return Ok(Outcome::UpToDate);
+  }
+  if options.dry_run {
+    return Ok(Outcome::WouldCommit);
+  }
+
+  let base_tree = base.tree()?;
+  // an executable script stays executable
+  let mode = base_tree.get_path(Path::new(&options.dest)).map(|e| e.filemode() as u32).unwrap_or(0o100_644);
+  let mut index = git2::Index::new()?;
+  index.read_tree(&base_tree)?;
+  index.add(&IndexEntry {
+    ctime: IndexTime::new(0, 0),
+    mtime: IndexTime::new(0, 0),
+    dev: 0,
+    ino: 0,
+    mode,
+    uid: 0,
+    gid: 0,
+    file_size: content.len() as u32,
+    id: repo.blob(content)?,
+    flags: 0,
+    flags_extended: 0,
+    path: options.dest.as_bytes().to_vec(),
+  })?;
+  let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+  let author = repo
+    .signature()
+    .map_err(|e| AppError::UserError(format!("No author, set user.name and user.email in git: {}", e.message())))?;
+  let commit = repo.commit(None, &author, &author, &options.message(), &tree, &[base])?;
+  repo.branch(&options.branch, &repo.find_commit(commit)?, false)?;
+  Ok(Outcome::Committed(commit))
+}
+
+fn propagate_project(config: &Config, project: &Project, source: &[u8], options: &PropagateOptions, logger: &Logger) -> Result<(String, Tone), AppError> {
+  let repo = Repository::open(config.checkout_path(project, logger))?;
+  let (base_name, base) = base_of(&repo)?;
+  let content = render(config, project, source, logger);
+  let commit = match propagate_in(&repo, &base, &content, options)? {
+    Outcome::UpToDate => return Ok(("up to date".to_owned(), Tone::Muted)),
+    Outcome::WouldCommit => return Ok((format!("would commit {} to {}", options.dest, options.branch), Tone::Plain)),
+    Outcome::AlreadyOnBranch(commit) if options.dry_run => return Ok((format!("already on {} at {}", options.branch, &commit.to_string()[..8]), Tone::Muted)),
+    Outcome::AlreadyOnBranch(commit) | Outcome::Committed(commit) => commit,
+  };
+  debug!(logger, "Propagated"; "branch" => &options.branch, "commit" => commit.to_string());
+  let message = format!("committed {} to {}", &commit.to_string()[..8], options.branch);
+  if options.pr {
+    let pull_request = PullRequestOptions {
+      title: options.message(),
+      body: String::new(),
+      branch: Some(options.branch.clone()),
+      base: base_name,
+      dry_run: false,
+      timeout: options.timeout,
+    };
+    pr::create_for(config, project, &pull_request, logger)
+  } else if options.push {
+    push_branch(project, &repo, &options.branch, logger)?;
+    Ok((format!("{}, pushed", message), Tone::Good))
+  } else {
+    Ok((message, Tone::Good))
+  }
+}
+
+/// Commits the file `source` as `options.dest` to a new branch in every selected project that is checked out, with the
+/// project placeholders replaced, and optionally pushes the branch or opens a pull request for it.
+pub fn propagate(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  source: &Path,
+  options: &PropagateOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  check_dest(&options.dest)?;
+  let source = std::fs::read(source).map_err(|e| AppError::UserError(format!("Could not read {}: {}", source.to_string_lossy(), e)))?;
+  let mut table = Table::new();
+  let mut failed = 0;
+  for project in selector.select_repositories(&config) {
+    if !config.checkout_path(project, logger).exists() {
+      debug!(logger, "Skipping, not checked out"; "project" => &project.name);
+      continue;
+    }
+    let project_logger = logger.new(o!("project" => project.name.clone()));
+    let (message, tone) = match propagate_project(&config, project, &source, options, &project_logger) {
+      Ok(result) => result,
+      Err(AppError::Interrupted) => return Err(AppError::Interrupted),
+      Err(AppError::UserError(message)) => {
+        failed += 1;
+        (message, Tone::Bad)
+      }
+      Err(e) => {
+        failed += 1;
+        (format!("{}", e), Tone::Bad)
+      }
+    };
+    table.add_row(vec![Cell::plain(project.name.clone()), Cell::new(message, tone)]);
+  }
+  table.print();
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::test_repo;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_propagate_in() {
+    let (_dir, repo) = test_repo();
+    repo.config().unwrap().set_str("user.name", "fw").unwrap();
+    repo.config().unwrap().set_str("user.email", "fw@example.com").unwrap();
+    let base = repo.head().unwrap().peel_to_commit().unwrap();
+    let first = base.id();
+    let options = PropagateOptions {
+      dest: ".github/workflows/ci.yml".to_owned(),
+      branch: "ci".to_owned(),
+      message: None,
+      push: false,
+      pr: false,
+      dry_run: false,
+      timeout: None,
+    };
+
+    let commit = match propagate_in(&repo, &base, b"on: push\n", &options).unwrap() {
+      Outcome::Committed(commit) => repo.find_commit(commit).unwrap(),
+      other => panic!("not committed: {:?}", other),
+    };
+    assert_that(&commit.message()).is_equal_to(Some("Update .github/workflows/ci.yml"));
+    assert_that(&repo.find_branch("ci", BranchType::Local).unwrap().get().target()).is_equal_to(Some(commit.id()));
+    assert_that(&repo.head().unwrap().target()).is_equal_to(Some(first));
+    assert_that(&propagate_in(&repo, &base, b"on: push\n", &options).unwrap()).is_equal_to(Outcome::AlreadyOnBranch(commit.id()));
+    assert_that(&propagate_in(&repo, &base, b"on: pull_request\n", &options).is_err()).is_true();
+    assert_that(
+      &propagate_in(
+        &repo,
+        &commit,
+        b"on: push\n",
+        &PropagateOptions {
+          branch: "other".to_owned(),
+          ..options.clone()
+        },
+      )
+      .unwrap(),
+    )
+    .is_equal_to(Outcome::UpToDate);
+    assert_that(&check_dest("../ci.yml").is_err()).is_true();
+    assert_that(&check_dest("/etc/ci.yml").is_err()).is_true();
+  }
+}
@@ -0,0 +1,260 @@
+use crate::config::{project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git::{is_dirty, update_lfs, update_project_remotes, with_retries, RetryPolicy};
+use crate::lock::{self, LockMode};
+use crate::output::{Cell, Table, Tone};
+use crate::signal;
+use crate::stash;
+use git2::build::CheckoutBuilder;
+use git2::{AnnotatedCommit, Branch, ErrorCode, RebaseOptions, Repository, Signature};
+use slog::{debug, o, Logger};
+
+/// How `fw pull` brings the checked out branches up to date.
+#[derive(Debug, Clone, Default)]
+pub struct PullOptions {
+  /// Rebase branches that diverged from their upstream instead of leaving them alone.
+  pub rebase: bool,
+  /// Stash uncommitted changes before and pop them after, instead of skipping dirty projects.
+  pub autostash: bool,
+  pub lock_mode: LockMode,
+}
+
+/// What `fw pull` did with a project.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pulled {
+  /// Left alone, for the given reason.
+  Skipped(String),
+  UpToDate,
+  FastForwarded(usize),
+  Rebased(usize),
+}
+
+fn fast_forward(repo: &Repository, upstream: &AnnotatedCommit<'_>) -> Result<(), AppError> {
+  repo.checkout_tree(&repo.find_object(upstream.id(), None)?, Some(CheckoutBuilder::new().safe()))?;
+  repo.head()?.set_target(upstream.id(), "fw pull: fast-forward")?;
+  Ok(())
+}
+
+fn conflicted_paths(repo: &Repository) -> Result<Vec<String>, AppError> {
+  let mut paths = Vec::new();
+  for conflict in repo.index()?.conflicts()? {
+    let conflict = conflict?;
+    if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+      paths.push(String::from_utf8_lossy(&entry.path).to_string());
+    }
+  }
+  Ok(paths)
+}
+
+// A conflict aborts the rebase, so a project is either rebased completely or left as it was.
+fn rebase(repo: &Repository, branch: &AnnotatedCommit<'_>, upstream: &AnnotatedCommit<'_>) -> Result<(), AppError> {
+  let signature = repo.signature().or_else(|_| Signature::now("fw", "fw@localhost"))?;
+  let mut rebase = repo.rebase(Some(branch), Some(upstream), None, Some(&mut RebaseOptions::new()))?;
+  while let Some(operation) = rebase.next() {
+    if let Err(e) = operation {
+      rebase.abort()?;
+      return Err(AppError::GitError(e));
+    }
+    if repo.index()?.has_conflicts() {
+      let paths = conflicted_paths(repo)?;
+      rebase.abort()?;
+      return Err(AppError::UserError(format!("rebase conflicts in {}, left unchanged", paths.join(", "))));
+    }
+    match rebase.commit(None, &signature, None) {
+      Ok(_) => {}
+      // the change is already upstream
+      Err(e) if e.code() == ErrorCode::Applied => {}
+      Err(e) => {
+        rebase.abort()?;
+        return Err(AppError::GitError(e));
+      }
+    }
+  }
+  rebase.finish(Some(&signature))?;
+  Ok(())
+}
+
+fn update_branch(repo: &Repository, options: &PullOptions, logger: &Logger) -> Result<Pulled, AppError> {
+  let head = Branch::wrap(repo.head()?);
+  let upstream = match head.upstream() {
+    Ok(upstream) => upstream,
+    Err(_) => return Ok(Pulled::Skipped("the checked out branch has no upstream".to_owned())),
+  };
+  let head_commit = repo.reference_to_annotated_commit(head.get())?;
+  let upstream_commit = repo.reference_to_annotated_commit(upstream.get())?;
+  let (ahead, behind) = repo.graph_ahead_behind(head_commit.id(), upstream_commit.id())?;
+  debug!(logger, "Compared with upstream"; "ahead" => ahead, "behind" => behind);
+  match (ahead, behind) {
+    (_, 0) => Ok(Pulled::UpToDate),
+    (0, behind) => fast_forward(repo, &upstream_commit).map(|_| Pulled::FastForwarded(behind)),
+    (_, behind) if options.rebase => rebase(repo, &head_commit, &upstream_commit).map(|_| Pulled::Rebased(behind)),
+    (ahead, behind) => Err(AppError::UserError(format!("diverged from upstream (+{} -{}), use --rebase", ahead, behind))),
+  }
+}
+
+fn describe(pulled: &Pulled) -> (String, Tone) {
+  match pulled {
+    Pulled::Skipped(reason) => (format!("skipped, {}", reason), Tone::Muted),
+    Pulled::UpToDate => ("up to date".to_owned(), Tone::Muted),
+    Pulled::FastForwarded(n) => (format!("fast-forwarded {} commit(s)", n), Tone::Good),
+    Pulled::Rebased(n) => (format!("rebased onto {} new commit(s)", n), Tone::Good),
+  }
+}
+
+// `lfs` if the project or one sharing its clone through `subdir` wants the LFS objects.
+fn pull_project(config: &Config, project: &Project, lfs: bool, options: &PullOptions, logger: &Logger) -> Result<Pulled, AppError> {
+  if project.pin.is_some() {
+    return Ok(Pulled::Skipped("pinned".to_owned()));
+  }
+  let _lock = match lock::lock_project(&project.name, options.lock_mode, logger)? {
+    Some(lock) => lock,
+    None => return Ok(Pulled::Skipped("locked by another fw".to_owned())),
+  };
+  let path = config.checkout_path(project, logger);
+  let mut repo = Repository::open(&path)?;
+  if !repo.head()?.is_branch() {
+    return Ok(Pulled::Skipped("HEAD is detached".to_owned()));
+  }
+  let stashed = if is_dirty(&repo)? {
+    if !options.autostash {
+      return Err(AppError::UserError("has uncommitted changes, use --autostash".to_owned()));
+    }
+    stash::stash(&mut repo, false)?
+  } else {
+    None
+  };
+
+  let policy = RetryPolicy::from_settings(&config.settings);
+  let ssh_key = config.resolve_ssh_key(project, logger);
+  let (fetched, _) = with_retries(&policy, logger, || update_project_remotes(project, &path, ssh_key.as_deref(), logger, false));
+  let result = fetched.and_then(|_| update_branch(&repo, options, logger)).and_then(|pulled| {
+    if lfs {
+      update_lfs(&path, false, false, logger)?;
+    }
+    Ok(pulled)
+  });
+
+  // the changes come back whether the pull worked or not
+  if let Some(oid) = stashed {
+    if let Err(e) = stash::unstash(&mut repo, oid) {
+      let pulled = result.map(|p| describe(&p).0).unwrap_or_else(|e| format!("{}", e));
+      return Err(AppError::UserError(format!(
+        "{}, but popping the autostash failed, it is kept in git stash: {}",
+        pulled, e
+      )));
+    }
+  }
+  result
+}
+
+/// Fetches the selected projects and fast-forwards (or with `rebase` rebases) their checked out branches.
+/// A failing project is reported and does not stop the others.
+pub fn pull(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, options: &PullOptions, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut table = Table::new();
+  let mut failed = 0;
+  let git_projects = selector.select_git_projects(&config);
+  for project in selector.select_repositories(&config) {
+    signal::check_interrupted()?;
+    let path = config.checkout_path(project, logger);
+    if !path.exists() {
+      continue;
+    }
+    let project_logger = logger.new(o!("project" => project.name.clone()));
+    let lfs = git_projects
+      .iter()
+      .filter(|p| config.checkout_path(p, logger) == path)
+      .any(|p| config.resolve_lfs(p, logger));
+    let (message, tone) = match pull_project(&config, project, lfs, options, &project_logger) {
+      Ok(pulled) => describe(&pulled),
+      Err(AppError::Interrupted) => return Err(AppError::Interrupted),
+      Err(e) => {
+        failed += 1;
+        match e {
+          AppError::UserError(message) => (message, Tone::Bad),
+          other => (format!("{}", other), Tone::Bad),
+        }
+      }
+    };
+    table.add_row(vec![Cell::plain(project.name.clone()), Cell::new(message, tone)]);
+  }
+  table.print();
+  if failed > 0 {
+    Err(AppError::UserError(format!("Could not pull {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{commit_file, temp_dir};
+  use spectral::prelude::*;
+  use std::fs;
+  use tempfile::TempDir;
+
+  fn a_logger() -> Logger {
+    Logger::root(slog::Discard, o!())
+  }
+
+  // `work` tracks the local branch `base`, which plays the part of the fetched upstream
+  fn a_repo() -> (TempDir, Repository) {
+    let dir = temp_dir();
+    let repo = Repository::init(dir.path()).unwrap();
+    commit_file(&repo, "a", "a");
+    {
+      let head = repo.head().unwrap().peel_to_commit().unwrap();
+      repo.branch("base", &head, false).unwrap();
+      let mut work = repo.branch("work", &head, false).unwrap();
+      work.set_upstream(Some("base")).unwrap();
+    }
+    repo.set_head("refs/heads/base").unwrap();
+    (dir, repo)
+  }
+
+  fn switch(repo: &Repository, branch: &str) {
+    repo.set_head(&format!("refs/heads/{}", branch)).unwrap();
+    repo.checkout_head(Some(CheckoutBuilder::new().force())).unwrap();
+  }
+
+  #[test]
+  fn test_fast_forward_and_rebase() {
+    let logger = a_logger();
+    let (_dir, repo) = a_repo();
+    commit_file(&repo, "b", "upstream");
+    switch(&repo, "work");
+    let options = PullOptions::default();
+    assert_that(&update_branch(&repo, &options, &logger).unwrap()).is_equal_to(Pulled::FastForwarded(1));
+    assert_that(&update_branch(&repo, &options, &logger).unwrap()).is_equal_to(Pulled::UpToDate);
+
+    commit_file(&repo, "c", "local");
+    switch(&repo, "base");
+    commit_file(&repo, "d", "upstream");
+    switch(&repo, "work");
+    assert_that(&update_branch(&repo, &options, &logger).is_err()).is_true();
+    let rebase = PullOptions { rebase: true, ..options };
+    assert_that(&update_branch(&repo, &rebase, &logger).unwrap()).is_equal_to(Pulled::Rebased(1));
+    assert_that(&repo.workdir().unwrap().join("d").exists()).is_true();
+    assert_that(&update_branch(&repo, &rebase, &logger).unwrap()).is_equal_to(Pulled::UpToDate);
+  }
+
+  #[test]
+  fn test_conflicting_rebase_leaves_branch_alone() {
+    let logger = a_logger();
+    let (_dir, repo) = a_repo();
+    commit_file(&repo, "a", "upstream");
+    switch(&repo, "work");
+    commit_file(&repo, "a", "local");
+    let before = repo.head().unwrap().target();
+
+    let rebase = PullOptions {
+      rebase: true,
+      ..PullOptions::default()
+    };
+    let error = format!("{}", update_branch(&repo, &rebase, &logger).unwrap_err());
+    assert_that(&error).contains("rebase conflicts in a");
+    assert_that(&repo.head().unwrap().target()).is_equal_to(before);
+    assert_that(&fs::read_to_string(repo.workdir().unwrap().join("a")).unwrap()).is_equal_to("local".to_owned());
+  }
+}
@@ -0,0 +1,418 @@
+//! `fw query`, a small filter and projection language over the resolved projects and the settings:
+//!
+//! ```text
+//! query      = ("projects" filter* | "settings") ("." field)*
+//! filter     = "[" or "]"
+//! or         = and ("or" and)*
+//! and        = unary ("and" unary)*
+//! unary      = "not" unary | "(" or ")" | comparison
+//! comparison = field ("." field)* [("==" | "!=" | "contains" | "matches") literal]
+//! literal    = "string" | 'string' | number | true | false | null
+//! ```
+//!
+//! Projects have the fields plugins get, see `PluginProject`.
+use crate::config::{settings::without_tokens, settings::Settings, Config};
+use crate::errors::AppError;
+use crate::plugin::PluginProject;
+use regex::Regex;
+use serde_json::Value;
+use slog::Logger;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Ident(String),
+  Literal(Value),
+  Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 7] = ["==", "!=", "[", "]", "(", ")", "."];
+
+fn invalid(message: String) -> AppError {
+  AppError::UserError(format!("Invalid query: {}", message))
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, AppError> {
+  let mut tokens = Vec::new();
+  let mut chars = input.char_indices().peekable();
+  while let Some(&(i, c)) = chars.peek() {
+    if c.is_whitespace() {
+      chars.next();
+    } else if c == '"' || c == '\'' {
+      chars.next();
+      let mut string = String::new();
+      loop {
+        match chars.next() {
+          Some((_, '\\')) => match chars.next() {
+            Some((_, escaped)) => string.push(escaped),
+            None => return Err(invalid(format!("unterminated string at {}", i))),
+          },
+          Some((_, quote)) if quote == c => break,
+          Some((_, other)) => string.push(other),
+          None => return Err(invalid(format!("unterminated string at {}", i))),
+        }
+      }
+      tokens.push(Token::Literal(Value::String(string)));
+    } else if c.is_ascii_digit() || c == '-' {
+      let mut number = String::new();
+      while let Some((_, digit)) = chars.next_if(|(_, d)| d.is_ascii_digit() || *d == '-' || *d == '.') {
+        number.push(digit);
+      }
+      let value = number.parse::<i64>().map(Value::from).or_else(|_| number.parse::<f64>().map(Value::from));
+      tokens.push(Token::Literal(value.map_err(|_| invalid(format!("bad number {} at {}", number, i)))?));
+    } else if c.is_alphabetic() || c == '_' {
+      let mut ident = String::new();
+      while let Some((_, letter)) = chars.next_if(|(_, l)| l.is_alphanumeric() || *l == '_') {
+        ident.push(letter);
+      }
+      tokens.push(match ident.as_str() {
+        "true" => Token::Literal(Value::Bool(true)),
+        "false" => Token::Literal(Value::Bool(false)),
+        "null" => Token::Literal(Value::Null),
+        _ => Token::Ident(ident),
+      });
+    } else {
+      let symbol = SYMBOLS
+        .iter()
+        .find(|symbol| input[i..].starts_with(**symbol))
+        .ok_or_else(|| invalid(format!("unexpected {} at {}", c, i)))?;
+      for _ in 0..symbol.len() {
+        chars.next();
+      }
+      tokens.push(Token::Symbol(symbol));
+    }
+  }
+  Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Op {
+  Eq,
+  Ne,
+  Contains,
+  Matches(Regex),
+}
+
+#[derive(Debug)]
+enum Filter {
+  Or(Box<Filter>, Box<Filter>),
+  And(Box<Filter>, Box<Filter>),
+  Not(Box<Filter>),
+  /// A field that is neither missing, null, false nor empty.
+  Truthy(Vec<String>),
+  Compare(Vec<String>, Op, Value),
+}
+
+#[derive(Debug, PartialEq)]
+enum Root {
+  Projects,
+  Settings,
+}
+
+#[derive(Debug)]
+pub struct Query {
+  root: Root,
+  filters: Vec<Filter>,
+  projection: Vec<String>,
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.position).cloned();
+    self.position += 1;
+    token
+  }
+
+  fn eat_symbol(&mut self, symbol: &str) -> bool {
+    if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+      self.position += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn eat_keyword(&mut self, keyword: &str) -> bool {
+    if matches!(self.peek(), Some(Token::Ident(ident)) if ident == keyword) {
+      self.position += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn expect_symbol(&mut self, symbol: &str) -> Result<(), AppError> {
+    if self.eat_symbol(symbol) {
+      Ok(())
+    } else {
+      Err(invalid(format!("expected {} instead of {}", symbol, self.describe_next())))
+    }
+  }
+
+  fn describe_next(&self) -> String {
+    match self.peek() {
+      None => "the end".to_owned(),
+      Some(Token::Ident(ident)) => ident.clone(),
+      Some(Token::Literal(value)) => value.to_string(),
+      Some(Token::Symbol(symbol)) => (*symbol).to_owned(),
+    }
+  }
+
+  fn field(&mut self) -> Result<String, AppError> {
+    match self.next() {
+      Some(Token::Ident(ident)) => Ok(ident),
+      _ => {
+        self.position -= 1;
+        Err(invalid(format!("expected a field instead of {}", self.describe_next())))
+      }
+    }
+  }
+
+  fn path(&mut self) -> Result<Vec<String>, AppError> {
+    let mut path = vec![self.field()?];
+    while self.eat_symbol(".") {
+      path.push(self.field()?);
+    }
+    Ok(path)
+  }
+
+  fn or(&mut self) -> Result<Filter, AppError> {
+    let mut filter = self.and()?;
+    while self.eat_keyword("or") {
+      filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+    }
+    Ok(filter)
+  }
+
+  fn and(&mut self) -> Result<Filter, AppError> {
+    let mut filter = self.unary()?;
+    while self.eat_keyword("and") {
+      filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+    }
+    Ok(filter)
+  }
+
+  fn unary(&mut self) -> Result<Filter, AppError> {
+    if self.eat_keyword("not") {
+      return Ok(Filter::Not(Box::new(self.unary()?)));
+    }
+    if self.eat_symbol("(") {
+      let filter = self.or()?;
+      self.expect_symbol(")")?;
+      return Ok(filter);
+    }
+    let path = self.path()?;
+    let op = if self.eat_symbol("==") {
+      Op::Eq
+    } else if self.eat_symbol("!=") {
+      Op::Ne
+    } else if self.eat_keyword("contains") {
+      Op::Contains
+    } else if self.eat_keyword("matches") {
+      match self.next() {
+        Some(Token::Literal(Value::String(pattern))) => Op::Matches(Regex::new(&pattern)?),
+        _ => return Err(invalid("matches needs a string with a regular expression".to_owned())),
+      }
+    } else {
+      return Ok(Filter::Truthy(path));
+    };
+    if let Op::Matches(_) = op {
+      return Ok(Filter::Compare(path, op, Value::Null));
+    }
+    match self.next() {
+      Some(Token::Literal(value)) => Ok(Filter::Compare(path, op, value)),
+      _ => {
+        self.position -= 1;
+        Err(invalid(format!(
+          "expected a string, number, true, false or null instead of {}",
+          self.describe_next()
+        )))
+      }
+    }
+  }
+}
+
+impl std::str::FromStr for Query {
+  type Err = AppError;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    let mut parser = Parser {
+      tokens: tokenize(input)?,
+      position: 0,
+    };
+    let root = if parser.eat_keyword("projects") {
+      Root::Projects
+    } else if parser.eat_keyword("settings") {
+      Root::Settings
+    } else {
+      return Err(invalid(format!("expected projects or settings instead of {}", parser.describe_next())));
+    };
+    let mut filters = Vec::new();
+    while parser.eat_symbol("[") {
+      if root == Root::Settings {
+        return Err(invalid("only projects can be filtered".to_owned()));
+      }
+      filters.push(parser.or()?);
+      parser.expect_symbol("]")?;
+    }
+    let projection = if parser.eat_symbol(".") { parser.path()? } else { vec![] };
+    if parser.peek().is_some() {
+      return Err(invalid(format!("unexpected {} at the end", parser.describe_next())));
+    }
+    Ok(Query { root, filters, projection })
+  }
+}
+
+fn lookup<'v>(value: &'v Value, path: &[String]) -> &'v Value {
+  path.iter().fold(value, |value, field| value.get(field).unwrap_or(&Value::Null))
+}
+
+fn truthy(value: &Value) -> bool {
+  match value {
+    Value::Null | Value::Bool(false) => false,
+    Value::String(string) => !string.is_empty(),
+    Value::Array(array) => !array.is_empty(),
+    Value::Object(object) => !object.is_empty(),
+    _ => true,
+  }
+}
+
+fn matches(filter: &Filter, value: &Value) -> bool {
+  match filter {
+    Filter::Or(left, right) => matches(left, value) || matches(right, value),
+    Filter::And(left, right) => matches(left, value) && matches(right, value),
+    Filter::Not(filter) => !matches(filter, value),
+    Filter::Truthy(path) => truthy(lookup(value, path)),
+    Filter::Compare(path, op, expected) => {
+      let actual = lookup(value, path);
+      match op {
+        Op::Eq => actual == expected,
+        Op::Ne => actual != expected,
+        Op::Contains => match (actual, expected) {
+          (Value::Array(items), _) => items.contains(expected),
+          (Value::String(string), Value::String(part)) => string.contains(part.as_str()),
+          (Value::Object(object), Value::String(key)) => object.contains_key(key),
+          _ => false,
+        },
+        Op::Matches(regex) => match actual {
+          Value::String(string) => regex.is_match(string),
+          Value::Array(items) => items.iter().any(|item| item.as_str().map(|s| regex.is_match(s)).unwrap_or(false)),
+          _ => false,
+        },
+      }
+    }
+  }
+}
+
+impl Query {
+  /// The projected values of the selected projects, or of the settings. Missing values are left out.
+  fn evaluate(&self, projects: &[Value], settings: &Value) -> Vec<Value> {
+    let selected: Vec<&Value> = match self.root {
+      Root::Projects => projects
+        .iter()
+        .filter(|project| self.filters.iter().all(|filter| matches(filter, project)))
+        .collect(),
+      Root::Settings => vec![settings],
+    };
+    selected
+      .into_iter()
+      .map(|value| lookup(value, &self.projection).clone())
+      .filter(|value| !value.is_null())
+      .collect()
+  }
+}
+
+// Tokens are left out, the output ends up in terminals and scripts.
+fn queried_settings(settings: &Settings) -> Result<Value, AppError> {
+  let mut settings = serde_json::to_value(settings)?;
+  without_tokens(&mut settings);
+  Ok(settings)
+}
+
+/// Prints the result of `query`, a line per value with strings unquoted, or a JSON array with `json`.
+pub fn query(maybe_config: Result<Config, AppError>, query: &Query, json: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let projects = config
+    .projects
+    .values()
+    .map(|project| serde_json::to_value(PluginProject::new(&config, project, logger)))
+    .collect::<Result<Vec<Value>, _>>()?;
+  let results = query.evaluate(&projects, &queried_settings(&config.settings)?);
+  if json {
+    println!("{}", Value::Array(results));
+  } else {
+    for result in results {
+      match result {
+        Value::String(string) => println!("{}", string),
+        other => println!("{}", other),
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use spectral::prelude::*;
+
+  fn run(query: &str) -> Vec<Value> {
+    let projects = vec![
+      json!({"name": "fw", "path": "/ws/fw", "cloned": true, "effective_tags": ["rust", "cli"], "git": "git@github.com:brocode/fw.git"}),
+      json!({"name": "web", "path": "/ws/web", "cloned": false, "effective_tags": ["js"], "git": "https://gitlab.com/me/web.git", "pin": "v2"}),
+      json!({"name": "api", "path": "/ws/api", "cloned": true, "effective_tags": [], "git": "git@github.com:me/api.git"}),
+    ];
+    let settings = json!({"workspace": "~/ws", "gitlab": {"host": "gitlab.com"}});
+    query.parse::<Query>().unwrap().evaluate(&projects, &settings)
+  }
+
+  #[test]
+  fn test_query() {
+    assert_that(&run(r#"projects[effective_tags contains "rust"].path"#)).is_equal_to(vec![json!("/ws/fw")]);
+    assert_that(&run("projects[cloned and not effective_tags].name")).is_equal_to(vec![json!("api")]);
+    assert_that(&run("projects[git matches 'github' and (name == 'fw' or name == 'api')].name")).is_equal_to(vec![json!("fw"), json!("api")]);
+    assert_that(&run("projects[name != 'fw'][cloned == false].pin")).is_equal_to(vec![json!("v2")]);
+    assert_that(&run("projects.pin")).is_equal_to(vec![json!("v2")]);
+    assert_that(&run("settings.gitlab.host")).is_equal_to(vec![json!("gitlab.com")]);
+    assert_that(&run("projects[git contains \"gitlab\"]").len()).is_equal_to(1);
+  }
+
+  #[test]
+  fn test_settings_without_tokens() {
+    let settings: Settings = serde_json::from_value(json!({
+      "workspace": "~/ws",
+      "github_token": "secret",
+      "gitea_token": "secret",
+      "azure_token": "secret",
+      "gitlab": {"host": "gitlab.com", "token": "secret"},
+    }))
+    .unwrap();
+    let queried = queried_settings(&settings).unwrap();
+    let fields: Vec<&String> = queried.as_object().unwrap().keys().collect();
+    assert_that(&fields.iter().any(|field| field.ends_with("_token") || *field == "gitlab")).is_false();
+    assert_that(&queried.to_string().contains("secret")).is_false();
+    assert_that(&queried["workspace"]).is_equal_to(json!("~/ws"));
+  }
+
+  #[test]
+  fn test_invalid_query() {
+    let message = |query: &str| match query.parse::<Query>() {
+      Err(AppError::UserError(message)) => message,
+      other => panic!("expected an error for {}, got {:?}", query, other),
+    };
+    assert_that(&message("repos")).is_equal_to("Invalid query: expected projects or settings instead of repos".to_owned());
+    assert_that(&message("projects[name ==]")).is_equal_to("Invalid query: expected a string, number, true, false or null instead of ]".to_owned());
+    assert_that(&message("projects[name == 'fw'")).is_equal_to("Invalid query: expected ] instead of the end".to_owned());
+    assert_that(&message("settings[x]")).is_equal_to("Invalid query: only projects can be filtered".to_owned());
+    assert_that(&message("projects.name 'x'")).is_equal_to("Invalid query: unexpected \"x\" at the end".to_owned());
+  }
+}
@@ -0,0 +1,219 @@
+use crate::config::path::fw_path;
+use crate::config::{self, project::Project, Config};
+use crate::errors::AppError;
+use crate::output::{Cell, Table, Tone};
+use crate::status::format_age;
+use serde::{Deserialize, Serialize};
+use slog::{debug, warn, Logger};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many workons `visits` remembers.
+const MAX_VISITS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Visit {
+  pub project: String,
+  pub at: u64,
+}
+
+/// When each project was last worked on, in seconds since the epoch, and the last workons in order.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WorkonHistory {
+  projects: BTreeMap<String, u64>,
+  /// Oldest first, a workon on the project of the last one only updates its time.
+  #[serde(default)]
+  visits: Vec<Visit>,
+}
+
+impl WorkonHistory {
+  pub fn last_workon(&self, project_name: &str) -> Option<u64> {
+    self.projects.get(project_name).copied()
+  }
+
+  /// The project worked on last, what `workon --last` goes back to.
+  pub fn last_visit(&self) -> Option<&str> {
+    self.visits.last().map(|visit| visit.project.as_str())
+  }
+
+  /// The project worked on before the last one, what `workon -` goes back to like `cd -`.
+  pub fn previous_visit(&self) -> Option<&str> {
+    self.visits.iter().rev().nth(1).map(|visit| visit.project.as_str())
+  }
+
+  fn visit(&mut self, project_name: &str, timestamp: u64) {
+    self.projects.insert(project_name.to_owned(), timestamp);
+    match self.visits.last_mut() {
+      Some(last) if last.project == project_name => last.at = timestamp,
+      _ => self.visits.push(Visit {
+        project: project_name.to_owned(),
+        at: timestamp,
+      }),
+    }
+    let excess = self.visits.len().saturating_sub(MAX_VISITS);
+    self.visits.drain(..excess);
+  }
+
+  /// Sorts `projects` so that the most recently used come first. Projects never worked on keep their order at the end.
+  pub fn sort(&self, projects: &mut [&Project]) {
+    projects.sort_by_key(|p| Reverse(self.last_workon(&p.name)));
+  }
+}
+
+fn history_file() -> Result<PathBuf, AppError> {
+  Ok(fw_path()?.state.join("workon.json"))
+}
+
+fn read_history_from(path: &Path, logger: &Logger) -> WorkonHistory {
+  match fs::read_to_string(path) {
+    Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+      warn!(logger, "Ignoring unreadable workon history"; "path" => path.to_string_lossy().to_string(), "error" => format!("{}", e));
+      WorkonHistory::default()
+    }),
+    Err(_) => WorkonHistory::default(),
+  }
+}
+
+pub fn read_history(logger: &Logger) -> Result<WorkonHistory, AppError> {
+  Ok(read_history_from(&history_file()?, logger))
+}
+
+fn now() -> Result<u64, AppError> {
+  Ok(SystemTime::now().duration_since(UNIX_EPOCH).map_err(AppError::ClockError)?.as_secs())
+}
+
+fn record_in(path: &Path, project_name: &str, timestamp: u64, logger: &Logger) -> Result<(), AppError> {
+  let mut history = read_history_from(path, logger);
+  history.visit(project_name, timestamp);
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  config::write_atomically(path, &serde_json::to_string(&history)?)
+}
+
+/// Remembers that `project_name` is worked on right now. Failing to do so only costs the ordering, so it is logged and not returned.
+pub fn record_workon(project_name: &str, logger: &Logger) {
+  let result = history_file().and_then(|path| now().and_then(|now| record_in(&path, project_name, now, logger)));
+  match result {
+    Ok(()) => debug!(logger, "Workon recorded"; "project" => project_name),
+    Err(e) => warn!(logger, "Could not record workon"; "project" => project_name, "error" => format!("{}", e)),
+  }
+}
+
+/// Lists the workons, newest first, with repeated projects.
+fn visits(history: &WorkonHistory, limit: Option<usize>) -> Result<(), AppError> {
+  let now = now()?;
+  let mut table = Table::new();
+  for visit in history.visits.iter().rev().take(limit.unwrap_or(usize::MAX)) {
+    table.add_row(vec![
+      Cell::plain(visit.project.clone()),
+      Cell::new(format_age(now.saturating_sub(visit.at)), Tone::Muted),
+    ]);
+  }
+  table.print();
+  Ok(())
+}
+
+/// Lists the projects worked on most recently, newest first, or every workon with `all_visits`.
+pub fn recent(maybe_config: Result<Config, AppError>, limit: Option<usize>, all_visits: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let history = read_history(logger)?;
+  if all_visits {
+    return visits(&history, limit);
+  }
+  let mut projects: Vec<&Project> = config.projects.values().filter(|p| history.last_workon(&p.name).is_some()).collect();
+  history.sort(&mut projects);
+
+  let now = now()?;
+  let mut table = Table::new();
+  for project in projects.into_iter().take(limit.unwrap_or(usize::MAX)) {
+    let age = history
+      .last_workon(&project.name)
+      .map(|t| format_age(now.saturating_sub(t)))
+      .unwrap_or_default();
+    table.add_row(vec![Cell::plain(project.name.clone()), Cell::new(age, Tone::Muted)]);
+  }
+  table.print();
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+
+  fn a_logger() -> Logger {
+    use slog::Drain;
+    let plain = slog_term::PlainSyncDecorator::new(std::io::stdout());
+    Logger::root(slog_term::FullFormat::new(plain).build().fuse(), slog::o!())
+  }
+
+  fn a_project(name: &str) -> Project {
+    Project {
+      name: name.to_owned(),
+      git: "irrelevant".to_owned(),
+      kind: None,
+      after_clone: None,
+      after_workon: None,
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
+      override_path: None,
+      subdir: None,
+      bare: None,
+      tags: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
+      additional_remotes: None,
+      project_config_path: "".to_owned(),
+    }
+  }
+
+  #[test]
+  fn test_records_and_sorts_by_last_workon() {
+    let logger = a_logger();
+    let temp = temp_dir();
+    let path = temp.path().join("workon.json");
+    record_in(&path, "old", 10, &logger).unwrap();
+    record_in(&path, "new", 30, &logger).unwrap();
+    record_in(&path, "old", 20, &logger).unwrap();
+    let history = read_history_from(&path, &logger);
+    assert_that(&history.last_workon("old")).is_equal_to(Some(20));
+
+    let (never, old, new, unused) = (a_project("never"), a_project("old"), a_project("new"), a_project("unused"));
+    let mut projects = vec![&never, &old, &new, &unused];
+    history.sort(&mut projects);
+    let names: Vec<&str> = projects.iter().map(|p| p.name.as_str()).collect();
+    assert_that(&names).is_equal_to(vec!["new", "old", "never", "unused"]);
+  }
+
+  #[test]
+  fn test_visits() {
+    let mut history = WorkonHistory::default();
+    assert_that(&history.previous_visit()).is_none();
+    history.visit("fw", 10);
+    history.visit("api", 20);
+    history.visit("api", 30);
+    assert_that(&history.last_visit()).is_equal_to(Some("api"));
+    assert_that(&history.previous_visit()).is_equal_to(Some("fw"));
+    history.visit("fw", 40);
+    assert_that(&history.previous_visit()).is_equal_to(Some("api"));
+    assert_that(&history.visits.len()).is_equal_to(3);
+
+    for i in 0..MAX_VISITS as u64 {
+      history.visit(&format!("p{}", i % 2), 100 + i);
+    }
+    assert_that(&history.visits.len()).is_equal_to(MAX_VISITS);
+    assert_that(&history.visits[0].at).is_equal_to(100);
+  }
+}
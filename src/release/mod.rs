@@ -0,0 +1,171 @@
+use crate::config::{self, project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git::{is_dirty, push_tag};
+use crate::output::{Cell, Table, Tone};
+use git2::{Oid, Repository};
+use serde::{Deserialize, Serialize};
+use slog::{debug, info, o, Logger};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The tag of one project in a release.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReleasedProject {
+  pub git: String,
+  /// The tagged commit.
+  pub commit: String,
+  /// The annotated tag object.
+  pub tag: String,
+  pub pushed: bool,
+}
+
+/// What `fw release tag` tagged, written to the manifest file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReleaseManifest {
+  pub version: String,
+  /// Seconds since the epoch.
+  pub created: u64,
+  pub projects: BTreeMap<String, ReleasedProject>,
+}
+
+/// How `fw release tag` tags every project.
+#[derive(Debug, Clone)]
+pub struct ReleaseOptions {
+  pub version: String,
+  pub message: Option<String>,
+  pub push: bool,
+}
+
+fn short(oid: Oid) -> String {
+  oid.to_string()[..8].to_owned()
+}
+
+// An existing tag is reused if it is annotated and points at HEAD, so that a failed push can be retried.
+fn tag_in(repo: &Repository, version: &str, message: &str) -> Result<(Oid, Oid, bool), AppError> {
+  let head = repo.head()?.peel_to_commit()?;
+  if let Ok(existing) = repo.revparse_single(&format!("refs/tags/{}", version)) {
+    let target = existing.peel_to_commit()?.id();
+    return match existing.as_tag() {
+      Some(tag) if target == head.id() => Ok((target, tag.id(), false)),
+      Some(_) => Err(AppError::UserError(format!("tag {} already exists at {}", version, short(target)))),
+      None => Err(AppError::UserError(format!("tag {} already exists and is not annotated", version))),
+    };
+  }
+  let tagger = repo
+    .signature()
+    .map_err(|e| AppError::UserError(format!("No tagger, set user.name and user.email in git: {}", e.message())))?;
+  let tag = repo.tag(version, head.as_object(), &tagger, message, false)?;
+  Ok((head.id(), tag, true))
+}
+
+fn release_project(project: &Project, repo: &Repository, options: &ReleaseOptions, logger: &Logger) -> Result<(ReleasedProject, String), AppError> {
+  let default_message = format!("Release {}", options.version);
+  let (commit, tag, created) = tag_in(repo, &options.version, options.message.as_deref().unwrap_or(&default_message))?;
+  debug!(logger, "Tagged"; "tag" => &options.version, "commit" => commit.to_string(), "created" => created);
+  let mut message = format!("{} {} at {}", if created { "tagged" } else { "already tagged" }, options.version, short(commit));
+  if options.push {
+    push_tag(project, repo, &options.version, logger)?;
+    message.push_str(", pushed");
+  }
+  Ok((
+    ReleasedProject {
+      git: project.git.clone(),
+      commit: commit.to_string(),
+      tag: tag.to_string(),
+      pushed: options.push,
+    },
+    message,
+  ))
+}
+
+/// Creates the annotated tag `options.version` at HEAD in every selected project that is checked out, pushes it if
+/// `options.push` and writes the tagged commits to `manifest`. The manifest lists the projects that were tagged even if
+/// others failed.
+pub fn tag(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  options: &ReleaseOptions,
+  manifest: &Path,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut released = ReleaseManifest {
+    version: options.version.clone(),
+    created: SystemTime::now().duration_since(UNIX_EPOCH).map_err(AppError::ClockError)?.as_secs(),
+    projects: BTreeMap::new(),
+  };
+  let mut table = Table::new();
+  let mut failed = 0;
+  for project in selector.select_repositories(&config) {
+    let path = config.checkout_path(project, logger);
+    if !path.exists() {
+      debug!(logger, "Not released, not checked out"; "project" => &project.name);
+      continue;
+    }
+    let project_logger = logger.new(o!("project" => project.name.clone()));
+    let result = Repository::open(&path)
+      .map_err(AppError::from)
+      .and_then(|repo| Ok((is_dirty(&repo)?, release_project(project, &repo, options, &project_logger)?)));
+    let row = match result {
+      Ok((dirty, (released_project, message))) => {
+        released.projects.insert(project.name.clone(), released_project);
+        let note = if dirty {
+          Cell::new("has uncommitted changes that are not tagged", Tone::Warning)
+        } else {
+          Cell::plain("")
+        };
+        vec![Cell::new(message, Tone::Good), note]
+      }
+      Err(AppError::UserError(message)) => {
+        failed += 1;
+        vec![Cell::new(message, Tone::Bad)]
+      }
+      Err(e) => {
+        failed += 1;
+        vec![Cell::new(format!("{}", e), Tone::Bad)]
+      }
+    };
+    let mut cells = vec![Cell::plain(project.name.clone())];
+    cells.extend(row);
+    table.add_row(cells);
+  }
+  table.print();
+
+  if !released.projects.is_empty() {
+    config::write_atomically(manifest, &format!("{}\n", serde_json::to_string_pretty(&released)?))?;
+    info!(logger, "Release manifest written"; "path" => manifest.to_string_lossy().to_string(), "projects" => released.projects.len());
+  }
+  if failed > 0 {
+    Err(AppError::UserError(format!("Could not release {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{commit, test_repo};
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_tag_at_head() {
+    let (_dir, repo) = test_repo();
+    repo.config().unwrap().set_str("user.name", "fw").unwrap();
+    repo.config().unwrap().set_str("user.email", "fw@example.com").unwrap();
+    let first = repo.head().unwrap().target().unwrap();
+
+    let (tagged, tag, created) = tag_in(&repo, "v1.2.3", "Release v1.2.3").unwrap();
+    assert_that(&tagged).is_equal_to(first);
+    assert_that(&created).is_true();
+    assert_that(&repo.find_tag(tag).unwrap().message()).is_equal_to(Some("Release v1.2.3"));
+    assert_that(&tag_in(&repo, "v1.2.3", "Release v1.2.3").unwrap()).is_equal_to((first, tag, false));
+
+    let parent = repo.find_commit(first).unwrap();
+    commit(&repo, "work");
+    assert_that(&tag_in(&repo, "v1.2.3", "Release v1.2.3").is_err()).is_true();
+    repo.tag_lightweight("v2", parent.as_object(), false).unwrap();
+    assert_that(&tag_in(&repo, "v2", "Release v2").is_err()).is_true();
+  }
+}
@@ -0,0 +1,199 @@
+use crate::config::{self, project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git_url::{host_and_path, repository_key};
+use crate::output::{Cell, Table, Tone};
+use crate::pr::{forge_of, Forge};
+use crate::runtime;
+use crate::ws::client::ForgeClient;
+use crate::ws::github::{self, GithubApi};
+use git2::Repository;
+use serde::Deserialize;
+use slog::{debug, info, Logger};
+use std::time::Duration;
+
+/// A repository that the forge knows under another owner or name, e.g. after it was renamed or transferred. Fetching
+/// from the old URL still works through a redirect until someone takes over the old name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovedRepository {
+  pub project: String,
+  pub old_git: String,
+  pub new_git: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitlabProject {
+  path_with_namespace: String,
+}
+
+/// `owner/repo` on GitHub or the full project path on GitLab where the forge has the repository now.
+fn current_path(github: &GithubApi, gitlab: Option<&ForgeClient>, forge: &Forge) -> Result<String, AppError> {
+  match forge {
+    Forge::GitHub { owner, repo } => github.repository(owner, repo).map(|r| format!("{}/{}", r.owner, r.name)),
+    Forge::GitLab { path } => match gitlab {
+      Some(client) => client
+        .get_json::<GitlabProject>(&format!("projects/{}", path.replace('/', "%2F")))
+        .map(|p| p.path_with_namespace),
+      None => Err(AppError::UserError("there are no gitlab settings (token and host)".to_owned())),
+    },
+  }
+}
+
+fn forge_path(forge: &Forge) -> String {
+  match forge {
+    Forge::GitHub { owner, repo } => format!("{}/{}", owner, repo),
+    Forge::GitLab { path } => path.clone(),
+  }
+}
+
+/// `url` with the repository path `old_path` replaced by `new_path`, keeping the protocol and a `.git` suffix.
+fn moved_url(url: &str, old_path: &str, new_path: &str) -> Option<String> {
+  let start = url.to_ascii_lowercase().rfind(&old_path.to_ascii_lowercase())?;
+  Some(format!("{}{}{}", &url[..start], new_path, &url[start + old_path.len()..]))
+}
+
+/// Asks GitHub and GitLab where the selected projects are now and returns those that moved. Projects elsewhere are
+/// skipped, failed lookups are logged.
+pub fn moved_repositories(config: &Config, selector: &ProjectSelector, timeout: Option<Duration>, logger: &Logger) -> Result<Vec<MovedRepository>, AppError> {
+  let gitlab_host = config.settings.gitlab.as_ref().map(|g| g.host.as_str());
+  let lookups: Vec<(String, String, Forge)> = selector
+    .select(config)
+    .into_iter()
+    .filter(|p| !p.is_directory())
+    .filter_map(|p| forge_of(&p.git, gitlab_host).map(|forge| (p.name.clone(), p.git.clone(), forge)))
+    .collect();
+  if lookups.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  // public repositories need no token
+  let github_api = match github::token(config.settings.github_token.as_ref()) {
+    Some(token) => github::github_api(&token, logger)?,
+    None => github::anonymous_github_api(logger)?,
+  };
+  let gitlab_client = match &config.settings.gitlab {
+    Some(gitlab) => Some(ForgeClient::gitlab(&gitlab.host, &gitlab.token, logger)?),
+    None => None,
+  };
+  let lookup_logger = logger.clone();
+  runtime::block_on_interruptible(runtime::blocking("Remote check", timeout, move || {
+    let mut moved = Vec::new();
+    for (project, git, forge) in lookups {
+      let old_path = forge_path(&forge);
+      match current_path(&github_api, gitlab_client.as_ref(), &forge) {
+        Ok(new_path) if new_path.eq_ignore_ascii_case(&old_path) => (),
+        Ok(new_path) => {
+          if let Some(new_git) = moved_url(&git, &old_path, &new_path) {
+            moved.push(MovedRepository {
+              project,
+              old_git: git,
+              new_git,
+            });
+          }
+        }
+        Err(e) => debug!(lookup_logger, "Could not look up repository"; "project" => &project, "error" => format!("{}", e)),
+      }
+    }
+    Ok(moved)
+  }))
+}
+
+// Remotes that still point to the old repository, origin included, get the new one. The config may have the URL in
+// another form than the checkout (see `prefer`), so they are compared by repository and each keeps its protocol.
+fn fix_remote_urls(repo: &Repository, moved: &MovedRepository, logger: &Logger) -> Result<(), AppError> {
+  let old_key = repository_key(&moved.old_git);
+  let paths = host_and_path(&moved.old_git).zip(host_and_path(&moved.new_git));
+  for name in repo.remotes()?.iter().flatten() {
+    let url = match repo.find_remote(name)?.url() {
+      Some(url) if repository_key(url) == old_key => url.to_owned(),
+      _ => continue,
+    };
+    let new_git = paths
+      .and_then(|((_, old_path), (_, new_path))| moved_url(&url, old_path, new_path))
+      .unwrap_or_else(|| moved.new_git.clone());
+    info!(logger, "Changing remote URL"; "remote" => name, "git" => &new_git);
+    repo.remote_set_url(name, &new_git)?;
+  }
+  Ok(())
+}
+
+fn fix_checkout(config: &Config, project: &Project, moved: &MovedRepository, logger: &Logger) -> Result<(), AppError> {
+  let path = config.checkout_path(project, logger);
+  if !path.exists() {
+    return Ok(());
+  }
+  fix_remote_urls(&Repository::open(&path)?, moved, logger)
+}
+
+/// Points the config and the checkouts of the selected projects that moved on their forge to the new location.
+pub fn fix_remotes(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  dry_run: bool,
+  timeout: Option<Duration>,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let moved = moved_repositories(&config, selector, timeout, logger)?;
+  if moved.is_empty() {
+    println!("{}", Tone::Muted.paint("No project moved"));
+    return Ok(());
+  }
+  let mut table = Table::new();
+  for moved_repository in &moved {
+    table.add_row(vec![
+      Cell::plain(moved_repository.project.clone()),
+      Cell::new(moved_repository.old_git.clone(), Tone::Muted),
+      Cell::new(moved_repository.new_git.clone(), Tone::Good),
+    ]);
+    if dry_run {
+      continue;
+    }
+    if let Some(project) = config.projects.get(&moved_repository.project) {
+      fix_checkout(&config, project, moved_repository, logger)?;
+      config::write_project(
+        &Project {
+          git: moved_repository.new_git.clone(),
+          ..project.clone()
+        },
+        logger,
+      )?;
+    }
+  }
+  table.print();
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_moved_url_keeps_protocol() {
+    assert_that(&moved_url("git@github.com:acme/fw.git", "acme/fw", "brocode/fw2")).is_equal_to(Some("git@github.com:brocode/fw2.git".to_owned()));
+    assert_that(&moved_url("https://gitlab.com/Acme/Sub/api", "acme/sub/api", "acme/api")).is_equal_to(Some("https://gitlab.com/acme/api".to_owned()));
+    assert_that(&moved_url("https://example.com/x", "acme/fw", "acme/fw2")).is_none();
+  }
+
+  #[test]
+  fn test_fix_remote_urls_matches_by_repository() {
+    let dir = temp_dir();
+    let repo = Repository::init(dir.path()).unwrap();
+    repo.remote("origin", "https://github.com/acme/fw").unwrap();
+    repo.remote("upstream", "git@github.com:acme/fw.git").unwrap();
+    repo.remote("fork", "git@github.com:someone/fw.git").unwrap();
+    let moved = MovedRepository {
+      project: "fw".to_owned(),
+      old_git: "git@github.com:acme/fw.git".to_owned(),
+      new_git: "git@github.com:brocode/fw2.git".to_owned(),
+    };
+    let logger = Logger::root(slog::Discard, slog::o!());
+
+    fix_remote_urls(&repo, &moved, &logger).unwrap();
+    let url = |name: &str| repo.find_remote(name).unwrap().url().map(str::to_owned);
+    assert_that(&url("origin")).is_equal_to(Some("https://github.com/brocode/fw2".to_owned()));
+    assert_that(&url("upstream")).is_equal_to(Some("git@github.com:brocode/fw2.git".to_owned()));
+    assert_that(&url("fork")).is_equal_to(Some("git@github.com:someone/fw.git".to_owned()));
+  }
+}
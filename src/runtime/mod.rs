@@ -0,0 +1,118 @@
+use crate::errors::AppError;
+use crate::signal;
+use std::cell::Cell;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+thread_local! {
+  static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+fn new_runtime() -> Result<Runtime, AppError> {
+  tokio::runtime::Builder::new_multi_thread()
+    .enable_all()
+    .build()
+    .map_err(|e| AppError::RuntimeError(format!("Failed to start async runtime: {}", e)))
+}
+
+pub fn block_on<F, T>(future: F) -> Result<T, AppError>
+where
+  F: Future<Output = Result<T, AppError>>,
+{
+  let runtime = new_runtime()?;
+  let result = runtime.block_on(future);
+  runtime.shutdown_background();
+  result
+}
+
+// Like block_on, but Ctrl-C cancels the future. Blocking work that is still running is abandoned instead of waited
+// for, so only use this for work without side effects on disk.
+pub fn block_on_interruptible<F, T>(future: F) -> Result<T, AppError>
+where
+  F: Future<Output = Result<T, AppError>>,
+{
+  block_on(async {
+    tokio::select! {
+      result = future => result,
+      _ = signal::interruption() => Err(AppError::Interrupted),
+    }
+  })
+}
+
+/// Whether the blocking work on this thread ran out of time. Git transfers check it and stop, the work is not waited
+/// for once its timeout fired.
+pub fn deadline_passed() -> bool {
+  DEADLINE.with(|deadline| deadline.get().is_some_and(|deadline| Instant::now() >= deadline))
+}
+
+/// The deadline of the blocking work on this thread, for work that can't check `deadline_passed` itself.
+pub fn deadline() -> Option<Instant> {
+  DEADLINE.with(Cell::get)
+}
+
+/// Runs `f` with `deadline` for `deadline_passed` on this thread. An earlier deadline that is already set stays.
+pub fn with_deadline<T>(deadline: Option<Instant>, f: impl FnOnce() -> T) -> T {
+  let previous = DEADLINE.with(Cell::get);
+  let earliest = match (previous, deadline) {
+    (Some(previous), Some(deadline)) => Some(previous.min(deadline)),
+    (previous, deadline) => previous.or(deadline),
+  };
+  DEADLINE.with(|d| d.set(earliest));
+  let result = f();
+  DEADLINE.with(|d| d.set(previous));
+  result
+}
+
+// Runs blocking work (git2, blocking http clients) on the blocking thread pool, optionally bounded by a timeout. The
+// work gets the timeout as its deadline, so it can stop on its own instead of running on unobserved.
+pub async fn blocking<F, T>(task_name: &str, timeout: Option<Duration>, f: F) -> Result<T, AppError>
+where
+  F: FnOnce() -> Result<T, AppError> + Send + 'static,
+  T: Send + 'static,
+{
+  let deadline = timeout.map(|duration| Instant::now() + duration);
+  let handle = tokio::task::spawn_blocking(move || with_deadline(deadline, f));
+  let joined = match timeout {
+    Some(duration) => tokio::time::timeout(duration, handle)
+      .await
+      .map_err(|_| AppError::RuntimeError(format!("{} timed out after {}s", task_name, duration.as_secs())))?,
+    None => handle.await,
+  };
+  joined.map_err(|e| AppError::RuntimeError(format!("{} failed: {}", task_name, e)))?
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+  use std::sync::mpsc;
+
+  #[test]
+  fn test_timed_out_work_sees_its_deadline() {
+    let (stopped, on_stop) = mpsc::channel();
+    let result = block_on(blocking("Test", Some(Duration::from_millis(50)), move || {
+      while !deadline_passed() {
+        std::thread::sleep(Duration::from_millis(5));
+      }
+      stopped.send(()).unwrap();
+      // still busy when the timeout fires
+      std::thread::sleep(Duration::from_millis(200));
+      Ok(())
+    }));
+    assert_that(&result.is_err()).is_true();
+    assert_that(&on_stop.recv_timeout(Duration::from_secs(5)).is_ok()).is_true();
+  }
+
+  #[test]
+  fn test_with_deadline_keeps_the_earlier_one() {
+    let soon = Instant::now();
+    let later = soon + Duration::from_secs(60);
+    with_deadline(Some(soon), || {
+      with_deadline(Some(later), || assert_that(&deadline_passed()).is_true());
+      assert_that(&deadline_passed()).is_true();
+    });
+    assert_that(&deadline_passed()).is_false();
+    with_deadline(Some(later), || assert_that(&deadline_passed()).is_false());
+  }
+}
@@ -0,0 +1,366 @@
+use super::{dry_run_row, load_project, ImportOptions};
+use crate::config::{
+  self,
+  path::{self, expand_path, fw_path},
+  project::Project,
+  Config,
+};
+use crate::errors::AppError;
+use crate::output::{Cell, Table, Tone};
+use crate::project::OnConflict;
+use slog::{debug, info, warn, Logger};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use walkdir::WalkDir;
+
+/// Multi-repo managers whose configuration `fw import-from` understands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+  /// myrepos, `~/.mrconfig`
+  Mr,
+  /// `ghq root`, checkouts in `<root>/<host>/<owner>/<repo>`
+  Ghq,
+  /// bare repositories in `~/.config/vcsh/repo.d` with the home directory as work tree
+  Vcsh,
+  /// `~/.config/gita/repos.csv`
+  Gita,
+}
+
+impl FromStr for Tool {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "mr" => Ok(Tool::Mr),
+      "ghq" => Ok(Tool::Ghq),
+      "vcsh" => Ok(Tool::Vcsh),
+      "gita" => Ok(Tool::Gita),
+      other => Err(AppError::UserError(format!("Unknown tool {}, expected mr, ghq, vcsh or gita", other))),
+    }
+  }
+}
+
+impl Tool {
+  fn name(self) -> &'static str {
+    match self {
+      Tool::Mr => "mr",
+      Tool::Ghq => "ghq",
+      Tool::Vcsh => "vcsh",
+      Tool::Gita => "gita",
+    }
+  }
+
+  fn default_path(self) -> Result<PathBuf, AppError> {
+    let home = dirs::home_dir().ok_or(AppError::InternalError("Cannot resolve home dir"))?;
+    let config_dir = dirs::config_dir().unwrap_or_else(|| home.join(".config"));
+    Ok(match self {
+      Tool::Mr => home.join(".mrconfig"),
+      Tool::Ghq => git2::Config::open_default()
+        .and_then(|c| c.get_path("ghq.root"))
+        .unwrap_or_else(|_| home.join("ghq")),
+      Tool::Vcsh => env::var_os("VCSH_REPO_D")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config_dir.join("vcsh").join("repo.d")),
+      Tool::Gita => config_dir.join("gita").join("repos.csv"),
+    })
+  }
+}
+
+/// A repository found in the other tool's configuration. Without `git` the URL is read from the checkout's origin.
+#[derive(Debug, Clone, PartialEq)]
+struct Found {
+  path: PathBuf,
+  git: Option<String>,
+}
+
+fn unquote(word: &str) -> &str {
+  word.trim_matches(|c| c == '\'' || c == '"')
+}
+
+// Options like `--depth 1` take values, the repository is the first argument that looks like a URL or path.
+fn clone_url(command: &str) -> Option<String> {
+  let words: Vec<&str> = command.split_whitespace().collect();
+  words
+    .windows(2)
+    .position(|pair| pair == ["git", "clone"])
+    .and_then(|start| {
+      words[start + 2..]
+        .iter()
+        .map(|w| unquote(w))
+        .find(|w| !w.starts_with('-') && (w.contains(':') || w.contains('/')))
+    })
+    .map(ToOwned::to_owned)
+}
+
+/// Sections of a `.mrconfig` are checkouts relative to the file's directory. The URL comes from a `git clone` checkout command,
+/// which can continue on indented lines.
+fn parse_mrconfig(content: &str, base: &Path) -> Vec<Found> {
+  let mut found: Vec<Found> = Vec::new();
+  let mut in_checkout = false;
+  for raw in content.lines() {
+    let line = raw.trim();
+    let continued = raw.starts_with(char::is_whitespace) && !line.is_empty();
+    if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+      in_checkout = false;
+      if section != "DEFAULT" {
+        found.push(Found {
+          path: base.join(section.trim()),
+          git: None,
+        });
+      }
+    } else if let Some(current) = found.last_mut() {
+      if let Some(command) = line.strip_prefix("checkout") {
+        in_checkout = true;
+        current.git = clone_url(command.trim_start().trim_start_matches('='));
+      } else if continued && in_checkout {
+        current.git = current.git.take().or_else(|| clone_url(line));
+      } else {
+        in_checkout = false;
+      }
+    }
+  }
+  found
+}
+
+/// Lines of gita's `repos.csv` are `path,name,flags`.
+fn parse_gita_csv(content: &str) -> Vec<Found> {
+  content
+    .lines()
+    .filter_map(|line| line.split(',').next())
+    .filter(|path| !path.trim().is_empty())
+    .map(|path| Found {
+      path: PathBuf::from(path.trim()),
+      git: None,
+    })
+    .collect()
+}
+
+/// The git checkouts below `root` up to `max_depth` levels deep, without those nested in other checkouts.
+pub(crate) fn checkout_dirs(root: &Path, max_depth: usize) -> Vec<PathBuf> {
+  let mut found = Vec::new();
+  let mut walker = WalkDir::new(root)
+    .min_depth(1)
+    .max_depth(max_depth)
+    .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+    .into_iter();
+  while let Some(Ok(entry)) = walker.next() {
+    if entry.file_type().is_dir() && entry.path().join(".git").exists() {
+      found.push(entry.path().to_path_buf());
+      walker.skip_current_dir();
+    }
+  }
+  found
+}
+
+fn checkouts_in(root: &Path, max_depth: usize) -> Vec<Found> {
+  checkout_dirs(root, max_depth).into_iter().map(|path| Found { path, git: None }).collect()
+}
+
+fn find(tool: Tool, path: &Path) -> Result<Vec<Found>, AppError> {
+  let read = |path: &Path| fs::read_to_string(path).map_err(|e| AppError::UserError(format!("Could not read {}: {}", path.to_string_lossy(), e)));
+  Ok(match tool {
+    Tool::Mr => parse_mrconfig(&read(path)?, path.parent().unwrap_or_else(|| Path::new("/"))),
+    Tool::Gita => parse_gita_csv(&read(path)?),
+    // host/owner/repo, some hosts nest deeper
+    Tool::Ghq => checkouts_in(path, 5),
+    Tool::Vcsh => fs::read_dir(path)
+      .map_err(|e| AppError::UserError(format!("Could not read {}: {}", path.to_string_lossy(), e)))?
+      .filter_map(Result::ok)
+      .map(|entry| entry.path())
+      .filter(|repo| repo.extension().map(|e| e == "git").unwrap_or(false))
+      .map(|repo| Found { path: repo, git: None })
+      .collect(),
+  })
+}
+
+fn found_name(found: &Found) -> Result<String, AppError> {
+  found
+    .path
+    .file_name()
+    .map(|n| n.to_string_lossy().trim_end_matches(".git").to_string())
+    .ok_or_else(|| AppError::UserError(format!("{} has no name", found.path.to_string_lossy())))
+}
+
+fn to_project(config: &Config, tool: Tool, found: Found, logger: &Logger) -> Result<Project, AppError> {
+  let name = found_name(&found)?;
+  let project = match found.git {
+    Some(git) => Project {
+      name,
+      tags: config.settings.tags_for_new_project(&git),
+      git,
+      kind: None,
+      after_clone: config.settings.default_after_clone.clone(),
+      after_workon: config.settings.default_after_workon.clone(),
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
+      override_path: None,
+      subdir: None,
+      additional_remotes: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      commands: None,
+      clone_depth: None,
+      env: None,
+      bare: None,
+      project_config_path: String::new(),
+    },
+    None => load_project(Some(config.settings.clone()), found.path.clone(), &name, logger)?,
+  };
+  Ok(Project {
+    override_path: Some(found.path.to_string_lossy().to_string()),
+    bare: if tool == Tool::Vcsh { Some(true) } else { project.bare },
+    project_config_path: tool.name().to_owned(),
+    ..project
+  })
+}
+
+/// Adds the repositories managed by another multi-repo tool as projects, in the subdirectory named after the tool. They keep their
+/// checkouts through `override_path`. `path` overrides where the tool's configuration or checkouts are looked for. Only
+/// the repositories `options` takes are added, with `options.dry_run` they are only listed.
+pub fn import_from(maybe_config: Result<Config, AppError>, tool: Tool, path: Option<&str>, options: &ImportOptions, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let path = match path {
+    Some(path) => expand_path(PathBuf::from(path)),
+    None => tool.default_path()?,
+  };
+  let found = find(tool, &path)?;
+  if found.is_empty() {
+    return Err(AppError::UserError(format!("No repositories found in {}", path.to_string_lossy())));
+  }
+  let mut known = config.all_projects();
+  let mut added = 0;
+  let mut plan = Table::new();
+  for repository in found {
+    let location = repository.path.to_string_lossy().to_string();
+    if let Some(name) = found_name(&repository).ok().filter(|name| !options.takes(name)) {
+      debug!(logger, "Skipping imported repository, filtered by --include or --exclude"; "repository" => name);
+      continue;
+    }
+    match to_project(&config, tool, repository, logger) {
+      Ok(project) if options.dry_run => {
+        plan.add_row(dry_run_row(&project, known.get(&project.name), None, OnConflict::Skip));
+        known.entry(project.name.clone()).or_insert(project);
+      }
+      Ok(project) if known.contains_key(&project.name) => {
+        info!(logger, "Skipping imported project because it already exists in the current fw config"; "project_name" => &project.name, "path" => location);
+      }
+      Ok(project) => {
+        info!(logger, "Saving new project"; "project_name" => &project.name);
+        config::write_project(&project, logger)?;
+        known.insert(project.name.clone(), project);
+        added += 1;
+      }
+      Err(e) => warn!(logger, "Skipping repository"; "path" => location, "error" => format!("{}", e)),
+    }
+  }
+  if options.dry_run {
+    plan.print();
+    return Ok(());
+  }
+  println!("Imported {} project(s) from {}", added, tool.name());
+  Ok(())
+}
+
+fn move_dir(from: &Path, to: &Path) -> Result<(), AppError> {
+  if let Some(parent) = to.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  fs::rename(from, to).map_err(|e| {
+    AppError::UserError(format!(
+      "Could not move {} to {} ({}), move it yourself",
+      from.to_string_lossy(),
+      to.to_string_lossy(),
+      e
+    ))
+  })
+}
+
+/// Moves the config, state and cache directories of earlier versions to their XDG locations and converts the
+/// `~/.fw.json` of fw 1.x into settings, project and tag files.
+pub fn migrate_paths(logger: &Logger) -> Result<(), AppError> {
+  let mut table = Table::new();
+  for relocation in path::relocations().into_iter().filter(|r| r.pending()) {
+    info!(logger, "Moving directory"; "from" => relocation.legacy.to_string_lossy().to_string(), "to" => relocation.xdg.to_string_lossy().to_string());
+    move_dir(&relocation.legacy, &relocation.xdg)?;
+    table.add_row(vec![
+      Cell::plain(relocation.legacy.to_string_lossy().to_string()),
+      Cell::new(format!("moved to {}", relocation.xdg.to_string_lossy()), Tone::Good),
+    ]);
+  }
+
+  if let Some(legacy) = path::legacy_json() {
+    let paths = fw_path()?;
+    if paths.settings.exists() {
+      table.add_row(vec![
+        Cell::plain(legacy.to_string_lossy().to_string()),
+        Cell::new(format!("not converted, {} exists", paths.settings.to_string_lossy()), Tone::Warning),
+      ]);
+    } else {
+      let config::LegacyParts { settings, projects, tags } = config::legacy_json_parts(&legacy)?;
+      config::write_settings(&settings, logger)?;
+      for project in projects.values() {
+        config::write_project(project, logger)?;
+      }
+      for (name, tag) in &tags {
+        config::write_tag(name, tag, logger)?;
+      }
+      let backup = legacy.with_file_name(".fw.json.migrated");
+      fs::rename(&legacy, &backup)?;
+      table.add_row(vec![
+        Cell::plain(legacy.to_string_lossy().to_string()),
+        Cell::new(
+          format!(
+            "converted to {} with {} project(s) and {} tag(s), kept as {}",
+            paths.base.to_string_lossy(),
+            projects.len(),
+            tags.len(),
+            backup.to_string_lossy()
+          ),
+          Tone::Good,
+        ),
+      ]);
+    }
+  }
+
+  if table.is_empty() {
+    println!("Nothing to migrate, fw uses {}", fw_path()?.base.to_string_lossy());
+  } else {
+    table.print();
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_parse_mrconfig() {
+    let content = "[DEFAULT]\ngit_gc = git gc \"$@\"\n\n[src/fw]\ncheckout = git clone 'git@github.com:brocode/fw.git' 'fw'\n\n[src/other]\ncheckout =\n  git clone --depth 1 https://example.com/other.git other\n";
+    let found = parse_mrconfig(content, Path::new("/home/me"));
+    assert_that(&found).is_equal_to(vec![
+      Found {
+        path: PathBuf::from("/home/me/src/fw"),
+        git: Some("git@github.com:brocode/fw.git".to_owned()),
+      },
+      Found {
+        path: PathBuf::from("/home/me/src/other"),
+        git: Some("https://example.com/other.git".to_owned()),
+      },
+    ]);
+  }
+
+  #[test]
+  fn test_parse_gita_csv() {
+    let found = parse_gita_csv("/home/me/src/fw,fw,\n/home/me/src/api,api,--flag\n\n");
+    assert_that(&found.iter().map(|f| f.path.clone()).collect::<Vec<_>>())
+      .is_equal_to(vec![PathBuf::from("/home/me/src/fw"), PathBuf::from("/home/me/src/api")]);
+  }
+}
@@ -1,14 +1,21 @@
-use crate::config::{self, project::Project, settings::Settings, Config};
+use crate::config::{self, project::Project, settings::GitlabSettings, settings::ImportNaming, settings::Settings, Config};
 use crate::errors::AppError;
-use crate::ws::github;
+use crate::output::{Cell, Table, Tone};
+use crate::project::{add_project, OnConflict};
+use crate::runtime;
+use crate::ws::client::ForgeClient;
+use crate::ws::{azure, cloud, gitea, github};
 use git2::Repository;
+use regex::Regex;
 use slog::Logger;
 use slog::{debug, info, o, warn};
-use std::collections::BTreeMap;
-use std::env;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 use std::iter::Iterator;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub mod migrate;
 
 #[derive(Copy, Clone)]
 pub enum ProjectState {
@@ -80,8 +87,13 @@ fn determine_projects(path: PathBuf, logger: &Logger) -> Result<BTreeMap<String,
   Ok(projects)
 }
 
-pub fn gitlab_import(maybe_config: Result<Config, AppError>, state: ProjectState, logger: &Logger) -> Result<(), AppError> {
-  use gitlab::api::Query;
+pub fn gitlab_import(
+  maybe_config: Result<Config, AppError>,
+  state: ProjectState,
+  timeout: Option<Duration>,
+  options: &ImportOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
   let current_config = maybe_config?;
 
   let gitlab_config = current_config.settings.gitlab.clone().ok_or_else(|| {
@@ -91,119 +103,415 @@ pub fn gitlab_import(maybe_config: Result<Config, AppError>, state: ProjectState
     )
   })?;
 
-  let gitlab_client =
-    gitlab::Gitlab::new(gitlab_config.host, gitlab_config.token).map_err(|e| AppError::RuntimeError(format!("Failed to create gitlab client: {}", e)))?;
+  let gitlab_logger = logger.clone();
+  let repositories: Vec<ImportedRepository> = runtime::block_on_interruptible(runtime::blocking("Gitlab import", timeout, move || {
+    list_gitlab_projects(gitlab_config, state, &gitlab_logger)
+  }))?;
 
-  let mut builder = gitlab::api::projects::Projects::builder();
-  builder.owned(true);
-  match state {
-    ProjectState::Active => {
-      builder.archived(false);
-    }
-    ProjectState::Archived => {
-      builder.archived(true);
-    }
-    ProjectState::Both => {}
+  save_imported_projects(current_config, "gitlab", repositories, "gitlab", None, options, logger).map(|_| ())
+}
+
+/// What the importers do with the repositories they find.
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+  /// What happens to repositories whose project name is taken.
+  pub on_conflict: OnConflict,
+  /// Overrides `import_naming` of the settings.
+  pub naming: Option<ImportNaming>,
+  /// Only repositories whose name matches are imported.
+  pub include: Option<Regex>,
+  /// Repositories whose name matches are skipped.
+  pub exclude: Option<Regex>,
+  /// Only print what would be added, skipped or in conflict, without writing to the config.
+  pub dry_run: bool,
+}
+
+impl ImportOptions {
+  /// Whether the repository `name` passes `include` and `exclude`.
+  pub fn takes(&self, name: &str) -> bool {
+    self.include.as_ref().is_none_or(|include| include.is_match(name)) && !self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(name))
   }
+}
 
-  // owned repos and your organizations repositories
-  let owned_projects: Vec<gitlab::Project> = gitlab::api::paged(builder.build().unwrap(), gitlab::api::Pagination::All)
-    .query(&gitlab_client)
-    .map_err(|e| AppError::RuntimeError(format!("Failed to query gitlab: {}", e)))?;
+/// A repository found by an importer.
+pub(crate) struct ImportedRepository {
+  pub name: String,
+  pub git: String,
+  /// The organization, group or owner, the prefix of `ImportNaming::OrgPrefix`.
+  pub org: Option<String>,
+  /// The primary language and the topics the forge reports, for `forge_tags` of the settings.
+  pub language: Option<String>,
+  pub topics: Vec<String>,
+}
 
-  let names_and_urls: Vec<(String, String)> = owned_projects
-    .iter()
-    .map(|repo| (repo.name.to_owned(), repo.ssh_url_to_repo.to_owned()))
-    .collect();
+fn in_org(names_and_urls: Vec<(String, String)>, org: Option<&str>) -> Vec<ImportedRepository> {
+  names_and_urls
+    .into_iter()
+    .map(|(name, git)| ImportedRepository {
+      name,
+      git,
+      org: org.map(str::to_owned),
+      language: None,
+      topics: Vec::new(),
+    })
+    .collect()
+}
+
+/// The row of `--dry-run` for an imported project: whether it would be added, skipped or is in conflict with `existing`.
+fn dry_run_row(project: &Project, existing: Option<&Project>, case_clash: Option<&str>, on_conflict: OnConflict) -> Vec<Cell> {
+  let (action, tone, note) = match (case_clash, existing) {
+    (Some(other), _) => ("skip", Tone::Muted, format!("only differs in case from {}", other)),
+    (None, None) => ("add", Tone::Good, String::new()),
+    (None, Some(existing)) if existing.git == project.git && on_conflict != OnConflict::Force => ("skip", Tone::Muted, "already present".to_owned()),
+    (None, Some(existing)) => {
+      let outcome = match on_conflict {
+        OnConflict::Force => "replaced",
+        OnConflict::Merge => "merged",
+        OnConflict::Ask => "asked about",
+        OnConflict::Fail => "refused",
+        OnConflict::Skip => "skipped",
+      };
+      ("conflict", Tone::Warning, format!("{} in the config, would be {}", existing.git, outcome))
+    }
+  };
+  vec![
+    Cell::new(action, tone),
+    Cell::plain(project.name.clone()),
+    Cell::new(project.git.clone(), Tone::Muted),
+    Cell::plain(note),
+  ]
+}
 
+/// Writes the imported repositories of `forge` as projects to `project_config_path`, named by the naming of `options` or
+/// the settings. `options.on_conflict` decides about those whose name is already in the config, names that only differ in
+/// case from an existing one are skipped. They get the tags for new projects and `tag`. Returns the names of the projects
+/// that were written, with `options.dry_run` none but a table of what would happen is printed.
+pub(crate) fn save_imported_projects(
+  current_config: Config,
+  forge: &str,
+  repositories: Vec<ImportedRepository>,
+  project_config_path: &str,
+  tag: Option<&str>,
+  options: &ImportOptions,
+  logger: &Logger,
+) -> Result<Vec<String>, AppError> {
   let after_clone = current_config.settings.default_after_clone.clone();
   let after_workon = current_config.settings.default_after_workon.clone();
-  let tags = current_config.settings.default_tags.clone();
-  let mut current_projects = current_config.projects;
+  let mut current_projects = current_config.all_projects();
+  let settings = current_config.settings;
+  let naming = options.naming.or(settings.import_naming).unwrap_or(ImportNaming::Name);
+  let mut saved = Vec::new();
+  let mut plan = Table::new();
 
-  for (name, url) in names_and_urls {
+  for repository in repositories {
+    if !options.takes(&repository.name) {
+      debug!(logger, "Skipping imported repository, filtered by --include or --exclude"; "repository" => &repository.name);
+      continue;
+    }
+    let url = settings.git_url(&repository.git);
+    let mut extra_tags: BTreeSet<String> = tag.map(str::to_owned).into_iter().collect();
+    if let Some(forge_tags) = &settings.forge_tags {
+      extra_tags.extend(forge_tags.tags_for(repository.language.as_deref(), &repository.topics));
+    }
     let p = Project {
-      name,
+      name: naming.apply(&repository.name, repository.org.as_deref(), forge),
+      kind: None,
+      tags: if extra_tags.is_empty() {
+        settings.tags_for_new_project(&url)
+      } else {
+        Some(settings.with_tags(&settings.tags_for_new_project(&url).unwrap_or_default(), extra_tags))
+      },
       git: url,
       after_clone: after_clone.clone(),
       after_workon: after_workon.clone(),
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       override_path: None,
-      tags: tags.clone(),
+      subdir: None,
       additional_remotes: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      commands: None,
+      clone_depth: None,
+      env: None,
       bare: None,
-      project_config_path: "gitlab".to_string(),
+      project_config_path: project_config_path.to_string(),
     };
 
-    if current_projects.contains_key(&p.name) {
-      info!(
-        logger,
-          "Skipping new project from Gitlab import because it already exists in the current fw config"; "project_name" => &p.name);
+    debug!(logger, "Imported project"; "project_name" => &p.name, "forge" => forge);
+    // the checkouts of `API` and `api` are the same directory on macOS
+    let case_clash = current_projects
+      .keys()
+      .find(|other| **other != p.name && other.eq_ignore_ascii_case(&p.name))
+      .cloned();
+    if options.dry_run {
+      plan.add_row(dry_run_row(&p, current_projects.get(&p.name), case_clash.as_deref(), options.on_conflict));
+      if case_clash.is_none() && !current_projects.contains_key(&p.name) {
+        current_projects.insert(p.name.clone(), p);
+      }
+      continue;
+    }
+    if let Some(other) = case_clash {
+      warn!(logger, "Skipping imported project, its name only differs in case from an existing one. Use another import_naming"; "project_name" => &p.name, "existing" => other);
+      continue;
+    }
+    if let Some(project) = add_project(&current_projects, p, options.on_conflict, logger)? {
+      saved.push(project.name.clone());
+      current_projects.insert(project.name.clone(), project); // to ensure no duplicated name encountered during processing
+    }
+  }
+
+  if options.dry_run {
+    if plan.is_empty() {
+      println!("{}", Tone::Muted.paint("Nothing to import"));
     } else {
-      info!(logger, "Saving new project"; "project_name" => &p.name);
-      config::write_project(&p)?; // TODO not sure if this should be default or gitlab subfolder? or even user specified?
-      current_projects.insert(p.name.clone(), p); // to ensure no duplicated name encountered during processing
+      plan.print();
     }
   }
 
-  Ok(())
+  Ok(saved)
 }
 
-pub fn org_import(maybe_config: Result<Config, AppError>, org_name: &str, include_archived: bool, logger: &Logger) -> Result<(), AppError> {
-  let current_config = maybe_config?;
-  let token = env::var_os("FW_GITHUB_TOKEN")
-    .map(|s| s.to_string_lossy().to_string())
-    .or_else(|| current_config.settings.github_token.clone())
-    .ok_or_else(|| {
-      AppError::UserError(format!(
-        "Can't call GitHub API for org {} because no github oauth token (settings.github_token) specified in the configuration.",
-        org_name
-      ))
-    })?;
-  let mut api = github::github_api(&token)?;
-  let org_repository_names: Vec<String> = api.list_repositories(org_name, include_archived)?;
-  let after_clone = current_config.settings.default_after_clone.clone();
-  let after_workon = current_config.settings.default_after_workon.clone();
-  let tags = current_config.settings.default_tags.clone();
-  let mut current_projects = current_config.projects;
+fn list_gitlab_projects(gitlab_config: GitlabSettings, state: ProjectState, logger: &Logger) -> Result<Vec<ImportedRepository>, AppError> {
+  use gitlab::api::Query;
 
-  for name in org_repository_names {
-    let p = Project {
-      name: name.clone(),
-      git: format!("git@github.com:{}/{}.git", org_name, name),
-      after_clone: after_clone.clone(),
-      after_workon: after_workon.clone(),
-      override_path: None,
-      tags: tags.clone(),
-      additional_remotes: None,
-      bare: None,
-      project_config_path: org_name.to_string(),
-    };
+  let gitlab_client = ForgeClient::gitlab(&gitlab_config.host, &gitlab_config.token, logger)?;
 
-    if current_projects.contains_key(&p.name) {
-      info!(
-        logger,
-          "Skipping new project from Github import because it already exists in the current fw config"; "project_name" => &p.name);
-    } else {
-      info!(logger, "Saving new project"; "project_name" => &p.name);
-      config::write_project(&p)?;
-      current_projects.insert(p.name.clone(), p); // to ensure no duplicated name encountered during processing
+  let mut builder = gitlab::api::projects::Projects::builder();
+  builder.owned(true);
+  match state {
+    ProjectState::Active => {
+      builder.archived(false);
+    }
+    ProjectState::Archived => {
+      builder.archived(true);
     }
+    ProjectState::Both => {}
   }
-  Ok(())
+
+  // owned repos and your organizations repositories
+  let owned_projects: Vec<gitlab::Project> = gitlab::api::paged(builder.build().unwrap(), gitlab::api::Pagination::All)
+    .query(&gitlab_client)
+    .map_err(|e| AppError::RuntimeError(format!("Failed to query gitlab: {}", e)))?;
+
+  Ok(
+    owned_projects
+      .iter()
+      .map(|repo| ImportedRepository {
+        name: repo.name.to_owned(),
+        git: repo.ssh_url_to_repo.to_owned(),
+        org: repo.path_with_namespace.rsplit_once('/').map(|(namespace, _)| namespace.to_owned()),
+        language: None,
+        topics: repo.tag_list.clone(),
+      })
+      .collect(),
+  )
+}
+
+pub fn org_import(
+  maybe_config: Result<Config, AppError>,
+  org_name: &str,
+  team: Option<&str>,
+  include_archived: bool,
+  timeout: Option<Duration>,
+  options: &ImportOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let current_config = maybe_config?;
+  let token = github::token(current_config.settings.github_token.as_ref()).ok_or_else(|| {
+    AppError::UserError(format!(
+      "Can't call GitHub API for org {} because no github oauth token (settings.github_token) specified in the configuration.",
+      org_name
+    ))
+  })?;
+  let api = github::github_api(&token, logger)?;
+  let org = org_name.to_string();
+  let team_slug = team.map(str::to_owned);
+  let org_repositories = runtime::block_on_interruptible(runtime::blocking("GitHub import", timeout, move || {
+    api.list_repositories(&org, team_slug.as_deref(), include_archived)
+  }))?;
+  let repositories = org_repositories
+    .into_iter()
+    .map(|r| ImportedRepository {
+      git: format!("git@github.com:{}/{}.git", org_name, r.name),
+      name: r.name,
+      org: Some(org_name.to_owned()),
+      language: r.language,
+      topics: r.topics,
+    })
+    .collect();
+  save_imported_projects(current_config, "github", repositories, org_name, None, options, logger).map(|_| ())
+}
+
+/// Imports the repositories starred by `user`, or by the owner of the GitHub token, that match `language` and `topic`. They
+/// are written to `oss` and tagged `oss`.
+pub fn github_stars_import(
+  maybe_config: Result<Config, AppError>,
+  user: Option<&str>,
+  language: Option<&str>,
+  topic: Option<&str>,
+  timeout: Option<Duration>,
+  options: &ImportOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let current_config = maybe_config?;
+  let api = match (github::token(current_config.settings.github_token.as_ref()), user) {
+    (Some(token), _) => github::github_api(&token, logger)?,
+    (None, Some(_)) => github::anonymous_github_api(logger)?,
+    (None, None) => {
+      return Err(AppError::UserError(
+        "Can't find your stars without a github oauth token (settings.github_token or FW_GITHUB_TOKEN), name the user with --user".to_owned(),
+      ))
+    }
+  };
+  let user_name = user.map(str::to_owned);
+  let starred = runtime::block_on_interruptible(runtime::blocking("GitHub import", timeout, move || {
+    api.starred_repositories(user_name.as_deref())
+  }))?;
+  let repositories = starred
+    .into_iter()
+    .filter(|r| r.matches(language, topic))
+    .map(|r| ImportedRepository {
+      name: r.name,
+      git: r.git,
+      org: Some(r.owner),
+      language: r.language,
+      topics: r.topics,
+    })
+    .collect();
+  save_imported_projects(current_config, "github", repositories, "oss", Some("oss"), options, logger).map(|_| ())
+}
+
+/// Imports the repositories of an organization or user from a Gitea compatible forge like Forgejo or Codeberg.
+pub fn gitea_import(
+  maybe_config: Result<Config, AppError>,
+  base_url: &str,
+  owner: &str,
+  include_archived: bool,
+  timeout: Option<Duration>,
+  options: &ImportOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let current_config = maybe_config?;
+  let token = gitea::token(current_config.settings.gitea_token.as_ref());
+  let api = gitea::gitea_api(base_url, token.as_deref(), logger)?;
+  let owner_name = owner.to_string();
+  let names_and_urls: Vec<(String, String)> = runtime::block_on_interruptible(runtime::blocking("Gitea import", timeout, move || {
+    api.list_repositories(&owner_name, include_archived)
+  }))?;
+  save_imported_projects(current_config, "gitea", in_org(names_and_urls, Some(owner)), owner, None, options, logger).map(|_| ())
+}
+
+/// Imports the git repositories of an Azure DevOps project, given as `organization/project`, and tags them with the project name.
+pub fn azure_import(
+  maybe_config: Result<Config, AppError>,
+  organization_and_project: &str,
+  timeout: Option<Duration>,
+  options: &ImportOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let current_config = maybe_config?;
+  let (organization, project) = organization_and_project
+    .split_once('/')
+    .filter(|(organization, project)| !organization.is_empty() && !project.is_empty())
+    .ok_or_else(|| AppError::UserError(format!("{} is not of the form organization/project", organization_and_project)))?;
+  let token = azure::token(current_config.settings.azure_token.as_ref()).ok_or_else(|| {
+    AppError::UserError(format!(
+      "Can't call Azure DevOps API for {} because no personal access token (settings.azure_token or FW_AZURE_TOKEN) specified in the configuration.",
+      organization_and_project
+    ))
+  })?;
+  let api = azure::azure_api(organization, &token, logger)?;
+  let project_name = project.to_string();
+  let names_and_urls: Vec<(String, String)> =
+    runtime::block_on_interruptible(runtime::blocking("Azure DevOps import", timeout, move || api.list_repositories(&project_name)))?;
+  save_imported_projects(
+    current_config,
+    "azure",
+    in_org(names_and_urls, Some(project)),
+    project,
+    Some(project),
+    options,
+    logger,
+  )
+  .map(|_| ())
 }
 
-pub fn import(maybe_config: Result<Config, AppError>, path: &str, logger: &Logger) -> Result<(), AppError> {
+/// Imports the AWS CodeCommit repositories of `region` with their HTTPS clone URLs, listed by the aws CLI.
+pub fn codecommit_import(
+  maybe_config: Result<Config, AppError>,
+  region: Option<&str>,
+  timeout: Option<Duration>,
+  options: &ImportOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let current_config = maybe_config?;
+  let region_name = region.map(str::to_owned);
+  let names_and_urls: Vec<(String, String)> = runtime::block_on_interruptible(runtime::blocking("CodeCommit import", timeout, move || {
+    cloud::list_codecommit_repositories(region_name.as_deref())
+  }))?;
+  save_imported_projects(
+    current_config,
+    "codecommit",
+    in_org(names_and_urls, region),
+    "codecommit",
+    None,
+    options,
+    logger,
+  )
+  .map(|_| ())
+}
+
+/// Imports the Google Cloud Source Repositories of `project` with their HTTPS clone URLs, listed by the gcloud CLI.
+pub fn cloud_source_import(
+  maybe_config: Result<Config, AppError>,
+  project: Option<&str>,
+  timeout: Option<Duration>,
+  options: &ImportOptions,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let current_config = maybe_config?;
+  let project_name = project.map(str::to_owned);
+  let names_and_urls: Vec<(String, String)> = runtime::block_on_interruptible(runtime::blocking("Cloud Source import", timeout, move || {
+    cloud::list_cloud_source_repositories(project_name.as_deref())
+  }))?;
+  save_imported_projects(
+    current_config,
+    "cloud-source",
+    in_org(names_and_urls, project),
+    "cloud-source",
+    None,
+    options,
+    logger,
+  )
+  .map(|_| ())
+}
+
+/// Adds the checkout at `path` as a project that keeps it through `override_path`, replacing a project of the same name.
+/// With `dry_run` only shows what would happen.
+pub fn import(maybe_config: Result<Config, AppError>, path: &str, dry_run: bool, logger: &Logger) -> Result<(), AppError> {
   let path = fs::canonicalize(Path::new(path))?;
   let project_path = path.to_str().ok_or(AppError::InternalError("project path is not valid unicode"))?.to_owned();
   let file_name = AppError::require(path.file_name(), AppError::UserError("Import path needs to be valid".to_string()))?;
   let project_name: String = file_name.to_string_lossy().into_owned();
-  let maybe_settings = maybe_config.ok().map(|c| c.settings);
-  let new_project = load_project(maybe_settings, path.clone(), &project_name, logger)?;
+  let config = maybe_config.ok();
+  let new_project = load_project(config.as_ref().map(|c| c.settings.clone()), path.clone(), &project_name, logger)?;
   let new_project_with_path = Project {
     override_path: Some(project_path),
     ..new_project
   };
-  config::write_project(&new_project_with_path)?;
+  if dry_run {
+    let existing = config.as_ref().and_then(|c| c.all_projects().get(&project_name).cloned());
+    let mut plan = Table::new();
+    plan.add_row(dry_run_row(&new_project_with_path, existing.as_ref(), None, OnConflict::Force));
+    plan.print();
+    return Ok(());
+  }
+  config::write_project(&new_project_with_path, logger)?;
   Ok(())
 }
 
@@ -219,12 +527,25 @@ fn load_project(maybe_settings: Option<Settings>, path_to_repo: PathBuf, name: &
   info!(project_logger, "git config validated");
   Ok(Project {
     name: name.to_owned(),
+    kind: None,
     git: url.to_owned(),
     after_clone: maybe_settings.clone().and_then(|s| s.default_after_clone),
     after_workon: maybe_settings.clone().and_then(|s| s.default_after_workon),
+    shell: None,
+    hook_timeout: None,
+    ssh_key: None,
+    lfs: None,
     override_path: None,
-    additional_remotes: None, // TODO: use remotes
-    tags: maybe_settings.and_then(|s| s.default_tags),
+    subdir: None,
+    additional_remotes: None,
+    pin: None,
+    archived: None,
+    archive_bundle: None,
+    depends_on: None,
+    commands: None,
+    clone_depth: None,
+    env: None,
+    tags: maybe_settings.and_then(|s| s.tags_for_new_project(url)),
     bare: None,
     project_config_path: "default".to_string(),
   })
@@ -236,13 +557,95 @@ fn write_new_config_with_projects(projects: BTreeMap<String, Project>, logger: &
     default_after_workon: None,
     default_after_clone: None,
     shell: None,
+    hook_timeout: None,
+    ssh_key: None,
+    lfs: None,
     github_token: None,
+    gitea_token: None,
+    azure_token: None,
     gitlab: None,
+    log_file: None,
+    readonly: None,
+    discover_config: None,
+    notify: None,
+    notify_command: None,
+    retries: None,
+    retry_backoff: None,
+    http_proxy: None,
+    https_proxy: None,
+    no_proxy: None,
+    ca_bundle: None,
+    import_naming: None,
+    time_tracking: None,
+    session_timeout: None,
+    layout: None,
+    prefer: None,
+    reference_repo: None,
+    max_network_operations: None,
+    bandwidth_limit: None,
+    hook_nice: None,
+    commands: None,
+    autotag: None,
+    project_defaults: None,
+    forge_tags: None,
+    tag_groups: None,
+    profiles: None,
+    shared_config: None,
   };
-  config::write_settings(&settings, &logger)?;
+  config::write_settings(&settings, logger)?;
   for p in projects.values() {
-    config::write_project(&p)?;
+    config::write_project(p, logger)?;
   }
   debug!(logger, "Finished"; "projects" => format!("{:?}", projects.len()));
   Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_dry_run_row() {
+    let fw = Project::example();
+    let fork = Project {
+      git: "git@github.com:fork/fw.git".to_owned(),
+      ..Project::example()
+    };
+    let row = |action: &str, tone: Tone, note: &str| {
+      vec![
+        Cell::new(action, tone),
+        Cell::plain("fw"),
+        Cell::new(fw.git.clone(), Tone::Muted),
+        Cell::plain(note),
+      ]
+    };
+    assert_that(&dry_run_row(&fw, None, None, OnConflict::Skip)).is_equal_to(row("add", Tone::Good, ""));
+    assert_that(&dry_run_row(&fw, Some(&fw), None, OnConflict::Skip)).is_equal_to(row("skip", Tone::Muted, "already present"));
+    assert_that(&dry_run_row(&fw, Some(&fw), None, OnConflict::Force)).is_equal_to(row(
+      "conflict",
+      Tone::Warning,
+      "git@github.com:brocode/fw.git in the config, would be replaced",
+    ));
+    assert_that(&dry_run_row(&fw, Some(&fork), None, OnConflict::Merge)).is_equal_to(row(
+      "conflict",
+      Tone::Warning,
+      "git@github.com:fork/fw.git in the config, would be merged",
+    ));
+    assert_that(&dry_run_row(&fw, None, Some("FW"), OnConflict::Skip)).is_equal_to(row("skip", Tone::Muted, "only differs in case from FW"));
+  }
+
+  #[test]
+  fn test_import_options_filter_names() {
+    let options = ImportOptions {
+      on_conflict: OnConflict::Skip,
+      naming: None,
+      include: Some(Regex::new("^service-").unwrap()),
+      exclude: Some(Regex::new("-deprecated$").unwrap()),
+      dry_run: false,
+    };
+    assert_that(&options.takes("service-api")).is_true();
+    assert_that(&options.takes("service-api-deprecated")).is_false();
+    assert_that(&options.takes("frontend")).is_false();
+  }
+}
@@ -0,0 +1,308 @@
+pub mod signature;
+
+use crate::config::path::fw_path;
+use crate::config::{self, project::Project, settings::SharedConfigSettings, Config};
+use crate::errors::AppError;
+use crate::git::clone_repository;
+use crate::output::Tone;
+use crate::trust;
+use crate::workon::looks_like_git_url;
+use slog::{debug, info, warn, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A project whose definition differs between the local config and the shared config.
+#[derive(Debug, Clone)]
+pub enum ProjectChange {
+  Added(Project),
+  Removed(Project),
+  /// The shared definition of the project and the keys it changes.
+  Changed {
+    upstream: Project,
+    fields: Vec<FieldChange>,
+  },
+}
+
+impl ProjectChange {
+  pub fn name(&self) -> &str {
+    match self {
+      ProjectChange::Added(project) | ProjectChange::Removed(project) | ProjectChange::Changed { upstream: project, .. } => &project.name,
+    }
+  }
+}
+
+/// A single key of a project file, `None` where the key is not set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+  pub key: String,
+  pub local: Option<String>,
+  pub upstream: Option<String>,
+}
+
+fn fields_of(project: &Project) -> Result<BTreeMap<String, toml::Value>, AppError> {
+  match toml::Value::try_from(project)? {
+    toml::Value::Table(table) => Ok(table.into_iter().collect()),
+    _ => Err(AppError::InternalError("Expected a project to serialize to a table")),
+  }
+}
+
+fn changed_fields(local: &Project, upstream: &Project) -> Result<Vec<FieldChange>, AppError> {
+  let local_fields = fields_of(local)?;
+  let upstream_fields = fields_of(upstream)?;
+  let keys: BTreeSet<&String> = local_fields.keys().chain(upstream_fields.keys()).collect();
+  Ok(
+    keys
+      .into_iter()
+      .filter(|key| local_fields.get(*key) != upstream_fields.get(*key))
+      .map(|key| FieldChange {
+        key: key.clone(),
+        local: local_fields.get(key).map(ToString::to_string),
+        upstream: upstream_fields.get(key).map(ToString::to_string),
+      })
+      .collect(),
+  )
+}
+
+/// Compares two sets of projects by name. Where the project file lives below `projects/` does not count as a change.
+pub fn diff_projects(local: &BTreeMap<String, Project>, upstream: &BTreeMap<String, Project>) -> Result<Vec<ProjectChange>, AppError> {
+  let mut changes = Vec::new();
+  let names: BTreeSet<&String> = local.keys().chain(upstream.keys()).collect();
+  for name in names {
+    match (local.get(name), upstream.get(name)) {
+      (None, Some(upstream)) => changes.push(ProjectChange::Added(upstream.clone())),
+      (Some(local), None) => changes.push(ProjectChange::Removed(local.clone())),
+      (Some(local), Some(upstream)) => {
+        let fields = changed_fields(local, upstream)?;
+        if !fields.is_empty() {
+          changes.push(ProjectChange::Changed {
+            upstream: upstream.clone(),
+            fields,
+          });
+        }
+      }
+      (None, None) => {}
+    }
+  }
+  Ok(changes)
+}
+
+fn cache_dir_for(source: &str) -> Result<PathBuf, AppError> {
+  let sanitized: String = source.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+  Ok(fw_path()?.cache.join("shared-config").join(sanitized))
+}
+
+// A fresh clone every time, the shared config is small and a stale checkout would show changes that are not there.
+fn checkout_shared_config(source: &str, git_ref: Option<&str>, logger: &Logger) -> Result<PathBuf, AppError> {
+  let path = cache_dir_for(source)?;
+  if path.exists() {
+    fs::remove_dir_all(&path)?;
+  }
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let source_project = Project {
+    name: "shared-config".to_owned(),
+    kind: None,
+    git: source.to_owned(),
+    after_clone: None,
+    after_workon: None,
+    shell: None,
+    hook_timeout: None,
+    ssh_key: None,
+    lfs: None,
+    override_path: None,
+    subdir: None,
+    bare: None,
+    tags: None,
+    pin: git_ref.map(ToOwned::to_owned),
+    archived: None,
+    archive_bundle: None,
+    depends_on: None,
+    commands: None,
+    clone_depth: None,
+    env: None,
+    additional_remotes: None,
+    project_config_path: "".to_owned(),
+  };
+  clone_repository(&source_project, &path, None, None, None, logger)?;
+  Ok(path)
+}
+
+/// Reads the projects of a shared config, given as a directory laid out like the fw config or as a git repository.
+/// With `keys` the projects are only read if the shared config is signed by one of them.
+pub fn read_shared_projects(
+  source: &str,
+  git_ref: Option<&str>,
+  keys: Option<&SharedConfigSettings>,
+  logger: &Logger,
+) -> Result<BTreeMap<String, Project>, AppError> {
+  let is_dir = Path::new(source).is_dir();
+  let base = if looks_like_git_url(source) || (is_dir && git_ref.is_some()) {
+    checkout_shared_config(source, git_ref, logger)?
+  } else if is_dir {
+    PathBuf::from(source)
+  } else {
+    return Err(AppError::UserError(format!("{} is neither a directory nor a git URL", source)));
+  };
+  let projects_dir = base.join("projects");
+  if !projects_dir.is_dir() {
+    return Err(AppError::UserError(format!("Shared config {} has no projects directory", source)));
+  }
+  match keys {
+    Some(keys) => {
+      signature::verify(&base, keys)?;
+      info!(logger, "Signature of the shared config verified"; "source" => source);
+    }
+    None => warn!(logger, "Shared config is not verified, add keys to shared_config in the settings to require a signature"; "source" => source),
+  }
+  debug!(logger, "Reading shared config"; "path" => projects_dir.to_string_lossy().to_string());
+  config::read_projects(&projects_dir, logger)
+}
+
+fn print_change(change: &ProjectChange) {
+  match change {
+    ProjectChange::Added(project) => println!("{}", Tone::Good.paint(&format!("+ {}", project.name))),
+    ProjectChange::Removed(project) => println!("{}", Tone::Bad.paint(&format!("- {}", project.name))),
+    ProjectChange::Changed { upstream, fields } => {
+      println!("{}", Tone::Warning.paint(&format!("~ {}", upstream.name)));
+      for field in fields {
+        println!(
+          "    {}: {} -> {}",
+          field.key,
+          Tone::Bad.paint(field.local.as_deref().unwrap_or("(unset)")),
+          Tone::Good.paint(field.upstream.as_deref().unwrap_or("(unset)"))
+        );
+      }
+    }
+  }
+}
+
+// Changed projects keep their place below `projects/` so that local organisation of the config survives an apply.
+fn apply_change(config: &Config, change: &ProjectChange, logger: &Logger) -> Result<(), AppError> {
+  match change {
+    ProjectChange::Added(project) => config::write_project(project, logger),
+    ProjectChange::Removed(project) => config::delete_project_config(project, logger),
+    ProjectChange::Changed { upstream, .. } => {
+      let local = config
+        .projects
+        .get(&upstream.name)
+        .ok_or(AppError::InternalError("Changed project is missing in the local config"))?;
+      config::write_project(
+        &Project {
+          project_config_path: local.project_config_path.clone(),
+          ..upstream.clone()
+        },
+        logger,
+      )
+    }
+  }
+}
+
+/// Shows how the local projects differ from a shared config. With `apply`, the shared definitions are written to the local config:
+/// all added and changed projects if `only` is empty, otherwise just the named ones. Projects are only removed when named.
+pub fn diff_config(
+  maybe_config: Result<Config, AppError>,
+  source: &str,
+  git_ref: Option<&str>,
+  apply: bool,
+  only: &[String],
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let upstream = read_shared_projects(source, git_ref, config.settings.shared_config.as_ref(), logger)?;
+  let changes = diff_projects(&config.projects, &upstream)?;
+
+  if let Some(unknown) = only.iter().find(|name| !changes.iter().any(|c| c.name() == name.as_str())) {
+    return Err(AppError::UserError(format!("Project {} does not differ from the shared config", unknown)));
+  }
+  if changes.is_empty() {
+    info!(logger, "Local config matches the shared config"; "source" => source);
+    return Ok(());
+  }
+
+  for change in &changes {
+    print_change(change);
+  }
+  if apply {
+    let selected = changes.iter().filter(|change| match change {
+      _ if !only.is_empty() => only.iter().any(|name| name == change.name()),
+      ProjectChange::Removed(_) => false,
+      _ => true,
+    });
+    for change in selected {
+      apply_change(&config, change, logger)?;
+      if !matches!(change, ProjectChange::Removed(_)) {
+        trust::mark_untrusted(change.name(), source, logger)?;
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  fn a_project(name: &str, git: &str) -> Project {
+    Project {
+      name: name.to_owned(),
+      git: git.to_owned(),
+      kind: None,
+      after_clone: None,
+      after_workon: None,
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
+      override_path: None,
+      subdir: None,
+      bare: None,
+      tags: None,
+      pin: None,
+      archived: None,
+      archive_bundle: None,
+      depends_on: None,
+      clone_depth: None,
+      env: None,
+      commands: None,
+      additional_remotes: None,
+      project_config_path: "default".to_owned(),
+    }
+  }
+
+  fn projects(projects: Vec<Project>) -> BTreeMap<String, Project> {
+    projects.into_iter().map(|p| (p.name.clone(), p)).collect()
+  }
+
+  #[test]
+  fn test_diff_projects() {
+    let local = projects(vec![a_project("same", "git@a"), a_project("changed", "git@b"), a_project("gone", "git@c")]);
+    let mut upstream_changed = a_project("changed", "git@b2");
+    upstream_changed.tags = Some(BTreeSet::from(["rust".to_owned()]));
+    upstream_changed.project_config_path = "team".to_owned();
+    let upstream = projects(vec![a_project("same", "git@a"), upstream_changed, a_project("new", "git@d")]);
+
+    let changes = diff_projects(&local, &upstream).unwrap();
+    let names: Vec<&str> = changes.iter().map(ProjectChange::name).collect();
+    assert_that(&names).is_equal_to(vec!["changed", "gone", "new"]);
+    assert_that(&matches!(changes[1], ProjectChange::Removed(_))).is_true();
+    assert_that(&matches!(changes[2], ProjectChange::Added(_))).is_true();
+    match &changes[0] {
+      ProjectChange::Changed { fields, .. } => assert_that(fields).is_equal_to(vec![
+        FieldChange {
+          key: "git".to_owned(),
+          local: Some("\"git@b\"".to_owned()),
+          upstream: Some("\"git@b2\"".to_owned()),
+        },
+        FieldChange {
+          key: "tags".to_owned(),
+          local: None,
+          upstream: Some("[\"rust\"]".to_owned()),
+        },
+      ]),
+      other => panic!("expected a change, got {:?}", other),
+    }
+  }
+}
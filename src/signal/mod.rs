@@ -0,0 +1,145 @@
+use crate::errors::AppError;
+use crate::output::{self, Tone};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// After the first Ctrl-C running operations get the chance to clean up (abort clones, remove partial checkouts).
+// A second Ctrl-C exits right away.
+pub fn install_handler() -> Result<(), AppError> {
+  ctrlc::set_handler(|| {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+      std::process::exit(130);
+    }
+    output::print_warning("Interrupted. Waiting for running operations to stop, press Ctrl-C again to exit immediately.");
+  })
+  .map_err(|e| AppError::RuntimeError(format!("Failed to install Ctrl-C handler: {}", e)))
+}
+
+pub fn interrupted() -> bool {
+  INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn check_interrupted() -> Result<(), AppError> {
+  if interrupted() {
+    Err(AppError::Interrupted)
+  } else {
+    Ok(())
+  }
+}
+
+/// Sleeps for `duration` or until Ctrl-C is pressed, whichever comes first.
+pub fn sleep_interruptibly(duration: Duration) {
+  let until = Instant::now() + duration;
+  while !interrupted() && Instant::now() < until {
+    std::thread::sleep(Duration::from_millis(250));
+  }
+}
+
+pub async fn interruption() {
+  while !interrupted() {
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  }
+}
+
+#[derive(Default)]
+pub struct BatchSummary {
+  completed: Vec<String>,
+  failed: Vec<String>,
+  aborted: Vec<String>,
+  /// Project names with the reason they were left out, e.g. `offline`.
+  skipped: Vec<(String, String)>,
+}
+
+impl BatchSummary {
+  pub fn record(&mut self, project_name: &str, result: &Result<(), AppError>) {
+    match result {
+      Ok(()) => self.completed.push(project_name.to_string()),
+      Err(AppError::Interrupted) => self.aborted.push(project_name.to_string()),
+      Err(_) => self.failed.push(project_name.to_string()),
+    }
+  }
+
+  /// `project_name` was left out on purpose, it neither completed nor failed.
+  pub fn record_skipped(&mut self, project_name: &str, reason: &str) {
+    self.skipped.push((project_name.to_string(), reason.to_string()));
+  }
+
+  /// Nothing failed or was aborted.
+  pub fn succeeded(&self) -> bool {
+    self.failed.is_empty() && self.aborted.is_empty()
+  }
+
+  /// A one line summary like `10 completed, 2 failed (a, b), 1 skipped: offline (c)`.
+  pub fn message(&self) -> String {
+    let mut message = format!("{} completed", self.completed.len());
+    if !self.failed.is_empty() {
+      message.push_str(&format!(", {} failed ({})", self.failed.len(), self.failed.join(", ")));
+    }
+    let mut skipped: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (project_name, reason) in &self.skipped {
+      skipped.entry(reason).or_default().push(project_name);
+    }
+    for (reason, project_names) in skipped {
+      message.push_str(&format!(", {} skipped: {} ({})", project_names.len(), reason, project_names.join(", ")));
+    }
+    if !self.aborted.is_empty() {
+      message.push_str(&format!(", {} aborted", self.aborted.len()));
+    }
+    message
+  }
+
+  pub fn print_if_interrupted(&self) {
+    if interrupted() {
+      eprintln!(
+        "{} {} completed, {} failed, {} aborted",
+        Tone::Warning.paint_err("Interrupted:"),
+        Tone::Good.paint_err(&self.completed.len().to_string()),
+        Tone::Bad.paint_err(&self.failed.len().to_string()),
+        self.aborted.len()
+      );
+      if !self.aborted.is_empty() {
+        eprintln!("{} {}", Tone::Warning.paint_err("Aborted:"), self.aborted.join(", "));
+      }
+    }
+  }
+
+  /// `{"operation":"sync","completed":["fw"],"failed":[],"aborted":[],"skipped":{"api":"offline"}}`
+  fn machine_report(&self, operation: &str) -> String {
+    let skipped: BTreeMap<&str, &str> = self.skipped.iter().map(|(name, reason)| (name.as_str(), reason.as_str())).collect();
+    serde_json::json!({
+      "operation": operation,
+      "completed": self.completed,
+      "failed": self.failed,
+      "aborted": self.aborted,
+      "skipped": skipped,
+    })
+    .to_string()
+  }
+
+  /// With `--non-interactive` the summary as one JSON line on stderr.
+  pub fn print_for_machines(&self, operation: &str) {
+    if output::non_interactive() {
+      eprintln!("{}", self.machine_report(operation));
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_machine_report() {
+    let mut summary = BatchSummary::default();
+    summary.record("fw", &Ok(()));
+    summary.record("api", &Err(AppError::UserError("failed".to_owned())));
+    summary.record("web", &Err(AppError::Interrupted));
+    summary.record_skipped("lib", "offline");
+    assert_that(&summary.machine_report("sync"))
+      .is_equal_to(r#"{"aborted":["web"],"completed":["fw"],"failed":["api"],"operation":"sync","skipped":{"lib":"offline"}}"#.to_owned());
+  }
+}
@@ -0,0 +1,366 @@
+use crate::config::{self, project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::git::{is_dirty, update_project_remotes};
+use crate::output::{print_warning, Cell, Table, Tone};
+use git2::build::CheckoutBuilder;
+use git2::{BranchType, ErrorCode, Oid, Repository};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use slog::{debug, info, o, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The git state of one project at the time of the snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectSnapshot {
+  pub git: String,
+  /// `None` when HEAD was detached.
+  pub branch: Option<String>,
+  /// `None` when the branch had no commits yet.
+  pub head: Option<String>,
+  /// Tracked files had uncommitted changes, which the snapshot does not contain.
+  pub dirty: bool,
+}
+
+/// Branches and commits of all selected projects, written by `fw snapshot` and read by `fw restore`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+  /// Seconds since the epoch.
+  pub created: u64,
+  pub projects: BTreeMap<String, ProjectSnapshot>,
+}
+
+/// The commit of one project in a manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+  pub git: String,
+  pub commit: String,
+}
+
+/// HEAD commits of all selected projects, written by `fw manifest write` and checked out by `fw manifest checkout`.
+/// Unlike a snapshot it has no branches or timestamps, so it can be committed and diffed. Release manifests of `fw release tag`
+/// can be checked out as well.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+  pub projects: BTreeMap<String, ManifestEntry>,
+}
+
+fn capture(project: &Project, path: &Path) -> Result<ProjectSnapshot, AppError> {
+  let repo = Repository::open(path)?;
+  let head = match repo.head() {
+    Ok(head) => head,
+    Err(ref e) if e.code() == ErrorCode::UnbornBranch => {
+      let symbolic = repo.find_reference("HEAD")?;
+      return Ok(ProjectSnapshot {
+        git: project.git.clone(),
+        branch: symbolic.symbolic_target().map(|target| target.trim_start_matches("refs/heads/").to_owned()),
+        head: None,
+        dirty: is_dirty(&repo)?,
+      });
+    }
+    Err(e) => return Err(e.into()),
+  };
+  Ok(ProjectSnapshot {
+    git: project.git.clone(),
+    branch: if head.is_branch() { head.shorthand().map(ToOwned::to_owned) } else { None },
+    head: Some(head.target().ok_or(AppError::InternalError("HEAD is not a direct reference"))?.to_string()),
+    dirty: is_dirty(&repo)?,
+  })
+}
+
+/// Records branch, HEAD and dirty state of the selected projects that are checked out.
+pub fn take_snapshot(config: &Config, selector: &ProjectSelector, logger: &Logger) -> Result<Snapshot, AppError> {
+  let captured: Vec<(String, Result<ProjectSnapshot, AppError>)> = selector
+    .select_repositories(config)
+    .par_iter()
+    .filter_map(|project| {
+      let path = config.checkout_path(project, logger);
+      if path.exists() {
+        Some((project.name.clone(), capture(project, &path)))
+      } else {
+        debug!(logger, "Not in snapshot, not checked out"; "project" => &project.name);
+        None
+      }
+    })
+    .collect();
+  let mut projects = BTreeMap::new();
+  for (name, snapshot) in captured {
+    projects.insert(
+      name.clone(),
+      snapshot.map_err(|e| AppError::UserError(format!("Could not snapshot {}: {}", name, e)))?,
+    );
+  }
+  Ok(Snapshot {
+    created: SystemTime::now().duration_since(UNIX_EPOCH).map_err(AppError::ClockError)?.as_secs(),
+    projects,
+  })
+}
+
+/// Writes a snapshot of the selected projects to `file`.
+pub fn snapshot(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, file: &Path, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let snapshot = take_snapshot(&config, selector, logger)?;
+  config::write_atomically(file, &format!("{}\n", serde_json::to_string_pretty(&snapshot)?))?;
+  for (name, project) in snapshot.projects.iter().filter(|(_, p)| p.dirty) {
+    print_warning(&format!(
+      "{} has uncommitted changes that are not part of the snapshot (HEAD {})",
+      name,
+      head_label(&project.head)
+    ));
+  }
+  info!(logger, "Snapshot written"; "path" => file.to_string_lossy().to_string(), "projects" => snapshot.projects.len());
+  Ok(())
+}
+
+fn short(sha: &str) -> &str {
+  &sha[..sha.len().min(8)]
+}
+
+fn head_label(head: &Option<String>) -> &str {
+  head.as_deref().map(short).unwrap_or("unborn")
+}
+
+/// What `fw restore` did with a project.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Restored {
+  /// The branch was checked out at the snapshot commit.
+  Branch(String),
+  /// HEAD was detached at the snapshot commit, because it was detached in the snapshot.
+  Detached,
+  /// The branch has moved since the snapshot, so HEAD was detached at the snapshot commit instead of moving the branch.
+  BranchMoved(String),
+  /// The branch had no commits in the snapshot, so there was nothing to check out.
+  Unborn,
+}
+
+// Detaches HEAD first so that the branch can be created or moved even if it is checked out right now.
+fn restore_project(project: &Project, path: &Path, snapshot: &ProjectSnapshot, force: bool, logger: &Logger) -> Result<Restored, AppError> {
+  let repo = Repository::open(path)?;
+  if !force && is_dirty(&repo)? {
+    return Err(AppError::UserError("has uncommitted changes, use --force to discard them".to_owned()));
+  }
+  let head = match &snapshot.head {
+    Some(head) => head,
+    None => return Ok(Restored::Unborn),
+  };
+  let oid = Oid::from_str(head)?;
+  if repo.find_commit(oid).is_err() {
+    debug!(logger, "Commit not found locally, fetching"; "commit" => head);
+    // the key of the project is in the core.sshCommand of the checkout since it was cloned or synced
+    update_project_remotes(project, &path.to_path_buf(), None, logger, false)?;
+  }
+  let commit = repo
+    .find_commit(oid)
+    .map_err(|_| AppError::UserError(format!("commit {} not found, not even after fetching", short(head))))?;
+
+  let mut checkout = CheckoutBuilder::new();
+  if force {
+    checkout.force();
+  } else {
+    checkout.safe();
+  }
+  repo.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+  repo.set_head_detached(oid)?;
+
+  let branch = match &snapshot.branch {
+    Some(branch) => branch,
+    None => return Ok(Restored::Detached),
+  };
+  if let Ok(existing) = repo.find_branch(branch, BranchType::Local) {
+    if existing.get().target() != Some(oid) && !force {
+      return Ok(Restored::BranchMoved(branch.clone()));
+    }
+  }
+  repo.branch(branch, &commit, true)?;
+  repo.set_head(&format!("refs/heads/{}", branch))?;
+  Ok(Restored::Branch(branch.clone()))
+}
+
+/// Checks out the branches and commits of a snapshot in all selected projects that are part of it.
+pub fn restore(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, file: &Path, force: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let raw = fs::read_to_string(file).map_err(|e| AppError::UserError(format!("Could not read snapshot {}: {}", file.to_string_lossy(), e)))?;
+  let snapshot: Snapshot = serde_json::from_str(&raw).map_err(|e| AppError::UserError(format!("Invalid snapshot {}: {}", file.to_string_lossy(), e)))?;
+  restore_snapshot(&config, selector, &snapshot, force, logger)
+}
+
+fn restore_snapshot(config: &Config, selector: &ProjectSelector, snapshot: &Snapshot, force: bool, logger: &Logger) -> Result<(), AppError> {
+  let selected: BTreeSet<&str> = selector.select_repositories(config).into_iter().map(|p| p.name.as_str()).collect();
+  let mut table = Table::new();
+  let mut failed = 0;
+  for (name, project_snapshot) in &snapshot.projects {
+    let name_cell = Cell::plain(name.clone());
+    let project = match config.projects.get(name).filter(|p| selected.contains(p.name.as_str())) {
+      Some(project) => project,
+      None if config.projects.contains_key(name) => continue,
+      None => {
+        table.add_row(vec![name_cell, Cell::new("not in config, skipped", Tone::Warning)]);
+        continue;
+      }
+    };
+    let path = config.checkout_path(project, logger);
+    if !path.exists() {
+      table.add_row(vec![name_cell, Cell::new("not checked out, run fw sync first", Tone::Warning)]);
+      continue;
+    }
+    let project_logger = logger.new(o!("project" => name.clone()));
+    let head = head_label(&project_snapshot.head).to_owned();
+    let (message, tone) = match restore_project(project, &path, project_snapshot, force, &project_logger) {
+      Ok(Restored::Branch(branch)) => (format!("{} at {}", branch, head), Tone::Good),
+      Ok(Restored::Detached) => (format!("detached at {}", head), Tone::Good),
+      Ok(Restored::BranchMoved(branch)) => (format!("detached at {}, {} has moved (--force resets it)", head, branch), Tone::Warning),
+      Ok(Restored::Unborn) => ("had no commits, left alone".to_owned(), Tone::Muted),
+      Err(AppError::UserError(message)) => {
+        failed += 1;
+        (message, Tone::Bad)
+      }
+      Err(e) => {
+        failed += 1;
+        (format!("{}", e), Tone::Bad)
+      }
+    };
+    let note = if project_snapshot.dirty {
+      Cell::new("had uncommitted changes", Tone::Muted)
+    } else {
+      Cell::plain("")
+    };
+    table.add_row(vec![name_cell, Cell::new(message, tone), note]);
+  }
+  table.print();
+
+  if failed > 0 {
+    Err(AppError::UserError(format!("Could not restore {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+/// Writes the HEAD commits of the selected projects to `file`.
+pub fn write_manifest(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, file: &Path, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let snapshot = take_snapshot(&config, selector, logger)?;
+  for (name, project) in snapshot.projects.iter().filter(|(_, p)| p.dirty) {
+    print_warning(&format!(
+      "{} has uncommitted changes that are not part of the manifest (HEAD {})",
+      name,
+      head_label(&project.head)
+    ));
+  }
+  let mut projects = BTreeMap::new();
+  for (name, project) in snapshot.projects {
+    match project.head {
+      Some(commit) => {
+        projects.insert(name, ManifestEntry { git: project.git, commit });
+      }
+      None => print_warning(&format!("{} has no commits yet and is not part of the manifest", name)),
+    }
+  }
+  let manifest = Manifest { projects };
+  config::write_atomically(file, &format!("{}\n", serde_json::to_string_pretty(&manifest)?))?;
+  info!(logger, "Manifest written"; "path" => file.to_string_lossy().to_string(), "projects" => manifest.projects.len());
+  Ok(())
+}
+
+// A snapshot without branches, so every project ends up with a detached HEAD.
+fn manifest_snapshot(manifest: Manifest) -> Snapshot {
+  Snapshot {
+    created: 0,
+    projects: manifest
+      .projects
+      .into_iter()
+      .map(|(name, entry)| {
+        (
+          name,
+          ProjectSnapshot {
+            git: entry.git,
+            branch: None,
+            head: Some(entry.commit),
+            dirty: false,
+          },
+        )
+      })
+      .collect(),
+  }
+}
+
+/// Detaches HEAD at the commits of a manifest in all selected projects that are part of it.
+pub fn checkout_manifest(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  file: &Path,
+  force: bool,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let raw = fs::read_to_string(file).map_err(|e| AppError::UserError(format!("Could not read manifest {}: {}", file.to_string_lossy(), e)))?;
+  let manifest: Manifest = serde_json::from_str(&raw).map_err(|e| AppError::UserError(format!("Invalid manifest {}: {}", file.to_string_lossy(), e)))?;
+  restore_snapshot(&config, selector, &manifest_snapshot(manifest), force, logger)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::temp_dir;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_snapshot_roundtrip() {
+    let mut projects = BTreeMap::new();
+    projects.insert(
+      "fw".to_owned(),
+      ProjectSnapshot {
+        git: "git@github.com:brocode/fw.git".to_owned(),
+        branch: Some("master".to_owned()),
+        head: Some("0123456789abcdef0123456789abcdef01234567".to_owned()),
+        dirty: true,
+      },
+    );
+    let snapshot = Snapshot { created: 42, projects };
+    let parsed: Snapshot = serde_json::from_str(&serde_json::to_string_pretty(&snapshot).unwrap()).unwrap();
+    assert_that(&parsed).is_equal_to(&snapshot);
+    assert_that(&head_label(&snapshot.projects["fw"].head)).is_equal_to("01234567");
+  }
+
+  #[test]
+  fn test_capture_unborn_branch() {
+    let dir = temp_dir();
+    Repository::init(dir.path()).unwrap().set_head("refs/heads/trunk").unwrap();
+    let project = Project {
+      name: "fw".to_owned(),
+      git: "git@github.com:brocode/fw.git".to_owned(),
+      tags: None,
+      override_path: None,
+      ..Project::example()
+    };
+
+    let captured = capture(&project, dir.path());
+
+    assert_that(&captured.unwrap()).is_equal_to(&ProjectSnapshot {
+      git: "git@github.com:brocode/fw.git".to_owned(),
+      branch: Some("trunk".to_owned()),
+      head: None,
+      dirty: false,
+    });
+  }
+
+  #[test]
+  fn test_manifest_of_release() {
+    let raw = r#"{
+      "version": "v1.2.3",
+      "created": 42,
+      "projects": {
+        "fw": {"git": "git@github.com:brocode/fw.git", "commit": "0123456789abcdef0123456789abcdef01234567", "tag": "89abcdef", "pushed": true}
+      }
+    }"#;
+    let manifest: Manifest = serde_json::from_str(raw).unwrap();
+    let snapshot = manifest_snapshot(manifest);
+    assert_that(&snapshot.projects["fw"]).is_equal_to(&ProjectSnapshot {
+      git: "git@github.com:brocode/fw.git".to_owned(),
+      branch: None,
+      head: Some("0123456789abcdef0123456789abcdef01234567".to_owned()),
+      dirty: false,
+    });
+  }
+}
@@ -1,23 +1,91 @@
-use crate::config::{project::Project, Config};
+use crate::config::{order, path::fw_path, project::Project, selector::ProjectSelector, Config};
 use crate::errors::AppError;
 
 use ansi_term::Colour;
 use rayon::prelude::*;
-use std::collections::BTreeSet;
 
 use slog::Logger;
 use slog::{error, info};
 use std::borrow::ToOwned;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
+use crate::notify::{self, Notification};
+use crate::output;
+use crate::signal::{self, BatchSummary};
+use crate::trust;
 use crate::util::random_colour;
 use slog::{debug, o};
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
 
 use std::thread;
+use std::time::{Duration, Instant};
 
-fn forward_process_output_to_stdout<T: std::io::Read>(read: T, prefix: &str, colour: Colour, atty: bool, mark_err: bool) -> Result<(), AppError> {
+// How many of the last lines of a hook's output go into the error when it fails
+const CAPTURED_LINES: usize = 20;
+
+/// The output of a hook: the last lines for the error report and, instead of printing it, maybe a log file.
+struct HookOutput {
+  last_lines: Mutex<VecDeque<String>>,
+  log: Option<(PathBuf, Mutex<File>)>,
+}
+
+impl HookOutput {
+  fn new(log: Option<&Path>, cmd: &str) -> Result<HookOutput, AppError> {
+    let log = match log {
+      Some(path) => {
+        let mut file = File::create(path)?;
+        writeln!(file, "$ {}", cmd)?;
+        Some((path.to_path_buf(), Mutex::new(file)))
+      }
+      None => None,
+    };
+    Ok(HookOutput {
+      last_lines: Mutex::new(VecDeque::new()),
+      log,
+    })
+  }
+
+  /// Keeps the line and returns whether it still has to be printed.
+  fn record(&self, line: &str) -> Result<bool, AppError> {
+    let mut lines = self.last_lines.lock().expect("hook output poisoned");
+    if lines.len() == CAPTURED_LINES {
+      lines.pop_front();
+    }
+    lines.push_back(line.trim_end().to_owned());
+    match &self.log {
+      Some((_, file)) => {
+        file.lock().expect("hook log poisoned").write_all(line.as_bytes())?;
+        Ok(false)
+      }
+      None => Ok(true),
+    }
+  }
+
+  fn error(&self, message: String) -> AppError {
+    let lines = self.last_lines.lock().expect("hook output poisoned");
+    let mut report = vec![message];
+    if !lines.is_empty() {
+      report.push(format!("Last output:\n{}", lines.iter().cloned().collect::<Vec<String>>().join("\n")));
+    }
+    if let Some((path, _)) = &self.log {
+      report.push(format!("Full output in {}", path.to_string_lossy()));
+    }
+    AppError::UserError(report.join("\n"))
+  }
+}
+
+fn forward_process_output_to_stdout<T: std::io::Read>(
+  read: T,
+  prefix: &str,
+  colour: Colour,
+  atty: bool,
+  mark_err: bool,
+  hook_output: Option<&HookOutput>,
+) -> Result<(), AppError> {
   let mut buf = BufReader::new(read);
   loop {
     let mut line = String::new();
@@ -25,6 +93,11 @@ fn forward_process_output_to_stdout<T: std::io::Read>(read: T, prefix: &str, col
     if read == 0 {
       break;
     }
+    if let Some(hook_output) = hook_output {
+      if !hook_output.record(&line)? {
+        continue;
+      }
+    }
     if mark_err {
       let prefix = format!("{:>21.21} |", prefix);
       if atty {
@@ -45,33 +118,47 @@ fn forward_process_output_to_stdout<T: std::io::Read>(read: T, prefix: &str, col
 }
 
 fn is_stdout_a_tty() -> bool {
-  atty::is(atty::Stream::Stdout)
+  output::colors_enabled(atty::Stream::Stdout)
 }
 
 fn is_stderr_a_tty() -> bool {
-  atty::is(atty::Stream::Stderr)
+  output::colors_enabled(atty::Stream::Stderr)
 }
 
-pub fn spawn_maybe(shell: &[String], cmd: &str, workdir: &PathBuf, project_name: &str, colour: Colour, logger: &Logger) -> Result<(), AppError> {
+fn shell_command(shell: &[String], cmd: &str, workdir: &PathBuf, project_name: &str, env: &BTreeMap<String, String>) -> Result<Command, AppError> {
   let program: &str = shell
     .first()
     .ok_or_else(|| AppError::UserError("shell entry in project settings must have at least one element".to_owned()))?;
   let rest: &[String] = shell.split_at(1).1;
-  let mut result: Child = Command::new(program)
+  let mut command = Command::new(program);
+  command
     .args(rest)
     .arg(cmd)
-    .current_dir(&workdir)
+    .current_dir(workdir)
+    .envs(env)
     .env("FW_PROJECT", project_name)
     .stdout(Stdio::piped())
     .stderr(Stdio::piped())
-    .stdin(Stdio::null())
-    .spawn()?;
+    .stdin(Stdio::null());
+  Ok(command)
+}
+
+pub fn spawn_maybe(
+  shell: &[String],
+  cmd: &str,
+  workdir: &PathBuf,
+  project_name: &str,
+  env: &BTreeMap<String, String>,
+  colour: Colour,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let mut result: Child = shell_command(shell, cmd, workdir, project_name, env)?.spawn()?;
 
   let stdout_child = if let Some(stdout) = result.stdout.take() {
     let project_name = project_name.to_owned();
     Some(thread::spawn(move || {
       let atty: bool = is_stdout_a_tty();
-      forward_process_output_to_stdout(stdout, &project_name, colour, atty, false)
+      forward_process_output_to_stdout(stdout, &project_name, colour, atty, false, None)
     }))
   } else {
     None
@@ -80,7 +167,7 @@ pub fn spawn_maybe(shell: &[String], cmd: &str, workdir: &PathBuf, project_name:
   // stream stderr in this thread. no need to spawn another one.
   if let Some(stderr) = result.stderr.take() {
     let atty: bool = is_stderr_a_tty();
-    forward_process_output_to_stdout(stderr, project_name, colour, atty, true)?
+    forward_process_output_to_stdout(stderr, project_name, colour, atty, true, None)?
   }
 
   if let Some(child) = stdout_child {
@@ -97,6 +184,98 @@ pub fn spawn_maybe(shell: &[String], cmd: &str, workdir: &PathBuf, project_name:
   }
 }
 
+// Kills the process group `child` leads, or only `child` if that fails.
+#[cfg(unix)]
+fn kill_group(child: &mut Child) -> std::io::Result<()> {
+  // SAFETY: kill has no memory effects, a negative pid addresses the process group
+  if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } == 0 {
+    Ok(())
+  } else {
+    child.kill()
+  }
+}
+
+#[cfg(not(unix))]
+fn kill_group(child: &mut Child) -> std::io::Result<()> {
+  child.kill()
+}
+
+// Polls instead of blocking in wait() so the hook can be killed when it runs out of time or on Ctrl-C.
+pub(crate) fn wait_until(child: &mut Child, deadline: Option<Instant>) -> Result<Option<ExitStatus>, AppError> {
+  loop {
+    if let Some(status) = child.try_wait()? {
+      return Ok(Some(status));
+    }
+    if signal::interrupted() || deadline.map(|d| Instant::now() >= d).unwrap_or(false) {
+      kill_group(child)?;
+      child.wait()?;
+      return Ok(None);
+    }
+    thread::sleep(Duration::from_millis(50));
+  }
+}
+
+/// The log file for the hook output of `project_name` in the state directory.
+pub fn hook_log_path(project_name: &str) -> Result<PathBuf, AppError> {
+  Ok(fw_path()?.ensure_state_dir_exists("hooks")?.join(format!("{}.log", project_name)))
+}
+
+/// Runs a hook like `spawn_maybe`, but kills it once `timeout` has passed. With a `log` the output goes there instead
+/// of the terminal. The error of a failed or killed hook includes the last lines it printed.
+pub fn spawn_hook(
+  shell: &[String],
+  cmd: &str,
+  workdir: &PathBuf,
+  project_name: &str,
+  env: &BTreeMap<String, String>,
+  colour: Colour,
+  timeout: Option<Duration>,
+  log: Option<&Path>,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let deadline = timeout.map(|t| Instant::now() + t);
+  let hook_output = Arc::new(HookOutput::new(log, cmd)?);
+  let mut command = shell_command(shell, cmd, workdir, project_name, env)?;
+  // its own process group, so that killing it reaches the processes it started as well
+  #[cfg(unix)]
+  std::os::unix::process::CommandExt::process_group(&mut command, 0);
+  let mut child: Child = command.spawn()?;
+  let forwarders: Vec<thread::JoinHandle<Result<(), AppError>>> = vec![
+    child.stdout.take().map(|stdout| {
+      let (project_name, hook_output) = (project_name.to_owned(), hook_output.clone());
+      thread::spawn(move || forward_process_output_to_stdout(stdout, &project_name, colour, is_stdout_a_tty(), false, Some(&hook_output)))
+    }),
+    child.stderr.take().map(|stderr| {
+      let (project_name, hook_output) = (project_name.to_owned(), hook_output.clone());
+      thread::spawn(move || forward_process_output_to_stdout(stderr, &project_name, colour, is_stderr_a_tty(), true, Some(&hook_output)))
+    }),
+  ]
+  .into_iter()
+  .flatten()
+  .collect();
+
+  match wait_until(&mut child, deadline)? {
+    // Processes that left the hook's process group can keep its output open, so the forwarders are left behind.
+    None => {
+      signal::check_interrupted()?;
+      error!(logger, "hook timed out");
+      Err(hook_output.error(format!("Timed out after {:?} and was killed", timeout.unwrap_or_default())))
+    }
+    Some(status) => {
+      for forwarder in forwarders {
+        forwarder.join().expect("Must be able to join child")?;
+      }
+      if status.success() {
+        info!(logger, "hook finished");
+        Ok(())
+      } else {
+        error!(logger, "hook failed");
+        Err(hook_output.error(format!("Exited with {}", status)))
+      }
+    }
+  }
+}
+
 pub fn init_threads(parallel_raw: &Option<String>, logger: &Logger) -> Result<(), AppError> {
   if let Some(ref raw_num) = *parallel_raw {
     let num_threads = raw_num.parse::<usize>()?;
@@ -108,28 +287,196 @@ pub fn init_threads(parallel_raw: &Option<String>, logger: &Logger) -> Result<()
   Ok(())
 }
 
+// Runs the command of each project from `commands`, by project name.
+fn run_batch(
+  config: &Config,
+  projects: Vec<&Project>,
+  commands: &BTreeMap<String, String>,
+  ordered: bool,
+  operation: &str,
+  notify: bool,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let started = Instant::now();
+  let levels: Vec<Vec<&Project>> = if ordered { order::in_dependency_order(&projects)? } else { vec![projects] };
+
+  let mut summary = BatchSummary::default();
+  let mut failed: BTreeSet<&str> = BTreeSet::new();
+  let mut script_results: Vec<Result<(), AppError>> = Vec::new();
+  for level in levels {
+    let level_results = level
+      .par_iter()
+      .map(|p| {
+        signal::check_interrupted()?;
+        if let Some(dependency) = p.dependencies().find(|d| failed.contains(d)) {
+          return Err(AppError::RuntimeError(format!("Skipped {}: dependency {} failed", p.name, dependency)));
+        }
+        let project_logger = logger.new(o!("project" => p.name.clone()));
+        config.check_workspace_choice(p, &project_logger)?;
+        let shell = config.resolve_shell(p, &project_logger);
+        let path = config.actual_path_to_project(p, &project_logger);
+        let cmd = commands.get(&p.name).ok_or(AppError::InternalError("project without command in batch"))?;
+        let env = config.resolve_env(p, &project_logger);
+        info!(project_logger, "Entering");
+        spawn_maybe(&shell, cmd, &path, &p.name, &env, random_colour(), &project_logger)
+      })
+      .collect::<Vec<Result<(), AppError>>>();
+
+    for (project, result) in level.iter().zip(level_results.iter()) {
+      summary.record(&project.name, result);
+      if result.is_err() {
+        failed.insert(&project.name);
+      }
+    }
+    script_results.extend(level_results);
+  }
+  summary.print_if_interrupted();
+  summary.print_for_machines(operation);
+  notify::batch_finished(
+    &config.settings,
+    notify,
+    &Notification::for_batch(operation, &summary, started.elapsed()),
+    logger,
+  );
+
+  signal::check_interrupted().and(script_results.into_iter().fold(Ok(()), Result::and))
+}
+
 pub fn foreach(
   maybe_config: Result<Config, AppError>,
   cmd: &str,
-  tags: &BTreeSet<String>,
+  selector: &ProjectSelector,
+  ordered: bool,
   logger: &Logger,
   parallel_raw: &Option<String>,
+  notify: bool,
 ) -> Result<(), AppError> {
   let config = maybe_config?;
   init_threads(parallel_raw, logger)?;
 
-  let projects: Vec<&Project> = config.projects.values().collect();
-  let script_results = projects
-    .par_iter()
-    .filter(|p| tags.is_empty() || p.tags.clone().unwrap_or_default().intersection(tags).count() > 0)
-    .map(|p| {
-      let shell = config.settings.get_shell_or_default();
-      let project_logger = logger.new(o!("project" => p.name.clone()));
-      let path = config.actual_path_to_project(p, &project_logger);
-      info!(project_logger, "Entering");
-      spawn_maybe(&shell, cmd, &path, &p.name, random_colour(), &project_logger)
-    })
-    .collect::<Vec<Result<(), AppError>>>();
+  let projects: Vec<&Project> = selector
+    .select(&config)
+    .into_iter()
+    .filter(|p| trust::allows_shell(&config, p, logger))
+    .collect();
+  let commands: BTreeMap<String, String> = projects.iter().map(|p| (p.name.clone(), cmd.to_owned())).collect();
+  run_batch(&config, projects, &commands, ordered, "foreach", notify, logger)
+}
 
-  script_results.into_iter().fold(Ok(()), Result::and)
+/// Runs the command `name` from the `commands` of the settings, tags or projects in every selected project that has one.
+pub fn run(
+  maybe_config: Result<Config, AppError>,
+  name: &str,
+  selector: &ProjectSelector,
+  ordered: bool,
+  logger: &Logger,
+  parallel_raw: &Option<String>,
+  notify: bool,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  init_threads(parallel_raw, logger)?;
+
+  let mut commands: BTreeMap<String, String> = BTreeMap::new();
+  let mut projects: Vec<&Project> = Vec::new();
+  for project in selector.select(&config) {
+    match config.resolve_command(name, project, logger) {
+      Some(cmd) if !trust::allows_hook(&config, project, &format!("command {}", name), &cmd, logger) => (),
+      Some(cmd) => {
+        debug!(logger, "Resolved command"; "project" => &project.name, "cmd" => &cmd);
+        commands.insert(project.name.clone(), cmd);
+        projects.push(project);
+      }
+      None => debug!(logger, "No such command, skipping"; "project" => &project.name, "command" => name),
+    }
+  }
+  if projects.is_empty() {
+    return Err(AppError::UserError(format!(
+      "None of the projects has a command '{}', define it in commands of the settings, a tag or a project",
+      name
+    )));
+  }
+  run_batch(&config, projects, &commands, ordered, &format!("run {}", name), notify, logger)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_spawn_hook_timeout() {
+    let logger = Logger::root(slog::Discard, o!());
+    let shell = vec!["sh".to_owned(), "-c".to_owned()];
+    let workdir = std::env::temp_dir();
+    let started = Instant::now();
+    let result = spawn_hook(
+      &shell,
+      "echo started; sleep 10",
+      &workdir,
+      "test",
+      &BTreeMap::new(),
+      Colour::Blue,
+      Some(Duration::from_millis(300)),
+      None,
+      &logger,
+    );
+    assert_that(&started.elapsed()).is_less_than(Duration::from_secs(5));
+    match result {
+      Err(AppError::UserError(message)) => {
+        assert_that(&message).starts_with("Timed out after 300ms");
+        assert_that(&message).ends_with("and was killed\nLast output:\nstarted");
+      }
+      other => panic!("expected a timeout, got {:?}", other),
+    }
+    let log = workdir.join(format!("fw-hook-test-{}.log", std::process::id()));
+    let failed = spawn_hook(
+      &shell,
+      "echo broken >&2; exit 3",
+      &workdir,
+      "test",
+      &BTreeMap::new(),
+      Colour::Blue,
+      None,
+      Some(&log),
+      &logger,
+    );
+    assert_that(&format!("{:?}", failed)).contains("Exited with exit status: 3\\nLast output:\\nbroken\\nFull output in");
+    assert_that(&std::fs::read_to_string(&log).unwrap()).is_equal_to("$ echo broken >&2; exit 3\nbroken\n".to_owned());
+    std::fs::remove_file(&log).unwrap();
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn test_spawn_hook_timeout_kills_what_the_hook_started() {
+    let logger = Logger::root(slog::Discard, o!());
+    let shell = vec!["sh".to_owned(), "-c".to_owned()];
+    let workdir = std::env::temp_dir();
+    let pid_file = workdir.join(format!("fw-hook-group-test-{}.pid", std::process::id()));
+    let hook = format!("sleep 30 & echo $! > {}; wait", pid_file.to_string_lossy());
+    let result = spawn_hook(
+      &shell,
+      &hook,
+      &workdir,
+      "test",
+      &BTreeMap::new(),
+      Colour::Blue,
+      Some(Duration::from_millis(300)),
+      None,
+      &logger,
+    );
+    assert_that(&result.is_err()).is_true();
+    let pid = std::fs::read_to_string(&pid_file).unwrap().trim().to_owned();
+    std::fs::remove_file(&pid_file).unwrap();
+    // gone, or a zombie nobody reaped yet
+    let killed = || {
+      std::fs::read_to_string(format!("/proc/{}/stat", pid))
+        .map(|stat| stat.rsplit(") ").next().unwrap_or_default().starts_with('Z'))
+        .unwrap_or(true)
+    };
+    let started = Instant::now();
+    while !killed() && started.elapsed() < Duration::from_secs(5) {
+      thread::sleep(Duration::from_millis(20));
+    }
+    assert_that(&killed()).is_true();
+  }
 }
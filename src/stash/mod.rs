@@ -0,0 +1,192 @@
+use crate::config::path::fw_path;
+use crate::config::{self, project::Project, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::output::{Cell, Table, Tone};
+use git2::{ErrorCode, Oid, Repository, Signature, StashApplyOptions, StashFlags};
+use serde::{Deserialize, Serialize};
+use slog::{debug, warn, Logger};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The stashes made by `fw stash-all` that `fw unstash-all` has not popped yet, by project.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StashRecord {
+  projects: BTreeMap<String, String>,
+}
+
+fn record_file() -> Result<PathBuf, AppError> {
+  Ok(fw_path()?.state.join("stash.json"))
+}
+
+fn read_record(path: &Path, logger: &Logger) -> StashRecord {
+  match fs::read_to_string(path) {
+    Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+      warn!(logger, "Ignoring unreadable stash record"; "path" => path.to_string_lossy().to_string(), "error" => format!("{}", e));
+      StashRecord::default()
+    }),
+    Err(_) => StashRecord::default(),
+  }
+}
+
+fn write_record(path: &Path, record: &StashRecord) -> Result<(), AppError> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  config::write_atomically(path, &serde_json::to_string(record)?)
+}
+
+pub(crate) fn stash(repo: &mut Repository, include_untracked: bool) -> Result<Option<Oid>, AppError> {
+  let signature = repo.signature().or_else(|_| Signature::now("fw", "fw@localhost"))?;
+  let flags = if include_untracked {
+    StashFlags::INCLUDE_UNTRACKED
+  } else {
+    StashFlags::DEFAULT
+  };
+  match repo.stash_save(&signature, "fw stash-all", Some(flags)) {
+    Ok(oid) => Ok(Some(oid)),
+    Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+    Err(e) => Err(AppError::GitError(e)),
+  }
+}
+
+// The stash list is a stack, so the index of our stash moves when the user stashed something on top of it.
+fn stash_index(repo: &mut Repository, oid: Oid) -> Result<Option<usize>, AppError> {
+  let mut found = None;
+  repo.stash_foreach(|index, _, stash_oid| {
+    if *stash_oid == oid {
+      found = Some(index);
+    }
+    found.is_none()
+  })?;
+  Ok(found)
+}
+
+pub(crate) fn unstash(repo: &mut Repository, oid: Oid) -> Result<bool, AppError> {
+  match stash_index(repo, oid)? {
+    Some(index) => {
+      repo.stash_pop(index, Some(&mut StashApplyOptions::new()))?;
+      Ok(true)
+    }
+    None => Ok(false),
+  }
+}
+
+fn checked_out<'a>(config: &'a Config, selector: &ProjectSelector, logger: &Logger) -> Vec<(&'a Project, PathBuf)> {
+  selector
+    .select_repositories(config)
+    .into_iter()
+    .map(|project| (project, config.checkout_path(project, logger)))
+    .filter(|(_, path)| path.exists())
+    .collect()
+}
+
+fn error_cell(e: AppError) -> Cell {
+  match e {
+    AppError::UserError(message) => Cell::new(message, Tone::Bad),
+    other => Cell::new(format!("{}", other), Tone::Bad),
+  }
+}
+
+/// Stashes uncommitted changes in every selected project and records which projects were stashed.
+pub fn stash_all(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, include_untracked: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let path = record_file()?;
+  let mut record = read_record(&path, logger);
+  let mut table = Table::new();
+  let mut failed = 0;
+  for (project, project_path) in checked_out(&config, selector, logger) {
+    let name = Cell::plain(project.name.clone());
+    if record.projects.contains_key(&project.name) {
+      table.add_row(vec![name, Cell::new("already stashed, run fw unstash-all first", Tone::Warning)]);
+      continue;
+    }
+    match Repository::open(&project_path)
+      .map_err(AppError::from)
+      .and_then(|mut repo| stash(&mut repo, include_untracked))
+    {
+      Ok(Some(oid)) => {
+        record.projects.insert(project.name.clone(), oid.to_string());
+        table.add_row(vec![name, Cell::new("stashed", Tone::Good)]);
+      }
+      Ok(None) => debug!(logger, "Nothing to stash"; "project" => &project.name),
+      Err(e) => {
+        failed += 1;
+        table.add_row(vec![name, error_cell(e)]);
+      }
+    }
+  }
+  write_record(&path, &record)?;
+  table.print();
+  if failed > 0 {
+    Err(AppError::UserError(format!("Could not stash {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+/// Pops the stashes `stash_all` made in the selected projects. Projects whose stash does not apply cleanly keep it.
+pub fn unstash_all(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let path = record_file()?;
+  let mut record = read_record(&path, logger);
+  let mut table = Table::new();
+  let mut failed = 0;
+  for (project, project_path) in checked_out(&config, selector, logger) {
+    let recorded = match record.projects.get(&project.name) {
+      Some(oid) => oid,
+      None => continue,
+    };
+    let name = Cell::plain(project.name.clone());
+    let oid = Oid::from_str(recorded).map_err(|_| AppError::UserError(format!("the recorded stash {} is not a commit id", recorded)));
+    match oid.and_then(|oid| {
+      Repository::open(&project_path)
+        .map_err(AppError::from)
+        .and_then(|mut repo| unstash(&mut repo, oid))
+    }) {
+      Ok(true) => {
+        record.projects.remove(&project.name);
+        table.add_row(vec![name, Cell::new("restored", Tone::Good)]);
+      }
+      Ok(false) => {
+        record.projects.remove(&project.name);
+        table.add_row(vec![name, Cell::new("stash is gone, dropped or popped by hand", Tone::Warning)]);
+      }
+      Err(e) => {
+        failed += 1;
+        table.add_row(vec![name, error_cell(e)]);
+      }
+    }
+  }
+  write_record(&path, &record)?;
+  table.print();
+  if failed > 0 {
+    Err(AppError::UserError(format!("Could not restore {} project(s), their stash is kept", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{commit_file, temp_dir};
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_stash_and_unstash() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let mut repo = Repository::init(dir).unwrap();
+    commit_file(&repo, "file", "committed");
+
+    assert_that(&stash(&mut repo, false).unwrap()).is_none();
+    fs::write(dir.join("file"), "changed").unwrap();
+    let oid = stash(&mut repo, false).unwrap().unwrap();
+    assert_that(&fs::read_to_string(dir.join("file")).unwrap()).is_equal_to("committed".to_owned());
+
+    assert_that(&unstash(&mut repo, oid).unwrap()).is_true();
+    assert_that(&fs::read_to_string(dir.join("file")).unwrap()).is_equal_to("changed".to_owned());
+    assert_that(&unstash(&mut repo, oid).unwrap()).is_false();
+  }
+}
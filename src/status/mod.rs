@@ -0,0 +1,290 @@
+use crate::config::path::fw_path;
+use crate::config::{self, project::Project, selector::ProjectSelector, Config};
+use crate::daemon;
+use crate::errors::AppError;
+use crate::git::resolve_pin;
+use crate::output::{Cell, Table, Tone};
+use crate::remotes;
+use git2::{BranchType, Repository};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use slog::{debug, warn, Logger};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Last known git state of a checked out project.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RepoStatus {
+  pub head: String,
+  pub upstream: Option<String>,
+  pub branch: Option<String>,
+  pub ahead: usize,
+  pub behind: usize,
+  /// Seconds since the epoch of the last fetch, taken from `FETCH_HEAD`.
+  pub last_fetch: Option<u64>,
+  /// The pin of the project, if any.
+  #[serde(default)]
+  pub pin: Option<String>,
+  /// HEAD is not at the pinned revision.
+  #[serde(default)]
+  pub drifted: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct StatusCache {
+  projects: BTreeMap<String, RepoStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProjectStatus {
+  pub(crate) name: String,
+  #[serde(flatten)]
+  pub(crate) status: Option<RepoStatus>,
+  error: Option<String>,
+  /// The new git URL if the forge has the repository under another name, only with `--check-remotes`.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  moved_to: Option<String>,
+}
+
+fn cache_file() -> Result<PathBuf, AppError> {
+  Ok(fw_path()?.cache.join("state.json"))
+}
+
+fn read_cache(path: &Path, logger: &Logger) -> StatusCache {
+  match fs::read_to_string(path) {
+    Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+      warn!(logger, "Ignoring unreadable status cache"; "path" => path.to_string_lossy().to_string(), "error" => format!("{}", e));
+      StatusCache::default()
+    }),
+    Err(_) => StatusCache::default(),
+  }
+}
+
+fn write_cache(path: &Path, cache: &StatusCache) -> Result<(), AppError> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  config::write_atomically(path, &serde_json::to_string(cache)?)
+}
+
+fn last_fetch(repo: &Repository) -> Option<u64> {
+  fs::metadata(repo.path().join("FETCH_HEAD"))
+    .and_then(|m| m.modified())
+    .ok()
+    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+    .map(|d| d.as_secs())
+}
+
+fn drifted(repo: &Repository, pin: Option<&String>, head: git2::Oid) -> bool {
+  pin
+    .map(|pin| resolve_pin(repo, pin).map(|pinned| pinned != head).unwrap_or(true))
+    .unwrap_or(false)
+}
+
+// Only the ahead/behind walk is expensive, so a cached entry is reused as long as HEAD and its upstream did not move.
+fn repo_status(path: &Path, pin: Option<&String>, cached: Option<&RepoStatus>) -> Result<RepoStatus, AppError> {
+  let repo = Repository::open(path)?;
+  let head = repo.head()?;
+  let head_oid = head.target().ok_or(AppError::InternalError("HEAD is not a direct reference"))?;
+  let branch = if head.is_branch() { head.shorthand().map(ToOwned::to_owned) } else { None };
+  let upstream_oid = branch
+    .as_ref()
+    .and_then(|name| repo.find_branch(name, BranchType::Local).ok())
+    .and_then(|local| local.upstream().ok())
+    .and_then(|upstream| upstream.get().target());
+  let head = head_oid.to_string();
+  let upstream = upstream_oid.map(|oid| oid.to_string());
+
+  if let Some(cached) = cached.filter(|c| c.head == head && c.upstream == upstream) {
+    return Ok(RepoStatus {
+      last_fetch: last_fetch(&repo),
+      pin: pin.cloned(),
+      drifted: drifted(&repo, pin, head_oid),
+      ..cached.clone()
+    });
+  }
+
+  let (ahead, behind) = match upstream_oid {
+    Some(upstream_oid) => repo.graph_ahead_behind(head_oid, upstream_oid)?,
+    None => (0, 0),
+  };
+  Ok(RepoStatus {
+    head,
+    upstream,
+    branch,
+    ahead,
+    behind,
+    last_fetch: last_fetch(&repo),
+    pin: pin.cloned(),
+    drifted: drifted(&repo, pin, head_oid),
+  })
+}
+
+pub(crate) fn format_age(seconds: u64) -> String {
+  match seconds {
+    s if s < 60 => "just now".to_owned(),
+    s if s < 60 * 60 => format!("{}m ago", s / 60),
+    s if s < 60 * 60 * 24 => format!("{}h ago", s / (60 * 60)),
+    s => format!("{}d ago", s / (60 * 60 * 24)),
+  }
+}
+
+fn status_row(project_status: &ProjectStatus, now: u64) -> Vec<Cell> {
+  let name = Cell::plain(project_status.name.clone());
+  match (&project_status.status, &project_status.error) {
+    (Some(status), _) => {
+      let fetched = status
+        .last_fetch
+        .map(|t| format!("fetched {}", format_age(now.saturating_sub(t))))
+        .unwrap_or_else(|| "never fetched".to_owned());
+      let pinned = match (&status.pin, status.drifted) {
+        (Some(pin), true) => Cell::new(format!("DRIFTED from pin {}", pin), Tone::Bad),
+        (Some(pin), false) => Cell::new(format!("pinned to {}", pin), Tone::Muted),
+        (None, _) => Cell::plain(""),
+      };
+      let branch = match &status.branch {
+        Some(branch) => Cell::plain(branch.clone()),
+        None => Cell::new("(detached)", Tone::Warning),
+      };
+      let count_tone = |count: usize, tone: Tone| if count > 0 { tone } else { Tone::Muted };
+      vec![
+        name,
+        branch,
+        Cell::new(format!("+{}", status.ahead), count_tone(status.ahead, Tone::Good)),
+        Cell::new(format!("-{}", status.behind), count_tone(status.behind, Tone::Warning)),
+        Cell::new(fetched, Tone::Muted),
+        pinned,
+      ]
+    }
+    (None, Some(error)) => vec![name, Cell::new(error.clone(), Tone::Bad)],
+    (None, None) => vec![name, Cell::new("missing", Tone::Warning)],
+  }
+}
+
+/// Queries the selected projects and updates the status cache. Unchanged repositories are answered from the cache if `use_cache`.
+pub(crate) fn refresh(config: &Config, selector: &ProjectSelector, use_cache: bool, logger: &Logger) -> Result<Vec<ProjectStatus>, AppError> {
+  let cache_path = cache_file()?;
+  let cache = if use_cache { read_cache(&cache_path, logger) } else { StatusCache::default() };
+
+  let projects: Vec<&Project> = selector.select_repositories(config);
+  let statuses: Vec<ProjectStatus> = projects
+    .par_iter()
+    .map(|project| {
+      let path = config.checkout_path(project, logger);
+      let (status, error) = if !path.exists() {
+        (None, None)
+      } else {
+        match repo_status(&path, project.pin.as_ref(), cache.projects.get(&project.name)) {
+          Ok(status) => (Some(status), None),
+          Err(e) => (None, Some(format!("{}", e))),
+        }
+      };
+      ProjectStatus {
+        name: project.name.clone(),
+        status,
+        error,
+        moved_to: None,
+      }
+    })
+    .collect();
+
+  let mut new_cache = cache;
+  for project_status in &statuses {
+    match &project_status.status {
+      Some(status) => new_cache.projects.insert(project_status.name.clone(), status.clone()),
+      None => new_cache.projects.remove(&project_status.name),
+    };
+  }
+  if let Err(e) = write_cache(&cache_path, &new_cache) {
+    warn!(logger, "Could not write status cache"; "path" => cache_path.to_string_lossy().to_string(), "error" => format!("{}", e));
+  } else {
+    debug!(logger, "Status cache written"; "path" => cache_path.to_string_lossy().to_string());
+  }
+  Ok(statuses)
+}
+
+/// Shows branch, ahead/behind and last fetch of the selected projects. Unchanged repositories are answered from the cache.
+/// With `check_remotes` GitHub and GitLab are asked whether the repositories moved.
+pub fn status(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  json: bool,
+  use_cache: bool,
+  check_remotes: bool,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let mut statuses = refresh(&config, selector, use_cache, logger)?;
+  let moved = if check_remotes {
+    remotes::moved_repositories(&config, selector, None, logger)?
+  } else {
+    Vec::new()
+  };
+  for moved_repository in &moved {
+    if let Some(project_status) = statuses.iter_mut().find(|s| s.name == moved_repository.project) {
+      project_status.moved_to = Some(moved_repository.new_git.clone());
+    }
+  }
+
+  if json {
+    println!("{}", serde_json::to_string(&statuses)?);
+  } else {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(AppError::ClockError)?.as_secs();
+    let mut table = Table::new();
+    for project_status in &statuses {
+      table.add_row(status_row(project_status, now));
+    }
+    table.print();
+    for moved_repository in &moved {
+      println!(
+        "{}",
+        Tone::Warning.paint(&format!("{} moved to {}", moved_repository.project, moved_repository.new_git))
+      );
+    }
+    if !moved.is_empty() {
+      println!("{}", Tone::Muted.paint("Run fw fix-remotes to point the config and the checkouts there"));
+    }
+    if let Some(daemon) = daemon::last_state() {
+      let (line, tone) = daemon::describe(&daemon, now);
+      println!("{}", tone.paint(&line));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_format_age() {
+    assert_that(&format_age(5)).is_equal_to("just now".to_owned());
+    assert_that(&format_age(120)).is_equal_to("2m ago".to_owned());
+    assert_that(&format_age(3 * 60 * 60)).is_equal_to("3h ago".to_owned());
+    assert_that(&format_age(2 * 24 * 60 * 60 + 5)).is_equal_to("2d ago".to_owned());
+  }
+
+  #[test]
+  fn test_cache_roundtrip() {
+    let mut cache = StatusCache::default();
+    cache.projects.insert(
+      "fw".to_owned(),
+      RepoStatus {
+        head: "abc".to_owned(),
+        upstream: None,
+        branch: Some("master".to_owned()),
+        ahead: 1,
+        behind: 2,
+        last_fetch: Some(42),
+        pin: None,
+        drifted: false,
+      },
+    );
+    let raw = serde_json::to_string(&cache).unwrap();
+    let parsed: StatusCache = serde_json::from_str(&raw).unwrap();
+    assert_that(&parsed.projects).is_equal_to(&cache.projects);
+  }
+}
@@ -1,117 +1,391 @@
-use crate::config::{project::Project, Config};
+use crate::config::{order, project::Project, selector::ProjectSelector, Config};
 use crate::errors::AppError;
-use std::collections::BTreeSet;
 
-use crate::git::{clone_project, update_project_remotes};
+use crate::git::{clone_project, clone_repository, run_after_clone, update_lfs, update_project_remotes, with_retries, RetryPolicy};
 
-use crossbeam::queue::SegQueue;
+use crate::lock::{self, LockMode};
+use crate::network;
+use crate::notify::{self, Notification};
+use crate::output::{self, Tone};
+use crate::runtime;
+use crate::signal::{self, BatchSummary};
 
-use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 
 use slog::Drain;
 use slog::Logger;
 use slog::{o, warn};
 use std::borrow::ToOwned;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
 
 use std::sync::Arc;
-use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
 
 #[cfg(unix)]
 use std::os::unix::fs::FileTypeExt;
 
-fn sync_project(config: &Config, project: &Project, logger: &Logger, only_new: bool, ff_merge: bool) -> Result<(), AppError> {
-  let path = config.actual_path_to_project(project, logger);
+// A project's name, its result or `None` when it was skipped offline, and the number of attempts.
+type JobResult = (String, Option<Result<(), AppError>>, u32);
+
+/// How long the remote hosts get to accept a connection before sync assumes there is no network.
+const OFFLINE_DETECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Returns the number of attempts along with the result.
+fn sync_project(config: &Config, project: &Project, logger: &Logger, options: &SyncOptions) -> (Result<(), AppError>, u32) {
+  let _lock = match lock::lock_project(&project.name, options.lock_mode, logger) {
+    Ok(Some(lock)) => lock,
+    Ok(None) => return (Ok(()), 1),
+    Err(e) => return (Err(e), 1),
+  };
+  if let Err(e) = config.check_workspace_choice(project, logger) {
+    return (Err(e), 1);
+  }
+  let project = &config.preferred(project);
+  let path = config.checkout_path(project, logger);
   let exists = path.exists();
   let project_logger = logger.new(o!(
+    "project" => project.name.clone(),
     "git" => project.git.clone(),
     "exists" => exists,
     "path" => format!("{:?}", path),
   ));
-  let result = if exists {
-    if only_new {
+  // every attempt looks at the checkout again: a failed clone leaves nothing behind and is cloned again
+  let policy = RetryPolicy {
+    timeout: options.timeout,
+    ..RetryPolicy::from_settings(&config.settings)
+  };
+  let ssh_key = config.resolve_ssh_key(project, &project_logger);
+  let reference = config.reference_repo();
+  let depth = config.resolve_clone_depth(project, &project_logger);
+  let (result, attempts) = with_retries(&policy, &project_logger, || {
+    if path.exists() {
+      if options.only_new {
+        Ok(())
+      } else {
+        update_project_remotes(project, &path, ssh_key.as_deref(), &project_logger, options.ff_merge)
+      }
+    } else if options.only_existing {
       Ok(())
+    } else if options.no_hooks {
+      clone_repository(project, &path, ssh_key.as_deref(), reference.as_deref(), depth, &project_logger)
     } else {
-      update_project_remotes(project, &path, &project_logger, ff_merge)
+      clone_project(config, project, &path, &project_logger)
     }
-  } else {
-    clone_project(config, project, &path, &project_logger)
-  };
-  result.map_err(|e| AppError::RuntimeError(format!("Failed to sync {}: {}", project.name, e)))
+  });
+  let synced = if exists { !options.only_new } else { !options.only_existing };
+  let result = result.and_then(|_| {
+    if synced && config.resolve_lfs(project, &project_logger) {
+      update_lfs(&path, !exists, project.bare.unwrap_or_default(), &project_logger)
+    } else {
+      Ok(())
+    }
+  });
+  let result = result.map_err(|e| {
+    if signal::interrupted() {
+      AppError::Interrupted
+    } else {
+      AppError::RuntimeError(format!("Failed to sync {}: {}", project.name, e))
+    }
+  });
+  (result, attempts)
 }
 
-pub fn synchronize(
-  maybe_config: Result<Config, AppError>,
-  no_progress_bar: bool,
-  only_new: bool,
-  ff_merge: bool,
-  tags: &BTreeSet<String>,
-  worker: i32,
+// The steps of a project after the project it shares the clone with through `subdir` fetched it, or cloned it when
+// `fresh`. `lfs_done` when the LFS objects were pulled for another project of the clone already.
+fn sync_shared_project(
+  config: &Config,
+  project: &Project,
+  path: &PathBuf,
+  fresh: bool,
+  lfs_done: bool,
   logger: &Logger,
+  options: &SyncOptions,
 ) -> Result<(), AppError> {
-  eprintln!("Synchronizing everything");
-  if !ssh_agent_running() {
-    warn!(logger, "SSH Agent not running. Process may hang.")
+  let _lock = match lock::lock_project(&project.name, options.lock_mode, logger)? {
+    Some(lock) => lock,
+    None => return Ok(()),
+  };
+  config.check_workspace_choice(project, logger)?;
+  let project = &config.preferred(project);
+  let project_logger = logger.new(o!("project" => project.name.clone(), "path" => format!("{:?}", path)));
+  let result = if fresh && !options.no_hooks {
+    run_after_clone(config, project, path, &project_logger)
+  } else {
+    Ok(())
+  };
+  result
+    .and_then(|_| {
+      if !lfs_done && config.resolve_lfs(project, &project_logger) {
+        update_lfs(path, fresh, project.bare.unwrap_or_default(), &project_logger)
+      } else {
+        Ok(())
+      }
+    })
+    .map_err(|e| {
+      if signal::interrupted() {
+        AppError::Interrupted
+      } else {
+        AppError::RuntimeError(format!("Failed to sync {}: {}", project.name, e))
+      }
+    })
+}
+
+// The first of the projects sharing one clone fetches or clones it, the others only run their own steps in it. Every
+// project's result comes with its number of attempts.
+fn sync_checkout(config: &Config, projects: &[Project], logger: &Logger, options: &SyncOptions) -> Vec<(String, Result<(), AppError>, u32)> {
+  let owner = &projects[0];
+  let path = config.checkout_path(owner, logger);
+  let existed = path.exists();
+  let (result, attempts) = sync_project(config, owner, logger, options);
+  let synced = result.is_ok() && path.exists() && if existed { !options.only_new } else { !options.only_existing };
+  let owner_failed = result.is_err();
+  let mut lfs_done = synced && config.resolve_lfs(owner, logger);
+  let mut results = vec![(owner.name.clone(), result, attempts)];
+  for project in &projects[1..] {
+    let result = if owner_failed {
+      Err(AppError::RuntimeError(format!(
+        "Skipped {}: the sync of {}, whose clone it shares, failed",
+        project.name, owner.name
+      )))
+    } else if synced {
+      sync_shared_project(config, project, &path, !existed, lfs_done, logger, options)
+    } else {
+      Ok(())
+    };
+    lfs_done = lfs_done || (result.is_ok() && synced && config.resolve_lfs(project, logger));
+    results.push((project.name.clone(), result, 0));
   }
-  let no_progress_bar = no_progress_bar || logger.is_debug_enabled();
-  let config = Arc::new(maybe_config?);
+  results
+}
+
+// Projects sharing a clone through `subdir` are synchronized together, so the clone is fetched once.
+fn checkout_levels(config: &Config, selected: &[&Project], logger: &Logger) -> Result<Vec<Vec<Vec<Project>>>, AppError> {
+  let mut checkouts: BTreeMap<PathBuf, Vec<&Project>> = BTreeMap::new();
+  for project in selected {
+    checkouts.entry(config.checkout_path(project, logger)).or_default().push(project);
+  }
+  Ok(
+    order::groups_in_dependency_order(checkouts.into_values().collect())?
+      .into_iter()
+      .map(|level| level.into_iter().map(|group| group.into_iter().map(ToOwned::to_owned).collect()).collect())
+      .collect(),
+  )
+}
 
-  let projects: Vec<Project> = config.projects.values().map(ToOwned::to_owned).collect();
-  let q: Arc<SegQueue<Project>> = Arc::new(SegQueue::new());
-  let projects_count = projects.len() as u64;
+/// `a (2 attempts), b (3 attempts, failed)` for the projects that needed more than one attempt.
+fn retry_report(attempts: &[(String, u32)], failed: &BTreeSet<String>) -> Option<String> {
+  let retried: Vec<String> = attempts
+    .iter()
+    .filter(|(_, attempts)| *attempts > 1)
+    .map(|(name, attempts)| {
+      if failed.contains(name) {
+        format!("{} ({} attempts, failed)", name, attempts)
+      } else {
+        format!("{} ({} attempts)", name, attempts)
+      }
+    })
+    .collect();
+  if retried.is_empty() {
+    None
+  } else {
+    Some(retried.join(", "))
+  }
+}
+
+/// Options for [`synchronize`].
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+  /// Hide the progress bar.
+  pub no_progress_bar: bool,
+  /// Only clone missing projects, leave existing ones alone.
+  pub only_new: bool,
+  /// Only fetch projects that are checked out, do not clone missing ones.
+  pub only_existing: bool,
+  /// Fast forward the checked out branch to its upstream after fetching.
+  pub ff_merge: bool,
+  /// Number of projects synchronized concurrently.
+  pub worker: i32,
+  /// Give up on a single project after this long.
+  pub timeout: Option<Duration>,
+  pub selector: ProjectSelector,
+  /// What to do with projects locked by another fw process.
+  pub lock_mode: LockMode,
+  /// Send a desktop notification when done, even if the settings do not ask for it.
+  pub notify: bool,
+  /// Clone without running the after_clone hooks.
+  pub no_hooks: bool,
+}
+
+impl Default for SyncOptions {
+  fn default() -> SyncOptions {
+    SyncOptions {
+      no_progress_bar: false,
+      only_new: false,
+      only_existing: false,
+      ff_merge: true,
+      worker: 8,
+      timeout: None,
+      selector: ProjectSelector::all(),
+      lock_mode: LockMode::default(),
+      notify: false,
+      no_hooks: false,
+    }
+  }
+}
 
-  projects
-    .into_iter()
-    .filter(|p| tags.is_empty() || p.tags.clone().unwrap_or_default().intersection(tags).count() > 0)
-    .for_each(|p| q.push(p));
+/// Clones missing projects and fetches the remotes of existing ones. Offline (`fw --offline`, or when no remote host
+/// answers) the projects with a remote on the network are skipped. Directory projects are never synchronized.
+pub fn synchronize(maybe_config: Result<Config, AppError>, options: &SyncOptions, logger: &Logger) -> Result<(), AppError> {
+  eprintln!("Synchronizing everything");
+  let started = Instant::now();
+  let no_progress_bar = options.no_progress_bar || logger.is_debug_enabled() || output::non_interactive();
+  let config = Arc::new(maybe_config?);
 
-  let spinner_style = ProgressStyle::default_spinner()
-    .tick_chars("⣾⣽⣻⢿⡿⣟⣯⣷⣿")
-    .template("{prefix:.bold.dim} {spinner} {wide_msg}");
+  let selected = options.selector.select_git_projects(&config);
+  let offline = network::offline() || {
+    let offline = network::detect_offline(selected.iter().map(|p| p.git.as_str()), OFFLINE_DETECTION_TIMEOUT);
+    if offline {
+      warn!(
+        logger,
+        "No remote host is reachable, skipping what needs the network. Use --offline to skip this check."
+      );
+    }
+    offline
+  };
+  let skipped: BTreeSet<String> = if offline {
+    selected
+      .iter()
+      .filter(|p| network::remote_host(&p.git).is_some())
+      .map(|p| p.name.clone())
+      .collect()
+  } else {
+    BTreeSet::new()
+  };
+  if !offline && !ssh_agent_running() {
+    warn!(logger, "SSH Agent not running. Process may hang.")
+  }
+  let project_count = selected.len();
+  let levels = checkout_levels(&config, &selected, logger)?;
 
-  let m = MultiProgress::new();
-  m.set_draw_target(if no_progress_bar {
+  let progress_bar = ProgressBar::new(project_count as u64);
+  progress_bar.set_draw_target(if no_progress_bar {
     ProgressDrawTarget::hidden()
   } else {
     ProgressDrawTarget::stderr()
   });
+  progress_bar.set_style(
+    ProgressStyle::default_spinner()
+      .tick_chars("⣾⣽⣻⢿⡿⣟⣯⣷⣿")
+      .template("{spinner} [{pos}/{len}] {wide_msg}"),
+  );
+  progress_bar.enable_steady_tick(250);
 
-  let job_results: Arc<SegQueue<Result<(), AppError>>> = Arc::new(SegQueue::new());
-  let progress_bars = (1..=worker).map(|i| {
-    let pb = m.add(ProgressBar::new(projects_count));
-    pb.set_style(spinner_style.clone());
-    pb.set_prefix(&format!("[{}/{}]", i, worker));
-    pb.set_message("initializing...");
-    pb.tick();
-    pb.enable_steady_tick(250);
-    pb
-  });
-  for pb in progress_bars {
-    let job_q = Arc::clone(&q);
-    let job_config = Arc::clone(&config);
-    let job_logger = logger.clone();
-    let job_result_queue = Arc::clone(&job_results);
-    thread::spawn(move || {
-      let mut job_result: Result<(), AppError> = Result::Ok(());
-      loop {
-        if let Some(project) = job_q.pop() {
-          pb.set_message(&project.name);
-          let sync_result = sync_project(&job_config, &project, &job_logger, only_new, ff_merge);
-          job_result = job_result.and(sync_result);
-        } else {
-          pb.finish_with_message("waiting...");
-          break;
+  let result = runtime::block_on(async {
+    let worker = match config.settings.max_network_operations {
+      Some(max) => options.worker.min(max as i32),
+      None => options.worker,
+    };
+    let semaphore = Arc::new(Semaphore::new(worker.max(1) as usize));
+    let mut summary = BatchSummary::default();
+    let mut synchronize_result: Result<(), AppError> = Ok(());
+    let mut failed: BTreeSet<String> = BTreeSet::new();
+    let mut attempts: Vec<(String, u32)> = Vec::new();
+
+    // a level only starts once everything it depends on is synchronized
+    for level in levels {
+      let jobs: Vec<_> = level
+        .into_iter()
+        .map(|projects| {
+          let semaphore = Arc::clone(&semaphore);
+          let job_config = Arc::clone(&config);
+          let job_logger = logger.clone();
+          let progress_bar = progress_bar.clone();
+          let job_options = options.clone();
+          let failed_dependency = projects
+            .iter()
+            .flat_map(|p| p.dependencies())
+            .find(|d| failed.contains(*d))
+            .map(ToOwned::to_owned);
+          // the projects of a clone have the same remote
+          let skip = skipped.contains(&projects[0].name);
+          tokio::spawn(async move {
+            let permit = semaphore.acquire_owned().await;
+            let names: Vec<String> = projects.iter().map(|p| p.name.clone()).collect();
+            let each =
+              |result: &dyn Fn(&str) -> Option<Result<(), AppError>>| -> Vec<JobResult> { names.iter().map(|name| (name.clone(), result(name), 0)).collect() };
+            // None for projects that are skipped because fw is offline
+            let results = if signal::interrupted() {
+              each(&|_| Some(Err(AppError::Interrupted)))
+            } else if skip {
+              each(&|_| None)
+            } else if let Some(dependency) = failed_dependency {
+              each(&|name| Some(Err(AppError::RuntimeError(format!("Skipped {}: dependency {} failed", name, dependency)))))
+            } else {
+              progress_bar.set_message(&names.join(", "));
+              let task_name = format!("Sync of {}", names.join(", "));
+              // the timeout is per attempt, the retries get the time they need on top
+              let policy = RetryPolicy {
+                timeout: job_options.timeout,
+                ..RetryPolicy::from_settings(&job_config.settings)
+              };
+              runtime::blocking(&task_name, policy.total_timeout(), move || {
+                // held until the work is done, also after the timeout gave up waiting for it
+                let _permit = permit;
+                Ok(sync_checkout(&job_config, &projects, &job_logger, &job_options))
+              })
+              .await
+              .map(|results| results.into_iter().map(|(name, result, attempts)| (name, Some(result), attempts)).collect())
+              .unwrap_or_else(|e| {
+                let message = format!("{}", e);
+                each(&|_| Some(Err(AppError::RuntimeError(message.clone()))))
+              })
+            };
+            progress_bar.inc(names.len() as u64);
+            results
+          })
+        })
+        .collect();
+
+      for job in jobs {
+        let results = job.await.map_err(|e| AppError::RuntimeError(format!("Sync job failed: {}", e)))?;
+        for (project_name, job_result, job_attempts) in results {
+          let job_result = match job_result {
+            Some(job_result) => job_result,
+            None => {
+              summary.record_skipped(&project_name, "offline");
+              continue;
+            }
+          };
+          summary.record(&project_name, &job_result);
+          if job_result.is_err() {
+            failed.insert(project_name.clone());
+          }
+          attempts.push((project_name, job_attempts));
+          synchronize_result = synchronize_result.and(job_result);
         }
       }
-      job_result_queue.push(job_result);
-    });
+    }
+    Ok((summary, synchronize_result, retry_report(&attempts, &failed)))
+  });
+  progress_bar.finish_and_clear();
+  let (summary, result, retries) = result?;
+  summary.print_if_interrupted();
+  summary.print_for_machines("sync");
+  if let Some(retries) = retries {
+    eprintln!("{} {}", Tone::Warning.paint_err("Retried:"), retries);
   }
-  m.join_and_clear().unwrap();
-
-  let mut synchronize_result: Result<(), AppError> = Result::Ok(());
-  while let Some(result) = job_results.pop() {
-    synchronize_result = synchronize_result.and(result);
+  if !skipped.is_empty() {
+    eprintln!(
+      "{} {}",
+      Tone::Warning.paint_err("Skipped (offline):"),
+      skipped.iter().map(String::as_str).collect::<Vec<_>>().join(", ")
+    );
   }
-  synchronize_result
+  let notification = Notification::for_batch("sync", &summary, started.elapsed());
+  notify::batch_finished(&config.settings, options.notify, &notification, logger);
+  signal::check_interrupted().and(result)
 }
 
 fn ssh_agent_running() -> bool {
@@ -130,3 +404,67 @@ fn is_socket(path: &str) -> bool {
 fn is_socket(_: &str) -> bool {
   false
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::path::fw_path;
+  use crate::config::settings::Settings;
+  use crate::testing::{commit, temp_dir};
+  use git2::Repository;
+  use spectral::prelude::*;
+  use std::fs;
+
+  #[test]
+  fn test_projects_sharing_a_clone_run_their_own_hooks() {
+    let temp = temp_dir();
+    let dir = temp.path();
+    let origin = dir.join("mono");
+    commit(&Repository::init(&origin).unwrap(), "init");
+
+    let names: Vec<String> = ["api", "web"]
+      .iter()
+      .map(|name| format!("fw-sync-test-{}-{}", name, std::process::id()))
+      .collect();
+    let projects: Vec<Project> = names
+      .iter()
+      .zip(&["services/api", "web"])
+      .map(|(name, subdir)| Project {
+        name: name.clone(),
+        git: format!("file://{}", origin.to_string_lossy()),
+        tags: None,
+        override_path: None,
+        subdir: Some((*subdir).to_owned()),
+        after_clone: Some(format!("touch {}.cloned", name)),
+        shell: None,
+        pin: None,
+        ssh_key: None,
+        additional_remotes: None,
+        ..Project::example()
+      })
+      .collect();
+    let config = Config {
+      projects: projects.iter().map(|p| (p.name.clone(), p.clone())).collect(),
+      settings: serde_json::from_value::<Settings>(serde_json::json!({ "workspace": dir.join("workspace") })).unwrap(),
+      outside_profile: BTreeMap::new(),
+    };
+    let logger = Logger::root(slog::Discard, o!());
+
+    let selected: Vec<&Project> = config.projects.values().collect();
+    let levels = checkout_levels(&config, &selected, &logger).unwrap();
+    let results = sync_checkout(&config, &levels[0][0], &logger, &SyncOptions::default());
+    let checkout = dir.join("workspace/mono");
+    let hooks_ran: Vec<bool> = names.iter().map(|name| checkout.join(format!("{}.cloned", name)).exists()).collect();
+    let state = fw_path().unwrap().state;
+    for name in &names {
+      let _ = fs::remove_file(state.join("hooks").join(format!("{}.log", name)));
+      let _ = fs::remove_file(state.join("locks").join(format!("{}.lock", name)));
+    }
+
+    assert_that(&levels.len()).is_equal_to(1);
+    assert_that(&levels[0].len()).is_equal_to(1);
+    let synced: Vec<(String, bool)> = results.iter().map(|(name, result, _)| (name.clone(), result.is_ok())).collect();
+    assert_that(&synced).is_equal_to(names.iter().map(|name| (name.clone(), true)).collect::<Vec<_>>());
+    assert_that(&hooks_ran).is_equal_to(vec![true, true]);
+  }
+}
@@ -0,0 +1,145 @@
+use crate::config::{self, autotag::ForgeTags, project::Project, selector::ProjectSelector, settings::GitlabSettings, Config};
+use crate::errors::AppError;
+use crate::output::{print_warning, Tone};
+use crate::pr::{forge_of, Forge};
+use crate::runtime;
+use crate::ws::client::ForgeClient;
+use crate::ws::github::{self, GithubApi};
+use serde::Deserialize;
+use slog::{debug, info, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+#[derive(Deserialize, Debug)]
+struct GitlabProject {
+  // renamed from tag_list in GitLab 14.0
+  #[serde(default, alias = "tag_list")]
+  topics: Vec<String>,
+}
+
+/// The primary language and the topics of a repository.
+type Metadata = (Option<String>, Vec<String>);
+
+fn gitlab_metadata(client: &ForgeClient, path: &str) -> Result<Metadata, AppError> {
+  let project_path = format!("projects/{}", path.replace('/', "%2F"));
+  let project: GitlabProject = client.get_json(&project_path)?;
+  // the share of every language in percent
+  let languages: BTreeMap<String, f64> = client.get_json(&format!("{}/languages", project_path))?;
+  let language = languages
+    .into_iter()
+    .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+    .map(|(language, _)| language);
+  Ok((language, project.topics))
+}
+
+fn fetch_metadata(github: &GithubApi, gitlab: Option<&ForgeClient>, forge: &Forge) -> Result<Metadata, AppError> {
+  match forge {
+    Forge::GitHub { owner, repo } => github.repository(owner, repo).map(|r| (r.language, r.topics)),
+    Forge::GitLab { path } => match gitlab {
+      Some(client) => gitlab_metadata(client, path),
+      None => Err(AppError::UserError("there are no gitlab settings (token and host)".to_owned())),
+    },
+  }
+}
+
+/// The tags of a project with `own_tags` after the forge reported `forge_tags`: tags that came from the forge earlier
+/// and are not reported any more are dropped.
+fn retagged(settings: &ForgeTags, own_tags: &BTreeSet<String>, forge_tags: BTreeSet<String>) -> BTreeSet<String> {
+  own_tags.iter().filter(|tag| !settings.is_forge_tag(tag)).cloned().chain(forge_tags).collect()
+}
+
+/// Refreshes the tags that come from the topics and the language of the selected GitHub and GitLab projects.
+pub fn retag_from_forge(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  timeout: Option<Duration>,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let forge_tags = config
+    .settings
+    .forge_tags
+    .clone()
+    .ok_or_else(|| AppError::UserError("No forge_tags defined in the settings".to_owned()))?;
+  let gitlab_host = config.settings.gitlab.as_ref().map(|g| g.host.as_str());
+  let lookups: Vec<(Project, Forge)> = selector
+    .select(&config)
+    .into_iter()
+    .filter(|p| !p.is_directory())
+    .filter_map(|p| match forge_of(&p.git, gitlab_host) {
+      Some(forge) => Some((p.clone(), forge)),
+      None => {
+        debug!(logger, "Skipping, not on GitHub or GitLab"; "project" => &p.name);
+        None
+      }
+    })
+    .collect();
+
+  // public repositories need no token
+  let github_api = match github::token(config.settings.github_token.as_ref()) {
+    Some(token) => github::github_api(&token, logger)?,
+    None => github::anonymous_github_api(logger)?,
+  };
+  let gitlab_client = match config.settings.gitlab.clone() {
+    Some(GitlabSettings { host, token }) => Some(ForgeClient::gitlab(&host, &token, logger)?),
+    None => None,
+  };
+  let results = runtime::block_on_interruptible(runtime::blocking("Forge metadata", timeout, move || {
+    Ok(
+      lookups
+        .into_iter()
+        .map(|(project, forge)| {
+          let metadata = fetch_metadata(&github_api, gitlab_client.as_ref(), &forge);
+          (project, metadata)
+        })
+        .collect::<Vec<_>>(),
+    )
+  }))?;
+
+  let mut retagged_projects = 0;
+  for (project, metadata) in results {
+    let (language, topics) = match metadata {
+      Ok(metadata) => metadata,
+      Err(e) => {
+        print_warning(&format!("Skipping {}: {}", project.name, e));
+        continue;
+      }
+    };
+    let own_tags = project.tags.clone().unwrap_or_default();
+    let kept = retagged(&forge_tags, &own_tags, BTreeSet::new());
+    let new_tags = config.settings.with_tags(&kept, forge_tags.tags_for(language.as_deref(), &topics));
+    if new_tags == own_tags {
+      continue;
+    }
+    let changes: Vec<String> = new_tags
+      .difference(&own_tags)
+      .map(|t| format!("+{}", t))
+      .chain(own_tags.difference(&new_tags).map(|t| format!("-{}", t)))
+      .collect();
+    println!("{} {}", project.name, Tone::Good.paint(&changes.join(" ")));
+    let mut new_project = project;
+    new_project.tags = Some(new_tags);
+    config::write_project(&new_project, logger)?;
+    retagged_projects += 1;
+  }
+  info!(logger, "Forge tags refreshed"; "retagged_projects" => retagged_projects);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use maplit::btreeset;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_retagged_replaces_forge_tags() {
+    let settings = ForgeTags {
+      topic_prefix: Some("topic-".to_owned()),
+      ..ForgeTags::default()
+    };
+    let own_tags = btreeset!["work".to_owned(), "topic-old".to_owned()];
+    let new_tags = retagged(&settings, &own_tags, btreeset!["topic-new".to_owned()]);
+    assert_that(&new_tags).is_equal_to(btreeset!["work".to_owned(), "topic-new".to_owned()]);
+  }
+}
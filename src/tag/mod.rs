@@ -1,21 +1,27 @@
 use crate::config;
-use crate::config::settings::Tag;
+use crate::config::settings::{Tag, TagCondition};
 use crate::config::{project::Project, Config};
 use crate::errors::AppError;
+use crate::output::{Cell, Table, Tone};
+use crate::signal;
 use crate::spawn::init_threads;
 use crate::spawn::spawn_maybe;
+use crate::trust;
 use crate::util::random_colour;
-use ansi_term::Style;
 use rayon::prelude::*;
 use slog::Logger;
 use slog::{debug, info, o};
 use std::collections::{BTreeMap, BTreeSet};
 
+mod forge;
+
+pub use forge::retag_from_forge;
+
 pub fn list_tags(maybe_config: Result<Config, AppError>, maybe_project_name: Option<String>, logger: &Logger) -> Result<(), AppError> {
   let config: Config = maybe_config?;
   if let Some(project_name) = maybe_project_name {
     debug!(logger, "Listing tags for project"; "project" => &project_name);
-    list_project_tags(&config, &project_name)
+    list_project_tags(&config, &project_name, logger)
   } else {
     debug!(logger, "Listing tags");
     list_all_tags(config)
@@ -24,7 +30,7 @@ pub fn list_tags(maybe_config: Result<Config, AppError>, maybe_project_name: Opt
 
 pub fn delete_tag(maybe_config: Result<Config, AppError>, tag_name: &str, logger: &Logger) -> Result<(), AppError> {
   let config: Config = maybe_config?;
-  let tags: BTreeMap<String, Tag> = config.settings.tags.unwrap_or_else(BTreeMap::new);
+  let tags: BTreeMap<String, Tag> = config.settings.tags.unwrap_or_default();
 
   // remove tags from projects
   for mut project in config.projects.values().cloned() {
@@ -32,13 +38,13 @@ pub fn delete_tag(maybe_config: Result<Config, AppError>, tag_name: &str, logger
     let mut new_tags: BTreeSet<String> = project.tags.clone().unwrap_or_else(BTreeSet::new);
     if new_tags.remove(tag_name) {
       project.tags = Some(new_tags);
-      config::write_project(&project)?;
+      config::write_project(&project, logger)?;
     }
   }
 
   info!(logger, "Delete tag"; "tag" => tag_name);
   if let Some(tag) = tags.get(tag_name) {
-    config::delete_tag_config(tag_name, tag)
+    config::delete_tag_config(tag_name, tag, logger)
   } else {
     Ok(())
   }
@@ -56,12 +62,20 @@ fn list_all_tags(config: Config) -> Result<(), AppError> {
 pub fn add_tag(config: &Config, project_name: String, tag_name: String, logger: &Logger) -> Result<(), AppError> {
   if let Some(mut project) = config.projects.get(&project_name).cloned() {
     info!(logger, "Add tag to project"; "tag" => &tag_name, "project" => &project_name);
-    let tags: BTreeMap<String, Tag> = config.settings.tags.clone().unwrap_or_else(BTreeMap::new);
+    let tags: BTreeMap<String, Tag> = config.settings.tags.clone().unwrap_or_default();
     if tags.contains_key(&tag_name) {
       let mut new_tags: BTreeSet<String> = project.tags.clone().unwrap_or_else(BTreeSet::new);
       new_tags.insert(tag_name);
+      if let Some((group, tags)) = config.settings.tag_group_conflicts(&new_tags).first() {
+        return Err(AppError::UserError(format!(
+          "{} can only have one of the tags {} of tag group {}, untag it first",
+          project_name,
+          tags.join(", "),
+          group
+        )));
+      }
       project.tags = Some(new_tags);
-      config::write_project(&project)?;
+      config::write_project(&project, logger)?;
       Ok(())
     } else {
       Err(AppError::UserError(format!("Unknown tag {}", tag_name)))
@@ -81,7 +95,7 @@ pub fn create_tag(
   logger: &Logger,
 ) -> Result<(), AppError> {
   let config: Config = maybe_config?;
-  let tags: BTreeMap<String, Tag> = config.settings.tags.unwrap_or_else(BTreeMap::new);
+  let tags: BTreeMap<String, Tag> = config.settings.tags.unwrap_or_default();
   info!(logger, "Create tag");
 
   if tags.contains_key(&tag_name) {
@@ -90,31 +104,55 @@ pub fn create_tag(
     let new_tag = Tag {
       after_clone,
       after_workon,
+      shell: None,
+      hook_timeout: None,
+      ssh_key: None,
+      lfs: None,
       priority,
       workspace: tag_workspace,
       default: None,
+      commands: None,
+      clone_depth: None,
+      env: None,
+      when: None,
+      default_branch_prefix: None,
+      branch_pattern: None,
       tag_config_path: "default".to_string(),
     };
-    config::write_tag(&tag_name, &new_tag)?;
+    config::write_tag(&tag_name, &new_tag, logger)?;
     Ok(())
   }
 }
 
-pub fn inspect_tag(maybe_config: Result<Config, AppError>, tag_name: &str) -> Result<(), AppError> {
+fn describe_condition(when: &TagCondition) -> String {
+  let mut conditions: Vec<String> = vec![];
+  if let Some(file) = &when.file_exists {
+    conditions.push(format!("file {} exists", file));
+  }
+  if let Some(project_type) = &when.project_type {
+    conditions.push(format!("{} project", project_type));
+  }
+  conditions.join(" and ")
+}
+
+pub fn inspect_tag(maybe_config: Result<Config, AppError>, tag_name: &str, logger: &Logger) -> Result<(), AppError> {
   let config: Config = maybe_config?;
-  let tags: BTreeMap<String, Tag> = config.settings.tags.unwrap_or_else(BTreeMap::new);
+  let tags: BTreeMap<String, Tag> = config.settings.tags.clone().unwrap_or_default();
   if let Some(tag) = tags.get(tag_name) {
-    println!("{}", Style::new().underline().bold().paint(tag_name));
+    println!("{}", Tone::Heading.paint(tag_name));
     println!("{:<20}: {}", "config path", tag.tag_config_path);
     println!("{:<20}: {}", "after workon", tag.after_workon.clone().unwrap_or_else(|| "".to_string()));
     println!("{:<20}: {}", "after clone", tag.after_clone.clone().unwrap_or_else(|| "".to_string()));
+    println!("{:<20}: {}", "shell", tag.shell.as_ref().map(|s| s.join(" ")).unwrap_or_default());
+    println!("{:<20}: {}", "ssh key", tag.ssh_key.clone().unwrap_or_default());
     println!("{:<20}: {}", "priority", tag.priority.map(|n| n.to_string()).unwrap_or_else(|| "".to_string()));
     println!("{:<20}: {}", "workspace", tag.workspace.clone().unwrap_or_else(|| "".to_string()));
     println!("{:<20}: {}", "default", tag.default.map(|n| n.to_string()).unwrap_or_else(|| "".to_string()));
+    println!("{:<20}: {}", "when", tag.when.as_ref().map(describe_condition).unwrap_or_default());
     println!();
-    println!("{}", Style::new().underline().bold().paint("projects".to_string()));
-    for project in config.projects.values().cloned() {
-      if project.tags.unwrap_or_default().contains(tag_name) {
+    println!("{}", Tone::Heading.paint("projects"));
+    for project in config.projects.values() {
+      if config.effective_tags(project, logger).contains(tag_name) {
         println!("{}", project.name)
       }
     }
@@ -132,7 +170,7 @@ pub fn remove_tag(maybe_config: Result<Config, AppError>, project_name: String,
     let mut new_tags: BTreeSet<String> = project.tags.clone().unwrap_or_else(BTreeSet::new);
     if new_tags.remove(tag_name) {
       project.tags = Some(new_tags);
-      config::write_project(&project)
+      config::write_project(&project, logger)
     } else {
       Ok(())
     }
@@ -141,12 +179,10 @@ pub fn remove_tag(maybe_config: Result<Config, AppError>, project_name: String,
   }
 }
 
-fn list_project_tags(config: &Config, project_name: &str) -> Result<(), AppError> {
+fn list_project_tags(config: &Config, project_name: &str, logger: &Logger) -> Result<(), AppError> {
   if let Some(project) = config.projects.get(project_name) {
-    if let Some(tags) = project.clone().tags {
-      for tag_name in tags {
-        println!("{}", tag_name);
-      }
+    for tag_name in config.effective_tags(project, logger) {
+      println!("{}", tag_name);
     }
     Ok(())
   } else {
@@ -154,6 +190,35 @@ fn list_project_tags(config: &Config, project_name: &str) -> Result<(), AppError
   }
 }
 
+/// Adds the tags of matching autotag rules to every project that does not carry them yet.
+pub fn apply_autotag_rules(maybe_config: Result<Config, AppError>, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  if config.settings.autotag.iter().flatten().next().is_none() {
+    return Err(AppError::UserError("No autotag rules defined in the settings".to_owned()));
+  }
+  let mut tagged = 0;
+  for project in config.projects.values() {
+    let own_tags = project.tags.clone().unwrap_or_default();
+    let missing: BTreeSet<String> = config.settings.autotags_for(&project.git).difference(&own_tags).cloned().collect();
+    if missing.is_empty() {
+      continue;
+    }
+    signal::check_interrupted()?;
+    let new_tags = config.settings.with_tags(&own_tags, missing);
+    if new_tags == own_tags {
+      continue;
+    }
+    let added: Vec<String> = new_tags.difference(&own_tags).map(|t| format!("+{}", t)).collect();
+    println!("{} {}", project.name, Tone::Good.paint(&added.join(" ")));
+    let mut new_project = project.clone();
+    new_project.tags = Some(new_tags);
+    config::write_project(&new_project, logger)?;
+    tagged += 1;
+  }
+  info!(logger, "Autotag rules applied"; "tagged_projects" => tagged);
+  Ok(())
+}
+
 pub fn autotag(maybe_config: Result<Config, AppError>, cmd: &str, tag_name: &str, logger: &Logger, parallel_raw: &Option<String>) -> Result<(), AppError> {
   let config = maybe_config?;
 
@@ -166,26 +231,48 @@ pub fn autotag(maybe_config: Result<Config, AppError>, cmd: &str, tag_name: &str
     let script_results = projects
       .par_iter()
       .map(|p| {
-        let shell = config.settings.get_shell_or_default();
+        signal::check_interrupted()?;
         let project_logger = logger.new(o!("project" => p.name.clone()));
+        if !trust::allows_shell(&config, p, &project_logger) {
+          return Err(AppError::UserError(format!("the shell of {} was not approved", p.name)));
+        }
+        let shell = config.resolve_shell(p, &project_logger);
         let path = &config.actual_path_to_project(p, &project_logger);
+        let env = config.resolve_env(p, &project_logger);
         info!(project_logger, "Entering");
-        spawn_maybe(&shell, cmd, &path, &p.name, random_colour(), &project_logger)
+        spawn_maybe(&shell, cmd, path, &p.name, &env, random_colour(), &project_logger)
       })
       .collect::<Vec<Result<(), AppError>>>();
 
+    signal::check_interrupted()?;
+
     // map with projects and filter if result == 0
     let filtered_projects: Vec<&Project> = script_results
       .into_iter()
-      .zip(projects.into_iter())
+      .zip(projects)
       .filter(|(x, _)| x.is_ok())
       .map(|(_, p)| p)
       .collect::<Vec<&Project>>();
 
+    // a project that cannot take the tag, e.g. because of its tag group, does not stop the others
+    let mut table = Table::new();
+    let mut failed = 0;
     for project in filtered_projects.iter() {
-      add_tag(&config, project.name.clone(), tag_name.to_string(), logger)?;
+      let name = Cell::plain(project.name.clone());
+      match add_tag(&config, project.name.clone(), tag_name.to_string(), logger) {
+        Ok(()) => table.add_row(vec![name, Cell::new("tagged", Tone::Good)]),
+        Err(e) => {
+          failed += 1;
+          table.add_row(vec![name, Cell::new(format!("{}", e), Tone::Bad)]);
+        }
+      }
+    }
+    table.print();
+    if failed > 0 {
+      Err(AppError::UserError(format!("Could not tag {} project(s)", failed)))
+    } else {
+      Ok(())
     }
-    Ok(())
   } else {
     Err(AppError::UserError(format!("Unknown tag {}", tag_name)))
   }
@@ -0,0 +1,41 @@
+//! Fixtures shared by the unit tests.
+
+use git2::{Oid, Repository, Signature};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// A new directory that is removed again when the test is done, also when it panics.
+pub fn temp_dir() -> TempDir {
+  tempfile::Builder::new().prefix("fw-test-").tempdir().unwrap()
+}
+
+/// The author and committer of the test commits.
+pub fn signature() -> Signature<'static> {
+  Signature::now("fw", "fw@example.com").unwrap()
+}
+
+/// A repository in a new temporary directory with a first, empty commit on HEAD.
+pub fn test_repo() -> (TempDir, Repository) {
+  let dir = temp_dir();
+  let repo = Repository::init(dir.path()).unwrap();
+  commit(&repo, "init");
+  (dir, repo)
+}
+
+/// Commits the index on top of HEAD, if HEAD has a commit yet.
+pub fn commit(repo: &Repository, message: &str) -> Oid {
+  let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+  let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+  let parents: Vec<_> = parent.iter().collect();
+  repo.commit(Some("HEAD"), &signature(), &signature(), message, &tree, &parents).unwrap()
+}
+
+/// Writes `content` to `file` in the work tree and commits it.
+pub fn commit_file(repo: &Repository, file: &str, content: &str) -> Oid {
+  fs::write(repo.workdir().unwrap().join(file), content).unwrap();
+  let mut index = repo.index().unwrap();
+  index.add_path(Path::new(file)).unwrap();
+  index.write().unwrap();
+  commit(repo, file)
+}
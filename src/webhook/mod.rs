@@ -0,0 +1,491 @@
+use crate::config::{self, Config};
+use crate::errors::AppError;
+use crate::pr::{forge_of, Forge};
+use crate::project::OnConflict;
+use crate::setup::{save_imported_projects, ImportOptions, ImportedRepository};
+use crate::signal;
+use crate::util::hex;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Deserialize;
+use slog::{debug, info, warn, Logger};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+// forge events are a few KB, anything much larger is not a repository event
+const MAX_BODY: usize = 1024 * 1024;
+// the request line and headers are read before the secret is checked, so they are kept small
+const MAX_HEADER_BYTES: u64 = 16 * 1024;
+const MAX_HEADER_LINES: usize = 100;
+const HEADERS_TOO_LARGE: Rejection = Rejection::Status("431 Request Header Fields Too Large", "The request headers are too large");
+// the whole request, requests are answered one after the other
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A repository of an organization or group that changed on the forge.
+#[derive(Debug, Clone, PartialEq)]
+enum RepoEvent {
+  /// `namespace` is the organization or group, new projects are written to the project config directory of that name.
+  Created {
+    namespace: String,
+    name: String,
+    git: String,
+  },
+  Renamed {
+    old_git: String,
+    git: String,
+  },
+  Deleted {
+    git: String,
+  },
+}
+
+struct Request {
+  method: String,
+  /// Header names in lowercase.
+  headers: BTreeMap<String, String>,
+  body: Vec<u8>,
+}
+
+/// Why a request is not read to the end.
+#[derive(Debug)]
+enum Rejection {
+  /// Answered with this status and message.
+  Status(&'static str, &'static str),
+  Io(io::Error),
+}
+
+impl From<io::Error> for Rejection {
+  fn from(e: io::Error) -> Rejection {
+    match e.kind() {
+      io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Rejection::Status("408 Request Timeout", "The request took too long"),
+      io::ErrorKind::InvalidData => Rejection::Status("400 Bad Request", "The request is not valid UTF-8"),
+      _ => Rejection::Io(e),
+    }
+  }
+}
+
+// Every read only gets the time that is left, so a client sending a byte now and then can't hold the server either.
+struct Deadline<'a> {
+  stream: &'a TcpStream,
+  until: Instant,
+}
+
+impl Read for Deadline<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let left = self
+      .until
+      .checked_duration_since(Instant::now())
+      .filter(|left| !left.is_zero())
+      .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "request took too long"))?;
+    self.stream.set_read_timeout(Some(left))?;
+    let mut stream = self.stream;
+    stream.read(buf)
+  }
+}
+
+#[derive(Deserialize)]
+struct GithubOwner {
+  login: String,
+}
+
+#[derive(Deserialize)]
+struct GithubRepository {
+  name: String,
+  ssh_url: String,
+  owner: GithubOwner,
+}
+
+#[derive(Deserialize)]
+struct GithubFrom {
+  from: String,
+}
+
+#[derive(Deserialize)]
+struct GithubNameChange {
+  name: GithubFrom,
+}
+
+#[derive(Deserialize)]
+struct GithubChanges {
+  repository: Option<GithubNameChange>,
+}
+
+#[derive(Deserialize)]
+struct GithubRepositoryEvent {
+  action: String,
+  repository: GithubRepository,
+  changes: Option<GithubChanges>,
+}
+
+#[derive(Deserialize)]
+struct GitlabSystemEvent {
+  event_name: String,
+  path_with_namespace: Option<String>,
+  old_path_with_namespace: Option<String>,
+}
+
+fn read_request(stream: &TcpStream, timeout: Duration) -> Result<Request, Rejection> {
+  let mut reader = BufReader::new(Deadline {
+    stream,
+    until: Instant::now() + timeout,
+  })
+  .take(MAX_HEADER_BYTES);
+  // a line without its end was cut off by the limit or the client
+  let mut read_line = |line: &mut String| -> Result<(), Rejection> {
+    reader.read_line(line)?;
+    if line.ends_with('\n') {
+      Ok(())
+    } else if reader.limit() == 0 {
+      Err(HEADERS_TOO_LARGE)
+    } else {
+      Err(Rejection::Status("400 Bad Request", "The request ended in the headers"))
+    }
+  };
+  let mut request_line = String::new();
+  read_line(&mut request_line)?;
+  let mut headers = BTreeMap::new();
+  for lines in 0.. {
+    let mut line = String::new();
+    read_line(&mut line)?;
+    if line.trim_end().is_empty() {
+      break;
+    }
+    if lines == MAX_HEADER_LINES {
+      return Err(HEADERS_TOO_LARGE);
+    }
+    if let Some((name, value)) = line.split_once(':') {
+      headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+    }
+  }
+  let length: usize = match headers.get("content-length") {
+    Some(length) => length.parse().map_err(|_| Rejection::Status("400 Bad Request", "Invalid Content-Length"))?,
+    None => 0,
+  };
+  if length > MAX_BODY {
+    return Err(Rejection::Status("413 Payload Too Large", "The request body is too large"));
+  }
+  let mut body = vec![0; length];
+  reader.into_inner().read_exact(&mut body)?;
+  Ok(Request {
+    method: request_line.split_whitespace().next().unwrap_or_default().to_owned(),
+    headers,
+    body,
+  })
+}
+
+fn hmac_sha256_hex(secret: &str, body: &[u8]) -> Option<String> {
+  let key = PKey::hmac(secret.as_bytes()).ok()?;
+  let mut signer = Signer::new(MessageDigest::sha256(), &key).ok()?;
+  signer.update(body).ok()?;
+  Some(hex(&signer.sign_to_vec().ok()?))
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+  a.len() == b.len() && memcmp::eq(a.as_bytes(), b.as_bytes())
+}
+
+/// GitHub signs the body with the secret (`X-Hub-Signature-256`), GitLab sends the secret itself (`X-Gitlab-Token`).
+fn authentic(headers: &BTreeMap<String, String>, body: &[u8], secret: &str) -> bool {
+  if let Some(signature) = headers.get("x-hub-signature-256") {
+    hmac_sha256_hex(secret, body)
+      .map(|expected| constant_time_eq(&signature.to_lowercase(), &format!("sha256={}", expected)))
+      .unwrap_or(false)
+  } else if let Some(token) = headers.get("x-gitlab-token") {
+    constant_time_eq(token, secret)
+  } else {
+    false
+  }
+}
+
+fn gitlab_git(host: &str, path: &str) -> String {
+  format!("git@{}:{}.git", host, path)
+}
+
+/// The repository event in a GitHub `repository` webhook or a GitLab system hook, `None` for events that do not concern the
+/// config.
+fn parse_event(headers: &BTreeMap<String, String>, body: &[u8], gitlab_host: &str) -> Result<Option<RepoEvent>, AppError> {
+  if let Some(kind) = headers.get("x-github-event") {
+    if kind != "repository" {
+      return Ok(None);
+    }
+    let event: GithubRepositoryEvent = serde_json::from_slice(body)?;
+    let repository = event.repository;
+    return Ok(match event.action.as_str() {
+      "created" => Some(RepoEvent::Created {
+        namespace: repository.owner.login,
+        name: repository.name,
+        git: repository.ssh_url,
+      }),
+      "renamed" => {
+        let old_name = event
+          .changes
+          .and_then(|changes| changes.repository)
+          .map(|change| change.name.from)
+          .ok_or_else(|| AppError::UserError("renamed event without the old name".to_owned()))?;
+        Some(RepoEvent::Renamed {
+          old_git: format!("git@github.com:{}/{}.git", repository.owner.login, old_name),
+          git: repository.ssh_url,
+        })
+      }
+      "deleted" => Some(RepoEvent::Deleted { git: repository.ssh_url }),
+      _ => None,
+    });
+  }
+  if headers.contains_key("x-gitlab-event") {
+    let event: GitlabSystemEvent = serde_json::from_slice(body)?;
+    let path = match event.path_with_namespace {
+      Some(path) => path,
+      None => return Ok(None),
+    };
+    return Ok(match event.event_name.as_str() {
+      "project_create" => path.rsplit_once('/').map(|(namespace, name)| RepoEvent::Created {
+        namespace: namespace.replace('/', "-"),
+        name: name.to_owned(),
+        git: gitlab_git(gitlab_host, &path),
+      }),
+      "project_rename" | "project_transfer" => event.old_path_with_namespace.map(|old_path| RepoEvent::Renamed {
+        old_git: gitlab_git(gitlab_host, &old_path),
+        git: gitlab_git(gitlab_host, &path),
+      }),
+      "project_destroy" => Some(RepoEvent::Deleted {
+        git: gitlab_git(gitlab_host, &path),
+      }),
+      _ => None,
+    });
+  }
+  Ok(None)
+}
+
+fn same_repository(a: &str, b: &str, gitlab_host: &str) -> bool {
+  match (forge_of(&a.to_lowercase(), Some(gitlab_host)), forge_of(&b.to_lowercase(), Some(gitlab_host))) {
+    (Some(a), Some(b)) => a == b,
+    _ => a == b,
+  }
+}
+
+/// Changes the config for `event` and describes what was done.
+fn apply(config: Config, event: RepoEvent, gitlab_host: &str, logger: &Logger) -> Result<String, AppError> {
+  match event {
+    RepoEvent::Created { namespace, name, git } => {
+      if let Some(known) = config.projects.values().find(|p| same_repository(&p.git, &git, gitlab_host)) {
+        return Ok(format!("{} is already known as {}", git, known.name));
+      }
+      let forge = match forge_of(&git, Some(gitlab_host)) {
+        Some(Forge::GitHub { .. }) => "github",
+        Some(Forge::GitLab { .. }) => "gitlab",
+        None => "git",
+      };
+      let repository = ImportedRepository {
+        name,
+        git,
+        org: Some(namespace.clone()),
+        language: None,
+        topics: Vec::new(),
+      };
+      let options = ImportOptions {
+        on_conflict: OnConflict::Skip,
+        naming: None,
+        include: None,
+        exclude: None,
+        dry_run: false,
+      };
+      match save_imported_projects(config, forge, vec![repository], &namespace, None, &options, logger)?.pop() {
+        Some(name) => Ok(format!("added {}", name)),
+        None => Ok("skipped, the name is taken".to_owned()),
+      }
+    }
+    RepoEvent::Renamed { old_git, git } => match config.projects.values().find(|p| same_repository(&p.git, &old_git, gitlab_host)) {
+      Some(project) => {
+        let mut project = project.clone();
+        project.git = git.clone();
+        config::write_project(&project, logger)?;
+        Ok(format!("{} now points to {}", project.name, git))
+      }
+      None => Ok(format!("{} is not in the config", old_git)),
+    },
+    RepoEvent::Deleted { git } => match config.projects.values().find(|p| same_repository(&p.git, &git, gitlab_host)) {
+      Some(project) => {
+        config::delete_project_config(project, logger)?;
+        Ok(format!("removed {}, its checkout is left alone", project.name))
+      }
+      None => Ok(format!("{} is not in the config", git)),
+    },
+  }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, message: &str) -> io::Result<()> {
+  write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+    status,
+    message.len() + 1,
+    message
+  )
+}
+
+fn handle(mut stream: TcpStream, secret: &str, logger: &Logger) -> io::Result<()> {
+  stream.set_nonblocking(false)?;
+  let request = match read_request(&stream, REQUEST_TIMEOUT) {
+    Ok(request) => request,
+    Err(Rejection::Status(status, message)) => return respond(&mut stream, status, message),
+    Err(Rejection::Io(e)) => return Err(e),
+  };
+  if request.method != "POST" {
+    return respond(&mut stream, "405 Method Not Allowed", "Forge webhooks are POSTed");
+  }
+  if !authentic(&request.headers, &request.body, secret) {
+    warn!(logger, "Rejected webhook with a wrong secret"; "peer" => stream.peer_addr().map(|a| a.to_string()).unwrap_or_default());
+    return respond(&mut stream, "401 Unauthorized", "Wrong secret");
+  }
+  // the config is read for every event, it may have changed since the last one
+  let result = config::read_config(logger).and_then(|config| {
+    let gitlab_host = config
+      .settings
+      .gitlab
+      .as_ref()
+      .map(|g| g.host.clone())
+      .unwrap_or_else(|| "gitlab.com".to_owned());
+    match parse_event(&request.headers, &request.body, &gitlab_host)? {
+      Some(event) => {
+        debug!(logger, "Repository event"; "event" => format!("{:?}", event));
+        apply(config, event, &gitlab_host, logger).map(Some)
+      }
+      None => Ok(None),
+    }
+  });
+  match result {
+    Ok(Some(message)) => {
+      info!(logger, "Config updated from webhook"; "change" => &message);
+      respond(&mut stream, "200 OK", &message)
+    }
+    Ok(None) => respond(&mut stream, "200 OK", "Ignored, not a repository event"),
+    Err(e) => {
+      warn!(logger, "Could not apply webhook"; "error" => format!("{}", e));
+      respond(&mut stream, "400 Bad Request", &format!("{}", e))
+    }
+  }
+}
+
+/// Listens on `address` for repository webhooks of GitHub organizations and GitLab system hooks, and adds, repoints or removes
+/// projects in the config when a repository is created, renamed or deleted. Runs until interrupted.
+pub fn serve_webhook(maybe_config: Result<Config, AppError>, address: SocketAddr, logger: &Logger) -> Result<(), AppError> {
+  maybe_config?;
+  let secret = env::var("FW_WEBHOOK_SECRET")
+    .ok()
+    .filter(|secret| !secret.is_empty())
+    .ok_or_else(|| AppError::UserError("Set FW_WEBHOOK_SECRET to the secret configured for the webhook".to_owned()))?;
+  let listener = TcpListener::bind(address).map_err(|e| AppError::UserError(format!("Could not listen on {}: {}", address, e)))?;
+  // non-blocking, so that Ctrl-C is noticed between requests
+  listener.set_nonblocking(true)?;
+  info!(logger, "Waiting for webhooks"; "address" => format!("http://{}/", address));
+  while !signal::interrupted() {
+    match listener.accept() {
+      Ok((stream, _)) => {
+        if let Err(e) = handle(stream, &secret, logger) {
+          warn!(logger, "Could not answer webhook"; "error" => format!("{}", e));
+        }
+      }
+      Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(Duration::from_millis(100)),
+      Err(e) => return Err(e.into()),
+    }
+  }
+  info!(logger, "Stopped waiting for webhooks");
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use maplit::btreemap;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_authentic() {
+    let body = br#"{"action":"created"}"#;
+    let signature = format!("sha256={}", hmac_sha256_hex("s3cret", body).unwrap());
+    assert_that(&authentic(&btreemap!["x-hub-signature-256".to_owned() => signature.clone()], body, "s3cret")).is_true();
+    assert_that(&authentic(
+      &btreemap!["x-hub-signature-256".to_owned() => signature],
+      br#"{"action":"deleted"}"#,
+      "s3cret",
+    ))
+    .is_false();
+    assert_that(&authentic(&btreemap!["x-gitlab-token".to_owned() => "s3cret".to_owned()], body, "s3cret")).is_true();
+    assert_that(&authentic(&btreemap!["x-gitlab-token".to_owned() => "guess".to_owned()], body, "s3cret")).is_false();
+    assert_that(&authentic(&BTreeMap::new(), body, "s3cret")).is_false();
+  }
+
+  // The request as the server reads it after `client` sent `raw` and stopped writing.
+  fn read_sent(raw: Vec<u8>, timeout: Duration) -> Result<Request, Rejection> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = std::thread::spawn(move || {
+      let mut stream = TcpStream::connect(address).unwrap();
+      let _ = stream.write_all(&raw);
+      // keeps the connection open until the server gave up
+      let _ = stream.read(&mut [0; 1]);
+    });
+    let (stream, _) = listener.accept().unwrap();
+    let request = read_request(&stream, timeout);
+    drop(stream);
+    client.join().unwrap();
+    request
+  }
+
+  fn status(request: Result<Request, Rejection>) -> &'static str {
+    match request {
+      Err(Rejection::Status(status, _)) => status,
+      other => panic!("expected a rejection, got {:?}", other.map(|r| r.method)),
+    }
+  }
+
+  #[test]
+  fn test_read_request_limits() {
+    let timeout = Duration::from_secs(5);
+    let request = read_sent(b"POST / HTTP/1.1\r\nContent-Length: 2\r\nX-Gitlab-Token: s3cret\r\n\r\n{}".to_vec(), timeout).unwrap();
+    assert_that(&request.method.as_str()).is_equal_to("POST");
+    assert_that(&request.headers["x-gitlab-token"].as_str()).is_equal_to("s3cret");
+    assert_that(&request.body).is_equal_to(b"{}".to_vec());
+
+    let long_header = format!("POST / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(MAX_HEADER_BYTES as usize));
+    assert_that(&status(read_sent(long_header.into_bytes(), timeout))).is_equal_to("431 Request Header Fields Too Large");
+    let many_headers = format!("POST / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEADER_LINES + 1));
+    assert_that(&status(read_sent(many_headers.into_bytes(), timeout))).is_equal_to("431 Request Header Fields Too Large");
+    let huge_body = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY + 1);
+    assert_that(&status(read_sent(huge_body.into_bytes(), timeout))).is_equal_to("413 Payload Too Large");
+    let unfinished = b"POST / HTTP/1.1\r\nX-Gitlab".to_vec();
+    assert_that(&status(read_sent(unfinished, Duration::from_millis(200)))).is_equal_to("408 Request Timeout");
+  }
+
+  #[test]
+  fn test_parse_event() {
+    let github = btreemap!["x-github-event".to_owned() => "repository".to_owned()];
+    let renamed = br#"{"action": "renamed", "changes": {"repository": {"name": {"from": "old-api"}}},
+      "repository": {"name": "api", "ssh_url": "git@github.com:acme/api.git", "owner": {"login": "acme"}}}"#;
+    assert_that(&parse_event(&github, renamed, "gitlab.com").unwrap()).is_equal_to(Some(RepoEvent::Renamed {
+      old_git: "git@github.com:acme/old-api.git".to_owned(),
+      git: "git@github.com:acme/api.git".to_owned(),
+    }));
+    let push = btreemap!["x-github-event".to_owned() => "push".to_owned()];
+    assert_that(&parse_event(&push, b"{}", "gitlab.com").unwrap()).is_none();
+
+    let gitlab = btreemap!["x-gitlab-event".to_owned() => "System Hook".to_owned()];
+    let created = br#"{"event_name": "project_create", "path_with_namespace": "acme/backend/api", "name": "api"}"#;
+    assert_that(&parse_event(&gitlab, created, "git.acme.com").unwrap()).is_equal_to(Some(RepoEvent::Created {
+      namespace: "acme-backend".to_owned(),
+      name: "api".to_owned(),
+      git: "git@git.acme.com:acme/backend/api.git".to_owned(),
+    }));
+    assert_that(&same_repository(
+      "git@git.acme.com:Acme/Backend/API.git",
+      "ssh://git@git.acme.com/acme/backend/api",
+      "git.acme.com",
+    ))
+    .is_true();
+  }
+}
@@ -0,0 +1,108 @@
+use crate::config::{selector::ProjectSelector, Config};
+use crate::errors::AppError;
+use crate::grep::walk_files;
+use crate::output::{Cell, Table, Tone};
+use globset::{Glob, GlobMatcher};
+use rayon::prelude::*;
+use serde::Serialize;
+use slog::Logger;
+use std::path::Path;
+
+/// The files of a project that matched.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Found {
+  pub project: String,
+  pub paths: Vec<String>,
+}
+
+/// Like in `.gitignore`, a pattern without `/` matches the file name at any depth, one with `/` the path in the project.
+struct FilePattern {
+  glob: GlobMatcher,
+  anywhere: bool,
+}
+
+impl FilePattern {
+  fn new(pattern: &str) -> Result<FilePattern, AppError> {
+    let glob = Glob::new(pattern).map_err(|e| AppError::UserError(format!("Invalid pattern: {}", e)))?;
+    Ok(FilePattern {
+      glob: glob.compile_matcher(),
+      anywhere: !pattern.contains('/'),
+    })
+  }
+
+  fn matches(&self, relative: &Path) -> bool {
+    if self.anywhere {
+      relative.file_name().map(|name| self.glob.is_match(name)).unwrap_or(false)
+    } else {
+      self.glob.is_match(relative)
+    }
+  }
+}
+
+fn find_in(pattern: &FilePattern, root: &Path, logger: &Logger) -> Result<Vec<String>, AppError> {
+  let mut paths = Vec::new();
+  walk_files(root, true, logger, |_, relative| {
+    if pattern.matches(relative) {
+      paths.push(relative.to_string_lossy().to_string());
+    }
+  })?;
+  paths.sort();
+  Ok(paths)
+}
+
+/// Lists the selected projects that contain files matching `pattern` (e.g. `Dockerfile` or `**/flake.nix`), walking them in parallel.
+/// Hidden files are included, files ignored by git are not.
+pub fn which(maybe_config: Result<Config, AppError>, selector: &ProjectSelector, pattern: &str, json: bool, logger: &Logger) -> Result<(), AppError> {
+  let config = maybe_config?;
+  let pattern = FilePattern::new(pattern)?;
+
+  let results: Vec<Result<Found, AppError>> = selector
+    .select(&config)
+    .par_iter()
+    .map(|project| (project, config.actual_path_to_project(project, logger)))
+    .filter(|(_, path)| path.exists())
+    .map(|(project, path)| {
+      find_in(&pattern, &path, logger).map(|paths| Found {
+        project: project.name.clone(),
+        paths,
+      })
+    })
+    .collect();
+  let found: Vec<Found> = results
+    .into_iter()
+    .filter(|r| r.as_ref().map(|f| !f.paths.is_empty()).unwrap_or(true))
+    .collect::<Result<_, _>>()?;
+
+  if json {
+    println!("{}", serde_json::to_string(&found)?);
+  } else {
+    let mut table = Table::new();
+    for project in found {
+      table.add_row(vec![Cell::plain(project.project), Cell::new(project.paths.join(", "), Tone::Muted)]);
+    }
+    table.print();
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_file_pattern() {
+    let name = FilePattern::new("Dockerfile").unwrap();
+    assert_that(&name.matches(Path::new("Dockerfile"))).is_true();
+    assert_that(&name.matches(Path::new("docker/api/Dockerfile"))).is_true();
+    assert_that(&name.matches(Path::new("Dockerfile.dev"))).is_false();
+
+    let nested = FilePattern::new("**/flake.nix").unwrap();
+    assert_that(&nested.matches(Path::new("flake.nix"))).is_true();
+    assert_that(&nested.matches(Path::new("nix/flake.nix"))).is_true();
+
+    let rooted = FilePattern::new(".github/workflows/*.yml").unwrap();
+    assert_that(&rooted.matches(Path::new(".github/workflows/ci.yml"))).is_true();
+    assert_that(&rooted.matches(Path::new("ci.yml"))).is_false();
+  }
+}
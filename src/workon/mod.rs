@@ -1,23 +1,30 @@
 use crate::config;
 use crate::config::project::Project;
+use crate::config::template;
 use crate::errors::AppError;
-use crate::spawn::spawn_maybe;
+use crate::git::{clone_repository, repo_name_from_url, update_lfs};
+use crate::project;
+use crate::recent;
+use crate::spawn::spawn_hook;
+use crate::timetrack;
+use crate::trust;
 
 use ansi_term::Colour;
-use slog::debug;
 use slog::Logger;
+use slog::{debug, info, o, warn};
 use std::borrow::ToOwned;
+use std::collections::BTreeMap;
 use std::env;
 
 pub fn gen_reworkon(maybe_config: Result<config::Config, AppError>, logger: &Logger) -> Result<(), AppError> {
   let config = maybe_config?;
   let project = current_project(&config, logger)?;
-  gen(&project.name, Ok(config), false, logger)
+  gen(&project.name, Ok(config), false, false, logger)
 }
 
 fn current_project(config: &config::Config, logger: &Logger) -> Result<Project, AppError> {
   let os_current_dir = env::current_dir()?;
-  let current_dir = os_current_dir.to_string_lossy().to_owned();
+  let current_dir = os_current_dir.to_string_lossy().into_owned();
   let maybe_match = config
     .projects
     .values()
@@ -33,32 +40,179 @@ pub fn reworkon(maybe_config: Result<config::Config, AppError>, logger: &Logger)
   let path = config.actual_path_to_project(&project, logger);
   let mut commands: Vec<String> = vec![];
   commands.push(format!("cd {}", path.to_string_lossy()));
-  commands.extend_from_slice(&config.resolve_after_workon(logger, &project));
+  commands.extend(trust::approved_hooks(
+    &config,
+    &project,
+    "after_workon",
+    config.resolve_after_workon(logger, &project),
+    logger,
+  ));
 
   debug!(logger, "Reworkon match: {:?} with command {:?}", project, commands);
-  let shell = config.settings.get_shell_or_default();
-  spawn_maybe(&shell, &commands.join(" && "), &path, &project.name, Colour::Yellow, logger)
+  let shell = config.settings.niced(config.resolve_shell(&project, logger));
+  let timeout = config.resolve_hook_timeout(&project, logger);
+  let env = config.resolve_env(&project, logger);
+  spawn_hook(
+    &shell,
+    &commands.join(" && "),
+    &path,
+    &project.name,
+    &env,
+    Colour::Yellow,
+    timeout,
+    None,
+    logger,
+  )
+  .map(|_| info!(logger, "Hook executed"; "hook" => "after_workon", "project" => &project.name))
 }
 
-pub fn gen(name: &str, maybe_config: Result<config::Config, AppError>, quick: bool, logger: &Logger) -> Result<(), AppError> {
-  let config = maybe_config?;
+/// Works on a project from the workon history: the one before the last with `previous` (`workon -`), else the last one
+/// (`workon --last`).
+pub fn gen_from_history(maybe_config: Result<config::Config, AppError>, previous: bool, quick: bool, logger: &Logger) -> Result<(), AppError> {
+  let history = recent::read_history(logger)?;
+  let name = if previous { history.previous_visit() } else { history.last_visit() }
+    .ok_or_else(|| AppError::UserError("No earlier workon to go back to".to_owned()))?
+    .to_owned();
+  gen(&name, maybe_config, quick, false, logger)
+}
+
+pub fn looks_like_git_url(name_or_url: &str) -> bool {
+  name_or_url.contains("://") || name_or_url.starts_with("git@") || name_or_url.ends_with(".git")
+}
+
+// Returns the name of the project for `name_or_url`, adding a config entry for unknown URLs.
+fn add_if_missing(config: &mut config::Config, name_or_url: &str, logger: &Logger) -> Result<String, AppError> {
+  if config.projects.contains_key(name_or_url) {
+    return Ok(name_or_url.to_owned());
+  }
+  if !looks_like_git_url(name_or_url) {
+    return Err(AppError::UserError(format!(
+      "project key {} not found in config. Pass a git URL to add it.",
+      name_or_url
+    )));
+  }
+  let name = repo_name_from_url(name_or_url)?.to_owned();
+  match config.projects.get(&name) {
+    Some(existing) if existing.git == name_or_url => Ok(name),
+    Some(existing) => Err(AppError::UserError(format!(
+      "project key {} already exists for {}, not gonna overwrite it for you",
+      name, existing.git
+    ))),
+    None => {
+      project::add_entry(
+        Ok(config.clone()),
+        Some(&name),
+        name_or_url,
+        None,
+        None,
+        None,
+        project::OnConflict::Fail,
+        logger,
+      )?;
+      *config = config::read_config(logger)?;
+      Ok(name)
+    }
+  }
+}
+
+// `export K='v'` for every variable with a name the shell accepts.
+fn env_exports(env: &BTreeMap<String, String>, logger: &Logger) -> Vec<String> {
+  env
+    .iter()
+    .filter(|(name, _)| {
+      let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+      if !valid {
+        warn!(logger, "Not exporting environment variable with an invalid name"; "name" => name.as_str());
+      }
+      valid
+    })
+    .map(|(name, value)| format!("export {}={}", name, template::shell_word(value)))
+    .collect()
+}
+
+pub fn gen(name_or_url: &str, maybe_config: Result<config::Config, AppError>, quick: bool, create: bool, logger: &Logger) -> Result<(), AppError> {
+  let mut config = maybe_config?;
+  let create = create || looks_like_git_url(name_or_url);
+  let name = if create {
+    add_if_missing(&mut config, name_or_url, logger)?
+  } else {
+    config.find_project(name_or_url, logger)?.name.clone()
+  };
   let project: &Project = config
     .projects
-    .get(name)
+    .get(&name)
     .ok_or_else(|| AppError::UserError(format!("project key {} not found in fw.json", name)))?;
+  if project.archived.unwrap_or_default() {
+    return Err(AppError::UserError(format!("{} is archived, fw unarchive {} brings it back", name, name)));
+  }
   let canonical_project_path = config.actual_path_to_project(project, logger);
   let path = canonical_project_path
     .to_str()
     .ok_or(AppError::InternalError("project path is not valid unicode"))?;
+  let mut after_clone: Vec<String> = vec![];
+  if !canonical_project_path.exists() && create && project.is_directory() {
+    std::fs::create_dir_all(&canonical_project_path)?;
+  } else if !config.checkout_path(project, logger).exists() && create {
+    let project_logger = logger.new(o!("project" => name.clone()));
+    let checkout = config.checkout_path(project, logger);
+    clone_repository(
+      project,
+      &checkout,
+      config.resolve_ssh_key(project, logger).as_deref(),
+      config.reference_repo().as_deref(),
+      config.resolve_clone_depth(project, logger),
+      &project_logger,
+    )?;
+    if config.resolve_lfs(project, logger) {
+      update_lfs(&checkout, true, project.bare.unwrap_or_default(), &project_logger)?;
+    }
+    // the hooks run in the calling shell, their output would end up in the generated script otherwise
+    after_clone = trust::approved_hooks(
+      &config,
+      project,
+      "after_clone",
+      config.resolve_after_clone(&project_logger, project),
+      &project_logger,
+    );
+  }
   if !canonical_project_path.exists() {
     Err(AppError::UserError(format!("project key {} found but path {} does not exist", name, path)))
   } else {
     let mut commands: Vec<String> = vec![];
     commands.push(format!("cd '{}'", path));
+    // the hooks run in the calling shell, which needs the environment they get everywhere else
+    commands.extend(env_exports(&config.resolve_env(project, logger), logger));
+    commands.extend(after_clone);
     if !quick {
-      commands.extend_from_slice(&config.resolve_after_workon(logger, project))
+      commands.extend(trust::approved_hooks(
+        &config,
+        project,
+        "after_workon",
+        config.resolve_after_workon(logger, project),
+        logger,
+      ))
     }
+    recent::record_workon(&name, logger);
+    timetrack::record_session(&config, &name, logger);
     println!("{}", commands.join(" && "));
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use maplit::btreemap;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_env_exports() {
+    let logger = Logger::root(slog::Discard, o!());
+    let env = btreemap![
+      "GOFLAGS".to_owned() => "-mod=mod".to_owned(),
+      "GREETING".to_owned() => "it's $(here)".to_owned(),
+      "NOT-A-NAME".to_owned() => "x".to_owned(),
+    ];
+    assert_that(&env_exports(&env, &logger)).is_equal_to(vec!["export GOFLAGS=-mod=mod".to_owned(), "export GREETING='it'\\''s $(here)'".to_owned()]);
+  }
+}
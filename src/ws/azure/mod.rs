@@ -0,0 +1,57 @@
+use crate::errors::AppError;
+use crate::ws::client::ForgeClient;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use slog::Logger;
+use std::env;
+
+/// The token from `FW_AZURE_TOKEN`, or else the one in the settings.
+pub fn token(settings_token: Option<&String>) -> Option<String> {
+  env::var_os("FW_AZURE_TOKEN")
+    .map(|s| s.to_string_lossy().to_string())
+    .or_else(|| settings_token.cloned())
+}
+
+pub fn azure_api(organization: &str, token: &str, logger: &Logger) -> Result<AzureApi, AppError> {
+  Ok(AzureApi {
+    client: ForgeClient::azure(organization, token, logger)?,
+  })
+}
+
+pub struct AzureApi {
+  client: ForgeClient,
+}
+
+#[derive(Deserialize, Debug)]
+struct RepositoryList {
+  value: Vec<Repository>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Repository {
+  name: String,
+  ssh_url: String,
+  #[serde(default)]
+  is_disabled: bool,
+}
+
+impl AzureApi {
+  /// Name and SSH URL of the git repositories in `project`. Disabled repositories can't be cloned and are left out.
+  pub fn list_repositories(&self, project: &str) -> Result<Vec<(String, String)>, AppError> {
+    let response = self
+      .client
+      .send(self.client.get(&format!("{}/_apis/git/repositories?api-version=6.0", project))?)?;
+    // a rejected token gets 203 and a sign-in page instead of 401
+    match response.status() {
+      StatusCode::OK => {}
+      StatusCode::NON_AUTHORITATIVE_INFORMATION | StatusCode::UNAUTHORIZED => {
+        return Err(AppError::UserError("Azure DevOps rejected the personal access token".to_owned()))
+      }
+      StatusCode::NOT_FOUND => return Err(AppError::UserError(format!("Azure DevOps project {} not found", project))),
+      status => return Err(AppError::RuntimeError(format!("Bad status from Azure DevOps {}", status))),
+    }
+    let repositories: RepositoryList = response.json()?;
+    Ok(repositories.value.into_iter().filter(|r| !r.is_disabled).map(|r| (r.name, r.ssh_url)).collect())
+  }
+}
@@ -0,0 +1,321 @@
+use crate::errors::AppError;
+use crate::network;
+use crate::signal;
+use bytes::Bytes;
+use reqwest::blocking::{Client, Request, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, LINK, USER_AGENT};
+use serde::de::DeserializeOwned;
+use slog::{warn, Logger};
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+// rather fail than wait out a quota that resets in an hour
+const MAX_WAIT: Duration = Duration::from_secs(15 * 60);
+const MAX_ATTEMPTS: u32 = 5;
+// a 429 without any hint when to come back
+const DEFAULT_WAIT: Duration = Duration::from_secs(60);
+
+/// HTTP client for the GitHub and GitLab APIs. Rate limited requests are retried once the quota resets, a used up quota delays
+/// the next request instead of failing it, and `get_all` follows the pages of a listing. It also serves the queries of the
+/// `gitlab` crate, so its pagination goes through the same rate limit handling.
+pub struct ForgeClient {
+  http: Client,
+  base: Url,
+  logger: Logger,
+  exhausted_until: Mutex<Option<SystemTime>>,
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, AppError> {
+  HeaderValue::from_str(value).map_err(|_| AppError::UserError("API token contains invalid characters".to_owned()))
+}
+
+fn header_u64(headers: &HeaderMap, names: &[&str]) -> Option<u64> {
+  names
+    .iter()
+    .filter_map(|name| headers.get(*name))
+    .find_map(|value| value.to_str().ok()?.trim().parse().ok())
+}
+
+fn until_reset(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+  let reset = UNIX_EPOCH + Duration::from_secs(header_u64(headers, &["x-ratelimit-reset", "ratelimit-reset"])?);
+  // a second of slack for clock skew
+  Some(reset.duration_since(now).unwrap_or_default() + Duration::from_secs(1))
+}
+
+fn quota_used_up(headers: &HeaderMap) -> bool {
+  header_u64(headers, &["x-ratelimit-remaining", "ratelimit-remaining"]) == Some(0)
+}
+
+/// How long to wait before repeating a request that got `status`, `None` if it was not rate limited.
+/// GitHub answers 403 for both, rate limits and missing permissions, only the headers tell them apart.
+fn rate_limit_wait(status: u16, headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+  if status != 403 && status != 429 {
+    return None;
+  }
+  if let Some(seconds) = header_u64(headers, &["retry-after"]) {
+    Some(Duration::from_secs(seconds))
+  } else if quota_used_up(headers) {
+    Some(until_reset(headers, now).unwrap_or(DEFAULT_WAIT))
+  } else if status == 429 {
+    Some(DEFAULT_WAIT)
+  } else {
+    None
+  }
+}
+
+/// The `rel="next"` URL of a `Link` header like `<https://api.github.com/orgs/acme/repos?page=2>; rel="next", <…>; rel="last"`.
+fn next_link(link: &str) -> Option<&str> {
+  link.split(',').find_map(|entry| {
+    let mut parts = entry.split(';');
+    let url = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+    if parts.any(|param| param.trim().replace('"', "") == "rel=next") {
+      Some(url)
+    } else {
+      None
+    }
+  })
+}
+
+// The next page gets the auth headers as well, so it has to be on the same scheme, host and port as the API.
+fn next_page(base: &Url, link: &str) -> Result<Option<Url>, AppError> {
+  let next = match next_link(link).and_then(|url| Url::parse(url).ok()) {
+    Some(next) => next,
+    None => return Ok(None),
+  };
+  if next.origin() == base.origin() {
+    Ok(Some(next))
+  } else {
+    Err(AppError::RuntimeError(format!(
+      "Refusing to follow the next page to {}, the API is at {}",
+      next.origin().ascii_serialization(),
+      base.origin().ascii_serialization()
+    )))
+  }
+}
+
+impl ForgeClient {
+  fn new(base: &str, headers: HeaderMap, logger: &Logger) -> Result<ForgeClient, AppError> {
+    let base = Url::parse(base).map_err(|e| AppError::UserError(format!("Invalid API URL {}: {}", base, e)))?;
+    Ok(ForgeClient {
+      http: network::http_client_builder()?.default_headers(headers).build()?,
+      base,
+      logger: logger.clone(),
+      exhausted_until: Mutex::new(None),
+    })
+  }
+
+  pub fn github(token: Option<&str>, logger: &Logger) -> Result<ForgeClient, AppError> {
+    let mut headers = HeaderMap::new();
+    if let Some(token) = token {
+      headers.insert(AUTHORIZATION, header_value(&format!("token {}", token))?);
+    }
+    headers.insert(ACCEPT, HeaderValue::from_static("application/vnd.github.v3+json"));
+    headers.insert(USER_AGENT, HeaderValue::from_static("fw"));
+    ForgeClient::new("https://api.github.com/", headers, logger)
+  }
+
+  pub fn gitlab(host: &str, token: &str, logger: &Logger) -> Result<ForgeClient, AppError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(HeaderName::from_static("private-token"), header_value(token)?);
+    headers.insert(USER_AGENT, HeaderValue::from_static("fw"));
+    ForgeClient::new(&format!("https://{}/api/v4/", host), headers, logger)
+  }
+
+  /// A Gitea or Forgejo instance like `https://codeberg.org`. Without a token only public repositories are visible.
+  pub fn gitea(base_url: &str, token: Option<&str>, logger: &Logger) -> Result<ForgeClient, AppError> {
+    let mut headers = HeaderMap::new();
+    if let Some(token) = token {
+      headers.insert(AUTHORIZATION, header_value(&format!("token {}", token))?);
+    }
+    headers.insert(USER_AGENT, HeaderValue::from_static("fw"));
+    let base_url = base_url.trim_end_matches('/');
+    if base_url.contains("://") {
+      ForgeClient::new(&format!("{}/api/v1/", base_url), headers, logger)
+    } else {
+      ForgeClient::new(&format!("https://{}/api/v1/", base_url), headers, logger)
+    }
+  }
+
+  /// The Azure DevOps `organization`, authenticated with a personal access token.
+  pub fn azure(organization: &str, token: &str, logger: &Logger) -> Result<ForgeClient, AppError> {
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, header_value(&format!("Basic {}", base64::encode(format!(":{}", token))))?);
+    headers.insert(USER_AGENT, HeaderValue::from_static("fw"));
+    ForgeClient::new(&format!("https://dev.azure.com/{}/", organization), headers, logger)
+  }
+
+  fn url(&self, path: &str) -> Result<Url, AppError> {
+    self
+      .base
+      .join(path)
+      .map_err(|e| AppError::RuntimeError(format!("Invalid API path {}: {}", path, e)))
+  }
+
+  pub fn get(&self, path: &str) -> Result<RequestBuilder, AppError> {
+    Ok(self.http.get(self.url(path)?))
+  }
+
+  pub fn post(&self, path: &str) -> Result<RequestBuilder, AppError> {
+    Ok(self.http.post(self.url(path)?))
+  }
+
+  pub fn send(&self, request: RequestBuilder) -> Result<Response, AppError> {
+    self.execute(request.build()?)
+  }
+
+  fn wait(&self, wait: Duration, reason: &str) -> Result<(), AppError> {
+    let host = self.base.host_str().unwrap_or_default();
+    if wait > MAX_WAIT {
+      return Err(AppError::UserError(format!(
+        "{} of {}, it resets in {} minutes",
+        reason,
+        host,
+        wait.as_secs() / 60 + 1
+      )));
+    }
+    warn!(self.logger, "Waiting for the API rate limit"; "host" => host, "reason" => reason, "seconds" => wait.as_secs());
+    signal::sleep_interruptibly(wait);
+    signal::check_interrupted()
+  }
+
+  fn execute(&self, mut request: Request) -> Result<Response, AppError> {
+    let mut attempt = 1;
+    loop {
+      let exhausted_until = self.exhausted_until.lock().expect("rate limit state poisoned").take();
+      if let Some(wait) = exhausted_until.and_then(|until| until.duration_since(SystemTime::now()).ok()) {
+        self.wait(wait, "used up the API quota")?;
+      }
+      let retry = request.try_clone();
+      let response = self.http.execute(request)?;
+      let now = SystemTime::now();
+      match rate_limit_wait(response.status().as_u16(), response.headers(), now) {
+        None => {
+          if quota_used_up(response.headers()) {
+            *self.exhausted_until.lock().expect("rate limit state poisoned") = until_reset(response.headers(), now).map(|wait| now + wait);
+          }
+          return Ok(response);
+        }
+        Some(wait) => match retry {
+          Some(retry) if attempt < MAX_ATTEMPTS => {
+            self.wait(wait, "hit the API rate limit")?;
+            request = retry;
+            attempt += 1;
+          }
+          _ => return Ok(response),
+        },
+      }
+    }
+  }
+
+  /// A single resource like `repos/acme/fw`.
+  pub fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, AppError> {
+    let response = self.execute(self.http.get(self.url(path)?).build()?)?;
+    let status = response.status();
+    if !status.is_success() {
+      return Err(AppError::RuntimeError(format!(
+        "Bad status from {} {}",
+        self.base.host_str().unwrap_or_default(),
+        status
+      )));
+    }
+    Ok(response.json::<T>()?)
+  }
+
+  /// All items of a paginated listing like `orgs/acme/repos?per_page=100`, following the `Link` headers.
+  pub fn get_all<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, AppError> {
+    let mut items = Vec::new();
+    let mut next = Some(self.url(path)?);
+    while let Some(url) = next {
+      let response = self.execute(self.http.get(url).build()?)?;
+      let status = response.status();
+      if !status.is_success() {
+        return Err(AppError::RuntimeError(format!(
+          "Bad status from {} {}",
+          self.base.host_str().unwrap_or_default(),
+          status
+        )));
+      }
+      next = match response.headers().get(LINK).and_then(|link| link.to_str().ok()) {
+        Some(link) => next_page(&self.base, link)?,
+        None => None,
+      };
+      items.extend(response.json::<Vec<T>>()?);
+      signal::check_interrupted()?;
+    }
+    Ok(items)
+  }
+
+  fn rest_call(&self, request: http::request::Builder, body: Vec<u8>) -> Result<http::Response<Bytes>, AppError> {
+    let request = request.body(body).map_err(|e| AppError::RuntimeError(format!("Invalid API request: {}", e)))?;
+    let response = self.execute(Request::try_from(request)?)?;
+    let mut builder = http::Response::builder().status(response.status()).version(response.version());
+    if let Some(headers) = builder.headers_mut() {
+      headers.extend(response.headers().clone());
+    }
+    builder
+      .body(response.bytes()?)
+      .map_err(|e| AppError::RuntimeError(format!("Invalid API response: {}", e)))
+  }
+}
+
+impl gitlab::api::Client for ForgeClient {
+  type Error = AppError;
+
+  fn rest_endpoint(&self, endpoint: &str) -> Result<Url, gitlab::api::ApiError<AppError>> {
+    Ok(self.base.join(endpoint)?)
+  }
+
+  fn rest(&self, request: http::request::Builder, body: Vec<u8>) -> Result<http::Response<Bytes>, gitlab::api::ApiError<AppError>> {
+    self.rest_call(request, body).map_err(gitlab::api::ApiError::client)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+      headers.insert(*name, HeaderValue::from_str(value).unwrap());
+    }
+    headers
+  }
+
+  #[test]
+  fn test_rate_limit_wait() {
+    let now = UNIX_EPOCH + Duration::from_secs(1000);
+    let exhausted = headers(&[("x-ratelimit-remaining", "0"), ("x-ratelimit-reset", "1100")]);
+    assert_that(&rate_limit_wait(403, &exhausted, now)).is_equal_to(Some(Duration::from_secs(101)));
+    let gitlab = headers(&[("ratelimit-remaining", "0"), ("ratelimit-reset", "1010")]);
+    assert_that(&rate_limit_wait(429, &gitlab, now)).is_equal_to(Some(Duration::from_secs(11)));
+    let secondary = headers(&[("retry-after", "30"), ("x-ratelimit-remaining", "4000")]);
+    assert_that(&rate_limit_wait(403, &secondary, now)).is_equal_to(Some(Duration::from_secs(30)));
+    assert_that(&rate_limit_wait(429, &HeaderMap::new(), now)).is_equal_to(Some(DEFAULT_WAIT));
+
+    let forbidden = headers(&[("x-ratelimit-remaining", "4999")]);
+    assert_that(&rate_limit_wait(403, &forbidden, now)).is_none();
+    assert_that(&rate_limit_wait(200, &exhausted, now)).is_none();
+  }
+
+  #[test]
+  fn test_next_link() {
+    let link = "<https://api.github.com/organizations/1/repos?page=2>; rel=\"next\", <https://api.github.com/organizations/1/repos?page=5>; rel=\"last\"";
+    assert_that(&next_link(link)).is_equal_to(Some("https://api.github.com/organizations/1/repos?page=2"));
+    let last = "<https://gitlab.com/api/v4/projects?page=1>; rel=\"first\", <https://gitlab.com/api/v4/projects?page=4>; rel=\"prev\"";
+    assert_that(&next_link(last)).is_none();
+  }
+
+  #[test]
+  fn test_next_page_stays_on_the_api_origin() {
+    let base = Url::parse("https://api.github.com/").unwrap();
+    let next = next_page(&base, "<https://api.github.com/organizations/1/repos?page=2>; rel=\"next\"").unwrap();
+    assert_that(&next.map(|url| url.to_string())).is_equal_to(Some("https://api.github.com/organizations/1/repos?page=2".to_owned()));
+    assert_that(&next_page(&base, "<https://evil.example.com/repos?page=2>; rel=\"next\"").is_err()).is_true();
+    assert_that(&next_page(&base, "<http://api.github.com/repos?page=2>; rel=\"next\"").is_err()).is_true();
+    assert_that(&next_page(&base, "<https://api.github.com/repos?page=1>; rel=\"prev\"").unwrap()).is_none();
+  }
+}
@@ -0,0 +1,118 @@
+use crate::errors::AppError;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::process::Command;
+
+// batch-get-repositories takes at most 25 names
+const CODECOMMIT_BATCH: usize = 25;
+
+// The cloud CLIs bring the credential chains of their SDKs (environment, profiles, SSO, instance roles), so they do the talking.
+fn run_json<T: DeserializeOwned>(tool: &str, args: &[String]) -> Result<T, AppError> {
+  let output = Command::new(tool)
+    .args(args)
+    .output()
+    .map_err(|e| AppError::UserError(format!("Could not run {}: {}", tool, e)))?;
+  if !output.status.success() {
+    return Err(AppError::UserError(format!(
+      "{} failed: {}",
+      tool,
+      String::from_utf8_lossy(&output.stderr).trim()
+    )));
+  }
+  serde_json::from_slice(&output.stdout).map_err(|e| AppError::RuntimeError(format!("Unexpected output of {}: {}", tool, e)))
+}
+
+#[derive(Deserialize, Debug)]
+struct CodeCommitList {
+  repositories: Vec<CodeCommitName>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CodeCommitName {
+  repository_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CodeCommitBatch {
+  repositories: Vec<CodeCommitRepository>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CodeCommitRepository {
+  repository_name: String,
+  clone_url_http: String,
+}
+
+fn aws_args(command: &[&str], region: Option<&str>) -> Vec<String> {
+  let mut args: Vec<String> = vec!["codecommit".to_owned()];
+  args.extend(command.iter().map(|arg| arg.to_string()));
+  if let Some(region) = region {
+    args.extend(vec!["--region".to_owned(), region.to_owned()]);
+  }
+  args.extend(vec!["--output".to_owned(), "json".to_owned()]);
+  args
+}
+
+/// Name and HTTPS clone URL of the CodeCommit repositories in `region`, the default region of the aws CLI otherwise.
+pub fn list_codecommit_repositories(region: Option<&str>) -> Result<Vec<(String, String)>, AppError> {
+  let list: CodeCommitList = run_json("aws", &aws_args(&["list-repositories"], region))?;
+  let names: Vec<String> = list.repositories.into_iter().map(|r| r.repository_name).collect();
+  let mut repositories = Vec::new();
+  for chunk in names.chunks(CODECOMMIT_BATCH) {
+    let mut command = vec!["batch-get-repositories", "--repository-names"];
+    command.extend(chunk.iter().map(String::as_str));
+    let batch: CodeCommitBatch = run_json("aws", &aws_args(&command, region))?;
+    repositories.extend(batch.repositories.into_iter().map(|r| (r.repository_name, r.clone_url_http)));
+  }
+  Ok(repositories)
+}
+
+#[derive(Deserialize, Debug)]
+struct CloudSourceRepository {
+  /// `projects/<project>/repos/<name>`
+  name: String,
+  url: String,
+}
+
+fn cloud_source_name(full_name: &str) -> &str {
+  full_name.rsplit("/repos/").next().unwrap_or(full_name)
+}
+
+/// Name and HTTPS clone URL of the Cloud Source Repositories in `project`, the default project of gcloud otherwise.
+pub fn list_cloud_source_repositories(project: Option<&str>) -> Result<Vec<(String, String)>, AppError> {
+  let mut args: Vec<String> = vec!["source".to_owned(), "repos".to_owned(), "list".to_owned(), "--format=json".to_owned()];
+  if let Some(project) = project {
+    args.extend(vec!["--project".to_owned(), project.to_owned()]);
+  }
+  let repositories: Vec<CloudSourceRepository> = run_json("gcloud", &args)?;
+  Ok(repositories.into_iter().map(|r| (cloud_source_name(&r.name).to_owned(), r.url)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_parse_cli_output() {
+    let batch: CodeCommitBatch = serde_json::from_str(
+      r#"{"repositories": [{"repositoryName": "api", "repositoryId": "f7579e13", "cloneUrlHttp": "https://git-codecommit.eu-west-1.amazonaws.com/v1/repos/api", "cloneUrlSsh": "ssh://git-codecommit.eu-west-1.amazonaws.com/v1/repos/api"}], "repositoriesNotFound": []}"#,
+    )
+    .unwrap();
+    assert_that(&batch.repositories[0].clone_url_http).is_equal_to("https://git-codecommit.eu-west-1.amazonaws.com/v1/repos/api".to_owned());
+
+    let repos: Vec<CloudSourceRepository> =
+      serde_json::from_str(r#"[{"name": "projects/acme/repos/infra", "url": "https://source.developers.google.com/p/acme/r/infra"}]"#).unwrap();
+    assert_that(&cloud_source_name(&repos[0].name)).is_equal_to("infra");
+    assert_that(&aws_args(&["list-repositories"], Some("eu-west-1"))).is_equal_to(vec![
+      "codecommit".to_owned(),
+      "list-repositories".to_owned(),
+      "--region".to_owned(),
+      "eu-west-1".to_owned(),
+      "--output".to_owned(),
+      "json".to_owned(),
+    ]);
+  }
+}
@@ -0,0 +1,47 @@
+use crate::errors::AppError;
+use crate::ws::client::ForgeClient;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use slog::Logger;
+use std::env;
+
+/// The token from `FW_GITEA_TOKEN`, or else the one in the settings.
+pub fn token(settings_token: Option<&String>) -> Option<String> {
+  env::var_os("FW_GITEA_TOKEN")
+    .map(|s| s.to_string_lossy().to_string())
+    .or_else(|| settings_token.cloned())
+}
+
+pub fn gitea_api(base_url: &str, token: Option<&str>, logger: &Logger) -> Result<GiteaApi, AppError> {
+  Ok(GiteaApi {
+    client: ForgeClient::gitea(base_url, token, logger)?,
+  })
+}
+
+/// Client for the API of Gitea and its forks like Forgejo, which runs Codeberg.
+pub struct GiteaApi {
+  client: ForgeClient,
+}
+
+#[derive(Deserialize, Debug)]
+struct Repository {
+  name: String,
+  ssh_url: String,
+  archived: bool,
+}
+
+impl GiteaApi {
+  /// Name and SSH URL of the repositories of `owner`, which can be an organization or a user.
+  pub fn list_repositories(&self, owner: &str, include_archived: bool) -> Result<Vec<(String, String)>, AppError> {
+    let org = self.client.send(self.client.get(&format!("orgs/{}", owner))?)?;
+    let kind = if org.status() == StatusCode::NOT_FOUND { "users" } else { "orgs" };
+    let repositories: Vec<Repository> = self.client.get_all(&format!("{}/{}/repos?limit=50", kind, owner))?;
+    Ok(
+      repositories
+        .into_iter()
+        .filter(|r| include_archived || !r.archived)
+        .map(|r| (r.name, r.ssh_url))
+        .collect(),
+    )
+  }
+}
@@ -1,128 +1,226 @@
-// some of the code is from here: https://github.com/mgattozzi/github-rs/tree/master/github-gql-rs
-// this package seems unmaintained at the moment. Also it is basically just a small http client wrapper.
-
 use crate::errors::AppError;
-use serde::de::DeserializeOwned;
+use crate::ws::client::ForgeClient;
+use serde::de::IgnoredAny;
 use serde::{Deserialize, Serialize};
+use slog::Logger;
+use std::env;
+
+/// The token from `FW_GITHUB_TOKEN`, or else the one in the settings.
+pub fn token(settings_token: Option<&String>) -> Option<String> {
+  env::var_os("FW_GITHUB_TOKEN")
+    .map(|s| s.to_string_lossy().to_string())
+    .or_else(|| settings_token.cloned())
+}
+
+pub fn github_api(token: &str, logger: &Logger) -> Result<GithubApi, AppError> {
+  Ok(GithubApi {
+    client: ForgeClient::github(Some(token), logger)?,
+  })
+}
 
-pub fn github_api(token: &str) -> Result<GithubApi, AppError> {
-  let client = reqwest::blocking::Client::new();
+/// Without a token only public data is visible, at a much lower rate limit.
+pub fn anonymous_github_api(logger: &Logger) -> Result<GithubApi, AppError> {
   Ok(GithubApi {
-    client,
-    token: token.to_string(),
+    client: ForgeClient::github(None, logger)?,
   })
 }
 
 pub struct GithubApi {
-  client: reqwest::blocking::Client,
-  token: String,
+  client: ForgeClient,
 }
 
-struct PageResult {
-  repository_names: Vec<String>,
-  next_cursor: Option<String>,
+#[derive(Deserialize, Debug)]
+struct Owner {
+  login: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct OrganizationQueryResponse {
-  data: OrganizationQueryResponseData,
+#[derive(Deserialize, Debug)]
+struct Repository {
+  name: String,
+  owner: Owner,
+  clone_url: String,
+  language: Option<String>,
+  #[serde(default)]
+  topics: Vec<String>,
+  #[serde(default)]
+  archived: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct OrganizationQueryResponseData {
-  organization: OrganizationRepositoriesResponseData,
+impl From<Repository> for GithubRepository {
+  fn from(r: Repository) -> GithubRepository {
+    GithubRepository {
+      name: r.name,
+      owner: r.owner.login,
+      git: r.clone_url,
+      language: r.language,
+      topics: r.topics,
+    }
+  }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct OrganizationRepositoriesResponseData {
-  repositories: RepositoriesResponseData,
+/// A repository with the metadata the importers use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GithubRepository {
+  pub name: String,
+  pub owner: String,
+  /// The HTTPS clone URL, which needs no SSH key registered at GitHub.
+  pub git: String,
+  pub language: Option<String>,
+  pub topics: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RepositoriesResponseData {
-  nodes: Vec<Repository>,
-  #[serde(rename = "pageInfo")]
-  page_info: PageInfo,
+impl GithubRepository {
+  /// Whether the repository is written in `language` and carries `topic`, ignoring case. `None` matches everything.
+  pub fn matches(&self, language: Option<&str>, topic: Option<&str>) -> bool {
+    language.is_none_or(|language| self.language.as_deref().is_some_and(|l| l.eq_ignore_ascii_case(language)))
+      && topic.is_none_or(|topic| self.topics.iter().any(|t| t.eq_ignore_ascii_case(topic)))
+  }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Repository {
-  name: String,
-  #[serde(rename = "isArchived")]
-  is_archived: bool,
+#[derive(Serialize, Debug)]
+struct NewPullRequest<'a> {
+  title: &'a str,
+  head: &'a str,
+  base: &'a str,
+  body: &'a str,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct PageInfo {
-  #[serde(rename = "endCursor")]
-  end_cursor: String,
-  #[serde(rename = "hasNextPage")]
-  has_next_page: bool,
+#[derive(Deserialize, Debug)]
+struct PullRequest {
+  html_url: String,
 }
 
-impl GithubApi {
-  fn query<T: DeserializeOwned>(&self, query: &str) -> Result<T, AppError> {
-    //escaping new lines and quotation marks for json
-    let mut escaped = query.to_string();
-    escaped = escaped.replace("\n", "\\n");
-    escaped = escaped.replace("\"", "\\\"");
+#[derive(Deserialize, Debug)]
+struct IssueRepository {
+  full_name: String,
+}
 
-    let mut q = String::from("{ \"query\": \"");
-    q.push_str(&escaped);
-    q.push_str("\" }");
+#[derive(Deserialize, Debug)]
+struct Issue {
+  number: u64,
+  title: String,
+  html_url: String,
+  repository: IssueRepository,
+  pull_request: Option<IgnoredAny>,
+}
 
-    let res = self
+/// An open issue or pull request assigned to the owner of the token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssignedIssue {
+  /// `owner/repo`
+  pub repository: String,
+  pub number: u64,
+  pub title: String,
+  pub url: String,
+  pub pull_request: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+  message: String,
+  #[serde(default)]
+  errors: Vec<ErrorDetail>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+  message: Option<String>,
+}
+
+impl GithubApi {
+  /// Opens a pull request from `head` into `base` and returns its URL.
+  pub fn create_pull_request(&self, owner: &str, repo: &str, head: &str, base: &str, title: &str, body: &str) -> Result<String, AppError> {
+    let request = self
       .client
-      .post("https://api.github.com/graphql")
-      .body(reqwest::blocking::Body::from(q))
-      .header("Content-Type", "application/json")
-      .header("User-Agent", "github-rs")
-      .header("Authorization", format!("token {}", self.token))
-      .send()?;
-
-    if res.status().is_success() {
-      res.json::<T>().map_err(|e| AppError::RuntimeError(format!("Failed to parse response: {}", e)))
-    } else {
-      Err(AppError::RuntimeError(format!("Bad status from github {}", res.status())))
+      .post(&format!("repos/{}/{}/pulls", owner, repo))?
+      .json(&NewPullRequest { title, head, base, body });
+    let res = self.client.send(request)?;
+
+    let status = res.status();
+    if status.is_success() {
+      return res
+        .json::<PullRequest>()
+        .map(|pr| pr.html_url)
+        .map_err(|e| AppError::RuntimeError(format!("Failed to parse response: {}", e)));
+    }
+    // 422 carries the reason, e.g. that a pull request for the branch already exists
+    match res.json::<ErrorResponse>() {
+      Ok(error) => {
+        let details: Vec<String> = error.errors.into_iter().filter_map(|e| e.message).collect();
+        Err(AppError::UserError(if details.is_empty() { error.message } else { details.join(", ") }))
+      }
+      Err(_) => Err(AppError::RuntimeError(format!("Bad status from github {}", status))),
     }
   }
 
-  pub fn list_repositories(&mut self, org: &str, include_archived: bool) -> Result<Vec<String>, AppError> {
-    let initial_page = self.page_repositories(org, None, include_archived)?;
-    let mut initial_names = initial_page.repository_names;
+  /// The repositories of `org`, or only those the team with the slug `team` has access to.
+  pub fn list_repositories(&self, org: &str, team: Option<&str>, include_archived: bool) -> Result<Vec<GithubRepository>, AppError> {
+    let path = match team {
+      Some(team) => format!("orgs/{}/teams/{}/repos?per_page=100", org, team),
+      None => format!("orgs/{}/repos?per_page=100", org),
+    };
+    let repositories: Vec<Repository> = self.client.get_all(&path)?;
+    Ok(
+      repositories
+        .into_iter()
+        .filter(|r| include_archived || !r.archived)
+        .map(GithubRepository::from)
+        .collect(),
+    )
+  }
 
-    let mut next: Option<String> = initial_page.next_cursor;
-    while next.is_some() {
-      let next_repos = self.page_repositories(org, next.clone(), include_archived)?;
-      initial_names.extend(next_repos.repository_names);
-      next = next_repos.next_cursor;
-    }
+  pub fn repository(&self, owner: &str, repo: &str) -> Result<GithubRepository, AppError> {
+    let repository: Repository = self.client.get_json(&format!("repos/{}/{}", owner, repo))?;
+    Ok(GithubRepository::from(repository))
+  }
 
-    Ok(initial_names)
+  /// The repositories starred by `user`, or by the owner of the token.
+  pub fn starred_repositories(&self, user: Option<&str>) -> Result<Vec<GithubRepository>, AppError> {
+    let path = match user {
+      Some(user) => format!("users/{}/starred?per_page=100", user),
+      None => "user/starred?per_page=100".to_owned(),
+    };
+    let repositories: Vec<Repository> = self.client.get_all(&path)?;
+    Ok(repositories.into_iter().map(GithubRepository::from).collect())
   }
-  fn page_repositories(&mut self, org: &str, after: Option<String>, include_archived: bool) -> Result<PageResult, AppError> {
-    let after_refinement = after.map(|a| format!(", after:\"{}\"", a)).unwrap_or_else(|| "".to_owned());
-    let response: OrganizationQueryResponse = self.query(
-      &("query {organization(login: \"".to_owned()
-        + org
-        + "\"){repositories(first: 100"
-        + &after_refinement
-        + ") {nodes {name, isArchived} pageInfo {endCursor hasNextPage}}}}"),
-    )?;
-    let repositories: Vec<Repository> = response.data.organization.repositories.nodes;
-    let repo_names: Vec<String> = repositories
-      .into_iter()
-      .filter(|r| include_archived || !r.is_archived)
-      .map(|r| r.name)
-      .collect();
-
-    Ok(PageResult {
-      repository_names: repo_names,
-      next_cursor: if response.data.organization.repositories.page_info.has_next_page {
-        Some(response.data.organization.repositories.page_info.end_cursor)
-      } else {
-        None
-      },
-    })
+
+  /// Open issues and pull requests assigned to the owner of the token, in all repositories.
+  pub fn assigned_issues(&self) -> Result<Vec<AssignedIssue>, AppError> {
+    let issues: Vec<Issue> = self.client.get_all("issues?filter=assigned&state=open&per_page=100")?;
+    Ok(
+      issues
+        .into_iter()
+        .map(|issue| AssignedIssue {
+          repository: issue.repository.full_name,
+          number: issue.number,
+          title: issue.title,
+          url: issue.html_url,
+          pull_request: issue.pull_request.is_some(),
+        })
+        .collect(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use spectral::prelude::*;
+
+  #[test]
+  fn test_repository_matches() {
+    let repository = GithubRepository {
+      name: "fw".to_owned(),
+      owner: "brocode".to_owned(),
+      git: "https://github.com/brocode/fw.git".to_owned(),
+      language: Some("Rust".to_owned()),
+      topics: vec!["cli".to_owned(), "workspace".to_owned()],
+    };
+    assert_that(&repository.matches(None, None)).is_true();
+    assert_that(&repository.matches(Some("rust"), Some("CLI"))).is_true();
+    assert_that(&repository.matches(Some("go"), None)).is_false();
+    assert_that(&repository.matches(None, Some("editor"))).is_false();
+    let without_language = GithubRepository { language: None, ..repository };
+    assert_that(&without_language.matches(Some("rust"), None)).is_false();
   }
 }
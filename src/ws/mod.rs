@@ -1 +1,5 @@
+pub mod azure;
+pub mod client;
+pub mod cloud;
+pub mod gitea;
 pub mod github;